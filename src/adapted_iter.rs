@@ -1,8 +1,14 @@
+use std::io::Cursor;
 use std::pin::Pin;
 
-use tokio_stream::Stream;
+use async_stream::stream;
+use async_trait::async_trait;
+use log::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
 
-use crate::adapters::AdaptInfo;
+use crate::adapters::{AdaptInfo, ReadBox};
+use crate::print_bytes;
 
 pub trait AdaptedFilesIter: Stream<Item = anyhow::Result<AdaptInfo>> + Send {}
 impl<T> AdaptedFilesIter for T where T: Stream<Item = anyhow::Result<AdaptInfo>> + Send {}
@@ -12,3 +18,166 @@ pub type AdaptedFilesIterBox = Pin<Box<dyn AdaptedFilesIter>>;
 pub fn one_file(ai: AdaptInfo) -> AdaptedFilesIterBox {
     Box::pin(tokio_stream::once(Ok(ai)))
 }
+
+/// Maximum number of bytes of adapted files that `--rga-sort` holds in memory per archive
+pub const SORT_BUFFER_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Reads the files into memory and yields them sorted by path,
+/// so the output does not depend on the order of the files within archives.
+///
+/// Once the files read so far are larger than `limit`, they are yielded sorted
+/// and the remaining files are streamed in their original order.
+pub fn sorted_by_path(mut inp: AdaptedFilesIterBox, limit: usize) -> AdaptedFilesIterBox {
+    let s = stream! {
+        let mut files = Vec::new();
+        let mut buffered = 0;
+        while let Some(file) = inp.next().await {
+            let mut file = file?;
+            let remaining = limit - buffered;
+            let mut content = Vec::new();
+            (&mut file.inp)
+                .take(remaining as u64 + 1)
+                .read_to_end(&mut content)
+                .await?;
+            if content.len() > remaining {
+                warn!(
+                    "--rga-sort: contents of {} larger than {}, not sorting the rest",
+                    file.filepath_hint.display(),
+                    print_bytes(limit as f64)
+                );
+                files.sort_by(|a, b| a.filepath_hint.cmp(&b.filepath_hint));
+                for file in files {
+                    yield Ok(file);
+                }
+                yield Ok(AdaptInfo {
+                    inp: Box::pin(Cursor::new(content).chain(file.inp)),
+                    ..file
+                });
+                while let Some(file) = inp.next().await {
+                    yield file;
+                }
+                return;
+            }
+            buffered += content.len();
+            files.push(AdaptInfo {
+                inp: Box::pin(Cursor::new(content)),
+                ..file
+            });
+        }
+        files.sort_by(|a, b| a.filepath_hint.cmp(&b.filepath_hint));
+        for file in files {
+            yield Ok(file);
+        }
+    };
+    Box::pin(s)
+}
+
+/// A file of an archive that stores its files one after another, see [sequential_members]
+pub struct SequentialMember {
+    pub path: String,
+    /// length of the data of the file, which follows the header
+    pub size: u64,
+    /// number of bytes after the data, to align the next header
+    pub padding: u64,
+}
+
+/// Reads the headers of an archive format like ar or cpio
+#[async_trait]
+pub trait MemberHeaders: Send + 'static {
+    /// Read the header of the next file, skipping entries without content (directories, symbol tables, ...).
+    ///
+    /// Returns None at the end of the archive.
+    async fn next_member(&mut self, inp: &mut ReadBox) -> anyhow::Result<Option<SequentialMember>>;
+}
+
+/// Skip `n` bytes of the input
+pub async fn skip_bytes(inp: &mut ReadBox, n: u64) -> anyhow::Result<()> {
+    let skipped = tokio::io::copy(&mut inp.take(n), &mut tokio::io::sink()).await?;
+    anyhow::ensure!(skipped == n, "unexpected end of archive");
+    Ok(())
+}
+
+/// Streams the files of an archive that stores them one after another, each header followed by the data of the file.
+///
+/// The archive is read in a background task that forwards the data of each file to the reader yielded for it,
+/// so files don't have to be held in memory. Files that are not read to the end are skipped.
+#[allow(clippy::type_complexity)]
+pub fn sequential_members(
+    mut inp: ReadBox,
+    mut headers: impl MemberHeaders,
+) -> Pin<Box<dyn Stream<Item = anyhow::Result<(String, ReadBox)>> + Send>> {
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    tokio::spawn(async move {
+        let res = async {
+            while let Some(member) = headers.next_member(&mut inp).await? {
+                let (reader, mut writer) = tokio::io::duplex(1 << 16);
+                let reader: ReadBox = Box::pin(reader);
+                if tx.send(Ok((member.path, reader))).await.is_err() {
+                    // the stream was dropped
+                    return Ok(());
+                }
+                let mut data = (&mut inp).take(member.size);
+                let mut buf = vec![0u8; 1 << 16];
+                let mut reader_dropped = false;
+                loop {
+                    let n = data.read(&mut buf).await?;
+                    if n == 0 {
+                        break;
+                    }
+                    if !reader_dropped && writer.write_all(&buf[..n]).await.is_err() {
+                        reader_dropped = true;
+                    }
+                }
+                anyhow::ensure!(data.limit() == 0, "unexpected end of archive");
+                drop(writer);
+                skip_bytes(&mut inp, member.padding).await?;
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = res {
+            tx.send(Err(e)).await.ok();
+        }
+    });
+    Box::pin(stream! {
+        while let Some(member) = rx.recv().await {
+            yield member;
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn sorted() -> anyhow::Result<()> {
+        let files = ["b.txt", "a/z.txt", "a.txt"].map(|name| {
+            let (ai, _) = simple_adapt_info(Path::new(name), Box::pin(Cursor::new(name)));
+            anyhow::Ok(ai)
+        });
+        let res = sorted_by_path(Box::pin(tokio_stream::iter(files)), SORT_BUFFER_LIMIT);
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "a.txta/z.txtb.txt"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sorted_limit() -> anyhow::Result<()> {
+        let files = ["d.txt", "c.txt", "b.txt", "a.txt"].map(|name| {
+            let (ai, _) = simple_adapt_info(Path::new(name), Box::pin(Cursor::new(name)));
+            anyhow::Ok(ai)
+        });
+        // only the first two files fit, the others are passed through in their order
+        let res = sorted_by_path(Box::pin(tokio_stream::iter(files)), 12);
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "c.txtd.txtb.txta.txt"
+        );
+        Ok(())
+    }
+}
@@ -1,14 +1,80 @@
+pub mod alias;
+pub mod apk;
+pub mod ar;
+pub mod avro;
+pub mod binary;
+pub mod binjson;
+pub mod borg;
+pub mod cfb;
+pub mod chm;
+pub mod csv;
 pub mod custom;
+pub mod deb;
 pub mod decompress;
+pub mod dicom;
+pub mod djvu;
+pub mod eml;
+pub mod epub;
+pub mod etl;
+pub mod evtx;
+pub mod executable;
+pub mod exif;
+pub mod fb2;
 pub mod ffmpeg;
+pub mod flatten;
+pub mod git;
+pub mod gron;
+pub mod hdf5;
+pub mod hexdump;
+pub mod ics;
+pub mod ipynb;
+pub mod iso;
+pub mod leveldb;
+pub mod lmdb;
+pub mod lucene;
+pub mod lz4;
+pub mod maildir;
+pub mod mat;
 pub mod mbox;
+pub mod minidump;
+pub mod msg;
+pub mod multivolume;
+pub mod npy;
+pub mod ocr;
+pub mod odf;
+pub mod orc;
+pub mod parquet;
+pub mod pcap;
+pub mod pdf;
+pub mod pgdump;
 pub mod postproc;
+pub mod pptx;
+pub mod property_list;
+pub mod protobuf;
+pub mod pst;
+pub mod rar;
+pub mod registry;
+pub mod restic;
+pub mod rpm;
+pub mod rtf;
+pub mod sevenzip;
+pub mod sfx;
 use std::sync::Arc;
 pub mod sqlite;
+pub mod strings;
+pub mod subtitles;
 pub mod tar;
+pub mod wasm;
+pub mod whisper;
 pub mod writing;
+pub mod xls;
+pub mod xlsx;
+pub mod xml;
 pub mod zip;
-use crate::{adapted_iter::AdaptedFilesIterBox, config::RgaConfig, matching::*};
+use crate::{
+    adapted_iter::AdaptedFilesIterBox, config::RgaConfig, matching::*, passwords::PasswordState,
+};
+use alias::{AdapterAliasConfig, apply_aliases};
 use anyhow::{Context, Result, format_err};
 use async_trait::async_trait;
 use custom::BUILTIN_SPAWNING_ADAPTERS;
@@ -34,6 +100,8 @@ pub struct AdapterMeta {
     pub description: String,
     /// indicates whether this adapter can descend (=call rga_preproc again). if true, the cache key needs to include the list of active adapters
     pub recurses: bool,
+    /// if true, the files this adapter outputs are the unchanged files within the archive (not text extracted from them), so they can be extracted as they are
+    pub outputs_unchanged_files: bool,
     /// list of matchers (interpreted as a OR b OR ...)
     pub fast_matchers: Vec<FastFileMatcher>,
     /// list of matchers when we have mime type detection active (interpreted as ORed)
@@ -104,12 +172,17 @@ pub struct AdaptInfo {
     pub line_prefix: String,
     pub postprocess: bool,
     pub config: RgaConfig,
+    /// the passwords entered and used for the file being searched, shared by all files within it
+    pub passwords: PasswordState,
 }
 
 /// (enabledAdapters, disabledAdapters)
 type AdaptersTuple = (Vec<Arc<dyn FileAdapter>>, Vec<Arc<dyn FileAdapter>>);
 
-pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> AdaptersTuple {
+pub fn get_all_adapters(
+    custom_adapters: Option<Vec<CustomAdapterConfig>>,
+    adapter_aliases: &[AdapterAliasConfig],
+) -> AdaptersTuple {
     // order in descending priority
     let mut adapters: Vec<Arc<dyn FileAdapter>> = vec![];
     if let Some(custom_adapters) = custom_adapters {
@@ -121,11 +194,68 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
     let internal_adapters: Vec<Arc<dyn FileAdapter>> = vec![
         Arc::new(PostprocPageBreaks::default()),
         Arc::new(ffmpeg::FFmpegAdapter::new()),
+        Arc::new(epub::EpubAdapter::new()),
+        Arc::new(chm::ChmAdapter::new()),
+        Arc::new(djvu::DjvuAdapter::new()),
+        Arc::new(fb2::Fb2Adapter::new()),
+        Arc::new(xlsx::XlsxAdapter::new()),
+        Arc::new(xls::XlsAdapter::new()),
+        Arc::new(pptx::PptxAdapter::new()),
+        Arc::new(odf::OdfAdapter::new()),
+        Arc::new(rtf::RtfAdapter::new()),
+        Arc::new(apk::ApkAdapter::new()),
         Arc::new(zip::ZipAdapter::new()),
+        Arc::new(sevenzip::SevenZipAdapter::new()),
+        Arc::new(rar::RarAdapter::new()),
+        Arc::new(iso::IsoAdapter::new()),
+        Arc::new(multivolume::MultiVolumeAdapter::new()),
+        Arc::new(sfx::SfxAdapter::new()),
         Arc::new(decompress::DecompressAdapter::new()),
+        Arc::new(eml::EmlAdapter::new()),
+        Arc::new(msg::MsgAdapter::new()),
+        Arc::new(pst::PstAdapter::new()),
+        Arc::new(maildir::MaildirAdapter::new()),
         Arc::new(mbox::MboxAdapter::new()),
+        Arc::new(ics::IcsAdapter::new()),
+        Arc::new(subtitles::SubtitlesAdapter::new()),
+        Arc::new(ipynb::IpynbAdapter::new()),
         Arc::new(tar::TarAdapter::new()),
+        Arc::new(deb::DebAdapter::new()),
+        Arc::new(ar::ArAdapter::new()),
+        Arc::new(rpm::RpmAdapter::new()),
+        Arc::new(restic::ResticAdapter::new()),
+        Arc::new(borg::BorgAdapter::new()),
+        Arc::new(git::GitAdapter::new()),
         Arc::new(sqlite::SqliteAdapter::new()),
+        Arc::new(leveldb::LeveldbAdapter::new()),
+        Arc::new(lmdb::LmdbAdapter::new()),
+        Arc::new(pgdump::PgDumpAdapter::new()),
+        Arc::new(pcap::PcapAdapter::new()),
+        Arc::new(parquet::ParquetAdapter::new()),
+        Arc::new(avro::AvroAdapter::new()),
+        Arc::new(orc::OrcAdapter::new()),
+        Arc::new(hdf5::Hdf5Adapter::new()),
+        Arc::new(mat::MatAdapter::new()),
+        Arc::new(npy::NpyAdapter::new()),
+        Arc::new(protobuf::ProtobufAdapter::new()),
+        Arc::new(binjson::BinJsonAdapter::new()),
+        Arc::new(flatten::FlattenAdapter::new()),
+        Arc::new(property_list::PlistAdapter::new()),
+        Arc::new(gron::GronAdapter::new()),
+        Arc::new(csv::CsvAdapter::new()),
+        Arc::new(etl::EtlAdapter::new()),
+        Arc::new(evtx::EvtxAdapter::new()),
+        Arc::new(registry::RegistryAdapter::new()),
+        Arc::new(lucene::LuceneAdapter::new()),
+        Arc::new(minidump::MinidumpAdapter::new()),
+        Arc::new(wasm::WasmAdapter::new()),
+        Arc::new(executable::ExecutableAdapter::new()),
+        Arc::new(exif::ExifAdapter::new()),
+        Arc::new(dicom::DicomAdapter::new()),
+        Arc::new(ocr::OcrAdapter::new()),
+        Arc::new(whisper::WhisperAdapter::new()),
+        Arc::new(hexdump::HexdumpAdapter::new()),
+        Arc::new(strings::StringsAdapter::new()),
     ];
     adapters.extend(
         BUILTIN_SPAWNING_ADAPTERS
@@ -133,12 +263,39 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
             .map(|e| -> Arc<dyn FileAdapter> { Arc::new(e.to_adapter()) }),
     );
     adapters.extend(internal_adapters);
+    apply_aliases(&mut adapters, adapter_aliases);
 
     adapters
         .into_iter()
         .partition(|e| !e.metadata().disabled_by_default)
 }
 
+/// The glob of files that rg should call rga-preproc for (`--pre-glob`), given the active adapters.
+///
+/// With accurate (mime type) matching, every file needs to be checked.
+/// Matchers that look at the directory of the file (like `DirectoryShape`) can't be written as a glob
+/// that is not much too broad, so those files are only found with accurate matching.
+pub fn pre_glob(adapters: &[Arc<dyn FileAdapter>], accurate: bool) -> String {
+    if accurate {
+        return "*".to_owned();
+    }
+    let extensions = adapters
+        .iter()
+        .flat_map(|a| &a.metadata().fast_matchers)
+        .flat_map(|m| match m {
+            FastFileMatcher::FileExtension(ext) => vec![ext.clone(), ext.to_ascii_uppercase()],
+            // `**/tmp/*` would send every file in any tmp directory through rga-preproc,
+            // `*.log` every log file though few of them have the sibling,
+            // and `**/objects/??/*` every file in such a directory, not only of git repositories
+            FastFileMatcher::DirectoryShape(_)
+            | FastFileMatcher::ExtensionWithSibling(_, _)
+            | FastFileMatcher::PathGlob(_) => vec![],
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("*.{{{extensions}}}")
+}
+
 /**
  * filter adapters by given names:
  *
@@ -149,9 +306,11 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
  */
 pub fn get_adapters_filtered<T: AsRef<str>>(
     custom_adapters: Option<Vec<CustomAdapterConfig>>,
+    adapter_aliases: &[AdapterAliasConfig],
     adapter_names: &[T],
 ) -> Result<Vec<Arc<dyn FileAdapter>>> {
-    let (def_enabled_adapters, def_disabled_adapters) = get_all_adapters(custom_adapters);
+    let (def_enabled_adapters, def_disabled_adapters) =
+        get_all_adapters(custom_adapters, adapter_aliases);
     let adapters = if !adapter_names.is_empty() {
         let adapters_map: HashMap<_, _> = def_enabled_adapters
             .iter()
@@ -214,3 +373,44 @@ pub fn get_adapters_filtered<T: AsRef<str>>(
     );
     Ok(adapters)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pre_glob_extensions() -> Result<()> {
+        let adapters = get_adapters_filtered(None, &[], &["zip".to_string()])?;
+        assert_eq!(
+            pre_glob(&adapters, false),
+            "*.{zip,ZIP,jar,JAR,xpi,XPI,kra,KRA,snagx,SNAGX,npz,NPZ}"
+        );
+        assert_eq!(pre_glob(&adapters, true), "*");
+        Ok(())
+    }
+
+    /// The name of the adapter chosen for the file when the given adapter is enabled
+    fn chosen_with(enable: &str, file: &str) -> Result<String> {
+        let adapters = get_adapters_filtered(None, &[], &[format!("+{enable}")])?;
+        let matcher = crate::matching::adapter_matcher(&adapters, false)?;
+        let (adapter, _) = matcher(crate::matching::FileMeta {
+            lossy_filename: file.to_string(),
+            mimetype: None,
+            path: None,
+        })
+        .context("no adapter")?;
+        Ok(adapter.metadata().name.clone())
+    }
+
+    #[test]
+    fn enabled_adapter_instead_of_default() -> Result<()> {
+        use crate::matching::is_known_overlap;
+        assert_eq!(chosen_with("ocr", "scan.png")?, "ocr");
+        assert_eq!(chosen_with("whisper", "talk.mp3")?, "whisper");
+        // chosen without a warning
+        assert!(is_known_overlap(&["ocr", "exif"]));
+        assert!(is_known_overlap(&["ffmpeg", "whisper"]));
+        assert!(!is_known_overlap(&["ocr", "ffmpeg"]));
+        Ok(())
+    }
+}
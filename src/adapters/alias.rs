@@ -0,0 +1,138 @@
+use super::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Makes an existing adapter also handle other file extensions or mime types, without defining a new adapter.
+///
+/// For example `{"adapter": "poppler", "extensions": ["ai"]}` searches Adobe Illustrator files (which are PDF files) with poppler.
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Clone)]
+pub struct AdapterAliasConfig {
+    /// The name of the existing adapter, as shown by `rga --rga-list-adapters`.
+    pub adapter: String,
+
+    /// The additional file extensions, for example `["ai", "eps"]`.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
+    /// The additional mime types, used if `--rga-accurate` is enabled.
+    #[serde(default)]
+    pub mimetypes: Vec<String>,
+}
+
+/// An adapter with the matchers of an alias added
+struct AliasedAdapter {
+    inner: Arc<dyn FileAdapter>,
+    meta: AdapterMeta,
+}
+
+impl GetMetadata for AliasedAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &self.meta
+    }
+}
+
+#[async_trait]
+impl FileAdapter for AliasedAdapter {
+    async fn adapt(
+        &self,
+        a: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        self.inner.adapt(a, detection_reason).await
+    }
+}
+
+fn with_alias(inner: Arc<dyn FileAdapter>, alias: &AdapterAliasConfig) -> AliasedAdapter {
+    let m = inner.metadata();
+    let extensions: Vec<FastFileMatcher> = alias
+        .extensions
+        .iter()
+        .map(|e| FastFileMatcher::FileExtension(e.to_string()))
+        .collect();
+    let mut fast_matchers = m.fast_matchers.clone();
+    fast_matchers.extend(extensions.iter().cloned());
+    // the slow matchers override the fast ones, so the new extensions are added to them as well
+    let slow_matchers = match (&m.slow_matchers, alias.mimetypes.is_empty()) {
+        (None, true) => None,
+        (slow, _) => Some(
+            slow.clone()
+                .unwrap_or_else(|| {
+                    m.fast_matchers
+                        .iter()
+                        .cloned()
+                        .map(FileMatcher::Fast)
+                        .collect()
+                })
+                .into_iter()
+                .chain(extensions.into_iter().map(FileMatcher::Fast))
+                .chain(
+                    alias
+                        .mimetypes
+                        .iter()
+                        .map(|t| FileMatcher::MimeType(t.to_string())),
+                )
+                .collect(),
+        ),
+    };
+    let meta = AdapterMeta {
+        name: m.name.clone(),
+        version: m.version,
+        description: format!(
+            "{}\nAliased for: {}",
+            m.description,
+            alias
+                .extensions
+                .iter()
+                .map(|e| format!(".{e}"))
+                .chain(alias.mimetypes.iter().cloned())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        recurses: m.recurses,
+        outputs_unchanged_files: m.outputs_unchanged_files,
+        fast_matchers,
+        slow_matchers,
+        keep_fast_matchers_if_accurate: m.keep_fast_matchers_if_accurate,
+        disabled_by_default: m.disabled_by_default,
+    };
+    AliasedAdapter { inner, meta }
+}
+
+/// Add the matchers of the aliases to the adapters they name.
+pub fn apply_aliases(adapters: &mut [Arc<dyn FileAdapter>], aliases: &[AdapterAliasConfig]) {
+    for alias in aliases {
+        match adapters
+            .iter_mut()
+            .find(|a| a.metadata().name == alias.adapter)
+        {
+            Some(adapter) => *adapter = Arc::new(with_alias(adapter.clone(), alias)),
+            None => warn!("adapter alias for unknown adapter {}", alias.adapter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alias() {
+        let (adapters, _) = get_all_adapters(
+            None,
+            &[AdapterAliasConfig {
+                adapter: "zip".to_string(),
+                extensions: vec!["jar".to_string()],
+                mimetypes: vec![],
+            }],
+        );
+        let zip = adapters
+            .iter()
+            .find(|a| a.metadata().name == "zip")
+            .unwrap();
+        let meta = zip.metadata();
+        let is_jar = |m: &FileMatcher| matches!(m, FileMatcher::Fast(FastFileMatcher::FileExtension(e)) if e == "jar");
+        assert!(meta.get_matchers(false).any(|m| is_jar(&m)));
+        // zip matches by mime type with --rga-accurate, the alias still matches by extension
+        assert!(meta.get_matchers(true).any(|m| is_jar(&m)));
+    }
+}
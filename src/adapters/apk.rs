@@ -0,0 +1,469 @@
+use super::binary::{u16_at, u32_at, utf16};
+use super::zip::ZipAdapter;
+use super::*;
+use crate::adapted_iter::one_file;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["apk", "dex"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "apk".to_owned(),
+        version: 1,
+        description: "Reads Android packages (apk) like zip files, but decodes the binary XML files (AndroidManifest.xml, layouts, ...) to text and outputs the strings of the resource table (resources.arsc).\nFor Dalvik executables (classes.dex), outputs the string table (class, method and field names, string constants)."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.android.package-archive".to_owned()),
+            FileMatcher::MimeType("application/x-android-dex".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ApkAdapter;
+
+impl ApkAdapter {
+    pub fn new() -> ApkAdapter {
+        ApkAdapter
+    }
+}
+impl GetMetadata for ApkAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Decode the "modified UTF-8" of dex files (surrogates encoded separately, NUL as two bytes)
+fn decode_mutf8(bytes: &[u8]) -> String {
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i] as u16;
+        let cont = |k: usize| bytes.get(i + k).map(|c| (*c & 0x3f) as u16).unwrap_or(0);
+        if b < 0x80 {
+            units.push(b);
+            i += 1;
+        } else if b & 0xe0 == 0xc0 {
+            units.push(((b & 0x1f) << 6) | cont(1));
+            i += 2;
+        } else {
+            units.push(((b & 0x0f) << 12) | (cont(1) << 6) | cont(2));
+            i += 3;
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+/// The strings of the string table of a dex file, one per line
+fn dex_strings(dex: &[u8]) -> Result<String> {
+    anyhow::ensure!(dex.starts_with(b"dex\n"), "not a dex file");
+    let count = u32_at(dex, 56).context("truncated dex header")? as usize;
+    let ids = u32_at(dex, 60).context("truncated dex header")? as usize;
+    let mut out = String::new();
+    for i in 0..count {
+        let offset = u32_at(dex, ids + i * 4).context("string id out of range")? as usize;
+        // uleb128 length in UTF-16 code units
+        let mut pos = offset;
+        while dex.get(pos).context("string data out of range")? & 0x80 != 0 {
+            pos += 1;
+        }
+        pos += 1;
+        let len = memchr::memchr(0, dex.get(pos..).unwrap_or_default()).unwrap_or(0);
+        let s = decode_mutf8(&dex[pos..pos + len]);
+        if !s.trim().is_empty() {
+            // keep one string per line
+            writeln!(out, "{}", s.replace('\n', "\\n").replace('\r', "\\r"))?;
+        }
+    }
+    Ok(out)
+}
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_TABLE_TYPE: u16 = 0x0002;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_NAMESPACE_TYPE: u16 = 0x0100;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_CDATA_TYPE: u16 = 0x0104;
+const UTF8_FLAG: u32 = 0x100;
+
+/// Parse a string pool chunk of binary XML and resource tables
+fn string_pool(chunk: &[u8]) -> Vec<String> {
+    let (Some(count), Some(flags), Some(strings_start)) =
+        (u32_at(chunk, 8), u32_at(chunk, 16), u32_at(chunk, 20))
+    else {
+        return Vec::new();
+    };
+    let header_size = u16_at(chunk, 2).unwrap_or(28) as usize;
+    let utf8 = flags & UTF8_FLAG != 0;
+    (0..count as usize)
+        .map(|i| {
+            let offset = u32_at(chunk, header_size + i * 4)? as usize + strings_start as usize;
+            if utf8 {
+                // lengths in UTF-16 units and in bytes, each one or two bytes
+                let skip_len = |pos: usize| -> Option<(usize, usize)> {
+                    let b = *chunk.get(pos)? as usize;
+                    if b & 0x80 != 0 {
+                        Some((((b & 0x7f) << 8) | *chunk.get(pos + 1)? as usize, pos + 2))
+                    } else {
+                        Some((b, pos + 1))
+                    }
+                };
+                let (_, pos) = skip_len(offset)?;
+                let (len, pos) = skip_len(pos)?;
+                Some(String::from_utf8_lossy(chunk.get(pos..pos + len)?).into_owned())
+            } else {
+                let mut len = u16_at(chunk, offset)? as usize;
+                let mut pos = offset + 2;
+                if len & 0x8000 != 0 {
+                    len = ((len & 0x7fff) << 16) | u16_at(chunk, pos)? as usize;
+                    pos += 2;
+                }
+                Some(utf16(chunk.get(pos..pos + len * 2)?))
+            }
+        })
+        .map(Option::unwrap_or_default)
+        .collect()
+}
+
+/// Iterate over the chunks (type, chunk) within `data`
+fn chunks(data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let kind = u16_at(data, pos)?;
+        let size = u32_at(data, pos + 4)? as usize;
+        if size < 8 {
+            return None;
+        }
+        let chunk = data.get(pos..pos + size)?;
+        pos += size;
+        Some((kind, chunk))
+    })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format a typed attribute value (`Res_value`)
+fn typed_value(strings: &[String], data_type: u8, data: u32) -> String {
+    match data_type {
+        // string
+        0x03 => strings.get(data as usize).cloned().unwrap_or_default(),
+        // reference, attribute
+        0x01 => format!("@0x{data:08x}"),
+        0x02 => format!("?0x{data:08x}"),
+        0x04 => f32::from_bits(data).to_string(),
+        0x11 => format!("0x{data:x}"),
+        0x12 => (data != 0).to_string(),
+        0x1c..=0x1f => format!("#{data:08x}"),
+        _ => (data as i32).to_string(),
+    }
+}
+
+/// Decode a binary XML file (as compiled by aapt) to XML text
+fn binary_xml_to_text(data: &[u8]) -> Result<String> {
+    anyhow::ensure!(u16_at(data, 0) == Some(RES_XML_TYPE), "not binary XML");
+    let header_size = u16_at(data, 2).context("truncated")? as usize;
+    let mut strings = Vec::new();
+    // namespace uri -> prefix
+    let mut namespaces: HashMap<u32, String> = HashMap::new();
+    let mut pending_namespaces = Vec::new();
+    let mut depth = 0;
+    let mut out = String::new();
+    let string = |strings: &[String], i: u32| strings.get(i as usize).cloned().unwrap_or_default();
+    for (kind, chunk) in chunks(data.get(header_size..).unwrap_or_default()) {
+        match kind {
+            RES_STRING_POOL_TYPE => strings = string_pool(chunk),
+            RES_XML_START_NAMESPACE_TYPE => {
+                let (Some(prefix), Some(uri)) = (u32_at(chunk, 16), u32_at(chunk, 20)) else {
+                    continue;
+                };
+                let prefix = string(&strings, prefix);
+                pending_namespaces.push(format!(
+                    " xmlns:{prefix}=\"{}\"",
+                    escape(&string(&strings, uri))
+                ));
+                namespaces.insert(uri, prefix);
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let ext = 16;
+                let (Some(name), Some(attr_start), Some(attr_size), Some(attr_count)) = (
+                    u32_at(chunk, ext + 4),
+                    u16_at(chunk, ext + 8),
+                    u16_at(chunk, ext + 10),
+                    u16_at(chunk, ext + 12),
+                ) else {
+                    continue;
+                };
+                write!(out, "{}<{}", "  ".repeat(depth), string(&strings, name))?;
+                for ns in pending_namespaces.drain(..) {
+                    out.push_str(&ns);
+                }
+                for i in 0..attr_count as usize {
+                    let a = ext + attr_start as usize + i * attr_size as usize;
+                    let (Some(ns), Some(name), Some(raw), Some(data)) = (
+                        u32_at(chunk, a),
+                        u32_at(chunk, a + 4),
+                        u32_at(chunk, a + 8),
+                        u32_at(chunk, a + 16),
+                    ) else {
+                        break;
+                    };
+                    let data_type = chunk.get(a + 15).copied().unwrap_or(0);
+                    let value = if raw != u32::MAX {
+                        string(&strings, raw)
+                    } else {
+                        typed_value(&strings, data_type, data)
+                    };
+                    let prefix = namespaces
+                        .get(&ns)
+                        .map(|p| format!("{p}:"))
+                        .unwrap_or_default();
+                    write!(
+                        out,
+                        " {prefix}{}=\"{}\"",
+                        string(&strings, name),
+                        escape(&value)
+                    )?;
+                }
+                out.push_str(">\n");
+                depth += 1;
+            }
+            RES_XML_END_ELEMENT_TYPE => {
+                depth = depth.saturating_sub(1);
+                let name = u32_at(chunk, 16 + 4).unwrap_or(u32::MAX);
+                writeln!(out, "{}</{}>", "  ".repeat(depth), string(&strings, name))?;
+            }
+            RES_XML_CDATA_TYPE => {
+                let text = string(&strings, u32_at(chunk, 16).unwrap_or(u32::MAX));
+                if !text.trim().is_empty() {
+                    writeln!(out, "{}{}", "  ".repeat(depth), escape(text.trim()))?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// The strings of the global string pool of a resource table (resources.arsc), one per line
+fn resource_table_strings(data: &[u8]) -> Result<String> {
+    anyhow::ensure!(
+        u16_at(data, 0) == Some(RES_TABLE_TYPE),
+        "not a resource table"
+    );
+    let header_size = u16_at(data, 2).context("truncated")? as usize;
+    let mut out = String::new();
+    if let Some((_, pool)) = chunks(data.get(header_size..).unwrap_or_default())
+        .find(|(kind, _)| *kind == RES_STRING_POOL_TYPE)
+    {
+        for s in string_pool(pool) {
+            if !s.trim().is_empty() {
+                writeln!(out, "{}", s.replace('\n', "\\n").replace('\r', "\\r"))?;
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Decode the files of an apk that are stored in binary formats, other files are passed through
+async fn decode_member(mut ai: AdaptInfo) -> Result<AdaptInfo> {
+    let name = ai.filepath_hint.to_string_lossy().into_owned();
+    let decode: fn(&[u8]) -> Result<String> = if name == "resources.arsc" {
+        resource_table_strings
+    } else if name.ends_with(".xml") {
+        binary_xml_to_text
+    } else {
+        return Ok(ai);
+    };
+    let mut data = Vec::new();
+    ai.inp.read_to_end(&mut data).await?;
+    let text = match decode(&data) {
+        Ok(text) => text.into_bytes(),
+        // e.g. plain text XML files in assets/
+        Err(_) => data,
+    };
+    ai.inp = Box::pin(Cursor::new(text));
+    if name == "resources.arsc" {
+        ai.filepath_hint.set_extension("arsc.txt");
+    }
+    Ok(ai)
+}
+
+#[async_trait]
+impl FileAdapter for ApkAdapter {
+    async fn adapt(
+        &self,
+        mut ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        if ai.filepath_hint.extension().is_some_and(|e| e == "dex") {
+            let mut dex = Vec::new();
+            ai.inp.read_to_end(&mut dex).await?;
+            let text = dex_strings(&dex)?;
+            let mut filepath_hint = ai.filepath_hint.into_os_string();
+            filepath_hint.push(".txt");
+            return Ok(one_file(AdaptInfo {
+                filepath_hint: filepath_hint.into(),
+                is_real_file: false,
+                archive_recursion_depth: ai.archive_recursion_depth + 1,
+                inp: Box::pin(Cursor::new(text)),
+                ..ai
+            }));
+        }
+        let files = ZipAdapter::new().adapt(ai, detection_reason).await?;
+        let s = stream! {
+            for await file in files {
+                yield decode_member(file?).await;
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn chunk(kind: u16, header_size: u16, body: &[u8]) -> Vec<u8> {
+        let mut c = kind.to_le_bytes().to_vec();
+        c.extend_from_slice(&header_size.to_le_bytes());
+        c.extend_from_slice(&((body.len() + 8) as u32).to_le_bytes());
+        c.extend_from_slice(body);
+        c
+    }
+
+    fn utf16_pool(strings: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for s in strings {
+            offsets.push(data.len() as u32);
+            let units: Vec<u16> = s.encode_utf16().collect();
+            data.extend_from_slice(&(units.len() as u16).to_le_bytes());
+            for u in units.iter().chain([&0]) {
+                data.extend_from_slice(&u.to_le_bytes());
+            }
+        }
+        let header_size = 28u32;
+        let strings_start = header_size + 4 * strings.len() as u32;
+        let mut body = Vec::new();
+        for v in [strings.len() as u32, 0, 0, strings_start, 0] {
+            body.extend_from_slice(&v.to_le_bytes());
+        }
+        for o in offsets {
+            body.extend_from_slice(&o.to_le_bytes());
+        }
+        body.extend(data);
+        chunk(RES_STRING_POOL_TYPE, header_size as u16, &body)
+    }
+
+    fn node(kind: u16, ext: &[u32], attrs: &[[u32; 5]]) -> Vec<u8> {
+        // line number, comment
+        let mut body: Vec<u8> = [1u32, u32::MAX]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        for v in ext {
+            body.extend_from_slice(&v.to_le_bytes());
+        }
+        if kind == RES_XML_START_ELEMENT_TYPE {
+            // attribute start, size, count, id / class / style index
+            for v in [20u16, 20, attrs.len() as u16, 0, 0, 0] {
+                body.extend_from_slice(&v.to_le_bytes());
+            }
+            for [ns, name, raw, data_type, data] in attrs {
+                for v in [*ns, *name, *raw] {
+                    body.extend_from_slice(&v.to_le_bytes());
+                }
+                body.extend_from_slice(&[8, 0, 0, *data_type as u8]);
+                body.extend_from_slice(&data.to_le_bytes());
+            }
+        }
+        chunk(kind, 16, &body)
+    }
+
+    #[test]
+    fn manifest() -> Result<()> {
+        let none = u32::MAX;
+        let pool = utf16_pool(&[
+            "android",
+            "http://schemas.android.com/apk/res/android",
+            "manifest",
+            "package",
+            "com.example.app",
+            "versionCode",
+            "uses-permission",
+            "name",
+            "android.permission.INTERNET",
+        ]);
+        let mut body = pool;
+        body.extend(node(RES_XML_START_NAMESPACE_TYPE, &[0, 1], &[]));
+        body.extend(node(
+            RES_XML_START_ELEMENT_TYPE,
+            &[none, 2],
+            &[[none, 3, 4, 0x03, 4], [1, 5, none, 0x10, 42]],
+        ));
+        body.extend(node(
+            RES_XML_START_ELEMENT_TYPE,
+            &[none, 6],
+            &[[1, 7, 8, 0x03, 8]],
+        ));
+        body.extend(node(RES_XML_END_ELEMENT_TYPE, &[none, 6], &[]));
+        body.extend(node(RES_XML_END_ELEMENT_TYPE, &[none, 2], &[]));
+        let xml = chunk(RES_XML_TYPE, 8, &body);
+        assert_eq!(
+            binary_xml_to_text(&xml)?,
+            r#"<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.app" android:versionCode="42">
+  <uses-permission android:name="android.permission.INTERNET">
+  </uses-permission>
+</manifest>
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dex() -> Result<()> {
+        let mut dex = b"dex\n035\0".to_vec();
+        dex.resize(0x70, 0);
+        let strings: [&[u8]; 3] = [b"Lcom/example/Main;", b"Gr\xc3\xbc\xc3\x9fe", b"two\nlines"];
+        dex[56..60].copy_from_slice(&(strings.len() as u32).to_le_bytes());
+        dex[60..64].copy_from_slice(&0x70u32.to_le_bytes());
+        let mut data_offset = 0x70 + 4 * strings.len();
+        let mut data = Vec::new();
+        for s in strings {
+            dex.extend_from_slice(&((data_offset + data.len()) as u32).to_le_bytes());
+            data.push(s.len() as u8);
+            data.extend_from_slice(s);
+            data.push(0);
+        }
+        data_offset += data.len();
+        dex.extend(data);
+        assert_eq!(dex.len(), data_offset);
+        assert_eq!(
+            dex_strings(&dex)?,
+            "Lcom/example/Main;\nGrüße\ntwo\\nlines\n"
+        );
+        Ok(())
+    }
+}
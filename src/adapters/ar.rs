@@ -0,0 +1,261 @@
+//! Reading of Unix `ar` archives (static libraries, the outer layer of deb packages).
+//!
+//! Supports the GNU (long name table `//`) and BSD (`#1/len`) variants. Symbol tables are skipped.
+
+use crate::adapted_iter::{MemberHeaders, SequentialMember, sequential_members, skip_bytes};
+
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["a", "ar"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ar".to_owned(),
+        version: 1,
+        description: "Reads Unix ar archives (static libraries) and recurses into their members, e.g. object files.\nDebian packages are read by the deb adapter"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-archive".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ArAdapter;
+
+impl ArAdapter {
+    pub fn new() -> ArAdapter {
+        ArAdapter
+    }
+}
+impl GetMetadata for ArAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for ArAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+            ..
+        } = ai;
+        let members = sequential_members(inp, ArHeaders::new());
+        let s = stream! {
+            for await member in members {
+                let (path, inp) = member?;
+                yield Ok(AdaptInfo {
+                    line_prefix: format!("{line_prefix}{path}: "),
+                    filepath_hint: PathBuf::from(path),
+                    is_real_file: false,
+                    inp,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+pub(crate) const MAGIC: &[u8] = b"!<arch>\n";
+const THIN_MAGIC: &[u8] = b"!<thin>\n";
+const HEADER_SIZE: usize = 60;
+
+/// Reads the headers of an ar archive, see [crate::adapted_iter::sequential_members]
+#[derive(Default)]
+pub struct ArHeaders {
+    started: bool,
+    /// the GNU table of names longer than 15 bytes
+    long_names: Vec<u8>,
+}
+
+impl ArHeaders {
+    pub fn new() -> ArHeaders {
+        ArHeaders::default()
+    }
+
+    /// The name of a member given the name field of its header
+    fn long_name(&self, offset: &str) -> Result<String> {
+        let offset: usize = offset.parse().context("invalid ar long name offset")?;
+        let name = self
+            .long_names
+            .get(offset..)
+            .context("ar long name offset out of range")?;
+        let end = memchr::memchr(b'\n', name).unwrap_or(name.len());
+        let name = &name[..end];
+        Ok(String::from_utf8_lossy(name.strip_suffix(b"/").unwrap_or(name)).into_owned())
+    }
+}
+
+/// Read a header, returns false at the end of the input
+async fn read_header(inp: &mut ReadBox, header: &mut [u8]) -> Result<bool> {
+    let mut n = 0;
+    while n < header.len() {
+        let read = inp.read(&mut header[n..]).await?;
+        if read == 0 {
+            // some archivers add a newline after the last member
+            anyhow::ensure!(
+                header[..n].iter().all(|b| *b == b'\n'),
+                "truncated ar header"
+            );
+            return Ok(false);
+        }
+        n += read;
+    }
+    Ok(true)
+}
+
+fn field(header: &[u8], range: std::ops::Range<usize>) -> &str {
+    std::str::from_utf8(&header[range]).unwrap_or("").trim_end()
+}
+
+fn is_symbol_table(name: &str) -> bool {
+    matches!(name, "/" | "/SYM64/" | "__.SYMDEF" | "__.SYMDEF SORTED")
+}
+
+#[async_trait]
+impl MemberHeaders for ArHeaders {
+    async fn next_member(&mut self, inp: &mut ReadBox) -> Result<Option<SequentialMember>> {
+        if !self.started {
+            let mut magic = [0u8; 8];
+            inp.read_exact(&mut magic).await?;
+            anyhow::ensure!(
+                magic != THIN_MAGIC,
+                "thin ar archives (which only reference files) are not supported"
+            );
+            anyhow::ensure!(magic == MAGIC, "not an ar archive");
+            self.started = true;
+        }
+        loop {
+            let mut header = [0u8; HEADER_SIZE];
+            if !read_header(inp, &mut header).await? {
+                return Ok(None);
+            }
+            anyhow::ensure!(&header[58..60] == b"`\n", "invalid ar header");
+            let mut size: u64 = field(&header, 48..58)
+                .parse()
+                .context("invalid ar member size")?;
+            // data is aligned to 2 bytes
+            let padding = size % 2;
+            let name = field(&header, 0..16);
+            let name = if name == "//" {
+                let mut long_names = vec![0u8; size as usize];
+                inp.read_exact(&mut long_names).await?;
+                self.long_names = long_names;
+                skip_bytes(inp, padding).await?;
+                continue;
+            } else if let Some(len) = name.strip_prefix("#1/") {
+                // BSD: the name precedes the data
+                let len: u64 = len.parse().context("invalid ar name length")?;
+                anyhow::ensure!(len <= size, "invalid ar name length");
+                let mut name = vec![0u8; len as usize];
+                inp.read_exact(&mut name).await?;
+                size -= len;
+                let end = memchr::memchr(0, &name).unwrap_or(name.len());
+                String::from_utf8_lossy(&name[..end]).into_owned()
+            } else if is_symbol_table(name) {
+                name.to_string()
+            } else if let Some(offset) = name.strip_prefix('/') {
+                self.long_name(offset)?
+            } else {
+                // GNU terminates names with a slash
+                name.strip_suffix('/').unwrap_or(name).to_string()
+            };
+            if is_symbol_table(&name) {
+                skip_bytes(inp, size + padding).await?;
+                continue;
+            }
+            return Ok(Some(SequentialMember {
+                path: name,
+                size,
+                padding,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn members() -> Result<()> {
+        let mut ar = create_ar(&[
+            ("/", b"\0\0\0\0"),
+            ("hello.o", b"odd"),
+            ("a_rather_long_file_name.o", b"even"),
+        ]);
+        // BSD style name
+        ar.extend_from_slice(format!("{:<16}{:<32}{:<10}`\n", "#1/8", 0, 12).as_bytes());
+        ar.extend_from_slice(b"bsd.o\0\0\0data");
+        let mut files = Vec::new();
+        let mut members = sequential_members(Box::pin(Cursor::new(ar)), ArHeaders::new());
+        while let Some(member) = members.next().await {
+            let (name, mut inp) = member?;
+            let mut content = String::new();
+            inp.read_to_string(&mut content).await?;
+            files.push((name, content));
+        }
+        assert_eq!(
+            files,
+            [
+                ("hello.o".to_string(), "odd".to_string()),
+                ("a_rather_long_file_name.o".to_string(), "even".to_string()),
+                ("bsd.o".to_string(), "data".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn static_library() -> Result<()> {
+        let tar = std::fs::read(test_data_dir().join("hello.tar"))?;
+        let ar = create_ar(&[
+            ("/", b"\0\0\0\0"),
+            ("notes.txt", b"hello from the library\n"),
+            ("nested.tar", &tar),
+        ]);
+        let (a, d) = simple_adapt_info(&PathBuf::from("libhello.a"), Box::pin(Cursor::new(ar)));
+        let buf = adapted_to_vec(loop_adapt(&ArAdapter::new(), d, a).await?).await?;
+        let out = String::from_utf8(buf)?;
+        assert_eq!(
+            out.lines().take(3).collect::<Vec<_>>(),
+            [
+                "PREFIX:notes.txt: hello from the library",
+                "PREFIX:notes.txt: ",
+                "PREFIX:nested.tar: dir/file-b.pdf: Page 1: hello world"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+use super::binary::read_all;
+use super::parquet::{Row, RowWriter};
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use async_compression::tokio::bufread::{BzDecoder, DeflateDecoder, XzDecoder, ZstdDecoder};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["avro"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "avro".to_owned(),
+        version: 1,
+        description: "Reads Avro container files and outputs each record as `row N: field=value, ...`, like the parquet adapter.\nThe number of rows is limited by --rga-max-rows"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct AvroAdapter;
+
+impl AvroAdapter {
+    pub fn new() -> AvroAdapter {
+        AvroAdapter
+    }
+}
+
+impl GetMetadata for AvroAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAGIC: &[u8] = b"Obj\x01";
+const SYNC_LEN: usize = 16;
+
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    /// reference to a record, enum or fixed type defined before
+    Named(String),
+}
+
+/// Parses the JSON schema of the file, collecting the named types
+struct SchemaParser {
+    named: HashMap<String, Schema>,
+}
+
+impl SchemaParser {
+    fn parse(&mut self, json: &Value, namespace: &str) -> Result<Schema> {
+        Ok(match json {
+            Value::String(name) => self.primitive(name),
+            Value::Array(types) => Schema::Union(
+                types
+                    .iter()
+                    .map(|t| self.parse(t, namespace))
+                    .collect::<Result<_>>()?,
+            ),
+            Value::Object(o) => {
+                let kind = o.get("type").context("avro type without type")?;
+                let Some(kind) = kind.as_str() else {
+                    // {"type": {"type": "array", ...}}
+                    return self.parse(kind, namespace);
+                };
+                let name = o.get("name").and_then(Value::as_str).unwrap_or_default();
+                let namespace = match o.get("namespace").and_then(Value::as_str) {
+                    Some(ns) => ns,
+                    // a name with dots contains its namespace
+                    None => name.rsplit_once('.').map_or(namespace, |(ns, _)| ns),
+                };
+                let schema = match kind {
+                    "record" | "error" => {
+                        // register before parsing the fields, which may refer to the record
+                        self.register(name, namespace, Schema::Null);
+                        let fields = o
+                            .get("fields")
+                            .and_then(Value::as_array)
+                            .context("avro record without fields")?;
+                        Schema::Record(
+                            fields
+                                .iter()
+                                .map(|f| {
+                                    let name = f["name"].as_str().unwrap_or_default().to_string();
+                                    Ok((name, self.parse(&f["type"], namespace)?))
+                                })
+                                .collect::<Result<_>>()?,
+                        )
+                    }
+                    "enum" => Schema::Enum(
+                        o.get("symbols")
+                            .and_then(Value::as_array)
+                            .context("avro enum without symbols")?
+                            .iter()
+                            .map(|s| s.as_str().unwrap_or_default().to_string())
+                            .collect(),
+                    ),
+                    "array" => Schema::Array(Box::new(self.parse(
+                        o.get("items").context("avro array without items")?,
+                        namespace,
+                    )?)),
+                    "map" => Schema::Map(Box::new(self.parse(
+                        o.get("values").context("avro map without values")?,
+                        namespace,
+                    )?)),
+                    "fixed" => Schema::Fixed(
+                        o.get("size")
+                            .and_then(Value::as_u64)
+                            .context("avro fixed without size")?
+                            .try_into()?,
+                    ),
+                    // primitive with attributes, e.g. logical types
+                    primitive => return Ok(self.primitive(primitive)),
+                };
+                if matches!(kind, "record" | "error" | "enum" | "fixed") {
+                    return Ok(self.register(name, namespace, schema));
+                }
+                schema
+            }
+            _ => anyhow::bail!("invalid avro schema {json}"),
+        })
+    }
+
+    fn primitive(&self, name: &str) -> Schema {
+        match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            name => Schema::Named(name.to_string()),
+        }
+    }
+
+    /// Store a named type and return a reference to it
+    fn register(&mut self, name: &str, namespace: &str, schema: Schema) -> Schema {
+        let short = name.rsplit('.').next().unwrap_or(name);
+        let full = if namespace.is_empty() {
+            short.to_string()
+        } else {
+            format!("{namespace}.{short}")
+        };
+        self.named.insert(full.clone(), schema);
+        Schema::Named(full)
+    }
+
+    fn resolve<'a>(&'a self, name: &str) -> Result<&'a Schema> {
+        let short = name.rsplit('.').next().unwrap_or(name);
+        self.named
+            .get(name)
+            .or_else(|| {
+                // references without namespace
+                self.named
+                    .iter()
+                    .find(|(full, _)| full.rsplit('.').next() == Some(short))
+                    .map(|(_, s)| s)
+            })
+            .with_context(|| format!("unknown avro type {name}"))
+    }
+}
+
+/// Reads the binary encoding of avro values
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("unexpected end of avro data")?;
+        self.pos += len;
+        Ok(b)
+    }
+
+    /// A zigzag encoded variable length integer
+    fn long(&mut self) -> Result<i64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.bytes(1)?[0];
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+        anyhow::bail!("invalid avro integer")
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.long()?.try_into()?)
+    }
+
+    fn byte_string(&mut self) -> Result<&'a [u8]> {
+        let len = self.len()?;
+        self.bytes(len)
+    }
+
+    /// Arrays and maps are encoded as blocks of items, ending with an empty block
+    fn block_len(&mut self) -> Result<usize> {
+        let count = self.long()?;
+        if count < 0 {
+            // followed by the size of the block in bytes
+            self.long()?;
+        }
+        Ok(count.unsigned_abs().try_into()?)
+    }
+
+    fn value(&mut self, schema: &Schema, types: &SchemaParser) -> Result<Value> {
+        Ok(match schema {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Bool(self.bytes(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::from(self.long()?),
+            Schema::Float => Value::from(f32::from_le_bytes(self.bytes(4)?.try_into()?)),
+            Schema::Double => Value::from(f64::from_le_bytes(self.bytes(8)?.try_into()?)),
+            Schema::Bytes | Schema::String => {
+                Value::String(String::from_utf8_lossy(self.byte_string()?).into_owned())
+            }
+            Schema::Record(fields) => {
+                let mut o = serde_json::Map::new();
+                for (name, schema) in fields {
+                    o.insert(name.clone(), self.value(schema, types)?);
+                }
+                Value::Object(o)
+            }
+            Schema::Enum(symbols) => {
+                let i = self.len()?;
+                Value::String(symbols.get(i).cloned().unwrap_or_else(|| i.to_string()))
+            }
+            Schema::Array(items) => {
+                let mut values = Vec::new();
+                loop {
+                    let len = self.block_len()?;
+                    if len == 0 {
+                        break;
+                    }
+                    for _ in 0..len {
+                        values.push(self.value(items, types)?);
+                    }
+                }
+                Value::Array(values)
+            }
+            Schema::Map(values) => {
+                let mut o = serde_json::Map::new();
+                loop {
+                    let len = self.block_len()?;
+                    if len == 0 {
+                        break;
+                    }
+                    for _ in 0..len {
+                        let key = String::from_utf8_lossy(self.byte_string()?).into_owned();
+                        o.insert(key, self.value(values, types)?);
+                    }
+                }
+                Value::Object(o)
+            }
+            Schema::Union(variants) => {
+                let i = self.len()?;
+                let variant = variants.get(i).context("invalid avro union index")?;
+                self.value(variant, types)?
+            }
+            Schema::Fixed(size) => {
+                Value::String(String::from_utf8_lossy(self.bytes(*size)?).into_owned())
+            }
+            Schema::Named(name) => self.value(types.resolve(name)?, types)?,
+        })
+    }
+
+    /// A record as a row, with the fields in the order of the schema
+    fn row(&mut self, schema: &Schema, types: &SchemaParser) -> Result<Row> {
+        let schema = match schema {
+            Schema::Named(name) => types.resolve(name)?,
+            schema => schema,
+        };
+        Ok(match schema {
+            Schema::Record(fields) => Row(fields
+                .iter()
+                .map(|(name, schema)| Ok((name.clone(), self.value(schema, types)?)))
+                .collect::<Result<_>>()?),
+            schema => Row(vec![("value".to_string(), self.value(schema, types)?)]),
+        })
+    }
+}
+
+/// Decompress a raw snappy block (without framing)
+pub fn snappy_decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut d = Decoder { data, pos: 0 };
+    // the length is not zigzag encoded
+    let mut len = 0usize;
+    for shift in (0..35).step_by(7) {
+        let b = d.bytes(1)?[0];
+        len |= ((b & 0x7f) as usize) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut out = Vec::with_capacity(len);
+    while d.pos < data.len() {
+        let tag = d.bytes(1)?[0];
+        let (len, offset) = match tag & 3 {
+            0 => {
+                let mut len = (tag >> 2) as usize;
+                if len >= 60 {
+                    let n = len - 59;
+                    len = d
+                        .bytes(n)?
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, b| (acc << 8) | *b as usize);
+                }
+                out.extend_from_slice(d.bytes(len + 1)?);
+                continue;
+            }
+            1 => (
+                ((tag >> 2) & 7) as usize + 4,
+                (((tag >> 5) as usize) << 8) | d.bytes(1)?[0] as usize,
+            ),
+            2 => (
+                (tag >> 2) as usize + 1,
+                u16::from_le_bytes(d.bytes(2)?.try_into()?) as usize,
+            ),
+            _ => (
+                (tag >> 2) as usize + 1,
+                u32::from_le_bytes(d.bytes(4)?.try_into()?) as usize,
+            ),
+        };
+        anyhow::ensure!(
+            offset > 0 && offset <= out.len(),
+            "invalid snappy copy offset"
+        );
+        // copies may overlap with their own output
+        let start = out.len() - offset;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+    Ok(out)
+}
+
+async fn decompress(codec: &str, block: &[u8]) -> Result<Vec<u8>> {
+    Ok(match codec {
+        "null" => block.to_vec(),
+        "deflate" => read_all(DeflateDecoder::new(block)).await?,
+        "zstandard" => read_all(ZstdDecoder::new(block)).await?,
+        "bzip2" => read_all(BzDecoder::new(block)).await?,
+        "xz" => read_all(XzDecoder::new(block)).await?,
+        // followed by the CRC32 of the uncompressed data
+        "snappy" => snappy_decompress(&block[..block.len().saturating_sub(4)])?,
+        codec => anyhow::bail!("unsupported avro codec {codec}"),
+    })
+}
+
+#[async_trait]
+impl WritingFileAdapter for AvroAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut d = Decoder {
+            data: &data,
+            pos: 0,
+        };
+        anyhow::ensure!(d.bytes(4).ok() == Some(MAGIC), "not an avro container file");
+        let mut meta = HashMap::new();
+        loop {
+            let len = d.block_len()?;
+            if len == 0 {
+                break;
+            }
+            for _ in 0..len {
+                let key = String::from_utf8_lossy(d.byte_string()?).into_owned();
+                meta.insert(key, d.byte_string()?);
+            }
+        }
+        let schema: Value = serde_json::from_slice(
+            meta.get("avro.schema")
+                .context("avro file without schema")?,
+        )
+        .context("invalid avro schema")?;
+        let codec = meta
+            .get("avro.codec")
+            .map(|c| String::from_utf8_lossy(c).into_owned())
+            .unwrap_or_else(|| "null".to_string());
+        let sync = d.bytes(SYNC_LEN)?;
+        let mut types = SchemaParser {
+            named: HashMap::new(),
+        };
+        let schema = types.parse(&schema, "")?;
+        let mut rows = RowWriter::new(line_prefix, &config);
+        while d.pos < data.len() {
+            let count = d.len()?;
+            let block = d.byte_string()?;
+            anyhow::ensure!(d.bytes(SYNC_LEN)? == sync, "avro sync marker mismatch");
+            let block = decompress(&codec, block).await?;
+            let mut records = Decoder {
+                data: &block,
+                pos: 0,
+            };
+            for _ in 0..count {
+                let row = records.row(&schema, &types)?;
+                if !rows.write(&row.text(), &mut oup).await? {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    fn long(v: i64) -> Vec<u8> {
+        let mut z = ((v << 1) ^ (v >> 63)) as u64;
+        let mut out = Vec::new();
+        loop {
+            let b = (z & 0x7f) as u8;
+            z >>= 7;
+            if z == 0 {
+                out.push(b);
+                return out;
+            }
+            out.push(b | 0x80);
+        }
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut out = long(s.len() as i64);
+        out.extend_from_slice(s.as_bytes());
+        out
+    }
+
+    #[tokio::test]
+    async fn records() -> Result<()> {
+        let schema = r#"{"type": "record", "name": "User", "namespace": "example", "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "name", "type": "string"},
+            {"name": "email", "type": ["null", "string"]},
+            {"name": "role", "type": {"type": "enum", "name": "Role", "symbols": ["ADMIN", "USER"]}},
+            {"name": "tags", "type": {"type": "array", "items": "string"}}
+        ]}"#;
+        let mut file = MAGIC.to_vec();
+        file.extend(long(1));
+        file.extend(string("avro.schema"));
+        file.extend(string(schema));
+        file.extend(long(0));
+        let sync = [7u8; SYNC_LEN];
+        file.extend(sync);
+        let mut block = Vec::new();
+        // id, name, email (union index 1), role, tags
+        block.extend(long(10432));
+        block.extend(string("Alice"));
+        block.extend(long(1));
+        block.extend(string("alice@example.com"));
+        block.extend(long(0));
+        block.extend(long(2));
+        block.extend(string("a"));
+        block.extend(string("b"));
+        block.extend(long(0));
+        // second record without email and tags
+        block.extend(long(7));
+        block.extend(string("Bob"));
+        block.extend(long(0));
+        block.extend(long(1));
+        block.extend(long(0));
+        file.extend(long(2));
+        file.extend(long(block.len() as i64));
+        file.extend(block);
+        file.extend(sync);
+
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("users.avro"),
+            Box::pin(std::io::Cursor::new(file)),
+        );
+        a.config.max_rows.0 = 1;
+        let adapter: Box<dyn FileAdapter> = Box::<AvroAdapter>::default();
+        let res = adapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            r#"PREFIX:row 1: id=10432, name=Alice, email=alice@example.com, role=ADMIN, tags=["a","b"]
+PREFIX:[rga: only the first 1 rows are output]
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn snappy() -> Result<()> {
+        // "abcabcabcabc": literal "abc" and a copy of 9 bytes at offset 3
+        let compressed = [12, 2 << 2, b'a', b'b', b'c', 1 | (5 << 2), 3];
+        assert_eq!(snappy_decompress(&compressed)?, b"abcabcabcabc");
+        Ok(())
+    }
+}
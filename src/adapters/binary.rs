@@ -0,0 +1,106 @@
+//! Reading numbers and strings from binary file formats. The number readers return None if the value is not within the data.
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub fn bytes_at<const N: usize>(b: &[u8], pos: usize) -> Option<[u8; N]> {
+    b.get(pos..pos.checked_add(N)?)?.try_into().ok()
+}
+
+/// Byte order of the numbers in a file format
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    pub fn u16_at(self, b: &[u8], pos: usize) -> Option<u16> {
+        bytes_at(b, pos).map(match self {
+            Endian::Little => u16::from_le_bytes,
+            Endian::Big => u16::from_be_bytes,
+        })
+    }
+
+    pub fn u32_at(self, b: &[u8], pos: usize) -> Option<u32> {
+        bytes_at(b, pos).map(match self {
+            Endian::Little => u32::from_le_bytes,
+            Endian::Big => u32::from_be_bytes,
+        })
+    }
+
+    pub fn u64_at(self, b: &[u8], pos: usize) -> Option<u64> {
+        bytes_at(b, pos).map(match self {
+            Endian::Little => u64::from_le_bytes,
+            Endian::Big => u64::from_be_bytes,
+        })
+    }
+
+    /// UTF-16 text, with invalid characters replaced
+    pub fn utf16(self, b: &[u8]) -> String {
+        let units: Vec<u16> = b
+            .chunks_exact(2)
+            .filter_map(|u| self.u16_at(u, 0))
+            .collect();
+        String::from_utf16_lossy(&units)
+    }
+}
+
+pub fn u16_at(b: &[u8], pos: usize) -> Option<u16> {
+    Endian::Little.u16_at(b, pos)
+}
+
+pub fn u32_at(b: &[u8], pos: usize) -> Option<u32> {
+    Endian::Little.u32_at(b, pos)
+}
+
+pub fn u64_at(b: &[u8], pos: usize) -> Option<u64> {
+    Endian::Little.u64_at(b, pos)
+}
+
+pub fn f64_at(b: &[u8], pos: usize) -> Option<f64> {
+    u64_at(b, pos).map(f64::from_bits)
+}
+
+pub fn u16_be(b: &[u8], pos: usize) -> Option<u16> {
+    Endian::Big.u16_at(b, pos)
+}
+
+pub fn u32_be(b: &[u8], pos: usize) -> Option<u32> {
+    Endian::Big.u32_at(b, pos)
+}
+
+/// Little endian UTF-16 text, as used by Windows
+pub fn utf16(b: &[u8]) -> String {
+    Endian::Little.utf16(b)
+}
+
+/// Reads all of a (decompressed) stream into memory
+pub async fn read_all(mut inp: impl AsyncRead + Unpin) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    inp.read_to_end(&mut out).await?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn bounds() {
+        let b = [1, 2, 3, 4, 5];
+        assert_eq!(u16_at(&b, 3), Some(0x0504));
+        assert_eq!(u32_be(&b, 1), Some(0x02030405));
+        assert_eq!(u32_at(&b, 2), None);
+        assert_eq!(u64_at(&b, usize::MAX - 2), None);
+        assert_eq!(Endian::Big.u16_at(&b, 3), Some(0x0405));
+        assert_eq!(Endian::Little.u64_at(&b, 0), None);
+    }
+
+    #[test]
+    fn utf16_text() {
+        assert_eq!(utf16(b"a\0b\0c"), "ab");
+        assert_eq!(Endian::Big.utf16(b"\0a\xd8\0"), "a\u{fffd}");
+    }
+}
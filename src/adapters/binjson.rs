@@ -0,0 +1,592 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use crate::print_unix_time;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["msgpack", "mpk", "cbor", "bson"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "binjson".to_owned(),
+        version: 1,
+        description: "Decodes the binary serialization formats MessagePack, CBOR and BSON and outputs the values as pretty-printed JSON. Files with several values (e.g. mongodump output) are output one value after the other. Binary data is only output if it is text"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/msgpack".to_owned()),
+            FileMatcher::MimeType("application/cbor".to_owned()),
+            FileMatcher::MimeType("application/bson".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct BinJsonAdapter;
+
+impl BinJsonAdapter {
+    pub fn new() -> BinJsonAdapter {
+        BinJsonAdapter
+    }
+}
+
+impl GetMetadata for BinJsonAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Maximum nesting of arrays and maps, deeper values are most likely garbage
+const MAX_DEPTH: usize = 256;
+
+/// A decoded value. Unlike `serde_json::Value`, maps keep their order and can have any keys
+#[derive(Debug, PartialEq)]
+enum Item {
+    Null,
+    Bool(bool),
+    Int(i128),
+    Float(f64),
+    Text(String),
+    Binary(Vec<u8>),
+    Array(Vec<Item>),
+    Map(Vec<(Item, Item)>),
+}
+
+impl Item {
+    fn time(secs: i64) -> Item {
+        Item::Text(print_unix_time(secs))
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("unexpected end of data")?;
+        self.pos += len;
+        Ok(b)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.bytes(N)?.try_into()?)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Big endian unsigned integer of `len` bytes
+    fn uint(&mut self, len: usize) -> Result<u64> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    fn text(&mut self, len: usize) -> Result<Item> {
+        Ok(Item::Text(
+            String::from_utf8_lossy(self.bytes(len)?).into_owned(),
+        ))
+    }
+
+    fn msgpack(&mut self, depth: usize) -> Result<Item> {
+        anyhow::ensure!(depth < MAX_DEPTH, "nested too deeply");
+        let b = self.byte()?;
+        let len = |d: &mut Self, bytes: usize| -> Result<usize> { Ok(d.uint(bytes)?.try_into()?) };
+        Ok(match b {
+            0x00..=0x7f => Item::Int(b.into()),
+            0x80..=0x8f => self.msgpack_map((b & 0x0f).into(), depth)?,
+            0x90..=0x9f => self.msgpack_array((b & 0x0f).into(), depth)?,
+            0xa0..=0xbf => self.text((b & 0x1f).into())?,
+            0xc0 => Item::Null,
+            0xc2 => Item::Bool(false),
+            0xc3 => Item::Bool(true),
+            0xc4..=0xc6 => {
+                let n = len(self, 1 << (b - 0xc4))?;
+                Item::Binary(self.bytes(n)?.to_vec())
+            }
+            0xc7..=0xc9 => {
+                let n = len(self, 1 << (b - 0xc7))?;
+                self.msgpack_ext(n)?
+            }
+            0xca => Item::Float(f32::from_be_bytes(self.array()?).into()),
+            0xcb => Item::Float(f64::from_be_bytes(self.array()?)),
+            0xcc..=0xcf => Item::Int(self.uint(1 << (b - 0xcc))?.into()),
+            0xd0 => Item::Int(i8::from_be_bytes(self.array()?).into()),
+            0xd1 => Item::Int(i16::from_be_bytes(self.array()?).into()),
+            0xd2 => Item::Int(i32::from_be_bytes(self.array()?).into()),
+            0xd3 => Item::Int(i64::from_be_bytes(self.array()?).into()),
+            0xd4..=0xd8 => self.msgpack_ext(1 << (b - 0xd4))?,
+            0xd9..=0xdb => {
+                let n = len(self, 1 << (b - 0xd9))?;
+                self.text(n)?
+            }
+            0xdc | 0xdd => {
+                let n = len(self, 2 << (b - 0xdc))?;
+                self.msgpack_array(n, depth)?
+            }
+            0xde | 0xdf => {
+                let n = len(self, 2 << (b - 0xde))?;
+                self.msgpack_map(n, depth)?
+            }
+            0xe0..=0xff => Item::Int((b as i8).into()),
+            0xc1 => anyhow::bail!("invalid msgpack byte 0xc1"),
+        })
+    }
+
+    fn msgpack_array(&mut self, len: usize, depth: usize) -> Result<Item> {
+        (0..len)
+            .map(|_| self.msgpack(depth + 1))
+            .collect::<Result<_>>()
+            .map(Item::Array)
+    }
+
+    fn msgpack_map(&mut self, len: usize, depth: usize) -> Result<Item> {
+        (0..len)
+            .map(|_| Ok((self.msgpack(depth + 1)?, self.msgpack(depth + 1)?)))
+            .collect::<Result<_>>()
+            .map(Item::Map)
+    }
+
+    /// Extension types, of which only timestamps (-1) are decoded
+    fn msgpack_ext(&mut self, len: usize) -> Result<Item> {
+        let kind = self.byte()? as i8;
+        let data = self.bytes(len)?;
+        Ok(match (kind, data.len()) {
+            (-1, 4) => Item::time(u32::from_be_bytes(data.try_into()?).into()),
+            // 30 bits nanoseconds, 34 bits seconds
+            (-1, 8) => Item::time((u64::from_be_bytes(data.try_into()?) & 0x3_ffff_ffff) as i64),
+            (-1, 12) => Item::time(i64::from_be_bytes(data[4..].try_into()?)),
+            _ => Item::Binary(data.to_vec()),
+        })
+    }
+
+    fn cbor(&mut self, depth: usize) -> Result<Item> {
+        anyhow::ensure!(depth < MAX_DEPTH, "nested too deeply");
+        let b = self.byte()?;
+        let (major, info) = (b >> 5, b & 0x1f);
+        let arg = match info {
+            0..=23 => Some(info.into()),
+            24..=27 => Some(self.uint(1 << (info - 24))?),
+            31 => None,
+            _ => anyhow::bail!("invalid cbor byte 0x{b:02x}"),
+        };
+        Ok(match major {
+            0 => Item::Int(arg.context("unexpected indefinite length")?.into()),
+            1 => Item::Int(-1 - arg.context("invalid negative integer")? as i128),
+            2 | 3 => {
+                let data = match arg {
+                    Some(n) => self.bytes(n.try_into()?)?.to_vec(),
+                    // chunks of definite length until the break
+                    None => {
+                        let mut data = Vec::new();
+                        while self.data.get(self.pos) != Some(&0xff) {
+                            match self.cbor(depth + 1)? {
+                                Item::Binary(chunk) => data.extend(chunk),
+                                Item::Text(chunk) => data.extend(chunk.into_bytes()),
+                                _ => anyhow::bail!("invalid cbor string chunk"),
+                            }
+                        }
+                        self.pos += 1;
+                        data
+                    }
+                };
+                if major == 2 {
+                    Item::Binary(data)
+                } else {
+                    Item::Text(String::from_utf8_lossy(&data).into_owned())
+                }
+            }
+            4 | 5 => {
+                let mut items = Vec::new();
+                let count = if major == 5 { 2 } else { 1 };
+                match arg {
+                    Some(n) => {
+                        for _ in 0..n.saturating_mul(count) {
+                            items.push(self.cbor(depth + 1)?);
+                        }
+                    }
+                    None => {
+                        while self.data.get(self.pos) != Some(&0xff) {
+                            items.push(self.cbor(depth + 1)?);
+                        }
+                        self.pos += 1;
+                    }
+                }
+                if major == 4 {
+                    Item::Array(items)
+                } else {
+                    let mut items = items.into_iter();
+                    let mut pairs = Vec::new();
+                    while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                        pairs.push((k, v));
+                    }
+                    Item::Map(pairs)
+                }
+            }
+            6 => {
+                let content = self.cbor(depth + 1)?;
+                match (arg, content) {
+                    // epoch-based date/time
+                    (Some(1), Item::Int(secs)) => Item::time(secs.try_into()?),
+                    (Some(1), Item::Float(secs)) => Item::time(secs as i64),
+                    // other tags (date strings, big numbers, ...) as their content
+                    (_, content) => content,
+                }
+            }
+            _ => match (info, arg) {
+                (20, _) => Item::Bool(false),
+                (21, _) => Item::Bool(true),
+                (22 | 23, _) => Item::Null,
+                (25, Some(bits)) => Item::Float(half_to_f64(bits as u16)),
+                (26, Some(bits)) => Item::Float(f32::from_bits(bits as u32).into()),
+                (27, Some(bits)) => Item::Float(f64::from_bits(bits)),
+                (_, arg) => Item::Int(arg.unwrap_or_default().into()),
+            },
+        })
+    }
+
+    fn bson_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn cstring(&mut self) -> Result<String> {
+        let rest = &self.data[self.pos.min(self.data.len())..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .context("unterminated bson string")?;
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += len + 1;
+        Ok(s)
+    }
+
+    fn bson_string(&mut self) -> Result<String> {
+        let len: usize = self.bson_i32()?.try_into()?;
+        let data = self.bytes(len)?;
+        Ok(String::from_utf8_lossy(data.strip_suffix(&[0]).unwrap_or(data)).into_owned())
+    }
+
+    /// A document, whose keys are dropped for arrays
+    fn bson_document(&mut self, depth: usize, is_array: bool) -> Result<Item> {
+        anyhow::ensure!(depth < MAX_DEPTH, "nested too deeply");
+        let start = self.pos;
+        let len: usize = self.bson_i32()?.try_into()?;
+        let end = start.saturating_add(len);
+        anyhow::ensure!(end <= self.data.len(), "bson document exceeds the data");
+        let mut fields = Vec::new();
+        loop {
+            let kind = self.byte()?;
+            if kind == 0 {
+                break;
+            }
+            let name = self.cstring()?;
+            let value = match kind {
+                0x01 => Item::Float(f64::from_le_bytes(self.array()?)),
+                0x02 | 0x0d | 0x0e => Item::Text(self.bson_string()?),
+                0x03 => self.bson_document(depth + 1, false)?,
+                0x04 => self.bson_document(depth + 1, true)?,
+                0x05 => {
+                    let len: usize = self.bson_i32()?.try_into()?;
+                    let subtype = self.byte()?;
+                    let data = self.bytes(len)?;
+                    match subtype {
+                        // uuid
+                        0x03 | 0x04 if len == 16 => Item::Text(hex(data)),
+                        _ => Item::Binary(data.to_vec()),
+                    }
+                }
+                0x06 | 0x0a | 0xff | 0x7f => Item::Null,
+                0x07 => Item::Text(hex(self.bytes(12)?)),
+                0x08 => Item::Bool(self.byte()? != 0),
+                0x09 => Item::time(i64::from_le_bytes(self.array()?).div_euclid(1000)),
+                0x0b => {
+                    let pattern = self.cstring()?;
+                    let flags = self.cstring()?;
+                    Item::Text(format!("/{pattern}/{flags}"))
+                }
+                0x0c => {
+                    let collection = self.bson_string()?;
+                    Item::Text(format!("{collection}/{}", hex(self.bytes(12)?)))
+                }
+                0x0f => {
+                    self.bson_i32()?;
+                    let code = self.bson_string()?;
+                    let scope = self.bson_document(depth + 1, false)?;
+                    Item::Map(vec![
+                        (Item::Text("code".to_string()), Item::Text(code)),
+                        (Item::Text("scope".to_string()), scope),
+                    ])
+                }
+                0x10 => Item::Int(self.bson_i32()?.into()),
+                // internal mongodb timestamp: increment and seconds
+                0x11 => Item::time(u32::from_le_bytes(self.bytes(8)?[4..].try_into()?).into()),
+                0x12 => Item::Int(i64::from_le_bytes(self.array()?).into()),
+                0x13 => Item::Text(format!("decimal128:{}", hex(self.bytes(16)?))),
+                kind => anyhow::bail!("unknown bson type 0x{kind:02x}"),
+            };
+            fields.push((Item::Text(name), value));
+        }
+        anyhow::ensure!(self.pos == end, "invalid bson document length");
+        Ok(if is_array {
+            Item::Array(fields.into_iter().map(|(_, v)| v).collect())
+        } else {
+            Item::Map(fields)
+        })
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// IEEE 754 half precision float
+fn half_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exp = ((bits >> 10) & 0x1f) as i32;
+    let mant = (bits & 0x3ff) as f64;
+    sign * match exp {
+        0 => mant * 2f64.powi(-24),
+        31 if mant == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        exp => (1.0 + mant / 1024.0) * 2f64.powi(exp - 15),
+    }
+}
+
+fn compact(item: &Item) -> String {
+    let mut out = String::new();
+    write_json(item, None, &mut out);
+    out
+}
+
+/// JSON of the item, pretty-printed with the given indentation level or compact
+fn write_json(item: &Item, indent: Option<usize>, out: &mut String) {
+    let quote = |s: &str| serde_json::Value::from(s).to_string();
+    match item {
+        Item::Null => out.push_str("null"),
+        Item::Bool(b) => out.push_str(&b.to_string()),
+        Item::Int(i) => out.push_str(&i.to_string()),
+        // NaN and infinity as null
+        Item::Float(f) => out.push_str(&serde_json::Value::from(*f).to_string()),
+        Item::Text(s) => out.push_str(&quote(s)),
+        Item::Binary(b) => match std::str::from_utf8(b) {
+            Ok(s) if !s.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                out.push_str(&quote(s))
+            }
+            _ => out.push_str(&quote(&format!("<{} bytes>", b.len()))),
+        },
+        Item::Array(items) if items.is_empty() => out.push_str("[]"),
+        Item::Map(items) if items.is_empty() => out.push_str("{}"),
+        Item::Array(_) | Item::Map(_) => {
+            let (open, close) = if matches!(item, Item::Array(_)) {
+                ('[', ']')
+            } else {
+                ('{', '}')
+            };
+            let entries: Vec<(Option<&Item>, &Item)> = match item {
+                Item::Array(items) => items.iter().map(|v| (None, v)).collect(),
+                Item::Map(items) => items.iter().map(|(k, v)| (Some(k), v)).collect(),
+                _ => unreachable!(),
+            };
+            out.push(open);
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                if let Some(indent) = indent {
+                    out.push('\n');
+                    out.push_str(&"  ".repeat(indent + 1));
+                }
+                if let Some(key) = key {
+                    match key {
+                        Item::Text(k) => out.push_str(&quote(k)),
+                        key => out.push_str(&quote(&compact(key))),
+                    }
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                }
+                write_json(value, indent.map(|i| i + 1), out);
+            }
+            if let Some(indent) = indent {
+                out.push('\n');
+                out.push_str(&"  ".repeat(indent));
+            }
+            out.push(close);
+        }
+    }
+}
+
+/// The values of the file, which may contain several values one after the other
+fn decode(data: &[u8], format: &str) -> Result<Vec<Item>> {
+    let mut d = Decoder { data, pos: 0 };
+    let mut items = Vec::new();
+    while !d.at_end() {
+        let item = match format {
+            "bson" => d.bson_document(0, false)?,
+            "cbor" => d.cbor(0)?,
+            _ => d.msgpack(0)?,
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn format_of(detection_reason: &FileMatcher) -> &str {
+    match detection_reason {
+        FileMatcher::Fast(FastFileMatcher::FileExtension(ext)) => match ext.as_str() {
+            "cbor" => "cbor",
+            "bson" => "bson",
+            _ => "msgpack",
+        },
+        FileMatcher::MimeType(mime) => match mime.as_str() {
+            "application/cbor" => "cbor",
+            "application/bson" => "bson",
+            _ => "msgpack",
+        },
+        _ => "msgpack",
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for BinJsonAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let format = format_of(detection_reason);
+        let items = decode(&data, format).with_context(|| format!("invalid {format} data"))?;
+        let mut out = String::new();
+        for item in items {
+            let mut json = String::new();
+            write_json(&item, Some(0), &mut json);
+            for line in json.lines() {
+                out.push_str(&format!("{line_prefix}{line}\n"));
+            }
+        }
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    const EXPECTED: &str = r#"PREFIX:{
+PREFIX:  "name": "Jürgen",
+PREFIX:  "age": 42,
+PREFIX:  "tags": [
+PREFIX:    "admin",
+PREFIX:    -3
+PREFIX:  ],
+PREFIX:  "ok": true
+PREFIX:}
+"#;
+
+    async fn adapt(file: &str, data: Vec<u8>) -> Result<String> {
+        let (a, d) = simple_adapt_info(&PathBuf::from(file), Box::pin(std::io::Cursor::new(data)));
+        let adapter: Box<dyn FileAdapter> = Box::<BinJsonAdapter>::default();
+        let res = adapter.adapt(a, &d).await?;
+        Ok(String::from_utf8(adapted_to_vec(res).await?)?)
+    }
+
+    #[tokio::test]
+    async fn msgpack() -> Result<()> {
+        let mut data = vec![0x84, 0xa4];
+        data.extend_from_slice(b"name");
+        data.push(0xa7);
+        data.extend_from_slice("Jürgen".as_bytes());
+        data.push(0xa3);
+        data.extend_from_slice(b"age");
+        data.push(42);
+        data.push(0xa4);
+        data.extend_from_slice(b"tags");
+        data.extend([0x92, 0xa5]);
+        data.extend_from_slice(b"admin");
+        data.push(0xfd);
+        data.push(0xa2);
+        data.extend_from_slice(b"ok");
+        data.push(0xc3);
+        assert_eq!(adapt("user.msgpack", data).await?, EXPECTED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cbor() -> Result<()> {
+        let mut data = vec![0xa4, 0x64];
+        data.extend_from_slice(b"name");
+        data.push(0x67);
+        data.extend_from_slice("Jürgen".as_bytes());
+        data.push(0x63);
+        data.extend_from_slice(b"age");
+        data.extend([0x18, 42]);
+        data.push(0x64);
+        data.extend_from_slice(b"tags");
+        // indefinite length array
+        data.extend([0x9f, 0x65]);
+        data.extend_from_slice(b"admin");
+        data.extend([0x22, 0xff]);
+        data.push(0x62);
+        data.extend_from_slice(b"ok");
+        data.push(0xf5);
+        assert_eq!(adapt("user.cbor", data).await?, EXPECTED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn bson() -> Result<()> {
+        let mut tags = Vec::new();
+        tags.extend([0x02, b'0', 0, 6, 0, 0, 0]);
+        tags.extend_from_slice(b"admin\0");
+        tags.extend([0x10, b'1', 0]);
+        tags.extend((-3i32).to_le_bytes());
+        tags.push(0);
+        let mut fields = Vec::new();
+        fields.extend([0x02]);
+        fields.extend_from_slice(b"name\0");
+        fields.extend(8i32.to_le_bytes());
+        fields.extend_from_slice("Jürgen\0".as_bytes());
+        fields.push(0x10);
+        fields.extend_from_slice(b"age\0");
+        fields.extend(42i32.to_le_bytes());
+        fields.push(0x04);
+        fields.extend_from_slice(b"tags\0");
+        fields.extend((tags.len() as i32 + 4).to_le_bytes());
+        fields.extend(tags);
+        fields.push(0x08);
+        fields.extend_from_slice(b"ok\0");
+        fields.push(1);
+        fields.push(0);
+        let mut data = (fields.len() as i32 + 4).to_le_bytes().to_vec();
+        data.extend(fields);
+        assert_eq!(adapt("user.bson", data).await?, EXPECTED);
+        Ok(())
+    }
+}
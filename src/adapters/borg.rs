@@ -0,0 +1,247 @@
+use super::custom::{map_exe_error, spawn_output};
+use super::*;
+use crate::adapted_iter::one_file;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["borg"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "borg".to_owned(),
+        version: 1,
+        description: "Searches an archive of a BorgBackup repository without extracting it.\nMatches `.borg` files that describe the archive, e.g.\n```toml\nrepository = \"/srv/borg-repo\" # anything borg accepts as repository\narchive = \"host-2024-05-01\" # default: the latest archive\npaths = [\"home/me/Documents\"] # default: everything\n```\nStreams the archive with `borg export-tar` and recurses into the files. The result is cached until the `.borg` file changes, so set the archive for caching.\nThe passphrase is taken from `backup.borg_passcommand` in the rga config or from `BORG_PASSPHRASE` in the environment.\nOnly `.borg` files on disk are searched, not ones within archives. Disabled by default, enable it with `--rga-adapters=+borg`."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        disabled_by_default: true,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+
+/// The contents of a `.borg` file
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct BorgArchive {
+    /// the repository. Relative paths are relative to the `.borg` file
+    repository: String,
+    /// None means the latest archive
+    archive: Option<String>,
+    /// paths within the archive to search
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+impl BorgArchive {
+    /// `base` is the directory of the `.borg` file
+    fn repository(&self, base: &Path) -> OsString {
+        // remote repositories are given as `ssh://...` or `user@host:path`
+        if self.repository.contains(':') {
+            self.repository.clone().into()
+        } else {
+            base.join(&self.repository).into_os_string()
+        }
+    }
+
+    /// The command that outputs the name of the latest archive
+    fn latest_command(&self, base: &Path, passcommand: Option<&str>) -> Command {
+        let mut cmd = borg_command(passcommand);
+        // the repository comes from the scanned file, it must not be parsed as an option
+        cmd.args(["list", "--last", "1", "--format", "{archive}", "--"])
+            .arg(self.repository(base));
+        cmd
+    }
+
+    /// The command that writes the archive as tar to stdout
+    fn export_command(&self, base: &Path, archive: &str, passcommand: Option<&str>) -> Command {
+        let mut location = self.repository(base);
+        location.push("::");
+        location.push(archive);
+        let mut cmd = borg_command(passcommand);
+        cmd.args(["export-tar", "--"])
+            .arg(location)
+            .arg("-")
+            .args(&self.paths);
+        cmd
+    }
+}
+
+/// `passcommand` is only taken from the user's config: running commands named by a scanned file is not safe
+fn borg_command(passcommand: Option<&str>) -> Command {
+    let mut cmd = Command::new("borg");
+    if let Some(passcommand) = passcommand {
+        cmd.env("BORG_PASSCOMMAND", passcommand);
+    }
+    cmd
+}
+
+#[derive(Default)]
+pub struct BorgAdapter;
+
+impl BorgAdapter {
+    pub fn new() -> BorgAdapter {
+        BorgAdapter
+    }
+}
+impl GetMetadata for BorgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have borg installed.";
+
+#[async_trait]
+impl FileAdapter for BorgAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            is_real_file,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+        } = ai;
+        if !is_real_file {
+            // don't access repositories named by files of downloaded archives
+            anyhow::bail!("borg files are only searched on disk, not within archives");
+        }
+        let mut descriptor = String::new();
+        inp.read_to_string(&mut descriptor).await?;
+        let archive: BorgArchive = toml::from_str(&descriptor)
+            .with_context(|| format!("invalid borg file {}", filepath_hint.display()))?;
+        let base = filepath_hint.parent().unwrap_or(Path::new(""));
+        let passcommand = config.backup.borg_passcommand.as_deref();
+        let name = match &archive.archive {
+            Some(name) => name.clone(),
+            None => {
+                let output = archive
+                    .latest_command(base, passcommand)
+                    .output()
+                    .await
+                    .map_err(|e| map_exe_error(e, "borg", HELP))?;
+                if !output.status.success() {
+                    anyhow::bail!(
+                        "borg list failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                }
+                let name = String::from_utf8(output.stdout)?.trim().to_string();
+                if name.is_empty() {
+                    anyhow::bail!("borg repository {} has no archives", archive.repository);
+                }
+                name
+            }
+        };
+        let cmd = archive.export_command(base, &name, passcommand);
+        debug!("executing {:?}", cmd);
+        let output = spawn_output(cmd, "borg", HELP)?;
+        Ok(one_file(AdaptInfo {
+            filepath_hint: filepath_hint.with_extension("borg.tar"),
+            inp: output,
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+            passwords,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn export_command() -> Result<()> {
+        let archive: BorgArchive = toml::from_str(
+            r#"
+            repository = "repo"
+            paths = ["home/me/Documents"]
+            "#,
+        )?;
+        let cmd = archive.export_command(Path::new("/backups"), "host-2024-05-01", None);
+        let args: Vec<&OsStr> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "export-tar",
+                "--",
+                "/backups/repo::host-2024-05-01",
+                "-",
+                "home/me/Documents"
+            ]
+        );
+        let remote: BorgArchive = toml::from_str(r#"repository = "ssh://host/./repo""#)?;
+        let cmd = remote.latest_command(Path::new("/backups"), Some("pass show borg"));
+        assert_eq!(
+            cmd.as_std().get_args().last(),
+            Some(OsStr::new("ssh://host/./repo"))
+        );
+        assert_eq!(
+            cmd.as_std().get_envs().collect::<Vec<_>>(),
+            [(
+                OsStr::new("BORG_PASSCOMMAND"),
+                Some(OsStr::new("pass show borg"))
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn options_in_descriptor() -> Result<()> {
+        let archive: BorgArchive = toml::from_str(
+            r#"
+            repository = "--rsh=touch /tmp/pwned"
+            paths = ["--remote-path=touch /tmp/pwned"]
+            "#,
+        )?;
+        let cmd = archive.latest_command(Path::new(""), None);
+        let args: Vec<&OsStr> = cmd.as_std().get_args().collect();
+        assert_eq!(args[5..], ["--", "--rsh=touch /tmp/pwned"]);
+        let cmd = archive.export_command(Path::new(""), "latest", None);
+        let args: Vec<&OsStr> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "export-tar",
+                "--",
+                "--rsh=touch /tmp/pwned::latest",
+                "-",
+                "--remote-path=touch /tmp/pwned"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_passcommand_in_descriptor() {
+        let res = toml::from_str::<BorgArchive>(
+            r#"
+            repository = "repo"
+            passcommand = "touch /tmp/pwned"
+            "#,
+        );
+        assert!(res.is_err());
+    }
+}
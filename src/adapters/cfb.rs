@@ -0,0 +1,257 @@
+//! Reading of OLE compound files (Compound File Binary format), the container of Outlook `.msg` files and legacy Office documents.
+//!
+//! The whole file is kept in memory, streams are read by following their sector chains.
+
+use anyhow::Result;
+
+use super::binary::{u16_at, u32_at, u64_at, utf16};
+use super::*;
+
+pub const MAGIC: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+pub(crate) const HEADER_SIZE: usize = 512;
+/// sector numbers from this value on are special (end of chain, free, ...)
+const MAX_REG_SECT: u32 = 0xffff_fffa;
+pub(crate) const NO_STREAM: u32 = 0xffff_ffff;
+pub(crate) const DIR_ENTRY_SIZE: usize = 128;
+
+// directory entry types
+pub(crate) const STORAGE: u8 = 1;
+pub(crate) const STREAM: u8 = 2;
+pub(crate) const ROOT: u8 = 5;
+
+/// A stream or storage (directory) of a compound file
+pub struct DirEntry {
+    pub name: String,
+    kind: u8,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    pub size: u64,
+}
+
+impl DirEntry {
+    pub fn is_stream(&self) -> bool {
+        self.kind == STREAM
+    }
+}
+
+pub struct CompoundFile {
+    data: Vec<u8>,
+    sector_size: usize,
+    mini_sector_size: usize,
+    /// streams smaller than this are stored in the mini stream
+    mini_cutoff: u64,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    entries: Vec<DirEntry>,
+}
+
+impl CompoundFile {
+    pub fn parse(data: Vec<u8>) -> Result<CompoundFile> {
+        anyhow::ensure!(data.starts_with(MAGIC), "not an OLE compound file");
+        let header = data.get(..HEADER_SIZE).context("truncated compound file")?;
+        let field = |pos| u32_at(header, pos).context("truncated compound file header");
+        let sector_shift = u16_at(header, 0x1e).context("truncated compound file header")?;
+        let mini_sector_shift = u16_at(header, 0x20).context("truncated compound file header")?;
+        anyhow::ensure!(
+            (7..=16).contains(&sector_shift) && mini_sector_shift < sector_shift,
+            "invalid compound file sector size"
+        );
+        let mut cf = CompoundFile {
+            sector_size: 1 << sector_shift,
+            mini_sector_size: 1 << mini_sector_shift,
+            mini_cutoff: field(0x38)? as u64,
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries: Vec::new(),
+            data: Vec::new(),
+        };
+        let num_fat_sectors = field(0x2c)? as usize;
+        // the DIFAT lists the sectors of the FAT, the first 109 entries are in the header
+        let mut difat = u32s(&header[0x4c..]);
+        let mut next = field(0x44)?;
+        let mut seen = std::collections::HashSet::new();
+        while next < MAX_REG_SECT && difat.len() < num_fat_sectors && seen.insert(next) {
+            let sector = cf.sector(&data, next).context("invalid DIFAT sector")?;
+            // the last entry links to the next DIFAT sector
+            let entries = (sector.len() / 4).saturating_sub(1);
+            difat.extend(u32s(&sector[..entries * 4]));
+            next = u32_at(sector, entries * 4).unwrap_or(NO_STREAM);
+        }
+        for &s in difat.iter().take(num_fat_sectors) {
+            if s >= MAX_REG_SECT {
+                break;
+            }
+            let sector = cf.sector(&data, s).context("invalid FAT sector")?;
+            cf.fat.extend(u32s(sector));
+        }
+        let dir = cf.read_chain(&data, field(0x30)?, None)?;
+        let large_sectors = cf.sector_size > 512;
+        cf.entries = dir
+            .chunks_exact(DIR_ENTRY_SIZE)
+            .map(|e| parse_entry(e, large_sectors))
+            .collect();
+        anyhow::ensure!(
+            cf.entries.first().map(|e| e.kind) == Some(ROOT),
+            "compound file without root entry"
+        );
+        cf.mini_fat = u32s(&cf.read_chain(&data, field(0x3c)?, None)?);
+        // the mini stream is the data of the root entry
+        let root = &cf.entries[0];
+        cf.mini_stream = cf.read_chain(&data, root.start, Some(root.size))?;
+        cf.data = data;
+        Ok(cf)
+    }
+
+    /// The data of a sector, the last sector of the file may be truncated
+    fn sector<'a>(&self, data: &'a [u8], n: u32) -> Option<&'a [u8]> {
+        let start = (n as usize + 1).checked_mul(self.sector_size)?;
+        data.get(start..(start + self.sector_size).min(data.len()))
+    }
+
+    /// The sector numbers of a chain, stops at invalid or cyclic links
+    fn chain(fat: &[u32], start: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut next = start;
+        while next < MAX_REG_SECT && chain.len() <= fat.len() {
+            chain.push(next);
+            next = *fat.get(next as usize).unwrap_or(&NO_STREAM);
+        }
+        chain
+    }
+
+    /// Read a chain of regular sectors, cut to `size` if given
+    fn read_chain(&self, data: &[u8], start: u32, size: Option<u64>) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for s in Self::chain(&self.fat, start) {
+            if size.is_some_and(|size| out.len() as u64 >= size) {
+                break;
+            }
+            out.extend_from_slice(self.sector(data, s).context("sector out of range")?);
+        }
+        if let Some(size) = size {
+            anyhow::ensure!(out.len() as u64 >= size, "truncated compound file stream");
+            out.truncate(size as usize);
+        }
+        Ok(out)
+    }
+
+    pub fn entry(&self, id: usize) -> Option<&DirEntry> {
+        self.entries.get(id)
+    }
+
+    /// The ids of the entries of a storage. The root storage has the id 0.
+    pub fn children(&self, id: usize) -> Vec<usize> {
+        let mut out = Vec::new();
+        let Some(entry) = self.entries.get(id) else {
+            return out;
+        };
+        // the children form a tree linked by the left and right siblings
+        let mut todo = vec![entry.child];
+        let mut seen = std::collections::HashSet::new();
+        while let Some(id) = todo.pop() {
+            let Some(e) = self.entries.get(id as usize) else {
+                continue;
+            };
+            if !seen.insert(id) {
+                continue;
+            }
+            if matches!(e.kind, STORAGE | STREAM) {
+                out.push(id as usize);
+            }
+            todo.push(e.right);
+            todo.push(e.left);
+        }
+        out.sort_unstable();
+        out
+    }
+
+    /// The child of a storage with the given name, which is compared case insensitively
+    pub fn child(&self, id: usize, name: &str) -> Option<usize> {
+        self.children(id)
+            .into_iter()
+            .find(|c| self.entries[*c].name.eq_ignore_ascii_case(name))
+    }
+
+    /// Read the content of a stream
+    pub fn read(&self, id: usize) -> Result<Vec<u8>> {
+        let e = self.entries.get(id).context("no such stream")?;
+        anyhow::ensure!(e.is_stream(), "{} is not a stream", e.name);
+        if e.size >= self.mini_cutoff {
+            return self.read_chain(&self.data, e.start, Some(e.size));
+        }
+        let mut out = Vec::new();
+        for s in Self::chain(&self.mini_fat, e.start) {
+            if out.len() as u64 >= e.size {
+                break;
+            }
+            let start = s as usize * self.mini_sector_size;
+            let end = (start + self.mini_sector_size).min(self.mini_stream.len());
+            out.extend_from_slice(
+                self.mini_stream
+                    .get(start..end)
+                    .context("mini sector out of range")?,
+            );
+        }
+        anyhow::ensure!(out.len() as u64 >= e.size, "truncated compound file stream");
+        out.truncate(e.size as usize);
+        Ok(out)
+    }
+}
+
+/// The little endian u32 values of a FAT or DIFAT, a trailing partial value is ignored
+fn u32s(b: &[u8]) -> Vec<u32> {
+    b.chunks_exact(4).filter_map(|c| u32_at(c, 0)).collect()
+}
+
+fn parse_entry(e: &[u8], large_sectors: bool) -> DirEntry {
+    // the length of the name in bytes, including the terminating null
+    let name_len = (u16_at(e, 64).unwrap_or(0) as usize).min(64);
+    DirEntry {
+        name: utf16(&e[..name_len.saturating_sub(2)]),
+        kind: e[66],
+        left: u32_at(e, 68).unwrap_or(NO_STREAM),
+        right: u32_at(e, 72).unwrap_or(NO_STREAM),
+        child: u32_at(e, 76).unwrap_or(NO_STREAM),
+        start: u32_at(e, 116).unwrap_or(NO_STREAM),
+        // the upper half is not reliable in files with 512 byte sectors
+        size: if large_sectors {
+            u64_at(e, 120).unwrap_or(0)
+        } else {
+            u32_at(e, 120).unwrap_or(0) as u64
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::create_cfb;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn streams() -> Result<()> {
+        let big = vec![b'x'; 5000];
+        let cf = CompoundFile::parse(create_cfb(&[
+            ("small", b"mini stream data"),
+            ("dir/big", &big),
+            ("dir/other", b"more"),
+        ]))?;
+        let names = |id| {
+            cf.children(id)
+                .into_iter()
+                .map(|c| cf.entry(c).unwrap().name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(0), ["small", "dir"]);
+        let dir = cf.child(0, "DIR").unwrap();
+        assert_eq!(names(dir), ["big", "other"]);
+        assert_eq!(cf.read(cf.child(0, "small").unwrap())?, b"mini stream data");
+        assert_eq!(cf.read(cf.child(dir, "big").unwrap())?, big);
+        assert_eq!(cf.read(cf.child(dir, "other").unwrap())?, b"more");
+        Ok(())
+    }
+}
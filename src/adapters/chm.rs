@@ -0,0 +1,266 @@
+use super::custom::spawn_output;
+use super::sevenzip::{HELP, Switches, archive_on_disk, extract_command, list};
+use super::xml::{XmlEvent, XmlReader, html_to_text, resolve_href};
+use super::*;
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Cursor;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["chm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "chm".to_owned(),
+        version: 1,
+        description:
+            "Extracts the pages of compiled HTML help files (.chm) with `7z` and converts them to plain text in table of contents order.\nEach line is prefixed with the title of its topic, or the path of the page if it is not in the table of contents."
+                .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.ms-htmlhelp".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ChmAdapter;
+
+impl ChmAdapter {
+    pub fn new() -> ChmAdapter {
+        ChmAdapter
+    }
+}
+
+impl GetMetadata for ChmAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Help files predate utf-8 and are usually in the ANSI code page of their author
+fn decode(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(s) => s.to_string(),
+        Err(_) => encoding_rs::WINDOWS_1252.decode(data).0.into_owned(),
+    }
+}
+
+/// The key a page is looked up by: the path within the help file, case insensitive
+fn page_key(local: &str) -> String {
+    // links to other help files look like ms-its:other.chm::/page.htm
+    let local = local.rsplit("::").next().unwrap_or_default();
+    resolve_href("", &local.replace('\\', "/")).to_lowercase()
+}
+
+/// The topics of a table of contents (.hhc) as (page key, title) in order
+fn parse_toc(hhc: &str) -> Vec<(String, String)> {
+    let mut topics = Vec::new();
+    let mut topic: Option<(Option<String>, Option<String>)> = None;
+    for event in XmlReader::new(hhc) {
+        match &event {
+            XmlEvent::Start { name, attrs, .. } => {
+                if name.eq_ignore_ascii_case("object") {
+                    topic = Some((None, None));
+                } else if name.eq_ignore_ascii_case("param")
+                    && let Some((title, local)) = &mut topic
+                {
+                    let attr = |key: &str| {
+                        attrs
+                            .iter()
+                            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                            .map(|(_, v)| v.trim().to_string())
+                    };
+                    match attr("name").map(|n| n.to_ascii_lowercase()).as_deref() {
+                        Some("name") => *title = attr("value"),
+                        Some("local") => *local = attr("value"),
+                        _ => {}
+                    }
+                }
+            }
+            XmlEvent::End { name } if name.eq_ignore_ascii_case("object") => {
+                if let Some((Some(title), Some(local))) = topic.take() {
+                    topics.push((page_key(&local), title));
+                }
+            }
+            _ => {}
+        }
+    }
+    topics
+}
+
+/// The html pages to output as (path in the help file, prefix): first the pages of the table of contents, then the remaining ones.
+fn page_order(paths: &[String], toc: &[(String, String)]) -> Vec<(String, String)> {
+    let by_key: HashMap<String, &String> = paths.iter().map(|p| (page_key(p), p)).collect();
+    let mut seen = std::collections::HashSet::new();
+    let mut pages = Vec::new();
+    for (key, title) in toc {
+        match by_key.get(key) {
+            Some(path) => {
+                if seen.insert(key.clone()) {
+                    pages.push(((*path).clone(), title.clone()));
+                }
+            }
+            None => debug!("topic {title} links to missing page {key}"),
+        }
+    }
+    for path in paths {
+        let lower = path.to_lowercase();
+        // the internal files (#SYSTEM, $FIftiMain, ...) are not pages
+        let internal = lower.starts_with('#') || lower.starts_with('$');
+        if !internal
+            && (lower.ends_with(".htm") || lower.ends_with(".html"))
+            && seen.insert(page_key(path))
+        {
+            pages.push((path.clone(), path.clone()));
+        }
+    }
+    pages
+}
+
+async fn extract(archive: &Path, path: &str) -> Result<Vec<u8>> {
+    let mut inp = spawn_output(
+        extract_command(archive, path, &Switches::default()),
+        "7z",
+        HELP,
+    )?;
+    let mut data = Vec::new();
+    inp.read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+#[async_trait]
+impl FileAdapter for ChmAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+        } = ai;
+        let (archive, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let paths: Vec<String> = list(&archive, &Switches::default())
+            .await?
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        let mut toc = Vec::new();
+        if let Some(hhc) = paths.iter().find(|p| p.to_lowercase().ends_with(".hhc")) {
+            toc = parse_toc(&decode(&extract(&archive, hhc).await?));
+        }
+        let pages = page_order(&paths, &toc);
+        let s = stream! {
+            // keep the temporary file until all pages are extracted
+            let _tmp = tmp;
+            for (path, title) in pages {
+                let text = html_to_text(&decode(&extract(&archive, &path).await?));
+                if text.is_empty() {
+                    continue;
+                }
+                yield Ok(AdaptInfo {
+                    // the text is already extracted, don't adapt it again
+                    filepath_hint: PathBuf::from(format!("{path}.txt")),
+                    is_real_file: false,
+                    inp: Box::pin(Cursor::new(text.into_bytes())),
+                    line_prefix: format!("{line_prefix}{title}: "),
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const HHC: &str = r#"<!DOCTYPE HTML PUBLIC "-//IETF//DTD HTML//EN">
+<HTML><HEAD><meta name="GENERATOR" content="Microsoft&reg; HTML Help Workshop 4.1"></HEAD><BODY>
+<OBJECT type="text/site properties"><param name="Window Styles" value="0x800025"></OBJECT>
+<UL>
+  <LI><OBJECT type="text/sitemap">
+    <param name="Name" value="Getting Started">
+    <param name="Local" value="html\Intro%20Page.htm#top">
+  </OBJECT>
+  <UL>
+    <LI><OBJECT type="text/sitemap">
+      <PARAM NAME="name" VALUE="Installation">
+      <PARAM NAME="local" VALUE="ms-its:help.chm::/html/install.htm">
+    </OBJECT>
+    <LI><OBJECT type="text/sitemap"><param name="Name" value="Overview"></OBJECT>
+  </UL>
+</UL>
+</BODY></HTML>"#;
+
+    #[test]
+    fn toc() {
+        assert_eq!(
+            parse_toc(HHC),
+            [
+                (
+                    "html/intro page.htm".to_string(),
+                    "Getting Started".to_string()
+                ),
+                ("html/install.htm".to_string(), "Installation".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn order() {
+        let paths: Vec<String> = [
+            "#SYSTEM",
+            "$FIftiMain",
+            "html/Install.htm",
+            "html/appendix.html",
+            "html/Intro Page.htm",
+            "images/logo.gif",
+            "toc.hhc",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(
+            page_order(&paths, &parse_toc(HHC)),
+            [
+                (
+                    "html/Intro Page.htm".to_string(),
+                    "Getting Started".to_string()
+                ),
+                ("html/Install.htm".to_string(), "Installation".to_string()),
+                (
+                    "html/appendix.html".to_string(),
+                    "html/appendix.html".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ansi() {
+        assert_eq!(decode(b"caf\xe9"), "café");
+    }
+}
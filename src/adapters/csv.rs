@@ -0,0 +1,243 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+static EXTENSIONS: &[&str] = &["csv", "tsv", "tab", "psv"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "csv".to_owned(),
+        version: 1,
+        description: "Outputs each field of CSV and TSV files prefixed with the row number and the column header (`row 503 [email]: `), so matches in wide tables show their column. The delimiter is detected from the start of the file, quoted fields may span several lines"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("text/csv".to_owned()),
+            FileMatcher::MimeType("text/tab-separated-values".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CsvAdapter;
+
+impl CsvAdapter {
+    pub fn new() -> CsvAdapter {
+        CsvAdapter
+    }
+}
+
+impl GetMetadata for CsvAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const DELIMITERS: &[u8] = b",\t;|";
+
+/// A quoted field longer than this is assumed to be missing its closing quote
+const MAX_RECORD_LENGTH: usize = 1 << 20;
+
+/// Number of occurrences of the delimiter outside of quotes
+fn count_delimiter(line: &str, delimiter: char) -> usize {
+    let mut quoted = false;
+    line.chars()
+        .filter(|c| {
+            if *c == '"' {
+                quoted = !quoted;
+            }
+            !quoted && *c == delimiter
+        })
+        .count()
+}
+
+/// The delimiter that occurs the same (non-zero) number of times in the most lines of the sample
+fn sniff_delimiter(sample: &str) -> char {
+    let mut lines: Vec<&str> = sample.lines().take(20).collect();
+    // the last line may be cut off
+    if lines.len() > 1 && !sample.ends_with('\n') {
+        lines.pop();
+    }
+    DELIMITERS
+        .iter()
+        .map(|d| *d as char)
+        .filter_map(|d| {
+            let counts: Vec<usize> = lines.iter().map(|l| count_delimiter(l, d)).collect();
+            let first = *counts.first()?;
+            let consistent = counts.iter().filter(|c| **c == first).count();
+            (first > 0).then_some(((consistent, first), d))
+        })
+        // the first of the best candidates
+        .rev()
+        .max_by_key(|(score, _)| *score)
+        .map_or(',', |(_, d)| d)
+}
+
+/// The fields of the record, or None if it ends within a quoted field
+fn parse_record(text: &str, delimiter: char, complete: bool) -> Option<Vec<String>> {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted && !complete {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+/// Reads the records of a CSV file, which may span several lines
+struct Records<R> {
+    inp: R,
+    delimiter: char,
+}
+
+impl<R: AsyncBufRead + Unpin> Records<R> {
+    async fn next(&mut self) -> Result<Option<Vec<String>>> {
+        let mut text = String::new();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let eof = self.inp.read_until(b'\n', &mut line).await? == 0;
+            text.push_str(&String::from_utf8_lossy(&line));
+            if eof && text.is_empty() {
+                return Ok(None);
+            }
+            let complete = eof || text.len() > MAX_RECORD_LENGTH;
+            if let Some(fields) = parse_record(&text, self.delimiter, complete) {
+                return Ok(Some(fields));
+            }
+        }
+    }
+}
+
+/// The names used in the prefixes of each column, the column number for columns without a usable header
+fn column_names(header: &[String]) -> Vec<String> {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let name = name.trim();
+            let duplicate = header[..i].iter().any(|h| h.trim() == name);
+            if name.is_empty() || duplicate {
+                (i + 1).to_string()
+            } else {
+                name.to_string()
+            }
+        })
+        .collect()
+}
+
+#[async_trait]
+impl WritingFileAdapter for CsvAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut inp = BufReader::with_capacity(1 << 16, inp);
+        let is_tsv = matches!(detection_reason, FileMatcher::Fast(FastFileMatcher::FileExtension(ext)) if ext == "tsv" || ext == "tab")
+            || matches!(detection_reason, FileMatcher::MimeType(mime) if mime == "text/tab-separated-values");
+        let delimiter = if is_tsv {
+            '\t'
+        } else {
+            // fill but do not consume
+            sniff_delimiter(&String::from_utf8_lossy(inp.fill_buf().await?))
+        };
+        let mut records = Records { inp, delimiter };
+        let Some(header) = records.next().await? else {
+            return Ok(());
+        };
+        let p = &line_prefix;
+        oup.write_all(format!("{p}header: {}\n", header.join(", ").trim_end()).as_bytes())
+            .await?;
+        let columns = column_names(&header);
+        let mut row = 0;
+        while let Some(fields) = records.next().await? {
+            if fields.len() == 1 && fields[0].trim().is_empty() {
+                // empty line
+                continue;
+            }
+            row += 1;
+            let mut out = String::new();
+            for (i, field) in fields.iter().enumerate() {
+                let column = columns
+                    .get(i)
+                    .cloned()
+                    .unwrap_or_else(|| (i + 1).to_string());
+                for line in field.lines().filter(|l| !l.trim().is_empty()) {
+                    out.push_str(&format!("{p}row {row} [{column}]: {}\n", line.trim_end()));
+                }
+            }
+            oup.write_all(out.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[test]
+    fn delimiters() {
+        assert_eq!(sniff_delimiter("a,b,c\n1,2,3\n"), ',');
+        assert_eq!(sniff_delimiter("name;note\nx;\"a, b, c\"\ny;d\n"), ';');
+        assert_eq!(sniff_delimiter("a\tb\n1\t2\n"), '\t');
+        assert_eq!(sniff_delimiter("single column\n"), ',');
+    }
+
+    #[tokio::test]
+    async fn rows() -> Result<()> {
+        let csv = "id,email,note,\r\n1,alice@example.com,\"multi\nline, \"\"quoted\"\"\",x\r\n\r\n2,,plain,y,extra\r\n";
+        let (a, d) = simple_adapt_info(&PathBuf::from("users.csv"), Box::pin(Cursor::new(csv)));
+        let buf = adapted_to_vec(loop_adapt(&CsvAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            r#"PREFIX:header: id, email, note,
+PREFIX:row 1 [id]: 1
+PREFIX:row 1 [email]: alice@example.com
+PREFIX:row 1 [note]: multi
+PREFIX:row 1 [note]: line, "quoted"
+PREFIX:row 1 [4]: x
+PREFIX:row 2 [id]: 2
+PREFIX:row 2 [note]: plain
+PREFIX:row 2 [4]: y
+PREFIX:row 2 [5]: extra
+"#
+        );
+        Ok(())
+    }
+}
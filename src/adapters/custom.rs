@@ -2,6 +2,7 @@ use super::*;
 use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata};
 use crate::adapted_iter::one_file;
 
+use crate::passwords::{PasswordState, UsedPassword, password_candidates, pdf_password_candidates};
 use crate::{
     adapted_iter::AdaptedFilesIterBox,
     expand::expand_str_ez,
@@ -12,14 +13,17 @@ use anyhow::Result;
 use async_stream::stream;
 use bytes::Bytes;
 use lazy_static::lazy_static;
-use log::debug;
+use log::{debug, warn};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::io::Cursor;
 use std::path::Path;
 use std::process::Stdio;
-use tokio::io::AsyncReadExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Child;
 use tokio::process::Command;
+use tokio_stream::StreamExt;
 
 use tokio_util::io::StreamReader;
 // mostly the same as AdapterMeta + SpawningFileAdapter
@@ -70,6 +74,14 @@ pub struct CustomAdapterConfig {
     ///
     /// Setting this is useful if the output format is not plain text (.txt) but instead some other format that should be passed to another adapter
     pub output_path_hint: Option<String>,
+
+    /// Arguments to add to try a password on encrypted files, for example `["-upw", "$password"]`.
+    /// Placeholders are the same as for `.args`, plus `$password`.
+    ///
+    /// If set and a password file is given (`--rga-password-file`, or `--rga-pdf-password` for the poppler adapter), the input is buffered in memory and if the program fails without a password,
+    /// it is run again with each password until it succeeds.
+    /// Passwords in the arguments are visible to other users in the process list, prefer environment variables (`env PW=$password ...`) if the program supports them.
+    pub password_args: Option<Vec<String>>,
 }
 
 fn strs(arr: &[&str]) -> Vec<String> {
@@ -116,8 +128,8 @@ lazy_static! {
         CustomAdapterConfig {
             name: "pandoc".to_string(),
             description: "Uses pandoc to convert binary/unreadable text documents to plain markdown-like text".to_string(),
-            version: 3,
-            extensions: strs(&["epub", "odt", "docx", "fb2", "ipynb", "html", "htm"]),
+            version: 7,
+            extensions: strs(&["docx", "html", "htm"]),
             binary: "pandoc".to_string(),
             mimetypes: None,
             // simpler markdown (with more information loss but plainer text)
@@ -130,11 +142,12 @@ lazy_static! {
             ]),
             disabled_by_default: None,
             match_only_by_mime: None,
-            output_path_hint: None
+            output_path_hint: None,
+            password_args: None
         },
         CustomAdapterConfig {
             name: "poppler".to_owned(),
-            version: 1,
+            version: 2,
             description: "Uses pdftotext (from poppler-utils) to extract plain text from PDF files"
                 .to_owned(),
 
@@ -145,7 +158,8 @@ lazy_static! {
             args: strs(&["-", "-"]),
             disabled_by_default: None,
             match_only_by_mime: None,
-            output_path_hint: Some("${input_virtual_path}.txt.asciipagebreaks".into())
+            output_path_hint: Some("${input_virtual_path}.txt.asciipagebreaks".into()),
+            password_args: Some(strs(&["-opw", "$password", "-upw", "$password"]))
         }
     ];
 }
@@ -155,7 +169,11 @@ lazy_static! {
 pub fn map_exe_error(err: std::io::Error, exe_name: &str, help: &str) -> anyhow::Error {
     use std::io::ErrorKind::*;
     match err.kind() {
-        NotFound => format_err!("Could not find executable \"{}\". {}", exe_name, help),
+        // keep the io error in the chain so it can be classified as a spawn failure
+        NotFound => anyhow::Error::from(err).context(format!(
+            "Could not find executable \"{}\". {}",
+            exe_name, help
+        )),
         _ => anyhow::Error::from(err),
     }
 }
@@ -198,11 +216,27 @@ pub fn pipe_output(
     )))
 }
 
+/// Run a command that does not read any input and return its output.
+/// Reading fails at the end of the output if the command failed.
+pub fn spawn_output(mut cmd: Command, exe_name: &str, help: &str) -> Result<ReadBox> {
+    let cmd_log = format!("{:?}", cmd);
+    let mut cmd = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| map_exe_error(e, exe_name, help))?;
+    let stdo = cmd.stdout.take().expect("is piped");
+    Ok(Box::pin(stdo.chain(proc_wait(cmd, move || {
+        format!("subprocess: {cmd_log}")
+    }))))
+}
+
 pub struct CustomSpawningFileAdapter {
     binary: String,
     args: Vec<String>,
     meta: AdapterMeta,
     output_path_hint: Option<String>,
+    password_args: Option<Vec<String>>,
 }
 impl GetMetadata for CustomSpawningFileAdapter {
     fn metadata(&self) -> &AdapterMeta {
@@ -210,7 +244,17 @@ impl GetMetadata for CustomSpawningFileAdapter {
     }
 }
 fn arg_replacer(arg: &str, filepath_hint: &Path) -> Result<String> {
+    arg_replacer_with_password(arg, filepath_hint, None)
+}
+fn arg_replacer_with_password(
+    arg: &str,
+    filepath_hint: &Path,
+    password: Option<&str>,
+) -> Result<String> {
     expand_str_ez(arg, |s| match s {
+        "password" => password
+            .map(Cow::Borrowed)
+            .ok_or_else(|| anyhow::format_err!("$password is only allowed in password_args")),
         "input_virtual_path" => Ok(filepath_hint.to_string_lossy()),
         "input_file_stem" => Ok(filepath_hint
             .file_stem()
@@ -238,6 +282,143 @@ impl CustomSpawningFileAdapter {
         log::debug!("running command {:?}", command);
         Ok(command)
     }
+
+    /// Run the program on the buffered input, retrying with the password candidates if it fails without one.
+    ///
+    /// Returns the output and the password that worked, if one was needed, which is also recorded in `state`.
+    async fn run_with_passwords(
+        &self,
+        filepath_hint: &Path,
+        line_prefix: &str,
+        mut inp: ReadBox,
+        password_args: &[String],
+        passwords: &[String],
+        state: &PasswordState,
+    ) -> Result<(ReadBox, Option<String>)> {
+        let mut input = Vec::new();
+        inp.read_to_end(&mut input).await?;
+        let first_err = match self.run_buffered(filepath_hint, &input, None).await {
+            Ok(output) => return Ok((output, None)),
+            Err(e) => e,
+        };
+        for (i, password) in passwords.iter().enumerate() {
+            let args = password_args
+                .iter()
+                .map(|arg| arg_replacer_with_password(arg, filepath_hint, Some(password)))
+                .collect::<Result<Vec<_>>>()?;
+            if let Ok(output) = self.run_buffered(filepath_hint, &input, Some(&args)).await {
+                debug!("password worked for {}", filepath_hint.display());
+                state.record_used(
+                    &format!("{}{}", line_prefix, filepath_hint.display()),
+                    UsedPassword::Candidate(i + 1),
+                );
+                return Ok((output, Some(password.clone())));
+            }
+        }
+        Err(first_err.context("none of the given passwords worked"))
+    }
+
+    /// Run pdftotext, OCR the pages without text (`--rga-ocr-pdf`) and add the form fields (`--rga-pdf-forms`).
+    ///
+    /// Returns the text, whether its pages are already formatted with the sections of the outline (`--rga-pdf-outline`),
+    /// otherwise they are separated by form feeds, and the password that decrypted the file.
+    async fn run_pdf(
+        &self,
+        filepath_hint: &Path,
+        line_prefix: &str,
+        input: &[u8],
+        passwords: &[String],
+        config: &RgaConfig,
+        state: &PasswordState,
+    ) -> Result<(ReadBox, bool, Option<String>)> {
+        let (mut output, password) = match &self.password_args {
+            Some(password_args) if !passwords.is_empty() => {
+                self.run_with_passwords(
+                    filepath_hint,
+                    line_prefix,
+                    Box::pin(Cursor::new(input.to_vec())),
+                    password_args,
+                    passwords,
+                    state,
+                )
+                .await?
+            }
+            _ => (self.run_buffered(filepath_hint, input, None).await?, None),
+        };
+        let mut text = Vec::new();
+        output.read_to_end(&mut text).await?;
+        if config.ocr.pdf {
+            let ocr = config.ocr.for_adapter(&self.meta.name);
+            text = super::ocr::ocr_empty_pdf_pages(input, &text, &ocr).await?;
+        }
+        if config.pdf.forms {
+            match super::pdf::form_fields(input, password.as_deref()).await {
+                Ok(fields) => text = super::pdf::add_form_fields(&text, &fields),
+                Err(e) => warn!(
+                    "could not read the form fields of {}: {:#}",
+                    filepath_hint.display(),
+                    e
+                ),
+            }
+        }
+        if config.pdf.outline {
+            match super::pdf::outline(input, password.as_deref()).await {
+                Ok(outline) if !outline.is_empty() => {
+                    let text = String::from_utf8_lossy(&text);
+                    let pages = super::pdf::sectioned_pages(&text, &outline);
+                    let formatted =
+                        super::postproc::format_sectioned_pages(pages, "", config.page_style);
+                    return Ok((
+                        Box::pin(Cursor::new(formatted.into_bytes())),
+                        true,
+                        password,
+                    ));
+                }
+                Ok(_) => {}
+                Err(e) => warn!(
+                    "could not read the outline of {}: {:#}",
+                    filepath_hint.display(),
+                    e
+                ),
+            }
+        }
+        Ok((Box::pin(Cursor::new(text)), false, password))
+    }
+
+    async fn run_buffered(
+        &self,
+        filepath_hint: &Path,
+        input: &[u8],
+        password_args: Option<&[String]>,
+    ) -> Result<ReadBox> {
+        let mut cmd = Command::new(&self.binary);
+        if let Some(password_args) = password_args {
+            cmd.args(password_args);
+        }
+        let mut child = self
+            .command(filepath_hint, cmd)?
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| map_exe_error(e, &self.binary, ""))?;
+        let mut stdi = child.stdin.take().expect("is piped");
+        let input = input.to_vec();
+        // write in the background so a program that outputs while reading can't deadlock
+        let writer = tokio::spawn(async move { stdi.write_all(&input).await });
+        let output = child.wait_with_output().await?;
+        // the program might not read all of its input if it fails
+        writer.await?.ok();
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed ({}): {}",
+                self.binary,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(Box::pin(Cursor::new(output.stdout)))
+    }
 }
 #[async_trait]
 impl FileAdapter for CustomSpawningFileAdapter {
@@ -248,34 +429,103 @@ impl FileAdapter for CustomSpawningFileAdapter {
     ) -> Result<AdaptedFilesIterBox> {
         let AdaptInfo {
             filepath_hint,
-            inp,
+            mut inp,
             line_prefix,
             archive_recursion_depth,
             postprocess,
             config,
+            passwords: password_state,
             ..
         } = ai;
 
-        let cmd = Command::new(&self.binary);
-        let cmd = self
-            .command(&filepath_hint, cmd)
-            .with_context(|| format!("Could not set cmd arguments for {}", self.binary))?;
-        debug!("executing {:?}", cmd);
-        let output = pipe_output(&line_prefix, cmd, inp, &self.binary, "")?;
-        Ok(one_file(AdaptInfo {
-            filepath_hint: PathBuf::from(arg_replacer(
-                self.output_path_hint
-                    .as_deref()
-                    .unwrap_or("${input_virtual_path}.txt"),
-                &filepath_hint,
-            )?),
+        let mut attachments = None;
+        let mut pages_formatted = false;
+        let passwords = match &self.password_args {
+            Some(_) if self.meta.name == "poppler" => pdf_password_candidates(&config)?,
+            Some(_) => password_candidates(&config)?,
+            None => Vec::new(),
+        };
+        let output = match &self.password_args {
+            _ if self.meta.name == "poppler"
+                && (config.ocr.pdf
+                    || config.pdf.forms
+                    || config.pdf.attachments
+                    || config.pdf.outline) =>
+            {
+                let mut input = Vec::new();
+                inp.read_to_end(&mut input).await?;
+                let (text, formatted, password) = self
+                    .run_pdf(
+                        &filepath_hint,
+                        &line_prefix,
+                        &input,
+                        &passwords,
+                        &config,
+                        &password_state,
+                    )
+                    .await?;
+                pages_formatted = formatted;
+                if config.pdf.attachments {
+                    match super::pdf::attachments(
+                        &input,
+                        password.as_deref(),
+                        &line_prefix,
+                        archive_recursion_depth + 1,
+                        postprocess,
+                        &config,
+                        &password_state,
+                    )
+                    .await
+                    {
+                        Ok(files) => attachments = Some(files),
+                        Err(e) => warn!(
+                            "could not list the attachments of {}: {:#}",
+                            filepath_hint.display(),
+                            e
+                        ),
+                    }
+                }
+                text
+            }
+            Some(password_args) if !passwords.is_empty() => {
+                self.run_with_passwords(
+                    &filepath_hint,
+                    &line_prefix,
+                    inp,
+                    password_args,
+                    &passwords,
+                    &password_state,
+                )
+                .await?
+                .0
+            }
+            _ => {
+                let cmd = Command::new(&self.binary);
+                let cmd = self
+                    .command(&filepath_hint, cmd)
+                    .with_context(|| format!("Could not set cmd arguments for {}", self.binary))?;
+                debug!("executing {:?}", cmd);
+                pipe_output(&line_prefix, cmd, inp, &self.binary, "")?
+            }
+        };
+        let output_path_hint = match &self.output_path_hint {
+            Some(hint) if !pages_formatted => hint.as_str(),
+            _ => "${input_virtual_path}.txt",
+        };
+        let text = one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(arg_replacer(output_path_hint, &filepath_hint)?),
             inp: output,
             line_prefix,
             is_real_file: false,
             archive_recursion_depth: archive_recursion_depth + 1,
             postprocess,
             config,
-        }))
+            passwords: password_state,
+        });
+        Ok(match attachments {
+            Some(attachments) => Box::pin(text.chain(attachments)),
+            None => text,
+        })
     }
 }
 impl CustomAdapterConfig {
@@ -284,6 +534,7 @@ impl CustomAdapterConfig {
             binary: self.binary.clone(),
             args: self.args.clone(),
             output_path_hint: self.output_path_hint.clone(),
+            password_args: self.password_args.clone(),
             meta: AdapterMeta {
                 name: self.name.clone(),
                 version: self.version,
@@ -294,6 +545,7 @@ impl CustomAdapterConfig {
                     self.args.join(" ")
                 ),
                 recurses: true,
+                outputs_unchanged_files: false,
                 fast_matchers: self
                     .extensions
                     .iter()
@@ -365,6 +617,7 @@ PREFIX:Page 1:
             binary: "sed".to_string(),
             args: vec!["s/e/u/g".to_string()],
             output_path_hint: None,
+            password_args: None,
         };
 
         let adapter = adapter.to_adapter();
@@ -390,4 +643,37 @@ PREFIX:Page 1:
         println!("output: {}", String::from_utf8_lossy(&oup));
         Ok(())
     }
+    #[tokio::test]
+    async fn password_candidates() -> Result<()> {
+        // fails unless it gets the right password as environment variable
+        let adapter = CustomAdapterConfig {
+            name: "locked".to_string(),
+            description: "".to_string(),
+            disabled_by_default: None,
+            version: 1,
+            extensions: vec!["locked".to_string()],
+            mimetypes: None,
+            match_only_by_mime: None,
+            binary: "env".to_string(),
+            args: strs(&["sh", "-c", "test \"$PW\" = secret && cat"]),
+            output_path_hint: None,
+            password_args: Some(strs(&["PW=$password"])),
+        }
+        .to_adapter();
+        let mut password_file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut password_file, b"wrong\nsecret\n")?;
+        let (mut a, d) = simple_adapt_info(
+            Path::new("doc.locked"),
+            Box::pin(Cursor::new(b"hello".to_vec())),
+        );
+        a.config.password_file = Some(password_file.path().to_string_lossy().into_owned());
+        let state = a.passwords.clone();
+        let output = adapter.adapt(a, &d).await?;
+        assert_eq!(String::from_utf8(adapted_to_vec(output).await?)?, "hello");
+        assert_eq!(
+            state.take_used().get("PREFIX:doc.locked"),
+            Some(&UsedPassword::Candidate(2))
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,107 @@
+use super::ar::ArHeaders;
+use super::*;
+use crate::adapted_iter::sequential_members;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+
+static EXTENSIONS: &[&str] = &["deb", "udeb", "ddeb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "deb".to_owned(),
+        version: 1,
+        description: "Reads Debian packages and recurses into the control archive (control file, maintainer scripts) and the data archive (the installed files)"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.debian.binary-package".to_owned()),
+            FileMatcher::MimeType("application/x-debian-package".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DebAdapter;
+
+impl DebAdapter {
+    pub fn new() -> DebAdapter {
+        DebAdapter
+    }
+}
+impl GetMetadata for DebAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for DebAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+            ..
+        } = ai;
+        // debian-binary, control.tar.* and data.tar.*, which are searched by the decompress and tar adapters
+        let members = sequential_members(inp, ArHeaders::new());
+        let s = stream! {
+            for await member in members {
+                let (path, inp) = member?;
+                yield Ok(AdaptInfo {
+                    line_prefix: format!("{line_prefix}{path}: "),
+                    filepath_hint: PathBuf::from(path),
+                    is_real_file: false,
+                    inp,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn package() -> Result<()> {
+        let tar = std::fs::read(test_data_dir().join("hello.tar"))?;
+        let deb = create_ar(&[("debian-binary", b"2.0\n"), ("data.tar", &tar)]);
+        let (a, d) = simple_adapt_info(&PathBuf::from("hello.deb"), Box::pin(Cursor::new(deb)));
+        let buf = adapted_to_vec(loop_adapt(&DebAdapter::new(), d, a).await?).await?;
+        let out = String::from_utf8(buf)?;
+        assert_eq!(
+            out.lines().take(3).collect::<Vec<_>>(),
+            [
+                "PREFIX:debian-binary: 2.0",
+                "PREFIX:debian-binary: ",
+                "PREFIX:data.tar: dir/file-b.pdf: Page 1: hello world"
+            ]
+        );
+        Ok(())
+    }
+}
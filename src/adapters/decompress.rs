@@ -2,27 +2,34 @@ use crate::adapted_iter::one_file;
 
 use super::*;
 
+use crate::preproc::matches_any_adapter;
 use anyhow::Result;
 use lazy_static::lazy_static;
-use tokio::io::BufReader;
+use tokio::io::{AsyncBufReadExt, BufReader};
 
 use std::path::{Path, PathBuf};
 
-static EXTENSIONS: &[&str] = &["als", "bz2", "gz", "tbz", "tbz2", "tgz", "xz", "zst"];
+static EXTENSIONS: &[&str] = &[
+    "als", "br", "bz2", "gz", "lz4", "lzma", "tbz", "tbz2", "tgz", "tzst", "xz", "zst",
+];
 static MIME_TYPES: &[&str] = &[
     "application/gzip",
     "application/x-bzip",
     "application/x-xz",
     "application/zstd",
+    "application/x-lz4",
+    "application/x-brotli",
+    "application/x-lzma",
 ];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "decompress".to_owned(),
-        version: 1,
+        version: 4,
         description:
-            "Reads compressed file as a stream and runs a different extractor on the contents."
+            "Reads compressed file (gzip, bzip2, xz, lzma, zstd, lz4, brotli) as a stream and runs a different extractor on the contents.\nIf the name of the file without the compression extension doesn't match an adapter, the type of the contents is detected from their start (e.g. a tar archive in `backup.zst`)."
                 .to_owned(),
         recurses: true,
+        outputs_unchanged_files: true,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -51,28 +58,57 @@ impl GetMetadata for DecompressAdapter {
     }
 }
 
-fn decompress_any(reason: &FileMatcher, inp: ReadBox) -> Result<ReadBox> {
+pub fn decompress_any(reason: &FileMatcher, inp: ReadBox) -> Result<ReadBox> {
     use FastFileMatcher::*;
     use FileMatcher::*;
     use async_compression::tokio::bufread;
-    let gz = |inp: ReadBox| Box::pin(bufread::GzipDecoder::new(BufReader::new(inp)));
-    let bz2 = |inp: ReadBox| Box::pin(bufread::BzDecoder::new(BufReader::new(inp)));
-    let xz = |inp: ReadBox| Box::pin(bufread::XzDecoder::new(BufReader::new(inp)));
-    let zst = |inp: ReadBox| Box::pin(bufread::ZstdDecoder::new(BufReader::new(inp)));
+    // concatenated streams (e.g. rotated logs joined with cat, pixz / pbzip2 / zstd -T output) are decompressed as one
+    let gz = |inp: ReadBox| {
+        let mut d = bufread::GzipDecoder::new(BufReader::new(inp));
+        d.multiple_members(true);
+        Box::pin(d)
+    };
+    let bz2 = |inp: ReadBox| {
+        let mut d = bufread::BzDecoder::new(BufReader::new(inp));
+        d.multiple_members(true);
+        Box::pin(d)
+    };
+    let xz = |inp: ReadBox| {
+        let mut d = bufread::XzDecoder::new(BufReader::new(inp));
+        d.multiple_members(true);
+        Box::pin(d)
+    };
+    let zst = |inp: ReadBox| {
+        let mut d = bufread::ZstdDecoder::new(BufReader::new(inp));
+        d.multiple_members(true);
+        Box::pin(d)
+    };
+    let lzma = |inp: ReadBox| Box::pin(bufread::LzmaDecoder::new(BufReader::new(inp)));
+    // brotli streams can't be concatenated
+    let br = |inp: ReadBox| Box::pin(bufread::BrotliDecoder::new(BufReader::new(inp)));
 
     Ok(match reason {
         Fast(FileExtension(ext)) => match ext.as_ref() {
             "als" | "gz" | "tgz" => gz(inp),
             "bz2" | "tbz" | "tbz2" => bz2(inp),
-            "zst" => zst(inp),
+            "zst" | "tzst" => zst(inp),
             "xz" => xz(inp),
+            "lzma" => lzma(inp),
+            "lz4" => lz4::lz4_decoder(inp),
+            "br" => br(inp),
             ext => Err(format_err!("don't know how to decompress {}", ext))?,
         },
+        Fast(DirectoryShape(_) | ExtensionWithSibling(_, _) | PathGlob(_)) => {
+            Err(format_err!("can't decompress by directory"))?
+        }
         MimeType(mime) => match mime.as_ref() {
             "application/gzip" => gz(inp),
             "application/x-bzip" => bz2(inp),
             "application/x-xz" => xz(inp),
             "application/zstd" => zst(inp),
+            "application/x-lz4" => lz4::lz4_decoder(inp),
+            "application/x-brotli" => br(inp),
+            "application/x-lzma" => lzma(inp),
             mime => Err(format_err!("don't know how to decompress mime {}", mime))?,
         },
     })
@@ -87,12 +123,41 @@ fn get_inner_filename(filename: &Path) -> PathBuf {
         .expect("no filename given?")
         .to_string_lossy();
     let new_extension = match extension.as_ref() {
-        "tgz" | "tbz" | "tbz2" => ".tar",
+        "tgz" | "tbz" | "tbz2" | "tzst" => ".tar",
         _other => "",
     };
     filename.with_file_name(format!("{}{}", stem, new_extension))
 }
 
+/// Magic numbers of formats that are commonly compressed, with their extension
+static SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (257, b"ustar", "tar"),
+    (0, b"PK\x03\x04", "zip"),
+    (0, b"\x1f\x8b", "gz"),
+    (0, b"BZh", "bz2"),
+    (0, b"\xfd7zXZ\x00", "xz"),
+    (0, b"\x28\xb5\x2f\xfd", "zst"),
+    (0, b"\x04\x22\x4d\x18", "lz4"),
+    (0, b"7z\xbc\xaf\x27\x1c", "7z"),
+    (0, b"Rar!\x1a\x07", "rar"),
+    (0, b"%PDF-", "pdf"),
+    (0, b"SQLite format 3\x00", "sqlite3"),
+];
+
+/// Guess the extension of decompressed content from its start, None for text and unknown data
+fn sniff_extension(buf: &[u8]) -> Option<&'static str> {
+    if let Some((_, _, ext)) = SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| buf.get(*offset..).is_some_and(|b| b.starts_with(magic)))
+    {
+        return Some(ext);
+    }
+    match tree_magic::from_u8(buf) {
+        "text/plain" | "application/octet-stream" => None,
+        mime => mime2ext::mime2ext(mime),
+    }
+}
+
 #[async_trait]
 impl FileAdapter for DecompressAdapter {
     async fn adapt(
@@ -100,13 +165,26 @@ impl FileAdapter for DecompressAdapter {
         ai: AdaptInfo,
         detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
+        let mut inp = BufReader::with_capacity(1 << 16, decompress_any(detection_reason, ai.inp)?);
+        let mut filepath_hint = get_inner_filename(&ai.filepath_hint);
+        // the name of the compressed file often doesn't tell what it contains (`data.br`, `backup.zst`)
+        if !matches_any_adapter(&ai.config, &filepath_hint)?
+            && let Some(ext) = sniff_extension(inp.fill_buf().await?)
+        {
+            debug!("detected {ext} content in {}", ai.filepath_hint.display());
+            let mut name = filepath_hint.into_os_string();
+            name.push(".");
+            name.push(ext);
+            filepath_hint = name.into();
+        }
         Ok(one_file(AdaptInfo {
-            filepath_hint: get_inner_filename(&ai.filepath_hint),
+            filepath_hint,
             is_real_file: false,
             archive_recursion_depth: ai.archive_recursion_depth + 1,
-            inp: decompress_any(detection_reason, ai.inp)?,
+            inp: Box::pin(inp),
             line_prefix: ai.line_prefix,
             config: ai.config.clone(),
+            passwords: ai.passwords.clone(),
             postprocess: ai.postprocess,
         }))
     }
@@ -130,6 +208,8 @@ mod tests {
             ("hi/test.tbz", "hi/test.tar"),
             ("hi/test.hi.bz2", "hi/test.hi"),
             ("hello.tar.gz", "hello.tar"),
+            ("backup.tzst", "backup.tar"),
+            ("app.log.lz4", "app.log"),
         ] {
             assert_eq!(get_inner_filename(&PathBuf::from(a)), PathBuf::from(*b));
         }
@@ -148,6 +228,74 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn concatenated_gz() -> Result<()> {
+        let filepath = test_data_dir().join("hello.gz");
+        let gz = std::fs::read(&filepath)?;
+        let (a, d) = simple_adapt_info(
+            &filepath,
+            Box::pin(std::io::Cursor::new([gz.clone(), gz].concat())),
+        );
+        let r = DecompressAdapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "hello\nhello\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zstd_and_brotli() -> Result<()> {
+        use async_compression::tokio::bufread::{BrotliEncoder, ZstdEncoder};
+        use tokio::io::AsyncReadExt;
+        let mut zst = Vec::new();
+        ZstdEncoder::new(&b"first frame\n"[..])
+            .read_to_end(&mut zst)
+            .await?;
+        ZstdEncoder::new(&b"second frame\n"[..])
+            .read_to_end(&mut zst)
+            .await?;
+        let mut br = Vec::new();
+        BrotliEncoder::new(&b"brotli\n"[..])
+            .read_to_end(&mut br)
+            .await?;
+        for (name, data, expected) in [
+            ("logs.zst", zst, "first frame\nsecond frame\n"),
+            ("page.html.br", br, "brotli\n"),
+        ] {
+            let (a, d) =
+                simple_adapt_info(&PathBuf::from(name), Box::pin(std::io::Cursor::new(data)));
+            let r = DecompressAdapter.adapt(a, &d).await?;
+            assert_eq!(String::from_utf8(adapted_to_vec(r).await?)?, expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sniffed_inner_type() -> Result<()> {
+        use async_compression::tokio::bufread::ZstdEncoder;
+        use tokio::io::AsyncReadExt;
+        // a gzip file compressed again, without telling so in the name
+        let gz = std::fs::read(test_data_dir().join("hello.gz"))?;
+        let mut zst = Vec::new();
+        ZstdEncoder::new(&gz[..]).read_to_end(&mut zst).await?;
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("data.zst"),
+            Box::pin(std::io::Cursor::new(zst)),
+        );
+        let r = loop_adapt(&DecompressAdapter, d, a).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(r).await?)?,
+            "PREFIX:hello\nPREFIX:\n"
+        );
+
+        assert_eq!(sniff_extension(b"just some text\n"), None);
+        let mut tar = vec![0u8; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff_extension(&tar), Some("tar"));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn pdf_gz() -> Result<()> {
         let adapter = DecompressAdapter;
@@ -0,0 +1,779 @@
+//! Reading of the attributes of DICOM files (medical images, structured reports, DICOMDIR).
+//!
+//! Parses the data set up to the pixel data, which is usually the last element and most of the file.
+
+use super::binary::Endian;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use async_compression::tokio::bufread::DeflateDecoder;
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use std::io::{BufReader, Cursor, Read, Write};
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["dcm", "dicom"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dicom".to_owned(),
+        version: 1,
+        description: "Reads the attributes of DICOM files (medical images and reports), e.g. `PatientName (0010,0010) = Doe^John`, including nested sequences. With --rga-dicom-redact, the values of identifying attributes are replaced with `[redacted]`"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/dicom".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DicomAdapter;
+
+impl DicomAdapter {
+    pub fn new() -> DicomAdapter {
+        DicomAdapter
+    }
+}
+
+impl GetMetadata for DicomAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const UNDEFINED_LENGTH: u32 = u32::MAX;
+const ITEM: u32 = 0xfffe_e000;
+const ITEM_DELIMITER: u32 = 0xfffe_e00d;
+const SEQUENCE_DELIMITER: u32 = 0xfffe_e0dd;
+const PIXEL_DATA: u32 = 0x7fe0_0010;
+const SPECIFIC_CHARACTER_SET: u32 = 0x0008_0005;
+const TRANSFER_SYNTAX: u32 = 0x0002_0010;
+
+const IMPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2";
+const EXPLICIT_VR_BIG_ENDIAN: &str = "1.2.840.10008.1.2.2";
+const DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN: &str = "1.2.840.10008.1.2.1.99";
+
+/// Sequences nested deeper than this are broken
+const MAX_DEPTH: usize = 64;
+/// Larger values (mostly binary data) are skipped
+const MAX_VALUE_LEN: u32 = 1 << 20;
+/// Number of values of numeric attributes that are output
+const MAX_VALUES: usize = 16;
+
+/// Value representations with a 4 byte length in explicit VR encoding
+static LONG_VRS: &[&[u8; 2]] = &[
+    b"OB", b"OD", b"OF", b"OL", b"OV", b"OW", b"SQ", b"SV", b"UC", b"UN", b"UR", b"UT", b"UV",
+];
+
+/// The value representation and keyword of common tags.
+/// The value representation is needed for the implicit VR transfer syntax, which doesn't store it
+static DICTIONARY: &[(u32, &[u8; 2], &str)] = &[
+    (0x0002_0001, b"OB", "FileMetaInformationVersion"),
+    (0x0002_0002, b"UI", "MediaStorageSOPClassUID"),
+    (0x0002_0003, b"UI", "MediaStorageSOPInstanceUID"),
+    (0x0002_0010, b"UI", "TransferSyntaxUID"),
+    (0x0002_0012, b"UI", "ImplementationClassUID"),
+    (0x0002_0013, b"SH", "ImplementationVersionName"),
+    (0x0002_0016, b"AE", "SourceApplicationEntityTitle"),
+    (0x0008_0005, b"CS", "SpecificCharacterSet"),
+    (0x0008_0008, b"CS", "ImageType"),
+    (0x0008_0012, b"DA", "InstanceCreationDate"),
+    (0x0008_0013, b"TM", "InstanceCreationTime"),
+    (0x0008_0016, b"UI", "SOPClassUID"),
+    (0x0008_0018, b"UI", "SOPInstanceUID"),
+    (0x0008_0020, b"DA", "StudyDate"),
+    (0x0008_0021, b"DA", "SeriesDate"),
+    (0x0008_0022, b"DA", "AcquisitionDate"),
+    (0x0008_0023, b"DA", "ContentDate"),
+    (0x0008_0030, b"TM", "StudyTime"),
+    (0x0008_0031, b"TM", "SeriesTime"),
+    (0x0008_0032, b"TM", "AcquisitionTime"),
+    (0x0008_0033, b"TM", "ContentTime"),
+    (0x0008_0050, b"SH", "AccessionNumber"),
+    (0x0008_0060, b"CS", "Modality"),
+    (0x0008_0064, b"CS", "ConversionType"),
+    (0x0008_0070, b"LO", "Manufacturer"),
+    (0x0008_0080, b"LO", "InstitutionName"),
+    (0x0008_0081, b"ST", "InstitutionAddress"),
+    (0x0008_0090, b"PN", "ReferringPhysicianName"),
+    (0x0008_0092, b"ST", "ReferringPhysicianAddress"),
+    (0x0008_0094, b"SH", "ReferringPhysicianTelephoneNumbers"),
+    (0x0008_0100, b"SH", "CodeValue"),
+    (0x0008_0102, b"SH", "CodingSchemeDesignator"),
+    (0x0008_0104, b"LO", "CodeMeaning"),
+    (0x0008_1010, b"SH", "StationName"),
+    (0x0008_1030, b"LO", "StudyDescription"),
+    (0x0008_1032, b"SQ", "ProcedureCodeSequence"),
+    (0x0008_103e, b"LO", "SeriesDescription"),
+    (0x0008_1040, b"LO", "InstitutionalDepartmentName"),
+    (0x0008_1048, b"PN", "PhysiciansOfRecord"),
+    (0x0008_1050, b"PN", "PerformingPhysicianName"),
+    (0x0008_1060, b"PN", "NameOfPhysiciansReadingStudy"),
+    (0x0008_1070, b"PN", "OperatorsName"),
+    (0x0008_1080, b"LO", "AdmittingDiagnosesDescription"),
+    (0x0008_1090, b"LO", "ManufacturerModelName"),
+    (0x0008_1110, b"SQ", "ReferencedStudySequence"),
+    (0x0008_1115, b"SQ", "ReferencedSeriesSequence"),
+    (0x0008_1140, b"SQ", "ReferencedImageSequence"),
+    (0x0008_1150, b"UI", "ReferencedSOPClassUID"),
+    (0x0008_1155, b"UI", "ReferencedSOPInstanceUID"),
+    (0x0008_2111, b"ST", "DerivationDescription"),
+    (0x0010_0010, b"PN", "PatientName"),
+    (0x0010_0020, b"LO", "PatientID"),
+    (0x0010_0021, b"LO", "IssuerOfPatientID"),
+    (0x0010_0030, b"DA", "PatientBirthDate"),
+    (0x0010_0032, b"TM", "PatientBirthTime"),
+    (0x0010_0040, b"CS", "PatientSex"),
+    (0x0010_1000, b"LO", "OtherPatientIDs"),
+    (0x0010_1001, b"PN", "OtherPatientNames"),
+    (0x0010_1002, b"SQ", "OtherPatientIDsSequence"),
+    (0x0010_1005, b"PN", "PatientBirthName"),
+    (0x0010_1010, b"AS", "PatientAge"),
+    (0x0010_1020, b"DS", "PatientSize"),
+    (0x0010_1030, b"DS", "PatientWeight"),
+    (0x0010_1040, b"LO", "PatientAddress"),
+    (0x0010_1060, b"PN", "PatientMotherBirthName"),
+    (0x0010_1090, b"LO", "MedicalRecordLocator"),
+    (0x0010_2154, b"SH", "PatientTelephoneNumbers"),
+    (0x0010_2160, b"SH", "EthnicGroup"),
+    (0x0010_21b0, b"LT", "AdditionalPatientHistory"),
+    (0x0010_4000, b"LT", "PatientComments"),
+    (0x0018_0015, b"CS", "BodyPartExamined"),
+    (0x0018_0050, b"DS", "SliceThickness"),
+    (0x0018_0060, b"DS", "KVP"),
+    (0x0018_1000, b"LO", "DeviceSerialNumber"),
+    (0x0018_1020, b"LO", "SoftwareVersions"),
+    (0x0018_1030, b"LO", "ProtocolName"),
+    (0x0018_5100, b"CS", "PatientPosition"),
+    (0x0020_000d, b"UI", "StudyInstanceUID"),
+    (0x0020_000e, b"UI", "SeriesInstanceUID"),
+    (0x0020_0010, b"SH", "StudyID"),
+    (0x0020_0011, b"IS", "SeriesNumber"),
+    (0x0020_0012, b"IS", "AcquisitionNumber"),
+    (0x0020_0013, b"IS", "InstanceNumber"),
+    (0x0020_0032, b"DS", "ImagePositionPatient"),
+    (0x0020_0037, b"DS", "ImageOrientationPatient"),
+    (0x0020_0052, b"UI", "FrameOfReferenceUID"),
+    (0x0020_4000, b"LT", "ImageComments"),
+    (0x0028_0002, b"US", "SamplesPerPixel"),
+    (0x0028_0004, b"CS", "PhotometricInterpretation"),
+    (0x0028_0008, b"IS", "NumberOfFrames"),
+    (0x0028_0010, b"US", "Rows"),
+    (0x0028_0011, b"US", "Columns"),
+    (0x0028_0030, b"DS", "PixelSpacing"),
+    (0x0028_0100, b"US", "BitsAllocated"),
+    (0x0028_0101, b"US", "BitsStored"),
+    (0x0028_0102, b"US", "HighBit"),
+    (0x0028_0103, b"US", "PixelRepresentation"),
+    (0x0028_1050, b"DS", "WindowCenter"),
+    (0x0028_1051, b"DS", "WindowWidth"),
+    (0x0028_1052, b"DS", "RescaleIntercept"),
+    (0x0028_1053, b"DS", "RescaleSlope"),
+    (0x0032_1032, b"PN", "RequestingPhysician"),
+    (0x0032_1060, b"LO", "RequestedProcedureDescription"),
+    (0x0040_0244, b"DA", "PerformedProcedureStepStartDate"),
+    (0x0040_0254, b"LO", "PerformedProcedureStepDescription"),
+    (0x0040_a010, b"CS", "RelationshipType"),
+    (0x0040_a040, b"CS", "ValueType"),
+    (0x0040_a043, b"SQ", "ConceptNameCodeSequence"),
+    (0x0040_a160, b"UT", "TextValue"),
+    (0x0040_a730, b"SQ", "ContentSequence"),
+    (0x0088_0200, b"SQ", "IconImageSequence"),
+    (0x7fe0_0010, b"OW", "PixelData"),
+];
+
+/// Attributes that identify the patient (besides person names and private tags), following the
+/// basic profile of the DICOM de-identification standard (PS3.15 E)
+static IDENTIFYING_TAGS: &[u32] = &[
+    0x0002_0003, // MediaStorageSOPInstanceUID
+    0x0008_0018, // SOPInstanceUID
+    0x0008_0050, // AccessionNumber
+    0x0008_0080, // InstitutionName
+    0x0008_0081, // InstitutionAddress
+    0x0008_0092, // ReferringPhysicianAddress
+    0x0008_0094, // ReferringPhysicianTelephoneNumbers
+    0x0008_1010, // StationName
+    0x0008_1040, // InstitutionalDepartmentName
+    0x0008_1155, // ReferencedSOPInstanceUID
+    0x0010_0020, // PatientID
+    0x0010_0030, // PatientBirthDate
+    0x0010_0032, // PatientBirthTime
+    0x0010_1000, // OtherPatientIDs
+    0x0010_1002, // OtherPatientIDsSequence
+    0x0010_1010, // PatientAge
+    0x0010_1040, // PatientAddress
+    0x0010_1090, // MedicalRecordLocator
+    0x0010_2154, // PatientTelephoneNumbers
+    0x0010_2160, // EthnicGroup
+    0x0010_21b0, // AdditionalPatientHistory
+    0x0010_4000, // PatientComments
+    0x0018_1000, // DeviceSerialNumber
+    0x0020_000d, // StudyInstanceUID
+    0x0020_000e, // SeriesInstanceUID
+    0x0020_0010, // StudyID
+    0x0020_0052, // FrameOfReferenceUID
+];
+
+fn dictionary(tag: u32) -> Option<(&'static [u8; 2], &'static str)> {
+    DICTIONARY
+        .iter()
+        .find(|(t, _, _)| *t == tag)
+        .map(|(_, vr, keyword)| (*vr, *keyword))
+}
+
+fn is_private(tag: u32) -> bool {
+    (tag >> 16) % 2 == 1
+}
+
+fn is_identifying(tag: u32, vr: &[u8; 2]) -> bool {
+    vr == b"PN" || is_private(tag) || IDENTIFYING_TAGS.contains(&tag)
+}
+
+/// `(0010,0010)`
+fn tag_number(tag: u32) -> String {
+    format!("({:04X},{:04X})", tag >> 16, tag & 0xffff)
+}
+
+/// The encoding of a Specific Character Set value. Code extensions (ISO 2022 escape sequences)
+/// are not supported, the first value is used
+fn character_set(value: &str) -> Option<&'static Encoding> {
+    let label = match value.split('\\').find(|v| !v.trim().is_empty())?.trim() {
+        "ISO_IR 192" => "utf-8",
+        "ISO_IR 100" | "ISO 2022 IR 100" => "windows-1252",
+        "ISO_IR 101" | "ISO 2022 IR 101" => "iso-8859-2",
+        "ISO_IR 109" | "ISO 2022 IR 109" => "iso-8859-3",
+        "ISO_IR 110" | "ISO 2022 IR 110" => "iso-8859-4",
+        "ISO_IR 144" | "ISO 2022 IR 144" => "iso-8859-5",
+        "ISO_IR 127" | "ISO 2022 IR 127" => "iso-8859-6",
+        "ISO_IR 126" | "ISO 2022 IR 126" => "iso-8859-7",
+        "ISO_IR 138" | "ISO 2022 IR 138" => "iso-8859-8",
+        "ISO_IR 148" | "ISO 2022 IR 148" => "windows-1254",
+        "ISO_IR 166" | "ISO 2022 IR 166" => "windows-874",
+        "ISO_IR 13" | "ISO 2022 IR 13" => "shift_jis",
+        "ISO 2022 IR 87" => "iso-2022-jp",
+        "ISO 2022 IR 149" => "euc-kr",
+        "GB18030" => "gb18030",
+        "GBK" => "gbk",
+        _ => return None,
+    };
+    Encoding::for_label(label.as_bytes())
+}
+
+struct Element {
+    tag: u32,
+    vr: [u8; 2],
+    len: u32,
+}
+
+struct Parser<'a, R> {
+    r: R,
+    pos: u64,
+    explicit_vr: bool,
+    endian: Endian,
+    encoding: Option<&'static Encoding>,
+    redact: bool,
+    transfer_syntax: Option<String>,
+    line_prefix: &'a str,
+}
+
+impl<'a, R: Read> Parser<'a, R> {
+    fn new(r: R, transfer_syntax: &str, redact: bool, line_prefix: &'a str) -> Parser<'a, R> {
+        Parser {
+            r,
+            pos: 0,
+            explicit_vr: transfer_syntax != IMPLICIT_VR_LITTLE_ENDIAN,
+            endian: if transfer_syntax == EXPLICIT_VR_BIG_ENDIAN {
+                Endian::Big
+            } else {
+                Endian::Little
+            },
+            encoding: None,
+            redact,
+            transfer_syntax: None,
+            line_prefix,
+        }
+    }
+
+    fn read(&mut self, len: u32) -> Result<Vec<u8>> {
+        let mut data = vec![0; len as usize];
+        self.r
+            .read_exact(&mut data)
+            .context("truncated DICOM file")?;
+        self.pos += len as u64;
+        Ok(data)
+    }
+
+    fn skip(&mut self, len: u32) -> Result<()> {
+        let skipped = std::io::copy(&mut self.r.by_ref().take(len as u64), &mut std::io::sink())?;
+        anyhow::ensure!(skipped == len as u64, "truncated DICOM file");
+        self.pos += skipped;
+        Ok(())
+    }
+
+    fn tag(&self, b: &[u8]) -> Option<u32> {
+        Some(((self.endian.u16_at(b, 0)? as u32) << 16) | self.endian.u16_at(b, 2)? as u32)
+    }
+
+    /// The next element header, None at the end of the file
+    fn header(&mut self) -> Result<Option<Element>> {
+        let mut b = [0; 4];
+        match self.r.read_exact(&mut b) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+        self.pos += 4;
+        let tag = self.tag(&b).context("truncated DICOM file")?;
+        if tag >> 16 == 0xfffe {
+            // items and delimiters have no value representation
+            let len = self.read(4)?;
+            return Ok(Some(Element {
+                tag,
+                vr: [0; 2],
+                len: self
+                    .endian
+                    .u32_at(&len, 0)
+                    .context("truncated DICOM file")?,
+            }));
+        }
+        if self.explicit_vr {
+            let vr = self.read(2)?;
+            let vr = [vr[0], vr[1]];
+            let len = if LONG_VRS.contains(&&vr) {
+                let len = self.read(6)?;
+                self.endian.u32_at(&len, 2)
+            } else {
+                let len = self.read(2)?;
+                self.endian.u16_at(&len, 0).map(u32::from)
+            }
+            .context("truncated DICOM file")?;
+            return Ok(Some(Element { tag, vr, len }));
+        }
+        let len = self.read(4)?;
+        let vr = match dictionary(tag) {
+            Some((vr, _)) => *vr,
+            // group length
+            None if tag & 0xffff == 0 => *b"UL",
+            // private creator
+            None if is_private(tag) && (0x10..=0xff).contains(&(tag & 0xffff)) => *b"LO",
+            None => *b"UN",
+        };
+        Ok(Some(Element {
+            tag,
+            vr,
+            len: self
+                .endian
+                .u32_at(&len, 0)
+                .context("truncated DICOM file")?,
+        }))
+    }
+
+    fn decode(&self, data: &[u8]) -> String {
+        match self.encoding {
+            Some(encoding) => encoding.decode_without_bom_handling(data).0.into_owned(),
+            None => match std::str::from_utf8(data) {
+                Ok(s) => s.to_owned(),
+                Err(_) => encoding_rs::WINDOWS_1252.decode(data).0.into_owned(),
+            },
+        }
+    }
+
+    /// The value as text, None for binary values
+    fn value(&self, vr: &[u8; 2], data: &[u8]) -> Option<String> {
+        let numbers = |size: usize, format: &dyn Fn(&[u8]) -> Option<String>| {
+            let mut values: Vec<String> = data
+                .chunks_exact(size)
+                .take(MAX_VALUES)
+                .filter_map(format)
+                .collect();
+            if data.len() / size > MAX_VALUES {
+                values.push("...".to_owned());
+            }
+            values.join("\\")
+        };
+        let (u16_of, u32_of, u64_of) = (
+            |b: &[u8]| self.endian.u16_at(b, 0),
+            |b: &[u8]| self.endian.u32_at(b, 0),
+            |b: &[u8]| self.endian.u64_at(b, 0),
+        );
+        Some(match vr {
+            b"AE" | b"AS" | b"CS" | b"DA" | b"DS" | b"DT" | b"IS" | b"LO" | b"PN" | b"SH"
+            | b"TM" | b"UC" | b"UI" | b"UR" => self
+                .decode(data)
+                .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_owned(),
+            b"LT" | b"ST" | b"UT" => self.decode(data).trim_end_matches(['\0', ' ']).to_owned(),
+            b"US" => numbers(2, &|b| Some(u16_of(b)?.to_string())),
+            b"SS" => numbers(2, &|b| Some((u16_of(b)? as i16).to_string())),
+            b"UL" => numbers(4, &|b| Some(u32_of(b)?.to_string())),
+            b"SL" => numbers(4, &|b| Some((u32_of(b)? as i32).to_string())),
+            b"UV" => numbers(8, &|b| Some(u64_of(b)?.to_string())),
+            b"SV" => numbers(8, &|b| Some((u64_of(b)? as i64).to_string())),
+            b"FL" => numbers(4, &|b| Some(f32::from_bits(u32_of(b)?).to_string())),
+            b"FD" => numbers(8, &|b| Some(f64::from_bits(u64_of(b)?).to_string())),
+            b"AT" => numbers(4, &|b| Some(tag_number(self.tag(b)?))),
+            // mostly private tags with text values in files with implicit VR
+            b"UN" => {
+                let text = std::str::from_utf8(data)
+                    .ok()?
+                    .trim_end_matches(['\0', ' ']);
+                let printable = text.chars().all(|c| !c.is_control() || c.is_whitespace());
+                if text.is_empty() || !printable {
+                    return None;
+                }
+                text.to_owned()
+            }
+            _ => return None,
+        })
+    }
+
+    /// Skips the value of an element, which can be encapsulated pixel data of undefined length
+    fn skip_value(&mut self, e: &Element) -> Result<()> {
+        if e.len != UNDEFINED_LENGTH {
+            return self.skip(e.len);
+        }
+        while let Some(item) = self.header()? {
+            if item.tag != ITEM {
+                break;
+            }
+            self.skip(item.len)?;
+        }
+        Ok(())
+    }
+
+    /// Elements until `end`, an item delimiter or the end of the file. At the top level, stops at the pixel data
+    fn data_set(
+        &mut self,
+        path: &str,
+        end: Option<u64>,
+        depth: usize,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        anyhow::ensure!(depth < MAX_DEPTH, "sequences nested too deeply");
+        while end.is_none_or(|end| self.pos < end) {
+            let Some(e) = self.header()? else {
+                break;
+            };
+            if e.tag == ITEM_DELIMITER || (e.tag == PIXEL_DATA && depth == 0) {
+                break;
+            }
+            self.element(&e, path, depth, out)?;
+        }
+        Ok(())
+    }
+
+    fn sequence(
+        &mut self,
+        e: &Element,
+        path: &str,
+        depth: usize,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let end = (e.len != UNDEFINED_LENGTH).then(|| self.pos + e.len as u64);
+        let mut item = 0;
+        while end.is_none_or(|end| self.pos < end) {
+            let Some(header) = self.header()? else {
+                break;
+            };
+            match header.tag {
+                ITEM => {
+                    item += 1;
+                    let item_end =
+                        (header.len != UNDEFINED_LENGTH).then(|| self.pos + header.len as u64);
+                    self.data_set(&format!("{path}[{item}] > "), item_end, depth + 1, out)?;
+                }
+                SEQUENCE_DELIMITER => break,
+                tag => anyhow::bail!("unexpected element {} in sequence", tag_number(tag)),
+            }
+        }
+        Ok(())
+    }
+
+    fn element(
+        &mut self,
+        e: &Element,
+        path: &str,
+        depth: usize,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        // group lengths, pixel data in nested data sets (icons) and stray items
+        if e.tag & 0xffff == 0 || e.tag == PIXEL_DATA || e.tag >> 16 == 0xfffe {
+            return self.skip_value(e);
+        }
+        let p = self.line_prefix;
+        let keyword = dictionary(e.tag).map(|(_, keyword)| keyword);
+        let label = match keyword {
+            Some(keyword) => format!("{path}{keyword} {}", tag_number(e.tag)),
+            None => format!("{path}{}", tag_number(e.tag)),
+        };
+        let redacted = self.redact && is_identifying(e.tag, &e.vr);
+        // values of unknown type and undefined length are sequences in implicit VR little endian
+        if &e.vr == b"SQ" || e.len == UNDEFINED_LENGTH {
+            let item_path = format!(
+                "{path}{}",
+                keyword.map_or_else(|| tag_number(e.tag), str::to_owned)
+            );
+            let encoding = (self.explicit_vr, self.endian);
+            if &e.vr != b"SQ" {
+                (self.explicit_vr, self.endian) = (false, Endian::Little);
+            }
+            let result = if redacted {
+                writeln!(out, "{p}{label} = [redacted]")?;
+                self.sequence(e, &item_path, depth, &mut std::io::sink())
+            } else {
+                self.sequence(e, &item_path, depth, out)
+            };
+            (self.explicit_vr, self.endian) = encoding;
+            return result;
+        }
+        if e.len > MAX_VALUE_LEN {
+            return self.skip(e.len);
+        }
+        let data = self.read(e.len)?;
+        let Some(value) = self.value(&e.vr, &data) else {
+            return Ok(());
+        };
+        match e.tag {
+            SPECIFIC_CHARACTER_SET => self.encoding = character_set(&value),
+            TRANSFER_SYNTAX => self.transfer_syntax = Some(value.clone()),
+            _ => {}
+        }
+        if redacted {
+            writeln!(out, "{p}{label} = [redacted]")?;
+            return Ok(());
+        }
+        for line in value.lines().filter(|l| !l.trim().is_empty()) {
+            writeln!(out, "{p}{label} = {}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+fn synchronous_dump_dicom(
+    inp: impl Read,
+    transfer_syntax: &str,
+    meta_output: &[u8],
+    redact: bool,
+    line_prefix: &str,
+    mut out: impl Write,
+) -> Result<()> {
+    out.write_all(meta_output)?;
+    let mut parser = Parser::new(BufReader::new(inp), transfer_syntax, redact, line_prefix);
+    parser.data_set("", None, 0, &mut out)?;
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for DicomAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let redact = config.dicom_redact;
+        let mut preamble = Vec::new();
+        (&mut inp).take(132).read_to_end(&mut preamble).await?;
+        let mut meta_output = Vec::new();
+        let (body, transfer_syntax): (ReadBox, String) =
+            if preamble.get(128..) == Some(&b"DICM"[..]) {
+                // the file meta information, always explicit VR little endian, starts with its length
+                let mut meta = vec![0; 12];
+                inp.read_exact(&mut meta).await?;
+                anyhow::ensure!(
+                    meta[..6] == *b"\x02\0\0\0UL",
+                    "DICOM file meta information without group length"
+                );
+                let len = u32::from_le_bytes(meta[8..12].try_into()?);
+                anyhow::ensure!(
+                    len <= MAX_VALUE_LEN,
+                    "invalid DICOM file meta information length"
+                );
+                meta.resize(12 + len as usize, 0);
+                inp.read_exact(&mut meta[12..]).await?;
+                let mut parser = Parser::new(Cursor::new(meta), "", redact, &line_prefix);
+                parser.data_set("", None, 0, &mut meta_output)?;
+                let transfer_syntax = parser.transfer_syntax.unwrap_or_default();
+                if transfer_syntax == DEFLATED_EXPLICIT_VR_LITTLE_ENDIAN {
+                    let inflated = DeflateDecoder::new(tokio::io::BufReader::new(inp));
+                    (Box::pin(inflated), transfer_syntax)
+                } else {
+                    (inp, transfer_syntax)
+                }
+            } else {
+                // no preamble (old files), the data set starts at the beginning
+                let body = AsyncReadExt::chain(Cursor::new(preamble), inp);
+                (Box::pin(body), IMPLICIT_VR_LITTLE_ENDIAN.to_owned())
+            };
+        let body = SyncIoBridge::new(body);
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            synchronous_dump_dicom(
+                body,
+                &transfer_syntax,
+                &meta_output,
+                redact,
+                &line_prefix,
+                oup,
+            )
+        })
+        .await?
+        .context("in synchronous dicom task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    fn explicit(tag: u32, vr: &[u8; 2], value: &[u8]) -> Vec<u8> {
+        let mut e = Vec::new();
+        e.extend(((tag >> 16) as u16).to_le_bytes());
+        e.extend((tag as u16).to_le_bytes());
+        e.extend(vr);
+        if LONG_VRS.contains(&vr) {
+            e.extend([0, 0]);
+            e.extend((value.len() as u32).to_le_bytes());
+        } else {
+            e.extend((value.len() as u16).to_le_bytes());
+        }
+        e.extend(value);
+        e
+    }
+
+    fn implicit(tag: u32, value: &[u8]) -> Vec<u8> {
+        let mut e = Vec::new();
+        e.extend(((tag >> 16) as u16).to_le_bytes());
+        e.extend((tag as u16).to_le_bytes());
+        e.extend((value.len() as u32).to_le_bytes());
+        e.extend(value);
+        e
+    }
+
+    /// A CT image with a referenced study (sequence of undefined length) and a private tag
+    fn image() -> Vec<u8> {
+        let meta = [
+            explicit(0x0002_0002, b"UI", b"1.2.840.10008.5.1.4.1.1.2\0"),
+            explicit(0x0002_0003, b"UI", b"1.2.3.4.5\0"),
+            explicit(0x0002_0010, b"UI", b"1.2.840.10008.1.2.1\0"),
+        ]
+        .concat();
+        let mut data = vec![0; 128];
+        data.extend(b"DICM");
+        data.extend(explicit(
+            0x0002_0000,
+            b"UL",
+            &(meta.len() as u32).to_le_bytes(),
+        ));
+        data.extend(meta);
+        data.extend(explicit(0x0008_0005, b"CS", b"ISO_IR 100"));
+        data.extend(explicit(0x0008_0060, b"CS", b"CT"));
+        data.extend(explicit(0x0008_1030, b"LO", b"Chest \xe9tude "));
+        // sequence and item of undefined length
+        data.extend(explicit(0x0008_1110, b"SQ", &[]));
+        data.truncate(data.len() - 4);
+        data.extend(UNDEFINED_LENGTH.to_le_bytes());
+        data.extend(implicit(ITEM, &[]));
+        data.truncate(data.len() - 4);
+        data.extend(UNDEFINED_LENGTH.to_le_bytes());
+        data.extend(explicit(0x0008_1155, b"UI", b"1.2.3.99"));
+        data.extend(implicit(ITEM_DELIMITER, &[]));
+        data.extend(implicit(SEQUENCE_DELIMITER, &[]));
+        data.extend(explicit(0x0009_1001, b"LO", b"ACME scanner"));
+        data.extend(explicit(0x0010_0010, b"PN", b"Doe^John"));
+        data.extend(explicit(0x0010_0020, b"LO", b"12345 "));
+        data.extend(explicit(0x0028_0010, b"US", &512u16.to_le_bytes()));
+        data.extend(explicit(
+            0x0040_a160,
+            b"UT",
+            b"No findings.\r\nFollow-up in 6 months.",
+        ));
+        data.extend(explicit(PIXEL_DATA, b"OW", &[0xff; 64]));
+        data
+    }
+
+    async fn adapt(filename: &str, data: Vec<u8>, redact: bool) -> Result<String> {
+        let (mut a, d) = simple_adapt_info(&PathBuf::from(filename), Box::pin(Cursor::new(data)));
+        a.config.dicom_redact = redact;
+        let buf = adapted_to_vec(loop_adapt(&DicomAdapter::new(), d, a).await?).await?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[tokio::test]
+    async fn image_attributes() -> Result<()> {
+        assert_eq!(
+            adapt("image.dcm", image(), false).await?,
+            "PREFIX:MediaStorageSOPClassUID (0002,0002) = 1.2.840.10008.5.1.4.1.1.2
+PREFIX:MediaStorageSOPInstanceUID (0002,0003) = 1.2.3.4.5
+PREFIX:TransferSyntaxUID (0002,0010) = 1.2.840.10008.1.2.1
+PREFIX:SpecificCharacterSet (0008,0005) = ISO_IR 100
+PREFIX:Modality (0008,0060) = CT
+PREFIX:StudyDescription (0008,1030) = Chest \u{e9}tude
+PREFIX:ReferencedStudySequence[1] > ReferencedSOPInstanceUID (0008,1155) = 1.2.3.99
+PREFIX:(0009,1001) = ACME scanner
+PREFIX:PatientName (0010,0010) = Doe^John
+PREFIX:PatientID (0010,0020) = 12345
+PREFIX:Rows (0028,0010) = 512
+PREFIX:TextValue (0040,A160) = No findings.
+PREFIX:TextValue (0040,A160) = Follow-up in 6 months.
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redacted() -> Result<()> {
+        assert_eq!(
+            adapt("image.dcm", image(), true).await?,
+            "PREFIX:MediaStorageSOPClassUID (0002,0002) = 1.2.840.10008.5.1.4.1.1.2
+PREFIX:MediaStorageSOPInstanceUID (0002,0003) = [redacted]
+PREFIX:TransferSyntaxUID (0002,0010) = 1.2.840.10008.1.2.1
+PREFIX:SpecificCharacterSet (0008,0005) = ISO_IR 100
+PREFIX:Modality (0008,0060) = CT
+PREFIX:StudyDescription (0008,1030) = Chest \u{e9}tude
+PREFIX:ReferencedStudySequence[1] > ReferencedSOPInstanceUID (0008,1155) = [redacted]
+PREFIX:(0009,1001) = [redacted]
+PREFIX:PatientName (0010,0010) = [redacted]
+PREFIX:PatientID (0010,0020) = [redacted]
+PREFIX:Rows (0028,0010) = 512
+PREFIX:TextValue (0040,A160) = No findings.
+PREFIX:TextValue (0040,A160) = Follow-up in 6 months.
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn implicit_vr_without_preamble() -> Result<()> {
+        let data = [
+            implicit(0x0008_0000, &8u32.to_le_bytes()),
+            implicit(0x0008_0060, b"MR"),
+            implicit(0x0010_0010, b"Roe^Jane"),
+            implicit(0x0029_0010, b"SIEMENS "),
+            implicit(0x0029_1008, &[1, 2, 0, 0]),
+        ]
+        .concat();
+        assert_eq!(
+            adapt("old.dcm", data, false).await?,
+            "PREFIX:Modality (0008,0060) = MR
+PREFIX:PatientName (0010,0010) = Roe^Jane
+PREFIX:(0029,0010) = SIEMENS
+"
+        );
+        Ok(())
+    }
+}
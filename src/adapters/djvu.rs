@@ -0,0 +1,151 @@
+use super::custom::spawn_output;
+use super::sevenzip::archive_on_disk;
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio_util::io::StreamReader;
+
+static EXTENSIONS: &[&str] = &["djvu", "djv"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "djvu".to_owned(),
+        version: 1,
+        description: "Uses djvutxt (from DjVuLibre) to extract the hidden text layer of DjVu documents, with the same page prefixes as PDF files.\nDocuments within other archives are written to a temporary file first."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("image/vnd.djvu".to_owned()),
+            FileMatcher::MimeType("image/x-djvu".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DjvuAdapter;
+
+impl DjvuAdapter {
+    pub fn new() -> DjvuAdapter {
+        DjvuAdapter
+    }
+}
+
+impl GetMetadata for DjvuAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have djvutxt (from djvulibre-bin or djvulibre) installed.";
+
+#[async_trait]
+impl FileAdapter for DjvuAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+        } = ai;
+        // djvutxt can't read from stdin
+        let (path, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let mut cmd = Command::new("djvutxt");
+        cmd.arg(&path);
+        debug!("executing {:?}", cmd);
+        let output = spawn_output(cmd, "djvutxt", HELP)?;
+        // keep the temporary file until djvutxt is done
+        let done = StreamReader::new(stream! {
+            let _tmp = tmp;
+            yield std::io::Result::Ok(&b""[..]);
+        });
+        Ok(one_file(AdaptInfo {
+            // djvutxt ends every page with a form feed, like pdftotext
+            filepath_hint: PathBuf::from(format!(
+                "{}.txt.asciipagebreaks",
+                filepath_hint.display()
+            )),
+            inp: Box::pin(output.chain(done)),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+            passwords,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use std::io::Cursor;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        c.extend((data.len() as u32).to_be_bytes());
+        c.extend(data);
+        if data.len() % 2 == 1 {
+            c.push(0);
+        }
+        c
+    }
+
+    /// A single page document with an uncompressed text layer
+    fn create_djvu(text: &str) -> Vec<u8> {
+        // 100x100 pixels, version 24, 300 dpi, gamma 2.2
+        let info = [0, 100, 0, 100, 24, 0, 0x2c, 0x01, 22, 1];
+        let mut txt = (text.len() as u32).to_be_bytes()[1..].to_vec();
+        txt.extend(text.as_bytes());
+        // version and the page zone, coordinates are stored offset by 0x8000
+        txt.push(1);
+        txt.push(1);
+        for n in [0u16, 0, 100, 100, 0] {
+            txt.extend((n + 0x8000).to_be_bytes());
+        }
+        txt.extend(&(text.len() as u32).to_be_bytes()[1..]);
+        txt.extend([0, 0, 0]);
+        let page = [&b"DJVU"[..], &chunk(b"INFO", &info), &chunk(b"TXTa", &txt)].concat();
+        [&b"AT&T"[..], &chunk(b"FORM", &page)].concat()
+    }
+
+    #[tokio::test]
+    async fn text_layer() -> Result<()> {
+        if std::process::Command::new("djvutxt").output().is_err() {
+            eprintln!("djvutxt not installed, skipping test");
+            return Ok(());
+        }
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("scan.djvu"),
+            Box::pin(Cursor::new(create_djvu("hello from the scanned page"))),
+        );
+        let buf = adapted_to_vec(loop_adapt(&DjvuAdapter::new(), d, a).await?).await?;
+        let text = String::from_utf8(buf)?;
+        assert!(
+            text.lines()
+                .any(|l| l == "PREFIX:Page 1: hello from the scanned page"),
+            "{text}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use mailparse::{DispositionType, MailHeaderMap, ParsedMail};
+use mime2ext::mime2ext;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["eml"];
+static MIME_TYPES: &[&str] = &["message/rfc822"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "eml".to_owned(),
+        version: 1,
+        description: "Reads single mails (.eml). Outputs the decoded headers (from, to, subject, date, ...) and body, and recurses into the attachments (prefixed with their file name), including attached mails"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIME_TYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        disabled_by_default: false,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EmlAdapter;
+
+impl EmlAdapter {
+    pub fn new() -> EmlAdapter {
+        EmlAdapter
+    }
+}
+impl GetMetadata for EmlAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Headers that are output, in this order
+static HEADERS: &[&str] = &["From", "To", "Cc", "Bcc", "Reply-To", "Date", "Subject"];
+
+/// A part of a mail that is searched as a file of its own
+pub struct MailPart {
+    /// file name of the part, used to find the adapter
+    pub name: String,
+    /// appended to the line prefix of the mail
+    pub prefix: String,
+    pub data: Vec<u8>,
+}
+
+/// The decoded headers of a mail as `Name: value` lines
+fn header_text(mail: &ParsedMail) -> String {
+    let mut lines = Vec::new();
+    for name in HEADERS {
+        for value in mail.headers.get_all_values(name) {
+            lines.push(format!("{name}: {}", value.trim()));
+        }
+    }
+    lines.join("\n")
+}
+
+fn filename(part: &ParsedMail) -> Option<String> {
+    part.get_content_disposition()
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .map(|name| {
+            // only the base name, names with directories are not unusual
+            let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+            name.to_string()
+        })
+        .filter(|name| !name.is_empty())
+}
+
+fn collect_parts(part: &ParsedMail, parts: &mut Vec<MailPart>) {
+    let mime = part.ctype.mimetype.as_str();
+    if mime == "multipart/alternative" && !part.subparts.is_empty() {
+        // the same content in different formats, plain text is the most searchable
+        let preferred = part
+            .subparts
+            .iter()
+            .find(|p| p.ctype.mimetype == "text/plain")
+            .or(part.subparts.last());
+        if let Some(preferred) = preferred {
+            collect_parts(preferred, parts);
+        }
+        return;
+    }
+    if mime.starts_with("multipart/") {
+        for sub in &part.subparts {
+            collect_parts(sub, parts);
+        }
+        return;
+    }
+    let attachment = part.get_content_disposition().disposition == DispositionType::Attachment;
+    let name = filename(part);
+    if attachment || name.is_some() || mime == "message/rfc822" {
+        let ext = mime2ext(mime).unwrap_or("bin");
+        let name = name.unwrap_or_else(|| format!("attachment.{ext}"));
+        match part.get_body_raw() {
+            Ok(data) => parts.push(MailPart {
+                prefix: format!("{name}: "),
+                name,
+                data,
+            }),
+            Err(e) => warn!("could not decode mail attachment {name}: {e}"),
+        }
+        return;
+    }
+    // inline body: decoded to UTF-8 for text, raw otherwise
+    let data = if mime.starts_with("text/") {
+        part.get_body().map(String::into_bytes)
+    } else {
+        part.get_body_raw()
+    };
+    match data {
+        Ok(data) => parts.push(MailPart {
+            name: format!("data.{}", mime2ext(mime).unwrap_or("txt")),
+            prefix: String::new(),
+            data,
+        }),
+        Err(e) => warn!("could not decode mail body: {e}"),
+    }
+}
+
+/// Split a parsed mail into its headers, body and attachments, with transfer encodings decoded
+pub fn mail_parts(mail: &ParsedMail) -> Vec<MailPart> {
+    let mut parts = Vec::new();
+    let headers = header_text(mail);
+    if !headers.is_empty() {
+        parts.push(MailPart {
+            name: "headers.txt".to_string(),
+            prefix: String::new(),
+            data: headers.into_bytes(),
+        });
+    }
+    collect_parts(mail, &mut parts);
+    parts
+}
+
+/// The parts of a mail as files within the mail `ai`, whose input is not read anymore
+pub fn adapt_parts(parts: Vec<MailPart>, ai: AdaptInfo) -> AdaptedFilesIterBox {
+    let AdaptInfo {
+        filepath_hint,
+        line_prefix,
+        archive_recursion_depth,
+        config,
+        passwords,
+        postprocess,
+        ..
+    } = ai;
+    let s = stream! {
+        for part in parts {
+            yield Ok(AdaptInfo {
+                filepath_hint: filepath_hint.join(part.name),
+                is_real_file: false,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                inp: Box::pin(Cursor::new(part.data)),
+                line_prefix: format!("{line_prefix}{}", part.prefix),
+                config: config.clone(),
+                passwords: passwords.clone(),
+                postprocess,
+            });
+        }
+    };
+    Box::pin(s)
+}
+
+#[async_trait]
+impl FileAdapter for EmlAdapter {
+    async fn adapt(
+        &self,
+        mut ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let mut content = Vec::new();
+        ai.inp.read_to_end(&mut content).await?;
+        let mail = mailparse::parse_mail(&content).context("invalid mail")?;
+        Ok(adapt_parts(mail_parts(&mail), ai))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn attachments() -> Result<()> {
+        let mail = "From: =?UTF-8?Q?J=C3=BCrgen?= <j@example.com>
+To: a@example.com, b@example.com
+Subject: =?UTF-8?B?R3LDvMOfZQ==?=
+Content-Type: multipart/mixed; boundary=\"outer\"
+
+--outer
+Content-Type: multipart/alternative; boundary=\"inner\"
+
+--inner
+Content-Type: text/plain; charset=utf-8
+Content-Transfer-Encoding: quoted-printable
+
+Sch=C3=B6ne Gr=C3=BC=C3=9Fe
+--inner
+Content-Type: text/html; charset=utf-8
+
+<p>Sch&ouml;ne Gr&uuml;&szlig;e</p>
+--inner--
+--outer
+Content-Type: text/plain; name=\"notes.txt\"
+Content-Disposition: attachment; filename=\"notes.txt\"
+Content-Transfer-Encoding: base64
+
+YXR0YWNoZWQgbm90ZXMK
+--outer
+Content-Type: message/rfc822
+
+Subject: forwarded
+
+the forwarded body
+--outer--
+";
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("greeting.eml"),
+            Box::pin(Cursor::new(mail.replace('\n', "\r\n"))),
+        );
+        let buf = adapted_to_vec(loop_adapt(&EmlAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?.replace('\r', ""),
+            "PREFIX:From: Jürgen <j@example.com>
+PREFIX:To: a@example.com, b@example.com
+PREFIX:Subject: Grüße
+PREFIX:Schöne Grüße
+PREFIX:notes.txt: attached notes
+PREFIX:notes.txt: 
+PREFIX:attachment.eml: Subject: forwarded
+PREFIX:attachment.eml: the forwarded body
+"
+        );
+        Ok(())
+    }
+}
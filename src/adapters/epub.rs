@@ -0,0 +1,282 @@
+use super::xml::{XmlEvent, XmlReader, html_to_text, local_name, resolve_href};
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Cursor;
+
+static EXTENSIONS: &[&str] = &["epub"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "epub".to_owned(),
+        version: 1,
+        description:
+            "Reads the chapters of EPUB e-books in reading order and converts them to plain text.\nEach line is prefixed with the file of its chapter."
+                .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/epub+zip".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EpubAdapter;
+
+impl EpubAdapter {
+    pub fn new() -> EpubAdapter {
+        EpubAdapter
+    }
+}
+
+impl GetMetadata for EpubAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Dublin Core metadata elements of the package document that are output
+const METADATA_ELEMENTS: &[&str] = &[
+    "title",
+    "creator",
+    "contributor",
+    "subject",
+    "description",
+    "publisher",
+    "date",
+];
+
+/// The text of the book as (path in the container, text) with the metadata first and then the chapters in reading order.
+fn book_text(files: &[(String, Vec<u8>)]) -> Vec<(String, String)> {
+    let get = |name: &str| {
+        files
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, content)| String::from_utf8_lossy(content))
+    };
+    let is_html = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        name.ends_with(".xhtml") || name.ends_with(".html") || name.ends_with(".htm")
+    };
+    let package_path = get("META-INF/container.xml")
+        .and_then(|container| {
+            XmlReader::new(&container)
+                .find(
+                    |e| matches!(e, XmlEvent::Start { name, .. } if local_name(name) == "rootfile"),
+                )
+                .and_then(|e| e.attr("full-path").map(str::to_string))
+        })
+        .or_else(|| {
+            files
+                .iter()
+                .find(|(n, _)| n.ends_with(".opf"))
+                .map(|(n, _)| n.clone())
+        });
+
+    let mut out = Vec::new();
+    let mut chapters = Vec::new();
+    if let Some(package_path) = package_path
+        && let Some(package) = get(&package_path)
+    {
+        let base_dir = package_path.rsplit_once('/').map(|(d, _)| d).unwrap_or("");
+        let mut metadata = String::new();
+        // id -> (path, media type)
+        let mut manifest = HashMap::new();
+        let mut spine = Vec::new();
+        let mut in_metadata: Option<(String, String)> = None;
+        for event in XmlReader::new(&package) {
+            match &event {
+                XmlEvent::Start {
+                    name, self_closing, ..
+                } => match local_name(name) {
+                    "item" => {
+                        if let (Some(id), Some(href)) = (event.attr("id"), event.attr("href")) {
+                            manifest.insert(
+                                id.to_string(),
+                                (
+                                    resolve_href(base_dir, href),
+                                    event.attr("media-type").unwrap_or_default().to_string(),
+                                ),
+                            );
+                        }
+                    }
+                    "itemref" => spine.extend(event.attr("idref").map(str::to_string)),
+                    name if METADATA_ELEMENTS.contains(&name) && !self_closing => {
+                        in_metadata = Some((name.to_string(), String::new()))
+                    }
+                    _ => {}
+                },
+                XmlEvent::Text(text) => {
+                    if let Some((_, value)) = &mut in_metadata {
+                        value.push_str(text);
+                    }
+                }
+                XmlEvent::End { name } => {
+                    if let Some((element, value)) =
+                        in_metadata.take_if(|(e, _)| e == local_name(name))
+                    {
+                        // descriptions are often html
+                        let value = html_to_text(&value).lines().collect::<Vec<_>>().join(" ");
+                        if !value.is_empty() {
+                            metadata.push_str(&format!("{element}: {value}\n"));
+                        }
+                    }
+                }
+            }
+        }
+        if !metadata.is_empty() {
+            out.push((package_path.clone(), metadata));
+        }
+        for idref in spine {
+            match manifest.get(&idref) {
+                Some((path, media_type)) if media_type.contains("html") || is_html(path) => {
+                    chapters.push(path.clone())
+                }
+                Some((path, media_type)) => debug!("skipping {path} ({media_type}) in spine"),
+                None => debug!("spine item {idref} not in manifest"),
+            }
+        }
+    }
+    if chapters.is_empty() {
+        // broken package document, use all html files in the order of the zip file
+        chapters = files
+            .iter()
+            .map(|(n, _)| n.clone())
+            .filter(|n| is_html(n))
+            .collect();
+    }
+    for chapter in chapters {
+        match get(&chapter) {
+            Some(html) => {
+                let text = html_to_text(&html);
+                if !text.is_empty() {
+                    out.push((chapter, text));
+                }
+            }
+            None => warn!("chapter {chapter} missing from epub"),
+        }
+    }
+    out
+}
+
+#[async_trait]
+impl FileAdapter for EpubAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+        } = ai;
+        let files = zip::read_zip_files(inp, &filepath_hint, is_real_file).await?;
+        let parts: Vec<_> = book_text(&files)
+            .into_iter()
+            .map(move |(path, text)| {
+                Ok(AdaptInfo {
+                    // the text is already extracted, don't adapt it again
+                    filepath_hint: PathBuf::from(format!("{path}.txt")),
+                    is_real_file: false,
+                    inp: Box::pin(Cursor::new(text.into_bytes())),
+                    line_prefix: format!("{line_prefix}{path}: "),
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                })
+            })
+            .collect();
+        Ok(Box::pin(tokio_stream::iter(parts)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use async_zip::{Compression, ZipEntryBuilder, write::ZipFileWriter};
+    use pretty_assertions::assert_eq;
+
+    async fn create_epub() -> Result<Vec<u8>> {
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        let files = [
+            ("mimetype", "application/epub+zip"),
+            (
+                "META-INF/container.xml",
+                r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#,
+            ),
+            (
+                "OEBPS/Text/chapter 1.xhtml",
+                "<html><head><title>1</title></head><body><h1>Chapter 1</h1><p>It was a dark and stormy night.</p></body></html>",
+            ),
+            (
+                "OEBPS/cover.xhtml",
+                "<html><body><p>Cover page</p></body></html>",
+            ),
+            (
+                "OEBPS/content.opf",
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:title>A Book</dc:title>
+    <dc:creator>Jane Doe</dc:creator>
+    <dc:description>&lt;p&gt;A &lt;b&gt;short&lt;/b&gt; book&lt;/p&gt;</dc:description>
+  </metadata>
+  <manifest>
+    <item id="cover" href="cover.xhtml" media-type="application/xhtml+xml"/>
+    <item id="c1" href="Text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+    <item id="img" href="../cover.png" media-type="image/png"/>
+  </manifest>
+  <spine><itemref idref="cover"/><itemref idref="img"/><itemref idref="c1"/></spine>
+</package>"#,
+            ),
+        ];
+        for (name, content) in files {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Deflate);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    #[tokio::test]
+    async fn chapters() -> Result<()> {
+        let adapter = EpubAdapter::new();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("book.epub"),
+            Box::pin(Cursor::new(create_epub().await?)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&adapter, d, a).await?).await?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:OEBPS/content.opf: title: A Book
+PREFIX:OEBPS/content.opf: creator: Jane Doe
+PREFIX:OEBPS/content.opf: description: A short book
+PREFIX:OEBPS/cover.xhtml: Cover page
+PREFIX:OEBPS/Text/chapter 1.xhtml: Chapter 1
+PREFIX:OEBPS/Text/chapter 1.xhtml: It was a dark and stormy night.
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,619 @@
+use super::{
+    binary::{u16_at, u32_at, utf16},
+    writing::WritingFileAdapter,
+    *,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["etl"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "etl".to_owned(),
+        version: 1,
+        description: "Decodes Windows Event Trace Logs (.etl) into one line per event with the provider, event id, task, opcode and level.\nTraceLogging events are rendered with their event name and fields, other events show the strings found in their payload (rendering manifest-based messages requires the provider manifests, which are only available on the system that recorded the trace)."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EtlAdapter;
+
+impl EtlAdapter {
+    pub fn new() -> EtlAdapter {
+        EtlAdapter
+    }
+}
+impl GetMetadata for EtlAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// size of WMI_BUFFER_HEADER, the events of a buffer follow it
+const BUFFER_HEADER_LEN: usize = 0x48;
+/// buffers are usually 64 KiB, anything much larger means this is not an ETL file
+const MAX_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+/// minimum length of the strings shown for events that can't be rendered
+const MIN_STRING_LEN: usize = 4;
+
+// header types of the event records within buffers
+const TRACE_HEADER_TYPE_SYSTEM32: u8 = 1;
+const TRACE_HEADER_TYPE_SYSTEM64: u8 = 2;
+const TRACE_HEADER_TYPE_COMPACT32: u8 = 3;
+const TRACE_HEADER_TYPE_COMPACT64: u8 = 4;
+const TRACE_HEADER_TYPE_FULL_HEADER32: u8 = 10;
+const TRACE_HEADER_TYPE_PERFINFO32: u8 = 16;
+const TRACE_HEADER_TYPE_PERFINFO64: u8 = 17;
+const EVENT_HEADER_EVENT32: u8 = 18;
+const EVENT_HEADER_EVENT64: u8 = 19;
+const TRACE_HEADER_TYPE_FULL_HEADER64: u8 = 20;
+
+// EVENT_HEADER flags
+const EVENT_HEADER_FLAG_EXTENDED_INFO: u16 = 0x0001;
+const EVENT_HEADER_FLAG_STRING_ONLY: u16 = 0x0004;
+const EVENT_HEADER_FLAG_64_BIT_HEADER: u16 = 0x0020;
+const EVENT_HEADER_LEN: usize = 80;
+
+// extended data item types
+const EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL: u16 = 11;
+const EVENT_HEADER_EXT_TYPE_PROV_TRAITS: u16 = 12;
+
+/// Little endian reader over a byte slice. All reads return None at the end of the data.
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Cursor<'a> {
+        Cursor { data, pos: 0 }
+    }
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(b)
+    }
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.array::<1>()?[0])
+    }
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.array()?))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.array()?))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.array()?))
+    }
+    /// null-terminated 8-bit string
+    fn cstr(&mut self) -> Option<String> {
+        let rest = &self.data[self.pos..];
+        let len = memchr::memchr(0, rest)?;
+        self.pos += len + 1;
+        Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+    /// null-terminated UTF-16LE string
+    fn wstr(&mut self) -> Option<String> {
+        let mut units = Vec::new();
+        loop {
+            match self.u16()? {
+                0 => return Some(String::from_utf16_lossy(&units)),
+                u => units.push(u),
+            }
+        }
+    }
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos.min(self.data.len())..]
+    }
+}
+
+pub fn to_hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn format_guid(g: [u8; 16]) -> String {
+    format!(
+        "{{{:08x}-{:04x}-{:04x}-{}-{}}}",
+        u32::from_le_bytes(g[0..4].try_into().unwrap()),
+        u16::from_le_bytes(g[4..6].try_into().unwrap()),
+        u16::from_le_bytes(g[6..8].try_into().unwrap()),
+        to_hex(&g[8..10]),
+        to_hex(&g[10..16])
+    )
+}
+
+/// Printable ASCII and UTF-16LE strings in an event payload that could not be decoded
+fn payload_strings(data: &[u8]) -> Vec<String> {
+    fn printable(b: u8) -> bool {
+        b.is_ascii_graphic() || b == b' '
+    }
+    let mut strings = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let utf16_len = data[i..]
+            .chunks_exact(2)
+            .take_while(|c| printable(c[0]) && c[1] == 0)
+            .count();
+        if utf16_len >= MIN_STRING_LEN {
+            strings.push(
+                data[i..i + utf16_len * 2]
+                    .iter()
+                    .step_by(2)
+                    .map(|b| *b as char)
+                    .collect(),
+            );
+            i += utf16_len * 2;
+            continue;
+        }
+        let ascii_len = data[i..].iter().take_while(|b| printable(**b)).count();
+        if ascii_len >= MIN_STRING_LEN {
+            strings.push(String::from_utf8_lossy(&data[i..i + ascii_len]).into_owned());
+            i += ascii_len;
+            continue;
+        }
+        i += ascii_len.max(1);
+    }
+    strings
+}
+
+/// A field of a TraceLogging event, from the self-describing metadata of the event
+struct TlField {
+    name: String,
+    in_type: u8,
+    /// number of elements of constant-count arrays
+    ccount: Option<u16>,
+    /// whether the number of elements is stored in the payload
+    vcount: bool,
+}
+
+/// Parse the TraceLogging event metadata (EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL).
+///
+/// Returns the event name and the fields. Fields after one that can't be decoded (custom schemas) are dropped.
+fn parse_tl_metadata(data: &[u8]) -> Option<(String, Vec<TlField>)> {
+    let mut c = Cursor::new(data);
+    let size = c.u16()? as usize;
+    let mut c = Cursor::new(data.get(..size)?);
+    c.u16()?;
+    // event tags
+    while c.u8()? & 0x80 != 0 {}
+    let name = c.cstr()?;
+    let mut fields = Vec::new();
+    while !c.rest().is_empty() {
+        let name = c.cstr()?;
+        let in_type = c.u8()?;
+        if in_type & 0x80 != 0 {
+            // out type
+            if c.u8()? & 0x80 != 0 {
+                // field tags
+                while c.u8()? & 0x80 != 0 {}
+            }
+        }
+        let (ccount, vcount) = match in_type & 0x60 {
+            0x20 => (Some(c.u16()?), false),
+            0x40 => (None, true),
+            0x60 => break,
+            _ => (None, false),
+        };
+        fields.push(TlField {
+            name,
+            in_type: in_type & 0x1f,
+            ccount,
+            vcount,
+        });
+    }
+    Some((name, fields))
+}
+
+/// Decode one value of the given TraceLogging type from the payload
+fn tl_value(c: &mut Cursor, in_type: u8, pointer_size: usize) -> Option<String> {
+    Some(match in_type {
+        1 => c.wstr()?,
+        2 => c.cstr()?,
+        3 => (c.u8()? as i8).to_string(),
+        4 => c.u8()?.to_string(),
+        5 => (c.u16()? as i16).to_string(),
+        6 => c.u16()?.to_string(),
+        7 => (c.u32()? as i32).to_string(),
+        8 => c.u32()?.to_string(),
+        9 => (c.u64()? as i64).to_string(),
+        10 => c.u64()?.to_string(),
+        11 => f32::from_le_bytes(c.array()?).to_string(),
+        12 => f64::from_le_bytes(c.array()?).to_string(),
+        13 => (c.u32()? != 0).to_string(),
+        14 | 25 => {
+            let len = c.u16()? as usize;
+            to_hex(c.bytes(len)?)
+        }
+        15 => format_guid(c.array()?),
+        16 => match pointer_size {
+            8 => format!("0x{:x}", c.u64()?),
+            _ => format!("0x{:x}", c.u32()?),
+        },
+        17 => {
+            // FILETIME: 100ns intervals since 1601
+            let ft = c.u64()?;
+            crate::print_unix_time((ft / 10_000_000) as i64 - 11_644_473_600)
+        }
+        18 => {
+            let [y, mo, _dow, d, h, mi, s, _ms] = [(); 8].map(|_| c.u16());
+            format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                y?, mo?, d?, h?, mi?, s?
+            )
+        }
+        19 => {
+            let revision = c.u8()?;
+            let sub_authorities = c.u8()?;
+            let authority = c.array::<6>()?;
+            let authority = authority.iter().fold(0u64, |a, b| a << 8 | *b as u64);
+            let mut sid = format!("S-{revision}-{authority}");
+            for _ in 0..sub_authorities {
+                write!(sid, "-{}", c.u32()?).unwrap();
+            }
+            sid
+        }
+        20 => format!("0x{:x}", c.u32()?),
+        21 => format!("0x{:x}", c.u64()?),
+        22 => {
+            let len = c.u16()? as usize;
+            utf16(c.bytes(len)?)
+        }
+        23 => {
+            let len = c.u16()? as usize;
+            String::from_utf8_lossy(c.bytes(len)?).into_owned()
+        }
+        _ => return None,
+    })
+}
+
+/// Render a TraceLogging event as `Name field=value, field=value`
+fn render_tl_event(name: &str, fields: &[TlField], payload: &[u8], pointer_size: usize) -> String {
+    let mut c = Cursor::new(payload);
+    let mut values = Vec::new();
+    for field in fields {
+        // structs only group the fields that follow them
+        if field.in_type == 24 {
+            continue;
+        }
+        let count = match (field.ccount, field.vcount) {
+            (Some(n), _) => Some(n),
+            (None, true) => c.u16(),
+            (None, false) => None,
+        };
+        let value = match count {
+            Some(n) => (0..n)
+                .map(|_| tl_value(&mut c, field.in_type, pointer_size))
+                .collect::<Option<Vec<_>>>()
+                .map(|v| format!("[{}]", v.join(", "))),
+            None => tl_value(&mut c, field.in_type, pointer_size),
+        };
+        let Some(value) = value else {
+            break;
+        };
+        values.push(format!("{}={}", field.name, value));
+    }
+    if values.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} {}", name, values.join(", "))
+    }
+}
+
+/// Decodes the event records of ETL buffers into lines
+#[derive(Default)]
+struct EtlDecoder {
+    /// provider names from the provider traits of TraceLogging events, by provider GUID
+    provider_names: HashMap<[u8; 16], String>,
+}
+
+impl EtlDecoder {
+    /// Decode all events in a buffer (including its WMI_BUFFER_HEADER)
+    fn decode_buffer(&mut self, buf: &[u8], out: &mut String) {
+        let Some(filled) = u32_at(buf, 0x30).map(|o| o as usize) else {
+            return;
+        };
+        let end = if filled >= BUFFER_HEADER_LEN {
+            filled.min(buf.len())
+        } else {
+            // buffers that are still in use only have the saved offset
+            u32_at(buf, 0x04).map_or(0, |o| o as usize).min(buf.len())
+        };
+        let mut pos = BUFFER_HEADER_LEN;
+        while pos + 8 <= end {
+            let rec = &buf[pos..end];
+            // all typed headers have the TRACE_HEADER_FLAG marker
+            if rec[3] & 0x80 == 0 {
+                break;
+            }
+            let header_type = rec[2];
+            let size = match header_type {
+                TRACE_HEADER_TYPE_SYSTEM32
+                | TRACE_HEADER_TYPE_SYSTEM64
+                | TRACE_HEADER_TYPE_COMPACT32
+                | TRACE_HEADER_TYPE_COMPACT64
+                | TRACE_HEADER_TYPE_PERFINFO32
+                | TRACE_HEADER_TYPE_PERFINFO64 => u16_at(rec, 4),
+                _ => u16_at(rec, 0),
+            }
+            .unwrap_or(0) as usize;
+            if size < 8 || size > rec.len() {
+                break;
+            }
+            let rec = &rec[..size];
+            if let Some(line) = self.decode_record(header_type, rec) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+            // records are 8 byte aligned
+            pos += (size + 7) & !7;
+        }
+    }
+
+    fn decode_record(&mut self, header_type: u8, rec: &[u8]) -> Option<String> {
+        match header_type {
+            EVENT_HEADER_EVENT32 | EVENT_HEADER_EVENT64 => self.decode_event(rec),
+            TRACE_HEADER_TYPE_FULL_HEADER32 | TRACE_HEADER_TYPE_FULL_HEADER64 => {
+                // EVENT_TRACE_HEADER of classic (MOF / WPP) providers
+                let class_type = *rec.get(4)?;
+                let pid = u32_at(rec, 12)?;
+                let tid = u32_at(rec, 8)?;
+                let guid = format_guid(rec.get(24..40)?.try_into().ok()?);
+                let mut line = format!("{guid} type={class_type} pid={pid} tid={tid}");
+                let strings = payload_strings(rec.get(48..)?);
+                if !strings.is_empty() {
+                    write!(line, ": {}", strings.join(" ")).unwrap();
+                }
+                Some(line)
+            }
+            TRACE_HEADER_TYPE_SYSTEM32
+            | TRACE_HEADER_TYPE_SYSTEM64
+            | TRACE_HEADER_TYPE_COMPACT32
+            | TRACE_HEADER_TYPE_COMPACT64
+            | TRACE_HEADER_TYPE_PERFINFO32
+            | TRACE_HEADER_TYPE_PERFINFO64 => {
+                let hook_id = u16_at(rec, 6)?;
+                let header_len = match header_type {
+                    TRACE_HEADER_TYPE_SYSTEM32 | TRACE_HEADER_TYPE_SYSTEM64 => 32,
+                    TRACE_HEADER_TYPE_COMPACT32 | TRACE_HEADER_TYPE_COMPACT64 => 24,
+                    _ => 16,
+                };
+                // kernel events are very frequent, only the ones that mention something (file names, processes) are useful to search
+                let strings = payload_strings(rec.get(header_len..)?);
+                if strings.is_empty() {
+                    return None;
+                }
+                Some(format!(
+                    "kernel hook=0x{hook_id:04x}: {}",
+                    strings.join(" ")
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decode an event with an EVENT_HEADER (manifest-based and TraceLogging providers)
+    fn decode_event(&mut self, rec: &[u8]) -> Option<String> {
+        let mut c = Cursor::new(rec);
+        c.bytes(4)?;
+        let flags = c.u16()?;
+        c.u16()?;
+        let tid = c.u32()?;
+        let pid = c.u32()?;
+        c.u64()?;
+        let provider: [u8; 16] = c.array()?;
+        let id = c.u16()?;
+        let version = c.u8()?;
+        c.u8()?;
+        let level = c.u8()?;
+        let opcode = c.u8()?;
+        let task = c.u16()?;
+        let mut c = Cursor::new(rec);
+        c.bytes(EVENT_HEADER_LEN)?;
+
+        let mut tl_schema = None;
+        if flags & EVENT_HEADER_FLAG_EXTENDED_INFO != 0 {
+            loop {
+                c.u16()?;
+                let ext_type = c.u16()?;
+                let linkage = c.u16()?;
+                let data_size = c.u16()? as usize;
+                let data = c.bytes(data_size)?;
+                c.bytes((8 - data_size % 8) % 8)?;
+                match ext_type {
+                    EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL => tl_schema = parse_tl_metadata(data),
+                    EVENT_HEADER_EXT_TYPE_PROV_TRAITS => {
+                        if let Some(name) = Cursor::new(data.get(2..)?).cstr() {
+                            self.provider_names.insert(provider, name);
+                        }
+                    }
+                    _ => {}
+                }
+                if linkage & 1 == 0 {
+                    break;
+                }
+            }
+        }
+        let payload = c.rest();
+        let provider = self
+            .provider_names
+            .get(&provider)
+            .cloned()
+            .unwrap_or_else(|| format_guid(provider));
+        let mut line = format!(
+            "{provider} id={id} v{version} task={task} opcode={opcode} level={level} pid={pid} tid={tid}"
+        );
+        let pointer_size = if flags & EVENT_HEADER_FLAG_64_BIT_HEADER != 0 {
+            8
+        } else {
+            4
+        };
+        let message = if flags & EVENT_HEADER_FLAG_STRING_ONLY != 0 {
+            Cursor::new(payload).wstr()
+        } else if let Some((name, fields)) = &tl_schema {
+            Some(render_tl_event(name, fields, payload, pointer_size))
+        } else {
+            Some(payload_strings(payload).join(" ")).filter(|s| !s.is_empty())
+        };
+        if let Some(message) = message {
+            write!(line, ": {message}").unwrap();
+        }
+        Some(line)
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for EtlAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut decoder = EtlDecoder::default();
+        let mut buf = vec![0u8; BUFFER_HEADER_LEN];
+        loop {
+            buf.resize(BUFFER_HEADER_LEN, 0);
+            match inp.read_exact(&mut buf).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let buffer_size = u32_at(&buf, 0).unwrap_or(0) as usize;
+            if !(BUFFER_HEADER_LEN..=MAX_BUFFER_SIZE).contains(&buffer_size) {
+                anyhow::bail!("invalid ETL buffer size {buffer_size}");
+            }
+            buf.resize(buffer_size, 0);
+            // the last buffer may be truncated
+            let mut filled = BUFFER_HEADER_LEN;
+            while filled < buffer_size {
+                let n = inp.read(&mut buf[filled..]).await?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            buf.truncate(filled);
+            let mut text = String::new();
+            decoder.decode_buffer(&buf, &mut text);
+            for line in text.lines() {
+                oup.write_all(format!("{line_prefix}{line}\n").as_bytes())
+                    .await?;
+            }
+            if filled < buffer_size {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    /// An EVENT_HEADER event with the given extended data items and payload
+    fn event(flags: u16, ext: &[(u16, &[u8])], payload: &[u8]) -> Vec<u8> {
+        let mut rec = vec![0u8; EVENT_HEADER_LEN];
+        rec[2] = EVENT_HEADER_EVENT64;
+        rec[3] = 0xc0;
+        rec[4..6].copy_from_slice(&flags.to_le_bytes());
+        rec[8..12].copy_from_slice(&12u32.to_le_bytes());
+        rec[12..16].copy_from_slice(&34u32.to_le_bytes());
+        rec[24..40].copy_from_slice(&[0x11; 16]);
+        rec[40..42].copy_from_slice(&7u16.to_le_bytes());
+        rec[44] = 4;
+        rec[46..48].copy_from_slice(&3u16.to_le_bytes());
+        for (i, (ext_type, data)) in ext.iter().enumerate() {
+            let linkage = u16::from(i + 1 < ext.len());
+            for v in [0, *ext_type, linkage, data.len() as u16] {
+                rec.extend_from_slice(&v.to_le_bytes());
+            }
+            rec.extend_from_slice(data);
+            rec.resize((rec.len() + 7) & !7, 0);
+        }
+        rec.extend_from_slice(payload);
+        let size = rec.len() as u16;
+        rec[0..2].copy_from_slice(&size.to_le_bytes());
+        rec.resize((rec.len() + 7) & !7, 0);
+        rec
+    }
+
+    fn buffer(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = vec![0u8; BUFFER_HEADER_LEN];
+        for rec in records {
+            buf.extend_from_slice(rec);
+        }
+        let filled = buf.len() as u32;
+        buf.resize(4096, 0xff);
+        buf[0..4].copy_from_slice(&4096u32.to_le_bytes());
+        buf[0x30..0x34].copy_from_slice(&filled.to_le_bytes());
+        buf
+    }
+
+    #[tokio::test]
+    async fn events() -> Result<()> {
+        let mut traits = 14u16.to_le_bytes().to_vec();
+        traits.extend_from_slice(b"MyProvider\0\0\0\0");
+        let mut schema = vec![0, 0, 0];
+        schema.extend_from_slice(b"FileOpened\0path\0\x01count\0\x08");
+        let schema_len = schema.len() as u16;
+        schema[0..2].copy_from_slice(&schema_len.to_le_bytes());
+        let mut payload: Vec<u8> = "C:\\a.txt\0"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        payload.extend_from_slice(&5u32.to_le_bytes());
+        let manifest_payload: Vec<u8> = "\x01\x02disk full\0"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        let etl = buffer(&[
+            event(
+                EVENT_HEADER_FLAG_EXTENDED_INFO | EVENT_HEADER_FLAG_64_BIT_HEADER,
+                &[
+                    (EVENT_HEADER_EXT_TYPE_PROV_TRAITS, &traits),
+                    (EVENT_HEADER_EXT_TYPE_EVENT_SCHEMA_TL, &schema),
+                ],
+                &payload,
+            ),
+            event(0, &[], &manifest_payload),
+        ]);
+
+        let adapter: Box<dyn FileAdapter> = Box::<EtlAdapter>::default();
+        let (a, d) = simple_adapt_info(
+            std::path::Path::new("trace.etl"),
+            Box::pin(std::io::Cursor::new(etl)),
+        );
+        let res = loop_adapt(adapter.as_ref(), d, a).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:MyProvider id=7 v0 task=3 opcode=0 level=4 pid=34 tid=12: FileOpened path=C:\\a.txt, count=5\n\
+             PREFIX:MyProvider id=7 v0 task=3 opcode=0 level=4 pid=34 tid=12: disk full\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,744 @@
+use super::binary::{u16_at, u32_at, u64_at, utf16};
+use super::etl::{format_guid, to_hex};
+use super::writing::WritingFileAdapter;
+use super::*;
+use crate::print_unix_time;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::fmt::Write as _;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["evtx"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "evtx".to_owned(),
+        version: 1,
+        description: "Decodes Windows event logs (.evtx) into one line per event with the time, the event id and the values of the event XML as `name=value` pairs (`Provider.Name=Microsoft-Windows-Security-Auditing ... TargetUserName=alice`)"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EvtxAdapter;
+
+impl EvtxAdapter {
+    pub fn new() -> EvtxAdapter {
+        EvtxAdapter
+    }
+}
+
+impl GetMetadata for EvtxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const FILE_MAGIC: &[u8] = b"ElfFile\0";
+const FILE_HEADER_LEN: usize = 4096;
+const CHUNK_MAGIC: &[u8] = b"ElfChnk\0";
+const CHUNK_LEN: usize = 65536;
+const CHUNK_HEADER_LEN: usize = 512;
+const RECORD_MAGIC: &[u8] = b"\x2a\x2a\0\0";
+/// record header: magic, size, record id, written time
+const RECORD_HEADER_LEN: usize = 24;
+/// nesting of elements, templates and BinXml values, deeper means the record is broken (or cyclic)
+const MAX_DEPTH: usize = 64;
+
+// BinXml tokens, the 0x40 bit marks that more data (e.g. attributes) follows
+const TOKEN_EOF: u8 = 0x00;
+const TOKEN_OPEN_START_ELEMENT: u8 = 0x01;
+const TOKEN_CLOSE_START_ELEMENT: u8 = 0x02;
+const TOKEN_CLOSE_EMPTY_ELEMENT: u8 = 0x03;
+const TOKEN_END_ELEMENT: u8 = 0x04;
+const TOKEN_VALUE: u8 = 0x05;
+const TOKEN_ATTRIBUTE: u8 = 0x06;
+const TOKEN_CDATA: u8 = 0x07;
+const TOKEN_CHAR_REF: u8 = 0x08;
+const TOKEN_ENTITY_REF: u8 = 0x09;
+const TOKEN_PI_TARGET: u8 = 0x0a;
+const TOKEN_PI_DATA: u8 = 0x0b;
+const TOKEN_TEMPLATE_INSTANCE: u8 = 0x0c;
+const TOKEN_NORMAL_SUBSTITUTION: u8 = 0x0d;
+const TOKEN_OPTIONAL_SUBSTITUTION: u8 = 0x0e;
+const TOKEN_FRAGMENT_HEADER: u8 = 0x0f;
+
+const TYPE_NULL: u8 = 0x00;
+const TYPE_STRING: u8 = 0x01;
+const TYPE_ANSI_STRING: u8 = 0x02;
+const TYPE_BINXML: u8 = 0x21;
+const TYPE_ARRAY: u8 = 0x80;
+
+fn filetime(ft: u64) -> String {
+    // 100ns intervals since 1601
+    print_unix_time((ft / 10_000_000) as i64 - 11_644_473_600)
+}
+
+/// The size of the elements of the value types with a fixed size
+fn fixed_size(value_type: u8) -> Option<usize> {
+    Some(match value_type {
+        0x03 | 0x04 => 1,
+        0x05 | 0x06 => 2,
+        0x07 | 0x08 | 0x0b | 0x0d | 0x14 => 4,
+        0x09 | 0x0a | 0x0c | 0x11 | 0x15 => 8,
+        0x0f | 0x12 => 16,
+        _ => return None,
+    })
+}
+
+/// Render a substitution value of the given type
+fn render_value(value_type: u8, b: &[u8]) -> String {
+    if value_type & TYPE_ARRAY != 0 {
+        let element_type = value_type & !TYPE_ARRAY;
+        let elements: Vec<String> = match (element_type, fixed_size(element_type)) {
+            (TYPE_STRING, _) => utf16(b)
+                .split('\0')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+            (_, Some(size)) => b
+                .chunks_exact(size)
+                .map(|e| render_value(element_type, e))
+                .collect(),
+            _ => vec![to_hex(b)],
+        };
+        return elements.join(", ");
+    }
+    let u = |n: usize| -> u64 {
+        let mut bytes = [0u8; 8];
+        let n = n.min(b.len()).min(8);
+        bytes[..n].copy_from_slice(&b[..n]);
+        u64::from_le_bytes(bytes)
+    };
+    match value_type {
+        TYPE_NULL => String::new(),
+        TYPE_STRING => utf16(b).trim_end_matches('\0').to_string(),
+        TYPE_ANSI_STRING => String::from_utf8_lossy(b)
+            .trim_end_matches('\0')
+            .to_string(),
+        0x03 => (u(1) as i8).to_string(),
+        0x04 => u(1).to_string(),
+        0x05 => (u(2) as i16).to_string(),
+        0x06 => u(2).to_string(),
+        0x07 => (u(4) as i32).to_string(),
+        0x08 => u(4).to_string(),
+        0x09 => (u(8) as i64).to_string(),
+        0x0a => u(8).to_string(),
+        0x0b => f32::from_bits(u(4) as u32).to_string(),
+        0x0c => f64::from_bits(u(8)).to_string(),
+        0x0d => (u(4) != 0).to_string(),
+        0x0f if b.len() == 16 => format_guid(b.try_into().unwrap()),
+        // size_t, with the size of the pointers of the machine
+        0x10 | 0x14 | 0x15 if b.len() <= 8 => format!("0x{:x}", u(b.len())),
+        0x11 => filetime(u(8)),
+        0x12 if b.len() == 16 => {
+            let [y, mo, _dow, d, h, mi, s, _ms] =
+                [0, 1, 2, 3, 4, 5, 6, 7].map(|i| u16_at(b, i * 2).unwrap());
+            format!("{y:04}-{mo:02}-{d:02} {h:02}:{mi:02}:{s:02}")
+        }
+        0x13 if b.len() >= 8 => {
+            let authority = b[2..8].iter().fold(0u64, |a, b| a << 8 | *b as u64);
+            let mut sid = format!("S-{}-{authority}", b[0]);
+            for sub_authority in b[8..].chunks_exact(4).take(b[1] as usize) {
+                write!(
+                    sid,
+                    "-{}",
+                    u32::from_le_bytes(sub_authority.try_into().unwrap())
+                )
+                .unwrap();
+            }
+            sid
+        }
+        _ => to_hex(b),
+    }
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+/// A substitution value of a template instance, by its position in the chunk
+#[derive(Clone, Copy)]
+struct Value {
+    value_type: u8,
+    offset: usize,
+    len: usize,
+}
+
+/// Parses BinXml within a chunk. Names and template definitions are referenced by their offset in the chunk
+struct BinXml<'a> {
+    chunk: &'a [u8],
+    pos: usize,
+    /// elements of BinXml substitution values have no dependency identifier
+    in_substitution: bool,
+    depth: usize,
+}
+
+impl<'a> BinXml<'a> {
+    fn new(chunk: &'a [u8], pos: usize, in_substitution: bool, depth: usize) -> Result<BinXml<'a>> {
+        if depth > MAX_DEPTH {
+            anyhow::bail!("BinXml nested too deeply");
+        }
+        Ok(BinXml {
+            chunk,
+            pos,
+            in_substitution,
+            depth,
+        })
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.chunk
+            .get(self.pos)
+            .copied()
+            .context("truncated BinXml")
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let v = u16_at(self.chunk, self.pos).context("truncated BinXml")?;
+        self.pos += 2;
+        Ok(v)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let v = u32_at(self.chunk, self.pos).context("truncated BinXml")?;
+        self.pos += 4;
+        Ok(v)
+    }
+
+    /// A string with a 16-bit character count
+    fn string(&mut self) -> Result<String> {
+        let len = self.u16()? as usize * 2;
+        let b = self
+            .chunk
+            .get(self.pos..self.pos + len)
+            .context("truncated BinXml")?;
+        self.pos += len;
+        Ok(utf16(b))
+    }
+
+    /// A name, which is stored inline the first time it is used in a chunk
+    fn name(&mut self) -> Result<String> {
+        let offset = self.u32()? as usize;
+        // next name offset, hash
+        let mut name = BinXml::new(self.chunk, offset + 6, false, self.depth)?;
+        let s = name.string()?;
+        if offset == self.pos {
+            // and the terminating null character
+            self.pos = name.pos + 2;
+        }
+        Ok(s)
+    }
+
+    /// Parses the nodes up to the end of the current element or fragment
+    fn nodes(&mut self, subs: &[Value]) -> Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        loop {
+            let token = self.u8()?;
+            match token & 0x0f {
+                TOKEN_EOF | TOKEN_END_ELEMENT => return Ok(nodes),
+                TOKEN_OPEN_START_ELEMENT => nodes.push(Node::Element(self.element(token, subs)?)),
+                TOKEN_VALUE => nodes.push(Node::Text(self.value()?)),
+                TOKEN_CDATA => nodes.push(Node::Text(self.string()?)),
+                TOKEN_CHAR_REF => {
+                    let c = char::from_u32(self.u16()? as u32).unwrap_or_default();
+                    nodes.push(Node::Text(c.to_string()));
+                }
+                TOKEN_ENTITY_REF => nodes.push(Node::Text(self.entity()?)),
+                TOKEN_PI_TARGET => {
+                    self.name()?;
+                }
+                TOKEN_PI_DATA => {
+                    self.string()?;
+                }
+                TOKEN_TEMPLATE_INSTANCE => nodes.extend(self.template()?),
+                TOKEN_NORMAL_SUBSTITUTION | TOKEN_OPTIONAL_SUBSTITUTION => {
+                    let value = self.substitution(subs)?;
+                    if let Some(value) = value.filter(|v| v.value_type == TYPE_BINXML) {
+                        let mut inner =
+                            BinXml::new(self.chunk, value.offset, true, self.depth + 1)?;
+                        nodes.extend(inner.nodes(&[])?);
+                    } else if let Some(value) = value {
+                        nodes.push(Node::Text(self.render(value)));
+                    }
+                }
+                TOKEN_FRAGMENT_HEADER => {
+                    // major and minor version, flags
+                    self.pos += 3;
+                }
+                _ => anyhow::bail!("unknown BinXml token 0x{token:02x} at 0x{:x}", self.pos - 1),
+            }
+        }
+    }
+
+    fn element(&mut self, token: u8, subs: &[Value]) -> Result<Element> {
+        if !self.in_substitution {
+            // dependency identifier
+            self.pos += 2;
+        }
+        // data size
+        self.pos += 4;
+        let name = self.name()?;
+        let mut attributes = Vec::new();
+        if token & 0x40 != 0 {
+            // attribute list size
+            self.pos += 4;
+            while self.peek()? & 0x0f == TOKEN_ATTRIBUTE {
+                self.pos += 1;
+                let name = self.name()?;
+                let value = self.attribute_value(subs)?;
+                attributes.push((name, value));
+            }
+        }
+        let children = match self.u8()? {
+            TOKEN_CLOSE_START_ELEMENT => {
+                let mut inner =
+                    BinXml::new(self.chunk, self.pos, self.in_substitution, self.depth + 1)?;
+                let children = inner.nodes(subs)?;
+                self.pos = inner.pos;
+                children
+            }
+            TOKEN_CLOSE_EMPTY_ELEMENT => Vec::new(),
+            token => anyhow::bail!("unexpected BinXml token 0x{token:02x} in element {name}"),
+        };
+        Ok(Element {
+            name,
+            attributes,
+            children,
+        })
+    }
+
+    fn attribute_value(&mut self, subs: &[Value]) -> Result<String> {
+        let mut value = String::new();
+        loop {
+            match self.peek()? & 0x0f {
+                TOKEN_VALUE => {
+                    self.pos += 1;
+                    value.push_str(&self.value()?);
+                }
+                TOKEN_NORMAL_SUBSTITUTION | TOKEN_OPTIONAL_SUBSTITUTION => {
+                    self.pos += 1;
+                    if let Some(v) = self.substitution(subs)? {
+                        value.push_str(&self.render(v));
+                    }
+                }
+                TOKEN_CHAR_REF => {
+                    self.pos += 1;
+                    value.extend(char::from_u32(self.u16()? as u32));
+                }
+                TOKEN_ENTITY_REF => {
+                    self.pos += 1;
+                    value.push_str(&self.entity()?);
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// The text of a value token
+    fn value(&mut self) -> Result<String> {
+        match self.u8()? {
+            TYPE_STRING => self.string(),
+            value_type => anyhow::bail!("unsupported BinXml value type 0x{value_type:02x}"),
+        }
+    }
+
+    fn entity(&mut self) -> Result<String> {
+        let name = self.name()?;
+        Ok(match name.as_str() {
+            "amp" => "&",
+            "lt" => "<",
+            "gt" => ">",
+            "quot" => "\"",
+            "apos" => "'",
+            _ => return Ok(format!("&{name};")),
+        }
+        .to_string())
+    }
+
+    /// The value of a substitution token, None for missing and null values
+    fn substitution(&mut self, subs: &[Value]) -> Result<Option<Value>> {
+        let id = self.u16()? as usize;
+        // the type of the value is also stored in the template instance
+        self.u8()?;
+        Ok(subs
+            .get(id)
+            .copied()
+            .filter(|v| v.value_type != TYPE_NULL && v.len > 0))
+    }
+
+    fn render(&self, value: Value) -> String {
+        render_value(
+            value.value_type,
+            &self.chunk[value.offset..value.offset + value.len],
+        )
+    }
+
+    /// Renders a template definition with the values of the instance
+    fn template(&mut self) -> Result<Vec<Node>> {
+        // unknown, template id
+        self.pos += 5;
+        let definition = self.u32()? as usize;
+        // the definition is stored inline the first time it is used in a chunk
+        if definition == self.pos {
+            let size = u32_at(self.chunk, definition + 20).context("truncated template")? as usize;
+            self.pos = definition + 24 + size;
+        }
+        let count = self.u32()? as usize;
+        if count > self.chunk.len() / 4 {
+            anyhow::bail!("invalid substitution count {count}");
+        }
+        let descriptors = (0..count)
+            .map(|_| {
+                let len = self.u16()? as usize;
+                let value_type = self.u8()?;
+                self.pos += 1;
+                Ok((len, value_type))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut subs = Vec::with_capacity(count);
+        for (len, value_type) in descriptors {
+            if self.pos + len > self.chunk.len() {
+                anyhow::bail!("truncated substitution value");
+            }
+            subs.push(Value {
+                value_type,
+                offset: self.pos,
+                len,
+            });
+            self.pos += len;
+        }
+        // next definition offset, guid, data size
+        let mut definition = BinXml::new(self.chunk, definition + 24, false, self.depth + 1)?;
+        definition.nodes(&subs)
+    }
+}
+
+/// Appends the attributes and texts of the element and its descendants as `name=value` pairs.
+///
+/// `<Data Name="TargetUserName">` elements are named by their `Name` attribute.
+fn flatten(element: &Element, pairs: &mut Vec<(String, String)>) {
+    let data_name = (element.name == "Data")
+        .then(|| element.attributes.iter().find(|(a, _)| a == "Name"))
+        .flatten();
+    let name = data_name.map_or(&element.name, |(_, v)| v);
+    for (attribute, value) in &element.attributes {
+        if attribute == "xmlns" || data_name.is_some() || value.is_empty() {
+            continue;
+        }
+        pairs.push((format!("{name}.{attribute}"), value.clone()));
+    }
+    let mut text = String::new();
+    for child in &element.children {
+        match child {
+            Node::Element(e) => flatten(e, pairs),
+            Node::Text(t) => text.push_str(t),
+        }
+    }
+    // one event per line
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !text.is_empty() {
+        pairs.push((name.clone(), text));
+    }
+}
+
+/// Renders the events of a chunk, one per line
+fn render_chunk(chunk: &[u8], line_prefix: &str, out: &mut String) {
+    let free_space = u32_at(chunk, 0x30).map_or(chunk.len(), |f| (f as usize).min(chunk.len()));
+    let mut pos = CHUNK_HEADER_LEN;
+    while pos + RECORD_HEADER_LEN <= free_space && &chunk[pos..pos + 4] == RECORD_MAGIC {
+        let size = u32_at(chunk, pos + 4).unwrap() as usize;
+        let record_id = u64_at(chunk, pos + 8).unwrap();
+        let time = filetime(u64_at(chunk, pos + 16).unwrap());
+        if size < RECORD_HEADER_LEN || pos + size > chunk.len() {
+            log::debug!("invalid size of event record {record_id}");
+            break;
+        }
+        let nodes =
+            BinXml::new(chunk, pos + RECORD_HEADER_LEN, false, 0).and_then(|mut b| b.nodes(&[]));
+        match nodes {
+            Ok(nodes) => {
+                let mut pairs = Vec::new();
+                for node in &nodes {
+                    if let Node::Element(e) = node {
+                        flatten(e, &mut pairs);
+                    }
+                }
+                let event_id = pairs.iter().position(|(k, _)| k == "EventID");
+                match event_id.map(|i| pairs.remove(i)) {
+                    Some((_, id)) => write!(out, "{line_prefix}{time} EventID {id}:").unwrap(),
+                    None => write!(out, "{line_prefix}{time}:").unwrap(),
+                }
+                for (key, value) in pairs {
+                    write!(out, " {key}={value}").unwrap();
+                }
+                out.push('\n');
+            }
+            Err(e) => writeln!(out, "{line_prefix}{time} record {record_id}: [{e}]").unwrap(),
+        }
+        pos += size;
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for EvtxAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut header = vec![0u8; FILE_HEADER_LEN];
+        inp.read_exact(&mut header).await?;
+        if !header.starts_with(FILE_MAGIC) {
+            anyhow::bail!("not an EVTX file");
+        }
+        let mut chunk = vec![0u8; CHUNK_LEN];
+        loop {
+            match inp.read_exact(&mut chunk).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            // unused chunks at the end of the file are zeroed
+            if !chunk.starts_with(CHUNK_MAGIC) {
+                continue;
+            }
+            let mut out = String::new();
+            render_chunk(&chunk, &line_prefix, &mut out);
+            oup.write_all(out.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    /// Writes BinXml into a chunk, with all names inline
+    struct Writer {
+        chunk: Vec<u8>,
+        in_substitution: bool,
+    }
+
+    impl Writer {
+        fn name(&mut self, name: &str) {
+            let offset = self.chunk.len() as u32 + 4;
+            self.chunk.extend(offset.to_le_bytes());
+            self.chunk.extend([0; 6]);
+            self.string(name);
+            self.chunk.extend([0, 0]);
+        }
+        fn string(&mut self, s: &str) {
+            let units: Vec<u16> = s.encode_utf16().collect();
+            self.chunk.extend((units.len() as u16).to_le_bytes());
+            self.chunk
+                .extend(units.iter().flat_map(|u| u.to_le_bytes()));
+        }
+        fn open(&mut self, name: &str, attributes: bool) {
+            self.chunk
+                .push(TOKEN_OPEN_START_ELEMENT | if attributes { 0x40 } else { 0 });
+            if !self.in_substitution {
+                self.chunk.extend([0xff, 0xff]);
+            }
+            self.chunk.extend([0; 4]);
+            self.name(name);
+            if attributes {
+                self.chunk.extend([0; 4]);
+            }
+        }
+        fn attribute(&mut self, name: &str) {
+            self.chunk.push(TOKEN_ATTRIBUTE);
+            self.name(name);
+        }
+        fn text(&mut self, s: &str) {
+            self.chunk.extend([TOKEN_VALUE, TYPE_STRING]);
+            self.string(s);
+        }
+        fn substitution(&mut self, id: u16, value_type: u8) {
+            self.chunk.push(TOKEN_OPTIONAL_SUBSTITUTION);
+            self.chunk.extend(id.to_le_bytes());
+            self.chunk.push(value_type);
+        }
+        /// A template instance, with the definition inline if it is not given
+        fn template(&mut self, definition: Option<u32>, values: &[(u8, Vec<u8>)]) -> u32 {
+            self.chunk.extend([TOKEN_TEMPLATE_INSTANCE, 1, 0, 0, 0, 0]);
+            let offset = definition.unwrap_or(self.chunk.len() as u32 + 4);
+            self.chunk.extend(offset.to_le_bytes());
+            if definition.is_none() {
+                let start = self.chunk.len();
+                self.chunk.extend([0; 24]);
+                self.chunk.extend([TOKEN_FRAGMENT_HEADER, 1, 1, 0]);
+                self.open("Event", true);
+                self.attribute("xmlns");
+                self.text("http://schemas.microsoft.com/win/2004/08/events/event");
+                self.chunk.push(TOKEN_CLOSE_START_ELEMENT);
+                self.open("System", false);
+                self.chunk.push(TOKEN_CLOSE_START_ELEMENT);
+                self.open("Provider", true);
+                self.attribute("Name");
+                self.substitution(0, TYPE_STRING);
+                self.chunk.push(TOKEN_CLOSE_EMPTY_ELEMENT);
+                self.open("EventID", false);
+                self.chunk.push(TOKEN_CLOSE_START_ELEMENT);
+                self.substitution(1, 0x06);
+                self.chunk.push(TOKEN_END_ELEMENT);
+                self.open("Computer", false);
+                self.chunk.push(TOKEN_CLOSE_START_ELEMENT);
+                self.substitution(2, TYPE_STRING);
+                self.chunk.push(TOKEN_END_ELEMENT);
+                self.chunk.push(TOKEN_END_ELEMENT);
+                self.substitution(3, TYPE_BINXML);
+                self.chunk.push(TOKEN_END_ELEMENT);
+                self.chunk.push(TOKEN_EOF);
+                let size = (self.chunk.len() - start - 24) as u32;
+                self.chunk[start + 20..start + 24].copy_from_slice(&size.to_le_bytes());
+            }
+            self.chunk.extend((values.len() as u32).to_le_bytes());
+            for (value_type, value) in values {
+                self.chunk.extend((value.len() as u16).to_le_bytes());
+                self.chunk.extend([*value_type, 0]);
+            }
+            for (_, value) in values {
+                self.chunk.extend(value);
+            }
+            offset
+        }
+        /// An event record containing the BinXml written by `f`
+        fn record(&mut self, id: u64, f: impl FnOnce(&mut Writer)) {
+            let start = self.chunk.len();
+            self.chunk.extend(RECORD_MAGIC);
+            self.chunk.extend([0; 4]);
+            self.chunk.extend(id.to_le_bytes());
+            // 2023-11-14 14:06:40 UTC
+            self.chunk.extend(133_444_444_000_000_000u64.to_le_bytes());
+            self.chunk.extend([TOKEN_FRAGMENT_HEADER, 1, 1, 0]);
+            f(self);
+            self.chunk.push(TOKEN_EOF);
+            self.chunk.extend([0; 4]);
+            let size = (self.chunk.len() - start) as u32;
+            self.chunk[start + 4..start + 8].copy_from_slice(&size.to_le_bytes());
+            let len = self.chunk.len();
+            self.chunk[len - 4..].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+
+    fn utf16_value(s: &str) -> (u8, Vec<u8>) {
+        (
+            TYPE_STRING,
+            s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn evtx() -> Result<()> {
+        let mut w = Writer {
+            chunk: CHUNK_MAGIC.to_vec(),
+            in_substitution: false,
+        };
+        w.chunk.resize(CHUNK_HEADER_LEN, 0);
+        let mut definition = 0;
+        w.record(1, |w| {
+            let values = |event_data: Vec<u8>| {
+                vec![
+                    utf16_value("Microsoft-Windows-Security-Auditing"),
+                    (0x06, 4624u16.to_le_bytes().to_vec()),
+                    utf16_value("DC01"),
+                    (TYPE_BINXML, event_data),
+                ]
+            };
+            // the names in the EventData value reference its position in the chunk, which is where the instance ends
+            let mut probe = Writer {
+                chunk: w.chunk.clone(),
+                in_substitution: false,
+            };
+            probe.template(None, &values(vec![]));
+            let start = probe.chunk.len();
+            let mut data = Writer {
+                chunk: vec![0; start],
+                in_substitution: true,
+            };
+            data.chunk.extend([TOKEN_FRAGMENT_HEADER, 1, 1, 0]);
+            data.open("EventData", false);
+            data.chunk.push(TOKEN_CLOSE_START_ELEMENT);
+            for (name, value) in [("TargetUserName", "alice"), ("LogonType", "3")] {
+                data.open("Data", true);
+                data.attribute("Name");
+                data.text(name);
+                data.chunk.push(TOKEN_CLOSE_START_ELEMENT);
+                data.text(value);
+                data.chunk.push(TOKEN_END_ELEMENT);
+            }
+            data.chunk.push(TOKEN_END_ELEMENT);
+            data.chunk.push(TOKEN_EOF);
+            definition = w.template(None, &values(data.chunk.split_off(start)));
+        });
+        w.record(2, |w| {
+            w.template(
+                Some(definition),
+                &[
+                    utf16_value("Service Control Manager"),
+                    (0x06, 7036u16.to_le_bytes().to_vec()),
+                    (TYPE_NULL, vec![]),
+                    (TYPE_NULL, vec![]),
+                ],
+            );
+        });
+        let free_space = w.chunk.len() as u32;
+        w.chunk[0x30..0x34].copy_from_slice(&free_space.to_le_bytes());
+        w.chunk.resize(CHUNK_LEN, 0);
+
+        let mut file = FILE_MAGIC.to_vec();
+        file.resize(FILE_HEADER_LEN, 0);
+        file.extend(w.chunk);
+        // an unused chunk
+        file.extend(vec![0; CHUNK_LEN]);
+        let (a, d) =
+            simple_adapt_info(&PathBuf::from("Security.evtx"), Box::pin(Cursor::new(file)));
+        let buf = adapted_to_vec(loop_adapt(&EvtxAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:2023-11-14 14:06:40 UTC EventID 4624: Provider.Name=Microsoft-Windows-Security-Auditing Computer=DC01 TargetUserName=alice LogonType=3
+PREFIX:2023-11-14 14:06:40 UTC EventID 7036: Provider.Name=Service Control Manager
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn render_long_hex_value() {
+        let value: Vec<u8> = (0..16).collect();
+        assert_eq!(render_value(0x15, &value), to_hex(&value));
+        assert_eq!(render_value(0x15, &0x1234u64.to_le_bytes()), "0x1234");
+    }
+}
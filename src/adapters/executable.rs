@@ -0,0 +1,708 @@
+use super::{
+    binary::{Endian, bytes_at},
+    strings::write_strings,
+    writing::WritingFileAdapter,
+    *,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["so", "dll", "dylib", "o", "obj", "ko", "elf", "sys", "ocx"];
+static MIME_TYPES: &[&str] = &[
+    "application/x-sharedlib",
+    "application/x-object",
+    "application/x-mach-binary",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "executable".to_owned(),
+        version: 1,
+        description: "Reads ELF, PE and Mach-O executables, libraries and object files and outputs the format, section names, linked libraries, imported / exported symbols and the embedded printable strings with their offset.\nDisabled by default since the output can be large, enable it with `--rga-adapters=+executable`. The minimum string length is set with `--rga-strings-min-length`.\n`.exe` files are handled by the sfx adapter first, to search the ones without an embedded archive use `--rga-binary=adapter --rga-binary-adapter=executable`."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIME_TYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ExecutableAdapter;
+
+impl ExecutableAdapter {
+    pub fn new() -> ExecutableAdapter {
+        ExecutableAdapter
+    }
+}
+impl GetMetadata for ExecutableAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// names longer than this are cut off
+const MAX_NAME_LEN: usize = 4096;
+
+/// Reads integers of either byte order from a binary
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&self, pos: usize) -> Option<[u8; N]> {
+        bytes_at(self.data, pos)
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        self.endian.u16_at(self.data, pos)
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        self.endian.u32_at(self.data, pos)
+    }
+
+    fn u64(&self, pos: usize) -> Option<u64> {
+        self.endian.u64_at(self.data, pos)
+    }
+
+    /// A 32 or 64 bit word as usize
+    fn word(&self, pos: usize, is_64: bool) -> Option<usize> {
+        if is_64 {
+            self.u64(pos)?.try_into().ok()
+        } else {
+            Some(self.u32(pos)? as usize)
+        }
+    }
+
+    /// A NUL terminated string
+    fn cstr(&self, pos: usize) -> Option<String> {
+        let s = self.data.get(pos..)?;
+        let s = &s[..s.len().min(MAX_NAME_LEN)];
+        let end = memchr::memchr(0, s).unwrap_or(s.len());
+        Some(String::from_utf8_lossy(&s[..end]).into_owned())
+    }
+
+    /// The number of `size` byte entries starting at `pos` that fit into the data, at most `count`
+    fn clamp(&self, pos: usize, size: usize, count: usize) -> usize {
+        count.min(self.data.len().saturating_sub(pos) / size.max(1))
+    }
+}
+
+/// What is output for a binary
+#[derive(Default)]
+struct Summary {
+    format: Vec<String>,
+    sections: Vec<String>,
+    libraries: Vec<String>,
+    imports: Vec<String>,
+    exports: Vec<String>,
+    /// other (local) symbols
+    symbols: Vec<String>,
+}
+
+impl Summary {
+    fn write(&self, p: &str, out: &mut impl Write) -> Result<()> {
+        let groups = [
+            ("format", &self.format),
+            ("section", &self.sections),
+            ("library", &self.libraries),
+            ("import", &self.imports),
+            ("export", &self.exports),
+            ("symbol", &self.symbols),
+        ];
+        for (label, values) in groups {
+            let mut seen = HashSet::new();
+            for value in values {
+                if !value.is_empty() && seen.insert(value) {
+                    writeln!(out, "{p}{label}: {value}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn elf_machine(machine: u16) -> &'static str {
+    match machine {
+        3 => "x86",
+        8 => "MIPS",
+        0x14 => "PowerPC",
+        0x15 => "PowerPC64",
+        0x28 => "ARM",
+        0x3e => "x86-64",
+        0xb7 => "AArch64",
+        0xf3 => "RISC-V",
+        _ => "unknown architecture",
+    }
+}
+
+fn pe_machine(machine: u16) -> &'static str {
+    match machine {
+        0x14c => "x86",
+        0x1c0 | 0x1c4 => "ARM",
+        0x8664 => "x86-64",
+        0xaa64 => "AArch64",
+        _ => "unknown architecture",
+    }
+}
+
+fn macho_cpu(cpu: u32) -> &'static str {
+    match cpu {
+        7 => "x86",
+        0x0100_0007 => "x86-64",
+        12 => "ARM",
+        0x0100_000c => "ARM64",
+        18 => "PowerPC",
+        0x0100_0012 => "PowerPC64",
+        _ => "unknown architecture",
+    }
+}
+
+// ELF section types
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_DYNAMIC: u32 = 6;
+const SHT_NOBITS: u32 = 8;
+const SHT_DYNSYM: u32 = 11;
+const DT_NEEDED: u64 = 1;
+
+fn parse_elf(data: &[u8], summary: &mut Summary) -> Option<()> {
+    let is_64 = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let r = Reader {
+        data,
+        endian: if *data.get(5)? == 2 {
+            Endian::Big
+        } else {
+            Endian::Little
+        },
+    };
+    let kind = match r.u16(0x10)? {
+        1 => "relocatable object",
+        2 => "executable",
+        3 => "shared object",
+        4 => "core dump",
+        _ => "file",
+    };
+    summary.format.push(format!(
+        "ELF {}-bit {} {kind}",
+        if is_64 { 64 } else { 32 },
+        elf_machine(r.u16(0x12)?)
+    ));
+    let (shoff, entsize, shnum, shstrndx) = if is_64 {
+        (
+            r.word(0x28, true)?,
+            r.u16(0x3a)?,
+            r.u16(0x3c)?,
+            r.u16(0x3e)?,
+        )
+    } else {
+        (
+            r.word(0x20, false)?,
+            r.u16(0x2e)?,
+            r.u16(0x30)?,
+            r.u16(0x32)?,
+        )
+    };
+    // (name, type, offset, size, link)
+    let mut sections = Vec::new();
+    let entsize = entsize as usize;
+    for i in 0..r.clamp(shoff, entsize, shnum as usize) {
+        let h = shoff + i * entsize;
+        sections.push(if is_64 {
+            (
+                r.u32(h)?,
+                r.u32(h + 4)?,
+                r.word(h + 24, true)?,
+                r.word(h + 32, true)?,
+                r.u32(h + 40)?,
+            )
+        } else {
+            (
+                r.u32(h)?,
+                r.u32(h + 4)?,
+                r.word(h + 16, false)?,
+                r.word(h + 20, false)?,
+                r.u32(h + 24)?,
+            )
+        });
+    }
+    // the data of a section as a reader, empty for sections without data
+    let section_data = |index: usize| {
+        let data = sections
+            .get(index)
+            .filter(|s| s.1 != SHT_NOBITS)
+            .and_then(|&(_, _, offset, size, _)| data.get(offset..offset.checked_add(size)?))
+            .unwrap_or_default();
+        Reader { data, ..r }
+    };
+    let names = section_data(shstrndx as usize);
+    for &(name, ..) in &sections {
+        summary.sections.extend(names.cstr(name as usize));
+    }
+    for (index, &(_, kind, _, _, link)) in sections.iter().enumerate() {
+        if sections.get(link as usize).map(|s| s.1) != Some(SHT_STRTAB) {
+            continue;
+        }
+        let s = section_data(index);
+        let strings = section_data(link as usize);
+        match kind {
+            SHT_SYMTAB | SHT_DYNSYM => {
+                let entsize = if is_64 { 24 } else { 16 };
+                for i in 1..s.clamp(0, entsize, usize::MAX) {
+                    let e = i * entsize;
+                    let (info, shndx) = if is_64 {
+                        (*s.data.get(e + 4)?, s.u16(e + 6)?)
+                    } else {
+                        (*s.data.get(e + 12)?, s.u16(e + 14)?)
+                    };
+                    // only functions, variables and untyped symbols, not sections or files
+                    if info & 0xf > 2 {
+                        continue;
+                    }
+                    let Some(name) = strings.cstr(s.u32(e)? as usize) else {
+                        continue;
+                    };
+                    // binding: 0 local, 1 global, 2 weak
+                    let global = matches!(info >> 4, 1 | 2);
+                    if shndx == 0 {
+                        summary.imports.push(name);
+                    } else if global {
+                        summary.exports.push(name);
+                    } else {
+                        summary.symbols.push(name);
+                    }
+                }
+            }
+            SHT_DYNAMIC => {
+                let entsize = if is_64 { 16 } else { 8 };
+                for i in 0..s.clamp(0, entsize, usize::MAX) {
+                    let e = i * entsize;
+                    let (tag, value) = if is_64 {
+                        (s.u64(e)?, s.word(e + 8, true)?)
+                    } else {
+                        (s.u32(e)? as u64, s.word(e + 4, false)?)
+                    };
+                    if tag == DT_NEEDED {
+                        summary.libraries.extend(strings.cstr(value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(())
+}
+
+fn parse_pe(data: &[u8], summary: &mut Summary) -> Option<()> {
+    let r = Reader {
+        data,
+        endian: Endian::Little,
+    };
+    let pe = r.u32(0x3c)? as usize;
+    if r.bytes::<4>(pe)? != *b"PE\0\0" {
+        return None;
+    }
+    let machine = r.u16(pe + 4)?;
+    let num_sections = r.u16(pe + 6)? as usize;
+    let opt_size = r.u16(pe + 20)? as usize;
+    let characteristics = r.u16(pe + 22)?;
+    let opt = pe + 24;
+    let is_64 = r.u16(opt)? == 0x20b;
+    let kind = if characteristics & 0x2000 != 0 {
+        "DLL"
+    } else {
+        "executable"
+    };
+    summary.format.push(format!(
+        "PE{} {} {kind}",
+        if is_64 { "32+" } else { "32" },
+        pe_machine(machine)
+    ));
+    // (virtual address, virtual size, file offset, file size)
+    let mut sections = Vec::new();
+    let table = opt + opt_size;
+    for i in 0..r.clamp(table, 40, num_sections) {
+        let s = table + i * 40;
+        let name = r.bytes::<8>(s)?;
+        let end = memchr::memchr(0, &name).unwrap_or(8);
+        summary
+            .sections
+            .push(String::from_utf8_lossy(&name[..end]).into_owned());
+        sections.push((
+            r.u32(s + 12)? as usize,
+            r.u32(s + 8)? as usize,
+            r.u32(s + 20)? as usize,
+            r.u32(s + 16)? as usize,
+        ));
+    }
+    let offset = |rva: usize| {
+        sections
+            .iter()
+            .find(|&&(va, vsize, _, size)| rva >= va && rva < va + vsize.max(size))
+            .map(|&(va, _, ptr, _)| rva - va + ptr)
+    };
+    let (num_dirs, dirs) = if is_64 {
+        (r.u32(opt + 108)?, opt + 112)
+    } else {
+        (r.u32(opt + 92)?, opt + 96)
+    };
+    let dir = |i: u32| {
+        if i >= num_dirs {
+            return None;
+        }
+        Some(r.u32(dirs + i as usize * 8)? as usize).filter(|rva| *rva != 0)
+    };
+    if let Some(exports) = dir(0).and_then(offset) {
+        let count = r.u32(exports + 24)? as usize;
+        if let Some(names) = offset(r.u32(exports + 32)? as usize) {
+            for i in 0..r.clamp(names, 4, count) {
+                summary
+                    .exports
+                    .extend(offset(r.u32(names + i * 4)? as usize).and_then(|o| r.cstr(o)));
+            }
+        }
+    }
+    if let Some(imports) = dir(1).and_then(offset) {
+        for i in 0..r.clamp(imports, 20, usize::MAX) {
+            let d = imports + i * 20;
+            let (lookup, name, thunks) = (r.u32(d)?, r.u32(d + 12)?, r.u32(d + 16)?);
+            if name == 0 && thunks == 0 {
+                break;
+            }
+            summary
+                .libraries
+                .extend(offset(name as usize).and_then(|o| r.cstr(o)));
+            // the lookup table is missing in some linkers' output, the address table then has the same content
+            let table = if lookup != 0 { lookup } else { thunks };
+            let Some(table) = offset(table as usize) else {
+                continue;
+            };
+            let size = if is_64 { 8 } else { 4 };
+            for j in 0..r.clamp(table, size, usize::MAX) {
+                let thunk = if is_64 {
+                    r.u64(table + j * size)?
+                } else {
+                    r.u32(table + j * size)? as u64
+                };
+                if thunk == 0 {
+                    break;
+                }
+                // imports by ordinal have no name
+                let by_ordinal = thunk >> (size * 8 - 1) == 1;
+                if !by_ordinal {
+                    // skip the hint
+                    summary
+                        .imports
+                        .extend(offset((thunk & 0x7fff_ffff) as usize).and_then(|o| r.cstr(o + 2)));
+                }
+            }
+        }
+    }
+    Some(())
+}
+
+// Mach-O load commands
+const LC_SEGMENT: u32 = 0x1;
+const LC_SYMTAB: u32 = 0x2;
+const LC_LOAD_DYLIB: u32 = 0xc;
+const LC_SEGMENT_64: u32 = 0x19;
+const LC_LOAD_WEAK_DYLIB: u32 = 0x8000_0018;
+const LC_REEXPORT_DYLIB: u32 = 0x8000_001f;
+
+fn fixed_name(b: [u8; 16]) -> String {
+    let end = memchr::memchr(0, &b).unwrap_or(16);
+    String::from_utf8_lossy(&b[..end]).into_owned()
+}
+
+fn parse_macho(data: &[u8], summary: &mut Summary) -> Option<()> {
+    let magic = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    let (endian, is_64) = match magic {
+        0xfeed_face => (Endian::Little, false),
+        0xfeed_facf => (Endian::Little, true),
+        0xcefa_edfe => (Endian::Big, false),
+        0xcffa_edfe => (Endian::Big, true),
+        _ => return None,
+    };
+    let r = Reader { data, endian };
+    let kind = match r.u32(12)? {
+        1 => "object",
+        2 => "executable",
+        6 => "dynamic library",
+        8 => "bundle",
+        _ => "file",
+    };
+    summary.format.push(format!(
+        "Mach-O {}-bit {} {kind}",
+        if is_64 { 64 } else { 32 },
+        macho_cpu(r.u32(4)?)
+    ));
+    let ncmds = r.u32(16)? as usize;
+    let mut cmd = if is_64 { 32 } else { 28 };
+    for _ in 0..r.clamp(cmd, 8, ncmds) {
+        let (kind, size) = (r.u32(cmd)?, r.u32(cmd + 4)? as usize);
+        match kind {
+            LC_SEGMENT | LC_SEGMENT_64 => {
+                let (nsects, first, sect_size) = if kind == LC_SEGMENT_64 {
+                    (r.u32(cmd + 64)?, cmd + 72, 80)
+                } else {
+                    (r.u32(cmd + 48)?, cmd + 56, 68)
+                };
+                for i in 0..r.clamp(first, sect_size, nsects as usize) {
+                    let s = first + i * sect_size;
+                    summary.sections.push(format!(
+                        "{},{}",
+                        fixed_name(r.bytes(s + 16)?),
+                        fixed_name(r.bytes(s)?)
+                    ));
+                }
+            }
+            LC_SYMTAB => {
+                let (symoff, nsyms) = (r.u32(cmd + 8)? as usize, r.u32(cmd + 12)? as usize);
+                let stroff = r.u32(cmd + 16)? as usize;
+                let entsize = if is_64 { 16 } else { 12 };
+                for i in 0..r.clamp(symoff, entsize, nsyms) {
+                    let e = symoff + i * entsize;
+                    let ty = *data.get(e + 4)?;
+                    // debugging symbols
+                    if ty & 0xe0 != 0 {
+                        continue;
+                    }
+                    let Some(name) = r.cstr(stroff + r.u32(e)? as usize) else {
+                        continue;
+                    };
+                    let external = ty & 1 != 0;
+                    if ty & 0x0e == 0 && external {
+                        summary.imports.push(name);
+                    } else if external {
+                        summary.exports.push(name);
+                    } else {
+                        summary.symbols.push(name);
+                    }
+                }
+            }
+            LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB | LC_REEXPORT_DYLIB => {
+                summary
+                    .libraries
+                    .extend(r.cstr(cmd + r.u32(cmd + 8)? as usize));
+            }
+            _ => {}
+        }
+        if size < 8 {
+            break;
+        }
+        cmd += size;
+    }
+    Some(())
+}
+
+/// Universal binaries contain a Mach-O binary per architecture
+fn parse_fat_macho(data: &[u8], summary: &mut Summary) -> Option<()> {
+    let r = Reader {
+        data,
+        endian: Endian::Big,
+    };
+    let is_64 = match r.u32(0)? {
+        0xcafe_babe => false,
+        0xcafe_babf => true,
+        _ => return None,
+    };
+    let count = r.u32(4)? as usize;
+    // java class files have the same magic, followed by their version (45 or higher)
+    if count > 30 {
+        return None;
+    }
+    let entsize = if is_64 { 32 } else { 20 };
+    for i in 0..r.clamp(8, entsize, count) {
+        let e = 8 + i * entsize;
+        let (offset, size) = if is_64 {
+            (r.word(e + 8, true)?, r.word(e + 16, true)?)
+        } else {
+            (r.word(e + 8, false)?, r.word(e + 12, false)?)
+        };
+        if let Some(slice) = data.get(offset..offset.checked_add(size)?) {
+            parse_macho(slice, summary);
+        }
+    }
+    Some(())
+}
+
+fn dump_executable(data: &[u8], min_len: usize, p: &str, mut out: impl Write) -> Result<()> {
+    let mut summary = Summary::default();
+    if data.starts_with(b"\x7fELF") {
+        parse_elf(data, &mut summary);
+    } else if data.starts_with(b"MZ") {
+        parse_pe(data, &mut summary);
+    } else if parse_fat_macho(data, &mut summary).is_none() {
+        parse_macho(data, &mut summary);
+    }
+    // whatever could be parsed is output even if the binary is truncated or corrupt
+    summary.write(p, &mut out)?;
+    write_strings(data, min_len, &format!("{p}string "), &mut out)?;
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for ExecutableAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        // the headers reference each other by offset
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            dump_executable(&data, config.strings_min_length.0, &line_prefix, oup)
+        })
+        .await?
+        .context("in synchronous executable task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    /// A 64-bit little endian ELF shared object with a dynamic symbol table
+    fn elf() -> Vec<u8> {
+        let shstrtab = b"\0.shstrtab\0.dynstr\0.dynsym\0.rodata\0";
+        let dynstr = b"\0puts\0greet\0";
+        let mut dynsym = vec![0u8; 24];
+        // undefined global function puts, defined global function greet
+        for (name, shndx) in [(1u32, 0u16), (6, 4)] {
+            let mut sym = vec![0u8; 24];
+            sym[0..4].copy_from_slice(&name.to_le_bytes());
+            sym[4] = 0x12;
+            sym[6..8].copy_from_slice(&shndx.to_le_bytes());
+            dynsym.extend(sym);
+        }
+        let rodata = b"\0\0hello from the library\0";
+        let mut d = vec![0u8; 64];
+        d[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        d[0x10..0x12].copy_from_slice(&3u16.to_le_bytes());
+        d[0x12..0x14].copy_from_slice(&0x3eu16.to_le_bytes());
+        // (name, type, data, link)
+        let sections: [(u32, u32, &[u8], u32); 5] = [
+            (0, 0, b"", 0),
+            (1, SHT_STRTAB, shstrtab, 0),
+            (11, SHT_STRTAB, dynstr, 0),
+            (19, SHT_DYNSYM, &dynsym, 2),
+            (27, 1, rodata, 0),
+        ];
+        let mut headers = Vec::new();
+        for (name, kind, data, link) in sections {
+            let mut h = vec![0u8; 64];
+            h[0..4].copy_from_slice(&name.to_le_bytes());
+            h[4..8].copy_from_slice(&kind.to_le_bytes());
+            h[24..32].copy_from_slice(&(d.len() as u64).to_le_bytes());
+            h[32..40].copy_from_slice(&(data.len() as u64).to_le_bytes());
+            h[40..44].copy_from_slice(&link.to_le_bytes());
+            headers.extend(h);
+            d.extend_from_slice(data);
+        }
+        let shoff = d.len() as u64;
+        d[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        d[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        d[0x3c..0x3e].copy_from_slice(&5u16.to_le_bytes());
+        d[0x3e..0x40].copy_from_slice(&1u16.to_le_bytes());
+        d.extend(headers);
+        d
+    }
+
+    #[tokio::test]
+    async fn elf_symbols() -> Result<()> {
+        let adapter: Box<dyn FileAdapter> = Box::<ExecutableAdapter>::default();
+        let (mut a, d) = simple_adapt_info(
+            std::path::Path::new("libgreet.so"),
+            Box::pin(std::io::Cursor::new(elf())),
+        );
+        a.config.strings_min_length.0 = 10;
+        let res = adapter.adapt(a, &d).await?;
+        let out = String::from_utf8(adapted_to_vec(res).await?)?;
+        assert_eq!(
+            out.lines().take(8).collect::<Vec<_>>(),
+            [
+                "PREFIX:format: ELF 64-bit x86-64 shared object",
+                "PREFIX:section: .shstrtab",
+                "PREFIX:section: .dynstr",
+                "PREFIX:section: .dynsym",
+                "PREFIX:section: .rodata",
+                "PREFIX:import: puts",
+                "PREFIX:export: greet",
+                "PREFIX:string 000000b9: hello from the library",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pe_imports() -> Result<()> {
+        let mut d = vec![0u8; 0x400];
+        d[..2].copy_from_slice(b"MZ");
+        d[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        d[0x80..0x84].copy_from_slice(b"PE\0\0");
+        d[0x84..0x86].copy_from_slice(&0x8664u16.to_le_bytes());
+        d[0x86..0x88].copy_from_slice(&1u16.to_le_bytes());
+        d[0x94..0x96].copy_from_slice(&240u16.to_le_bytes());
+        let opt = 0x98;
+        d[opt..opt + 2].copy_from_slice(&0x20bu16.to_le_bytes());
+        d[opt + 108..opt + 112].copy_from_slice(&16u32.to_le_bytes());
+        // import directory at rva 0x1000
+        d[opt + 120..opt + 124].copy_from_slice(&0x1000u32.to_le_bytes());
+        // .idata at rva 0x1000, file offset 0x200
+        let s = opt + 240;
+        d[s..s + 6].copy_from_slice(b".idata");
+        d[s + 8..s + 12].copy_from_slice(&0x200u32.to_le_bytes());
+        d[s + 12..s + 16].copy_from_slice(&0x1000u32.to_le_bytes());
+        d[s + 16..s + 20].copy_from_slice(&0x200u32.to_le_bytes());
+        d[s + 20..s + 24].copy_from_slice(&0x200u32.to_le_bytes());
+        // descriptor: lookup table at 0x1040, name at 0x1080
+        d[0x200..0x204].copy_from_slice(&0x1040u32.to_le_bytes());
+        d[0x20c..0x210].copy_from_slice(&0x1080u32.to_le_bytes());
+        d[0x240..0x248].copy_from_slice(&0x1090u64.to_le_bytes());
+        d[0x280..0x28c].copy_from_slice(b"KERNEL32.dll");
+        d[0x292..0x29d].copy_from_slice(b"CreateFileW");
+        let mut out = Vec::new();
+        dump_executable(&d, 20, "", &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "format: PE32+ x86-64 executable\n\
+             section: .idata\n\
+             library: KERNEL32.dll\n\
+             import: CreateFileW\n"
+        );
+        Ok(())
+    }
+}
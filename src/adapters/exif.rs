@@ -0,0 +1,887 @@
+//! Image metadata: EXIF (including GPS), IPTC and XMP, embedded in JPEG, PNG, WebP and TIFF files.
+
+use super::{binary::Endian, writing::WritingFileAdapter, xml::XmlEvent, xml::XmlReader, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+// raw camera formats that are TIFF files
+static EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "tif", "tiff", "dng", "nef", "cr2", "arw", "pef",
+];
+static MIMETYPES: &[&str] = &["image/jpeg", "image/png", "image/webp", "image/tiff"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "exif".to_owned(),
+        version: 1,
+        description: "Outputs the metadata of images as `tag = value` lines: EXIF (camera, exposure, dates, GPS coordinates), IPTC (caption, keywords, location) and XMP.\nWith --rga-adapters=+ocr, images are read by the ocr adapter instead"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIMETYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ExifAdapter;
+
+impl ExifAdapter {
+    pub fn new() -> ExifAdapter {
+        ExifAdapter
+    }
+}
+impl GetMetadata for ExifAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const JPEG_MAGIC: &[u8] = b"\xff\xd8";
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+const XMP_PNG_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Image file directories of the EXIF data, which have their own tag numbers
+#[derive(Clone, Copy, PartialEq)]
+enum Ifd {
+    Main,
+    Exif,
+    Gps,
+}
+
+// tags that are not simple values
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const XMP_TAG: u16 = 0x02bc;
+const IPTC_TAG: u16 = 0x83bb;
+const USER_COMMENT: u16 = 0x9286;
+const EXPOSURE_TIME: u16 = 0x829a;
+const GPS_TIME_STAMP: u16 = 0x07;
+
+fn tag_name(ifd: Ifd, tag: u16) -> Option<&'static str> {
+    Some(match (ifd, tag) {
+        (Ifd::Main, 0x010d) => "DocumentName",
+        (Ifd::Main, 0x010e) => "ImageDescription",
+        (Ifd::Main, 0x010f) => "Make",
+        (Ifd::Main, 0x0110) => "Model",
+        (Ifd::Main, 0x0131) => "Software",
+        (Ifd::Main, 0x0132) => "DateTime",
+        (Ifd::Main, 0x013b) => "Artist",
+        (Ifd::Main, 0x013c) => "HostComputer",
+        (Ifd::Main, 0x8298) => "Copyright",
+        // Windows, UTF-16
+        (Ifd::Main, 0x9c9b) => "XPTitle",
+        (Ifd::Main, 0x9c9c) => "XPComment",
+        (Ifd::Main, 0x9c9d) => "XPAuthor",
+        (Ifd::Main, 0x9c9e) => "XPKeywords",
+        (Ifd::Main, 0x9c9f) => "XPSubject",
+        (Ifd::Exif, EXPOSURE_TIME) => "ExposureTime",
+        (Ifd::Exif, 0x829d) => "FNumber",
+        (Ifd::Exif, 0x8827) => "ISO",
+        (Ifd::Exif, 0x9003) => "DateTimeOriginal",
+        (Ifd::Exif, 0x9004) => "DateTimeDigitized",
+        (Ifd::Exif, 0x9011) => "OffsetTimeOriginal",
+        (Ifd::Exif, 0x920a) => "FocalLength",
+        (Ifd::Exif, USER_COMMENT) => "UserComment",
+        (Ifd::Exif, 0xa405) => "FocalLengthIn35mmFilm",
+        (Ifd::Exif, 0xa420) => "ImageUniqueID",
+        (Ifd::Exif, 0xa430) => "CameraOwnerName",
+        (Ifd::Exif, 0xa431) => "BodySerialNumber",
+        (Ifd::Exif, 0xa433) => "LensMake",
+        (Ifd::Exif, 0xa434) => "LensModel",
+        (Ifd::Exif, 0xa435) => "LensSerialNumber",
+        (Ifd::Gps, GPS_TIME_STAMP) => "GPSTimeStamp",
+        (Ifd::Gps, 0x12) => "GPSMapDatum",
+        (Ifd::Gps, 0x1d) => "GPSDateStamp",
+        _ => return None,
+    })
+}
+
+/// IPTC IIM datasets of the application record (2)
+fn iptc_name(dataset: u8) -> Option<&'static str> {
+    Some(match dataset {
+        5 => "ObjectName",
+        15 => "Category",
+        20 => "SupplementalCategories",
+        25 => "Keywords",
+        40 => "SpecialInstructions",
+        55 => "DateCreated",
+        80 => "By-line",
+        85 => "By-lineTitle",
+        90 => "City",
+        92 => "Sub-location",
+        95 => "Province-State",
+        100 => "Country-PrimaryLocationCode",
+        101 => "Country-PrimaryLocationName",
+        105 => "Headline",
+        110 => "Credit",
+        115 => "Source",
+        116 => "CopyrightNotice",
+        120 => "Caption-Abstract",
+        122 => "Writer-Editor",
+        _ => return None,
+    })
+}
+
+/// Bytes per value of the TIFF field types
+fn type_size(kind: u16) -> Option<usize> {
+    Some(match kind {
+        // byte, ascii, signed byte, undefined
+        1 | 2 | 6 | 7 => 1,
+        // short, signed short
+        3 | 8 => 2,
+        // long, signed long, float, ifd
+        4 | 9 | 11 | 13 => 4,
+        // rational, signed rational, double
+        5 | 10 | 12 => 8,
+        _ => return None,
+    })
+}
+
+/// Text that may be UTF-8, otherwise Latin-1
+fn decode_text(data: &[u8]) -> String {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text.to_owned(),
+        Err(_) => data.iter().map(|b| *b as char).collect(),
+    };
+    text.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_owned()
+}
+
+fn decode_utf16(data: &[u8], endian: Endian) -> String {
+    endian
+        .utf16(data)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_owned()
+}
+
+/// Formats a number without trailing zeros
+fn format_number(n: f64) -> String {
+    let s = format!("{n:.6}");
+    s.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+struct Entry<'a> {
+    tag: u16,
+    kind: u16,
+    value: &'a [u8],
+}
+
+/// The TIFF structure of EXIF data
+struct Tiff<'a> {
+    data: &'a [u8],
+    endian: Endian,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Result<Tiff<'a>> {
+        let endian = match data.get(..4) {
+            Some(b"II*\0") => Endian::Little,
+            Some(b"MM\0*") => Endian::Big,
+            _ => anyhow::bail!("invalid TIFF header"),
+        };
+        Ok(Tiff { data, endian })
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8]> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .context("offset out of range")
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        self.endian
+            .u16_at(self.data, offset)
+            .context("offset out of range")
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        self.endian
+            .u32_at(self.data, offset)
+            .context("offset out of range")
+    }
+
+    fn first_ifd(&self) -> Result<usize> {
+        Ok(self.u32(4)? as usize)
+    }
+
+    /// The entries of an IFD, skipping those with unknown types or values out of range
+    fn entries(&self, offset: usize) -> Result<Vec<Entry<'a>>> {
+        let count = self.u16(offset)? as usize;
+        let mut entries = Vec::new();
+        for i in 0..count {
+            let e = offset + 2 + 12 * i;
+            let (tag, kind, count) = (self.u16(e)?, self.u16(e + 2)?, self.u32(e + 4)?);
+            let Some(len) = type_size(kind).and_then(|s| s.checked_mul(count as usize)) else {
+                continue;
+            };
+            let value = if len <= 4 {
+                self.bytes(e + 8, len)?
+            } else {
+                match self.u32(e + 8).and_then(|o| self.bytes(o as usize, len)) {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                }
+            };
+            entries.push(Entry { tag, kind, value });
+        }
+        Ok(entries)
+    }
+
+    fn rationals(&self, e: &Entry) -> Vec<(f64, f64)> {
+        e.value
+            .chunks_exact(8)
+            .filter_map(|r| {
+                let (n, d) = (self.endian.u32_at(r, 0)?, self.endian.u32_at(r, 4)?);
+                Some(if e.kind == 10 {
+                    (n as i32 as f64, d as i32 as f64)
+                } else {
+                    (n as f64, d as f64)
+                })
+            })
+            .filter(|(_, d)| *d != 0.0)
+            .collect()
+    }
+
+    /// The value of a field as text, None for binary data
+    fn value(&self, e: &Entry) -> Option<String> {
+        let numbers: Vec<String> = match e.kind {
+            2 => return Some(decode_text(e.value)),
+            3 => e
+                .value
+                .chunks_exact(2)
+                .filter_map(|v| self.endian.u16_at(v, 0))
+                .map(|n| n.to_string())
+                .collect(),
+            8 => e
+                .value
+                .chunks_exact(2)
+                .filter_map(|v| self.endian.u16_at(v, 0))
+                .map(|n| (n as i16).to_string())
+                .collect(),
+            4 => e
+                .value
+                .chunks_exact(4)
+                .filter_map(|v| self.endian.u32_at(v, 0))
+                .map(|n| n.to_string())
+                .collect(),
+            9 => e
+                .value
+                .chunks_exact(4)
+                .filter_map(|v| self.endian.u32_at(v, 0))
+                .map(|n| (n as i32).to_string())
+                .collect(),
+            5 | 10 if e.tag == EXPOSURE_TIME => self
+                .rationals(e)
+                .into_iter()
+                .map(|(n, d)| {
+                    // fractions of a second like 1/250
+                    if n < d && (d / n).fract() == 0.0 {
+                        format!("1/{}", d / n)
+                    } else {
+                        format_number(n / d)
+                    }
+                })
+                .collect(),
+            5 | 10 => self
+                .rationals(e)
+                .into_iter()
+                .map(|(n, d)| format_number(n / d))
+                .collect(),
+            11 => e
+                .value
+                .chunks_exact(4)
+                .filter_map(|v| self.endian.u32_at(v, 0))
+                .map(|n| format_number(f32::from_bits(n) as f64))
+                .collect(),
+            _ => return None,
+        };
+        Some(numbers.join(", "))
+    }
+
+    /// Degrees from degrees, minutes and seconds
+    fn degrees(&self, e: &Entry) -> Option<f64> {
+        let dms = self.rationals(e);
+        let [d, m, s] = dms.get(..3)? else {
+            return None;
+        };
+        Some(d.0 / d.1 + m.0 / m.1 / 60.0 + s.0 / s.1 / 3600.0)
+    }
+
+    fn write_gps(&self, entries: &[Entry], p: &str, out: &mut impl Write) -> Result<()> {
+        let find = |tag: u16| entries.iter().find(|e| e.tag == tag);
+        let reference = |tag: u16| find(tag).map(|e| decode_text(e.value)).unwrap_or_default();
+        for (name, value_tag, ref_tag, negative) in
+            [("GPSLatitude", 2, 1, "S"), ("GPSLongitude", 4, 3, "W")]
+        {
+            if let Some(degrees) = find(value_tag).and_then(|e| self.degrees(e)) {
+                let sign = if reference(ref_tag) == negative {
+                    -1.0
+                } else {
+                    1.0
+                };
+                writeln!(out, "{p}{name} = {:.6}", sign * degrees)?;
+            }
+        }
+        if let Some((n, d)) = find(6).and_then(|e| self.rationals(e).first().copied()) {
+            // 1 is below sea level
+            let below = find(5).is_some_and(|e| e.value.first() == Some(&1));
+            let altitude = if below { -n / d } else { n / d };
+            writeln!(out, "{p}GPSAltitude = {} m", format_number(altitude))?;
+        }
+        Ok(())
+    }
+
+    fn write_ifd(&self, ifd: Ifd, offset: usize, p: &str, out: &mut impl Write) -> Result<()> {
+        let entries = self.entries(offset)?;
+        if ifd == Ifd::Gps {
+            self.write_gps(&entries, p, out)?;
+        }
+        for e in &entries {
+            let pointer = || {
+                self.endian
+                    .u32_at(e.value, 0)
+                    .map(|o| o as usize)
+                    .context("invalid pointer")
+            };
+            match (ifd, e.tag) {
+                (Ifd::Main, EXIF_IFD) if e.value.len() == 4 => pointer()
+                    .and_then(|o| self.write_ifd(Ifd::Exif, o, p, out))
+                    .context("invalid EXIF IFD")
+                    .unwrap_or_else(|e| warn!("{e:#}")),
+                (Ifd::Main, GPS_IFD) if e.value.len() == 4 => pointer()
+                    .and_then(|o| self.write_ifd(Ifd::Gps, o, p, out))
+                    .context("invalid GPS IFD")
+                    .unwrap_or_else(|e| warn!("{e:#}")),
+                (Ifd::Main, XMP_TAG) => write_xmp(e.value, p, out)?,
+                (Ifd::Main, IPTC_TAG) => write_iptc(e.value, p, out)?,
+                (Ifd::Main, 0x9c9b..=0x9c9f) => {
+                    let text = decode_utf16(e.value, Endian::Little);
+                    if !text.is_empty() {
+                        writeln!(
+                            out,
+                            "{p}{} = {text}",
+                            tag_name(ifd, e.tag).unwrap_or_default()
+                        )?;
+                    }
+                }
+                (Ifd::Exif, USER_COMMENT) if e.value.len() >= 8 => {
+                    let (charset, text) = e.value.split_at(8);
+                    let text = if charset.starts_with(b"UNICODE") {
+                        decode_utf16(text, self.endian)
+                    } else {
+                        decode_text(text)
+                    };
+                    if !text.is_empty() {
+                        writeln!(out, "{p}UserComment = {text}")?;
+                    }
+                }
+                (Ifd::Gps, GPS_TIME_STAMP) => {
+                    if let [h, m, s] = self.rationals(e)[..] {
+                        let (h, m, s) = (h.0 / h.1, m.0 / m.1, s.0 / s.1);
+                        writeln!(out, "{p}GPSTimeStamp = {h:02}:{m:02}:{s:02}")?;
+                    }
+                }
+                _ => {
+                    let Some(name) = tag_name(ifd, e.tag) else {
+                        continue;
+                    };
+                    match self.value(e) {
+                        Some(value) if !value.is_empty() => writeln!(out, "{p}{name} = {value}")?,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_exif(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    let tiff = Tiff::new(data)?;
+    tiff.write_ifd(Ifd::Main, tiff.first_ifd()?, p, out)
+}
+
+/// IPTC IIM: records of `0x1c`, record number, dataset number, size, value
+fn write_iptc(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    let mut pos = 0;
+    while let Some(header) = data.get(pos..pos + 5) {
+        anyhow::ensure!(header[0] == 0x1c, "invalid IPTC record");
+        let (record, dataset) = (header[1], header[2]);
+        let mut size = u16::from_be_bytes([header[3], header[4]]) as usize;
+        pos += 5;
+        if size & 0x8000 != 0 {
+            // extended dataset, the size is in the next bytes
+            let len = size & 0x7fff;
+            let bytes = data.get(pos..pos + len).context("truncated IPTC size")?;
+            size = bytes
+                .iter()
+                .fold(0usize, |s, b| s.saturating_mul(256) | *b as usize);
+            pos += len;
+        }
+        let value = data
+            .get(pos..pos.saturating_add(size))
+            .context("truncated IPTC value")?;
+        pos += size;
+        if record == 2 {
+            if let Some(name) = iptc_name(dataset) {
+                let value = decode_text(value);
+                if !value.is_empty() {
+                    writeln!(out, "{p}{name} = {value}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Photoshop image resources: `8BIM`, id, name as padded pascal string, size, data padded to an even length
+fn write_photoshop(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    const IPTC_RESOURCE: u16 = 0x0404;
+    let mut pos = 0;
+    while data.get(pos..pos + 4) == Some(b"8BIM") {
+        let id = u16::from_be_bytes(
+            data.get(pos + 4..pos + 6)
+                .context("truncated resource")?
+                .try_into()?,
+        );
+        let name_len = *data.get(pos + 6).context("truncated resource")? as usize;
+        pos += 6 + (name_len + 2) / 2 * 2;
+        let size = u32::from_be_bytes(
+            data.get(pos..pos + 4)
+                .context("truncated resource")?
+                .try_into()?,
+        ) as usize;
+        pos += 4;
+        let value = data
+            .get(pos..pos.saturating_add(size))
+            .context("truncated resource")?;
+        if id == IPTC_RESOURCE {
+            write_iptc(value, p, out)?;
+        }
+        pos += size + size % 2;
+    }
+    Ok(())
+}
+
+/// Elements of the RDF structure, the values are in the properties around them
+fn is_rdf_container(name: &str) -> bool {
+    name.starts_with("rdf:") || name.starts_with("x:")
+}
+
+/// Outputs the properties of an XMP packet, with their namespace prefix (e.g. `dc:title`)
+fn write_xmp(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    let xmp = String::from_utf8_lossy(data);
+    let mut elements: Vec<&str> = Vec::new();
+    for event in XmlReader::new(&xmp) {
+        match event {
+            XmlEvent::Start {
+                name,
+                attrs,
+                self_closing,
+            } => {
+                // properties can be written as attributes, xmlns and xml:lang are not properties
+                for (key, value) in &attrs {
+                    let value = value.trim();
+                    if key.starts_with("xml") || is_rdf_container(key) || value.is_empty() {
+                        continue;
+                    }
+                    writeln!(out, "{p}{key} = {value}")?;
+                }
+                if !self_closing {
+                    elements.push(name);
+                }
+            }
+            XmlEvent::End { .. } => {
+                elements.pop();
+            }
+            XmlEvent::Text(text) => {
+                let text = text.trim();
+                let property = elements.iter().rev().find(|e| !is_rdf_container(e));
+                if let (Some(property), false) = (property, text.is_empty()) {
+                    writeln!(out, "{p}{property} = {text}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_jpeg(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    let mut pos = 2;
+    loop {
+        let Some(&[0xff, marker]) = data.get(pos..pos + 2) else {
+            anyhow::ensure!(pos >= data.len(), "invalid JPEG marker");
+            return Ok(());
+        };
+        match marker {
+            // padding
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            // markers without a length
+            0x01 | 0xd0..=0xd7 => {
+                pos += 2;
+                continue;
+            }
+            // start of scan (the image data) or end of image, the metadata comes before
+            0xda | 0xd9 => return Ok(()),
+            _ => {}
+        }
+        let len = u16::from_be_bytes(
+            data.get(pos + 2..pos + 4)
+                .context("truncated JPEG")?
+                .try_into()?,
+        ) as usize;
+        let segment = data
+            .get(pos + 4..pos + 2 + len.max(2))
+            .context("truncated JPEG segment")?;
+        pos += 2 + len;
+        let res = match marker {
+            // APP1
+            0xe1 => {
+                if let Some(exif) = segment.strip_prefix(EXIF_HEADER) {
+                    write_exif(exif, p, out)
+                } else if let Some(xmp) = segment.strip_prefix(XMP_HEADER) {
+                    write_xmp(xmp, p, out)
+                } else {
+                    Ok(())
+                }
+            }
+            // APP13
+            0xed => match segment.strip_prefix(PHOTOSHOP_HEADER) {
+                Some(resources) => write_photoshop(resources, p, out),
+                None => Ok(()),
+            },
+            // comment
+            0xfe => {
+                let comment = decode_text(segment);
+                if !comment.is_empty() {
+                    writeln!(out, "{p}Comment = {comment}")?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        res.context("invalid metadata segment, ignoring it")
+            .unwrap_or_else(|e| warn!("{e:#}"));
+    }
+}
+
+/// Outputs the eXIf chunk, XMP and the (uncompressed) text chunks
+fn write_png(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    let mut pos = PNG_MAGIC.len();
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_be_bytes(header[..4].try_into()?) as usize;
+        let kind = &header[4..];
+        let chunk = data
+            .get(pos + 8..pos + 8 + len)
+            .context("truncated PNG chunk")?;
+        // length, type, data, crc
+        pos += 12 + len;
+        match kind {
+            b"eXIf" => write_exif(chunk, p, out)
+                .context("invalid eXIf chunk")
+                .unwrap_or_else(|e| warn!("{e:#}")),
+            b"tEXt" => {
+                if let Some((keyword, text)) = split_nul(chunk) {
+                    let text = decode_text(text);
+                    if !text.is_empty() {
+                        writeln!(out, "{p}{} = {text}", decode_text(keyword))?;
+                    }
+                }
+            }
+            b"iTXt" => {
+                // keyword, compression flag and method, language, translated keyword, text
+                let Some((keyword, rest)) = split_nul(chunk) else {
+                    continue;
+                };
+                let Some((&[0, _], rest)) = rest.split_first_chunk::<2>() else {
+                    debug!("skipping compressed iTXt chunk");
+                    continue;
+                };
+                let Some((_, rest)) = split_nul(rest) else {
+                    continue;
+                };
+                let Some((_, text)) = split_nul(rest) else {
+                    continue;
+                };
+                if keyword == XMP_PNG_KEYWORD {
+                    write_xmp(text, p, out)?;
+                } else {
+                    let text = decode_text(text);
+                    if !text.is_empty() {
+                        writeln!(out, "{p}{} = {text}", decode_text(keyword))?;
+                    }
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn split_nul(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let i = memchr::memchr(0, data)?;
+    Some((&data[..i], &data[i + 1..]))
+}
+
+/// RIFF chunks: type, little endian size, data padded to an even length
+fn write_webp(data: &[u8], p: &str, out: &mut impl Write) -> Result<()> {
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let len = u32::from_le_bytes(header[4..].try_into()?) as usize;
+        let chunk = data
+            .get(pos + 8..pos + 8 + len)
+            .context("truncated WebP chunk")?;
+        pos += 8 + len + len % 2;
+        match &header[..4] {
+            // some writers include the JPEG header
+            b"EXIF" => write_exif(chunk.strip_prefix(EXIF_HEADER).unwrap_or(chunk), p, out)
+                .context("invalid EXIF chunk")
+                .unwrap_or_else(|e| warn!("{e:#}")),
+            b"XMP " => write_xmp(chunk, p, out)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_metadata(data: &[u8], p: &str, mut out: impl Write) -> Result<()> {
+    if data.starts_with(JPEG_MAGIC) {
+        write_jpeg(data, p, &mut out)
+    } else if data.starts_with(PNG_MAGIC) {
+        write_png(data, p, &mut out)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        write_webp(data, p, &mut out)
+    } else if Tiff::new(data).is_ok() {
+        write_exif(data, p, &mut out)
+    } else {
+        anyhow::bail!("unknown image format")
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for ExifAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || write_metadata(&data, &line_prefix, oup))
+            .await?
+            .context("in synchronous exif task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    /// A big endian TIFF structure with the given IFDs. The values of the EXIF and GPS IFD
+    /// pointers are the indexes of the IFDs they point to
+    fn tiff(ifds: &[Vec<(u16, u16, Vec<u8>)>]) -> Vec<u8> {
+        let mut offsets = vec![8];
+        for ifd in ifds {
+            offsets.push(offsets.last().unwrap() + 2 + 12 * ifd.len() + 4);
+        }
+        let mut out = b"MM\0*".to_vec();
+        out.extend_from_slice(&8u32.to_be_bytes());
+        let mut values = Vec::new();
+        let values_start = *offsets.last().unwrap();
+        for ifd in ifds {
+            out.extend_from_slice(&(ifd.len() as u16).to_be_bytes());
+            for (tag, kind, value) in ifd {
+                let mut value = value.clone();
+                if *tag == EXIF_IFD || *tag == GPS_IFD {
+                    value = (offsets[value[0] as usize] as u32).to_be_bytes().to_vec();
+                }
+                out.extend_from_slice(&tag.to_be_bytes());
+                out.extend_from_slice(&kind.to_be_bytes());
+                let count = value.len() / type_size(*kind).unwrap();
+                out.extend_from_slice(&(count as u32).to_be_bytes());
+                if value.len() <= 4 {
+                    value.resize(4, 0);
+                    out.extend_from_slice(&value);
+                } else {
+                    let offset = values_start + values.len();
+                    out.extend_from_slice(&(offset as u32).to_be_bytes());
+                    values.extend_from_slice(&value);
+                }
+            }
+            out.extend_from_slice(&0u32.to_be_bytes());
+        }
+        out.extend(values);
+        out
+    }
+
+    fn rationals(values: &[(u32, u32)]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|(n, d)| [n.to_be_bytes(), d.to_be_bytes()].concat())
+            .collect()
+    }
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut s = vec![0xff, marker];
+        s.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        s.extend_from_slice(data);
+        s
+    }
+
+    const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/" photoshop:City="Paris">
+   <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Eiffel tower</rdf:li></rdf:Alt></dc:title>
+   <dc:subject><rdf:Bag><rdf:li>paris</rdf:li><rdf:li>night</rdf:li></rdf:Bag></dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    async fn metadata(filename: &str, data: Vec<u8>) -> Result<String> {
+        let adapter: Box<dyn FileAdapter> = Box::<ExifAdapter>::default();
+        let (a, d) = simple_adapt_info(
+            std::path::Path::new(filename),
+            Box::pin(std::io::Cursor::new(data)),
+        );
+        let res = adapter.adapt(a, &d).await?;
+        Ok(String::from_utf8(adapted_to_vec(res).await?)?)
+    }
+
+    #[tokio::test]
+    async fn jpeg() -> Result<()> {
+        let xp_title: Vec<u8> = "Eiffel\0"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let exif = tiff(&[
+            vec![
+                (0x010f, 2, b"Canon\0".to_vec()),
+                (0x0110, 2, b"Canon EOS R5\0".to_vec()),
+                // strip offsets
+                (0x0111, 4, vec![0, 0, 0, 42]),
+                (EXIF_IFD, 4, vec![1]),
+                (GPS_IFD, 4, vec![2]),
+                (0x9c9b, 1, xp_title),
+            ],
+            vec![
+                (EXPOSURE_TIME, 5, rationals(&[(1, 250)])),
+                (0x829d, 5, rationals(&[(28, 10)])),
+                (0x8827, 3, vec![0, 100]),
+                (0x9003, 2, b"2024:05:01 21:30:00\0".to_vec()),
+                (USER_COMMENT, 7, b"ASCII\0\0\0tower at night".to_vec()),
+            ],
+            vec![
+                (1, 2, b"N\0".to_vec()),
+                (2, 5, rationals(&[(48, 1), (51, 1), (2970, 100)])),
+                (3, 2, b"W\0".to_vec()),
+                (4, 5, rationals(&[(2, 1), (17, 1), (4020, 100)])),
+                (6, 5, rationals(&[(35, 1)])),
+                (GPS_TIME_STAMP, 5, rationals(&[(21, 1), (30, 1), (0, 1)])),
+            ],
+        ]);
+        let mut iptc = Vec::new();
+        for (dataset, value) in [(120, "Tour Eiffel by night"), (25, "landmark")] {
+            iptc.extend_from_slice(&[0x1c, 2, dataset]);
+            iptc.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            iptc.extend_from_slice(value.as_bytes());
+        }
+        let mut photoshop = PHOTOSHOP_HEADER.to_vec();
+        photoshop.extend_from_slice(b"8BIM\x04\x04\0\0");
+        photoshop.extend_from_slice(&(iptc.len() as u32).to_be_bytes());
+        photoshop.extend(iptc);
+
+        let mut jpeg = JPEG_MAGIC.to_vec();
+        jpeg.extend(segment(0xe1, &[EXIF_HEADER, &exif].concat()));
+        jpeg.extend(segment(0xe1, &[XMP_HEADER, XMP.as_bytes()].concat()));
+        jpeg.extend(segment(0xed, &photoshop));
+        jpeg.extend(segment(0xfe, b"taken from the Trocadero"));
+        jpeg.extend(segment(0xda, b"\0\0image data"));
+        jpeg.extend_from_slice(b"\xff\xd9");
+
+        assert_eq!(
+            metadata("tower.jpg", jpeg).await?,
+            "PREFIX:Make = Canon
+PREFIX:Model = Canon EOS R5
+PREFIX:ExposureTime = 1/250
+PREFIX:FNumber = 2.8
+PREFIX:ISO = 100
+PREFIX:DateTimeOriginal = 2024:05:01 21:30:00
+PREFIX:UserComment = tower at night
+PREFIX:GPSLatitude = 48.858250
+PREFIX:GPSLongitude = -2.294500
+PREFIX:GPSAltitude = 35 m
+PREFIX:GPSTimeStamp = 21:30:00
+PREFIX:XPTitle = Eiffel
+PREFIX:photoshop:City = Paris
+PREFIX:dc:title = Eiffel tower
+PREFIX:dc:subject = paris
+PREFIX:dc:subject = night
+PREFIX:Caption-Abstract = Tour Eiffel by night
+PREFIX:Keywords = landmark
+PREFIX:Comment = taken from the Trocadero
+"
+        );
+        Ok(())
+    }
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        // the crc is not checked
+        [&(data.len() as u32).to_be_bytes(), kind, data, &[0; 4]].concat()
+    }
+
+    #[tokio::test]
+    async fn png() -> Result<()> {
+        let mut png = PNG_MAGIC.to_vec();
+        png.extend(chunk(b"IHDR", &[0; 13]));
+        png.extend(chunk(b"tEXt", b"Author\0Gustave"));
+        png.extend(chunk(b"IDAT", b"image data"));
+        let xmp = [XMP_PNG_KEYWORD, b"\0\0\0\0\0", XMP.as_bytes()].concat();
+        png.extend(chunk(b"iTXt", &xmp));
+        png.extend(chunk(b"iTXt", b"Description\0\x01\0\0\0compressed"));
+        png.extend(chunk(b"IEND", b""));
+        assert_eq!(
+            metadata("tower.png", png).await?,
+            "PREFIX:Author = Gustave
+PREFIX:photoshop:City = Paris
+PREFIX:dc:title = Eiffel tower
+PREFIX:dc:subject = paris
+PREFIX:dc:subject = night
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use super::writing::WritingFileAdapter;
+use super::xml::{XmlEvent, XmlReader, local_name};
+use super::*;
+use anyhow::Result;
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["fb2"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "fb2".to_owned(),
+        version: 1,
+        description: "Reads FictionBook (.fb2) e-books. Outputs the title, authors and annotation and then the text of the book, prefixed with the titles of its sections (e.g. `Part 1 / Chapter 2: `).\nZipped books (.fb2.zip) are read through the zip adapter."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-fictionbook+xml".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct Fb2Adapter;
+
+impl Fb2Adapter {
+    pub fn new() -> Fb2Adapter {
+        Fb2Adapter
+    }
+}
+
+impl GetMetadata for Fb2Adapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Elements whose text is output as one line. Inline elements (emphasis, links, ...) are part of the line.
+const BLOCK_ELEMENTS: &[&str] = &["p", "v", "subtitle", "text-author", "td", "th"];
+
+/// Decode the book with the encoding of its XML declaration. Many books are in windows-1251.
+fn decode(data: &[u8]) -> String {
+    let head = String::from_utf8_lossy(&data[..data.len().min(200)]);
+    let declared = head
+        .split_once("encoding=")
+        .and_then(|(_, rest)| {
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            rest[1..].split(quote).next()
+        })
+        .and_then(|label| Encoding::for_label(label.trim().as_bytes()));
+    let (text, _, _) = declared.unwrap_or(encoding_rs::UTF_8).decode(data);
+    text.into_owned()
+}
+
+/// The lines of the book as (section path, text): first the description of the book (with an empty path), then the bodies
+fn book_text(xml: &str) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    // names of the open elements
+    let mut stack: Vec<&str> = Vec::new();
+    // titles of the open sections (and the names of bodies other than the main one, e.g. notes)
+    let mut sections: Vec<Option<String>> = Vec::new();
+    let mut text = String::new();
+    // the title of the innermost section while it is read
+    let mut title: Option<String> = None;
+    let mut author: Vec<String> = Vec::new();
+    let path = |sections: &[Option<String>]| {
+        sections
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" / ")
+    };
+    for event in XmlReader::new(xml) {
+        match &event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => {
+                let name = local_name(*name);
+                if *self_closing {
+                    continue;
+                }
+                match name {
+                    "section" => sections.push(None),
+                    "body" => sections.push(event.attr("name").map(str::to_string)),
+                    "title" if stack.last() == Some(&"section") => title = Some(String::new()),
+                    "author" => author.clear(),
+                    _ => {}
+                }
+                // text outside of blocks is only whitespace or metadata values
+                if !stack.iter().any(|n| BLOCK_ELEMENTS.contains(n)) {
+                    text.clear();
+                }
+                stack.push(name);
+            }
+            XmlEvent::Text(t) => {
+                if !stack.contains(&"binary") {
+                    text.push_str(t);
+                }
+            }
+            XmlEvent::End { name } => {
+                let name = local_name(*name);
+                // tolerate unclosed elements
+                let Some(i) = stack.iter().rposition(|n| *n == name) else {
+                    continue;
+                };
+                stack.truncate(i);
+                let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                let in_title_info = stack.contains(&"title-info");
+                match name {
+                    "section" | "body" => {
+                        sections.pop();
+                    }
+                    "title" if title.is_some() => {
+                        let title = title.take().unwrap_or_default();
+                        if !title.is_empty() {
+                            out.push((path(&sections), title.clone()));
+                            if let Some(section) = sections.last_mut() {
+                                *section = Some(title);
+                            }
+                        }
+                    }
+                    "book-title" | "keywords" | "date" if in_title_info && !line.is_empty() => {
+                        let key = name.strip_prefix("book-").unwrap_or(name);
+                        out.push((String::new(), format!("{key}: {line}")));
+                    }
+                    "first-name" | "middle-name" | "last-name" | "nickname"
+                        if in_title_info && !line.is_empty() =>
+                    {
+                        author.push(line)
+                    }
+                    "author" if in_title_info && !author.is_empty() => {
+                        out.push((String::new(), format!("author: {}", author.join(" "))));
+                    }
+                    name if BLOCK_ELEMENTS.contains(&name) && !line.is_empty() => {
+                        if let Some(title) = &mut title {
+                            // titles can have several paragraphs
+                            if !title.is_empty() {
+                                title.push(' ');
+                            }
+                            title.push_str(&line);
+                        } else if stack.contains(&"description") {
+                            if stack.contains(&"annotation") && in_title_info {
+                                out.push((String::new(), format!("annotation: {line}")));
+                            }
+                        } else {
+                            out.push((path(&sections), line));
+                        }
+                    }
+                    _ => {}
+                }
+                if BLOCK_ELEMENTS.contains(&name) {
+                    text.clear();
+                }
+            }
+        }
+    }
+    out
+}
+
+#[async_trait]
+impl WritingFileAdapter for Fb2Adapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut out = String::new();
+        for (section, line) in book_text(&decode(&data)) {
+            if section.is_empty() {
+                out.push_str(&format!("{line_prefix}{line}\n"));
+            } else {
+                out.push_str(&format!("{line_prefix}{section}: {line}\n"));
+            }
+        }
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn book() -> Result<()> {
+        let book = r##"<?xml version="1.0" encoding="windows-1251"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description>
+    <title-info>
+      <genre>sf</genre>
+      <author><first-name>Jane</first-name><last-name>Doe</last-name></author>
+      <book-title>A Book</book-title>
+      <annotation><p>A <emphasis>short</emphasis> book.</p></annotation>
+      <lang>en</lang>
+    </title-info>
+    <document-info><author><nickname>converter</nickname></author></document-info>
+  </description>
+  <body>
+    <title><p>Jane Doe</p><p>A Book</p></title>
+    <section>
+      <title><p>Part 1</p></title>
+      <section>
+        <title><p>Chapter 1</p><p>The Beginning</p></title>
+        <epigraph><p>All is well.</p><text-author>Anonymous</text-author></epigraph>
+        <p>It was a dark and
+          stormy night.<a l:href="#n1" type="note">[1]</a></p>
+        <empty-line/>
+        <poem><stanza><v>Roses are red,</v><v>caf&#233; is brown,</v><v>Привет, мир</v></stanza></poem>
+      </section>
+    </section>
+  </body>
+  <body name="notes">
+    <section id="n1"><title><p>1</p></title><p>A note.</p></section>
+  </body>
+  <binary id="cover.jpg" content-type="image/jpeg">/9j/4AAQSkZJRgABAQ==</binary>
+</FictionBook>"##;
+        let data = encoding_rs::WINDOWS_1251.encode(book).0.into_owned();
+        let (a, d) = simple_adapt_info(&PathBuf::from("book.fb2"), Box::pin(Cursor::new(data)));
+        let buf = adapted_to_vec(loop_adapt(&Fb2Adapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:author: Jane Doe
+PREFIX:title: A Book
+PREFIX:annotation: A short book.
+PREFIX:Jane Doe
+PREFIX:A Book
+PREFIX:Part 1
+PREFIX:Part 1: Chapter 1 The Beginning
+PREFIX:Part 1 / Chapter 1 The Beginning: All is well.
+PREFIX:Part 1 / Chapter 1 The Beginning: Anonymous
+PREFIX:Part 1 / Chapter 1 The Beginning: It was a dark and stormy night.[1]
+PREFIX:Part 1 / Chapter 1 The Beginning: Roses are red,
+PREFIX:Part 1 / Chapter 1 The Beginning: café is brown,
+PREFIX:Part 1 / Chapter 1 The Beginning: Привет, мир
+PREFIX:notes: 1
+PREFIX:notes / 1: A note.
+"
+        );
+        Ok(())
+    }
+}
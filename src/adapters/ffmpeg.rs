@@ -5,9 +5,10 @@ use async_trait::async_trait;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::process::Stdio;
-use tokio::io::AsyncWrite;
 use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use tokio::process::Command;
 use writing::WritingFileAdapter;
 // todo:
@@ -19,11 +20,12 @@ static EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "mp3", "ogg", "flac", "webm"
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "ffmpeg".to_owned(),
-        version: 1,
+        version: 3,
         description:
-            "Uses ffmpeg to extract video metadata/chapters, subtitles, lyrics, and other metadata"
+            "Uses ffmpeg to extract video metadata/chapters, subtitles, lyrics, and other metadata.\nOutputs the tags of the container and the streams, the chapter titles with their start and end time and every text subtitle stream, prefixed with its index, language and title"
                 .to_owned(),
         recurses: false,
+        outputs_unchanged_files: false,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -50,11 +52,127 @@ impl GetMetadata for FFmpegAdapter {
 
 #[derive(Serialize, Deserialize)]
 struct FFprobeOutput {
+    #[serde(default)]
+    format: FFprobeFormat,
+    #[serde(default)]
     streams: Vec<FFprobeStream>,
+    #[serde(default)]
+    chapters: Vec<FFprobeChapter>,
+}
+#[derive(Serialize, Deserialize, Default)]
+struct FFprobeFormat {
+    format_long_name: Option<String>,
+    duration: Option<String>, // seconds
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
 }
 #[derive(Serialize, Deserialize)]
 struct FFprobeStream {
     index: i32, // stream index
+    #[serde(default)]
+    codec_type: String,
+    #[serde(default)]
+    codec_name: String,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+#[derive(Serialize, Deserialize)]
+struct FFprobeChapter {
+    start_time: String, // seconds
+    end_time: String,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+}
+
+/// Tags that Matroska muxers add to every stream, not interesting to search
+static SKIPPED_TAGS: &[&str] = &["DURATION", "NUMBER_OF_FRAMES", "NUMBER_OF_BYTES", "BPS"];
+
+/// Tag names differ in case between containers (`title` in mp4, `TITLE` in ogg)
+fn tag<'a>(tags: &'a BTreeMap<String, String>, name: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// `hh:mm:ss` from seconds as output by ffprobe
+fn format_time(seconds: &str) -> String {
+    let seconds = seconds.parse::<f64>().unwrap_or(0.0).max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// `{prefix}key = value`, with the prefix on every line of values with multiple lines (descriptions, lyrics)
+fn write_tags(tags: &BTreeMap<String, String>, prefix: &str, out: &mut String) {
+    for (key, value) in tags {
+        if SKIPPED_TAGS.contains(&key.as_str()) || key.starts_with("_STATISTICS") {
+            continue;
+        }
+        for line in value.lines().filter(|l| !l.trim().is_empty()) {
+            out.push_str(&format!("{prefix}{key} = {}\n", line.trim_end()));
+        }
+    }
+}
+
+impl FFprobeOutput {
+    /// The metadata of the container, the streams and the chapters
+    fn to_text(&self, line_prefix: &str) -> String {
+        let mut out = String::new();
+        let format = &self.format;
+        let mut summary = Vec::new();
+        if let Some(name) = &format.format_long_name {
+            summary.push(name.clone());
+        }
+        if let Some(duration) = &format.duration {
+            summary.push(format!("duration {}", format_time(duration)));
+        }
+        if !summary.is_empty() {
+            out.push_str(&format!("{line_prefix}metadata: {}\n", summary.join(", ")));
+        }
+        write_tags(&format.tags, &format!("{line_prefix}metadata: "), &mut out);
+        for stream in &self.streams {
+            let prefix = format!("{line_prefix}stream {}: ", stream.index);
+            out.push_str(&format!(
+                "{prefix}{} {}\n",
+                stream.codec_type, stream.codec_name
+            ));
+            write_tags(&stream.tags, &prefix, &mut out);
+        }
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            let title = tag(&chapter.tags, "title").unwrap_or_default().trim();
+            out.push_str(&format!(
+                "{line_prefix}chapter {} ({} - {}): {title}\n",
+                i + 1,
+                format_time(&chapter.start_time),
+                format_time(&chapter.end_time)
+            ));
+        }
+        out
+    }
+}
+
+/// Subtitles that are images, they can't be converted to text
+static BITMAP_SUBTITLE_CODECS: &[&str] =
+    &["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"];
+
+impl FFprobeStream {
+    /// Prefix for the lines of a subtitle stream, e.g. `subtitles 2 (eng, SDH)`
+    fn label(&self) -> String {
+        let details: Vec<&str> = [tag(&self.tags, "language"), tag(&self.tags, "title")]
+            .into_iter()
+            .flatten()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty() && *s != "und")
+            .collect();
+        if details.is_empty() {
+            format!("subtitles {}", self.index)
+        } else {
+            format!("subtitles {} ({})", self.index, details.join(", "))
+        }
+    }
 }
 
 #[async_trait]
@@ -80,67 +198,44 @@ impl WritingFileAdapter for FFmpegAdapter {
         }
         let inp_fname = filepath_hint;
         let spawn_fail = |e| map_exe_error(e, "ffprobe", "Make sure you have ffmpeg installed.");
-        let subtitle_streams = {
-            let probe = Command::new("ffprobe")
-                .args(vec![
-                    "-v",
-                    "error", // show all errors
-                    "-select_streams",
-                    "s", // show only subtitle streams
-                    "-of",
-                    "json", // use json as output format
-                    "-show_entries",
-                    "stream=index", // show index of subtitle streams
-                ])
-                .arg("-i")
-                .arg(&inp_fname)
-                .output()
-                .await
-                .map_err(spawn_fail)?;
-            if !probe.status.success() {
-                return Err(format_err!(
-                    "ffprobe failed: {:?}\n{}",
-                    probe.status,
-                    String::from_utf8_lossy(&probe.stderr)
-                ));
-            }
-            let p: FFprobeOutput = serde_json::from_slice(&probe.stdout)?;
-            p.streams
-        };
-        {
-            // extract file metadata (especially chapter names in a greppable format)
-            let mut probe = Command::new("ffprobe")
-                .args(vec![
-                    "-v",
-                    "error",
-                    "-show_format",
-                    "-show_streams",
-                    "-of",
-                    "flat",
-                    // "-show_data",
-                    "-show_error",
-                    "-show_programs",
-                    "-show_chapters",
-                    // "-count_frames",
-                    //"-count_packets",
-                ])
-                .arg("-i")
-                .arg(&inp_fname)
-                .stdout(Stdio::piped())
-                .spawn()?;
-            let mut lines = BufReader::new(probe.stdout.as_mut().unwrap()).lines();
-            while let Some(line) = lines.next_line().await? {
-                let line = line.replace("\\r\\n", "\n").replace("\\n", "\n"); // just unescape newlines
-                async_writeln!(oup, "metadata: {line}")?;
-            }
-            let exit = probe.wait().await?;
-            if !exit.success() {
-                return Err(format_err!("ffprobe failed: {:?}", exit));
-            }
+        // metadata of the container and the streams, chapter titles
+        let probe = Command::new("ffprobe")
+            .args([
+                "-v",
+                "error", // show all errors
+                "-of",
+                "json", // use json as output format
+                "-show_format",
+                "-show_streams",
+                "-show_chapters",
+            ])
+            .arg("-i")
+            .arg(&inp_fname)
+            .output()
+            .await
+            .map_err(spawn_fail)?;
+        if !probe.status.success() {
+            return Err(format_err!(
+                "ffprobe failed: {:?}\n{}",
+                probe.status,
+                String::from_utf8_lossy(&probe.stderr)
+            ));
         }
+        let info: FFprobeOutput = serde_json::from_slice(&probe.stdout)?;
+        oup.write_all(info.to_text(&line_prefix).as_bytes()).await?;
+        let subtitle_streams: Vec<_> = info
+            .streams
+            .iter()
+            .filter(|s| s.codec_type == "subtitle")
+            .collect();
         if !subtitle_streams.is_empty() {
             let time_re = Regex::new(r".*\d.*-->.*\d.*").unwrap();
-            for probe_stream in subtitle_streams.iter() {
+            for probe_stream in subtitle_streams {
+                if BITMAP_SUBTITLE_CODECS.contains(&probe_stream.codec_name.as_str()) {
+                    debug!("skipping bitmap subtitles in stream {}", probe_stream.index);
+                    continue;
+                }
+                let label = probe_stream.label();
                 // extract subtitles
                 let mut cmd = Command::new("ffmpeg");
                 cmd.arg("-hide_banner")
@@ -153,8 +248,8 @@ impl WritingFileAdapter for FFmpegAdapter {
                     .arg("-f")
                     .arg("webvtt")
                     .arg("-");
-                let mut cmd = cmd.stdout(Stdio::piped()).spawn().map_err(spawn_fail)?;
-                let stdo = cmd.stdout.as_mut().expect("is piped");
+                let mut child = cmd.stdout(Stdio::piped()).spawn().map_err(spawn_fail)?;
+                let stdo = child.stdout.as_mut().expect("is piped");
                 let mut time: String = "".to_owned();
                 // rewrite subtitle times so they are shown as a prefix in every line
                 let mut lines = BufReader::new(stdo).lines();
@@ -165,11 +260,74 @@ impl WritingFileAdapter for FFmpegAdapter {
                     } else if line.is_empty() {
                         async_writeln!(oup)?;
                     } else {
-                        async_writeln!(oup, "{time}: {line}")?;
+                        async_writeln!(oup, "{line_prefix}{label}: {time}: {line}")?;
                     }
                 }
+                let exit = child.wait().await?;
+                if !exit.success() {
+                    // one broken stream shouldn't hide the others
+                    warn!("ffmpeg failed to extract {label}: {exit:?}");
+                }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn subtitle_labels() -> Result<()> {
+        let probe: FFprobeOutput = serde_json::from_str(
+            r#"{"streams": [
+                {"index": 2, "codec_name": "subrip", "tags": {"language": "eng", "title": "SDH"}},
+                {"index": 3, "codec_name": "ass", "tags": {"LANGUAGE": "und"}},
+                {"index": 4}
+            ]}"#,
+        )?;
+        assert_eq!(
+            probe.streams.iter().map(|s| s.label()).collect::<Vec<_>>(),
+            ["subtitles 2 (eng, SDH)", "subtitles 3", "subtitles 4"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_and_chapters() -> Result<()> {
+        let probe: FFprobeOutput = serde_json::from_str(
+            r#"{
+                "streams": [
+                    {"index": 0, "codec_type": "video", "codec_name": "h264", "tags": {"DURATION": "00:10:34.5", "title": "Main"}},
+                    {"index": 1, "codec_type": "audio", "codec_name": "aac", "tags": {"language": "eng"}}
+                ],
+                "chapters": [
+                    {"id": 0, "start_time": "0.000000", "end_time": "312.500000", "tags": {"title": "Opening"}},
+                    {"id": 1, "start_time": "312.500000", "end_time": "3725.000000", "tags": {"title": "The Heist"}}
+                ],
+                "format": {
+                    "format_long_name": "Matroska / WebM",
+                    "duration": "3725.000000",
+                    "tags": {"title": "Big Buck Bunny", "DESCRIPTION": "A rabbit\nand three rodents"}
+                }
+            }"#,
+        )?;
+        assert_eq!(
+            probe.to_text("PREFIX:"),
+            "PREFIX:metadata: Matroska / WebM, duration 01:02:05
+PREFIX:metadata: DESCRIPTION = A rabbit
+PREFIX:metadata: DESCRIPTION = and three rodents
+PREFIX:metadata: title = Big Buck Bunny
+PREFIX:stream 0: video h264
+PREFIX:stream 0: title = Main
+PREFIX:stream 1: audio aac
+PREFIX:stream 1: language = eng
+PREFIX:chapter 1 (00:00:00 - 00:05:12): Opening
+PREFIX:chapter 2 (00:05:12 - 01:02:05): The Heist
+"
+        );
+        Ok(())
+    }
+}
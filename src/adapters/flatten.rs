@@ -0,0 +1,611 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["yaml", "yml", "toml"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "flatten".to_owned(),
+        version: 2,
+        description: "Flattens YAML and TOML files into `key.path = value` lines, so matches show the full path of the key in nested config files. Multi-line strings are output as one line per line of the string, YAML files with several documents are prefixed with `document N: `.\nDisabled by default since YAML and TOML files can be searched as they are, enable it with `--rga-adapters=+flatten`"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FlattenAdapter;
+
+impl FlattenAdapter {
+    pub fn new() -> FlattenAdapter {
+        FlattenAdapter
+    }
+}
+
+impl GetMetadata for FlattenAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// A parsed document, with the scalars as their text
+#[derive(Debug, PartialEq)]
+pub enum Node {
+    Scalar(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl From<toml::Value> for Node {
+    fn from(value: toml::Value) -> Node {
+        match value {
+            toml::Value::String(s) => Node::Scalar(s),
+            toml::Value::Array(items) => Node::Seq(items.into_iter().map(Node::from).collect()),
+            toml::Value::Table(table) => {
+                Node::Map(table.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+            other => Node::Scalar(other.to_string()),
+        }
+    }
+}
+
+/// The path of a key within its parent, quoted if it isn't a plain word
+fn key_path(parent: &str, key: &str) -> String {
+    let plain = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '/' | '$' | '@'));
+    let key = if plain {
+        key.to_string()
+    } else {
+        serde_json::Value::from(key).to_string()
+    };
+    if parent.is_empty() {
+        key
+    } else {
+        format!("{parent}.{key}")
+    }
+}
+
+/// Write `path = value` lines for all scalars of the node
+pub fn flatten(node: &Node, path: &str, line_prefix: &str, out: &mut String) {
+    let mut line = |value: &str| {
+        let line = if path.is_empty() {
+            value.to_string()
+        } else {
+            format!("{path} = {value}")
+        };
+        out.push_str(&format!("{line_prefix}{}\n", line.trim_end()));
+    };
+    match node {
+        Node::Scalar(s) if s.contains('\n') => {
+            for l in s.lines().filter(|l| !l.trim().is_empty()) {
+                line(l);
+            }
+        }
+        Node::Scalar(s) => line(s),
+        Node::Seq(items) if items.is_empty() => line("[]"),
+        Node::Map(items) if items.is_empty() => line("{}"),
+        Node::Seq(items) => {
+            for (i, item) in items.iter().enumerate() {
+                flatten(item, &format!("{path}[{i}]"), line_prefix, out);
+            }
+        }
+        Node::Map(items) => {
+            for (key, value) in items {
+                flatten(value, &key_path(path, key), line_prefix, out);
+            }
+        }
+    }
+}
+
+/// Whether a quoted scalar can start at this position of a line
+fn starts_token(prev: Option<char>) -> bool {
+    match prev {
+        None => true,
+        Some(c) => c.is_whitespace() || matches!(c, '[' | '{' | ',' | ':' | '-'),
+    }
+}
+
+/// The line without its comment and trailing whitespace
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut prev = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if (c == '"' || c == '\'') && starts_token(prev) => quote = Some(c),
+            None if c == '#' && prev.is_none_or(char::is_whitespace) => {
+                return line[..i].trim_end();
+            }
+            None => {}
+        }
+        prev = Some(c);
+    }
+    line.trim_end()
+}
+
+/// The value of a quoted or plain scalar
+fn unquote(s: &str) -> String {
+    let s = s.trim();
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        serde_json::from_str(s).unwrap_or_else(|_| s[1..s.len() - 1].to_string())
+    } else if s.len() >= 2 && s.starts_with('\'') && s.ends_with('\'') {
+        s[1..s.len() - 1].replace("''", "'")
+    } else {
+        s.to_string()
+    }
+}
+
+/// Anchors (`&name`) and tags (`!!str`) in front of a value
+fn strip_properties(mut s: &str) -> &str {
+    while s.starts_with('&') || s.starts_with('!') {
+        s = s
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest.trim_start());
+    }
+    s
+}
+
+fn is_seq_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// The key and the rest of a `key: value` line
+fn split_key(text: &str) -> Option<(String, &str)> {
+    let key_end = if text.starts_with('"') || text.starts_with('\'') {
+        let q = text.chars().next()?;
+        let close = text[1..].find(q)? + 1;
+        text[close + 1..].find(':')? + close + 1
+    } else if text.starts_with(['[', '{']) {
+        return None;
+    } else {
+        text.match_indices(':')
+            .map(|(i, _)| i)
+            .find(|i| text[i + 1..].is_empty() || text[i + 1..].starts_with(char::is_whitespace))?
+    };
+    let rest = &text[key_end + 1..];
+    if !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    let key = text[..key_end].trim_start_matches("? ");
+    Some((unquote(key), rest.trim()))
+}
+
+/// Parser for flow style collections (`[a, b]`, `{a: 1}`)
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Flow<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn scalar(&mut self, in_key: bool) -> String {
+        let rest = &self.text[self.pos..];
+        if let Some(q) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+            // the closing quote, skipping escaped ones
+            let mut end = rest.len();
+            let mut chars = rest.char_indices().skip(1).peekable();
+            while let Some((i, c)) = chars.next() {
+                if q == '"' && c == '\\' {
+                    chars.next();
+                } else if c == q && q == '\'' && chars.peek().map(|(_, c)| *c) == Some('\'') {
+                    chars.next();
+                } else if c == q {
+                    end = i + 1;
+                    break;
+                }
+            }
+            self.pos += end;
+            return unquote(&rest[..end]);
+        }
+        let end = rest
+            .char_indices()
+            .find(|&(i, c)| {
+                matches!(c, ',' | ']' | '}')
+                    || (in_key
+                        && c == ':'
+                        && (rest[i + 1..].is_empty()
+                            || rest[i + 1..].starts_with([' ', ',', '}', ']'])))
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        self.pos += end;
+        rest[..end].trim().to_string()
+    }
+
+    fn node(&mut self, depth: usize) -> Result<Node> {
+        anyhow::ensure!(depth < MAX_DEPTH, "nested too deeply");
+        self.skip_whitespace();
+        let text = strip_properties(&self.text[self.pos..]);
+        self.pos = self.text.len() - text.len();
+        match self.peek() {
+            Some(open @ ('[' | '{')) => {
+                self.pos += 1;
+                let close = if open == '[' { ']' } else { '}' };
+                let mut items = Vec::new();
+                let mut pairs = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.peek() {
+                        None => anyhow::bail!("unterminated flow collection"),
+                        Some(c) if c == close => {
+                            self.pos += 1;
+                            break;
+                        }
+                        Some(',') => {
+                            self.pos += 1;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    if open == '[' {
+                        items.push(self.node(depth + 1)?);
+                        continue;
+                    }
+                    let key = self.scalar(true);
+                    self.skip_whitespace();
+                    let value = if self.peek() == Some(':') {
+                        self.pos += 1;
+                        self.node(depth + 1)?
+                    } else {
+                        Node::Scalar(String::new())
+                    };
+                    pairs.push((key, value));
+                }
+                Ok(if open == '[' {
+                    Node::Seq(items)
+                } else {
+                    Node::Map(pairs)
+                })
+            }
+            _ => Ok(Node::Scalar(self.scalar(false))),
+        }
+    }
+}
+
+/// Maximum nesting of collections, to not overflow the stack on garbage
+const MAX_DEPTH: usize = 256;
+
+struct Line<'a> {
+    indent: usize,
+    /// The line without its indentation, including comments
+    raw: &'a str,
+}
+
+impl Line<'_> {
+    fn text(&self) -> &str {
+        strip_comment(self.raw)
+    }
+}
+
+/// Parser for the block style of YAML, which is what config files consist of
+struct Block<'a> {
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl<'a> Block<'a> {
+    fn new(lines: &[&'a str]) -> Block<'a> {
+        Block {
+            lines: lines
+                .iter()
+                .map(|l| {
+                    let raw = l.trim_start_matches(' ');
+                    Line {
+                        indent: l.len() - raw.len(),
+                        raw,
+                    }
+                })
+                .collect(),
+            pos: 0,
+        }
+    }
+
+    /// The next line with content, skipping empty and comment lines
+    fn next_line(&mut self) -> Option<&Line<'a>> {
+        while self.lines.get(self.pos)?.text().is_empty() {
+            self.pos += 1;
+        }
+        self.lines.get(self.pos)
+    }
+
+    fn node(&mut self, depth: usize) -> Result<Node> {
+        anyhow::ensure!(depth < MAX_DEPTH, "nested too deeply");
+        let Some(line) = self.next_line() else {
+            return Ok(Node::Scalar(String::new()));
+        };
+        let indent = line.indent;
+        let text = line.text();
+        if is_seq_item(text) {
+            self.seq(indent, depth)
+        } else if split_key(text).is_some() {
+            self.map(indent, depth)
+        } else {
+            let text = text.to_string();
+            self.pos += 1;
+            self.value(&text, indent, depth)
+        }
+    }
+
+    fn seq(&mut self, indent: usize, depth: usize) -> Result<Node> {
+        let mut items = Vec::new();
+        while let Some(line) = self.next_line() {
+            if line.indent != indent || !is_seq_item(line.text()) {
+                break;
+            }
+            let raw = line.raw;
+            let rest = raw[1..].trim_start_matches(' ');
+            let item_indent = indent + raw.len() - rest.len();
+            if strip_comment(rest).is_empty() {
+                self.pos += 1;
+                items.push(self.child(indent, depth)?);
+            } else {
+                // the rest of the line is the first line of the item
+                self.lines[self.pos] = Line {
+                    indent: item_indent,
+                    raw: rest,
+                };
+                items.push(self.node(depth + 1)?);
+            }
+        }
+        Ok(Node::Seq(items))
+    }
+
+    fn map(&mut self, indent: usize, depth: usize) -> Result<Node> {
+        let mut pairs = Vec::new();
+        while let Some(line) = self.next_line() {
+            if line.indent != indent {
+                break;
+            }
+            let text = line.text().to_string();
+            let Some((key, rest)) = split_key(&text) else {
+                break;
+            };
+            self.pos += 1;
+            let value = self.value(rest, indent, depth)?;
+            pairs.push((key, value));
+        }
+        Ok(Node::Map(pairs))
+    }
+
+    /// The nested node following a key or sequence item without an inline value
+    fn child(&mut self, indent: usize, depth: usize) -> Result<Node> {
+        match self.next_line() {
+            Some(line) if line.indent > indent => self.node(depth + 1),
+            // sequences may have the same indentation as their key
+            Some(line) if line.indent == indent && is_seq_item(line.text()) => {
+                self.seq(indent, depth + 1)
+            }
+            _ => Ok(Node::Scalar(String::new())),
+        }
+    }
+
+    /// The value after a key (or a top level scalar) of a line with the given indentation
+    fn value(&mut self, rest: &str, indent: usize, depth: usize) -> Result<Node> {
+        let rest = strip_properties(rest);
+        if rest.is_empty() {
+            return self.child(indent, depth);
+        }
+        if rest.starts_with(['|', '>']) {
+            // block scalar: all following lines that are indented more (or empty)
+            let mut lines = Vec::new();
+            while let Some(line) = self.lines.get(self.pos) {
+                if line.indent <= indent && !line.raw.trim().is_empty() {
+                    break;
+                }
+                lines.push(line.raw.trim());
+                self.pos += 1;
+            }
+            return Ok(Node::Scalar(lines.join("\n")));
+        }
+        // continuation lines of multi-line plain and quoted scalars and flow collections
+        let mut text = rest.to_string();
+        while let Some(line) = self.lines.get(self.pos) {
+            let raw = line.raw.trim();
+            if line.indent <= indent && !raw.is_empty() {
+                break;
+            }
+            if !raw.is_empty() {
+                text.push(' ');
+                text.push_str(if rest.starts_with(['"', '\'']) {
+                    raw
+                } else {
+                    strip_comment(raw)
+                });
+            }
+            self.pos += 1;
+        }
+        if text.starts_with(['[', '{']) {
+            Flow {
+                text: &text,
+                pos: 0,
+            }
+            .node(depth + 1)
+        } else if text.starts_with('*') {
+            // aliases aren't resolved
+            Ok(Node::Scalar(text))
+        } else {
+            Ok(Node::Scalar(unquote(&text)))
+        }
+    }
+}
+
+/// The documents of a YAML stream
+fn parse_yaml(text: &str) -> Result<Vec<Node>> {
+    let mut documents = Vec::new();
+    let mut lines: Vec<&str> = Vec::new();
+    let mut started = false;
+    for line in text.lines().chain(std::iter::once("...")) {
+        let line = line.trim_end_matches('\r');
+        let marker = line == "---" || line.starts_with("--- ") || line == "...";
+        if marker || (line.starts_with('%') && lines.is_empty()) {
+            if lines.iter().any(|l| !strip_comment(l).trim().is_empty()) || started {
+                let mut block = Block::new(&lines);
+                let node = block.node(0)?;
+                if block.next_line().is_some() {
+                    anyhow::bail!("unexpected indentation in line {}", block.pos + 1);
+                }
+                documents.push(node);
+            }
+            lines.clear();
+            started = line.starts_with("---");
+            // a value on the same line as the document start
+            if let Some(rest) = line.strip_prefix("--- ") {
+                lines.push(rest);
+            }
+            continue;
+        }
+        lines.push(line);
+    }
+    Ok(documents)
+}
+
+fn flatten_file(data: &str, is_toml: bool, line_prefix: &str) -> Result<String> {
+    let documents = if is_toml {
+        vec![Node::from(toml::Value::Table(
+            toml::from_str(data).context("invalid toml")?,
+        ))]
+    } else {
+        parse_yaml(data).context("invalid yaml")?
+    };
+    let mut out = String::new();
+    for (i, document) in documents.iter().enumerate() {
+        let prefix = if documents.len() > 1 {
+            format!("{line_prefix}document {}: ", i + 1)
+        } else {
+            line_prefix.to_string()
+        };
+        flatten(document, "", &prefix, &mut out);
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for FlattenAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let is_toml = matches!(
+            detection_reason,
+            FileMatcher::Fast(FastFileMatcher::FileExtension(ext)) if ext == "toml"
+        );
+        let out = flatten_file(&String::from_utf8_lossy(&data), is_toml, &line_prefix)?;
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[test]
+    fn yaml() -> Result<()> {
+        let yaml = r#"# deployment
+apiVersion: apps/v1
+metadata:
+  name: "web # frontend"
+  labels: {app: web, tier: 'front end'}
+spec:
+  replicas: 3  # more in production
+  containers:
+  - name: nginx
+    image: nginx:1.25
+    ports: [80, 443]
+    args:
+      - --verbose
+  - name: sidecar
+    command: >
+      run the
+      sidecar
+  empty:
+"some.key": it's here
+"#;
+        assert_eq!(
+            flatten_file(yaml, false, "PREFIX:")?,
+            r#"PREFIX:apiVersion = apps/v1
+PREFIX:metadata.name = web # frontend
+PREFIX:metadata.labels.app = web
+PREFIX:metadata.labels.tier = front end
+PREFIX:spec.replicas = 3
+PREFIX:spec.containers[0].name = nginx
+PREFIX:spec.containers[0].image = nginx:1.25
+PREFIX:spec.containers[0].ports[0] = 80
+PREFIX:spec.containers[0].ports[1] = 443
+PREFIX:spec.containers[0].args[0] = --verbose
+PREFIX:spec.containers[1].name = sidecar
+PREFIX:spec.containers[1].command = run the
+PREFIX:spec.containers[1].command = sidecar
+PREFIX:spec.empty =
+PREFIX:"some.key" = it's here
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_documents() -> Result<()> {
+        let yaml = "---\nkind: Service\n---\n- a\n- b: c\n...\n";
+        assert_eq!(
+            flatten_file(yaml, false, "")?,
+            "document 1: kind = Service\ndocument 2: [0] = a\ndocument 2: [1].b = c\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn toml() -> Result<()> {
+        let toml = r#"[package]
+name = "ripgrep_all"
+keywords = ["rga", "search"]
+
+[[bin]]
+name = "rga"
+path = "src/bin/rga.rs"
+"#;
+        let (a, d) = simple_adapt_info(&PathBuf::from("Cargo.toml"), Box::pin(Cursor::new(toml)));
+        let buf = adapted_to_vec(loop_adapt(&FlattenAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            r#"PREFIX:bin[0].name = rga
+PREFIX:bin[0].path = src/bin/rga.rs
+PREFIX:package.keywords[0] = rga
+PREFIX:package.keywords[1] = search
+PREFIX:package.name = ripgrep_all
+"#
+        );
+        Ok(())
+    }
+}
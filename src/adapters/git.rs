@@ -0,0 +1,706 @@
+use super::*;
+use crate::adapted_iter::AdaptedFilesIterBox;
+use anyhow::Result;
+use async_compression::tokio::bufread::ZlibDecoder;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["pack"];
+/// loose objects are stored in a directory named after the first two hex digits of their id
+const LOOSE_OBJECTS: &str = "**/objects/[0-9a-f][0-9a-f]/*";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "git".to_owned(),
+        version: 1,
+        description: "Reads git packfiles and loose objects, e.g. of bare repositories, and recurses into the blobs they contain. Each blob is prefixed with its abbreviated id and, if a commit in the same file references it, its path (`3b18e51:docs/readme.txt: `).\nLoose objects are only found with `--rga-accurate`"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .chain([FastFileMatcher::PathGlob(LOOSE_OBJECTS.to_owned())])
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GitAdapter;
+
+impl GitAdapter {
+    pub fn new() -> GitAdapter {
+        GitAdapter
+    }
+}
+
+impl GetMetadata for GitAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+pub type ObjectId = [u8; 20];
+
+/// Length of the abbreviated object ids in the prefixes
+pub const SHORT_ID_LENGTH: usize = 7;
+/// Longer delta chains are considered broken (git's default maximum is 50)
+const MAX_DELTA_DEPTH: usize = 1000;
+/// Resolved objects are cached so delta bases don't need to be resolved again
+const CACHE_SIZE: usize = 64 * 1024 * 1024;
+pub const MODE_TREE: u32 = 0o40000;
+pub const MODE_SUBMODULE: u32 = 0o160000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectKind {
+    fn name(self) -> &'static str {
+        match self {
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tag => "tag",
+        }
+    }
+
+    fn from_name(name: &[u8]) -> Option<ObjectKind> {
+        [
+            ObjectKind::Commit,
+            ObjectKind::Tree,
+            ObjectKind::Blob,
+            ObjectKind::Tag,
+        ]
+        .into_iter()
+        .find(|k| k.name().as_bytes() == name)
+    }
+}
+
+pub struct Object {
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+}
+
+pub fn hex(id: &ObjectId) -> String {
+    id.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn parse_hex(s: &[u8]) -> Option<ObjectId> {
+    let mut id = [0; 20];
+    if s.len() != 40 {
+        return None;
+    }
+    for (b, pair) in id.iter_mut().zip(s.chunks(2)) {
+        *b = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(id)
+}
+
+fn sha1_block(h: &mut [u32; 5], block: &[u8]) {
+    let mut w = [0u32; 80];
+    for (i, word) in w.iter_mut().take(16).enumerate() {
+        *word = u32::from_be_bytes([
+            block[4 * i],
+            block[4 * i + 1],
+            block[4 * i + 2],
+            block[4 * i + 3],
+        ]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = *h;
+    for (i, word) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5A827999),
+            20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+            _ => (b ^ c ^ d, 0xCA62C1D6),
+        };
+        let t = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*word);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = t;
+    }
+    for (x, y) in h.iter_mut().zip([a, b, c, d, e]) {
+        *x = x.wrapping_add(y);
+    }
+}
+
+/// SHA-1 of the concatenated parts
+fn sha1(parts: &[&[u8]]) -> ObjectId {
+    let mut h = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    let padding = [0x80u8];
+    let zeros = [0u8; 64];
+    let bits = (len as u64 * 8).to_be_bytes();
+    let zero_count = (119 - len % 64) % 64;
+    let mut buf = Vec::with_capacity(64);
+    for part in parts
+        .iter()
+        .copied()
+        .chain([&padding[..], &zeros[..zero_count], &bits[..]])
+    {
+        let mut part = part;
+        if !buf.is_empty() {
+            let n = part.len().min(64 - buf.len());
+            buf.extend_from_slice(&part[..n]);
+            part = &part[n..];
+            if buf.len() < 64 {
+                continue;
+            }
+            sha1_block(&mut h, &buf);
+            buf.clear();
+        }
+        let mut blocks = part.chunks_exact(64);
+        for block in &mut blocks {
+            sha1_block(&mut h, block);
+        }
+        buf.extend_from_slice(blocks.remainder());
+    }
+    let mut id = [0; 20];
+    for (chunk, word) in id.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    id
+}
+
+pub fn object_id(kind: ObjectKind, data: &[u8]) -> ObjectId {
+    let header = format!("{} {}\0", kind.name(), data.len());
+    sha1(&[header.as_bytes(), data])
+}
+
+/// Inflates the zlib stream at the start of `data`, returning the inflated data and the length of the stream
+pub async fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut rest = data;
+    let mut out = Vec::new();
+    ZlibDecoder::new(&mut rest)
+        .read_to_end(&mut out)
+        .await
+        .context("invalid zlib data")?;
+    Ok((out, data.len() - rest.len()))
+}
+
+/// A loose object: a header with the kind and size, and the data
+pub async fn parse_loose_object(raw: &[u8]) -> Result<Object> {
+    let (data, _) = inflate(raw).await?;
+    let nul = memchr::memchr(0, &data).context("invalid object header")?;
+    let kind = data[..nul]
+        .split(|b| *b == b' ')
+        .next()
+        .and_then(ObjectKind::from_name)
+        .context("unknown object kind")?;
+    Ok(Object {
+        kind,
+        data: data[nul + 1..].to_vec(),
+    })
+}
+
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub id: ObjectId,
+}
+
+pub fn parse_tree(data: &[u8]) -> Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = memchr::memchr(b' ', rest).context("invalid tree entry")?;
+        let mode = u32::from_str_radix(std::str::from_utf8(&rest[..space])?, 8)?;
+        let nul = memchr::memchr(0, rest).context("invalid tree entry")?;
+        let id = rest.get(nul + 1..nul + 21).context("truncated tree")?;
+        entries.push(TreeEntry {
+            mode,
+            name: String::from_utf8_lossy(&rest[space + 1..nul]).into_owned(),
+            id: id.try_into()?,
+        });
+        rest = &rest[nul + 21..];
+    }
+    Ok(entries)
+}
+
+/// The root tree of a commit, from its first header line
+pub fn commit_tree(data: &[u8]) -> Option<ObjectId> {
+    parse_hex(data.strip_prefix(b"tree ")?.get(..40)?)
+}
+
+fn next_byte(data: &[u8], pos: &mut usize) -> Result<u8> {
+    let b = *data.get(*pos).context("unexpected end of data")?;
+    *pos += 1;
+    Ok(b)
+}
+
+/// Applies a delta, which copies ranges of the base and inserts new data
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let mut size = || -> Result<usize> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let b = next_byte(delta, &mut pos)?;
+            value |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        Ok(value)
+    };
+    let base_size = size()?;
+    let result_size = size()?;
+    anyhow::ensure!(base_size == base.len(), "delta base has the wrong size");
+    let mut out = Vec::with_capacity(result_size.min(CACHE_SIZE));
+    while pos < delta.len() {
+        let op = next_byte(delta, &mut pos)?;
+        if op & 0x80 != 0 {
+            // the bytes of the offset and size that are present
+            let mut offset = 0;
+            let mut len = 0;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (next_byte(delta, &mut pos)? as usize) << (8 * i);
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    len |= (next_byte(delta, &mut pos)? as usize) << (8 * i);
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            let copied = base
+                .get(offset..offset.saturating_add(len))
+                .context("invalid delta copy")?;
+            out.extend_from_slice(copied);
+        } else if op != 0 {
+            let inserted = delta
+                .get(pos..pos + op as usize)
+                .context("truncated delta")?;
+            out.extend_from_slice(inserted);
+            pos += inserted.len();
+        } else {
+            anyhow::bail!("invalid delta instruction");
+        }
+    }
+    anyhow::ensure!(out.len() == result_size, "delta result has the wrong size");
+    Ok(out)
+}
+
+enum Base {
+    None(ObjectKind),
+    Offset(usize),
+    Id(ObjectId),
+}
+
+struct PackEntry {
+    offset: usize,
+    base: Base,
+    /// start of the compressed data
+    data_start: usize,
+}
+
+/// The header of the pack entry at `offset`: the type and size, and the delta base for deltas
+fn pack_entry(data: &[u8], offset: usize) -> Result<PackEntry> {
+    let mut pos = offset;
+    let mut b = next_byte(data, &mut pos)?;
+    let kind = (b >> 4) & 7;
+    // the inflated size, which is not needed
+    while b & 0x80 != 0 {
+        b = next_byte(data, &mut pos)?;
+    }
+    let base = match kind {
+        1 => Base::None(ObjectKind::Commit),
+        2 => Base::None(ObjectKind::Tree),
+        3 => Base::None(ObjectKind::Blob),
+        4 => Base::None(ObjectKind::Tag),
+        6 => {
+            // distance to the base, with an offset added to each continuation byte
+            let mut b = next_byte(data, &mut pos)?;
+            let mut distance = (b & 0x7f) as usize;
+            while b & 0x80 != 0 {
+                b = next_byte(data, &mut pos)?;
+                distance = ((distance + 1) << 7) | (b & 0x7f) as usize;
+            }
+            Base::Offset(offset.checked_sub(distance).context("invalid delta base")?)
+        }
+        7 => {
+            let id = data.get(pos..pos + 20).context("truncated pack")?;
+            pos += 20;
+            Base::Id(id.try_into()?)
+        }
+        t => anyhow::bail!("invalid object type {t}"),
+    };
+    Ok(PackEntry {
+        offset,
+        base,
+        data_start: pos,
+    })
+}
+
+/// The objects of a packfile, with the trees and the root trees of the commits to find the paths of blobs
+pub struct Pack {
+    data: Vec<u8>,
+    entries: Vec<PackEntry>,
+    by_id: HashMap<ObjectId, usize>,
+    cache: HashMap<usize, Arc<Object>>,
+    cached_bytes: usize,
+    /// the index of the entry, kind and id of each resolved object, in the order of the pack
+    pub objects: Vec<(usize, ObjectKind, ObjectId)>,
+    pub trees: HashMap<ObjectId, Vec<TreeEntry>>,
+    pub roots: Vec<ObjectId>,
+}
+
+impl Pack {
+    /// Reads all objects of the pack. Deltas whose base is not in the pack (thin packs) are skipped
+    pub async fn read(data: Vec<u8>) -> Result<Pack> {
+        anyhow::ensure!(data.starts_with(b"PACK"), "not a git packfile");
+        let header = data.get(4..12).context("truncated pack")?;
+        let version = u32::from_be_bytes(header[..4].try_into()?);
+        anyhow::ensure!(
+            version == 2 || version == 3,
+            "unsupported pack version {version}"
+        );
+        let count = u32::from_be_bytes(header[4..].try_into()?);
+        let mut pack = Pack {
+            data,
+            entries: Vec::new(),
+            by_id: HashMap::new(),
+            cache: HashMap::new(),
+            cached_bytes: 0,
+            objects: Vec::new(),
+            trees: HashMap::new(),
+            roots: Vec::new(),
+        };
+        let mut pending = Vec::new();
+        let mut pos = 12;
+        for _ in 0..count {
+            let entry = pack_entry(&pack.data, pos)?;
+            let (inflated, len) = inflate(&pack.data[entry.data_start..]).await?;
+            pos = entry.data_start + len;
+            pack.entries.push(entry);
+            let index = pack.entries.len() - 1;
+            match pack.resolve(index, Some(inflated)).await? {
+                Some(object) => pack.add(index, &object)?,
+                None => pending.push(index),
+            }
+        }
+        // deltas with bases later in the pack
+        while !pending.is_empty() {
+            let mut missing = Vec::new();
+            for &index in &pending {
+                match pack.resolve(index, None).await? {
+                    Some(object) => pack.add(index, &object)?,
+                    None => missing.push(index),
+                }
+            }
+            if missing.len() == pending.len() {
+                debug!("{} deltas with bases outside of the pack", missing.len());
+                break;
+            }
+            pending = missing;
+        }
+        Ok(pack)
+    }
+
+    fn add(&mut self, index: usize, object: &Object) -> Result<()> {
+        let id = object_id(object.kind, &object.data);
+        self.by_id.insert(id, index);
+        self.objects.push((index, object.kind, id));
+        match object.kind {
+            ObjectKind::Tree => {
+                self.trees.insert(id, parse_tree(&object.data)?);
+            }
+            ObjectKind::Commit => self.roots.extend(commit_tree(&object.data)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, offset: usize, object: Arc<Object>) {
+        if self.cached_bytes > CACHE_SIZE {
+            self.cache.clear();
+            self.cached_bytes = 0;
+        }
+        self.cached_bytes += object.data.len();
+        self.cache.insert(offset, object);
+    }
+
+    /// The object of an entry, applying its deltas. None if a delta base is not (yet) known
+    pub async fn resolve(
+        &mut self,
+        index: usize,
+        mut inflated: Option<Vec<u8>>,
+    ) -> Result<Option<Arc<Object>>> {
+        let mut deltas = Vec::new();
+        let mut i = index;
+        let mut object = loop {
+            let entry = &self.entries[i];
+            if let Some(object) = self.cache.get(&entry.offset) {
+                break object.clone();
+            }
+            let data = match inflated.take() {
+                Some(data) => data,
+                None => inflate(&self.data[entry.data_start..]).await?.0,
+            };
+            let base = match entry.base {
+                Base::None(kind) => {
+                    let object = Arc::new(Object { kind, data });
+                    self.remember(self.entries[i].offset, object.clone());
+                    break object;
+                }
+                Base::Offset(offset) => self
+                    .entries
+                    .binary_search_by_key(&offset, |e| e.offset)
+                    .ok()
+                    .context("invalid delta base offset")?,
+                Base::Id(id) => match self.by_id.get(&id) {
+                    Some(base) => *base,
+                    None => return Ok(None),
+                },
+            };
+            deltas.push((entry.offset, data));
+            anyhow::ensure!(deltas.len() <= MAX_DELTA_DEPTH, "delta chain too long");
+            i = base;
+        };
+        for (offset, delta) in deltas.into_iter().rev() {
+            object = Arc::new(Object {
+                kind: object.kind,
+                data: apply_delta(&object.data, &delta)?,
+            });
+            self.remember(offset, object.clone());
+        }
+        Ok(Some(object))
+    }
+
+    /// The object with the given id, if it is in the pack
+    pub async fn get(&mut self, id: &ObjectId) -> Result<Option<Arc<Object>>> {
+        match self.by_id.get(id) {
+            Some(&index) => self.resolve(index, None).await,
+            None => Ok(None),
+        }
+    }
+
+    /// The path of each blob in the first tree it is found in, with the trees of the newest commits first
+    pub fn blob_paths(&self) -> HashMap<ObjectId, String> {
+        let mut paths = HashMap::new();
+        let mut visited = HashSet::new();
+        for root in &self.roots {
+            let mut stack = vec![(*root, String::new())];
+            while let Some((tree, dir)) = stack.pop() {
+                if !visited.insert(tree) {
+                    continue;
+                }
+                let Some(entries) = self.trees.get(&tree) else {
+                    continue;
+                };
+                for entry in entries {
+                    let path = if dir.is_empty() {
+                        entry.name.clone()
+                    } else {
+                        format!("{dir}/{}", entry.name)
+                    };
+                    match entry.mode {
+                        MODE_TREE => stack.push((entry.id, path)),
+                        MODE_SUBMODULE => {}
+                        _ => {
+                            paths.entry(entry.id).or_insert(path);
+                        }
+                    }
+                }
+            }
+        }
+        paths
+    }
+}
+
+#[async_trait]
+impl FileAdapter for GitAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            passwords,
+            postprocess,
+            ..
+        } = ai;
+        let s = stream! {
+            let mut data = Vec::new();
+            inp.read_to_end(&mut data).await?;
+            let blob = |id: &ObjectId, path: Option<&String>, data: Vec<u8>| {
+                let short = &hex(id)[..SHORT_ID_LENGTH];
+                let (filepath_hint, line_prefix) = match path {
+                    Some(path) => (PathBuf::from(path), format!("{line_prefix}{short}:{path}: ")),
+                    None => (PathBuf::from(short), format!("{line_prefix}{short}: ")),
+                };
+                AdaptInfo {
+                    filepath_hint,
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(Cursor::new(data)),
+                    line_prefix,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                    postprocess,
+                }
+            };
+            if !data.starts_with(b"PACK") {
+                let object = parse_loose_object(&data).await?;
+                if object.kind == ObjectKind::Blob {
+                    yield Ok(blob(&object_id(object.kind, &object.data), None, object.data));
+                }
+                return;
+            }
+            let mut pack = Pack::read(data).await?;
+            let paths = pack.blob_paths();
+            let blobs: Vec<_> = pack
+                .objects
+                .iter()
+                .filter(|(_, kind, _)| *kind == ObjectKind::Blob)
+                .map(|(index, _, id)| (*index, *id))
+                .collect();
+            for (index, id) in blobs {
+                let object = pack.resolve(index, None).await?.context("blob disappeared")?;
+                yield Ok(blob(&id, paths.get(&id), object.data.clone()));
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{matching::matches_path_glob, preproc::loop_adapt, test_utils::*};
+    use async_compression::tokio::bufread::ZlibEncoder;
+    use pretty_assertions::assert_eq;
+
+    async fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        ZlibEncoder::new(data).read_to_end(&mut out).await?;
+        Ok(out)
+    }
+
+    /// Appends a pack entry with a header for objects smaller than 16 bytes or their size in a second byte
+    async fn entry(
+        pack: &mut Vec<u8>,
+        kind: u8,
+        data: &[u8],
+        base_distance: Option<u8>,
+    ) -> Result<()> {
+        if data.len() < 16 {
+            pack.push((kind << 4) | data.len() as u8);
+        } else {
+            pack.push(0x80 | (kind << 4) | (data.len() & 15) as u8);
+            pack.push((data.len() >> 4) as u8);
+        }
+        pack.extend(base_distance);
+        pack.extend(deflate(data).await?);
+        Ok(())
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(
+            hex(&sha1(&[b"abc"])),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&sha1(&[&[b'a'; 600][..], &[b'a'; 400][..]])),
+            "291e9a6c66994949b57ba5e650361e98fc36b1ba"
+        );
+    }
+
+    #[tokio::test]
+    async fn pack() -> Result<()> {
+        let readme = b"hello world\n";
+        let readme_id = object_id(ObjectKind::Blob, readme);
+        let docs = [b"100644 readme.txt\0".as_slice(), &readme_id].concat();
+        let root = [
+            b"40000 docs\0".as_slice(),
+            &object_id(ObjectKind::Tree, &docs),
+        ]
+        .concat();
+        let commit = format!(
+            "tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\ninitial\n",
+            hex(&object_id(ObjectKind::Tree, &root))
+        );
+
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x05".to_vec();
+        entry(&mut pack, 1, commit.as_bytes(), None).await?;
+        entry(&mut pack, 2, &root, None).await?;
+        entry(&mut pack, 2, &docs, None).await?;
+        let readme_offset = pack.len();
+        entry(&mut pack, 3, readme, None).await?;
+        // "hello rga\n" as a delta of the readme: copy 6 bytes from offset 0, insert 4 bytes
+        let distance = (pack.len() - readme_offset) as u8;
+        entry(&mut pack, 6, b"\x0c\x0a\x90\x06\x04rga\n", Some(distance)).await?;
+        pack.extend([0; 20]);
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("pack-1234.pack"),
+            Box::pin(Cursor::new(pack)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&GitAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:3b18e51:docs/readme.txt: hello world\nPREFIX:3b18e51:docs/readme.txt: \nPREFIX:3eeb72e: hello rga\nPREFIX:3eeb72e: \n"
+        );
+
+        let loose = deflate(b"blob 12\0hello world\n").await?;
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("repo.git/objects/3b/18e512dba79e4c8300dd08aeb37f8e728b8dad"),
+            Box::pin(Cursor::new(loose)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&GitAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:3b18e51: hello world\nPREFIX:3b18e51: \n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn loose_objects() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let objects = dir.path().join("repo.git/objects");
+        std::fs::create_dir_all(objects.join("3b"))?;
+        std::fs::create_dir_all(objects.join("pack"))?;
+        let loose = objects.join("3b/18e512dba79e4c8300dd08aeb37f8e728b8dad");
+        let index = objects.join("pack/pack-1234.idx");
+        std::fs::write(&loose, "")?;
+        std::fs::write(&index, "")?;
+        assert!(matches_path_glob(LOOSE_OBJECTS, &loose));
+        assert!(!matches_path_glob(LOOSE_OBJECTS, &index));
+        // not selected by the pre-glob, only found with --rga-accurate
+        let adapter: Arc<dyn FileAdapter> = Arc::new(GitAdapter::new());
+        assert!(!found_by(adapter.clone(), &loose, false)?);
+        assert!(found_by(adapter, &loose, true)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["json", "ndjson", "jsonl", "geojson"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "gron".to_owned(),
+        version: 1,
+        description: "Flattens JSON into one `json.path.to.key = value` line per value like gron, so a match in a large (minified) JSON file shows where the value is. Files with several values (NDJSON, JSON lines) are output as `json[N]...`. Object keys are sorted"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/json".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GronAdapter;
+
+impl GronAdapter {
+    pub fn new() -> GronAdapter {
+        GronAdapter
+    }
+}
+
+impl GetMetadata for GronAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// The path of a key within its parent, `.key` for identifiers and `["key"]` otherwise
+fn key_path(parent: &str, key: &str) -> String {
+    let identifier = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$');
+    if identifier {
+        format!("{parent}.{key}")
+    } else {
+        format!("{parent}[{}]", Value::from(key))
+    }
+}
+
+/// Write a `path = value` line for every scalar and empty container of the value
+fn gron(value: &Value, path: &str, line_prefix: &str, out: &mut String) {
+    match value {
+        Value::Array(items) if !items.is_empty() => {
+            for (i, item) in items.iter().enumerate() {
+                gron(item, &format!("{path}[{i}]"), line_prefix, out);
+            }
+        }
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                gron(value, &key_path(path, key), line_prefix, out);
+            }
+        }
+        value => out.push_str(&format!("{line_prefix}{path} = {value}\n")),
+    }
+}
+
+fn gron_file(data: &[u8], is_stream: bool, line_prefix: &str) -> Result<String> {
+    let values = serde_json::Deserializer::from_slice(data)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid json")?;
+    let mut out = String::new();
+    for (i, value) in values.iter().enumerate() {
+        let path = if is_stream || values.len() > 1 {
+            format!("json[{i}]")
+        } else {
+            "json".to_string()
+        };
+        gron(value, &path, line_prefix, &mut out);
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for GronAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let is_stream = matches!(detection_reason, FileMatcher::Fast(FastFileMatcher::FileExtension(ext)) if ext == "ndjson" || ext == "jsonl");
+        let out = gron_file(&data, is_stream, &line_prefix)?;
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn nested() -> Result<()> {
+        let json = r#"{"name":"rga","authors":[{"name":"phiresky","email":null}],"keywords":[],"my-key":{"a.b":1.5,"ok":true}}"#;
+        let (a, d) = simple_adapt_info(&PathBuf::from("package.json"), Box::pin(Cursor::new(json)));
+        let buf = adapted_to_vec(loop_adapt(&GronAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            r#"PREFIX:json.authors[0].email = null
+PREFIX:json.authors[0].name = "phiresky"
+PREFIX:json.keywords = []
+PREFIX:json["my-key"]["a.b"] = 1.5
+PREFIX:json["my-key"].ok = true
+PREFIX:json.name = "rga"
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lines() -> Result<()> {
+        assert_eq!(
+            gron_file(b"{\"id\":1}\n{\"id\":2}\n", false, "")?,
+            "json[0].id = 1\njson[1].id = 2\n"
+        );
+        assert_eq!(
+            gron_file(b"\"single\"\n", true, "")?,
+            "json[0] = \"single\"\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1075 @@
+//! Reading of the structure of HDF5 files: groups, datasets, attributes and small string datasets.
+//!
+//! Supports groups with symbol tables (the default of the HDF5 library) and compact link storage.
+//! Groups and attributes in dense storage (fractal heaps, used for many links with `libver=latest`) are skipped.
+
+use super::sevenzip::archive_on_disk;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["h5", "hdf5", "he5", "nc4"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "hdf5".to_owned(),
+        version: 2,
+        description: "Reads HDF5 files (also NetCDF-4). Outputs the groups and datasets with their type and shape (`/run1/temperature: dataset float64 [100, 3]`), their attributes (`/run1 @ operator = Ada`) and the values of small string datasets and MATLAB char arrays"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-hdf".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct Hdf5Adapter;
+
+impl Hdf5Adapter {
+    pub fn new() -> Hdf5Adapter {
+        Hdf5Adapter
+    }
+}
+
+impl GetMetadata for Hdf5Adapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const SIGNATURE: &[u8] = b"\x89HDF\r\n\x1a\n";
+/// Groups nested deeper than this are broken (or cyclic)
+const MAX_DEPTH: usize = 64;
+/// Larger blocks are not read, the file is probably broken
+const MAX_READ: usize = 64 << 20;
+/// String datasets and MATLAB char arrays with more data are not output
+const MAX_STRING_DATA: usize = 64 << 10;
+/// Number of values of numeric attributes that are output
+const MAX_VALUES: usize = 16;
+
+// object header message types
+const DATASPACE: u16 = 0x01;
+const LINK_INFO: u16 = 0x02;
+const DATATYPE: u16 = 0x03;
+const LINK: u16 = 0x06;
+const DATA_LAYOUT: u16 = 0x08;
+const ATTRIBUTE: u16 = 0x0c;
+const CONTINUATION: u16 = 0x10;
+const SYMBOL_TABLE: u16 = 0x11;
+const ATTRIBUTE_INFO: u16 = 0x15;
+
+// datatype classes
+const FIXED_POINT: u8 = 0;
+const FLOATING_POINT: u8 = 1;
+const STRING: u8 = 3;
+const VARIABLE_LENGTH: u8 = 9;
+
+/// Reads the little endian fields of a block
+struct Fields<'a> {
+    data: &'a [u8],
+    pos: usize,
+    offset_size: usize,
+    length_size: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let b = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .context("truncated HDF5 structure")?;
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn uint(&mut self, n: usize) -> Result<u64> {
+        Ok(self
+            .bytes(n)?
+            .iter()
+            .rev()
+            .fold(0, |v, b| (v << 8) | *b as u64))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(self.uint(4)? as u32)
+    }
+
+    fn offset(&mut self) -> Result<u64> {
+        self.uint(self.offset_size)
+    }
+
+    fn length(&mut self) -> Result<u64> {
+        self.uint(self.length_size)
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+}
+
+/// The class, bit field and size of a datatype message
+struct Datatype {
+    class: u8,
+    bits: u32,
+    size: usize,
+}
+
+impl Datatype {
+    fn parse(data: &[u8]) -> Result<Datatype> {
+        let header = data.get(..8).context("truncated datatype")?;
+        Ok(Datatype {
+            class: header[0] & 0x0f,
+            bits: u32::from_le_bytes([header[1], header[2], header[3], 0]),
+            size: u32::from_le_bytes(header[4..8].try_into()?) as usize,
+        })
+    }
+
+    fn is_string(&self) -> bool {
+        self.class == STRING || (self.class == VARIABLE_LENGTH && self.bits & 0x0f == 1)
+    }
+
+    fn name(&self) -> String {
+        match self.class {
+            FIXED_POINT if self.bits & 0x08 != 0 => format!("int{}", self.size * 8),
+            FIXED_POINT => format!("uint{}", self.size * 8),
+            FLOATING_POINT => format!("float{}", self.size * 8),
+            2 => "time".to_owned(),
+            4 => "bitfield".to_owned(),
+            5 => "opaque".to_owned(),
+            6 => "compound".to_owned(),
+            7 => "reference".to_owned(),
+            8 => "enum".to_owned(),
+            _ if self.is_string() => "string".to_owned(),
+            VARIABLE_LENGTH => "vlen".to_owned(),
+            10 => "array".to_owned(),
+            class => format!("class {class}"),
+        }
+    }
+}
+
+enum Link {
+    Hard(u64),
+    Soft(String),
+}
+
+struct Object {
+    messages: Vec<(u16, Vec<u8>)>,
+}
+
+impl Object {
+    fn message(&self, kind: u16) -> Option<&[u8]> {
+        self.messages
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, d)| d.as_slice())
+    }
+}
+
+struct Hdf5<R> {
+    file: R,
+    base: u64,
+    offset_size: usize,
+    length_size: usize,
+    global_heaps: HashMap<u64, Vec<u8>>,
+    visited: HashSet<u64>,
+}
+
+impl<R: Read + Seek> Hdf5<R> {
+    /// The file and the address of the object header of the root group
+    fn open(mut file: R) -> Result<(Hdf5<R>, u64)> {
+        let len = file.seek(SeekFrom::End(0))?;
+        // the superblock is at 0 or after a user block of 512, 1024, 2048, ... bytes
+        let mut start = 0;
+        let mut superblock = vec![0; 96];
+        loop {
+            anyhow::ensure!(start < len, "not an HDF5 file");
+            file.seek(SeekFrom::Start(start))?;
+            let n = file.by_ref().take(96).read(&mut superblock)?;
+            if superblock[..n].starts_with(SIGNATURE) {
+                break;
+            }
+            start = if start == 0 { 512 } else { start * 2 };
+        }
+        let version = superblock[8];
+        let mut hdf5 = Hdf5 {
+            file,
+            base: 0,
+            offset_size: 8,
+            length_size: 8,
+            global_heaps: HashMap::new(),
+            visited: HashSet::new(),
+        };
+        let (sizes_at, fields_at) = match version {
+            0 => (13, 24),
+            // with the indexed storage K
+            1 => (13, 28),
+            2 | 3 => (9, 12),
+            v => anyhow::bail!("unsupported HDF5 superblock version {v}"),
+        };
+        hdf5.offset_size = superblock[sizes_at] as usize;
+        hdf5.length_size = superblock[sizes_at + 1] as usize;
+        anyhow::ensure!(
+            matches!(hdf5.offset_size, 2 | 4 | 8) && matches!(hdf5.length_size, 2 | 4 | 8),
+            "invalid HDF5 superblock"
+        );
+        let mut f = hdf5.fields(&superblock);
+        f.pos = fields_at;
+        hdf5.base = f.offset()?;
+        let root = if version < 2 {
+            // free-space info, end of file and driver info addresses, then the symbol table entry of the root group
+            f.bytes(3 * hdf5.offset_size)?;
+            f.offset()?;
+            f.offset()?
+        } else {
+            // superblock extension and end of file addresses
+            f.bytes(2 * hdf5.offset_size)?;
+            f.offset()?
+        };
+        Ok((hdf5, root))
+    }
+
+    fn fields<'a>(&self, data: &'a [u8]) -> Fields<'a> {
+        Fields {
+            data,
+            pos: 0,
+            offset_size: self.offset_size,
+            length_size: self.length_size,
+        }
+    }
+
+    fn read_at(&mut self, address: u64, len: usize) -> Result<Vec<u8>> {
+        anyhow::ensure!(len <= MAX_READ, "invalid HDF5 block size {len}");
+        let mut data = vec![0; len];
+        self.file.seek(SeekFrom::Start(
+            self.base.checked_add(address).context("invalid address")?,
+        ))?;
+        self.file
+            .read_exact(&mut data)
+            .with_context(|| format!("reading {len} bytes at {address}"))?;
+        Ok(data)
+    }
+
+    /// Messages of an object header, following continuation messages
+    fn object(&mut self, address: u64) -> Result<Object> {
+        let prefix = self.read_at(address, 16)?;
+        let mut messages = Vec::new();
+        let mut blocks = Vec::new();
+        let v2 = prefix.starts_with(b"OHDR");
+        if v2 {
+            let flags = prefix[5];
+            let mut start = 6;
+            if flags & 0x20 != 0 {
+                // times
+                start += 16;
+            }
+            if flags & 0x10 != 0 {
+                // attribute storage phase change values
+                start += 4;
+            }
+            let size_len = 1 << (flags & 0x03);
+            let header = self.read_at(address, start + size_len)?;
+            let size = self.fields(&header[start..]).uint(size_len)? as usize;
+            let chunk = self.read_at(address + (start + size_len) as u64, size)?;
+            self.parse_messages_v2(&chunk, flags, &mut messages, &mut blocks)?;
+        } else {
+            anyhow::ensure!(prefix[0] == 1, "invalid object header at {address}");
+            let size = u32::from_le_bytes(prefix[8..12].try_into()?) as usize;
+            let chunk = self.read_at(address + 16, size)?;
+            self.parse_messages_v1(&chunk, &mut messages, &mut blocks)?;
+        }
+        let mut followed = 0;
+        while let Some((address, len)) = blocks.pop() {
+            followed += 1;
+            anyhow::ensure!(followed < 1000, "too many object header continuations");
+            let block = self.read_at(address, len as usize)?;
+            if v2 {
+                anyhow::ensure!(
+                    block.starts_with(b"OCHK"),
+                    "invalid object header continuation"
+                );
+                let flags = prefix[5];
+                let end = block.len().saturating_sub(4);
+                self.parse_messages_v2(&block[4..end], flags, &mut messages, &mut blocks)?;
+            } else {
+                self.parse_messages_v1(&block, &mut messages, &mut blocks)?;
+            }
+        }
+        Ok(Object { messages })
+    }
+
+    fn add_message(
+        &self,
+        kind: u16,
+        data: &[u8],
+        messages: &mut Vec<(u16, Vec<u8>)>,
+        blocks: &mut Vec<(u64, u64)>,
+    ) -> Result<()> {
+        if kind == CONTINUATION {
+            let mut f = self.fields(data);
+            blocks.push((f.offset()?, f.length()?));
+        } else {
+            messages.push((kind, data.to_vec()));
+        }
+        Ok(())
+    }
+
+    fn parse_messages_v1(
+        &self,
+        chunk: &[u8],
+        messages: &mut Vec<(u16, Vec<u8>)>,
+        blocks: &mut Vec<(u64, u64)>,
+    ) -> Result<()> {
+        let mut f = self.fields(chunk);
+        while f.remaining() >= 8 {
+            let kind = f.u16()?;
+            let size = f.u16()? as usize;
+            // flags and reserved
+            f.bytes(4)?;
+            let data = f.bytes(size)?;
+            self.add_message(kind, data, messages, blocks)?;
+        }
+        Ok(())
+    }
+
+    fn parse_messages_v2(
+        &self,
+        chunk: &[u8],
+        header_flags: u8,
+        messages: &mut Vec<(u16, Vec<u8>)>,
+        blocks: &mut Vec<(u64, u64)>,
+    ) -> Result<()> {
+        let tracks_order = header_flags & 0x04 != 0;
+        let header_size = if tracks_order { 6 } else { 4 };
+        let mut f = self.fields(chunk);
+        // the chunk can end with a gap smaller than a message header
+        while f.remaining() >= header_size {
+            let kind = f.u8()? as u16;
+            let size = f.u16()? as usize;
+            f.u8()?;
+            if tracks_order {
+                f.u16()?;
+            }
+            let data = f.bytes(size)?;
+            self.add_message(kind, data, messages, blocks)?;
+        }
+        Ok(())
+    }
+
+    /// The dimensions of a dataspace message, None for a null dataspace (no elements)
+    fn dataspace(&self, data: &[u8]) -> Result<Option<Vec<u64>>> {
+        let mut f = self.fields(data);
+        let version = f.u8()?;
+        let rank = f.u8()? as usize;
+        f.u8()?;
+        if version == 1 {
+            f.bytes(5)?;
+        } else if f.u8()? == 2 {
+            return Ok(None);
+        }
+        Ok(Some((0..rank).map(|_| f.length()).collect::<Result<_>>()?))
+    }
+
+    /// Links of a group with a symbol table: a B-tree of symbol table nodes, names in a local heap
+    fn symbol_table(&mut self, data: &[u8], depth: usize) -> Result<Vec<(String, Link)>> {
+        let mut f = self.fields(data);
+        let (btree, heap) = (f.offset()?, f.offset()?);
+        let heap_header = self.read_at(heap, 8 + 2 * self.length_size + self.offset_size)?;
+        anyhow::ensure!(heap_header.starts_with(b"HEAP"), "invalid local heap");
+        let mut f = self.fields(&heap_header);
+        f.pos = 8;
+        let heap_size = f.length()? as usize;
+        f.length()?;
+        let heap_data = f.offset()?;
+        let heap = self.read_at(heap_data, heap_size)?;
+        let mut links = Vec::new();
+        self.btree_links(btree, &heap, depth, &mut links)?;
+        Ok(links)
+    }
+
+    fn btree_links(
+        &mut self,
+        address: u64,
+        heap: &[u8],
+        depth: usize,
+        links: &mut Vec<(String, Link)>,
+    ) -> Result<()> {
+        anyhow::ensure!(depth < MAX_DEPTH, "group B-tree nested too deeply");
+        let header = self.read_at(address, 8 + 2 * self.offset_size)?;
+        anyhow::ensure!(header.starts_with(b"TREE"), "invalid group B-tree");
+        let level = header[5];
+        let entries = u16::from_le_bytes(header[6..8].try_into()?) as usize;
+        // keys and children alternate, with one more key than children
+        let node_size = (entries + 1) * self.length_size + entries * self.offset_size;
+        let node = self.read_at(address + header.len() as u64, node_size)?;
+        let mut f = self.fields(&node);
+        let mut children = Vec::new();
+        for _ in 0..entries {
+            f.length()?;
+            children.push(f.offset()?);
+        }
+        for child in children {
+            if level > 0 {
+                self.btree_links(child, heap, depth + 1, links)?;
+                continue;
+            }
+            let header = self.read_at(child, 8)?;
+            anyhow::ensure!(header.starts_with(b"SNOD"), "invalid symbol table node");
+            let symbols = u16::from_le_bytes(header[6..8].try_into()?) as usize;
+            let entry_size = 2 * self.offset_size + 24;
+            let entries = self.read_at(child + 8, symbols * entry_size)?;
+            for entry in entries.chunks_exact(entry_size) {
+                let mut f = self.fields(entry);
+                let name_offset = f.offset()? as usize;
+                let object = f.offset()?;
+                let name = heap
+                    .get(name_offset..)
+                    .context("invalid link name offset")?;
+                let name = &name[..memchr::memchr(0, name).unwrap_or(name.len())];
+                links.push((
+                    String::from_utf8_lossy(name).into_owned(),
+                    Link::Hard(object),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// A link message of a group with compact link storage
+    fn link(&self, data: &[u8]) -> Result<Option<(String, Link)>> {
+        let mut f = self.fields(data);
+        f.u8()?;
+        let flags = f.u8()?;
+        let kind = if flags & 0x08 != 0 { f.u8()? } else { 0 };
+        if flags & 0x04 != 0 {
+            // creation order
+            f.bytes(8)?;
+        }
+        if flags & 0x10 != 0 {
+            // character set
+            f.u8()?;
+        }
+        let name_len = f.uint(1 << (flags & 0x03))? as usize;
+        let name = String::from_utf8_lossy(f.bytes(name_len)?).into_owned();
+        Ok(match kind {
+            0 => Some((name, Link::Hard(f.offset()?))),
+            1 => {
+                let len = f.u16()? as usize;
+                let target = String::from_utf8_lossy(f.bytes(len)?).into_owned();
+                Some((name, Link::Soft(target)))
+            }
+            // external and user-defined links
+            _ => None,
+        })
+    }
+
+    /// An object of a global heap collection, which contains the data of variable-length values
+    fn global_heap_object(&mut self, collection: u64, index: u32) -> Result<Vec<u8>> {
+        if !self.global_heaps.contains_key(&collection) {
+            let header = self.read_at(collection, 8 + self.length_size)?;
+            anyhow::ensure!(header.starts_with(b"GCOL"), "invalid global heap");
+            let size = self.fields(&header[8..]).length()? as usize;
+            let data = self.read_at(collection, size)?;
+            self.global_heaps.insert(collection, data);
+        }
+        let data = &self.global_heaps[&collection];
+        let mut f = self.fields(data);
+        f.pos = 8 + self.length_size;
+        while f.remaining() >= 8 + self.length_size {
+            let object_index = f.u16()?;
+            // reference count and reserved
+            f.bytes(6)?;
+            let size = f.length()? as usize;
+            if object_index == 0 {
+                // free space
+                break;
+            }
+            let object = f.bytes(size)?;
+            if object_index as u32 == index {
+                return Ok(object.to_vec());
+            }
+            f.bytes(size.next_multiple_of(8) - size)?;
+        }
+        anyhow::bail!("global heap object {index} not found")
+    }
+
+    /// The values of strings and numbers as text, empty for other types
+    fn values(&mut self, datatype: &Datatype, count: usize, data: &[u8]) -> Result<Vec<String>> {
+        let big_endian = datatype.bits & 0x01 != 0;
+        let size = datatype.size;
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let elements = data.chunks_exact(size).take(count);
+        let number = |e: &[u8]| {
+            let mut b = [0u8; 8];
+            if big_endian {
+                b[8 - size..].copy_from_slice(e);
+                u64::from_be_bytes(b)
+            } else {
+                b[..size].copy_from_slice(e);
+                u64::from_le_bytes(b)
+            }
+        };
+        Ok(match datatype.class {
+            STRING => elements
+                .map(|e| {
+                    let e = &e[..memchr::memchr(0, e).unwrap_or(e.len())];
+                    String::from_utf8_lossy(e).trim_end().to_owned()
+                })
+                .collect(),
+            VARIABLE_LENGTH if datatype.is_string() => {
+                let mut strings = Vec::new();
+                for e in elements {
+                    let mut f = self.fields(e);
+                    let len = f.u32()? as usize;
+                    let (collection, index) = (f.offset()?, f.u32()?);
+                    if len == 0 || collection == 0 {
+                        strings.push(String::new());
+                        continue;
+                    }
+                    let object = self.global_heap_object(collection, index)?;
+                    let s = object.get(..len).unwrap_or(&object);
+                    strings.push(String::from_utf8_lossy(s).trim_end_matches('\0').to_owned());
+                }
+                strings
+            }
+            FIXED_POINT if matches!(size, 1 | 2 | 4 | 8) => elements
+                .map(|e| {
+                    let v = number(e);
+                    if datatype.bits & 0x08 != 0 {
+                        // sign extension
+                        let shift = 64 - 8 * size;
+                        (((v << shift) as i64) >> shift).to_string()
+                    } else {
+                        v.to_string()
+                    }
+                })
+                .collect(),
+            FLOATING_POINT if size == 4 => elements
+                .map(|e| f32::from_bits(number(e) as u32).to_string())
+                .collect(),
+            FLOATING_POINT if size == 8 => elements
+                .map(|e| f64::from_bits(number(e)).to_string())
+                .collect(),
+            _ => Vec::new(),
+        })
+    }
+
+    /// The names, types and values (empty for types other than strings and numbers) of the attributes
+    fn attributes(
+        &mut self,
+        object: &Object,
+        path: &str,
+    ) -> Result<Vec<(String, Datatype, Vec<String>)>> {
+        if object.message(ATTRIBUTE_INFO).is_some() {
+            debug!("{path}: skipping attributes in dense storage");
+        }
+        let mut attributes = Vec::new();
+        for (_, data) in object.messages.iter().filter(|(k, _)| *k == ATTRIBUTE) {
+            let mut f = self.fields(data);
+            let version = f.u8()?;
+            f.u8()?;
+            let name_size = f.u16()? as usize;
+            let datatype_size = f.u16()? as usize;
+            let dataspace_size = f.u16()? as usize;
+            if version == 3 {
+                // name encoding
+                f.u8()?;
+            }
+            // version 1 pads the parts to multiples of 8
+            let padded = |n: usize| {
+                if version == 1 {
+                    n.next_multiple_of(8)
+                } else {
+                    n
+                }
+            };
+            let name = f.bytes(padded(name_size))?;
+            let name = &name[..memchr::memchr(0, name).unwrap_or(name_size.min(name.len()))];
+            let name = String::from_utf8_lossy(name).into_owned();
+            let datatype = Datatype::parse(f.bytes(padded(datatype_size))?)?;
+            let Some(dims) = self.dataspace(f.bytes(padded(dataspace_size))?)? else {
+                continue;
+            };
+            let count = dims.iter().product::<u64>() as usize;
+            let data = &f.data[f.pos..];
+            let values = self.values(&datatype, count, data)?;
+            attributes.push((name, datatype, values));
+        }
+        Ok(attributes)
+    }
+
+    fn write_attributes(
+        &self,
+        attributes: &[(String, Datatype, Vec<String>)],
+        path: &str,
+        p: &str,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        for (name, datatype, values) in attributes {
+            if values.is_empty() {
+                writeln!(out, "{p}{path} @ {name}: {}", datatype.name())?;
+                continue;
+            }
+            let mut value = if datatype.is_string() {
+                values.join(", ")
+            } else {
+                values[..values.len().min(MAX_VALUES)].join(", ")
+            };
+            if values.len() > MAX_VALUES && !datatype.is_string() {
+                value.push_str(", ...");
+            }
+            for line in value.lines() {
+                writeln!(out, "{p}{path} @ {name} = {line}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The data of a dataset that is stored contiguously or compactly (not chunked, so not compressed)
+    fn dataset_data(&mut self, object: &Object, size: usize) -> Result<Option<Vec<u8>>> {
+        let Some(layout) = object.message(DATA_LAYOUT) else {
+            return Ok(None);
+        };
+        let mut f = self.fields(layout);
+        let version = f.u8()?;
+        let data = if version >= 3 {
+            match f.u8()? {
+                // compact
+                0 => {
+                    let len = f.u16()? as usize;
+                    f.bytes(len)?.to_vec()
+                }
+                // contiguous
+                1 => {
+                    let address = f.offset()?;
+                    if address == u64::MAX >> (64 - 8 * self.offset_size) {
+                        // not written yet
+                        return Ok(None);
+                    }
+                    self.read_at(address, size)?
+                }
+                _ => return Ok(None),
+            }
+        } else {
+            let rank = f.u8()? as usize;
+            let class = f.u8()?;
+            f.bytes(5)?;
+            match class {
+                0 => {
+                    f.bytes(4 * rank)?;
+                    let len = f.u32()? as usize;
+                    f.bytes(len)?.to_vec()
+                }
+                1 => {
+                    let address = f.offset()?;
+                    self.read_at(address, size)?
+                }
+                _ => return Ok(None),
+            }
+        };
+        Ok(Some(data))
+    }
+
+    /// MATLAB (v7.3 MAT-files) stores char arrays as uint16 datasets with the dimensions reversed,
+    /// the characters of each row of the array are `rows` apart
+    fn matlab_chars(
+        &mut self,
+        object: &Object,
+        datatype: &Datatype,
+        dims: &[u64],
+    ) -> Result<Vec<String>> {
+        let count = dims.iter().product::<u64>() as usize;
+        let size = count.saturating_mul(2);
+        if datatype.class != FIXED_POINT || datatype.size != 2 || size > MAX_STRING_DATA {
+            return Ok(Vec::new());
+        }
+        let Some(data) = self.dataset_data(object, size)? else {
+            return Ok(Vec::new());
+        };
+        let big_endian = datatype.bits & 0x01 != 0;
+        let units: Vec<u16> = data
+            .chunks_exact(2)
+            .map(|b| {
+                if big_endian {
+                    u16::from_be_bytes([b[0], b[1]])
+                } else {
+                    u16::from_le_bytes([b[0], b[1]])
+                }
+            })
+            .collect();
+        let rows = dims.last().copied().unwrap_or(1).max(1) as usize;
+        let columns = units.len() / rows;
+        Ok((0..rows)
+            .map(|r| {
+                String::from_utf16_lossy(
+                    &(0..columns)
+                        .map(|c| units[r + c * rows])
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .map(|row| row.trim_end_matches(['\0', ' ']).to_owned())
+            .filter(|row| !row.is_empty())
+            .collect())
+    }
+
+    fn write_object(
+        &mut self,
+        address: u64,
+        path: &str,
+        depth: usize,
+        p: &str,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        anyhow::ensure!(depth < MAX_DEPTH, "groups nested too deeply");
+        if !self.visited.insert(address) {
+            // hard link to an object that was already output
+            return Ok(());
+        }
+        let object = self.object(address)?;
+        let dataset = object.message(DATATYPE).zip(object.message(DATASPACE));
+        if let Some((datatype, dataspace)) = dataset {
+            let datatype = Datatype::parse(datatype)?;
+            let dims = self.dataspace(dataspace)?.unwrap_or_default();
+            let shape = dims
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(out, "{p}{path}: dataset {} [{shape}]", datatype.name())?;
+            let attributes = self.attributes(&object, path)?;
+            self.write_attributes(&attributes, path, p, out)?;
+            let count = dims.iter().product::<u64>() as usize;
+            let size = count.saturating_mul(datatype.size);
+            let matlab_char = attributes.iter().any(|(name, _, values)| {
+                name == "MATLAB_class" && values.first().is_some_and(|v| v == "char")
+            });
+            let values = if datatype.is_string() && size <= MAX_STRING_DATA {
+                match self.dataset_data(&object, size)? {
+                    Some(data) => self.values(&datatype, count, &data)?,
+                    None => Vec::new(),
+                }
+            } else if matlab_char {
+                self.matlab_chars(&object, &datatype, &dims)?
+            } else {
+                Vec::new()
+            };
+            for value in values {
+                for line in value.lines() {
+                    writeln!(out, "{p}{path} = {line}")?;
+                }
+            }
+            return Ok(());
+        }
+        if depth > 0 {
+            writeln!(out, "{p}{path}: group")?;
+        }
+        let attributes = self.attributes(&object, path)?;
+        self.write_attributes(&attributes, path, p, out)?;
+        let mut links = Vec::new();
+        if let Some(symbol_table) = object.message(SYMBOL_TABLE) {
+            links = self.symbol_table(symbol_table, depth)?;
+        }
+        for (_, data) in object.messages.iter().filter(|(k, _)| *k == LINK) {
+            links.extend(self.link(data)?);
+        }
+        if object.message(LINK_INFO).is_some() && links.is_empty() {
+            debug!("{path}: skipping links in dense storage");
+        }
+        let parent = path.trim_end_matches('/');
+        for (name, link) in links {
+            let child = format!("{parent}/{name}");
+            match link {
+                Link::Hard(address) => self
+                    .write_object(address, &child, depth + 1, p, out)
+                    .with_context(|| format!("reading {child}"))?,
+                Link::Soft(target) => writeln!(out, "{p}{child}: link to {target}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn synchronous_dump_hdf5(path: &Path, line_prefix: &str, mut out: impl Write) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let (mut hdf5, root) = Hdf5::open(BufReader::new(file))?;
+    hdf5.write_object(root, "/", 0, line_prefix, &mut out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for Hdf5Adapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            ..
+        } = ai;
+        let (path, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            let _tmp = tmp;
+            synchronous_dump_hdf5(&path, &line_prefix, oup)
+        })
+        .await?
+        .context("in synchronous hdf5 task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    /// Appends a block aligned to 8 bytes, returns its address
+    fn add(file: &mut Vec<u8>, block: &[u8]) -> u64 {
+        let address = file.len() as u64;
+        file.extend_from_slice(block);
+        file.resize(file.len().next_multiple_of(8), 0);
+        address
+    }
+
+    fn padded(data: &[u8]) -> Vec<u8> {
+        let mut d = data.to_vec();
+        d.resize(data.len().next_multiple_of(8), 0);
+        d
+    }
+
+    /// A message of a version 1 object header
+    fn message(kind: u16, data: &[u8]) -> Vec<u8> {
+        let data = padded(data);
+        let mut m = Vec::new();
+        m.extend(kind.to_le_bytes());
+        m.extend((data.len() as u16).to_le_bytes());
+        m.extend([0; 4]);
+        m.extend(data);
+        m
+    }
+
+    fn object(messages: &[Vec<u8>]) -> Vec<u8> {
+        let body = messages.concat();
+        let mut o = vec![1, 0];
+        o.extend((messages.len() as u16).to_le_bytes());
+        o.extend(1u32.to_le_bytes());
+        o.extend((body.len() as u32).to_le_bytes());
+        o.extend([0; 4]);
+        o.extend(body);
+        o
+    }
+
+    fn datatype(class_and_version: u8, bits: u8, size: u32) -> Vec<u8> {
+        let mut t = vec![class_and_version, bits, 0, 0];
+        t.extend(size.to_le_bytes());
+        t
+    }
+
+    fn dataspace(dims: &[u64]) -> Vec<u8> {
+        let mut s = vec![1, dims.len() as u8, 0, 0, 0, 0, 0, 0];
+        for d in dims {
+            s.extend(d.to_le_bytes());
+        }
+        s
+    }
+
+    fn attribute(name: &str, datatype: &[u8], dataspace: &[u8], value: &[u8]) -> Vec<u8> {
+        let name = format!("{name}\0");
+        let mut a = vec![1, 0];
+        a.extend((name.len() as u16).to_le_bytes());
+        a.extend((datatype.len() as u16).to_le_bytes());
+        a.extend((dataspace.len() as u16).to_le_bytes());
+        a.extend(padded(name.as_bytes()));
+        a.extend(padded(datatype));
+        a.extend(padded(dataspace));
+        a.extend_from_slice(value);
+        message(ATTRIBUTE, &a)
+    }
+
+    /// A file as written by the HDF5 library with default settings: superblock version 0, groups with symbol tables
+    fn file() -> Vec<u8> {
+        let mut file = vec![0; 96];
+        let mut gcol = b"GCOL\x01\0\0\0".to_vec();
+        gcol.extend(56u64.to_le_bytes());
+        gcol.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+        gcol.extend(3u64.to_le_bytes());
+        gcol.extend(padded(b"Ada"));
+        gcol.extend([0; 8]);
+        gcol.extend(16u64.to_le_bytes());
+        let gcol = add(&mut file, &gcol);
+
+        let mut layout = vec![3, 0];
+        layout.extend(16u16.to_le_bytes());
+        layout.extend(b"alpha\0\0\0beta\0\0\0\0");
+        let names = add(
+            &mut file,
+            &object(&[
+                message(DATASPACE, &dataspace(&[2])),
+                message(DATATYPE, &datatype(0x13, 0, 8)),
+                message(DATA_LAYOUT, &layout),
+            ]),
+        );
+
+        // the MATLAB char array ['hello'; 'world']
+        let mut layout = vec![3, 0];
+        layout.extend(20u16.to_le_bytes());
+        layout.extend("hweolrllod".encode_utf16().flat_map(u16::to_le_bytes));
+        let label = add(
+            &mut file,
+            &object(&[
+                message(DATASPACE, &dataspace(&[5, 2])),
+                message(DATATYPE, &datatype(0x10, 0, 2)),
+                message(DATA_LAYOUT, &layout),
+                attribute(
+                    "MATLAB_class",
+                    &datatype(0x13, 0, 4),
+                    &dataspace(&[]),
+                    b"char",
+                ),
+            ]),
+        );
+
+        let mut float = datatype(0x11, 0x20, 8);
+        float.extend([0; 12]);
+        let mut layout = vec![3, 1];
+        layout.extend(u64::MAX.to_le_bytes());
+        layout.extend(48u64.to_le_bytes());
+        let scale: Vec<u8> = [1i32, -2, 3].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let temperature = add(
+            &mut file,
+            &object(&[
+                message(DATASPACE, &dataspace(&[2, 3])),
+                message(DATATYPE, &float),
+                message(DATA_LAYOUT, &layout),
+                attribute("units", &datatype(0x13, 0, 1), &dataspace(&[]), b"K"),
+                attribute("scale", &datatype(0x10, 0x08, 4), &dataspace(&[3]), &scale),
+            ]),
+        );
+
+        let mut link = vec![1, 0x08, 1, 6];
+        link.extend(b"latest");
+        link.extend(12u16.to_le_bytes());
+        link.extend(b"/temperature");
+        let mut vlen_string = datatype(0x19, 0x01, 16);
+        vlen_string.extend(datatype(0x13, 0, 1));
+        let mut operator = 3u32.to_le_bytes().to_vec();
+        operator.extend(gcol.to_le_bytes());
+        operator.extend(1u32.to_le_bytes());
+        let run1 = add(
+            &mut file,
+            &object(&[
+                attribute("operator", &vlen_string, &dataspace(&[]), &operator),
+                message(LINK, &link),
+            ]),
+        );
+
+        let heap_data = padded(b"\0names\0run1\0temperature\0label\0");
+        let heap_size = heap_data.len() as u64;
+        let heap_data = add(&mut file, &heap_data);
+        let mut heap = b"HEAP\0\0\0\0".to_vec();
+        heap.extend(heap_size.to_le_bytes());
+        heap.extend(u64::MAX.to_le_bytes());
+        heap.extend(heap_data.to_le_bytes());
+        let heap = add(&mut file, &heap);
+
+        let mut snod = b"SNOD\x01\0".to_vec();
+        snod.extend(4u16.to_le_bytes());
+        for (name, address) in [(1u64, names), (24, label), (7, run1), (12, temperature)] {
+            snod.extend(name.to_le_bytes());
+            snod.extend(address.to_le_bytes());
+            snod.extend([0; 24]);
+        }
+        let snod = add(&mut file, &snod);
+        let mut tree = b"TREE\0\0".to_vec();
+        tree.extend(1u16.to_le_bytes());
+        tree.extend(u64::MAX.to_le_bytes());
+        tree.extend(u64::MAX.to_le_bytes());
+        tree.extend(0u64.to_le_bytes());
+        tree.extend(snod.to_le_bytes());
+        tree.extend(12u64.to_le_bytes());
+        let tree = add(&mut file, &tree);
+
+        let mut symbol_table = tree.to_le_bytes().to_vec();
+        symbol_table.extend(heap.to_le_bytes());
+        let root = add(
+            &mut file,
+            &object(&[
+                message(SYMBOL_TABLE, &symbol_table),
+                attribute("title", &datatype(0x13, 0, 6), &dataspace(&[]), b"Run 42"),
+            ]),
+        );
+
+        let mut superblock = SIGNATURE.to_vec();
+        superblock.extend([0, 0, 0, 0, 0, 8, 8, 0]);
+        superblock.extend(4u16.to_le_bytes());
+        superblock.extend(16u16.to_le_bytes());
+        superblock.extend(0u32.to_le_bytes());
+        superblock.extend(0u64.to_le_bytes());
+        superblock.extend(u64::MAX.to_le_bytes());
+        superblock.extend((file.len() as u64).to_le_bytes());
+        superblock.extend(u64::MAX.to_le_bytes());
+        superblock.extend(0u64.to_le_bytes());
+        superblock.extend(root.to_le_bytes());
+        superblock.extend(1u32.to_le_bytes());
+        superblock.extend(0u32.to_le_bytes());
+        superblock.extend(symbol_table);
+        file[..96].copy_from_slice(&superblock);
+        file
+    }
+
+    #[tokio::test]
+    async fn groups_datasets_attributes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("experiment.h5");
+        std::fs::write(&path, file())?;
+        let (a, _) = simple_fs_adapt_info(&path).await?;
+        let matcher = METADATA.fast_matchers[0].clone().into();
+        let buf = adapted_to_vec(Hdf5Adapter::new().adapt(a, &matcher).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:/ @ title = Run 42
+PREFIX:/names: dataset string [2]
+PREFIX:/names = alpha
+PREFIX:/names = beta
+PREFIX:/label: dataset uint16 [5, 2]
+PREFIX:/label @ MATLAB_class = char
+PREFIX:/label = hello
+PREFIX:/label = world
+PREFIX:/run1: group
+PREFIX:/run1 @ operator = Ada
+PREFIX:/run1/latest: link to /temperature
+PREFIX:/temperature: dataset float64 [2, 3]
+PREFIX:/temperature @ units = K
+PREFIX:/temperature @ scale = 1, -2, 3
+"
+        );
+        Ok(())
+    }
+}
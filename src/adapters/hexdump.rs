@@ -0,0 +1,154 @@
+use crate::adapted_iter::one_file;
+
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use std::fmt::Write;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// bytes per output line
+const LINE_LEN: usize = 16;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "hexdump".to_owned(),
+        version: 1,
+        description: "Renders binary data as a hexdump with offsets and an ASCII column (like `xxd -g1`), so hex byte sequences (`7f 45 4c 46`) and embedded ASCII can be searched.\nDoes not match any files by itself, use it for binary files that no other adapter handles with `--rga-adapters=+hexdump --rga-binary=adapter --rga-binary-adapter=hexdump`."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: vec![],
+        slow_matchers: None,
+        disabled_by_default: true,
+        keep_fast_matchers_if_accurate: false
+    };
+}
+
+#[derive(Default)]
+pub struct HexdumpAdapter;
+
+impl HexdumpAdapter {
+    pub fn new() -> HexdumpAdapter {
+        HexdumpAdapter
+    }
+}
+impl GetMetadata for HexdumpAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Format one line of at most `LINE_LEN` bytes starting at the given offset.
+///
+/// Lines are separated by newlines, the last line does not end with a newline.
+fn hexdump_line(offset: u64, bytes: &[u8], out: &mut String) {
+    if offset > 0 {
+        out.push('\n');
+    }
+    write!(out, "{offset:08x}:").unwrap();
+    for b in bytes {
+        write!(out, " {b:02x}").unwrap();
+    }
+    // pad short (last) lines so the ASCII column stays aligned
+    out.push_str(&"   ".repeat(LINE_LEN - bytes.len()));
+    out.push_str("  ");
+    out.extend(bytes.iter().map(|&b| {
+        if b.is_ascii_graphic() || b == b' ' {
+            b as char
+        } else {
+            '.'
+        }
+    }));
+}
+
+/// Converts the input to a hexdump, `LINE_LEN` bytes per line.
+pub fn hexdump(inp: ReadBox) -> ReadBox {
+    let s = stream! {
+        let mut offset: u64 = 0;
+        let mut pending: Vec<u8> = Vec::with_capacity(LINE_LEN);
+        for await chunk in ReaderStream::new(inp) {
+            let chunk = chunk?;
+            let mut out = String::new();
+            for &b in chunk.iter() {
+                pending.push(b);
+                if pending.len() == LINE_LEN {
+                    hexdump_line(offset, &pending, &mut out);
+                    offset += LINE_LEN as u64;
+                    pending.clear();
+                }
+            }
+            if !out.is_empty() {
+                yield std::io::Result::Ok(Bytes::from(out));
+            }
+        }
+        if !pending.is_empty() {
+            let mut out = String::new();
+            hexdump_line(offset, &pending, &mut out);
+            yield Ok(Bytes::from(out));
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+#[async_trait]
+impl FileAdapter for HexdumpAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        Ok(one_file(AdaptInfo {
+            is_real_file: false,
+            archive_recursion_depth: ai.archive_recursion_depth + 1,
+            inp: hexdump(ai.inp),
+            ..ai
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BinaryPolicy;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use tokio::io::AsyncReadExt;
+    use tokio_test::io::Builder;
+
+    #[tokio::test]
+    async fn hexdump_chunks() -> Result<()> {
+        let mock = Builder::new()
+            .read(b"\x7fELF\x02\x01\x01\x00")
+            .read(b"\x00\x00\x00\x00\x00\x00\x00\x00\x03\x00")
+            .build();
+        let mut oup = String::new();
+        hexdump(Box::pin(mock)).read_to_string(&mut oup).await?;
+        assert_eq!(
+            oup,
+            "00000000: 7f 45 4c 46 02 01 01 00 00 00 00 00 00 00 00 00  .ELF............\n00000010: 03 00                                            .."
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn binary_fallback() -> Result<()> {
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("firmware.bin"),
+            Box::pin(Cursor::new(b"foo\0bar")),
+        );
+        a.config.binary = BinaryPolicy::Adapter;
+        a.config.binary_adapter = Some("hexdump".to_string());
+        a.config.adapters = vec!["+hexdump".to_string()];
+        let res = postproc::PostprocPrefix {}.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:00000000: 66 6f 6f 00 62 61 72                             foo.bar\n"
+        );
+        Ok(())
+    }
+}
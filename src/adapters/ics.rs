@@ -0,0 +1,391 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["ics", "ical", "ifb", "vcs"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ics".to_owned(),
+        version: 1,
+        description: "Reads iCalendar files. Outputs the summary, description, location, times, organizer and attendees of every event, todo and journal entry, prefixed with the kind, start date and summary of the entry (e.g. `VEVENT[2023-05-01 Meeting]: LOCATION: Room 3`)"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("text/calendar".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct IcsAdapter;
+
+impl IcsAdapter {
+    pub fn new() -> IcsAdapter {
+        IcsAdapter
+    }
+}
+
+impl GetMetadata for IcsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Properties without searchable content. Extension properties (`X-...`) are skipped as well
+const SKIPPED_PROPERTIES: &[&str] = &[
+    "ACTION",
+    "CALSCALE",
+    "CLASS",
+    "CREATED",
+    "DTSTAMP",
+    "LAST-MODIFIED",
+    "METHOD",
+    "PRIORITY",
+    "PRODID",
+    "SEQUENCE",
+    "TRANSP",
+    "TRIGGER",
+    "UID",
+    "VERSION",
+];
+
+/// Extension properties that are output, with the name and description of the calendar
+const KEPT_EXTENSIONS: &[&str] = &["X-WR-CALNAME", "X-WR-CALDESC"];
+
+/// Properties with date-time values
+const TIME_PROPERTIES: &[&str] = &[
+    "COMPLETED",
+    "DTEND",
+    "DTSTART",
+    "DUE",
+    "EXDATE",
+    "RDATE",
+    "RECURRENCE-ID",
+];
+
+/// A content line `NAME;PARAM=VALUE:value`
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Property> {
+        // colons and semicolons within quoted parameter values don't count
+        let mut in_quotes = false;
+        let mut parts = Vec::new();
+        let mut start = 0;
+        let mut colon = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ';' if !in_quotes => {
+                    parts.push(&line[start..i]);
+                    start = i + 1;
+                }
+                ':' if !in_quotes => {
+                    colon = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let colon = colon?;
+        parts.push(&line[start..colon]);
+        let params = parts[1..]
+            .iter()
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+            .collect();
+        Some(Property {
+            name: parts[0].to_ascii_uppercase(),
+            params,
+            value: line[colon + 1..].to_string(),
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn is_searchable(&self) -> bool {
+        if self.name.starts_with("X-") {
+            KEPT_EXTENSIONS.contains(&self.name.as_str())
+        } else {
+            !SKIPPED_PROPERTIES.contains(&self.name.as_str())
+        }
+    }
+
+    /// The value in a readable form
+    fn text(&self) -> String {
+        let name = self.name.as_str();
+        if TIME_PROPERTIES.contains(&name) {
+            let times = self
+                .value
+                .split(',')
+                .map(|v| format_time(v).unwrap_or_else(|| v.to_string()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            return match self.param("TZID") {
+                Some(tz) => format!("{times} ({tz})"),
+                None => times,
+            };
+        }
+        if name == "ORGANIZER" || name == "ATTENDEE" {
+            let address = match self.value.get(..7) {
+                Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &self.value[7..],
+                _ => self.value.as_str(),
+            };
+            return match self.param("CN") {
+                Some(cn) => format!("{cn} <{address}>"),
+                None => address.to_string(),
+            };
+        }
+        unescape(&self.value)
+    }
+}
+
+/// Join folded lines (continued by a line starting with a space or tab)
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+/// Decode the escaped newlines, commas, semicolons and backslashes of text values
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// `20230501` as `2023-05-01`, `20230501T090000Z` as `2023-05-01 09:00 UTC`
+fn format_time(value: &str) -> Option<String> {
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    if date.len() != 8 || !digits(date) {
+        return None;
+    }
+    let date = format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]);
+    let Some(time) = time else {
+        return Some(date);
+    };
+    let (time, utc) = match time.strip_suffix('Z') {
+        Some(time) => (time, " UTC"),
+        None => (time, ""),
+    };
+    if time.len() != 6 || !digits(time) {
+        return None;
+    }
+    Some(format!("{date} {}:{}{utc}", &time[..2], &time[2..4]))
+}
+
+/// An event, todo, ... with the properties of it and of its subcomponents (alarms)
+struct Block {
+    component: String,
+    depth: usize,
+    properties: Vec<Property>,
+}
+
+impl Block {
+    /// `VEVENT[2023-05-01 Meeting]`
+    fn header(&self) -> String {
+        let find = |name: &str| self.properties.iter().find(|p| p.name == name);
+        let date = find("DTSTART")
+            .or_else(|| find("DUE"))
+            .and_then(|p| format_time(p.value.split(',').next()?))
+            .map(|t| t[..10].to_string());
+        let summary = find("SUMMARY").map(|p| p.text().replace('\n', " "));
+        let label = [date, summary]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if label.is_empty() {
+            self.component.clone()
+        } else {
+            format!("{}[{label}]", self.component)
+        }
+    }
+}
+
+fn write_properties(properties: &[Property], prefix: &str, out: &mut String) {
+    for p in properties.iter().filter(|p| p.is_searchable()) {
+        for line in p.text().lines() {
+            let line = line.trim_end();
+            if !line.is_empty() {
+                out.push_str(&format!("{prefix}{}: {line}\n", p.name));
+            }
+        }
+    }
+}
+
+fn ics_to_text(text: &str, line_prefix: &str) -> String {
+    let mut out = String::new();
+    let mut components: Vec<String> = Vec::new();
+    let mut block: Option<Block> = None;
+    for line in unfold(text) {
+        let Some(p) = Property::parse(&line) else {
+            continue;
+        };
+        match p.name.as_str() {
+            "BEGIN" => {
+                let component = p.value.trim().to_ascii_uppercase();
+                // time zone definitions only contain offsets and transitions
+                let starts_block = block.is_none()
+                    && components.len() <= 1
+                    && component != "VCALENDAR"
+                    && component != "VTIMEZONE";
+                components.push(component.clone());
+                if starts_block {
+                    block = Some(Block {
+                        component,
+                        depth: components.len(),
+                        properties: Vec::new(),
+                    });
+                }
+            }
+            "END" => {
+                if block.as_ref().is_some_and(|b| b.depth == components.len()) {
+                    let b = block.take().expect("checked above");
+                    let prefix = format!("{line_prefix}{}: ", b.header());
+                    write_properties(&b.properties, &prefix, &mut out);
+                }
+                components.pop();
+            }
+            _ => match &mut block {
+                Some(b) => b.properties.push(p),
+                // properties of the calendar itself
+                None if components.len() <= 1 => {
+                    write_properties(&[p], line_prefix, &mut out);
+                }
+                None => {}
+            },
+        }
+    }
+    out
+}
+
+#[async_trait]
+impl WritingFileAdapter for IcsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let out = ics_to_text(&String::from_utf8_lossy(&data), &line_prefix);
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn events() -> Result<()> {
+        let ics = "BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Example//Calendar//EN
+X-WR-CALNAME:Work
+BEGIN:VTIMEZONE
+TZID:Europe/Berlin
+BEGIN:STANDARD
+TZNAME:CET
+END:STANDARD
+END:VTIMEZONE
+BEGIN:VEVENT
+UID:1234@example.com
+DTSTAMP:20230420T100000Z
+DTSTART;TZID=Europe/Berlin:20230501T090000
+DTEND;TZID=Europe/Berlin:20230501T100000
+SUMMARY:Meeting
+DESCRIPTION:Agenda:\\n1. Budget\\, planning\\n2. Room for the
+  offsite
+LOCATION:Room 3
+ORGANIZER;CN=Alice:mailto:alice@example.com
+ATTENDEE;CN=\"Bob; the Builder\";PARTSTAT=ACCEPTED:MAILTO:bob@example.com
+X-MICROSOFT-CDO-BUSYSTATUS:BUSY
+BEGIN:VALARM
+ACTION:DISPLAY
+TRIGGER:-PT15M
+DESCRIPTION:Reminder
+END:VALARM
+END:VEVENT
+BEGIN:VTODO
+DUE;VALUE=DATE:20230505
+SUMMARY:Send minutes
+END:VTODO
+BEGIN:VEVENT
+DTSTART:20230601T120000Z
+END:VEVENT
+END:VCALENDAR
+";
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("work.ics"),
+            Box::pin(Cursor::new(ics.replace('\n', "\r\n"))),
+        );
+        let buf = adapted_to_vec(loop_adapt(&IcsAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:X-WR-CALNAME: Work
+PREFIX:VEVENT[2023-05-01 Meeting]: DTSTART: 2023-05-01 09:00 (Europe/Berlin)
+PREFIX:VEVENT[2023-05-01 Meeting]: DTEND: 2023-05-01 10:00 (Europe/Berlin)
+PREFIX:VEVENT[2023-05-01 Meeting]: SUMMARY: Meeting
+PREFIX:VEVENT[2023-05-01 Meeting]: DESCRIPTION: Agenda:
+PREFIX:VEVENT[2023-05-01 Meeting]: DESCRIPTION: 1. Budget, planning
+PREFIX:VEVENT[2023-05-01 Meeting]: DESCRIPTION: 2. Room for the offsite
+PREFIX:VEVENT[2023-05-01 Meeting]: LOCATION: Room 3
+PREFIX:VEVENT[2023-05-01 Meeting]: ORGANIZER: Alice <alice@example.com>
+PREFIX:VEVENT[2023-05-01 Meeting]: ATTENDEE: Bob; the Builder <bob@example.com>
+PREFIX:VEVENT[2023-05-01 Meeting]: DESCRIPTION: Reminder
+PREFIX:VTODO[2023-05-05 Send minutes]: DUE: 2023-05-05
+PREFIX:VTODO[2023-05-05 Send minutes]: SUMMARY: Send minutes
+PREFIX:VEVENT[2023-06-01]: DTSTART: 2023-06-01 12:00 UTC
+"
+        );
+        Ok(())
+    }
+}
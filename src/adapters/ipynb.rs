@@ -0,0 +1,244 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["ipynb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ipynb".to_owned(),
+        version: 1,
+        description: "Reads Jupyter notebooks. Outputs the source of code and markdown cells and the text outputs of code cells, prefixed with the cell number and kind (e.g. `cell 12 (code): `). Images and other binary outputs are skipped"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/x-ipynb+json".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct IpynbAdapter;
+
+impl IpynbAdapter {
+    pub fn new() -> IpynbAdapter {
+        IpynbAdapter
+    }
+}
+
+impl GetMetadata for IpynbAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Output formats that are searched, in order of preference
+const TEXT_MIME_TYPES: &[&str] = &["text/plain", "text/markdown", "text/latex"];
+
+lazy_static! {
+    /// Terminal colors, used in error tracebacks
+    static ref ANSI_ESCAPE: regex::Regex = regex::Regex::new("\x1b\\[[0-9;]*[a-zA-Z]").unwrap();
+}
+
+/// Multiline strings are stored either as a string or as a list of lines (with their newlines)
+fn multiline(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(lines) => lines.iter().filter_map(Value::as_str).collect(),
+        _ => String::new(),
+    }
+}
+
+/// The searchable text of a code cell output
+fn output_text(output: &Value) -> String {
+    match output["output_type"].as_str() {
+        Some("stream") => multiline(&output["text"]),
+        Some("execute_result" | "display_data") => TEXT_MIME_TYPES
+            .iter()
+            .map(|mime| &output["data"][mime])
+            .find(|data| !data.is_null())
+            .map(multiline)
+            .unwrap_or_default(),
+        Some("error") => {
+            let traceback = multiline_lines(&output["traceback"]);
+            if traceback.is_empty() {
+                format!(
+                    "{}: {}",
+                    output["ename"].as_str().unwrap_or_default(),
+                    output["evalue"].as_str().unwrap_or_default()
+                )
+            } else {
+                ANSI_ESCAPE.replace_all(&traceback, "").into_owned()
+            }
+        }
+        // nbformat 3
+        Some("pyout" | "pyerr") | None => multiline(&output["text"]),
+        _ => String::new(),
+    }
+}
+
+/// A list of lines without their newlines, as in tracebacks
+fn multiline_lines(value: &Value) -> String {
+    match value {
+        Value::Array(lines) => lines
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => multiline(value),
+    }
+}
+
+fn write_text(text: &str, prefix: &str, out: &mut String) {
+    for line in text.lines() {
+        let line = line.trim_end();
+        if !line.is_empty() {
+            out.push_str(&format!("{prefix}{line}\n"));
+        }
+    }
+}
+
+fn notebook_to_text(notebook: &Value, line_prefix: &str) -> Result<String> {
+    let cells = match notebook["cells"].as_array() {
+        Some(cells) => cells.clone(),
+        // nbformat 3 puts the cells into worksheets
+        None => notebook["worksheets"]
+            .as_array()
+            .context("not a Jupyter notebook")?
+            .iter()
+            .filter_map(|w| w["cells"].as_array())
+            .flatten()
+            .cloned()
+            .collect(),
+    };
+    let mut out = String::new();
+    for (i, cell) in cells.iter().enumerate() {
+        let kind = cell["cell_type"].as_str().unwrap_or("unknown");
+        let source = match &cell["source"] {
+            Value::Null => &cell["input"],
+            source => source,
+        };
+        let prefix = format!("{line_prefix}cell {} ({kind}): ", i + 1);
+        write_text(&multiline(source), &prefix, &mut out);
+        if let Some(outputs) = cell["outputs"].as_array() {
+            let prefix = format!("{line_prefix}cell {} (output): ", i + 1);
+            for output in outputs {
+                write_text(&output_text(output), &prefix, &mut out);
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for IpynbAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let notebook: Value = serde_json::from_slice(&data).context("invalid notebook json")?;
+        let out = notebook_to_text(&notebook, &line_prefix)?;
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn cells() -> Result<()> {
+        let notebook = r##"{
+ "cells": [
+  {"cell_type": "markdown", "metadata": {}, "source": ["# Analysis\n", "\n", "Loading the data"]},
+  {
+   "cell_type": "code",
+   "execution_count": 1,
+   "metadata": {},
+   "source": "import pandas as pd\nprint('loaded')\npd.DataFrame({'a': [1]})",
+   "outputs": [
+    {"output_type": "stream", "name": "stdout", "text": ["loaded\n"]},
+    {
+     "output_type": "execute_result",
+     "execution_count": 1,
+     "metadata": {},
+     "data": {"text/html": ["<table>...</table>"], "text/plain": ["   a\n", "0  1"]}
+    },
+    {"output_type": "display_data", "metadata": {}, "data": {"image/png": "iVBORw0KGgoAAAANSUhEUg=="}}
+   ]
+  },
+  {
+   "cell_type": "code",
+   "execution_count": 2,
+   "metadata": {},
+   "source": ["1 / 0"],
+   "outputs": [
+    {
+     "output_type": "error",
+     "ename": "ZeroDivisionError",
+     "evalue": "division by zero",
+     "traceback": ["\u001b[0;31mZeroDivisionError\u001b[0m: division by zero"]
+    }
+   ]
+  }
+ ],
+ "metadata": {},
+ "nbformat": 4,
+ "nbformat_minor": 5
+}"##;
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("analysis.ipynb"),
+            Box::pin(Cursor::new(notebook)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&IpynbAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:cell 1 (markdown): # Analysis
+PREFIX:cell 1 (markdown): Loading the data
+PREFIX:cell 2 (code): import pandas as pd
+PREFIX:cell 2 (code): print('loaded')
+PREFIX:cell 2 (code): pd.DataFrame({'a': [1]})
+PREFIX:cell 2 (output): loaded
+PREFIX:cell 2 (output):    a
+PREFIX:cell 2 (output): 0  1
+PREFIX:cell 3 (code): 1 / 0
+PREFIX:cell 3 (output): ZeroDivisionError: division by zero
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chosen_by_default() -> Result<()> {
+        let adapters = get_adapters_filtered(None, &[], &[] as &[String])?;
+        let matcher = crate::matching::adapter_matcher(&adapters, false)?;
+        let (adapter, _) = matcher(crate::matching::FileMeta {
+            lossy_filename: "analysis.ipynb".to_string(),
+            mimetype: None,
+            path: None,
+        })
+        .context("no adapter for notebooks")?;
+        assert_eq!(adapter.metadata().name, "ipynb");
+        Ok(())
+    }
+}
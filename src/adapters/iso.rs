@@ -0,0 +1,408 @@
+use super::binary::{Endian, u32_at};
+use super::sevenzip::{SevenZipAdapter, archive_on_disk};
+use super::*;
+use crate::print_bytes;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+static EXTENSIONS: &[&str] = &["iso"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "iso".to_owned(),
+        version: 1,
+        description: "Reads the ISO 9660 file system of CD / DVD images (with Joliet and Rock Ridge names) and recurses into its files.\nImages with only a UDF file system are listed with `7z`."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-iso9660-image".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct IsoAdapter;
+
+impl IsoAdapter {
+    pub fn new() -> IsoAdapter {
+        IsoAdapter
+    }
+}
+impl GetMetadata for IsoAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const SECTOR_SIZE: u64 = 2048;
+/// the volume descriptors start after the system area
+const FIRST_DESCRIPTOR: u64 = 16;
+/// directories larger than this are considered invalid
+const MAX_DIRECTORY_SIZE: u32 = 64 * 1024 * 1024;
+
+/// A directory record of an ISO 9660 file system
+#[derive(Debug, Clone)]
+struct DirRecord {
+    name: String,
+    extent: u32,
+    size: u32,
+    is_dir: bool,
+    /// the name is a Rock Ridge name
+    rock_ridge: bool,
+}
+
+/// Parse a directory record. `joliet` names are UCS-2, Rock Ridge names are taken from the system use area.
+fn parse_record(record: &[u8], joliet: bool) -> Option<DirRecord> {
+    if record.len() < 34 {
+        return None;
+    }
+    let name_len = record[32] as usize;
+    let name = record.get(33..33 + name_len)?;
+    // padded to an even length
+    let system_use = record
+        .get(33 + name_len + (1 - name_len % 2)..)
+        .unwrap_or_default();
+    let rock_ridge_name = rock_ridge_name(system_use);
+    let rock_ridge = rock_ridge_name.is_some();
+    let name = if name == b"\0" {
+        ".".to_string()
+    } else if name == b"\x01" {
+        "..".to_string()
+    } else if let Some(name) = rock_ridge_name {
+        name
+    } else {
+        let name = if joliet {
+            Endian::Big.utf16(name)
+        } else {
+            String::from_utf8_lossy(name).into_owned()
+        };
+        // file version and empty extension: `README.;1`
+        let name = name.split_once(';').map(|(n, _)| n).unwrap_or(&name);
+        name.strip_suffix('.').unwrap_or(name).to_string()
+    };
+    Some(DirRecord {
+        name,
+        extent: u32_at(record, 2)?,
+        size: u32_at(record, 10)?,
+        is_dir: record[25] & 0x02 != 0,
+        rock_ridge,
+    })
+}
+
+/// The name from the Rock Ridge `NM` entries of the system use area of a directory record
+fn rock_ridge_name(mut system_use: &[u8]) -> Option<String> {
+    let mut name: Option<Vec<u8>> = None;
+    while system_use.len() >= 4 {
+        let len = system_use[2] as usize;
+        if len < 4 || len > system_use.len() {
+            break;
+        }
+        // NM: flags, name. Flag 1 means the name continues in the next NM entry
+        if &system_use[..2] == b"NM" && len > 5 {
+            name.get_or_insert_with(Vec::new)
+                .extend_from_slice(&system_use[5..len]);
+        }
+        system_use = &system_use[len..];
+    }
+    name.map(|n| String::from_utf8_lossy(&n).into_owned())
+}
+
+/// Split the data of a directory into its records. Records don't span sectors, the rest of a sector is zero padded.
+fn directory_records(data: &[u8], joliet: bool) -> Vec<DirRecord> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let len = data[pos] as usize;
+        if len == 0 {
+            // continue in the next sector
+            pos = (pos / SECTOR_SIZE as usize + 1) * SECTOR_SIZE as usize;
+            continue;
+        }
+        let Some(record) = data.get(pos..pos + len) else {
+            break;
+        };
+        if let Some(record) = parse_record(record, joliet) {
+            records.push(record);
+        }
+        pos += len;
+    }
+    records
+}
+
+/// A file of the image
+#[derive(Debug, PartialEq)]
+struct ImageFile {
+    path: String,
+    extent: u32,
+    size: u32,
+}
+
+async fn read_sectors(file: &mut tokio::fs::File, sector: u64, len: usize) -> Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(sector * SECTOR_SIZE)).await?;
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// The root directory record to list the files from, None if the image has no ISO 9660 file system.
+///
+/// Prefers Rock Ridge names (in the primary volume descriptor) over Joliet names over the short ISO 9660 names.
+async fn root_directory(file: &mut tokio::fs::File) -> Result<Option<(DirRecord, bool)>> {
+    let mut primary = None;
+    let mut joliet = None;
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + 64 {
+        let descriptor = read_sectors(file, sector, SECTOR_SIZE as usize).await?;
+        if &descriptor[1..6] != b"CD001" {
+            break;
+        }
+        match descriptor[0] {
+            1 => primary = parse_record(&descriptor[156..190], false),
+            // supplementary volume descriptor with UCS-2 escape sequence
+            2 if matches!(&descriptor[88..91], b"%/@" | b"%/C" | b"%/E") => {
+                joliet = parse_record(&descriptor[156..190], true)
+            }
+            255 => break,
+            _ => {}
+        }
+    }
+    let Some(primary) = primary else {
+        return Ok(None);
+    };
+    if let Some(joliet) = joliet {
+        let size = primary.size.min(MAX_DIRECTORY_SIZE) as usize;
+        let root = read_sectors(file, primary.extent as u64, size).await?;
+        if !directory_records(&root, false).iter().any(|r| r.rock_ridge) {
+            return Ok(Some((joliet, true)));
+        }
+    }
+    Ok(Some((primary, false)))
+}
+
+/// List the files of the image, the files of a directory before the contents of its subdirectories
+async fn list_files(
+    file: &mut tokio::fs::File,
+    root: DirRecord,
+    joliet: bool,
+) -> Result<Vec<ImageFile>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = vec![(String::new(), root)];
+    while let Some((dir_path, dir)) = stack.pop() {
+        // guard against loops in corrupt images
+        if !visited.insert(dir.extent) {
+            continue;
+        }
+        anyhow::ensure!(dir.size <= MAX_DIRECTORY_SIZE, "directory too large");
+        let data = read_sectors(file, dir.extent as u64, dir.size as usize).await?;
+        let mut subdirs = Vec::new();
+        for record in directory_records(&data, joliet) {
+            if record.name == "." || record.name == ".." {
+                continue;
+            }
+            let path = format!("{dir_path}{}", record.name);
+            if record.is_dir {
+                subdirs.push((format!("{path}/"), record));
+            } else {
+                files.push(ImageFile {
+                    path,
+                    extent: record.extent,
+                    size: record.size,
+                });
+            }
+        }
+        // depth first, in directory order
+        stack.extend(subdirs.into_iter().rev());
+    }
+    Ok(files)
+}
+
+/// Whether the image has a UDF file system (NSR descriptor in the volume recognition sequence)
+async fn is_udf(path: &Path) -> Result<bool> {
+    let mut file = tokio::fs::File::open(path).await?;
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + 64 {
+        let Ok(descriptor) = read_sectors(&mut file, sector, 7).await else {
+            break;
+        };
+        match &descriptor[1..6] {
+            b"NSR02" | b"NSR03" => return Ok(true),
+            b"BEA01" | b"CD001" | b"CDW02" | b"BOOT2" | b"TEA01" => {}
+            _ => break,
+        }
+    }
+    Ok(false)
+}
+
+#[async_trait]
+impl FileAdapter for IsoAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+        } = ai;
+        let (image, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let mut file = tokio::fs::File::open(&image).await?;
+        let Some((root, joliet)) = root_directory(&mut file).await? else {
+            anyhow::ensure!(
+                is_udf(&image).await?,
+                "{} is not an ISO 9660 or UDF image",
+                filepath_hint.display()
+            );
+            debug!("listing UDF image {} with 7z", filepath_hint.display());
+            return SevenZipAdapter::new()
+                .adapt(
+                    AdaptInfo {
+                        inp: Box::pin(tokio::fs::File::open(&image).await?),
+                        filepath_hint: image,
+                        is_real_file: true,
+                        archive_recursion_depth,
+                        postprocess,
+                        line_prefix,
+                        config,
+                        passwords,
+                    },
+                    detection_reason,
+                )
+                .await
+                .map(|files| -> AdaptedFilesIterBox {
+                    Box::pin(stream! {
+                        // keep the temporary file until all files are extracted
+                        let _tmp = tmp;
+                        for await file in files {
+                            yield file;
+                        }
+                    })
+                });
+        };
+        let files = list_files(&mut file, root, joliet).await?;
+        let s = stream! {
+            let _tmp = tmp;
+            for entry in files {
+                debug!(
+                    "{}{}|{}: {}",
+                    line_prefix,
+                    filepath_hint.display(),
+                    entry.path,
+                    print_bytes(entry.size as f64),
+                );
+                let mut file = tokio::fs::File::open(&image).await?;
+                file.seek(SeekFrom::Start(entry.extent as u64 * SECTOR_SIZE)).await?;
+                yield Ok(AdaptInfo {
+                    line_prefix: format!("{}{}: ", line_prefix, entry.path),
+                    filepath_hint: PathBuf::from(entry.path),
+                    is_real_file: false,
+                    inp: Box::pin(file.take(entry.size as u64)),
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn record(name: &[u8], extent: u32, size: u32, is_dir: bool, system_use: &[u8]) -> Vec<u8> {
+        let mut r = vec![0u8; 33];
+        r[2..6].copy_from_slice(&extent.to_le_bytes());
+        r[6..10].copy_from_slice(&extent.to_be_bytes());
+        r[10..14].copy_from_slice(&size.to_le_bytes());
+        r[14..18].copy_from_slice(&size.to_be_bytes());
+        r[25] = if is_dir { 2 } else { 0 };
+        r[32] = name.len() as u8;
+        r.extend_from_slice(name);
+        if name.len() % 2 == 0 {
+            r.push(0);
+        }
+        r.extend_from_slice(system_use);
+        r[0] = r.len() as u8;
+        r
+    }
+
+    fn sector(data: &[u8]) -> Vec<u8> {
+        let mut s = data.to_vec();
+        s.resize(SECTOR_SIZE as usize, 0);
+        s
+    }
+
+    fn create_iso() -> Vec<u8> {
+        let mut iso = vec![0u8; (FIRST_DESCRIPTOR * SECTOR_SIZE) as usize];
+        let mut pvd = b"\x01CD001\x01".to_vec();
+        pvd.resize(156, 0);
+        pvd.extend(record(b"\0", 18, 2048, true, b""));
+        iso.extend(sector(&pvd));
+        iso.extend(sector(b"\xffCD001\x01"));
+        // sector 18: root directory
+        let mut root = record(b"\0", 18, 2048, true, b"");
+        root.extend(record(b"\x01", 18, 2048, true, b""));
+        root.extend(record(b"DOCS", 19, 2048, true, b""));
+        root.extend(record(b"README.;1", 20, 6, false, b""));
+        iso.extend(sector(&root));
+        // sector 19: DOCS with a Rock Ridge name
+        let mut docs = record(b"\0", 19, 2048, true, b"");
+        docs.extend(record(b"\x01", 18, 2048, true, b""));
+        docs.extend(record(
+            b"NOTES000.TXT;1",
+            21,
+            12,
+            false,
+            b"NM\x13\x01\x00Notes 2024.txt",
+        ));
+        iso.extend(sector(&docs));
+        iso.extend(sector(b"hello\n"));
+        iso.extend(sector(b"from a disc\n"));
+        iso
+    }
+
+    #[test]
+    fn names() {
+        let r = parse_record(&record(b"FILE.TXT;1", 1, 2, false, b""), false).unwrap();
+        assert_eq!(r.name, "FILE.TXT");
+        let r = parse_record(&record(b"\0a\0\xe4", 1, 2, false, b""), true).unwrap();
+        assert_eq!(r.name, "aä");
+    }
+
+    #[tokio::test]
+    async fn image() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("disc.iso"),
+            Box::pin(Cursor::new(create_iso())),
+        );
+        let buf = adapted_to_vec(loop_adapt(&IsoAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:README: hello\nPREFIX:README: \nPREFIX:DOCS/Notes 2024.txt: from a disc\nPREFIX:DOCS/Notes 2024.txt: \n"
+        );
+        Ok(())
+    }
+}
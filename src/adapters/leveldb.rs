@@ -0,0 +1,576 @@
+use super::avro::snappy_decompress;
+use super::binary::read_all;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use async_compression::tokio::bufread::{BzDecoder, DeflateDecoder, ZstdDecoder};
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The file next to the tables and logs that identifies a LevelDB/RocksDB database directory
+const DATABASE_MARKER: &str = "CURRENT";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "leveldb".to_owned(),
+        version: 1,
+        description: "Reads the table files (.ldb, .sst) and write-ahead logs (.log) of LevelDB and RocksDB databases, as used by Chrome profiles, IndexedDB and many apps. Outputs each entry as `key = value` and deleted keys as `key (deleted)`. Entries of all versions are output, since each file is read on its own. Binary keys and values are escaped.\n.sst and .log files are only found with `--rga-accurate`, since they are only read next to a CURRENT file"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: vec![
+            FastFileMatcher::FileExtension("ldb".to_owned()),
+            FastFileMatcher::ExtensionWithSibling("sst".to_owned(), DATABASE_MARKER.to_owned()),
+            FastFileMatcher::ExtensionWithSibling("log".to_owned(), DATABASE_MARKER.to_owned()),
+        ],
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct LeveldbAdapter;
+
+impl LeveldbAdapter {
+    pub fn new() -> LeveldbAdapter {
+        LeveldbAdapter
+    }
+}
+
+impl GetMetadata for LeveldbAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const LEVELDB_MAGIC: u64 = 0xdb4775248b80fb57;
+const ROCKSDB_MAGIC: u64 = 0x88e241b785f4cff7;
+/// compression type and checksum after each block
+const BLOCK_TRAILER_SIZE: u64 = 5;
+const LOG_BLOCK_SIZE: usize = 32 * 1024;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("unexpected end of data")?;
+        self.pos += len;
+        Ok(b)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("invalid varint")
+    }
+
+    fn varint_usize(&mut self) -> Result<usize> {
+        Ok(self.varint()?.try_into()?)
+    }
+
+    /// Length-prefixed bytes
+    fn slice(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint_usize()?;
+        self.bytes(len)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Entry {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+#[derive(Clone, Copy)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    fn read(r: &mut Reader) -> Result<BlockHandle> {
+        Ok(BlockHandle {
+            offset: r.varint()?,
+            size: r.varint()?,
+        })
+    }
+}
+
+/// A table file (`.ldb` of LevelDB, `.sst` of RocksDB)
+struct Table<'a> {
+    data: &'a [u8],
+    rocksdb: bool,
+    format_version: u32,
+}
+
+impl<'a> Table<'a> {
+    /// The table and the handle of its index block, from the footer at the end of the file
+    fn open(data: &'a [u8]) -> Result<(Table<'a>, BlockHandle)> {
+        anyhow::ensure!(data.len() >= 48, "file too short for a table");
+        let magic = u64::from_le_bytes(data[data.len() - 8..].try_into()?);
+        let (rocksdb, format_version, footer) = match magic {
+            LEVELDB_MAGIC => (false, 0, &data[data.len() - 48..]),
+            ROCKSDB_MAGIC => {
+                anyhow::ensure!(data.len() >= 53, "file too short for a table");
+                let version = u32::from_le_bytes(data[data.len() - 12..][..4].try_into()?);
+                anyhow::ensure!(
+                    version < 6,
+                    "unsupported RocksDB table format version {version}"
+                );
+                // after the checksum type
+                (true, version, &data[data.len() - 52..])
+            }
+            _ => anyhow::bail!("not a LevelDB/RocksDB table"),
+        };
+        let mut r = Reader {
+            data: footer,
+            pos: 0,
+        };
+        let _metaindex = BlockHandle::read(&mut r)?;
+        let index = BlockHandle::read(&mut r)?;
+        Ok((
+            Table {
+                data,
+                rocksdb,
+                format_version,
+            },
+            index,
+        ))
+    }
+
+    async fn block(&self, handle: BlockHandle) -> Result<Vec<u8>> {
+        let start: usize = handle.offset.try_into()?;
+        let end = start.saturating_add(handle.size.try_into()?);
+        let raw = self.data.get(start..end).context("block out of range")?;
+        let compression = *self.data.get(end).context("block out of range")?;
+        let prefixed = || strip_size_prefix(raw, self.format_version);
+        Ok(match (compression, self.rocksdb) {
+            (0, _) => raw.to_vec(),
+            (1, _) => snappy_decompress(raw)?,
+            (2, false) => read_all(ZstdDecoder::new(raw)).await?,
+            (2, true) => read_all(DeflateDecoder::new(prefixed()?)).await?,
+            (3, true) => read_all(BzDecoder::new(prefixed()?)).await?,
+            (4 | 5, true) => {
+                anyhow::ensure!(self.format_version >= 2, "unsupported lz4 block format");
+                let mut out = Vec::new();
+                super::lz4::decompress_block(prefixed()?, &mut out)?;
+                out
+            }
+            (7, true) => read_all(ZstdDecoder::new(prefixed()?)).await?,
+            (c, _) => anyhow::bail!("unsupported block compression {c}"),
+        })
+    }
+}
+
+/// RocksDB prefixes compressed blocks (other than snappy) with their decompressed size
+fn strip_size_prefix(raw: &[u8], format_version: u32) -> Result<&[u8]> {
+    let mut r = Reader { data: raw, pos: 0 };
+    if format_version >= 2 {
+        r.varint()?;
+    }
+    Ok(&raw[r.pos..])
+}
+
+/// The end of the entries of a block, which are followed by the restart points
+fn entries_end(block: &[u8]) -> Result<usize> {
+    anyhow::ensure!(block.len() >= 4, "block too short");
+    let footer = u32::from_le_bytes(block[block.len() - 4..].try_into()?);
+    // RocksDB blocks with a hash index have the high bit set and the hash buckets before the footer
+    let num_restarts = (footer & 0x7fff_ffff) as usize;
+    let mut end = block.len() - 4;
+    if footer & 0x8000_0000 != 0 {
+        anyhow::ensure!(end >= 2, "block too short");
+        let buckets = u16::from_le_bytes(block[end - 2..end].try_into()?) as usize;
+        end = end.checked_sub(2 + buckets).context("invalid block")?;
+    }
+    end.checked_sub(4 * num_restarts).context("invalid block")
+}
+
+/// The keys and values of a block, with the keys prefix compressed
+fn block_entries(block: &[u8]) -> Result<Vec<(Vec<u8>, &[u8])>> {
+    let mut r = Reader {
+        data: &block[..entries_end(block)?],
+        pos: 0,
+    };
+    let mut entries = Vec::new();
+    let mut key = Vec::new();
+    while !r.at_end() {
+        let shared = r.varint_usize()?;
+        let non_shared = r.varint_usize()?;
+        let value_len = r.varint_usize()?;
+        anyhow::ensure!(shared <= key.len(), "invalid key prefix");
+        key.truncate(shared);
+        key.extend_from_slice(r.bytes(non_shared)?);
+        entries.push((key.clone(), r.bytes(value_len)?));
+    }
+    Ok(entries)
+}
+
+/// The handles of the data blocks in an index block.
+///
+/// RocksDB tables since format version 4 only store the size difference to the previous block after the first entry of each restart interval
+fn index_handles(block: &[u8], delta_encoded: bool) -> Result<Vec<BlockHandle>> {
+    if !delta_encoded {
+        return block_entries(block)?
+            .into_iter()
+            .map(|(_, value)| {
+                BlockHandle::read(&mut Reader {
+                    data: value,
+                    pos: 0,
+                })
+            })
+            .collect();
+    }
+    let mut r = Reader {
+        data: &block[..entries_end(block)?],
+        pos: 0,
+    };
+    let mut handles: Vec<BlockHandle> = Vec::new();
+    while !r.at_end() {
+        let shared = r.varint_usize()?;
+        let non_shared = r.varint_usize()?;
+        r.bytes(non_shared)?;
+        let handle = match handles.last() {
+            Some(prev) if shared > 0 => {
+                let delta = r.varint()?;
+                // zigzag encoded
+                let delta = (delta >> 1) as i64 ^ -((delta & 1) as i64);
+                BlockHandle {
+                    offset: prev.offset + prev.size + BLOCK_TRAILER_SIZE,
+                    size: prev
+                        .size
+                        .checked_add_signed(delta)
+                        .context("invalid size")?,
+                }
+            }
+            _ => BlockHandle::read(&mut r)?,
+        };
+        handles.push(handle);
+    }
+    Ok(handles)
+}
+
+async fn table_entries(data: &[u8]) -> Result<Vec<Entry>> {
+    let (table, index) = Table::open(data)?;
+    let index = table.block(index).await?;
+    let delta_encoded = table.rocksdb && table.format_version >= 4;
+    let mut entries = Vec::new();
+    for handle in index_handles(&index, delta_encoded)? {
+        let block = table.block(handle).await?;
+        for (key, value) in block_entries(&block)? {
+            // internal keys end with the sequence number and the type of the entry
+            anyhow::ensure!(key.len() >= 8, "invalid internal key");
+            let (user_key, trailer) = key.split_at(key.len() - 8);
+            entries.push(match trailer[0] {
+                // deletion, single deletion
+                0 | 7 => Entry::Delete(user_key.to_vec()),
+                _ => Entry::Put(user_key.to_vec(), value.to_vec()),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The records of a write-ahead log, which are split into fragments to fit into blocks
+fn log_records(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut pos = 0;
+    while pos + 7 <= data.len() {
+        let block_left = LOG_BLOCK_SIZE - pos % LOG_BLOCK_SIZE;
+        if block_left < 7 {
+            // padding at the end of a block
+            pos += block_left;
+            continue;
+        }
+        let len = u16::from_le_bytes([data[pos + 4], data[pos + 5]]) as usize;
+        let kind = data[pos + 6];
+        // RocksDB's recyclable records have the log number in their header
+        let (header, kind) = match kind {
+            5..=8 => (11, kind - 4),
+            kind => (7, kind),
+        };
+        if kind == 0 {
+            // preallocated space
+            pos += block_left;
+            continue;
+        }
+        let Some(fragment) = data.get(pos + header..pos + header + len) else {
+            break;
+        };
+        pos += header + len;
+        match kind {
+            1 => records.push(fragment.to_vec()),
+            2 => record = fragment.to_vec(),
+            3 => record.extend_from_slice(fragment),
+            4 => {
+                record.extend_from_slice(fragment);
+                records.push(std::mem::take(&mut record));
+            }
+            _ => {}
+        }
+    }
+    records
+}
+
+/// The entries of a write batch, the content of a log record
+fn batch_entries(record: &[u8], entries: &mut Vec<Entry>) -> Result<()> {
+    let mut r = Reader {
+        data: record,
+        pos: 0,
+    };
+    // sequence number and count
+    r.bytes(12)?;
+    while !r.at_end() {
+        let tag = r.byte()?;
+        // column family
+        if matches!(tag, 4 | 5 | 6 | 8 | 14) {
+            r.varint()?;
+        }
+        match tag {
+            // value, merge
+            1 | 2 | 4 | 6 => {
+                let key = r.slice()?;
+                entries.push(Entry::Put(key.to_vec(), r.slice()?.to_vec()));
+            }
+            // deletion, single deletion
+            0 | 5 | 7 | 8 => entries.push(Entry::Delete(r.slice()?.to_vec())),
+            // range deletion
+            14 | 15 => {
+                let begin = display(r.slice()?);
+                let end = display(r.slice()?);
+                entries.push(Entry::Delete(format!("{begin}..{end}").into_bytes()));
+            }
+            // log data
+            3 => {
+                r.slice()?;
+            }
+            tag => anyhow::bail!("unsupported write batch entry {tag}"),
+        }
+    }
+    Ok(())
+}
+
+/// Keys and values as text, with control characters and non-text bytes escaped
+pub fn display(b: &[u8]) -> String {
+    let escape = |c: char| {
+        if c.is_control() {
+            format!("\\x{:02x}", c as u32)
+        } else {
+            c.to_string()
+        }
+    };
+    // Chrome's local storage stores strings as UTF-16 prefixed with a zero byte
+    if let Some(rest) = b.strip_prefix(&[0]).filter(|r| r.len() % 2 == 0) {
+        let (s, had_errors) = encoding_rs::UTF_16LE.decode_without_bom_handling(rest);
+        if !had_errors && !s.chars().any(char::is_control) {
+            return s.into_owned();
+        }
+    }
+    if let Ok(s) = std::str::from_utf8(b) {
+        return s.chars().map(escape).collect();
+    }
+    let printable = b
+        .iter()
+        .filter(|c| c.is_ascii_graphic() || **c == b' ')
+        .count();
+    if b.len() > 16 && printable < b.len() / 2 {
+        return format!("<binary, {} bytes>", b.len());
+    }
+    b.iter()
+        .map(|c| {
+            if c.is_ascii_graphic() || *c == b' ' {
+                (*c as char).to_string()
+            } else {
+                format!("\\x{c:02x}")
+            }
+        })
+        .collect()
+}
+
+fn format_entries(entries: &[Entry], line_prefix: &str) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        let line = match entry {
+            Entry::Put(key, value) => format!("{} = {}", display(key), display(value)),
+            Entry::Delete(key) => format!("{} (deleted)", display(key)),
+        };
+        out.push_str(&format!("{line_prefix}{line}\n"));
+    }
+    out
+}
+
+#[async_trait]
+impl WritingFileAdapter for LeveldbAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let is_log = matches!(detection_reason, FileMatcher::Fast(FastFileMatcher::ExtensionWithSibling(ext, _)) if ext == "log");
+        let entries = if is_log {
+            let mut entries = Vec::new();
+            for record in log_records(&data) {
+                batch_entries(&record, &mut entries)?;
+            }
+            entries
+        } else {
+            table_entries(&data).await?
+        };
+        oup.write_all(format_entries(&entries, &line_prefix).as_bytes())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{matching::matches_extension_with_sibling, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn varint(mut v: u64, out: &mut Vec<u8>) {
+        while v >= 0x80 {
+            out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    /// A block with a single restart point, without prefix compression
+    fn block(entries: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut b = Vec::new();
+        for (key, value) in entries {
+            varint(0, &mut b);
+            varint(key.len() as u64, &mut b);
+            varint(value.len() as u64, &mut b);
+            b.extend_from_slice(key);
+            b.extend_from_slice(value);
+        }
+        b.extend(0u32.to_le_bytes());
+        b.extend(1u32.to_le_bytes());
+        b
+    }
+
+    fn internal_key(key: &[u8], seq: u64, kind: u8) -> Vec<u8> {
+        let mut k = key.to_vec();
+        k.extend(((seq << 8) | kind as u64).to_le_bytes());
+        k
+    }
+
+    /// Append an uncompressed block with its trailer, returns its handle
+    fn append_block(file: &mut Vec<u8>, block: &[u8]) -> Vec<u8> {
+        let mut handle = Vec::new();
+        varint(file.len() as u64, &mut handle);
+        varint(block.len() as u64, &mut handle);
+        file.extend_from_slice(block);
+        file.extend([0, 0, 0, 0, 0]);
+        handle
+    }
+
+    #[tokio::test]
+    async fn table() -> Result<()> {
+        let (k1, k2) = (internal_key(b"user:1", 5, 1), internal_key(b"user:2", 6, 0));
+        let mut utf16 = vec![0];
+        utf16.extend("Jürgen".encode_utf16().flat_map(u16::to_le_bytes));
+        let data = block(&[(k1.as_slice(), utf16.as_slice()), (k2.as_slice(), &[])]);
+        let mut file = Vec::new();
+        let data_handle = append_block(&mut file, &data);
+        let metaindex_handle = append_block(&mut file, &block(&[]));
+        let index_handle = append_block(
+            &mut file,
+            &block(&[(k2.as_slice(), data_handle.as_slice())]),
+        );
+        let mut footer = [metaindex_handle, index_handle].concat();
+        footer.resize(40, 0);
+        footer.extend(LEVELDB_MAGIC.to_le_bytes());
+        file.extend(footer);
+
+        let (a, d) = simple_adapt_info(&PathBuf::from("000005.ldb"), Box::pin(Cursor::new(file)));
+        let buf = adapted_to_vec(LeveldbAdapter::new().adapt(a, &d).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:user:1 = Jürgen\nPREFIX:user:2 (deleted)\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn log() -> Result<()> {
+        let mut batch = Vec::new();
+        batch.extend(1u64.to_le_bytes());
+        batch.extend(2u32.to_le_bytes());
+        batch.push(1);
+        varint(4, &mut batch);
+        batch.extend_from_slice(b"_key");
+        varint(6, &mut batch);
+        batch.extend_from_slice(b"\x01value");
+        batch.push(0);
+        varint(3, &mut batch);
+        batch.extend_from_slice(b"old");
+        let mut log = vec![0, 0, 0, 0];
+        log.extend((batch.len() as u16).to_le_bytes());
+        log.push(1);
+        log.extend(batch);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("000003.log");
+        std::fs::write(&path, &log)?;
+        assert!(!matches_extension_with_sibling(
+            "log",
+            DATABASE_MARKER,
+            &path
+        ));
+        std::fs::write(dir.path().join(DATABASE_MARKER), "MANIFEST-000001\n")?;
+        assert!(matches_extension_with_sibling(
+            "log",
+            DATABASE_MARKER,
+            &path
+        ));
+        // not selected by the pre-glob, only found with --rga-accurate
+        let adapter: Arc<dyn FileAdapter> = Arc::new(LeveldbAdapter::new());
+        assert!(!found_by(adapter.clone(), &path, false)?);
+        assert!(found_by(adapter, &path, true)?);
+
+        let (a, _) = simple_fs_adapt_info(&path).await?;
+        let matcher = FileMatcher::Fast(FastFileMatcher::ExtensionWithSibling(
+            "log".to_owned(),
+            DATABASE_MARKER.to_owned(),
+        ));
+        let buf = adapted_to_vec(LeveldbAdapter::new().adapt(a, &matcher).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:_key = \\x01value\nPREFIX:old (deleted)\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+use super::binary::{u16_at, u32_at, u64_at};
+use super::leveldb::display;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+/// The lock file next to the data file of an LMDB environment
+const LOCK_FILE: &str = "lock.mdb";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "lmdb".to_owned(),
+        version: 1,
+        description: "Reads the data file (data.mdb) of LMDB environments and outputs each entry as `key = value`, prefixed with the name of the database for named databases. Binary keys and values are escaped.\nOnly found with `--rga-accurate`, since data.mdb is only read next to lock.mdb"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        // .mdb alone would also match Microsoft Access databases
+        fast_matchers: vec![FastFileMatcher::ExtensionWithSibling(
+            "mdb".to_owned(),
+            LOCK_FILE.to_owned()
+        )],
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct LmdbAdapter;
+
+impl LmdbAdapter {
+    pub fn new() -> LmdbAdapter {
+        LmdbAdapter
+    }
+}
+
+impl GetMetadata for LmdbAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAGIC: u32 = 0xBEEFC0DE;
+const PAGE_HEADER_SIZE: usize = 16;
+const NODE_HEADER_SIZE: usize = 8;
+/// root of an empty database
+const P_INVALID: u64 = u64::MAX;
+const P_BRANCH: u16 = 0x01;
+const P_LEAF2: u16 = 0x20;
+const F_BIGDATA: u16 = 0x01;
+const F_SUBDATA: u16 = 0x02;
+const F_DUPDATA: u16 = 0x04;
+/// B-trees deeper than this are broken (or cyclic)
+const MAX_DEPTH: usize = 64;
+const TRUNCATED: &str = "truncated page";
+
+/// The root page of a database record (`MDB_db`)
+fn db_root(db: &[u8]) -> Result<u64> {
+    u64_at(db, 40).context(TRUNCATED)
+}
+
+struct Node<'a> {
+    flags: u16,
+    key: &'a [u8],
+    /// the value, or the number of its first overflow page for big values
+    data: &'a [u8],
+    /// lo and hi of the header: the size of the value, or the child page in branch pages
+    size: u64,
+}
+
+fn page_flags(page: &[u8]) -> Result<u16> {
+    u16_at(page, 10).context(TRUNCATED)
+}
+
+fn num_keys(page: &[u8]) -> Result<usize> {
+    Ok((u16_at(page, 12).context(TRUNCATED)? as usize).saturating_sub(PAGE_HEADER_SIZE) / 2)
+}
+
+fn nodes(page: &[u8]) -> Result<Vec<Node<'_>>> {
+    let is_branch = page_flags(page)? & P_BRANCH != 0;
+    (0..num_keys(page)?)
+        .map(|i| {
+            let offset = u16_at(page, PAGE_HEADER_SIZE + 2 * i).context(TRUNCATED)? as usize;
+            let header = page
+                .get(offset..offset + NODE_HEADER_SIZE)
+                .context("invalid node offset")?;
+            let flags = u16_at(header, 4).context(TRUNCATED)?;
+            let key_size = u16_at(header, 6).context(TRUNCATED)? as usize;
+            let mut size = u16_at(header, 0).context(TRUNCATED)? as u64
+                | ((u16_at(header, 2).context(TRUNCATED)? as u64) << 16);
+            if is_branch {
+                size |= (flags as u64) << 32;
+            }
+            let key_start = offset + NODE_HEADER_SIZE;
+            let key = page
+                .get(key_start..key_start + key_size)
+                .context("invalid key size")?;
+            let data_len = if is_branch {
+                0
+            } else if flags & F_BIGDATA != 0 {
+                8
+            } else {
+                size as usize
+            };
+            let data = page
+                .get(key_start + key_size..key_start + key_size + data_len)
+                .context("invalid data size")?;
+            Ok(Node {
+                flags,
+                key,
+                data,
+                size,
+            })
+        })
+        .collect()
+}
+
+/// The keys of a leaf page, which are the values of duplicate keys in sub-pages and sub-databases
+fn page_keys(page: &[u8]) -> Result<Vec<Vec<u8>>> {
+    if page_flags(page)? & P_LEAF2 != 0 {
+        // fixed size keys without node headers
+        let key_size = u16_at(page, 8).context(TRUNCATED)? as usize;
+        return (0..num_keys(page)?)
+            .map(|i| {
+                let start = PAGE_HEADER_SIZE + i * key_size;
+                Ok(page
+                    .get(start..start + key_size)
+                    .context(TRUNCATED)?
+                    .to_vec())
+            })
+            .collect();
+    }
+    Ok(nodes(page)?.iter().map(|n| n.key.to_vec()).collect())
+}
+
+struct Environment<R> {
+    file: R,
+    page_size: usize,
+}
+
+impl<R: Read + Seek> Environment<R> {
+    /// The environment and the main database record of the newest of the two meta pages
+    fn open(mut file: R) -> Result<(Environment<R>, Vec<u8>)> {
+        let mut metas = Vec::new();
+        let mut offset = 0;
+        for _ in 0..2 {
+            let mut meta = vec![0; PAGE_HEADER_SIZE + 136];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut meta)?;
+            anyhow::ensure!(
+                u32_at(&meta, 16).context(TRUNCATED)? == MAGIC,
+                "not an LMDB data file"
+            );
+            // stored in the padding of the free page database
+            let page_size = u32_at(&meta, 40).context(TRUNCATED)?;
+            anyhow::ensure!(page_size >= 512, "invalid page size {page_size}");
+            offset = page_size.into();
+            metas.push(meta);
+        }
+        let meta = metas
+            .into_iter()
+            .max_by_key(|m| u64_at(m, 144).unwrap_or_default())
+            .expect("two meta pages");
+        let env = Environment {
+            file,
+            page_size: offset as usize,
+        };
+        Ok((env, meta[88..136].to_vec()))
+    }
+
+    fn read_pages(&mut self, pgno: u64, count: usize) -> Result<Vec<u8>> {
+        let mut page = vec![0; self.page_size * count];
+        let offset = pgno
+            .checked_mul(self.page_size as u64)
+            .context("invalid page number")?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file
+            .read_exact(&mut page)
+            .with_context(|| format!("reading page {pgno}"))?;
+        Ok(page)
+    }
+
+    /// Call `f` with each leaf page of the B-tree with the given root, in order
+    fn for_each_leaf(
+        &mut self,
+        root: u64,
+        depth: usize,
+        f: &mut dyn FnMut(&mut Self, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        anyhow::ensure!(depth < MAX_DEPTH, "database nested too deeply");
+        if root == P_INVALID {
+            return Ok(());
+        }
+        let page = self.read_pages(root, 1)?;
+        if page_flags(&page)? & P_BRANCH == 0 {
+            return f(self, &page);
+        }
+        for child in nodes(&page)?.iter().map(|n| n.size) {
+            self.for_each_leaf(child, depth + 1, f)?;
+        }
+        Ok(())
+    }
+
+    /// A value stored in overflow pages
+    fn big_value(&mut self, pgno: u64, size: usize) -> Result<Vec<u8>> {
+        let count = (PAGE_HEADER_SIZE + size).div_ceil(self.page_size);
+        let pages = self.read_pages(pgno, count)?;
+        Ok(pages[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + size].to_vec())
+    }
+
+    fn dump(&mut self, root: u64, prefix: &str, is_main: bool, out: &mut dyn Write) -> Result<()> {
+        self.for_each_leaf(root, 0, &mut |env, page| {
+            for node in nodes(page)? {
+                let key = display(node.key);
+                let values =
+                    if node.flags & F_DUPDATA != 0 {
+                        if node.flags & F_SUBDATA != 0 {
+                            let mut values = Vec::new();
+                            env.for_each_leaf(db_root(node.data)?, 0, &mut |_, p| {
+                                values.extend(page_keys(p)?);
+                                Ok(())
+                            })?;
+                            values
+                        } else {
+                            page_keys(node.data)?
+                        }
+                    } else if node.flags & F_SUBDATA != 0 && is_main {
+                        // a named database
+                        env.dump(db_root(node.data)?, &format!("{prefix}{key}: "), false, out)?;
+                        continue;
+                    } else if node.flags & F_BIGDATA != 0 {
+                        vec![env.big_value(
+                            u64_at(node.data, 0).context(TRUNCATED)?,
+                            node.size as usize,
+                        )?]
+                    } else {
+                        vec![node.data.to_vec()]
+                    };
+                for value in values {
+                    writeln!(out, "{prefix}{key} = {}", display(&value))?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn synchronous_dump_lmdb(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
+    let AdaptInfo {
+        is_real_file,
+        filepath_hint,
+        line_prefix,
+        ..
+    } = ai;
+    if !is_real_file {
+        writeln!(s, "{line_prefix}[rga: skipping lmdb in archive]")?;
+        return Ok(());
+    }
+    // opened directly, since LMDB's own lock file protocol would need write access
+    let file = File::open(&filepath_hint)
+        .with_context(|| format!("opening {}", filepath_hint.display()))?;
+    let (mut env, main) = Environment::open(std::io::BufReader::new(file))?;
+    env.dump(db_root(&main)?, &line_prefix, true, &mut s)?;
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for LmdbAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(|| synchronous_dump_lmdb(ai, oup_sync))
+            .await?
+            .context("in synchronous lmdb task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    const PAGE_SIZE: usize = 4096;
+
+    fn page(pgno: u64, flags: u16) -> Vec<u8> {
+        let mut p = vec![0; PAGE_SIZE];
+        p[..8].copy_from_slice(&pgno.to_le_bytes());
+        p[10..12].copy_from_slice(&flags.to_le_bytes());
+        p
+    }
+
+    fn db(root: u64) -> Vec<u8> {
+        let mut db = vec![0; 48];
+        db[40..].copy_from_slice(&root.to_le_bytes());
+        db
+    }
+
+    /// A leaf page with the given nodes, stored from the end of the page
+    fn leaf(pgno: u64, entries: &[(&[u8], u16, &[u8])]) -> Vec<u8> {
+        let mut p = page(pgno, 0x02);
+        let mut upper = PAGE_SIZE;
+        for (i, (key, flags, data)) in entries.iter().enumerate() {
+            let mut node = Vec::new();
+            node.extend((data.len() as u16).to_le_bytes());
+            node.extend(0u16.to_le_bytes());
+            node.extend(flags.to_le_bytes());
+            node.extend((key.len() as u16).to_le_bytes());
+            node.extend_from_slice(key);
+            node.extend_from_slice(data);
+            upper -= node.len();
+            p[upper..upper + node.len()].copy_from_slice(&node);
+            let ptr = PAGE_HEADER_SIZE + 2 * i;
+            p[ptr..ptr + 2].copy_from_slice(&(upper as u16).to_le_bytes());
+        }
+        let lower = (PAGE_HEADER_SIZE + 2 * entries.len()) as u16;
+        p[12..14].copy_from_slice(&lower.to_le_bytes());
+        p[14..16].copy_from_slice(&(upper as u16).to_le_bytes());
+        p
+    }
+
+    fn meta(pgno: u64, txnid: u64, main_root: u64) -> Vec<u8> {
+        let mut p = page(pgno, 0x08);
+        p[16..20].copy_from_slice(&MAGIC.to_le_bytes());
+        p[20..24].copy_from_slice(&1u32.to_le_bytes());
+        let mut free = db(P_INVALID);
+        free[..4].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        p[40..88].copy_from_slice(&free);
+        p[88..136].copy_from_slice(&db(main_root));
+        p[144..152].copy_from_slice(&txnid.to_le_bytes());
+        p
+    }
+
+    #[tokio::test]
+    async fn environment() -> Result<()> {
+        let mut data = Vec::new();
+        // the second meta page is newer and points to the current root
+        data.extend(meta(0, 1, P_INVALID));
+        data.extend(meta(1, 2, 2));
+        data.extend(leaf(
+            2,
+            &[
+                (b"config", 0, b"{\"theme\":\"dark\"}"),
+                (b"users", F_SUBDATA, &db(3)),
+            ],
+        ));
+        data.extend(leaf(3, &[(b"alice", 0, b"alice@example.com\0")]));
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.mdb");
+        std::fs::write(&path, &data)?;
+        let (a, _) = simple_fs_adapt_info(&path).await?;
+        let matcher = METADATA.fast_matchers[0].clone().into();
+        let buf = adapted_to_vec(LmdbAdapter::new().adapt(a, &matcher).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            r#"PREFIX:config = {"theme":"dark"}
+PREFIX:users: alice = alice@example.com\x00
+"#
+        );
+        Ok(())
+    }
+}
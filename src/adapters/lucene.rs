@@ -0,0 +1,515 @@
+use super::{strings::write_strings, writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_stream::stream;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::{BufRead, BufReader, Read, Write};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+/// stored fields, the other segment files have extensions that are too generic, so they are found by their header with `--rga-accurate`
+static EXTENSIONS: &[&str] = &["fdt"];
+/// stored fields and term dictionaries, the segment files within compound files that are output
+static SEGMENT_FILES: &[&str] = &["fdt", "tim"];
+/// for the codec header at the start of every segment file, see `CODEC_MAGIC`
+pub const MIMETYPE: &str = "application/x-lucene-segment";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "lucene".to_owned(),
+        version: 1,
+        description: "Dumps Lucene index segments (e.g. from an Elasticsearch / OpenSearch / Solr data directory) as text.\nStored fields (.fdt, Lucene 9 with the default LZ4 compression) are output one value per line, other segment files (term dictionaries, older formats) as the strings they contain.\nCompound files (.cfs) are split into their segment files using the .cfe file next to them.\nOnly .fdt files are found by their extension, the other segment files (e.g. .tim, .cfs) by their header with `--rga-accurate`"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(MIMETYPE.to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default)]
+pub struct LuceneAdapter;
+
+impl LuceneAdapter {
+    pub fn new() -> LuceneAdapter {
+        LuceneAdapter
+    }
+}
+impl GetMetadata for LuceneAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Dumps a single segment file, see `dump_segment_file`
+#[derive(Default, Clone)]
+struct LuceneFileAdapter;
+
+impl GetMetadata for LuceneFileAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+pub const CODEC_MAGIC: u32 = 0x3fd76c17;
+const FOOTER_LEN: usize = 16;
+const STORED_FIELDS_LZ4: &str = "Lucene90StoredFieldsFastData";
+/// minimum length of a LZ4 match
+const MIN_MATCH: usize = 4;
+
+// types of stored field values
+const STRING: u64 = 0;
+const BYTE_ARR: u64 = 1;
+const NUMERIC_INT: u64 = 2;
+
+fn read_u8(r: &mut impl Read) -> std::io::Result<u8> {
+    let mut b = [0u8];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn read_vlong(r: &mut impl Read) -> std::io::Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = read_u8(r)?;
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "invalid vint",
+    ))
+}
+
+fn read_vint(r: &mut impl Read) -> Result<usize> {
+    Ok(usize::try_from(read_vlong(r)?)?)
+}
+
+fn read_string(r: &mut impl Read) -> Result<String> {
+    let len = read_vint(r)?;
+    let mut b = vec![0u8; len];
+    r.read_exact(&mut b)?;
+    Ok(String::from_utf8_lossy(&b).into_owned())
+}
+
+/// Read a CodecUtil index header, returns the codec name and version
+fn read_index_header(r: &mut impl Read) -> Result<(String, u32)> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    if u32::from_be_bytes(b) != CODEC_MAGIC {
+        anyhow::bail!("not a Lucene index file");
+    }
+    let codec = read_string(r)?;
+    r.read_exact(&mut b)?;
+    let version = u32::from_be_bytes(b);
+    // segment id and suffix
+    let mut id = [0u8; 16];
+    r.read_exact(&mut id)?;
+    let suffix_len = read_u8(r)?;
+    std::io::copy(&mut r.take(suffix_len as u64), &mut std::io::sink())?;
+    Ok((codec, version))
+}
+
+/// Leaves out the CodecUtil footer at the end of the input
+struct WithoutFooter<R> {
+    inner: R,
+    pending: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Read for WithoutFooter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while !self.eof && self.pending.len() < FOOTER_LEN + buf.len() {
+            let start = self.pending.len();
+            self.pending.resize(FOOTER_LEN + buf.len(), 0);
+            let n = self.inner.read(&mut self.pending[start..])?;
+            self.pending.truncate(start + n);
+            self.eof = n == 0;
+        }
+        let n = self.pending.len().saturating_sub(FOOTER_LEN).min(buf.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Decompress a LZ4 block of `len` bytes, appending it to `dest` (which holds the preset dictionary).
+fn lz4_decompress(compressed: &[u8], len: usize, dest: &mut Vec<u8>) -> Result<()> {
+    let end = dest.len() + len;
+    /// lengths of 15 are continued in the following bytes
+    fn ext_len(c: &mut &[u8], mut n: usize) -> Result<usize> {
+        if n == 0x0f {
+            loop {
+                let b = read_u8(c)?;
+                n += b as usize;
+                if b != 0xff {
+                    break;
+                }
+            }
+        }
+        Ok(n)
+    }
+    let mut c = compressed;
+    while dest.len() < end {
+        let token = read_u8(&mut c)?;
+        let literals = ext_len(&mut c, (token >> 4) as usize)?;
+        let lit = c.get(..literals).context("truncated LZ4 literals")?;
+        dest.extend_from_slice(lit);
+        c = &c[literals..];
+        if dest.len() >= end {
+            break;
+        }
+        let offset = u16::from_le_bytes([read_u8(&mut c)?, read_u8(&mut c)?]) as usize;
+        let match_len = ext_len(&mut c, (token & 0x0f) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > dest.len() {
+            anyhow::bail!("invalid LZ4 match offset");
+        }
+        // matches can overlap with their own output
+        for _ in 0..match_len {
+            dest.push(dest[dest.len() - offset]);
+        }
+    }
+    dest.truncate(end);
+    Ok(())
+}
+
+/// Sum of the per-document values (number of fields / byte lengths) in a chunk header
+fn read_ints_sum(r: &mut impl Read, count: usize) -> Result<usize> {
+    if count == 1 {
+        return read_vint(r);
+    }
+    let width = match read_u8(r)? {
+        0 => return Ok(read_vint(r)? * count),
+        8 => 1,
+        16 => 2,
+        32 => 4,
+        bpv => anyhow::bail!("invalid bits per value {bpv}"),
+    };
+    let mut b = vec![0u8; count * width];
+    r.read_exact(&mut b)?;
+    Ok(b.chunks_exact(width)
+        .map(|v| v.iter().rev().fold(0usize, |a, b| a << 8 | *b as usize))
+        .sum())
+}
+
+/// Decompress the data of a chunk written by LZ4WithPresetDictCompressionMode
+fn decompress_chunk(r: &mut impl Read, len: usize) -> Result<Vec<u8>> {
+    let dict_len = read_vint(r)?;
+    let block_len = read_vint(r)?;
+    let num_blocks = match block_len {
+        0 => 0,
+        _ => len.saturating_sub(dict_len).div_ceil(block_len),
+    };
+    let compressed_lens = (0..=num_blocks)
+        .map(|_| read_vint(r))
+        .collect::<Result<Vec<_>>>()?;
+    let mut read_compressed = |n: usize| -> Result<Vec<u8>> {
+        let mut b = vec![0u8; n];
+        r.read_exact(&mut b)?;
+        Ok(b)
+    };
+    let mut dict = Vec::with_capacity(dict_len);
+    lz4_decompress(&read_compressed(compressed_lens[0])?, dict_len, &mut dict)?;
+    let mut out = dict.clone();
+    for (i, compressed_len) in compressed_lens[1..].iter().enumerate() {
+        let block = block_len.min(len - dict_len - i * block_len);
+        let mut buf = dict.clone();
+        lz4_decompress(&read_compressed(*compressed_len)?, block, &mut buf)?;
+        out.extend_from_slice(&buf[dict_len..]);
+    }
+    Ok(out)
+}
+
+/// Output the values of `num_fields` stored fields from decompressed chunk data.
+///
+/// Returns false if a value could not be decoded (floating point and long values are not supported).
+fn write_stored_fields(
+    mut data: &[u8],
+    num_fields: usize,
+    line_prefix: &str,
+    out: &mut impl Write,
+) -> Result<bool> {
+    for _ in 0..num_fields {
+        let info = read_vlong(&mut data)?;
+        let field = info >> 3;
+        let value = match info & 7 {
+            STRING | BYTE_ARR => {
+                let len = read_vint(&mut data)?;
+                let value = data.get(..len).context("truncated field value")?;
+                data = &data[len..];
+                match std::str::from_utf8(value) {
+                    Ok(s) => s.to_string(),
+                    // binary values (e.g. encoded ids)
+                    Err(_) => continue,
+                }
+            }
+            NUMERIC_INT => {
+                let v = read_vlong(&mut data)? as u32;
+                // zig-zag encoded
+                ((v >> 1) as i32 ^ -((v & 1) as i32)).to_string()
+            }
+            _ => return Ok(false),
+        };
+        for line in value.lines() {
+            writeln!(out, "{line_prefix}field {field}: {line}")?;
+        }
+    }
+    Ok(true)
+}
+
+/// Output the stored fields of a Lucene 9 .fdt file, starting after the header
+fn dump_stored_fields(
+    r: &mut BufReader<impl Read>,
+    min_len: usize,
+    line_prefix: &str,
+    out: &mut impl Write,
+) -> Result<()> {
+    while !r.fill_buf()?.is_empty() {
+        let _doc_base = read_vint(r)?;
+        let token = read_vint(r)?;
+        let num_docs = token >> 2;
+        let sliced = token & 1 != 0;
+        let num_fields = read_ints_sum(r, num_docs)?;
+        let len = read_ints_sum(r, num_docs)?;
+        if sliced {
+            // chunks of single huge documents are compressed in slices of the (unknown) chunk size
+            writeln!(
+                out,
+                "{line_prefix}[lucene] large documents, showing strings"
+            )?;
+            return write_strings(r, min_len, line_prefix, out);
+        }
+        let data = decompress_chunk(r, len)?;
+        if !write_stored_fields(&data, num_fields, line_prefix, out)? {
+            write_strings(&data[..], min_len, line_prefix, &mut *out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Output a segment file: a line with the codec, then the stored fields or the strings in the file
+fn dump_segment_file(
+    inp: impl Read,
+    min_len: usize,
+    line_prefix: &str,
+    mut out: impl Write,
+) -> Result<()> {
+    let mut r = BufReader::with_capacity(
+        1 << 16,
+        WithoutFooter {
+            inner: inp,
+            pending: Vec::new(),
+            eof: false,
+        },
+    );
+    let (codec, version) = read_index_header(&mut r)?;
+    writeln!(out, "{line_prefix}[lucene] {codec} version {version}")?;
+    if codec == STORED_FIELDS_LZ4 {
+        dump_stored_fields(&mut r, min_len, line_prefix, &mut out)
+    } else {
+        write_strings(r, min_len, line_prefix, out)
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for LuceneFileAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let inp = SyncIoBridge::new(inp);
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            dump_segment_file(inp, config.strings_min_length.0, &line_prefix, oup)
+        })
+        .await?
+        .context("in synchronous lucene task")?;
+        Ok(())
+    }
+}
+
+/// A file within a compound file
+struct CompoundEntry {
+    /// file name suffix, e.g. `.fdt`
+    suffix: String,
+    offset: u64,
+    length: u64,
+}
+
+/// Parse the entries (.cfe) file of a compound file
+fn read_compound_entries(mut r: impl Read) -> Result<Vec<CompoundEntry>> {
+    let (codec, _) = read_index_header(&mut r)?;
+    // before Lucene 9, longs were big endian
+    let little_endian = codec.starts_with("Lucene9");
+    let read_long = |r: &mut dyn Read| -> Result<u64> {
+        let mut b = [0u8; 8];
+        r.read_exact(&mut b)?;
+        Ok(if little_endian {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        })
+    };
+    let count = read_vint(&mut r)?;
+    (0..count)
+        .map(|_| -> Result<CompoundEntry> {
+            Ok(CompoundEntry {
+                suffix: read_string(&mut r)?,
+                offset: read_long(&mut r)?,
+                length: read_long(&mut r)?,
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl FileAdapter for LuceneAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let is_compound = ai
+            .filepath_hint
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("cfs"));
+        // the entries are only found for real files, compound files in archives are shown as strings
+        if !is_compound || !ai.is_real_file {
+            return LuceneFileAdapter.adapt(ai, detection_reason).await;
+        }
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            passwords,
+            postprocess,
+            ..
+        } = ai;
+        let entries_path = filepath_hint.with_extension("cfe");
+        let entries = read_compound_entries(
+            &std::fs::read(&entries_path).with_context(|| {
+                format!("reading compound file entries {}", entries_path.display())
+            })?[..],
+        )?;
+        let segment = filepath_hint
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let detection_reason = detection_reason.clone();
+        let s = stream! {
+            for entry in entries {
+                // the other segment files (norms, points, doc values, ...) are not useful to search
+                let name = format!("{segment}{}", entry.suffix);
+                if !SEGMENT_FILES.iter().any(|e| entry.suffix.ends_with(&format!(".{e}"))) {
+                    continue;
+                }
+                let mut file = tokio::fs::File::open(&filepath_hint).await?;
+                file.seek(std::io::SeekFrom::Start(entry.offset)).await?;
+                let entry = AdaptInfo {
+                    filepath_hint: filepath_hint.join(&name),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(file.take(entry.length)),
+                    line_prefix: format!("{line_prefix}{name}: "),
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                    postprocess,
+                };
+                // dumped directly, since the term dictionaries are not matched by their extension
+                for await output in LuceneFileAdapter.adapt(entry, &detection_reason).await? {
+                    yield output;
+                }
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    fn header(codec: &str) -> Vec<u8> {
+        let mut b = CODEC_MAGIC.to_be_bytes().to_vec();
+        b.push(codec.len() as u8);
+        b.extend_from_slice(codec.as_bytes());
+        b.extend_from_slice(&1u32.to_be_bytes());
+        b.extend_from_slice(&[0; 16]);
+        b.push(0);
+        b
+    }
+
+    #[tokio::test]
+    async fn stored_fields() -> Result<()> {
+        let mut fdt = header(STORED_FIELDS_LZ4);
+        // field 1, string "hello"
+        let fields = b"\x08\x05hello";
+        // doc base, one doc, one field, length
+        fdt.extend_from_slice(&[0, 1 << 2, 1, fields.len() as u8]);
+        // no dictionary, one block
+        fdt.extend_from_slice(&[0, fields.len() as u8, 1, 1 + fields.len() as u8]);
+        // empty dictionary, then the block as literals only
+        fdt.push(0);
+        fdt.push((fields.len() as u8) << 4);
+        fdt.extend_from_slice(fields);
+        // footer
+        fdt.extend_from_slice(&[0xc0, 0x28, 0x93, 0xe8]);
+        fdt.extend_from_slice(&[0; 12]);
+
+        let (a, d) = simple_adapt_info(
+            std::path::Path::new("_0.fdt"),
+            Box::pin(std::io::Cursor::new(fdt)),
+        );
+        let res = loop_adapt(&LuceneAdapter, d, a).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:[lucene] Lucene90StoredFieldsFastData version 1\nPREFIX:field 1: hello\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn matched_by_header() -> Result<()> {
+        use crate::matching::{FileMeta, adapter_matcher};
+        let adapters: Vec<Arc<dyn FileAdapter>> = vec![Arc::new(LuceneAdapter)];
+        let meta = |name: &str, mimetype| FileMeta {
+            lossy_filename: name.to_string(),
+            mimetype,
+            path: None,
+        };
+        let fast = adapter_matcher(&adapters, false)?;
+        assert!(fast(meta("_0.fdt", None)).is_some());
+        assert!(fast(meta("_0.tim", None)).is_none());
+        let accurate = adapter_matcher(&adapters, true)?;
+        assert!(accurate(meta("_0.tim", Some(MIMETYPE))).is_some());
+        assert!(accurate(meta("clip.tim", Some("application/octet-stream"))).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn lz4() -> Result<()> {
+        // "abc" literal, then a match of 6 bytes at offset 3
+        let mut out = b"xy".to_vec();
+        lz4_decompress(b"\x32abc\x03\x00", 9, &mut out)?;
+        assert_eq!(out, b"xyabcabcabc");
+        Ok(())
+    }
+}
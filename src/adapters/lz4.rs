@@ -0,0 +1,199 @@
+//! Streaming decoder for the LZ4 frame format (`.lz4` files as written by the `lz4` tool), including the legacy frame format and concatenated frames.
+//!
+//! Block and content checksums are skipped, not verified.
+
+use async_stream::stream;
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+
+const FRAME_MAGIC: u32 = 0x184D2204;
+const LEGACY_MAGIC: u32 = 0x184C2102;
+/// skippable frames have the magic numbers 0x184D2A50 to 0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+/// matches can reference up to 64 KiB back, also into previous blocks
+const WINDOW_SIZE: usize = 64 * 1024;
+/// blocks of the legacy format decompress to at most 8 MiB, so they are at most slightly larger compressed
+const MAX_BLOCK_SIZE: usize = 9 * 1024 * 1024;
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid lz4 data: {msg}"))
+}
+
+/// Decompress a single LZ4 block, appending it to `out`. Matches may reference data already in `out`.
+pub fn decompress_block(src: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut i = 0;
+    let byte = |i: usize| {
+        src.get(i)
+            .copied()
+            .ok_or_else(|| invalid("truncated block"))
+    };
+    // lengths of 15 are continued in the next bytes
+    let length = |i: &mut usize, mut len: usize| -> Result<usize> {
+        if len == 15 {
+            loop {
+                let b = byte(*i)?;
+                *i += 1;
+                len += b as usize;
+                if b != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    };
+    while i < src.len() {
+        let token = byte(i)?;
+        i += 1;
+        let literals = length(&mut i, (token >> 4) as usize)?;
+        let literals = src
+            .get(i..i + literals)
+            .ok_or_else(|| invalid("truncated literals"))?;
+        out.extend_from_slice(literals);
+        i += literals.len();
+        // the last sequence has no match
+        if i == src.len() {
+            break;
+        }
+        let offset = u16::from_le_bytes([byte(i)?, byte(i + 1)?]) as usize;
+        i += 2;
+        if offset == 0 || offset > out.len() {
+            return Err(invalid("match offset out of range"));
+        }
+        let len = length(&mut i, (token & 0xf) as usize)? + 4;
+        // the match may overlap with the data it produces
+        for _ in 0..len {
+            out.push(out[out.len() - offset]);
+        }
+    }
+    Ok(())
+}
+
+async fn read_u32(inp: &mut (impl AsyncRead + Unpin)) -> Result<Option<u32>> {
+    let mut buf = [0u8; 4];
+    let mut n = 0;
+    while n < 4 {
+        let read = inp.read(&mut buf[n..]).await?;
+        if read == 0 {
+            return match n {
+                0 => Ok(None),
+                _ => Err(invalid("truncated")),
+            };
+        }
+        n += read;
+    }
+    Ok(Some(u32::from_le_bytes(buf)))
+}
+
+async fn read_vec(inp: &mut (impl AsyncRead + Unpin), len: usize) -> Result<Vec<u8>> {
+    if len > MAX_BLOCK_SIZE {
+        return Err(invalid("block too large"));
+    }
+    let mut buf = vec![0u8; len];
+    inp.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Decompress a stream of LZ4 frames
+pub fn lz4_decoder<'a>(
+    mut inp: Pin<Box<dyn AsyncRead + Send + 'a>>,
+) -> Pin<Box<dyn AsyncRead + Send + 'a>> {
+    let s = stream! {
+        let mut next_magic = read_u32(&mut inp).await?;
+        while let Some(magic) = next_magic.take() {
+            match magic {
+                FRAME_MAGIC => {
+                    let descriptor = read_vec(&mut inp, 2).await?;
+                    let flags = descriptor[0];
+                    if flags >> 6 != 1 {
+                        Err(invalid("unsupported frame version"))?;
+                    }
+                    let block_checksum = flags & 0x10 != 0;
+                    let content_size = flags & 0x08 != 0;
+                    let content_checksum = flags & 0x04 != 0;
+                    let dict_id = flags & 0x01 != 0;
+                    // optional content size and dictionary id, header checksum
+                    let skip = 8 * content_size as usize + 4 * dict_id as usize + 1;
+                    read_vec(&mut inp, skip).await?;
+                    let mut window: Vec<u8> = Vec::new();
+                    loop {
+                        let size = read_u32(&mut inp).await?.ok_or_else(|| invalid("truncated frame"))?;
+                        if size == 0 {
+                            break;
+                        }
+                        let data = read_vec(&mut inp, (size & 0x7fff_ffff) as usize).await?;
+                        if block_checksum {
+                            read_vec(&mut inp, 4).await?;
+                        }
+                        let start = window.len();
+                        if size & 0x8000_0000 != 0 {
+                            // stored uncompressed
+                            window.extend_from_slice(&data);
+                        } else {
+                            decompress_block(&data, &mut window)?;
+                        }
+                        yield std::io::Result::Ok(bytes::Bytes::copy_from_slice(&window[start..]));
+                        if window.len() > WINDOW_SIZE {
+                            window.drain(..window.len() - WINDOW_SIZE);
+                        }
+                    }
+                    if content_checksum {
+                        read_vec(&mut inp, 4).await?;
+                    }
+                    next_magic = read_u32(&mut inp).await?;
+                }
+                LEGACY_MAGIC => {
+                    // independent blocks until the end of the input or the next frame
+                    while let Some(size) = read_u32(&mut inp).await? {
+                        if size == FRAME_MAGIC || size == LEGACY_MAGIC {
+                            next_magic = Some(size);
+                            break;
+                        }
+                        let data = read_vec(&mut inp, size as usize).await?;
+                        let mut block = Vec::new();
+                        decompress_block(&data, &mut block)?;
+                        yield std::io::Result::Ok(bytes::Bytes::from(block));
+                    }
+                }
+                magic if magic & 0xffff_fff0 == SKIPPABLE_MAGIC => {
+                    let size = read_u32(&mut inp).await?.ok_or_else(|| invalid("truncated skippable frame"))?;
+                    let skipped = tokio::io::copy(&mut (&mut inp).take(size as u64), &mut tokio::io::sink()).await?;
+                    if skipped < size as u64 {
+                        Err(invalid("truncated skippable frame"))?;
+                    }
+                    next_magic = read_u32(&mut inp).await?;
+                }
+                _ => Err(invalid("unknown frame magic number"))?,
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn frames() -> anyhow::Result<()> {
+        let mut lz4 = Vec::new();
+        // frame with a compressed block ("hello " + match of 11 bytes at offset 6 + "\n") and a stored block
+        lz4.extend_from_slice(b"\x04\x22\x4d\x18\x60\x40\x82");
+        lz4.extend_from_slice(b"\x0b\x00\x00\x00\x67hello \x06\x00\x10\n");
+        lz4.extend_from_slice(b"\x04\x00\x00\x80bye\n");
+        lz4.extend_from_slice(b"\x00\x00\x00\x00");
+        // skippable frame
+        lz4.extend_from_slice(b"\x51\x2a\x4d\x18\x03\x00\x00\x00abc");
+        // legacy frame
+        lz4.extend_from_slice(b"\x02\x21\x4c\x18\x05\x00\x00\x00\x40end\n");
+        let mut out = String::new();
+        lz4_decoder(Box::pin(Cursor::new(lz4)))
+            .read_to_string(&mut out)
+            .await?;
+        assert_eq!(out, "hello hello hello\nbye\nend\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use super::eml::{adapt_parts, mail_parts};
+use super::*;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::AsyncReadExt;
+
+/// The subdirectories of a Maildir, messages are files directly within them
+static SUBDIRECTORIES: &[&str] = &["cur", "new", "tmp"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "maildir".to_owned(),
+        version: 1,
+        description: "Reads the messages of Maildir folders, which are files without extension in the cur, new and tmp directories of the folder. Only found with `--rga-accurate`, since a glob for them would match far too many files. Like the eml adapter, outputs the decoded headers and body and recurses into the attachments"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: vec![FastFileMatcher::DirectoryShape(
+            SUBDIRECTORIES.iter().map(|s| s.to_string()).collect()
+        )],
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MaildirAdapter;
+
+impl MaildirAdapter {
+    pub fn new() -> MaildirAdapter {
+        MaildirAdapter
+    }
+}
+impl GetMetadata for MaildirAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for MaildirAdapter {
+    async fn adapt(
+        &self,
+        mut ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let mut content = Vec::new();
+        ai.inp.read_to_end(&mut content).await?;
+        let mail = mailparse::parse_mail(&content).context("invalid mail in Maildir")?;
+        Ok(adapt_parts(mail_parts(&mail), ai))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        matching::{FileMeta, adapter_matcher},
+        preproc::loop_adapt,
+        test_utils::*,
+    };
+    use std::{path::Path, sync::Arc};
+
+    #[tokio::test]
+    async fn maildir() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let maildir = dir.path().join("Mail/.Archive");
+        for sub in SUBDIRECTORIES {
+            std::fs::create_dir_all(maildir.join(sub))?;
+        }
+        let message = maildir.join("cur/1712345678.M1P2.host,S=120:2,S");
+        std::fs::write(
+            &message,
+            "From: alice@example.com\r\nSubject: lunch\r\n\r\nPizza at noon?\r\n",
+        )?;
+        // a cur directory on its own is not a Maildir
+        std::fs::create_dir(dir.path().join("cur"))?;
+        let other = dir.path().join("cur/notes");
+        std::fs::write(&other, "")?;
+
+        let adapters: Vec<Arc<dyn FileAdapter>> = vec![Arc::new(MaildirAdapter::new())];
+        let matcher = adapter_matcher(&adapters, false)?;
+        let matches = |path: &Path| {
+            matcher(FileMeta {
+                lossy_filename: path.file_name().unwrap().to_string_lossy().into_owned(),
+                mimetype: None,
+                path: Some(path.to_path_buf()),
+            })
+            .is_some()
+        };
+        assert!(matches(&message));
+        assert!(!matches(&other));
+        assert!(!matches(&maildir.join("cur")));
+        // not selected by the pre-glob, only found with --rga-accurate
+        assert!(!pre_glob_selects(&pre_glob(&adapters, false), &message));
+        assert!(pre_glob_selects(&pre_glob(&adapters, true), &message));
+        let accurate = adapter_matcher(&adapters, true)?;
+        assert!(
+            accurate(FileMeta {
+                lossy_filename: message.file_name().unwrap().to_string_lossy().into_owned(),
+                mimetype: Some("text/plain"),
+                path: Some(message.clone()),
+            })
+            .is_some()
+        );
+
+        let (a, d) = simple_fs_adapt_info(&message).await?;
+        let buf = adapted_to_vec(loop_adapt(&MaildirAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?.replace('\r', ""),
+            "PREFIX:From: alice@example.com
+PREFIX:Subject: lunch
+PREFIX:Pizza at noon?
+PREFIX:
+"
+        );
+        Ok(())
+    }
+}
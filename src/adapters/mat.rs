@@ -0,0 +1,492 @@
+//! Reading of MATLAB MAT-files: the names, classes and dimensions of the variables and the contents of char arrays.
+//!
+//! Version 5 to 7 files are a sequence of (often zlib compressed) data elements.
+//! Version 7.3 files are HDF5 files, they are read by the hdf5 adapter.
+
+use super::binary::Endian;
+use super::hdf5::synchronous_dump_hdf5;
+use super::sevenzip::archive_on_disk;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use async_compression::tokio::bufread::ZlibDecoder;
+use lazy_static::lazy_static;
+use std::io::{Cursor, Write};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["mat"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "mat".to_owned(),
+        version: 1,
+        description: "Reads MATLAB MAT-files (v5 to v7.3). Outputs the variables with their class and dimensions (`results.labels{2}: char 1x5`) and the contents of char arrays (`results.labels{2} = speed`)"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MatAdapter;
+
+impl MatAdapter {
+    pub fn new() -> MatAdapter {
+        MatAdapter
+    }
+}
+
+impl GetMetadata for MatAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+// data types
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_MATRIX: u32 = 14;
+const MI_COMPRESSED: u32 = 15;
+const MI_UTF8: u32 = 16;
+const MI_UTF16: u32 = 17;
+const MI_UTF32: u32 = 18;
+
+// array classes
+const MX_CELL: u8 = 1;
+const MX_STRUCT: u8 = 2;
+const MX_OBJECT: u8 = 3;
+const MX_CHAR: u8 = 4;
+
+static CLASS_NAMES: &[&str] = &[
+    "unknown", "cell", "struct", "object", "char", "sparse", "double", "single", "int8", "uint8",
+    "int16", "uint16", "int32", "uint32", "int64", "uint64", "function", "opaque",
+];
+
+/// Cells and structs nested deeper than this are broken
+const MAX_DEPTH: usize = 64;
+/// Only the beginning of larger variables is read (the header and the first elements of cells and structs)
+const MAX_ELEMENT_LEN: u64 = 64 << 20;
+
+/// The data elements of an array
+struct Elements<'a> {
+    data: &'a [u8],
+    pos: usize,
+    endian: Endian,
+}
+
+impl<'a> Elements<'a> {
+    /// The type and data of the next element, None at the end
+    fn next(&mut self) -> Result<Option<(u32, &'a [u8])>> {
+        let Some(tag) = self.data.get(self.pos..self.pos + 8) else {
+            return Ok(None);
+        };
+        let first = self.endian.u32_at(tag, 0).context("invalid data element")?;
+        if first >> 16 != 0 {
+            // small data element: type, size and up to 4 bytes of data in 8 bytes
+            let size = (first >> 16) as usize;
+            let data = tag.get(4..4 + size).context("invalid small data element")?;
+            self.pos += 8;
+            return Ok(Some((first & 0xffff, data)));
+        }
+        let size = self.endian.u32_at(tag, 4).context("invalid data element")? as usize;
+        let start = self.pos + 8;
+        let data = self
+            .data
+            .get(start..start + size)
+            .context("truncated data element")?;
+        self.pos = start + size.next_multiple_of(8);
+        Ok(Some((first, data)))
+    }
+
+    fn expect(&mut self, what: &str) -> Result<(u32, &'a [u8])> {
+        self.next()?.with_context(|| format!("missing {what}"))
+    }
+}
+
+/// The characters of a char array, one string per row. The array is stored column by column
+fn char_rows(kind: u32, data: &[u8], dims: &[u32], endian: Endian) -> Vec<String> {
+    let chars: Vec<char> = match kind {
+        MI_UINT16 | MI_UTF16 => {
+            char::decode_utf16(data.chunks_exact(2).filter_map(|b| endian.u16_at(b, 0)))
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect()
+        }
+        MI_UTF8 => String::from_utf8_lossy(data).chars().collect(),
+        MI_UTF32 => data
+            .chunks_exact(4)
+            .filter_map(|b| endian.u32_at(b, 0))
+            .map(|c| char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+        MI_INT8 | MI_UINT8 => data.iter().map(|&b| b as char).collect(),
+        _ => Vec::new(),
+    };
+    let rows = dims.first().copied().unwrap_or(1).max(1) as usize;
+    let columns = chars.len() / rows;
+    let rows = if rows * columns == chars.len() {
+        (0..rows)
+            .map(|r| (0..columns).map(|c| chars[r + c * rows]).collect())
+            .collect()
+    } else {
+        // multi-byte UTF-8 of a different length than the dimensions
+        vec![chars.into_iter().collect::<String>()]
+    };
+    rows.into_iter()
+        .map(|row: String| row.trim_end_matches(['\0', ' ']).to_owned())
+        .filter(|row| !row.is_empty())
+        .collect()
+}
+
+/// Writes an array (a miMATRIX element): its class and dimensions, the rows of char arrays
+/// and the arrays in cells and structs. `path` replaces the name for arrays in cells and structs
+fn write_array(
+    data: &[u8],
+    endian: Endian,
+    path: Option<String>,
+    depth: usize,
+    p: &str,
+    out: &mut dyn Write,
+) -> Result<()> {
+    anyhow::ensure!(depth < MAX_DEPTH, "arrays nested too deeply");
+    let mut e = Elements {
+        data,
+        pos: 0,
+        endian,
+    };
+    // empty cells have no data
+    let Some((_, flags)) = e.next()? else {
+        return Ok(());
+    };
+    let flags = endian.u32_at(flags, 0).context("invalid array flags")?;
+    let class = flags as u8;
+    let logical = flags & 0x0200 != 0;
+    let (_, dims) = e.expect("dimensions")?;
+    let dims: Vec<u32> = dims
+        .chunks_exact(4)
+        .filter_map(|b| endian.u32_at(b, 0))
+        .collect();
+    let (_, name) = e.expect("array name")?;
+    let path = path.unwrap_or_else(|| String::from_utf8_lossy(name).into_owned());
+    let class_name = if logical {
+        "logical".to_owned()
+    } else if class == MX_OBJECT {
+        let (_, class_name) = e.expect("class name")?;
+        String::from_utf8_lossy(class_name).into_owned()
+    } else {
+        CLASS_NAMES
+            .get(class as usize)
+            .unwrap_or(&"unknown")
+            .to_string()
+    };
+    let shape = dims
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>()
+        .join("x");
+    let count = dims
+        .iter()
+        .try_fold(1usize, |n, &d| n.checked_mul(d as usize))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{path}: the dimensions {shape} overflow"),
+            )
+        })?;
+    writeln!(out, "{p}{path}: {class_name} {shape}")?;
+    match class {
+        MX_CHAR => {
+            let (kind, chars) = e.expect("characters")?;
+            for row in char_rows(kind, chars, &dims, endian) {
+                writeln!(out, "{p}{path} = {row}")?;
+            }
+        }
+        MX_CELL => {
+            for i in 0..count {
+                let (kind, cell) = e.expect("cell")?;
+                if kind == MI_MATRIX {
+                    let cell_path = format!("{path}{{{}}}", i + 1);
+                    write_array(cell, endian, Some(cell_path), depth + 1, p, out)?;
+                }
+            }
+        }
+        MX_STRUCT | MX_OBJECT => {
+            let (kind, name_len) = e.expect("field name length")?;
+            anyhow::ensure!(kind == MI_INT32, "invalid field name length");
+            let name_len = endian
+                .u32_at(name_len, 0)
+                .context("invalid field name length")? as usize;
+            let (_, names) = e.expect("field names")?;
+            let names: Vec<String> = names
+                .chunks(name_len.max(1))
+                .map(|n| {
+                    let n = &n[..memchr::memchr(0, n).unwrap_or(n.len())];
+                    String::from_utf8_lossy(n).into_owned()
+                })
+                .collect();
+            // structs without fields have no data, but can have huge dimensions
+            if names.is_empty() {
+                return Ok(());
+            }
+            for i in 0..count {
+                for name in &names {
+                    let (kind, field) = e.expect("field")?;
+                    if kind != MI_MATRIX {
+                        continue;
+                    }
+                    let field_path = if count == 1 {
+                        format!("{path}.{name}")
+                    } else {
+                        format!("{path}({}).{name}", i + 1)
+                    };
+                    write_array(field, endian, Some(field_path), depth + 1, p, out)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Writes a top level data element, which is an array or a compressed array
+async fn write_element(
+    kind: u32,
+    data: Vec<u8>,
+    endian: Endian,
+    p: &str,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    let data = if kind == MI_COMPRESSED {
+        let mut inflated = Vec::new();
+        let result = ZlibDecoder::new(&data[..])
+            .take(MAX_ELEMENT_LEN)
+            .read_to_end(&mut inflated)
+            .await;
+        if let Err(e) = result {
+            // variables larger than MAX_ELEMENT_LEN are truncated, the beginning can still be read
+            debug!("decompressing MAT-file variable: {e}");
+        }
+        // a truncated element contains at least the header of the array
+        let (Some(kind), Some(size)) = (endian.u32_at(&inflated, 0), endian.u32_at(&inflated, 4))
+        else {
+            return Ok(());
+        };
+        let size = size as usize;
+        if kind != MI_MATRIX {
+            return Ok(());
+        }
+        inflated[8..inflated.len().min(8 + size)].to_vec()
+    } else if kind == MI_MATRIX {
+        data
+    } else {
+        return Ok(());
+    };
+    write_array(&data, endian, None, 0, p, out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for MatAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            ..
+        } = ai;
+        // descriptive text, subsystem data offset, version and endian indicator
+        let mut header = vec![0; 128];
+        inp.read_exact(&mut header)
+            .await
+            .context("not a MAT-file (too short)")?;
+        let endian = match &header[126..] {
+            b"IM" => Endian::Little,
+            b"MI" => Endian::Big,
+            _ => anyhow::bail!("not a MAT-file (v4 MAT-files are not supported)"),
+        };
+        if endian.u16_at(&header, 124) == Some(0x0200) {
+            // v7.3: HDF5 with the MAT-file header as user block
+            let inp = Box::pin(AsyncReadExt::chain(Cursor::new(header), inp));
+            let (path, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+            let oup = SyncIoBridge::new(oup);
+            tokio::task::spawn_blocking(move || {
+                let _tmp = tmp;
+                synchronous_dump_hdf5(&path, &line_prefix, oup)
+            })
+            .await?
+            .context("in synchronous hdf5 task")?;
+            return Ok(());
+        }
+        loop {
+            let mut tag = [0; 8];
+            match inp.read_exact(&mut tag).await {
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                r => r?,
+            };
+            let kind = endian.u32_at(&tag, 0).context("invalid data element")?;
+            let size = endian.u32_at(&tag, 4).context("invalid data element")? as u64;
+            let mut data = Vec::new();
+            (&mut inp)
+                .take(size.min(MAX_ELEMENT_LEN))
+                .read_to_end(&mut data)
+                .await?;
+            // the rest of large variables and the padding of uncompressed elements to 8 bytes
+            let mut rest = size - data.len() as u64;
+            if kind != MI_COMPRESSED {
+                rest += size.next_multiple_of(8) - size;
+            }
+            tokio::io::copy(&mut (&mut inp).take(rest), &mut tokio::io::sink()).await?;
+            let mut out = Vec::new();
+            if let Err(e) = write_element(kind, data, endian, &line_prefix, &mut out).await {
+                // other variables can still be read, unless the file is corrupt
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::InvalidData)
+                {
+                    return Err(e.context("invalid MAT-file"));
+                }
+                debug!("reading MAT-file variable: {e:#}");
+            }
+            oup.write_all(&out).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use async_compression::tokio::bufread::ZlibEncoder;
+    use pretty_assertions::assert_eq;
+
+    fn element(kind: u32, data: &[u8]) -> Vec<u8> {
+        let mut e = kind.to_le_bytes().to_vec();
+        e.extend((data.len() as u32).to_le_bytes());
+        e.extend(data);
+        e.resize(e.len().next_multiple_of(8), 0);
+        e
+    }
+
+    fn array(class: u8, dims: &[u32], name: &str, contents: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = element(6, &[class, 0, 0, 0, 0, 0, 0, 0]);
+        let dims: Vec<u8> = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+        data.extend(element(MI_INT32, &dims));
+        data.extend(element(MI_INT8, name.as_bytes()));
+        data.extend(contents.concat());
+        element(MI_MATRIX, &data)
+    }
+
+    /// A char array with the rows of equal length
+    fn chars(dims: &[u32], name: &str, rows: &[&str]) -> Vec<u8> {
+        let columns: Vec<u8> = (0..rows[0].len())
+            .flat_map(|c| rows.iter().map(move |r| r.as_bytes()[c] as u16))
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        array(MX_CHAR, dims, name, &[element(MI_UINT16, &columns)])
+    }
+
+    fn header() -> Vec<u8> {
+        let mut header = b"MATLAB 5.0 MAT-file, Platform: GLNXA64".to_vec();
+        header.resize(124, b' ');
+        header.extend(0x0100u16.to_le_bytes());
+        header.extend(b"IM");
+        header
+    }
+
+    #[tokio::test]
+    async fn variables() -> Result<()> {
+        let mut data = header();
+        data.extend(chars(&[2, 5], "title", &["hello", "world"]));
+        data.extend(array(6, &[100, 3], "measurements", &[element(9, &[0; 16])]));
+        // struct with the field `labels`, a cell of strings, compressed
+        let mut fields = ((4 << 16) | MI_INT32).to_le_bytes().to_vec();
+        fields.extend(8u32.to_le_bytes());
+        let results = array(
+            MX_STRUCT,
+            &[1, 1],
+            "results",
+            &[
+                fields,
+                element(MI_INT8, b"labels\0\0"),
+                array(
+                    MX_CELL,
+                    &[1, 2],
+                    "",
+                    &[
+                        chars(&[1, 4], "", &["time"]),
+                        chars(&[1, 5], "", &["speed"]),
+                    ],
+                ),
+            ],
+        );
+        let mut compressed = Vec::new();
+        ZlibEncoder::new(&results[..])
+            .read_to_end(&mut compressed)
+            .await?;
+        data.extend(MI_COMPRESSED.to_le_bytes());
+        data.extend((compressed.len() as u32).to_le_bytes());
+        data.extend(compressed);
+
+        let (a, d) = simple_adapt_info(&PathBuf::from("data.mat"), Box::pin(Cursor::new(data)));
+        let buf = adapted_to_vec(loop_adapt(&MatAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:title: char 2x5
+PREFIX:title = hello
+PREFIX:title = world
+PREFIX:measurements: double 100x3
+PREFIX:results: struct 1x1
+PREFIX:results.labels: cell 1x2
+PREFIX:results.labels{1}: char 1x4
+PREFIX:results.labels{1} = time
+PREFIX:results.labels{2}: char 1x5
+PREFIX:results.labels{2} = speed
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn struct_without_fields() -> Result<()> {
+        let mut data = header();
+        let mut name_len = ((4 << 16) | MI_INT32).to_le_bytes().to_vec();
+        name_len.extend(8u32.to_le_bytes());
+        data.extend(array(
+            MX_STRUCT,
+            &[u32::MAX, u32::MAX],
+            "empty",
+            &[name_len, element(MI_INT8, &[])],
+        ));
+
+        let (a, d) = simple_adapt_info(&PathBuf::from("empty.mat"), Box::pin(Cursor::new(data)));
+        let buf = adapted_to_vec(loop_adapt(&MatAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:empty: struct 4294967295x4294967295\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dimensions_overflow() -> Result<()> {
+        let mut data = header();
+        data.extend(array(MX_CELL, &[u32::MAX; 3], "huge", &[]));
+
+        let (a, d) = simple_adapt_info(&PathBuf::from("huge.mat"), Box::pin(Cursor::new(data)));
+        let res = adapted_to_vec(loop_adapt(&MatAdapter::new(), d, a).await?).await;
+        assert!(res.is_err());
+        Ok(())
+    }
+}
@@ -1,24 +1,21 @@
+use super::eml::{adapt_parts, mail_parts};
 use super::*;
 
 use anyhow::Result;
-use async_stream::stream;
 use lazy_static::lazy_static;
-use mime2ext::mime2ext;
 use regex::bytes::Regex;
 use tokio::io::AsyncReadExt;
 
-use std::{collections::VecDeque, io::Cursor};
-
-static EXTENSIONS: &[&str] = &["mbox", "mbx", "eml"];
-static MIME_TYPES: &[&str] = &["application/mbox", "message/rfc822"];
+static EXTENSIONS: &[&str] = &["mbox", "mbx"];
+static MIME_TYPES: &[&str] = &["application/mbox"];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "mail".to_owned(),
-        version: 1,
-        description:
-            "Reads mailbox/mail files and runs extractors on the contents and attachments."
-                .to_owned(),
+        version: 2,
+        description: "Reads mailbox files. Like the eml adapter, outputs the decoded headers and body of every mail and recurses into the attachments (prefixed with their file name)."
+            .to_owned(),
         recurses: true,
+        outputs_unchanged_files: false,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -48,82 +45,39 @@ impl GetMetadata for MboxAdapter {
     }
 }
 
+/// Split a mailbox into its mails, without the `From ` separator lines
+fn split_mails(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    FROM_REGEX.split(content).map(|mail| {
+        // the separator of the first mail is not preceded by a newline
+        if mail.starts_with(b"From ") {
+            mail.splitn(2, |x| *x == b'\n').nth(1).unwrap_or_default()
+        } else {
+            mail
+        }
+    })
+}
+
 #[async_trait]
 impl FileAdapter for MboxAdapter {
     async fn adapt(
         &self,
-        ai: AdaptInfo,
+        mut ai: AdaptInfo,
         _detection_reason: &FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let AdaptInfo {
-            filepath_hint,
-            mut inp,
-            line_prefix,
-            archive_recursion_depth,
-            config,
-            postprocess,
-            ..
-        } = ai;
-
         let mut content = Vec::new();
-        let s = stream! {
-            inp.read_to_end(&mut content).await?;
-
-            let mut ais = vec![];
-            for mail_bytes in FROM_REGEX.splitn(&content, usize::MAX) {
-                let mail_content = mail_bytes.splitn(2, |x| *x == b'\n').nth(1).unwrap();
-                let mail = mailparse::parse_mail(mail_content);
-                if mail.is_err() {
-                    continue;
-                }
-                let mail = mail.unwrap();
-
-                let mut todos = VecDeque::new();
-                todos.push_back(mail);
-
-                while let Some(mail) = todos.pop_front() {
-                let mut path = filepath_hint.clone();
-                let filename = mail.get_content_disposition().params.get("filename").cloned();
-                match &*mail.ctype.mimetype {
-                    x if x.starts_with("multipart/") => {
-                        todos.extend(mail.subparts);
-                        continue;
-                    }
-                    mime => {
-                        if let Some(name) = filename {
-                            path.push(name);
-                        } else if let Some(extension) = mime2ext(mime) {
-                            path.push(format!("data.{extension}"));
-                        } else {
-                            path.push("data");
-                        }
-                    }
-                }
-
-                let mut config = config.clone();
-                config.accurate = true;
-
-                let raw_body = mail.get_body_raw();
-                if raw_body.is_err() {
-                    continue;
-                }
-                let ai2: AdaptInfo = AdaptInfo {
-                    filepath_hint: path,
-                    is_real_file: false,
-                    archive_recursion_depth: archive_recursion_depth + 1,
-                    inp: Box::pin(Cursor::new(raw_body.unwrap())),
-                    line_prefix: line_prefix.to_string(),
-                    config,
-                    postprocess,
-                };
-                ais.push(ai2);
-                }
+        ai.inp.read_to_end(&mut content).await?;
+        let mut parts = Vec::new();
+        for (i, mail) in split_mails(&content).enumerate() {
+            match mailparse::parse_mail(mail) {
+                // each mail in a directory of its own, numbered from 1
+                Ok(mail) => parts.extend(mail_parts(&mail).into_iter().map(|mut part| {
+                    part.name = format!("{}/{}", i + 1, part.name);
+                    part
+                })),
+                Err(e) => warn!("skipping invalid mail {} of mailbox: {e}", i + 1),
             }
-            for a in ais {
-                yield(Ok(a));
-            }
-        };
-        Ok(Box::pin(s))
+        }
+        Ok(adapt_parts(parts, ai))
     }
 }
 
@@ -158,7 +112,14 @@ mod tests {
                 .to_str()
                 .unwrap()
             {
-                "data.txt" | "data.html" => {
+                "headers.txt" => {
+                    assert!(
+                        String::from_utf8(buf)?
+                            .contains("Subject: Re: [KeYProject/key] Fix more UI bugs (PR #3232)")
+                    );
+                }
+                // the plain text of the alternatives
+                "data.txt" => {
                     assert!(String::from_utf8(buf)?.contains("Thank you for your contribution"));
                 }
                 x => panic!("unexpected filename {x:?}"),
@@ -180,9 +141,13 @@ mod tests {
         let mut count = 0;
         while let Some(file) = r.next().await {
             let mut file = file?;
+            if file.filepath_hint.ends_with("headers.txt") {
+                continue;
+            }
+            count += 1;
             assert_eq!(
-                "data.html",
-                file.filepath_hint.components().last().unwrap().as_os_str()
+                PathBuf::from(format!("{count}/data.html")),
+                file.filepath_hint.strip_prefix(&filepath)?
             );
             let mut buf = Vec::new();
             file.inp.read_to_end(&mut buf).await?;
@@ -190,7 +155,6 @@ mod tests {
                 "<html>\r\n  <head>\r\n    <meta http-equiv=\"content-type\" content=\"text/html; charset=UTF-8\">\r\n  </head>\r\n  <body>\r\n    <p>&gt;From</p>\r\n    <p>Another word &gt;From<br>\r\n    </p>\r\n  </body>\r\n</html>",
                 String::from_utf8(buf)?.trim()
             );
-            count += 1;
         }
         assert_eq!(3, count);
         Ok(())
@@ -220,6 +184,12 @@ mod tests {
             let mut buf = Vec::new();
             file.inp.read_to_end(&mut buf).await?;
             match path {
+                "headers.txt" => {
+                    assert_eq!(
+                        "PREFIX:From: Arne Keller <uskyk@student.kit.edu>\nPREFIX:To: <recipient@gmail.com>\nPREFIX:Date: Mon, 31 Jul 2023 15:15:41 +0200\nPREFIX:Subject: Subject line\n",
+                        String::from_utf8(buf).unwrap_or("err".to_owned())
+                    );
+                }
                 "data.html.txt" => {
                     assert_eq!(
                         "PREFIX:regular text\nPREFIX:\n",
@@ -228,7 +198,7 @@ mod tests {
                 }
                 "short.pdf.txt" => {
                     assert_eq!(
-                        "PREFIX:Page 1: hello world\nPREFIX:Page 1: this is just a test.\nPREFIX:Page 1: \nPREFIX:Page 1: 1\nPREFIX:Page 1: \nPREFIX:Page 1: \n",
+                        "PREFIX:short.pdf: Page 1: hello world\nPREFIX:short.pdf: Page 1: this is just a test.\nPREFIX:short.pdf: Page 1: \nPREFIX:short.pdf: Page 1: 1\nPREFIX:short.pdf: Page 1: \nPREFIX:short.pdf: Page 1: \n",
                         String::from_utf8(buf).unwrap_or("err".to_owned())
                     );
                 }
@@ -238,7 +208,7 @@ mod tests {
             }
             count += 1;
         }
-        assert_eq!(2, count); // one message + one attachment
+        assert_eq!(3, count); // headers, message and attachment
         Ok(())
     }
 }
@@ -0,0 +1,565 @@
+use super::{
+    binary::{u16_at, u32_at, u64_at, utf16},
+    strings::write_strings,
+    writing::WritingFileAdapter,
+    *,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+/// `.dmp` is also used for kernel memory dumps and the dumps of many other programs, so those files are found by their signature with `--rga-accurate`
+static EXTENSIONS: &[&str] = &["mdmp"];
+/// for the `MDMP` signature at the start of minidumps
+pub const MIMETYPE: &str = "application/x-minidump";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "minidump".to_owned(),
+        version: 1,
+        description: "Reads Windows / Breakpad minidumps (crash dumps) and outputs the system info, exception, modules (with version and PDB name), threads with the module addresses found on their stacks, Linux process info and the strings in the captured memory regions.\n.dmp files are only found by their signature with `--rga-accurate`, since the extension is used by many other programs"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(MIMETYPE.to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MinidumpAdapter;
+
+impl MinidumpAdapter {
+    pub fn new() -> MinidumpAdapter {
+        MinidumpAdapter
+    }
+}
+impl GetMetadata for MinidumpAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+pub const SIGNATURE: &[u8; 4] = b"MDMP";
+
+// stream types
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const MEMORY_LIST_STREAM: u32 = 5;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+const MEMORY64_LIST_STREAM: u32 = 9;
+const MISC_INFO_STREAM: u32 = 15;
+const THREAD_NAMES_STREAM: u32 = 24;
+/// text streams written by Breakpad / Crashpad on Linux
+static LINUX_STREAMS: &[(u32, &str)] = &[
+    (0x47670004, "proc status"),
+    (0x47670005, "lsb release"),
+    (0x47670006, "cmdline"),
+    (0x47670007, "environ"),
+    (0x47670009, "maps"),
+];
+
+// processor architectures
+const ARCH_X86: u16 = 0;
+const ARCH_ARM: u16 = 5;
+const ARCH_AMD64: u16 = 9;
+const ARCH_ARM64: u16 = 12;
+
+/// number of module addresses shown per thread stack
+const MAX_STACK_FRAMES: usize = 32;
+
+static EXCEPTION_NAMES: &[(u32, &str)] = &[
+    (0x80000003, "EXCEPTION_BREAKPOINT"),
+    (0xc0000005, "EXCEPTION_ACCESS_VIOLATION"),
+    (0xc0000006, "EXCEPTION_IN_PAGE_ERROR"),
+    (0xc000001d, "EXCEPTION_ILLEGAL_INSTRUCTION"),
+    (0xc0000094, "EXCEPTION_INT_DIVIDE_BY_ZERO"),
+    (0xc00000fd, "EXCEPTION_STACK_OVERFLOW"),
+    (0xc0000374, "STATUS_HEAP_CORRUPTION"),
+    (0xc0000409, "STATUS_STACK_BUFFER_OVERRUN"),
+    (0xc0000417, "STATUS_INVALID_CRUNTIME_PARAMETER"),
+    (0xe06d7363, "C++ exception"),
+];
+
+static SIGNAL_NAMES: &[(u32, &str)] = &[
+    (4, "SIGILL"),
+    (6, "SIGABRT"),
+    (7, "SIGBUS"),
+    (8, "SIGFPE"),
+    (11, "SIGSEGV"),
+];
+
+struct Module {
+    base: u64,
+    size: u64,
+    name: String,
+}
+
+/// A minidump loaded into memory
+struct Minidump<'a> {
+    data: &'a [u8],
+    /// (stream type, data)
+    streams: Vec<(u32, &'a [u8])>,
+    pointer_size: usize,
+    arch: Option<u16>,
+    modules: Vec<Module>,
+}
+
+fn base_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+impl<'a> Minidump<'a> {
+    fn parse(data: &'a [u8]) -> Result<Minidump<'a>> {
+        if !data.starts_with(SIGNATURE) {
+            anyhow::bail!("not a minidump");
+        }
+        let num_streams = u32_at(data, 8).context("truncated header")? as usize;
+        let dir = u32_at(data, 12).context("truncated header")? as usize;
+        let mut dump = Minidump {
+            data,
+            streams: Vec::new(),
+            pointer_size: 8,
+            arch: None,
+            modules: Vec::new(),
+        };
+        for i in 0..num_streams {
+            let entry = dir + i * 12;
+            let (Some(stream_type), Some(size), Some(rva)) = (
+                u32_at(data, entry),
+                u32_at(data, entry + 4),
+                u32_at(data, entry + 8),
+            ) else {
+                break;
+            };
+            if let Some(stream) = dump.location(size as u64, rva as u64) {
+                dump.streams.push((stream_type, stream));
+            }
+        }
+        if let Some(arch) = dump.stream(SYSTEM_INFO_STREAM).and_then(|s| u16_at(s, 0)) {
+            dump.arch = Some(arch);
+            dump.pointer_size = match arch {
+                ARCH_X86 | ARCH_ARM => 4,
+                _ => 8,
+            };
+        }
+        dump.modules = dump.read_modules();
+        Ok(dump)
+    }
+
+    fn stream(&self, stream_type: u32) -> Option<&'a [u8]> {
+        self.streams
+            .iter()
+            .find(|(t, _)| *t == stream_type)
+            .map(|(_, s)| *s)
+    }
+
+    /// The data at a MINIDUMP_LOCATION_DESCRIPTOR
+    fn location(&self, size: u64, rva: u64) -> Option<&'a [u8]> {
+        let start = usize::try_from(rva).ok()?;
+        self.data
+            .get(start..start.checked_add(usize::try_from(size).ok()?)?)
+    }
+
+    /// A MINIDUMP_STRING (length prefixed UTF-16)
+    fn string(&self, rva: u64) -> Option<String> {
+        let len = u32_at(self.data, usize::try_from(rva).ok()?)? as u64;
+        Some(utf16(self.location(len, rva + 4)?))
+    }
+
+    fn read_modules(&self) -> Vec<Module> {
+        let Some(s) = self.stream(MODULE_LIST_STREAM) else {
+            return Vec::new();
+        };
+        let count = u32_at(s, 0).unwrap_or(0) as usize;
+        let mut modules: Vec<Module> = (0..count)
+            .map_while(|i| {
+                let m = s.get(4 + i * 108..4 + (i + 1) * 108)?;
+                Some(Module {
+                    base: u64_at(m, 0)?,
+                    size: u32_at(m, 8)? as u64,
+                    name: self.string(u32_at(m, 20)? as u64).unwrap_or_default(),
+                })
+            })
+            .collect();
+        modules.sort_by_key(|m| m.base);
+        modules
+    }
+
+    /// `module+0xoffset` if the address is within a module
+    fn symbolize(&self, addr: u64) -> Option<String> {
+        let m = self
+            .modules
+            .iter()
+            .rev()
+            .find(|m| m.base <= addr && addr - m.base < m.size)?;
+        Some(format!("{}+0x{:x}", base_name(&m.name), addr - m.base))
+    }
+
+    fn symbolize_or_hex(&self, addr: u64) -> String {
+        self.symbolize(addr)
+            .unwrap_or_else(|| format!("0x{addr:x}"))
+    }
+
+    /// The instruction pointer in a thread context
+    fn instruction_pointer(&self, context: &[u8]) -> Option<u64> {
+        match self.arch? {
+            ARCH_AMD64 => u64_at(context, 0xf8),
+            ARCH_X86 => u32_at(context, 0xb8).map(u64::from),
+            ARCH_ARM64 => u64_at(context, 0x108),
+            _ => None,
+        }
+    }
+
+    fn write_system_info(&self, p: &str, out: &mut impl Write) -> Result<()> {
+        if let Some(s) = self.stream(SYSTEM_INFO_STREAM) {
+            let arch = match self.arch {
+                Some(ARCH_X86) => "x86",
+                Some(ARCH_ARM) => "arm",
+                Some(ARCH_AMD64) => "amd64",
+                Some(ARCH_ARM64) => "arm64",
+                _ => "unknown architecture",
+            };
+            let cpus = s.get(6).copied().unwrap_or(0);
+            let (major, minor, build) = (
+                u32_at(s, 8).unwrap_or(0),
+                u32_at(s, 12).unwrap_or(0),
+                u32_at(s, 16).unwrap_or(0),
+            );
+            let os = match u32_at(s, 20) {
+                Some(2) => "Windows",
+                Some(0x8101) => "macOS",
+                Some(0x8102) => "iOS",
+                Some(0x8201) => "Linux",
+                Some(0x8203) => "Android",
+                _ => "OS",
+            };
+            let csd = u32_at(s, 24)
+                .and_then(|rva| self.string(rva as u64))
+                .filter(|s| !s.is_empty())
+                .map(|s| format!(" ({s})"))
+                .unwrap_or_default();
+            writeln!(
+                out,
+                "{p}system: {os} {major}.{minor}.{build}{csd}, {arch}, {cpus} cpus"
+            )?;
+        }
+        if let Some(s) = self.stream(MISC_INFO_STREAM) {
+            // the process id is only valid with MINIDUMP_MISC1_PROCESS_ID
+            let pid = u32_at(s, 8).filter(|_| u32_at(s, 4).is_some_and(|flags| flags & 1 != 0));
+            if let Some(pid) = pid {
+                writeln!(out, "{p}process id: {pid}")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_exception(&self, p: &str, out: &mut impl Write) -> Result<()> {
+        let Some(s) = self.stream(EXCEPTION_STREAM) else {
+            return Ok(());
+        };
+        let (Some(tid), Some(code), Some(addr)) = (u32_at(s, 0), u32_at(s, 8), u64_at(s, 24))
+        else {
+            return Ok(());
+        };
+        let name = EXCEPTION_NAMES
+            .iter()
+            .chain(SIGNAL_NAMES)
+            .find(|(c, _)| *c == code)
+            .map(|(_, n)| format!(" {n}"))
+            .unwrap_or_default();
+        let mut line = format!(
+            "{p}exception: 0x{code:08x}{name} at {} in thread {tid}",
+            self.symbolize_or_hex(addr)
+        );
+        // the accessed address of access violations
+        let has_target = code == 0xc0000005 && u32_at(s, 32).is_some_and(|n| n >= 2);
+        if let (true, Some(kind), Some(target)) = (has_target, u64_at(s, 40), u64_at(s, 48)) {
+            let kind = match kind {
+                0 => "reading",
+                1 => "writing",
+                _ => "executing",
+            };
+            line.push_str(&format!(", {kind} 0x{target:x}"));
+        }
+        writeln!(out, "{line}")?;
+        Ok(())
+    }
+
+    fn write_modules(&self, p: &str, out: &mut impl Write) -> Result<()> {
+        let Some(s) = self.stream(MODULE_LIST_STREAM) else {
+            return Ok(());
+        };
+        let count = u32_at(s, 0).unwrap_or(0) as usize;
+        for i in 0..count {
+            let Some(m) = s.get(4 + i * 108..4 + (i + 1) * 108) else {
+                break;
+            };
+            let base = u64_at(m, 0).unwrap_or(0);
+            let name = u32_at(m, 20)
+                .and_then(|rva| self.string(rva as u64))
+                .unwrap_or_default();
+            let mut line = format!("{p}module: {name} at 0x{base:x}");
+            // VS_FIXEDFILEINFO, only set if the signature is present
+            if u32_at(m, 24) == Some(0xfeef04bd) {
+                let (ms, ls) = (u32_at(m, 32).unwrap_or(0), u32_at(m, 36).unwrap_or(0));
+                line.push_str(&format!(
+                    ", version {}.{}.{}.{}",
+                    ms >> 16,
+                    ms & 0xffff,
+                    ls >> 16,
+                    ls & 0xffff
+                ));
+            }
+            // CodeView record with the PDB name
+            let cv = match (u32_at(m, 76), u32_at(m, 80)) {
+                (Some(size), Some(rva)) => self.location(size as u64, rva as u64),
+                _ => None,
+            };
+            if let Some(pdb) = cv
+                .filter(|cv| cv.starts_with(b"RSDS"))
+                .and_then(|cv| cv.get(24..))
+            {
+                let pdb = pdb.split(|b| *b == 0).next().unwrap_or_default();
+                line.push_str(&format!(", pdb {}", String::from_utf8_lossy(pdb)));
+            }
+            writeln!(out, "{line}")?;
+        }
+        Ok(())
+    }
+
+    fn write_threads(&self, p: &str, out: &mut impl Write) -> Result<()> {
+        let Some(s) = self.stream(THREAD_LIST_STREAM) else {
+            return Ok(());
+        };
+        let mut names = std::collections::HashMap::new();
+        if let Some(t) = self.stream(THREAD_NAMES_STREAM) {
+            let count = u32_at(t, 0).unwrap_or(0) as usize;
+            for i in 0..count {
+                let (Some(tid), Some(rva)) = (u32_at(t, 4 + i * 12), u64_at(t, 8 + i * 12)) else {
+                    break;
+                };
+                if let Some(name) = self.string(rva) {
+                    names.insert(tid, name);
+                }
+            }
+        }
+        let count = u32_at(s, 0).unwrap_or(0) as usize;
+        for i in 0..count {
+            let Some(t) = s.get(4 + i * 48..4 + (i + 1) * 48) else {
+                break;
+            };
+            let tid = u32_at(t, 0).unwrap_or(0);
+            let name = names
+                .get(&tid)
+                .map(|n| format!(" ({n})"))
+                .unwrap_or_default();
+            let mut frames = Vec::new();
+            let context = match (u32_at(t, 40), u32_at(t, 44)) {
+                (Some(size), Some(rva)) => self.location(size as u64, rva as u64),
+                _ => None,
+            };
+            if let Some(ip) = context.and_then(|c| self.instruction_pointer(c)) {
+                frames.push(self.symbolize_or_hex(ip));
+            }
+            // without unwind info, the return addresses are found by scanning the stack for pointers into modules
+            let stack = match (u32_at(t, 32), u32_at(t, 36)) {
+                (Some(size), Some(rva)) => self.location(size as u64, rva as u64),
+                _ => None,
+            };
+            for word in stack.unwrap_or_default().chunks_exact(self.pointer_size) {
+                if frames.len() >= MAX_STACK_FRAMES {
+                    break;
+                }
+                let addr = match self.pointer_size {
+                    4 => u32_at(word, 0).map(u64::from),
+                    _ => u64_at(word, 0),
+                };
+                if let Some(frame) = addr.and_then(|a| self.symbolize(a)) {
+                    frames.push(frame);
+                }
+            }
+            writeln!(out, "{p}thread {tid}{name}: {}", frames.join(" < "))?;
+        }
+        Ok(())
+    }
+
+    fn write_linux_streams(&self, p: &str, out: &mut impl Write) -> Result<()> {
+        for (stream_type, name) in LINUX_STREAMS {
+            let Some(s) = self.stream(*stream_type) else {
+                continue;
+            };
+            let text = String::from_utf8_lossy(s);
+            if *stream_type == 0x47670006 {
+                // arguments are separated by null bytes
+                let cmdline = text.trim_end_matches('\0').replace('\0', " ");
+                writeln!(out, "{p}{name}: {cmdline}")?;
+                continue;
+            }
+            for line in text.split(['\n', '\0']).filter(|l| !l.is_empty()) {
+                writeln!(out, "{p}{name}: {line}")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_memory_strings(&self, p: &str, min_len: usize, out: &mut impl Write) -> Result<()> {
+        let mut regions = Vec::new();
+        if let Some(s) = self.stream(MEMORY_LIST_STREAM) {
+            let count = u32_at(s, 0).unwrap_or(0) as usize;
+            for i in 0..count {
+                let d = 4 + i * 16;
+                let (Some(start), Some(size), Some(rva)) =
+                    (u64_at(s, d), u32_at(s, d + 8), u32_at(s, d + 12))
+                else {
+                    break;
+                };
+                regions.extend(
+                    self.location(size as u64, rva as u64)
+                        .map(|data| (start, data)),
+                );
+            }
+        }
+        if let Some(s) = self.stream(MEMORY64_LIST_STREAM) {
+            // the memory of all regions follows each other starting at the base rva
+            let count = u64_at(s, 0).unwrap_or(0) as usize;
+            let mut rva = u64_at(s, 8).unwrap_or(0);
+            for i in 0..count {
+                let d = 16 + i * 16;
+                let (Some(start), Some(size)) = (u64_at(s, d), u64_at(s, d + 8)) else {
+                    break;
+                };
+                regions.extend(self.location(size, rva).map(|data| (start, data)));
+                rva += size;
+            }
+        }
+        for (start, data) in regions {
+            write_strings(data, min_len, &format!("{p}memory 0x{start:x}+"), &mut *out)?;
+        }
+        Ok(())
+    }
+}
+
+fn dump_minidump(data: &[u8], min_len: usize, p: &str, mut out: impl Write) -> Result<()> {
+    let dump = Minidump::parse(data)?;
+    dump.write_system_info(p, &mut out)?;
+    dump.write_exception(p, &mut out)?;
+    dump.write_modules(p, &mut out)?;
+    dump.write_threads(p, &mut out)?;
+    dump.write_linux_streams(p, &mut out)?;
+    dump.write_memory_strings(p, min_len, &mut out)?;
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for MinidumpAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        // the streams reference each other by offset
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            dump_minidump(&data, config.strings_min_length.0, &line_prefix, oup)
+        })
+        .await?
+        .context("in synchronous minidump task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    /// A minidump with a module list and an exception
+    fn minidump() -> Vec<u8> {
+        let mut d = Vec::new();
+        d.extend_from_slice(SIGNATURE);
+        for v in [0xa793u32, 2, 32, 0, 0, 0, 0] {
+            d.extend_from_slice(&v.to_le_bytes());
+        }
+        // directory at 32, streams at 56
+        let modules_rva = 56u32;
+        let exception_rva = modules_rva + 4 + 108;
+        let name_rva = exception_rva + 168;
+        for v in [MODULE_LIST_STREAM, 112, modules_rva] {
+            d.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [EXCEPTION_STREAM, 168, exception_rva] {
+            d.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut module = vec![0u8; 108];
+        module[0..8].copy_from_slice(&0x7ff0_0000u64.to_le_bytes());
+        module[8..12].copy_from_slice(&0x1000u32.to_le_bytes());
+        module[20..24].copy_from_slice(&name_rva.to_le_bytes());
+        d.extend_from_slice(&1u32.to_le_bytes());
+        d.extend_from_slice(&module);
+        let mut exception = vec![0u8; 168];
+        exception[0..4].copy_from_slice(&42u32.to_le_bytes());
+        exception[8..12].copy_from_slice(&0xc0000005u32.to_le_bytes());
+        exception[24..32].copy_from_slice(&0x7ff0_0123u64.to_le_bytes());
+        d.extend_from_slice(&exception);
+        let name: Vec<u8> = "C:\\app\\crashy.dll"
+            .encode_utf16()
+            .flat_map(|u| u.to_le_bytes())
+            .collect();
+        d.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        d.extend_from_slice(&name);
+        d
+    }
+
+    #[tokio::test]
+    async fn modules_and_exception() -> Result<()> {
+        let adapter: Box<dyn FileAdapter> = Box::<MinidumpAdapter>::default();
+        let (a, d) = simple_adapt_info(
+            std::path::Path::new("crash.dmp"),
+            Box::pin(std::io::Cursor::new(minidump())),
+        );
+        let res = loop_adapt(adapter.as_ref(), d, a).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:exception: 0xc0000005 EXCEPTION_ACCESS_VIOLATION at crashy.dll+0x123 in thread 42\n\
+             PREFIX:module: C:\\app\\crashy.dll at 0x7ff00000\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn matched_by_signature() -> Result<()> {
+        use crate::matching::{FileMeta, adapter_matcher};
+        let adapters: Vec<Arc<dyn FileAdapter>> = vec![Arc::new(MinidumpAdapter::new())];
+        let meta = |name: &str, mimetype| FileMeta {
+            lossy_filename: name.to_string(),
+            mimetype,
+            path: None,
+        };
+        let fast = adapter_matcher(&adapters, false)?;
+        assert!(fast(meta("crash.mdmp", None)).is_some());
+        assert!(fast(meta("MEMORY.DMP", None)).is_none());
+        let accurate = adapter_matcher(&adapters, true)?;
+        assert!(accurate(meta("crash.dmp", Some(MIMETYPE))).is_some());
+        assert!(accurate(meta("MEMORY.DMP", Some("application/octet-stream"))).is_none());
+        Ok(())
+    }
+}
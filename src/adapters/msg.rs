@@ -0,0 +1,388 @@
+use super::binary::{u16_at, u64_at, utf16};
+use super::cfb::CompoundFile;
+use super::eml::{MailPart, adapt_parts};
+use super::*;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["msg"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "msg".to_owned(),
+        version: 1,
+        description: "Reads Outlook messages (.msg). Outputs the sender, recipients, date, subject and body, and recurses into the attachments (prefixed with their file name), including attached messages"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.ms-outlook".to_owned()
+        )]),
+        disabled_by_default: false,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MsgAdapter;
+
+impl MsgAdapter {
+    pub fn new() -> MsgAdapter {
+        MsgAdapter
+    }
+}
+impl GetMetadata for MsgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+// property ids
+const PR_SUBJECT: u16 = 0x0037;
+const PR_CLIENT_SUBMIT_TIME: u16 = 0x0039;
+const PR_SENDER_NAME: u16 = 0x0c1a;
+const PR_SENDER_EMAIL_ADDRESS: u16 = 0x0c1f;
+const PR_SENDER_SMTP_ADDRESS: u16 = 0x5d01;
+const PR_RECIPIENT_TYPE: u16 = 0x0c15;
+const PR_DISPLAY_BCC: u16 = 0x0e02;
+const PR_DISPLAY_CC: u16 = 0x0e03;
+const PR_DISPLAY_TO: u16 = 0x0e04;
+const PR_MESSAGE_DELIVERY_TIME: u16 = 0x0e06;
+const PR_BODY: u16 = 0x1000;
+const PR_HTML: u16 = 0x1013;
+const PR_DISPLAY_NAME: u16 = 0x3001;
+const PR_EMAIL_ADDRESS: u16 = 0x3003;
+const PR_SMTP_ADDRESS: u16 = 0x39fe;
+const PR_ATTACH_DATA: u16 = 0x3701;
+const PR_ATTACH_FILENAME: u16 = 0x3704;
+const PR_ATTACH_LONG_FILENAME: u16 = 0x3707;
+
+// property types
+const PT_STRING8: u16 = 0x001e;
+const PT_UNICODE: u16 = 0x001f;
+const PT_SYSTIME: u16 = 0x0040;
+const PT_OBJECT: u16 = 0x000d;
+const PT_BINARY: u16 = 0x0102;
+
+const RECIPIENT_PREFIX: &str = "__recip_version1.0_";
+const ATTACHMENT_PREFIX: &str = "__attach_version1.0_";
+const PROPERTIES: &str = "__properties_version1.0";
+
+/// size of the header of the fixed size property stream, which depends on the kind of storage
+const MESSAGE_HEADER: usize = 32;
+const EMBEDDED_MESSAGE_HEADER: usize = 24;
+const SUB_STORAGE_HEADER: usize = 8;
+
+/// attached messages nested deeper than this are skipped
+const MAX_NESTING: usize = 16;
+
+/// A storage with properties: a message, recipient or attachment
+struct PropertyStorage<'a> {
+    cf: &'a CompoundFile,
+    id: usize,
+    header_size: usize,
+}
+
+impl<'a> PropertyStorage<'a> {
+    fn stream(&self, prop: u16, kind: u16) -> Option<usize> {
+        self.cf
+            .child(self.id, &format!("__substg1.0_{prop:04X}{kind:04X}"))
+    }
+
+    fn string(&self, prop: u16) -> Option<String> {
+        let s = if let Some(id) = self.stream(prop, PT_UNICODE) {
+            let data = self.cf.read(id).ok()?;
+            utf16(&data)
+        } else {
+            let data = self.cf.read(self.stream(prop, PT_STRING8)?).ok()?;
+            // the code page of the message is usually the ANSI code page of the sender
+            encoding_rs::WINDOWS_1252.decode(&data).0.into_owned()
+        };
+        let s = s.trim_end_matches('\0').trim();
+        (!s.is_empty()).then(|| s.to_string())
+    }
+
+    fn binary(&self, prop: u16) -> Option<Vec<u8>> {
+        self.cf.read(self.stream(prop, PT_BINARY)?).ok()
+    }
+
+    /// The 8 byte value of a fixed size property
+    fn fixed(&self, prop: u16, kind: u16) -> Option<u64> {
+        let data = self.cf.read(self.cf.child(self.id, PROPERTIES)?).ok()?;
+        data.get(self.header_size..)?
+            .chunks_exact(16)
+            .find(|e| u16_at(e, 0) == Some(kind) && u16_at(e, 2) == Some(prop))
+            .and_then(|e| u64_at(e, 8))
+    }
+
+    fn time(&self, prop: u16) -> Option<String> {
+        // FILETIME: 100ns intervals since 1601
+        let ft = self.fixed(prop, PT_SYSTIME).filter(|ft| *ft != 0)?;
+        Some(crate::print_unix_time(
+            (ft / 10_000_000) as i64 - 11_644_473_600,
+        ))
+    }
+
+    /// The sub storages whose name starts with the prefix, in the order of their number
+    fn sub_storages(&self, prefix: &str) -> Vec<PropertyStorage<'a>> {
+        let mut subs: Vec<(String, usize)> = self
+            .cf
+            .children(self.id)
+            .into_iter()
+            .filter_map(|c| {
+                let e = self.cf.entry(c)?;
+                (!e.is_stream() && e.name.starts_with(prefix)).then(|| (e.name.clone(), c))
+            })
+            .collect();
+        subs.sort();
+        subs.into_iter()
+            .map(|(_, id)| PropertyStorage {
+                cf: self.cf,
+                id,
+                header_size: SUB_STORAGE_HEADER,
+            })
+            .collect()
+    }
+}
+
+/// `Name <address>`, or whichever of them is known
+fn name_address(name: Option<String>, address: Option<String>) -> Option<String> {
+    match (name, address) {
+        (Some(name), Some(address)) if name != address => Some(format!("{name} <{address}>")),
+        (name, address) => name.or(address),
+    }
+}
+
+fn header_text(msg: &PropertyStorage) -> String {
+    let mut lines = Vec::new();
+    let sender = name_address(
+        msg.string(PR_SENDER_NAME),
+        msg.string(PR_SENDER_SMTP_ADDRESS)
+            .or_else(|| msg.string(PR_SENDER_EMAIL_ADDRESS)),
+    );
+    if let Some(sender) = sender {
+        lines.push(format!("From: {sender}"));
+    }
+    // recipients by type: 1 to, 2 cc, 3 bcc
+    let mut recipients: [Vec<String>; 3] = Default::default();
+    for r in msg.sub_storages(RECIPIENT_PREFIX) {
+        let kind = r.fixed(PR_RECIPIENT_TYPE, 0x0003).unwrap_or(1) as usize;
+        let address = r
+            .string(PR_SMTP_ADDRESS)
+            .or_else(|| r.string(PR_EMAIL_ADDRESS));
+        if let (Some(list), Some(recipient)) = (
+            recipients.get_mut(kind.wrapping_sub(1)),
+            name_address(r.string(PR_DISPLAY_NAME), address),
+        ) {
+            list.push(recipient);
+        }
+    }
+    for ((label, display), list) in [
+        ("To", PR_DISPLAY_TO),
+        ("Cc", PR_DISPLAY_CC),
+        ("Bcc", PR_DISPLAY_BCC),
+    ]
+    .into_iter()
+    .zip(recipients)
+    {
+        // the display properties only contain the names
+        let value = if list.is_empty() {
+            msg.string(display)
+        } else {
+            Some(list.join(", "))
+        };
+        if let Some(value) = value {
+            lines.push(format!("{label}: {value}"));
+        }
+    }
+    let date = msg
+        .time(PR_CLIENT_SUBMIT_TIME)
+        .or_else(|| msg.time(PR_MESSAGE_DELIVERY_TIME));
+    if let Some(date) = date {
+        lines.push(format!("Date: {date}"));
+    }
+    if let Some(subject) = msg.string(PR_SUBJECT) {
+        lines.push(format!("Subject: {subject}"));
+    }
+    lines.join("\n")
+}
+
+fn message_parts(msg: &PropertyStorage, depth: usize, parts: &mut Vec<MailPart>) {
+    let headers = header_text(msg);
+    if !headers.is_empty() {
+        parts.push(MailPart {
+            name: "headers.txt".to_string(),
+            prefix: String::new(),
+            data: headers.into_bytes(),
+        });
+    }
+    if let Some(body) = msg.string(PR_BODY) {
+        parts.push(MailPart {
+            name: "data.txt".to_string(),
+            prefix: String::new(),
+            data: body.into_bytes(),
+        });
+    } else if let Some(html) = msg
+        .binary(PR_HTML)
+        .or_else(|| msg.string(PR_HTML).map(String::into_bytes))
+    {
+        parts.push(MailPart {
+            name: "data.html".to_string(),
+            prefix: String::new(),
+            data: html,
+        });
+    }
+    for a in msg.sub_storages(ATTACHMENT_PREFIX) {
+        let name = a
+            .string(PR_ATTACH_LONG_FILENAME)
+            .or_else(|| a.string(PR_ATTACH_FILENAME))
+            .or_else(|| a.string(PR_DISPLAY_NAME));
+        if let Some(data) = a.binary(PR_ATTACH_DATA) {
+            let name = name.unwrap_or_else(|| "attachment.bin".to_string());
+            parts.push(MailPart {
+                prefix: format!("{name}: "),
+                name,
+                data,
+            });
+        } else if let Some(id) = a.stream(PR_ATTACH_DATA, PT_OBJECT) {
+            if depth >= MAX_NESTING {
+                warn!("skipping attached message nested too deeply");
+                continue;
+            }
+            // attached messages are storages, not files of their own
+            let name = name.unwrap_or_else(|| "attachment.msg".to_string());
+            let embedded = PropertyStorage {
+                cf: a.cf,
+                id,
+                header_size: EMBEDDED_MESSAGE_HEADER,
+            };
+            let mut nested = Vec::new();
+            message_parts(&embedded, depth + 1, &mut nested);
+            parts.extend(nested.into_iter().map(|p| MailPart {
+                name: format!("{name}/{}", p.name),
+                prefix: format!("{name}: {}", p.prefix),
+                data: p.data,
+            }));
+        }
+    }
+}
+
+#[async_trait]
+impl FileAdapter for MsgAdapter {
+    async fn adapt(
+        &self,
+        mut ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let mut content = Vec::new();
+        ai.inp.read_to_end(&mut content).await?;
+        let cf = CompoundFile::parse(content)?;
+        let msg = PropertyStorage {
+            cf: &cf,
+            id: 0,
+            header_size: MESSAGE_HEADER,
+        };
+        let mut parts = Vec::new();
+        message_parts(&msg, 0, &mut parts);
+        Ok(adapt_parts(parts, ai))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    fn fixed(header_size: usize, props: &[(u16, u16, u64)]) -> Vec<u8> {
+        let mut out = vec![0u8; header_size];
+        for (kind, id, value) in props {
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&6u32.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn message() -> Result<()> {
+        // 2020-01-01 00:00:00 UTC
+        let date = (1_577_836_800 + 11_644_473_600) * 10_000_000;
+        let streams: Vec<(&str, Vec<u8>)> = vec![
+            ("__substg1.0_0037001F", utf16("Quarterly report")),
+            ("__substg1.0_0C1A001F", utf16("Alice")),
+            ("__substg1.0_5D01001F", utf16("alice@example.com")),
+            ("__substg1.0_1000001F", utf16("Numbers are up")),
+            (
+                PROPERTIES,
+                fixed(MESSAGE_HEADER, &[(PT_SYSTIME, PR_CLIENT_SUBMIT_TIME, date)]),
+            ),
+            (
+                "__recip_version1.0_#00000000/__substg1.0_3001001F",
+                utf16("Bob"),
+            ),
+            (
+                "__recip_version1.0_#00000000/__substg1.0_39FE001F",
+                utf16("bob@example.com"),
+            ),
+            (
+                "__recip_version1.0_#00000000/__properties_version1.0",
+                fixed(SUB_STORAGE_HEADER, &[(0x0003, PR_RECIPIENT_TYPE, 1)]),
+            ),
+            (
+                "__attach_version1.0_#00000000/__substg1.0_3707001F",
+                utf16("notes.txt"),
+            ),
+            (
+                "__attach_version1.0_#00000000/__substg1.0_37010102",
+                b"attached notes".to_vec(),
+            ),
+            (
+                "__attach_version1.0_#00000001/__substg1.0_3001001F",
+                utf16("forwarded"),
+            ),
+            (
+                "__attach_version1.0_#00000001/__substg1.0_3701000D/__substg1.0_0037001F",
+                utf16("Old news"),
+            ),
+            (
+                "__attach_version1.0_#00000001/__substg1.0_3701000D/__substg1.0_1000001F",
+                utf16("the forwarded body"),
+            ),
+        ];
+        let streams: Vec<(&str, &[u8])> = streams.iter().map(|(n, d)| (*n, &d[..])).collect();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("report.msg"),
+            Box::pin(Cursor::new(create_cfb(&streams))),
+        );
+        let buf = adapted_to_vec(loop_adapt(&MsgAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            format!(
+                "PREFIX:From: Alice <alice@example.com>
+PREFIX:To: Bob <bob@example.com>
+PREFIX:Date: {}
+PREFIX:Subject: Quarterly report
+PREFIX:Numbers are up
+PREFIX:notes.txt: attached notes
+PREFIX:forwarded: Subject: Old news
+PREFIX:forwarded: the forwarded body
+",
+                crate::print_unix_time(1_577_836_800)
+            )
+        );
+        Ok(())
+    }
+}
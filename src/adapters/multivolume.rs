@@ -0,0 +1,179 @@
+use crate::adapted_iter::one_file;
+
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// extensions of the first volume of split archives
+static EXTENSIONS: &[&str] = &["001", "z01"];
+
+/// Marker at the start of the first volume of a split zip archive
+const ZIP_SPLIT_MARKER: &[u8] = b"PK\x07\x08";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "multivolume".to_owned(),
+        version: 1,
+        description: "Joins the volumes of split archives and searches the combined archive: `x.7z.001`, `x.7z.002`, ... (any file split into numbered parts) and split zip files `x.z01`, `x.z02`, ..., `x.zip`.\nMatches the first volume, the other volumes must be in the same directory. The joined archive is read as a stream, so for split zip files only the first file decides whether 7z is needed (for Zip64, encryption or names that are not UTF-8)."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        disabled_by_default: false,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+
+#[derive(Default)]
+pub struct MultiVolumeAdapter;
+
+impl MultiVolumeAdapter {
+    pub fn new() -> MultiVolumeAdapter {
+        MultiVolumeAdapter
+    }
+}
+impl GetMetadata for MultiVolumeAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// The volumes of a split archive in order and the name of the joined archive, given the path of the first volume.
+///
+/// Supported are `x.7z.001`, `x.7z.002`, ... (-> `x.7z`) and `x.z01`, `x.z02`, ..., `x.zip` (-> `x.zip`).
+/// Returns None if the path is not the first volume of a split archive.
+pub fn volumes(first: &Path) -> Option<(Vec<PathBuf>, PathBuf)> {
+    let name = first.file_name()?.to_str()?;
+    let sibling = |name: String| first.with_file_name(name);
+    let numbered = |prefix: &str, width: usize| {
+        (1..)
+            .map(|i| sibling(format!("{prefix}{i:0width$}")))
+            .take_while(|p| p.is_file())
+            .collect::<Vec<_>>()
+    };
+    if let Some(stem) = name.strip_suffix(".001") {
+        Some((numbered(&format!("{stem}."), 3), sibling(stem.to_string())))
+    } else if let Some(stem) = name.strip_suffix(".z01") {
+        let mut volumes = numbered(&format!("{stem}.z"), 2);
+        // the last volume has the normal extension
+        let last = sibling(format!("{stem}.zip"));
+        if !last.is_file() {
+            return None;
+        }
+        volumes.push(last.clone());
+        Some((volumes, last))
+    } else {
+        None
+    }
+}
+
+/// Whether a zip file is the last volume of a split zip archive, which is searched as part of the first volume (`.z01`).
+pub fn is_last_zip_volume(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "zip") && path.with_extension("z01").is_file()
+}
+
+/// Concatenates the volumes, without the split marker at the start of split zip archives
+fn join_volumes(volumes: Vec<PathBuf>) -> ReadBox {
+    let s = stream! {
+        for (i, volume) in volumes.into_iter().enumerate() {
+            let mut file = tokio::fs::File::open(&volume).await?;
+            if i == 0 {
+                let mut start = Vec::new();
+                (&mut file).take(ZIP_SPLIT_MARKER.len() as u64).read_to_end(&mut start).await?;
+                if start != ZIP_SPLIT_MARKER {
+                    yield std::io::Result::Ok(bytes::Bytes::from(start));
+                }
+            }
+            for await chunk in ReaderStream::new(file) {
+                yield chunk;
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+#[async_trait]
+impl FileAdapter for MultiVolumeAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+            ..
+        } = ai;
+        if !is_real_file {
+            // the other volumes can't be found within archives
+            anyhow::bail!("split archives can only be searched on disk");
+        }
+        let (volumes, joined) = volumes(&filepath_hint)
+            .with_context(|| format!("other volumes of {} not found", filepath_hint.display()))?;
+        debug!("joining volumes {:?}", volumes);
+        Ok(one_file(AdaptInfo {
+            filepath_hint: joined,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            inp: join_volumes(volumes),
+            line_prefix,
+            postprocess,
+            config,
+            passwords,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn split_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = dir.path().join("notes.txt.001");
+        std::fs::write(&first, "hello ")?;
+        std::fs::write(dir.path().join("notes.txt.002"), "world\n")?;
+        let (volumes, joined) = volumes(&first).context("no volumes")?;
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(joined, dir.path().join("notes.txt"));
+
+        let (a, d) = simple_fs_adapt_info(&first).await?;
+        let res = MultiVolumeAdapter::new().adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "hello world\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn split_zip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["a.z01", "a.z02", "a.zip"] {
+            std::fs::write(dir.path().join(name), "")?;
+        }
+        let (volumes, joined) = volumes(&dir.path().join("a.z01")).context("no volumes")?;
+        let names: Vec<_> = volumes.iter().map(|v| v.file_name().unwrap()).collect();
+        assert_eq!(names, ["a.z01", "a.z02", "a.zip"]);
+        assert_eq!(joined, dir.path().join("a.zip"));
+        assert!(is_last_zip_volume(&dir.path().join("a.zip")));
+        Ok(())
+    }
+}
@@ -0,0 +1,464 @@
+//! Reading of NumPy `.npy` files: the dtype and shape of the array and the contents of string and object arrays.
+//!
+//! `.npz` files are zip files of `.npy` files, they are read by the zip adapter.
+
+use super::writing::{WritingFileAdapter, async_writeln};
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+static EXTENSIONS: &[&str] = &["npy"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "npy".to_owned(),
+        version: 1,
+        description: "Reads NumPy arrays (.npy, and .npz with the zip adapter). Outputs the dtype and shape (`dtype float64, shape (100, 3)`) and the elements of string and object arrays, one per line"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct NpyAdapter;
+
+impl NpyAdapter {
+    pub fn new() -> NpyAdapter {
+        NpyAdapter
+    }
+}
+
+impl GetMetadata for NpyAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+/// Larger headers are broken, numpy writes less than 64 KiB
+const MAX_HEADER_LEN: usize = 1 << 20;
+/// Only the beginning of larger pickles (object arrays) is read
+const MAX_PICKLE_LEN: u64 = 64 << 20;
+
+/// The value of a key of the header, a Python dict literal like
+/// `{'descr': '<U5', 'fortran_order': False, 'shape': (3, 4), }`
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{key}'"))? + key.len() + 2;
+    let value = header[start..].trim_start().strip_prefix(':')?.trim_start();
+    if let Some(quoted) = value.strip_prefix('\'') {
+        return Some(&quoted[..quoted.find('\'')?]);
+    }
+    // tuples (shape) and lists (structured dtypes) can contain commas
+    let mut depth = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&value[..=i]);
+                }
+            }
+            ',' | '}' if depth == 0 => return Some(value[..i].trim()),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The numpy name of numeric dtypes (`<f8` is `float64`), other dtypes as they are
+fn dtype_name(descr: &str) -> String {
+    let d = descr.trim_start_matches(['<', '>', '|', '=']);
+    let bits = d
+        .get(1..)
+        .and_then(|s| s.parse::<usize>().ok())
+        .map(|s| s * 8);
+    match (d.get(..1), bits) {
+        (Some("b"), Some(8)) => "bool".to_owned(),
+        (Some("i"), Some(bits)) => format!("int{bits}"),
+        (Some("u"), Some(bits)) => format!("uint{bits}"),
+        (Some("f"), Some(bits)) => format!("float{bits}"),
+        (Some("c"), Some(bits)) => format!("complex{bits}"),
+        (Some("O"), _) => "object".to_owned(),
+        _ => descr.to_owned(),
+    }
+}
+
+/// Reads the fields of a pickle
+struct Pickle<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Pickle<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let b = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(b)
+    }
+
+    fn len(&mut self, size: usize) -> Option<usize> {
+        let b = self.take(size)?;
+        Some(b.iter().rev().fold(0, |v, b| (v << 8) | *b as usize))
+    }
+
+    fn line(&mut self) -> Option<&'a [u8]> {
+        let rest = self.data.get(self.pos..)?;
+        let end = memchr::memchr(b'\n', rest)?;
+        self.pos += end + 1;
+        Some(&rest[..end])
+    }
+}
+
+/// The strings of a pickled object array. numpy pickles the array with its elements as a list,
+/// which follows the class names and the dtype
+fn pickle_strings(data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    // a truncated pickle ends the walk, the strings before it are still output
+    walk_pickle(&mut Pickle { data, pos: 0 }, &mut strings);
+    strings
+}
+
+/// Collects the strings of the elements until the end of the pickle or an unknown opcode
+fn walk_pickle(p: &mut Pickle, strings: &mut Vec<String>) -> Option<()> {
+    let mut in_elements = false;
+    // strings pushed directly before, which are the module and name of STACK_GLOBAL
+    let mut pushed = 0;
+    loop {
+        let string = match p.take(1)?[0] {
+            // STOP
+            b'.' => return Some(()),
+            // EMPTY_LIST, LIST
+            b']' | b'l' => {
+                in_elements = true;
+                None
+            }
+            // MARK, POP, POP_MARK, DUP, NONE, BINPERSID, REDUCE, APPEND, BUILD, DICT, EMPTY_DICT, APPENDS, OBJ,
+            // SETITEM, TUPLE, EMPTY_TUPLE, SETITEMS, NEWOBJ, TUPLE1-3, NEWTRUE, NEWFALSE, EMPTY_SET, ADDITEMS,
+            // FROZENSET, NEWOBJ_EX, NEXT_BUFFER, READONLY_BUFFER
+            b'('
+            | b'0'
+            | b'1'
+            | b'2'
+            | b'N'
+            | b'Q'
+            | b'R'
+            | b'a'
+            | b'b'
+            | b'd'
+            | b'}'
+            | b'e'
+            | b'o'
+            | b's'
+            | b't'
+            | b')'
+            | b'u'
+            | 0x81
+            | 0x85..=0x89
+            | 0x8f..=0x92
+            | 0x97
+            | 0x98 => None,
+            // STACK_GLOBAL: the module and class name are not elements
+            0x93 => {
+                strings.truncate(strings.len() - pushed.min(2));
+                None
+            }
+            // MEMOIZE, PUT, BINPUT, LONG_BINPUT
+            0x94 => continue,
+            b'p' => {
+                p.line()?;
+                continue;
+            }
+            b'q' => {
+                p.take(1)?;
+                continue;
+            }
+            b'r' => {
+                p.take(4)?;
+                continue;
+            }
+            // STRING, UNICODE (protocol 0)
+            b'S' | b'V' => Some(
+                String::from_utf8_lossy(p.line()?)
+                    .trim_matches(['\'', '"'])
+                    .to_owned(),
+            ),
+            // FLOAT, INT, LONG, PERSID, GET
+            b'F' | b'I' | b'L' | b'P' | b'g' => {
+                p.line()?;
+                None
+            }
+            // GLOBAL, INST
+            b'c' | b'i' => {
+                p.line()?;
+                p.line()?;
+                None
+            }
+            // BININT1, BINGET, PROTO, EXT1
+            b'K' | b'h' | 0x80 | 0x82 => {
+                p.take(1)?;
+                None
+            }
+            // BININT2, EXT2
+            b'M' | 0x83 => {
+                p.take(2)?;
+                None
+            }
+            // BININT, LONG_BINGET, EXT4
+            b'J' | b'j' | 0x84 => {
+                p.take(4)?;
+                None
+            }
+            // BINFLOAT, FRAME
+            b'G' | 0x95 => {
+                p.take(8)?;
+                None
+            }
+            // LONG1, SHORT_BINBYTES, LONG4, BINBYTES, BINBYTES8, BYTEARRAY8
+            op @ (0x8a | b'C' | 0x8b | b'B' | 0x8e | 0x96) => {
+                let size = match op {
+                    0x8a | b'C' => 1,
+                    0x8b | b'B' => 4,
+                    _ => 8,
+                };
+                let len = p.len(size)?;
+                p.take(len)?;
+                None
+            }
+            // SHORT_BINSTRING, SHORT_BINUNICODE, BINSTRING, BINUNICODE, BINUNICODE8
+            op @ (b'U' | 0x8c | b'T' | b'X' | 0x8d) => {
+                let size = match op {
+                    b'U' | 0x8c => 1,
+                    b'T' | b'X' => 4,
+                    _ => 8,
+                };
+                let len = p.len(size)?;
+                Some(String::from_utf8_lossy(p.take(len)?).into_owned())
+            }
+            op => {
+                debug!("unknown pickle opcode {op:#x}");
+                return None;
+            }
+        };
+        match string {
+            Some(s) if in_elements => {
+                strings.push(s);
+                pushed += 1;
+            }
+            _ => pushed = 0,
+        }
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for NpyAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut inp = tokio::io::BufReader::new(inp);
+        let mut magic = [0; 8];
+        inp.read_exact(&mut magic).await?;
+        anyhow::ensure!(magic.starts_with(MAGIC), "not a NumPy array file");
+        let header_len = match magic[6] {
+            1 => inp.read_u16_le().await? as usize,
+            _ => inp.read_u32_le().await? as usize,
+        };
+        anyhow::ensure!(header_len <= MAX_HEADER_LEN, "invalid NumPy header length");
+        let mut header = vec![0; header_len];
+        inp.read_exact(&mut header).await?;
+        let header = String::from_utf8_lossy(&header);
+        let descr = header_value(&header, "descr").context("NumPy header without descr")?;
+        let shape: Vec<u64> = header_value(&header, "shape")
+            .context("NumPy header without shape")?
+            .trim_matches(['(', ')'])
+            .split(',')
+            .map(|d| d.trim())
+            .filter(|d| !d.is_empty())
+            .map(|d| d.trim_end_matches('L').parse())
+            .collect::<Result<_, _>>()
+            .context("invalid NumPy shape")?;
+        let shape_text = match &shape[..] {
+            [d] => format!("({d},)"),
+            dims => format!(
+                "({})",
+                dims.iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        async_writeln!(
+            oup,
+            "{line_prefix}dtype {}, shape {shape_text}",
+            dtype_name(descr)
+        )?;
+        let count = shape.iter().product::<u64>();
+        let big_endian = descr.starts_with('>');
+        let kind = descr.trim_start_matches(['<', '>', '|', '=']);
+        let size = kind.get(1..).and_then(|s| s.parse::<usize>().ok());
+        match (kind.get(..1), size) {
+            // strings of fixed length in UCS-4 or bytes, padded with zeros
+            (Some("U" | "S"), Some(len)) => {
+                let unicode = kind.starts_with('U');
+                let mut element = vec![0; if unicode { len * 4 } else { len }];
+                for _ in 0..count {
+                    inp.read_exact(&mut element).await?;
+                    let value: String = if unicode {
+                        element
+                            .chunks_exact(4)
+                            .map(|c| {
+                                let c = [c[0], c[1], c[2], c[3]];
+                                let c = if big_endian {
+                                    u32::from_be_bytes(c)
+                                } else {
+                                    u32::from_le_bytes(c)
+                                };
+                                char::from_u32(c).unwrap_or(char::REPLACEMENT_CHARACTER)
+                            })
+                            .collect()
+                    } else {
+                        String::from_utf8_lossy(&element).into_owned()
+                    };
+                    for line in value.trim_end_matches('\0').lines() {
+                        if !line.trim().is_empty() {
+                            async_writeln!(oup, "{line_prefix}{line}")?;
+                        }
+                    }
+                }
+            }
+            (Some("O"), _) => {
+                let mut pickle = Vec::new();
+                (&mut inp)
+                    .take(MAX_PICKLE_LEN)
+                    .read_to_end(&mut pickle)
+                    .await?;
+                for value in pickle_strings(&pickle) {
+                    for line in value.lines().filter(|l| !l.trim().is_empty()) {
+                        async_writeln!(oup, "{line_prefix}{line}")?;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn npy(header: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = header.to_owned();
+        // numpy pads the header with spaces so the data is aligned to 64 bytes
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut npy = MAGIC.to_vec();
+        npy.extend([1, 0]);
+        npy.extend((header.len() as u16).to_le_bytes());
+        npy.extend(header.as_bytes());
+        npy.extend(data);
+        npy
+    }
+
+    /// SHORT_BINUNICODE followed by MEMOIZE
+    fn short_unicode(s: &str) -> Vec<u8> {
+        [&[0x8c, s.len() as u8], s.as_bytes(), &[0x94]].concat()
+    }
+
+    async fn adapt(data: Vec<u8>) -> Result<String> {
+        let (a, d) = simple_adapt_info(&PathBuf::from("array.npy"), Box::pin(Cursor::new(data)));
+        let buf = adapted_to_vec(loop_adapt(&NpyAdapter::new(), d, a).await?).await?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[tokio::test]
+    async fn numeric_and_strings() -> Result<()> {
+        let floats = npy(
+            "{'descr': '<f8', 'fortran_order': False, 'shape': (100, 3), }",
+            &[0; 2400],
+        );
+        assert_eq!(
+            adapt(floats).await?,
+            "PREFIX:dtype float64, shape (100, 3)\n"
+        );
+        let labels: Vec<u8> = ["cat", "dog", "horse"]
+            .iter()
+            .flat_map(|l| {
+                let mut chars: Vec<u32> = l.chars().map(|c| c as u32).collect();
+                chars.resize(5, 0);
+                chars
+            })
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        let strings = npy(
+            "{'descr': '<U5', 'fortran_order': False, 'shape': (3,), }",
+            &labels,
+        );
+        assert_eq!(
+            adapt(strings).await?,
+            "PREFIX:dtype <U5, shape (3,)
+PREFIX:cat
+PREFIX:dog
+PREFIX:horse
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn object_array() -> Result<()> {
+        // pickle.dumps(np.array(['first run', 'second run'], dtype=object), protocol=4)
+        let pickle = [
+            b"\x80\x04".as_slice(),
+            &short_unicode("numpy.core.multiarray"),
+            &short_unicode("_reconstruct"),
+            b"\x93\x94",
+            &short_unicode("numpy"),
+            &short_unicode("ndarray"),
+            b"\x93\x94K\x00\x85\x94C\x01b\x94\x87\x94R\x94(K\x01K\x02\x85\x94",
+            &short_unicode("numpy"),
+            &short_unicode("dtype"),
+            b"\x93\x94",
+            &short_unicode("O8"),
+            b"\x89\x88\x87\x94R\x94(K\x03",
+            &short_unicode("|"),
+            b"NNNJ\xff\xff\xff\xffJ\xff\xff\xff\xffK?t\x94b\x89]\x94(",
+            &short_unicode("first run"),
+            &short_unicode("second run"),
+            b"et\x94b.",
+        ]
+        .concat();
+        let objects = npy(
+            "{'descr': '|O', 'fortran_order': False, 'shape': (2,), }",
+            &pickle,
+        );
+        assert_eq!(
+            adapt(objects).await?,
+            "PREFIX:dtype object, shape (2,)
+PREFIX:first run
+PREFIX:second run
+"
+        );
+        Ok(())
+    }
+}
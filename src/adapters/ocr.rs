@@ -0,0 +1,241 @@
+use super::custom::{map_exe_error, pipe_output};
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::config::OcrConfig;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "tif", "tiff", "bmp", "webp"];
+static MIMETYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/tiff",
+    "image/bmp",
+    "image/webp",
+];
+
+const HELP: &str =
+    "Please install tesseract (e.g. tesseract-ocr) or set ocr.engine_command in the config file.";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ocr".to_owned(),
+        version: 1,
+        description: "Uses tesseract (or ocr.engine_command) to recognize the text in images.\nSlow, so it is disabled by default, enable it with --rga-adapters=+ocr. Enabled, it is used instead of the exif adapter for the images both read".to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIMETYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OcrAdapter;
+
+impl OcrAdapter {
+    pub fn new() -> OcrAdapter {
+        OcrAdapter
+    }
+}
+
+impl GetMetadata for OcrAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for OcrAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+            ..
+        } = ai;
+        let (binary, args) = config.ocr.for_adapter("ocr").command()?;
+        let mut cmd = Command::new(&binary);
+        cmd.args(args);
+        debug!("executing {:?}", cmd);
+        let output = pipe_output(&line_prefix, cmd, inp, &binary, HELP)?;
+        // tesseract ends every page with a form feed, only tiff files can have multiple pages
+        let is_tiff = filepath_hint
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("tif") || e.eq_ignore_ascii_case("tiff"));
+        let output_hint = if is_tiff {
+            format!("{}.txt.asciipagebreaks", filepath_hint.display())
+        } else {
+            format!("{}.txt", filepath_hint.display())
+        };
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(output_hint),
+            inp: output,
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+            passwords,
+        }))
+    }
+}
+
+/// Run a program with the given input and return its output
+async fn run_with_input(
+    binary: &str,
+    args: &[String],
+    input: &[u8],
+    help: &str,
+) -> Result<Vec<u8>> {
+    let mut child = Command::new(binary)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| map_exe_error(e, binary, help))?;
+    let mut stdin = child.stdin.take().expect("is piped");
+    let input = input.to_vec();
+    let writer = tokio::spawn(async move { stdin.write_all(&input).await });
+    let output = child.wait_with_output().await?;
+    writer.await?.ok();
+    if !output.status.success() {
+        anyhow::bail!(
+            "{binary} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Rasterize one page of a PDF and recognize its text
+async fn ocr_pdf_page(pdf: &[u8], page: usize, ocr: &OcrConfig) -> Result<Vec<u8>> {
+    let page = page.to_string();
+    let dpi = ocr.dpi.to_string();
+    let png = run_with_input(
+        "pdftoppm",
+        &[
+            "-f",
+            page.as_str(),
+            "-l",
+            page.as_str(),
+            "-r",
+            dpi.as_str(),
+            "-png",
+            "-",
+        ]
+        .map(String::from),
+        pdf,
+        "Please make sure you have poppler-utils installed.",
+    )
+    .await?;
+    let (binary, args) = ocr.command()?;
+    run_with_input(&binary, &args, &png, HELP).await
+}
+
+/// Replace the pages without text in the output of pdftotext (separated by form feeds) with the OCR result of the page (`--rga-ocr-pdf`).
+pub async fn ocr_empty_pdf_pages(pdf: &[u8], text: &[u8], ocr: &OcrConfig) -> Result<Vec<u8>> {
+    let mut pages: Vec<&[u8]> = text.split(|b| *b == b'\x0c').collect();
+    // every page ends with a form feed
+    if pages.last().is_some_and(|p| p.is_empty()) {
+        pages.pop();
+    }
+    let mut out = Vec::with_capacity(text.len());
+    for (i, page) in pages.into_iter().enumerate() {
+        if page.iter().all(u8::is_ascii_whitespace) {
+            debug!("page {} has no text, running OCR", i + 1);
+            match ocr_pdf_page(pdf, i + 1, ocr).await {
+                Ok(recognized) => {
+                    // the OCR engine also separates pages with form feeds
+                    let recognized = String::from_utf8_lossy(&recognized).replace('\x0c', "");
+                    let recognized = recognized.trim_end();
+                    if !recognized.is_empty() {
+                        out.extend_from_slice(recognized.as_bytes());
+                        out.push(b'\n');
+                    }
+                }
+                Err(e) => warn!("OCR of page {} failed: {:#}", i + 1, e),
+            }
+        } else {
+            out.extend_from_slice(page);
+        }
+        out.push(b'\x0c');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn engine_command() -> Result<()> {
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("scan.png"),
+            Box::pin(Cursor::new(b"not really a png".to_vec())),
+        );
+        a.config.ocr.languages = vec!["eng".to_string(), "deu".to_string()];
+        a.config.ocr.engine_command = Some(
+            [
+                "sh",
+                "-c",
+                "cat > /dev/null; echo \"text in $0\"",
+                "$languages",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+        );
+        let buf = adapted_to_vec(loop_adapt(&OcrAdapter::new(), d, a).await?).await?;
+        assert_eq!(String::from_utf8(buf)?, "PREFIX:text in eng+deu\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pdf_blank_pages() -> Result<()> {
+        let fname = test_data_dir().join("twoblankpages.pdf");
+        let (mut a, d) = simple_adapt_info(&fname, Box::pin(tokio::fs::File::open(&fname).await?));
+        a.config.ocr.pdf = true;
+        a.config.ocr.engine_command = Some(
+            ["sh", "-c", "cat > /dev/null; echo scanned"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        );
+        let buf = adapted_to_vec(loop_adapt(&poppler_adapter(), d, a).await?).await?;
+        let text = String::from_utf8(buf)?;
+        assert!(
+            text.starts_with(
+                "PREFIX:Page 1: scanned\nPREFIX:Page 2: scanned\nPREFIX:Page 3: HelloWorld\n"
+            ),
+            "{text}"
+        );
+        Ok(())
+    }
+}
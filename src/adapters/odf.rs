@@ -0,0 +1,391 @@
+use super::postproc::format_pages;
+use super::writing::WritingFileAdapter;
+use super::xlsx::column_name;
+use super::xml::{XmlEvent, XmlReader, local_name};
+use super::*;
+use crate::config::PageStyle;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["odt", "ott", "ods", "ots", "odp", "otp", "odg"];
+static MIMETYPES: &[&str] = &[
+    "application/vnd.oasis.opendocument.text",
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.presentation",
+    "application/vnd.oasis.opendocument.graphics",
+];
+
+/// Repeated rows and cells with content are output at most this many times
+const MAX_REPEAT: usize = 100;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "odf".to_owned(),
+        version: 1,
+        description: "Reads OpenDocument (LibreOffice) text documents, spreadsheets and presentations.\nLines are prefixed like the pdf, xlsx and pptx adapters: with the page (if the document contains page breaks), the sheet and cell or the slide".to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIMETYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OdfAdapter;
+
+impl OdfAdapter {
+    pub fn new() -> OdfAdapter {
+        OdfAdapter
+    }
+}
+
+impl GetMetadata for OdfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Collects the text of paragraphs and headings (`text:p`, `text:h`), one line each
+#[derive(Default)]
+struct Paragraphs {
+    depth: usize,
+    line: String,
+    lines: Vec<String>,
+}
+
+impl Paragraphs {
+    fn event(&mut self, event: &XmlEvent) {
+        match event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => match local_name(name) {
+                "p" | "h" if !self_closing => self.depth += 1,
+                // runs of spaces are stored as an element with their count
+                "s" if self.depth > 0 => {
+                    let count = event.attr("c").and_then(|c| c.parse().ok()).unwrap_or(1);
+                    self.line.push_str(&" ".repeat(count));
+                }
+                "tab" if self.depth > 0 => self.line.push('\t'),
+                "line-break" if self.depth > 0 => self.end_line(),
+                _ => {}
+            },
+            XmlEvent::End { name } => {
+                if matches!(local_name(name), "p" | "h") && self.depth > 0 {
+                    self.depth -= 1;
+                    // paragraphs in footnotes and annotations continue the outer paragraph
+                    if self.depth == 0 {
+                        self.end_line();
+                    }
+                }
+            }
+            XmlEvent::Text(text) if self.depth > 0 => self.line.push_str(text),
+            XmlEvent::Text(_) => {}
+        }
+    }
+
+    fn end_line(&mut self) {
+        let line = self.line.trim();
+        if !line.is_empty() {
+            self.lines.push(line.to_string());
+        }
+        self.line.clear();
+    }
+
+    /// The lines so far, including the unfinished one
+    fn take(&mut self) -> Vec<String> {
+        self.end_line();
+        std::mem::take(&mut self.lines)
+    }
+}
+
+/// The events of the document body, without deleted text of tracked changes
+fn body_events(xml: &str) -> impl Iterator<Item = XmlEvent<'_>> {
+    let mut tracked_changes = 0;
+    XmlReader::new(xml).filter(move |event| match event {
+        XmlEvent::Start {
+            name, self_closing, ..
+        } if local_name(name) == "tracked-changes" => {
+            if !self_closing {
+                tracked_changes += 1;
+            }
+            false
+        }
+        XmlEvent::End { name } if local_name(name) == "tracked-changes" => {
+            tracked_changes -= 1;
+            false
+        }
+        _ => tracked_changes == 0,
+    })
+}
+
+/// The pages of a text document, split at the page breaks stored by the office suite.
+///
+/// Returns a single page if the document contains no page breaks.
+fn text_pages(xml: &str) -> Vec<Vec<String>> {
+    let mut pages = Vec::new();
+    let mut paragraphs = Paragraphs::default();
+    for event in body_events(xml) {
+        if matches!(&event, XmlEvent::Start { name, .. } if local_name(name) == "soft-page-break") {
+            pages.push(paragraphs.take());
+            continue;
+        }
+        paragraphs.event(&event);
+    }
+    pages.push(paragraphs.take());
+    pages
+}
+
+/// The slides (`draw:page`) of a presentation or drawing, including their notes
+fn slides(xml: &str) -> Vec<Vec<String>> {
+    let mut slides = Vec::new();
+    let mut paragraphs = Paragraphs::default();
+    for event in body_events(xml) {
+        paragraphs.event(&event);
+        if matches!(&event, XmlEvent::End { name } if *name == "draw:page") {
+            slides.push(paragraphs.take());
+        }
+    }
+    slides
+}
+
+/// Output the rows of the sheets of a spreadsheet as `{prefix}{sheet}!{first cell}: {cells separated by tabs}`, like the xlsx adapter
+fn write_sheets(xml: &str, line_prefix: &str, out: &mut String) {
+    let mut sheet = String::new();
+    let mut row = 0;
+    let mut row_repeat = 1;
+    let mut column = 0;
+    let mut column_repeat = 1;
+    // the value of cells that are not text, e.g. numbers
+    let mut value: Option<String> = None;
+    // (column, text)
+    let mut cells: Vec<(usize, String)> = Vec::new();
+    let mut paragraphs = Paragraphs::default();
+    let repeat = |event: &XmlEvent, attr: &str| -> usize {
+        event
+            .attr(attr)
+            .and_then(|r| r.parse().ok())
+            .unwrap_or(1)
+            .max(1)
+    };
+    for event in body_events(xml) {
+        paragraphs.event(&event);
+        let (name, is_end, self_closing) = match &event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => (local_name(name), false, *self_closing),
+            XmlEvent::End { name } => (local_name(name), true, false),
+            XmlEvent::Text(_) => continue,
+        };
+        match name {
+            "table" if !is_end => {
+                sheet = event.attr("name").unwrap_or_default().to_string();
+                row = 0;
+            }
+            "table-row" if !is_end => {
+                row_repeat = repeat(&event, "number-rows-repeated");
+                column = 0;
+                cells.clear();
+            }
+            "table-row" => {
+                if let Some(&(first, _)) = cells.first() {
+                    for r in 0..row_repeat.min(MAX_REPEAT) {
+                        let mut line = format!(
+                            "{line_prefix}{sheet}!{}{}: ",
+                            column_name(first),
+                            row + r + 1
+                        );
+                        let mut previous = first;
+                        for (i, (column, text)) in cells.iter().enumerate() {
+                            if i > 0 {
+                                line.push_str(&"\t".repeat(column - previous));
+                            }
+                            line.push_str(text);
+                            previous = *column;
+                        }
+                        out.push_str(&line);
+                        out.push('\n');
+                    }
+                }
+                row += row_repeat;
+            }
+            "table-cell" | "covered-table-cell" if !is_end => {
+                column_repeat = repeat(&event, "number-columns-repeated");
+                value = ["value", "date-value", "time-value", "boolean-value"]
+                    .iter()
+                    .find_map(|a| event.attr(a))
+                    .map(str::to_string);
+                paragraphs.take();
+                if self_closing {
+                    // no content, but may have a value
+                    end_cell(
+                        &mut cells,
+                        &mut column,
+                        column_repeat,
+                        value.take(),
+                        Vec::new(),
+                    );
+                }
+            }
+            "table-cell" | "covered-table-cell" => {
+                end_cell(
+                    &mut cells,
+                    &mut column,
+                    column_repeat,
+                    value.take(),
+                    paragraphs.take(),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+fn end_cell(
+    cells: &mut Vec<(usize, String)>,
+    column: &mut usize,
+    repeat: usize,
+    value: Option<String>,
+    lines: Vec<String>,
+) {
+    let text = if lines.is_empty() {
+        value.unwrap_or_default()
+    } else {
+        lines.join(" ")
+    };
+    let text = text.replace('\t', " ");
+    if !text.trim().is_empty() {
+        for c in 0..repeat.min(MAX_REPEAT) {
+            cells.push((*column + c, text.clone()));
+        }
+    }
+    *column += repeat;
+}
+
+/// Convert the body of `content.xml` to text depending on the type of the document
+fn document_text(content: &str, line_prefix: &str, page_style: PageStyle) -> String {
+    let body = XmlReader::new(content).find_map(|e| match e {
+        XmlEvent::Start { name, .. }
+            if matches!(
+                name,
+                "office:text" | "office:spreadsheet" | "office:presentation" | "office:drawing"
+            ) =>
+        {
+            Some(name)
+        }
+        _ => None,
+    });
+    match body {
+        Some("office:spreadsheet") => {
+            let mut out = String::new();
+            write_sheets(content, line_prefix, &mut out);
+            out
+        }
+        Some("office:presentation" | "office:drawing") => {
+            format_pages("Slide", slides(content), line_prefix, page_style)
+        }
+        _ => {
+            let pages = text_pages(content);
+            if pages.len() > 1 {
+                format_pages("Page", pages, line_prefix, page_style)
+            } else {
+                pages
+                    .concat()
+                    .iter()
+                    .map(|line| format!("{line_prefix}{line}\n"))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for OdfAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let files = zip::read_zip_files(inp, &filepath_hint, is_real_file).await?;
+        let content = files
+            .iter()
+            .find(|(name, _)| name == "content.xml")
+            .map(|(_, content)| String::from_utf8_lossy(content))
+            .context("content.xml not found")?;
+        let out = document_text(&content, &line_prefix, config.page_style);
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn content(body: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:draw="urn:oasis:names:tc:opendocument:xmlns:drawing:1.0"><office:automatic-styles/><office:body>{body}</office:body></office:document-content>"#
+        )
+    }
+
+    #[test]
+    fn text() {
+        let xml = content(
+            r#"<office:text><text:tracked-changes><text:changed-region><text:deletion><text:p>deleted</text:p></text:deletion></text:changed-region></text:tracked-changes>
+<text:h text:outline-level="1">Title</text:h><text:p>a<text:s text:c="2"/>b<text:tab/>c<text:line-break/>d<text:note><text:note-body><text:p> (note)</text:p></text:note-body></text:note></text:p>
+<text:p>end of <text:soft-page-break/>page</text:p><text:p><text:span>second</text:span> page</text:p></office:text>"#,
+        );
+        assert_eq!(
+            document_text(&xml, "P:", PageStyle::Prefix),
+            "P:Page 1: Title\nP:Page 1: a  b\tc\nP:Page 1: d (note)\nP:Page 1: end of\nP:Page 2: page\nP:Page 2: second page\n"
+        );
+        let xml = content("<office:text><text:p>no pages</text:p></office:text>");
+        assert_eq!(document_text(&xml, "P:", PageStyle::Prefix), "P:no pages\n");
+    }
+
+    #[test]
+    fn spreadsheet() {
+        let xml = content(
+            r#"<office:spreadsheet><table:table table:name="Data"><table:table-column/>
+<table:table-row><table:table-cell office:value-type="string"><text:p>Name</text:p></table:table-cell><table:table-cell table:number-columns-repeated="2"/><table:table-cell office:value-type="float" office:value="3.5"><text:p>3,50 €</text:p></table:table-cell></table:table-row>
+<table:table-row table:number-rows-repeated="3"><table:table-cell table:number-columns-repeated="1024"/></table:table-row>
+<table:table-row><table:table-cell/><table:table-cell office:value-type="float" office:value="42"/></table:table-row>
+</table:table></office:spreadsheet>"#,
+        );
+        assert_eq!(
+            document_text(&xml, "P:", PageStyle::Prefix),
+            "P:Data!A1: Name\t\t\t3,50 €\nP:Data!B5: 42\n"
+        );
+    }
+
+    #[test]
+    fn presentation() {
+        let xml = content(
+            r#"<office:presentation><draw:page draw:name="page1"><draw:frame><draw:text-box><text:p>Hello</text:p></draw:text-box></draw:frame><presentation:notes><draw:frame><draw:text-box><text:p>a note</text:p></draw:text-box></draw:frame></presentation:notes></draw:page><draw:page draw:name="page2"><draw:frame><draw:text-box><text:p>World</text:p></draw:text-box></draw:frame></draw:page></office:presentation>"#,
+        );
+        assert_eq!(
+            document_text(&xml, "P:", PageStyle::Heading),
+            "P:== Slide 1 ==\nP:Hello\nP:a note\nP:== Slide 2 ==\nP:World\n"
+        );
+    }
+}
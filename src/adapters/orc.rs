@@ -0,0 +1,195 @@
+use super::parquet::{RowWriter, write_json_rows};
+use super::sevenzip::archive_on_disk;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::AsyncWrite;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["orc"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "orc".to_owned(),
+        version: 1,
+        description: "Reads Apache ORC files with `orc-contents` and outputs each row as `row N: column=value, ...`, like the parquet adapter.\nThe number of rows is limited by --rga-max-rows"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OrcAdapter;
+
+impl OrcAdapter {
+    pub fn new() -> OrcAdapter {
+        OrcAdapter
+    }
+}
+
+impl GetMetadata for OrcAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have orc-contents (from the Apache ORC tools, package orc-tools or apache-orc) installed.";
+
+#[async_trait]
+impl WritingFileAdapter for OrcAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let (path, _tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        // outputs one JSON object per row
+        let mut cmd = Command::new("orc-contents");
+        cmd.arg(&path);
+        let rows = RowWriter::new(line_prefix, &config);
+        write_json_rows(cmd, None, "orc-contents", HELP, rows, oup).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn varint(mut v: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        while v >= 0x80 {
+            out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+        out
+    }
+
+    /// A protobuf varint field
+    fn uint(field: u64, v: usize) -> Vec<u8> {
+        [varint(field << 3), varint(v as u64)].concat()
+    }
+
+    /// A protobuf length-delimited field (message, string or packed numbers)
+    fn bytes(field: u64, b: &[u8]) -> Vec<u8> {
+        [varint(field << 3 | 2), varint(b.len() as u64), b.to_vec()].concat()
+    }
+
+    /// An uncompressed ORC file with the columns `n: bigint` and `name: string`, in one stripe without indexes
+    fn create_orc(rows: &[(i64, &str)]) -> Vec<u8> {
+        // integer run length encoding version 1, all values as one run of literals
+        let rle = |values: Vec<u64>| {
+            let mut out = vec![(-(values.len() as i8)) as u8];
+            for v in values {
+                out.extend(varint(v));
+            }
+            out
+        };
+        let numbers = rle(rows
+            .iter()
+            .map(|(n, _)| ((n << 1) ^ (n >> 63)) as u64)
+            .collect());
+        let lengths = rle(rows.iter().map(|(_, s)| s.len() as u64).collect());
+        let names: Vec<u8> = rows.iter().flat_map(|(_, s)| s.bytes()).collect();
+        let stream =
+            |kind, column, len| bytes(1, &[uint(1, kind), uint(2, column), uint(3, len)].concat());
+        // DIRECT encoding for the struct and both columns
+        let direct = bytes(2, &uint(1, 0));
+        let stripe_footer = [
+            stream(1, 1, numbers.len()),
+            stream(1, 2, names.len()),
+            stream(2, 2, lengths.len()),
+            direct.clone(),
+            direct.clone(),
+            direct,
+        ]
+        .concat();
+        let data = [numbers, names, lengths].concat();
+        let stripe = [
+            uint(1, 3),
+            uint(2, 0),
+            uint(3, data.len()),
+            uint(4, stripe_footer.len()),
+            uint(5, rows.len()),
+        ]
+        .concat();
+        // struct, long and string
+        let types = [
+            bytes(
+                4,
+                &[
+                    uint(1, 12),
+                    bytes(2, &[1, 2]),
+                    bytes(3, b"n"),
+                    bytes(3, b"name"),
+                ]
+                .concat(),
+            ),
+            bytes(4, &uint(1, 4)),
+            bytes(4, &uint(1, 7)),
+        ]
+        .concat();
+        let footer = [
+            uint(1, 3),
+            uint(2, 3 + data.len() + stripe_footer.len()),
+            bytes(3, &stripe),
+            types,
+            uint(6, rows.len()),
+        ]
+        .concat();
+        let postscript = [
+            uint(1, footer.len()),
+            uint(2, 0),
+            bytes(4, &[0, 11]),
+            uint(5, 0),
+            bytes(8000, b"ORC"),
+        ]
+        .concat();
+        let postscript_len = postscript.len() as u8;
+        [
+            &b"ORC"[..],
+            &data,
+            &stripe_footer,
+            &footer,
+            &postscript,
+            &[postscript_len],
+        ]
+        .concat()
+    }
+
+    #[tokio::test]
+    async fn rows() -> Result<()> {
+        if std::process::Command::new("orc-contents").output().is_err() {
+            eprintln!("orc-contents not installed, skipping test");
+            return Ok(());
+        }
+        let orc = create_orc(&[(1, "hello"), (-2, "world")]);
+        let (a, d) = simple_adapt_info(&PathBuf::from("data.orc"), Box::pin(Cursor::new(orc)));
+        let buf = adapted_to_vec(loop_adapt(&OrcAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:row 1: n=1, name=hello\nPREFIX:row 2: n=-2, name=world\n"
+        );
+        Ok(())
+    }
+}
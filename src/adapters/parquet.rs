@@ -0,0 +1,270 @@
+use super::custom::map_exe_error;
+use super::sevenzip::archive_on_disk;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::Value;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["parquet", "parq"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "parquet".to_owned(),
+        version: 1,
+        description: "Reads Apache Parquet files with `duckdb` and outputs each row as `row N: column=value, ...`.\nThe number of rows is limited by --rga-max-rows, the columns can be selected with --rga-parquet-columns"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ParquetAdapter;
+
+impl ParquetAdapter {
+    pub fn new() -> ParquetAdapter {
+        ParquetAdapter
+    }
+}
+
+impl GetMetadata for ParquetAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have the duckdb command line tool installed.";
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The query for the first `limit` rows of the file
+fn query(path: &Path, columns: &[String], limit: usize) -> String {
+    let columns = if columns.is_empty() {
+        "*".to_string()
+    } else {
+        columns
+            .iter()
+            .map(|c| quote_identifier(c))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let path = path.to_string_lossy().replace('\'', "''");
+    format!("SELECT {columns} FROM read_parquet('{path}') LIMIT {limit};")
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.replace('\n', " "),
+        // lists and structs
+        v => v.to_string(),
+    }
+}
+
+/// The columns of a row in the order of the file, which a `serde_json::Map` would sort
+pub struct Row(pub Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Row, D::Error> {
+        struct RowVisitor;
+        impl<'de> Visitor<'de> for RowVisitor {
+            type Value = Row;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a row object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> std::result::Result<Row, A::Error> {
+                let mut columns = Vec::new();
+                while let Some(column) = map.next_entry()? {
+                    columns.push(column);
+                }
+                Ok(Row(columns))
+            }
+        }
+        deserializer.deserialize_map(RowVisitor)
+    }
+}
+
+impl Row {
+    /// `column=value, ...` without null values
+    pub fn text(&self) -> String {
+        self.0
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| format!("{k}={}", format_value(v)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A row as output in JSON lines format (by `duckdb`, `orc-contents`, ...)
+fn format_row(line: &str) -> Result<String> {
+    let row: Row = serde_json::from_str(line).context("invalid json row")?;
+    Ok(row.text())
+}
+
+/// Writes the rows of data files as `row N: column=value, ...` lines, up to the configured maximum
+pub struct RowWriter {
+    line_prefix: String,
+    max_rows: usize,
+    count: usize,
+}
+
+impl RowWriter {
+    pub fn new(line_prefix: String, config: &RgaConfig) -> RowWriter {
+        RowWriter {
+            line_prefix,
+            max_rows: config.max_rows.0,
+            count: 0,
+        }
+    }
+
+    /// Write the next row, returns false if the maximum number of rows was reached
+    pub async fn write(
+        &mut self,
+        row: &str,
+        oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<bool> {
+        let p = &self.line_prefix;
+        if self.count == self.max_rows {
+            let max_rows = self.max_rows;
+            oup.write_all(
+                format!("{p}[rga: only the first {max_rows} rows are output]\n").as_bytes(),
+            )
+            .await?;
+            return Ok(false);
+        }
+        self.count += 1;
+        oup.write_all(format!("{p}row {}: {row}\n", self.count).as_bytes())
+            .await?;
+        Ok(true)
+    }
+}
+
+/// Run a command that outputs one JSON object per row and write the rows.
+///
+/// Stops reading the output once the maximum number of rows is reached
+pub async fn write_json_rows(
+    mut cmd: Command,
+    stdin: Option<String>,
+    exe_name: &str,
+    help: &str,
+    mut rows: RowWriter,
+    mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    cmd.stdin(if stdin.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+    debug!("executing {:?}", cmd);
+    let mut child = cmd.spawn().map_err(|e| map_exe_error(e, exe_name, help))?;
+    if let Some(input) = stdin {
+        let mut stdin = child.stdin.take().expect("is piped");
+        stdin.write_all(input.as_bytes()).await?;
+    }
+    let mut lines = BufReader::new(child.stdout.take().expect("is piped")).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !rows.write(&format_row(&line)?, &mut oup).await? {
+            // the rest of the output isn't needed
+            child.kill().await?;
+            return Ok(());
+        }
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "{exe_name} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for ParquetAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let (path, _tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        // one more row to know whether the limit was reached
+        let script = format!(
+            ".mode jsonlines\n{}\n",
+            query(
+                &path,
+                &config.parquet_columns,
+                config.max_rows.0.saturating_add(1)
+            )
+        );
+        let mut cmd = Command::new("duckdb");
+        cmd.args(["-batch", "-bail"]);
+        let rows = RowWriter::new(line_prefix, &config);
+        write_json_rows(cmd, Some(script), "duckdb", HELP, rows, oup).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn queries() {
+        assert_eq!(
+            query(Path::new("/data/it's.parquet"), &[], 11),
+            "SELECT * FROM read_parquet('/data/it''s.parquet') LIMIT 11;"
+        );
+        assert_eq!(
+            query(
+                Path::new("a.parquet"),
+                &["title".to_string(), "the \"tag\"".to_string()],
+                1
+            ),
+            "SELECT \"title\", \"the \"\"tag\"\"\" FROM read_parquet('a.parquet') LIMIT 1;"
+        );
+    }
+
+    #[test]
+    fn rows() -> Result<()> {
+        assert_eq!(
+            format_row(
+                r#"{"id":10432,"name":"Alice\nSmith","tags":["a","b"],"deleted":null,"score":1.5}"#
+            )?,
+            r#"id=10432, name=Alice Smith, tags=["a","b"], score=1.5"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,706 @@
+use super::binary::{Endian, u16_be, u32_be};
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["pcap", "pcapng"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pcap".to_owned(),
+        version: 1,
+        description: "Reads network captures (.pcap, .pcapng), reassembles TCP streams and outputs the printable text of their payloads and of UDP datagrams, prefixed with the connection (`tcp 3: 10.0.0.1:51234 > 10.0.0.2:80: `). Fragmented IP packets are skipped"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PcapAdapter;
+
+impl PcapAdapter {
+    pub fn new() -> PcapAdapter {
+        PcapAdapter
+    }
+}
+
+impl GetMetadata for PcapAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// block type of the section header block, which starts every pcapng file
+const PCAPNG_SECTION_HEADER: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+/// larger packets or blocks mean the file is broken
+const MAX_BLOCK_SIZE: usize = 256 * 1024 * 1024;
+
+// link types, see https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u16 = 0;
+const LINKTYPE_ETHERNET: u16 = 1;
+const LINKTYPE_RAW_OPENBSD: u16 = 12;
+const LINKTYPE_RAW: u16 = 101;
+const LINKTYPE_LOOP: u16 = 108;
+const LINKTYPE_LINUX_SLL: u16 = 113;
+const LINKTYPE_IPV4: u16 = 228;
+const LINKTYPE_IPV6: u16 = 229;
+const LINKTYPE_LINUX_SLL2: u16 = 276;
+
+const PROTO_TCP: u8 = 6;
+const PROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+/// minimum length of the printable runs of a payload that are output
+const MIN_TEXT_LEN: usize = 4;
+/// out-of-order data buffered per direction before skipping the missing segment
+const MAX_PENDING: usize = 16 * 1024 * 1024;
+
+/// Reads the packets of a pcap or pcapng file
+struct PacketReader {
+    inp: ReadBox,
+    endian: Endian,
+    /// link type of the whole file for pcap
+    linktype: u16,
+    /// link types of the interfaces of the current section for pcapng
+    interfaces: Option<Vec<u16>>,
+    /// the first block type of a pcapng file, which was read to detect the format
+    first_block: Option<[u8; 4]>,
+}
+
+impl PacketReader {
+    async fn open(mut inp: ReadBox) -> Result<PacketReader> {
+        let mut magic = [0u8; 4];
+        inp.read_exact(&mut magic).await?;
+        if magic == PCAPNG_SECTION_HEADER {
+            return Ok(PacketReader {
+                inp,
+                endian: Endian::Little,
+                linktype: 0,
+                interfaces: Some(Vec::new()),
+                first_block: Some(magic),
+            });
+        }
+        let endian = match magic {
+            // microsecond and nanosecond timestamps
+            [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => Endian::Big,
+            [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => Endian::Little,
+            _ => anyhow::bail!("not a pcap or pcapng file"),
+        };
+        let mut header = [0u8; 20];
+        inp.read_exact(&mut header).await?;
+        Ok(PacketReader {
+            inp,
+            endian,
+            // the upper bits of the link type may contain the FCS length
+            linktype: endian.u32_at(&header, 16).context("invalid pcap header")? as u16,
+            interfaces: None,
+            first_block: None,
+        })
+    }
+
+    /// Fills the buffer, returning false if the file ends before (captures are often truncated)
+    async fn read(&mut self, buf: &mut [u8]) -> Result<bool> {
+        match self.inp.read_exact(buf).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_vec(&mut self, len: usize) -> Result<Option<Vec<u8>>> {
+        if len > MAX_BLOCK_SIZE {
+            anyhow::bail!("invalid record length {len}");
+        }
+        let mut buf = vec![0u8; len];
+        Ok(self.read(&mut buf).await?.then_some(buf))
+    }
+
+    /// The next packet with its link type
+    async fn next(&mut self) -> Result<Option<(u16, Vec<u8>)>> {
+        if self.interfaces.is_some() {
+            return self.next_block().await;
+        }
+        let mut header = [0u8; 16];
+        if !self.read(&mut header).await? {
+            return Ok(None);
+        }
+        let len = self
+            .endian
+            .u32_at(&header, 8)
+            .context("invalid packet header")? as usize;
+        Ok(self.read_vec(len).await?.map(|data| (self.linktype, data)))
+    }
+
+    async fn next_block(&mut self) -> Result<Option<(u16, Vec<u8>)>> {
+        loop {
+            let mut header = [0u8; 8];
+            if let Some(block_type) = self.first_block.take() {
+                header[..4].copy_from_slice(&block_type);
+                if !self.read(&mut header[4..]).await? {
+                    return Ok(None);
+                }
+            } else if !self.read(&mut header).await? {
+                return Ok(None);
+            }
+            let mut skip = 0;
+            if header[..4] == PCAPNG_SECTION_HEADER {
+                // a new section, with its own byte order and interfaces
+                let mut byte_order = [0u8; 4];
+                if !self.read(&mut byte_order).await? {
+                    return Ok(None);
+                }
+                self.endian = match byte_order {
+                    [0x1a, 0x2b, 0x3c, 0x4d] => Endian::Big,
+                    [0x4d, 0x3c, 0x2b, 0x1a] => Endian::Little,
+                    _ => anyhow::bail!("invalid pcapng byte order magic"),
+                };
+                self.interfaces = Some(Vec::new());
+                skip = 4;
+            }
+            let block_type = self
+                .endian
+                .u32_at(&header, 0)
+                .context("invalid block header")?;
+            let block_len = self
+                .endian
+                .u32_at(&header, 4)
+                .context("invalid block header")? as usize;
+            if block_len < 12 + skip {
+                anyhow::bail!("invalid pcapng block length {block_len}");
+            }
+            // the body is followed by the block length again
+            let Some(body) = self.read_vec(block_len - 8 - skip).await? else {
+                return Ok(None);
+            };
+            let body = &body[..body.len() - 4];
+            if skip > 0 {
+                continue;
+            }
+            if block_type == PCAPNG_INTERFACE_DESCRIPTION {
+                let linktype = self.endian.u16_at(body, 0);
+                self.interfaces.as_mut().unwrap().extend(linktype);
+                continue;
+            }
+            let Some((interface, data)) = packet_block(self.endian, block_type, body) else {
+                continue;
+            };
+            let Some(&linktype) = self.interfaces.as_ref().unwrap().get(interface) else {
+                anyhow::bail!("packet of unknown interface {interface}");
+            };
+            return Ok(Some((linktype, data.to_vec())));
+        }
+    }
+}
+
+/// The interface and data of a pcapng packet block, None for other blocks
+fn packet_block(endian: Endian, block_type: u32, body: &[u8]) -> Option<(usize, &[u8])> {
+    let (interface, len, data) = match block_type {
+        PCAPNG_ENHANCED_PACKET => (
+            endian.u32_at(body, 0)? as usize,
+            endian.u32_at(body, 12)?,
+            body.get(20..)?,
+        ),
+        PCAPNG_PACKET => (
+            endian.u16_at(body, 0)? as usize,
+            endian.u32_at(body, 12)?,
+            body.get(20..)?,
+        ),
+        PCAPNG_SIMPLE_PACKET => (0, endian.u32_at(body, 0)?, body.get(4..)?),
+        _ => return None,
+    };
+    Some((interface, truncate(data, len as usize)))
+}
+
+/// The packet data of a pcapng block without the padding
+fn truncate(data: &[u8], len: usize) -> &[u8] {
+    &data[..len.min(data.len())]
+}
+
+/// The IP packet within a link layer frame
+fn ip_packet(linktype: u16, frame: &[u8]) -> Option<&[u8]> {
+    let packet = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut pos = 12;
+            let mut ethertype = u16_be(frame, pos)?;
+            // VLAN tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                pos += 4;
+                ethertype = u16_be(frame, pos)?;
+            }
+            frame.get(pos + 2..)?
+        }
+        // the address family in the byte order of the capturing machine
+        LINKTYPE_NULL | LINKTYPE_LOOP => frame.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_RAW_OPENBSD | LINKTYPE_IPV4 | LINKTYPE_IPV6 => frame,
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        _ => return None,
+    };
+    Some(packet)
+}
+
+/// A TCP segment or UDP datagram
+struct Segment<'a> {
+    protocol: u8,
+    src: SocketAddr,
+    dst: SocketAddr,
+    /// the transport header, followed by the payload
+    data: &'a [u8],
+}
+
+fn parse_ip(packet: &[u8]) -> Option<Segment<'_>> {
+    let (mut protocol, src, dst, mut data): (u8, IpAddr, IpAddr, &[u8]) = match packet.first()? >> 4
+    {
+        4 => {
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+            // 0 with TCP segmentation offload
+            let total_len = match u16_be(packet, 2)? as usize {
+                0 => packet.len(),
+                len => len.min(packet.len()),
+            };
+            // only the first fragment has the transport header, reassembling IP fragments is not supported
+            if u16_be(packet, 6)? & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            (
+                packet[9],
+                Ipv4Addr::from(src).into(),
+                Ipv4Addr::from(dst).into(),
+                packet.get(header_len..total_len)?,
+            )
+        }
+        6 => {
+            let end = match u16_be(packet, 4)? as usize {
+                // jumbograms
+                0 => packet.len(),
+                len => (40 + len).min(packet.len()),
+            };
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            (
+                packet[6],
+                Ipv6Addr::from(src).into(),
+                Ipv6Addr::from(dst).into(),
+                packet.get(40..end)?,
+            )
+        }
+        _ => return None,
+    };
+    // IPv6 extension headers (and IPsec authentication headers for both)
+    loop {
+        let len = match protocol {
+            0 | 43 | 60 => (*data.get(1)? as usize + 1) * 8,
+            51 => (*data.get(1)? as usize + 2) * 4,
+            // fragments
+            44 => return None,
+            _ => break,
+        };
+        protocol = *data.first()?;
+        data = data.get(len..)?;
+    }
+    Some(Segment {
+        protocol,
+        src: SocketAddr::new(src, u16_be(data, 0)?),
+        dst: SocketAddr::new(dst, u16_be(data, 2)?),
+        data,
+    })
+}
+
+/// Appends a line for each printable run of at least `MIN_TEXT_LEN` characters of the data
+fn write_text(out: &mut String, line_prefix: &str, prefix: &str, data: &[u8]) {
+    let text = String::from_utf8_lossy(data);
+    for run in
+        text.split(|c: char| (c.is_control() && c != '\t') || c == char::REPLACEMENT_CHARACTER)
+    {
+        if run.trim().chars().count() >= MIN_TEXT_LEN {
+            writeln!(out, "{line_prefix}{prefix}{run}").unwrap();
+        }
+    }
+}
+
+/// One direction of a TCP connection
+#[derive(Default)]
+struct Direction {
+    /// sequence number of the first data byte
+    base: Option<u32>,
+    /// offset of the next data byte that was not received yet, relative to `base`
+    next: u64,
+    /// out of order segments by their offset
+    pending: BTreeMap<u64, Vec<u8>>,
+    pending_len: usize,
+    finished: bool,
+}
+
+impl Direction {
+    /// Adds a segment, returning the data that is now in order
+    fn receive(&mut self, seq: u32, payload: &[u8]) -> Vec<u8> {
+        if payload.is_empty() {
+            return Vec::new();
+        }
+        let base = *self.base.get_or_insert(seq);
+        // offsets are relative to the start of the stream, so streams above 2 GiB are not reassembled correctly
+        let offset = seq.wrapping_sub(base);
+        if offset >= 1 << 31 || (offset as u64) + (payload.len() as u64) <= self.next {
+            // retransmission of data that was received already
+            return Vec::new();
+        }
+        self.pending_len += payload.len();
+        let old = self.pending.insert(offset as u64, payload.to_vec());
+        self.pending_len -= old.map_or(0, |o| o.len());
+        let mut data = self.take_in_order();
+        if self.pending_len > MAX_PENDING {
+            // a segment is missing from the capture
+            data.extend(self.take_all());
+        }
+        data
+    }
+
+    fn take_in_order(&mut self) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() > self.next {
+                break;
+            }
+            let start = (self.next - *entry.key()) as usize;
+            let segment = entry.remove();
+            self.pending_len -= segment.len();
+            if start < segment.len() {
+                data.extend_from_slice(&segment[start..]);
+                self.next += (segment.len() - start) as u64;
+            }
+        }
+        data
+    }
+
+    /// All buffered data, skipping the missing segments
+    fn take_all(&mut self) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(&offset) = self.pending.keys().next() {
+            self.next = self.next.max(offset);
+            data.extend(self.take_in_order());
+        }
+        data
+    }
+}
+
+struct Stream {
+    id: usize,
+    /// the endpoint that sent the first packet, usually the client
+    client: SocketAddr,
+    server: SocketAddr,
+    /// from the client and from the server
+    directions: [Direction; 2],
+    /// data received in order since the other direction last sent data
+    run: Vec<u8>,
+    run_direction: usize,
+}
+
+impl Stream {
+    fn add(&mut self, direction: usize, data: Vec<u8>, out: &mut String, line_prefix: &str) {
+        if data.is_empty() {
+            return;
+        }
+        if direction != self.run_direction {
+            self.flush(out, line_prefix);
+            self.run_direction = direction;
+        }
+        self.run.extend(data);
+    }
+
+    /// Outputs the data of the current direction, so text split over several segments stays on one line
+    fn flush(&mut self, out: &mut String, line_prefix: &str) {
+        let (src, dst) = if self.run_direction == 0 {
+            (self.client, self.server)
+        } else {
+            (self.server, self.client)
+        };
+        let prefix = format!("tcp {}: {src} > {dst}: ", self.id);
+        write_text(out, line_prefix, &prefix, &self.run);
+        self.run.clear();
+    }
+
+    fn close(mut self, out: &mut String, line_prefix: &str) {
+        for direction in 0..2 {
+            let data = self.directions[direction].take_all();
+            self.add(direction, data, out, line_prefix);
+        }
+        self.flush(out, line_prefix);
+    }
+}
+
+/// Reassembles the TCP streams of the packets, writing their text to `out`
+struct Reassembler {
+    line_prefix: String,
+    /// by the endpoints in sorted order
+    streams: HashMap<(SocketAddr, SocketAddr), Stream>,
+    next_id: usize,
+    out: String,
+}
+
+impl Reassembler {
+    fn new(line_prefix: String) -> Reassembler {
+        Reassembler {
+            line_prefix,
+            streams: HashMap::new(),
+            next_id: 0,
+            out: String::new(),
+        }
+    }
+
+    fn packet(&mut self, linktype: u16, frame: &[u8]) {
+        let Some(segment) = ip_packet(linktype, frame).and_then(parse_ip) else {
+            return;
+        };
+        match segment.protocol {
+            PROTO_TCP => self.tcp(segment),
+            PROTO_UDP => {
+                let len = u16_be(segment.data, 4).unwrap_or(0) as usize;
+                if let Some(payload) = segment.data.get(8..len.min(segment.data.len())) {
+                    let prefix = format!("udp: {} > {}: ", segment.src, segment.dst);
+                    write_text(&mut self.out, &self.line_prefix, &prefix, payload);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn tcp(&mut self, segment: Segment) {
+        let (Some(seq), Some(&offset), Some(&flags)) = (
+            u32_be(segment.data, 4),
+            segment.data.get(12),
+            segment.data.get(13),
+        ) else {
+            return;
+        };
+        let payload = segment
+            .data
+            .get((offset >> 4) as usize * 4..)
+            .unwrap_or_default();
+        let key = if segment.src < segment.dst {
+            (segment.src, segment.dst)
+        } else {
+            (segment.dst, segment.src)
+        };
+        if !self.streams.contains_key(&key) {
+            // e.g. the ACK of the FIN of a closed connection
+            if payload.is_empty() && flags & TCP_SYN == 0 {
+                return;
+            }
+            let stream = Stream {
+                id: self.next_id,
+                client: segment.src,
+                server: segment.dst,
+                directions: Default::default(),
+                run: Vec::new(),
+                run_direction: 0,
+            };
+            self.next_id += 1;
+            self.streams.insert(key, stream);
+        }
+        let stream = self.streams.get_mut(&key).unwrap();
+        let direction = usize::from(segment.src != stream.client);
+        let dir = &mut stream.directions[direction];
+        if flags & TCP_SYN != 0 {
+            dir.base = Some(seq.wrapping_add(1));
+            return;
+        }
+        let data = dir.receive(seq, payload);
+        if flags & TCP_FIN != 0 {
+            dir.finished = true;
+        }
+        stream.add(direction, data, &mut self.out, &self.line_prefix);
+        let finished = stream.directions.iter().all(|d| d.finished);
+        if flags & TCP_RST != 0 || finished {
+            let stream = self.streams.remove(&key).unwrap();
+            stream.close(&mut self.out, &self.line_prefix);
+        }
+    }
+
+    /// Outputs the connections that were not closed within the capture
+    fn finish(&mut self) {
+        let mut streams: Vec<Stream> = self.streams.drain().map(|(_, s)| s).collect();
+        streams.sort_by_key(|s| s.id);
+        for stream in streams {
+            stream.close(&mut self.out, &self.line_prefix);
+        }
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for PcapAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let mut reader = PacketReader::open(inp).await?;
+        let mut reassembler = Reassembler::new(line_prefix);
+        while let Some((linktype, frame)) = reader.next().await? {
+            reassembler.packet(linktype, &frame);
+            if !reassembler.out.is_empty() {
+                oup.write_all(std::mem::take(&mut reassembler.out).as_bytes())
+                    .await?;
+            }
+        }
+        reassembler.finish();
+        oup.write_all(reassembler.out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    const CLIENT: [u8; 4] = [10, 0, 0, 1];
+    const SERVER: [u8; 4] = [10, 0, 0, 2];
+
+    /// An Ethernet frame with an IPv4 packet
+    fn frame(protocol: u8, src: [u8; 4], dst: [u8; 4], transport: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        frame.extend([0x08, 0x00, 0x45, 0x00]);
+        frame.extend((20 + transport.len() as u16).to_be_bytes());
+        frame.extend([0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        frame.extend(src);
+        frame.extend(dst);
+        frame.extend(transport);
+        frame
+    }
+
+    fn tcp(
+        src: [u8; 4],
+        dst: [u8; 4],
+        ports: (u16, u16),
+        seq: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend(ports.0.to_be_bytes());
+        segment.extend(ports.1.to_be_bytes());
+        segment.extend(seq.to_be_bytes());
+        segment.extend([0, 0, 0, 0, 0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        segment.extend(payload);
+        frame(PROTO_TCP, src, dst, &segment)
+    }
+
+    /// A connection with the request split into two segments which arrive out of order, one of them twice
+    fn frames() -> Vec<Vec<u8>> {
+        let client =
+            |seq, flags, payload: &[u8]| tcp(CLIENT, SERVER, (51234, 80), seq, flags, payload);
+        let server =
+            |seq, flags, payload: &[u8]| tcp(SERVER, CLIENT, (80, 51234), seq, flags, payload);
+        let mut udp = Vec::new();
+        udp.extend(5140u16.to_be_bytes());
+        udp.extend(514u16.to_be_bytes());
+        let payload = b"<14>login ok\0\x01\x02xyz\n";
+        udp.extend((8 + payload.len() as u16).to_be_bytes());
+        udp.extend([0, 0]);
+        udp.extend(payload);
+        vec![
+            client(999, TCP_SYN, b""),
+            server(4999, TCP_SYN | 0x10, b""),
+            client(1016, 0x18, b"/1.1\r\nHost: example.com\r\n\r\n"),
+            client(1000, 0x18, b"GET /secret HTTP"),
+            client(1000, 0x18, b"GET /secret HTTP"),
+            frame(PROTO_UDP, CLIENT, SERVER, &udp),
+            server(5000, 0x18, b"HTTP/1.1 200 OK\r\n\r\ntoken=abc123"),
+            client(1043, TCP_FIN, b""),
+            server(5030, TCP_FIN, b""),
+        ]
+    }
+
+    const EXPECTED: &str = "PREFIX:udp: 10.0.0.1:5140 > 10.0.0.2:514: <14>login ok
+PREFIX:tcp 0: 10.0.0.1:51234 > 10.0.0.2:80: GET /secret HTTP/1.1
+PREFIX:tcp 0: 10.0.0.1:51234 > 10.0.0.2:80: Host: example.com
+PREFIX:tcp 0: 10.0.0.2:80 > 10.0.0.1:51234: HTTP/1.1 200 OK
+PREFIX:tcp 0: 10.0.0.2:80 > 10.0.0.1:51234: token=abc123
+";
+
+    #[tokio::test]
+    async fn pcap() -> Result<()> {
+        let mut file = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        file.extend([0; 8]);
+        file.extend(65535u32.to_le_bytes());
+        file.extend((LINKTYPE_ETHERNET as u32).to_le_bytes());
+        for frame in frames() {
+            file.extend([0; 8]);
+            file.extend((frame.len() as u32).to_le_bytes());
+            file.extend((frame.len() as u32).to_le_bytes());
+            file.extend(frame);
+        }
+        let (a, d) = simple_adapt_info(&PathBuf::from("capture.pcap"), Box::pin(Cursor::new(file)));
+        let buf = adapted_to_vec(loop_adapt(&PcapAdapter::new(), d, a).await?).await?;
+        assert_eq!(String::from_utf8(buf)?, EXPECTED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pcapng() -> Result<()> {
+        let block = |block_type: u32, body: &[u8]| {
+            let padded = body.len().div_ceil(4) * 4;
+            let len = (12 + padded) as u32;
+            let mut block = Vec::new();
+            block.extend(block_type.to_be_bytes());
+            block.extend(len.to_be_bytes());
+            block.extend(body);
+            block.resize(8 + padded, 0);
+            block.extend(len.to_be_bytes());
+            block
+        };
+        let mut file = block(
+            0x0a0d0d0a,
+            &[
+                0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ],
+        );
+        let mut interface = LINKTYPE_ETHERNET.to_be_bytes().to_vec();
+        interface.extend([0, 0]);
+        interface.extend(65535u32.to_be_bytes());
+        file.extend(block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+        // an unknown block
+        file.extend(block(0x0bad, b"skip"));
+        for frame in frames() {
+            let mut body = vec![0u8; 12];
+            body.extend((frame.len() as u32).to_be_bytes());
+            body.extend((frame.len() as u32).to_be_bytes());
+            body.extend(frame);
+            file.extend(block(PCAPNG_ENHANCED_PACKET, &body));
+        }
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("capture.pcapng"),
+            Box::pin(Cursor::new(file)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&PcapAdapter::new(), d, a).await?).await?;
+        assert_eq!(String::from_utf8(buf)?, EXPECTED);
+        Ok(())
+    }
+}
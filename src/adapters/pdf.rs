@@ -0,0 +1,518 @@
+//! Reads the parts of PDF files that pdftotext doesn't output (form fields, attachments, outline) with qpdf
+use super::custom::{map_exe_error, spawn_output};
+use super::xml::{XmlEvent, XmlReader, local_name};
+use super::*;
+use anyhow::{Context, Result};
+use async_stream::stream;
+use log::*;
+use serde_json::Value;
+use std::collections::HashSet;
+use tempfile::TempPath;
+use tokio::process::Command;
+
+const HELP: &str = "Please make sure you have qpdf installed.";
+
+/// A filled-in field of a form
+#[derive(Debug, PartialEq)]
+pub struct FormField {
+    /// The page of the field (from 1). XFA fields are not on a page.
+    pub page: Option<usize>,
+    pub name: String,
+    pub value: String,
+}
+
+/// A PDF written to a temporary file (qpdf can't read from stdin) with the password to decrypt it
+struct QpdfInput {
+    path: TempPath,
+    password: Option<String>,
+}
+
+impl QpdfInput {
+    async fn new(pdf: &[u8], password: Option<&str>) -> Result<QpdfInput> {
+        let path = tempfile::Builder::new()
+            .suffix(".pdf")
+            .tempfile()?
+            .into_temp_path();
+        tokio::fs::write(&path, pdf).await?;
+        Ok(QpdfInput {
+            path,
+            password: password.map(str::to_string),
+        })
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        // not logging the whole command, which contains the password
+        debug!("executing qpdf {:?} {}", args, self.path.display());
+        let mut cmd = Command::new("qpdf");
+        if let Some(password) = &self.password {
+            cmd.arg(format!("--password={password}"));
+        }
+        cmd.args(args).arg(&self.path);
+        cmd
+    }
+
+    /// Run qpdf. Exit code 3 means success with warnings (e.g. for slightly damaged files).
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>> {
+        let output = self
+            .command(args)
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, "qpdf", HELP))?;
+        if !matches!(output.status.code(), Some(0 | 3)) {
+            anyhow::bail!(
+                "qpdf failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// The text of a PDF object in qpdf's JSON v2. Strings are prefixed with `u:` (text) or `b:` (binary, as hex), names with `/`.
+fn object_text(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(s) => {
+            if let Some(text) = s.strip_prefix("u:") {
+                Some(text.to_string())
+            } else if let Some(hex) = s.strip_prefix("b:") {
+                let bytes: Vec<u8> = (0..hex.len() / 2)
+                    .filter_map(|i| u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok())
+                    .collect();
+                Some(String::from_utf8_lossy(&bytes).into_owned())
+            } else {
+                Some(s.strip_prefix('/').unwrap_or(s).to_string())
+            }
+        }
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        // the selected options of a list box
+        Value::Array(items) => Some(
+            items
+                .iter()
+                .filter_map(object_text)
+                .collect::<Vec<_>>()
+                .join(", "),
+        ),
+        _ => None,
+    }?;
+    single_line(&text)
+}
+
+/// Join the lines of multiline text fields, None if the field is empty
+fn single_line(text: &str) -> Option<String> {
+    let text = text
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// The filled-in fields in the `acroform` part of qpdf's JSON. qpdf lists fields with several widgets (e.g. radio buttons) once per widget, they are output once per page.
+fn acroform_fields(json: &Value) -> Vec<FormField> {
+    let mut seen = HashSet::new();
+    json["acroform"]["fields"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|field| !field["ispushbutton"].as_bool().unwrap_or(false))
+        .filter_map(|field| {
+            Some(FormField {
+                page: field["pageposfrom1"].as_u64().map(|p| p as usize),
+                name: field["fullname"].as_str()?.to_string(),
+                value: object_text(&field["value"])?,
+            })
+        })
+        .filter(|field| seen.insert((field.page, field.name.clone())))
+        .collect()
+}
+
+/// The reference (`12 0 R`) to the stream with the data of the XFA form in the objects of qpdf's JSON.
+/// The XFA is either a single stream or an array of packet names and streams, the data is in the `datasets` packet.
+fn xfa_datasets(json: &Value) -> Option<String> {
+    let objects = json["qpdf"].get(1)?;
+    let resolve = |value: &Value| match value.as_str() {
+        Some(reference) if reference.ends_with(" R") => {
+            objects[format!("obj:{reference}")]["value"].clone()
+        }
+        _ => value.clone(),
+    };
+    let root = resolve(&objects["trailer"]["value"]["/Root"]);
+    let acroform = resolve(&root["/AcroForm"]);
+    let xfa = &acroform["/XFA"];
+    match resolve(xfa) {
+        Value::Array(packets) => packets
+            .chunks(2)
+            .find(|packet| object_text(&packet[0]).as_deref() == Some("datasets"))
+            .and_then(|packet| packet.get(1)?.as_str().map(str::to_string)),
+        _ => xfa.as_str().map(str::to_string),
+    }
+}
+
+/// The fields in the `data` element of the XFA datasets, named by the path of their element (`form1.address.city`)
+fn xfa_fields(xml: &str) -> Vec<FormField> {
+    let mut fields = Vec::new();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut text = String::new();
+    for event in XmlReader::new(xml) {
+        match event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => {
+                text.clear();
+                if !self_closing {
+                    stack.push(local_name(name));
+                }
+            }
+            XmlEvent::Text(t) => text.push_str(&t),
+            XmlEvent::End { .. } => {
+                if let Some(data) = stack.iter().position(|n| *n == "data")
+                    && stack.len() > data + 1
+                    && let Some(value) = single_line(&text)
+                {
+                    fields.push(FormField {
+                        page: None,
+                        name: stack[data + 1..].join("."),
+                        value,
+                    });
+                }
+                text.clear();
+                stack.pop();
+            }
+        }
+    }
+    fields
+}
+
+/// The filled-in fields of the AcroForm and XFA forms of a PDF, see `--rga-pdf-forms`
+pub async fn form_fields(pdf: &[u8], password: Option<&str>) -> Result<Vec<FormField>> {
+    let input = QpdfInput::new(pdf, password).await?;
+    let json: Value =
+        serde_json::from_slice(&input.run(&["--json=2", "--json-key=acroform"]).await?)
+            .context("invalid qpdf json")?;
+    if !json["acroform"]["hasacroform"].as_bool().unwrap_or(false) {
+        return Ok(Vec::new());
+    }
+    let mut fields = acroform_fields(&json);
+    let objects: Value = serde_json::from_slice(
+        &input
+            .run(&["--json=2", "--json-key=qpdf", "--json-stream-data=none"])
+            .await?,
+    )
+    .context("invalid qpdf json")?;
+    if let Some(reference) = xfa_datasets(&objects) {
+        let id = reference.trim_end_matches(" R").replace(' ', ",");
+        let show_object = format!("--show-object={id}");
+        let xml = input.run(&[&show_object, "--filtered-stream-data"]).await?;
+        fields.extend(xfa_fields(&String::from_utf8_lossy(&xml)));
+    }
+    Ok(fields)
+}
+
+/// An entry of the outline (bookmarks) of a PDF
+#[derive(Debug, PartialEq)]
+pub struct OutlineItem {
+    /// The page the entry points to (from 1)
+    pub page: usize,
+    pub title: String,
+}
+
+/// The entries of the `outlines` part of qpdf's JSON in document order, skipping entries that don't point to a page
+fn outline_items(outlines: &Value, items: &mut Vec<OutlineItem>) {
+    for item in outlines.as_array().into_iter().flatten() {
+        if let Some(page) = item["destpageposfrom1"].as_u64()
+            && let Some(title) = item["title"].as_str().and_then(single_line)
+        {
+            items.push(OutlineItem {
+                page: page as usize,
+                title,
+            });
+        }
+        outline_items(&item["kids"], items);
+    }
+}
+
+/// The outline of a PDF, see `--rga-pdf-outline`
+pub async fn outline(pdf: &[u8], password: Option<&str>) -> Result<Vec<OutlineItem>> {
+    let input = QpdfInput::new(pdf, password).await?;
+    let json: Value =
+        serde_json::from_slice(&input.run(&["--json=2", "--json-key=outlines"]).await?)
+            .context("invalid qpdf json")?;
+    let mut items = Vec::new();
+    outline_items(&json["outlines"], &mut items);
+    Ok(items)
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Split the output of pdftotext into pages of lines with the title of the section they are in.
+///
+/// Outline entries only point to pages, so a section starts at the first line of its page that contains its title,
+/// or at the top of the page if the title is not found.
+pub fn sectioned_pages<'a>(
+    text: &'a str,
+    outline: &'a [OutlineItem],
+) -> Vec<Vec<(Option<&'a str>, &'a str)>> {
+    let mut pages: Vec<&str> = text.split('\x0c').collect();
+    // every page ends with a form feed
+    if pages.len() > 1 && pages.last().is_some_and(|p| p.is_empty()) {
+        pages.pop();
+    }
+    let mut items: Vec<&OutlineItem> = outline.iter().collect();
+    items.sort_by_key(|item| item.page);
+    let mut section = None;
+    let mut out = Vec::with_capacity(pages.len());
+    for (i, page) in pages.into_iter().enumerate() {
+        let lines: Vec<&str> = page.lines().collect();
+        // (line, title) of the sections starting on this page
+        let mut starts = Vec::new();
+        let mut from = 0;
+        for item in items.iter().filter(|item| item.page == i + 1) {
+            let title = normalize(&item.title);
+            if let Some(line) = lines[from..]
+                .iter()
+                .position(|line| normalize(line).contains(&title))
+            {
+                from += line;
+            }
+            starts.push((from, item.title.as_str()));
+        }
+        let mut starts = starts.into_iter().peekable();
+        let mut page_lines = Vec::with_capacity(lines.len());
+        for (n, line) in lines.into_iter().enumerate() {
+            while let Some((_, title)) = starts.next_if(|(start, _)| *start <= n) {
+                section = Some(title);
+            }
+            page_lines.push((section, line));
+        }
+        // sections on empty pages
+        if let Some((_, title)) = starts.last() {
+            section = Some(title);
+        }
+        out.push(page_lines);
+    }
+    out
+}
+
+/// The names of the embedded files in the output of `qpdf --list-attachments` (`name -> 12,0`, details are indented)
+fn parse_attachments(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .filter(|line| !line.starts_with(char::is_whitespace))
+        .filter_map(|line| line.rsplit_once(" -> "))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+/// The embedded files of a PDF (e.g. the XML of ZUGFeRD invoices) as files to recurse into, see `--rga-pdf-attachments`
+pub async fn attachments(
+    pdf: &[u8],
+    password: Option<&str>,
+    line_prefix: &str,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: &RgaConfig,
+    passwords: &PasswordState,
+) -> Result<AdaptedFilesIterBox> {
+    let input = QpdfInput::new(pdf, password).await?;
+    let listing = input.run(&["--list-attachments"]).await?;
+    let names = parse_attachments(&String::from_utf8_lossy(&listing));
+    let line_prefix = line_prefix.to_string();
+    let config = config.clone();
+    let passwords = passwords.clone();
+    let s = stream! {
+        for name in names {
+            debug!("{line_prefix}attachment {name}");
+            let show_attachment = format!("--show-attachment={name}");
+            let inp = spawn_output(input.command(&[&show_attachment]), "qpdf", HELP)?;
+            yield Ok(AdaptInfo {
+                line_prefix: format!("{line_prefix}{name}: "),
+                filepath_hint: PathBuf::from(name),
+                is_real_file: false,
+                inp,
+                archive_recursion_depth,
+                postprocess,
+                config: config.clone(),
+                passwords: passwords.clone(),
+            });
+        }
+        // the temporary file is deleted after all attachments are extracted
+        drop(input);
+    };
+    Ok(Box::pin(s))
+}
+
+/// Add the fields to the end of their pages in the output of pdftotext (every page ends with a form feed)
+pub fn add_form_fields(text: &[u8], fields: &[FormField]) -> Vec<u8> {
+    let mut pages: Vec<Vec<u8>> = text.split(|b| *b == b'\x0c').map(<[u8]>::to_vec).collect();
+    if pages.len() > 1 && pages.last().is_some_and(|p| p.is_empty()) {
+        pages.pop();
+    }
+    for field in fields {
+        let page = &mut pages[field.page.unwrap_or(1).clamp(1, pages.len()) - 1];
+        if !page.is_empty() && !page.ends_with(b"\n") {
+            page.push(b'\n');
+        }
+        page.extend_from_slice(format!("form: {} = {}\n", field.name, field.value).as_bytes());
+    }
+    let mut out = Vec::with_capacity(text.len());
+    for page in pages {
+        out.extend_from_slice(&page);
+        out.push(b'\x0c');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn field(page: Option<usize>, name: &str, value: &str) -> FormField {
+        FormField {
+            page,
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn acroform() -> Result<()> {
+        let json: Value = serde_json::from_str(
+            r#"{"version": 2, "acroform": {"hasacroform": true, "needappearances": false, "fields": [
+  {"fullname": "applicant.name", "fieldtype": "/Tx", "ispushbutton": false, "pageposfrom1": 1, "value": "u:Jane Doe"},
+  {"fullname": "applicant.notes", "fieldtype": "/Tx", "ispushbutton": false, "pageposfrom1": 1, "value": "u:line one\rline two"},
+  {"fullname": "agree", "fieldtype": "/Btn", "ispushbutton": false, "pageposfrom1": 2, "value": "/Yes"},
+  {"fullname": "agree", "fieldtype": "/Btn", "ispushbutton": false, "pageposfrom1": 2, "value": "/Yes"},
+  {"fullname": "colors", "fieldtype": "/Ch", "ispushbutton": false, "pageposfrom1": 2, "value": ["u:red", "u:blue"]},
+  {"fullname": "empty", "fieldtype": "/Tx", "ispushbutton": false, "pageposfrom1": 2, "value": "u:"},
+  {"fullname": "unset", "fieldtype": "/Tx", "ispushbutton": false, "pageposfrom1": 2, "value": null},
+  {"fullname": "submit", "fieldtype": "/Btn", "ispushbutton": true, "pageposfrom1": 2, "value": null}
+]}}"#,
+        )?;
+        assert_eq!(
+            acroform_fields(&json),
+            [
+                field(Some(1), "applicant.name", "Jane Doe"),
+                field(Some(1), "applicant.notes", "line one line two"),
+                field(Some(2), "agree", "Yes"),
+                field(Some(2), "colors", "red, blue"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn xfa() -> Result<()> {
+        let json: Value = serde_json::from_str(
+            r#"{"version": 2, "qpdf": [{"jsonversion": 2}, {
+  "obj:1 0 R": {"value": {"/Type": "/Catalog", "/AcroForm": "2 0 R"}},
+  "obj:2 0 R": {"value": {"/Fields": [], "/XFA": ["u:template", "5 0 R", "u:datasets", "6 0 R"]}},
+  "obj:5 0 R": {"stream": {"dict": {}}},
+  "obj:6 0 R": {"stream": {"dict": {}}},
+  "trailer": {"value": {"/Root": "1 0 R", "/Size": 7}}
+}]}"#,
+        )?;
+        assert_eq!(xfa_datasets(&json).as_deref(), Some("6 0 R"));
+
+        let xml = r#"<xfa:datasets xmlns:xfa="http://www.xfa.org/schema/xfa-data/1.0/">
+<xfa:data><form1><name>Jane Doe</name><address><city>Springfield</city><zip/></address></form1></xfa:data>
+<dd:dataDescription xmlns:dd="http://ns.adobe.com/data-description/"><form1/></dd:dataDescription>
+</xfa:datasets>"#;
+        assert_eq!(
+            xfa_fields(xml),
+            [
+                field(None, "form1.name", "Jane Doe"),
+                field(None, "form1.address.city", "Springfield"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn attachment_listing() {
+        let listing = "factur-x.xml -> 12,0
+   preferred name: factur-x.xml
+   all data streams:
+     /F -> 12,0
+notes -> v2.txt -> 14,0
+";
+        assert_eq!(
+            parse_attachments(listing),
+            ["factur-x.xml", "notes -> v2.txt"]
+        );
+        assert!(parse_attachments("invoice.pdf has no embedded files\n").is_empty());
+    }
+
+    #[test]
+    fn outline() -> Result<()> {
+        let json: Value = serde_json::from_str(
+            r#"{"version": 2, "outlines": [
+  {"title": "1 Introduction", "destpageposfrom1": 1, "kids": []},
+  {"title": "2 Methods", "destpageposfrom1": 2, "kids": [
+    {"title": "2.1 Setup", "destpageposfrom1": 2, "kids": []},
+    {"title": "External link", "destpageposfrom1": null, "kids": []},
+    {"title": "2.2  Data\r", "destpageposfrom1": 3, "kids": []}
+  ]},
+  {"title": "3 Results", "destpageposfrom1": 3, "kids": []}
+]}"#,
+        )?;
+        let mut items = Vec::new();
+        outline_items(&json["outlines"], &mut items);
+        assert_eq!(
+            items
+                .iter()
+                .map(|i| (i.page, i.title.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (1, "1 Introduction"),
+                (2, "2 Methods"),
+                (2, "2.1 Setup"),
+                (3, "2.2 Data"),
+                (3, "3 Results")
+            ]
+        );
+        let text = "Title page\n\x0cA Report\n2 Methods\ntext\n2.1 Setup\nmore\n\x0cA Report\nstill data\n3   RESULTS\nfound\n\x0c";
+        assert_eq!(
+            sectioned_pages(text, &items),
+            [
+                vec![(Some("1 Introduction"), "Title page")],
+                vec![
+                    (Some("1 Introduction"), "A Report"),
+                    (Some("2 Methods"), "2 Methods"),
+                    (Some("2 Methods"), "text"),
+                    (Some("2.1 Setup"), "2.1 Setup"),
+                    (Some("2.1 Setup"), "more"),
+                ],
+                // the title of 2.2 is not on its page
+                vec![
+                    (Some("2.2 Data"), "A Report"),
+                    (Some("2.2 Data"), "still data"),
+                    (Some("3 Results"), "3   RESULTS"),
+                    (Some("3 Results"), "found"),
+                ],
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pages() {
+        let fields = [
+            field(Some(2), "agree", "Yes"),
+            field(None, "form1.name", "Jane Doe"),
+            field(Some(9), "late", "x"),
+        ];
+        assert_eq!(
+            String::from_utf8_lossy(&add_form_fields(b"first\x0csecond\n\x0c", &fields)),
+            "first\nform: form1.name = Jane Doe\n\x0csecond\nform: agree = Yes\nform: late = x\n\x0c"
+        );
+    }
+}
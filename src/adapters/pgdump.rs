@@ -0,0 +1,484 @@
+use super::leveldb::display;
+use super::lz4::lz4_decoder;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use async_compression::tokio::bufread::{ZlibDecoder, ZstdDecoder};
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    BufWriter,
+};
+use tokio_util::io::StreamReader;
+
+/// `.dump` and `.backup`, which pg_dump files are often named, are too generic,
+/// they can be added with an adapter alias: `{"adapter": "pgdump", "extensions": ["dump"]}`
+static EXTENSIONS: &[&str] = &["pgdump"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pgdump".to_owned(),
+        version: 1,
+        description: "Reads PostgreSQL custom-format dumps (`pg_dump -Fc`). Lists the table of contents like `pg_restore -l` with the SQL definitions of each entry, and outputs the rows of each table as COPY lines prefixed with the table name (`public.users: `). Matches `.pgdump` files, add `.dump` files with an adapter alias (`{\"adapter\": \"pgdump\", \"extensions\": [\"dump\"]}`). Files without the `PGDMP` signature (e.g. plain SQL dumps) are output as they are"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PgDumpAdapter;
+
+impl PgDumpAdapter {
+    pub fn new() -> PgDumpAdapter {
+        PgDumpAdapter
+    }
+}
+
+impl GetMetadata for PgDumpAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAGIC: &[u8] = b"PGDMP";
+/// the archive format byte of custom-format dumps (tar and directory dumps are not single files of this format)
+const FORMAT_CUSTOM: u8 = 1;
+const BLK_DATA: u8 = 1;
+const BLK_BLOBS: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Lz4,
+    Zstd,
+}
+
+/// A sign byte followed by the absolute value with `int_size` bytes in little endian
+async fn read_int(inp: &mut (impl AsyncRead + Unpin), int_size: usize) -> std::io::Result<i64> {
+    let negative = inp.read_u8().await? != 0;
+    let mut value: i64 = 0;
+    for i in 0..int_size {
+        value |= (inp.read_u8().await? as i64) << (8 * i);
+    }
+    Ok(if negative { -value } else { value })
+}
+
+struct TocEntry {
+    dump_id: i64,
+    tag: String,
+    desc: String,
+    defn: String,
+    namespace: Option<String>,
+    owner: Option<String>,
+}
+
+impl TocEntry {
+    fn qualified_name(&self) -> String {
+        match &self.namespace {
+            Some(namespace) if !namespace.is_empty() => format!("{namespace}.{}", self.tag),
+            _ => self.tag.clone(),
+        }
+    }
+}
+
+struct Archive<R> {
+    inp: R,
+    version: (u8, u8),
+    int_size: usize,
+    offset_size: usize,
+    compression: Compression,
+}
+
+impl<R: AsyncRead + Send + Unpin> Archive<R> {
+    /// Reads the header after the magic bytes
+    async fn open(mut inp: R) -> Result<(Archive<R>, Option<String>)> {
+        let major = inp.read_u8().await?;
+        let minor = inp.read_u8().await?;
+        let _revision = inp.read_u8().await?;
+        // the oldest format written since PostgreSQL 8.4, and the newest known one
+        anyhow::ensure!(
+            (1, 10) <= (major, minor) && (major, minor) <= (1, 16),
+            "unsupported pg_dump archive version {major}.{minor}"
+        );
+        let int_size = inp.read_u8().await? as usize;
+        anyhow::ensure!(
+            (1..=8).contains(&int_size),
+            "invalid integer size {int_size}"
+        );
+        let offset_size = inp.read_u8().await? as usize;
+        let format = inp.read_u8().await?;
+        anyhow::ensure!(
+            format == FORMAT_CUSTOM,
+            "unsupported pg_dump archive format {format}"
+        );
+        let mut archive = Archive {
+            inp,
+            version: (major, minor),
+            int_size,
+            offset_size,
+            compression: Compression::None,
+        };
+        archive.compression = if archive.at_least(15) {
+            match archive.inp.read_u8().await? {
+                0 => Compression::None,
+                1 => Compression::Gzip,
+                2 => Compression::Lz4,
+                3 => Compression::Zstd,
+                c => anyhow::bail!("unknown compression {c}"),
+            }
+        } else {
+            // the gzip compression level
+            match archive.int().await? {
+                0 => Compression::None,
+                _ => Compression::Gzip,
+            }
+        };
+        // creation time as seconds, minutes, hours, day, month, year, dst
+        for _ in 0..7 {
+            archive.int().await?;
+        }
+        let database = archive.string().await?;
+        let _server_version = archive.string().await?;
+        let _pg_dump_version = archive.string().await?;
+        Ok((archive, database))
+    }
+
+    fn at_least(&self, minor: u8) -> bool {
+        self.version >= (1, minor)
+    }
+
+    async fn int(&mut self) -> Result<i64> {
+        Ok(read_int(&mut self.inp, self.int_size).await?)
+    }
+
+    async fn string(&mut self) -> Result<Option<String>> {
+        let len = self.int().await?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let mut buf = Vec::new();
+        (&mut self.inp)
+            .take(len as u64)
+            .read_to_end(&mut buf)
+            .await?;
+        anyhow::ensure!(buf.len() as i64 == len, "unexpected end of archive");
+        Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+    }
+
+    async fn toc_entry(&mut self) -> Result<TocEntry> {
+        let dump_id = self.int().await?;
+        let _had_dumper = self.int().await?;
+        let _table_oid = self.string().await?;
+        let _oid = self.string().await?;
+        let tag = self.string().await?.unwrap_or_default();
+        let desc = self.string().await?.unwrap_or_default();
+        if self.at_least(11) {
+            let _section = self.int().await?;
+        }
+        let defn = self.string().await?.unwrap_or_default();
+        let _drop_stmt = self.string().await?;
+        let _copy_stmt = self.string().await?;
+        let namespace = self.string().await?;
+        let _tablespace = self.string().await?;
+        if self.at_least(14) {
+            let _table_access_method = self.string().await?;
+        }
+        if self.at_least(16) {
+            let _relkind = self.int().await?;
+        }
+        let owner = self.string().await?;
+        let _with_oids = self.string().await?;
+        // the ids of the entries this one depends on
+        while self.string().await?.is_some() {}
+        // position of the data: a flag and `offset_size` bytes
+        for _ in 0..=self.offset_size {
+            self.inp.read_u8().await?;
+        }
+        Ok(TocEntry {
+            dump_id,
+            tag,
+            desc,
+            defn,
+            namespace,
+            owner,
+        })
+    }
+
+    /// Writes the lines of a data block, which is split into chunks that are compressed together
+    async fn write_data(
+        &mut self,
+        prefix: &str,
+        format: fn(&[u8]) -> String,
+        oup: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<()> {
+        let int_size = self.int_size;
+        let inp = &mut self.inp;
+        let mut chunks = StreamReader::new(Box::pin(stream! {
+            loop {
+                let len = read_int(&mut *inp, int_size).await?;
+                if len <= 0 {
+                    break;
+                }
+                let mut chunk = Vec::new();
+                (&mut *inp).take(len as u64).read_to_end(&mut chunk).await?;
+                if chunk.len() as i64 != len {
+                    Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?;
+                }
+                yield std::io::Result::Ok(bytes::Bytes::from(chunk));
+            }
+        }));
+        let data: Pin<Box<dyn AsyncRead + Send + '_>> = match self.compression {
+            Compression::None => Box::pin(&mut chunks),
+            Compression::Gzip => Box::pin(ZlibDecoder::new(&mut chunks)),
+            Compression::Lz4 => lz4_decoder(Box::pin(&mut chunks)),
+            Compression::Zstd => Box::pin(ZstdDecoder::new(&mut chunks)),
+        };
+        write_lines(BufReader::new(data), prefix, format, oup).await?;
+        // the decoder may stop before the end of the chunks
+        tokio::io::copy(&mut chunks, &mut tokio::io::sink()).await?;
+        Ok(())
+    }
+}
+
+/// Writes each non-empty line with the prefix, except for the end marker of COPY data
+async fn write_lines(
+    mut inp: impl AsyncBufRead + Unpin,
+    prefix: &str,
+    format: fn(&[u8]) -> String,
+    oup: &mut (impl AsyncWrite + Unpin),
+) -> Result<()> {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if inp.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(());
+        }
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        if text.is_empty() || text == b"\\." {
+            continue;
+        }
+        oup.write_all(format!("{prefix}{}\n", format(text)).as_bytes())
+            .await?;
+    }
+}
+
+fn lossy(b: &[u8]) -> String {
+    String::from_utf8_lossy(b).into_owned()
+}
+
+#[async_trait]
+impl WritingFileAdapter for PgDumpAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp, line_prefix, ..
+        } = ai;
+        let p = &line_prefix;
+        let mut oup = BufWriter::new(oup);
+        let mut inp = BufReader::new(inp);
+        if !inp.fill_buf().await?.starts_with(MAGIC) {
+            write_lines(inp, p, lossy, &mut oup).await?;
+            oup.flush().await?;
+            return Ok(());
+        }
+        inp.consume(MAGIC.len());
+        let (mut archive, database) = Archive::open(inp).await?;
+        if let Some(database) = database {
+            oup.write_all(format!("{p}database: {database}\n").as_bytes())
+                .await?;
+        }
+        let mut names = HashMap::new();
+        for _ in 0..archive.int().await? {
+            let entry = archive.toc_entry().await?;
+            // like `pg_restore -l`
+            let namespace = entry.namespace.as_deref().filter(|n| !n.is_empty());
+            oup.write_all(
+                format!(
+                    "{p}toc: {}; {} {} {} {}\n",
+                    entry.dump_id,
+                    entry.desc,
+                    namespace.unwrap_or("-"),
+                    entry.tag,
+                    entry.owner.as_deref().unwrap_or("-")
+                )
+                .as_bytes(),
+            )
+            .await?;
+            let name = entry.qualified_name();
+            for line in entry.defn.lines().filter(|l| !l.trim().is_empty()) {
+                oup.write_all(format!("{p}{} {name}: {line}\n", entry.desc).as_bytes())
+                    .await?;
+            }
+            names.insert(entry.dump_id, name);
+        }
+        // the data blocks follow the table of contents
+        loop {
+            let block_type = match archive.inp.read_u8().await {
+                Ok(b) => b,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            let id = archive.int().await?;
+            match block_type {
+                BLK_DATA => {
+                    let name = names
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or_else(|| format!("entry {id}"));
+                    archive
+                        .write_data(&format!("{p}{name}: "), lossy, &mut oup)
+                        .await?;
+                }
+                BLK_BLOBS => loop {
+                    let oid = archive.int().await?;
+                    if oid == 0 {
+                        break;
+                    }
+                    archive
+                        .write_data(&format!("{p}large object {oid}: "), display, &mut oup)
+                        .await?;
+                },
+                t => anyhow::bail!("unknown block type {t}"),
+            }
+        }
+        oup.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use async_compression::tokio::bufread::ZlibEncoder;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn int(out: &mut Vec<u8>, value: i64) {
+        out.push((value < 0) as u8);
+        out.extend(value.unsigned_abs().to_le_bytes()[..4].iter());
+    }
+
+    fn string(out: &mut Vec<u8>, s: Option<&str>) {
+        match s {
+            Some(s) => {
+                int(out, s.len() as i64);
+                out.extend(s.as_bytes());
+            }
+            None => int(out, -1),
+        }
+    }
+
+    fn toc_entry(out: &mut Vec<u8>, dump_id: i64, desc: &str, tag: &str, defn: &str) {
+        int(out, dump_id);
+        int(out, 1);
+        string(out, Some("1259"));
+        string(out, Some("16385"));
+        string(out, Some(tag));
+        string(out, Some(desc));
+        int(out, 2);
+        string(out, Some(defn));
+        string(out, Some(""));
+        string(out, Some(""));
+        string(out, Some("public"));
+        string(out, Some(""));
+        string(out, Some("heap"));
+        string(out, Some("postgres"));
+        string(out, Some("false"));
+        string(out, Some("212"));
+        string(out, None);
+        out.push(2);
+        out.extend(0u64.to_le_bytes());
+    }
+
+    /// A dump of version 1.14 with one table, its data compressed with the given gzip level
+    async fn archive(level: i64) -> Result<Vec<u8>> {
+        let mut out = b"PGDMP\x01\x0e\x00\x04\x08\x01".to_vec();
+        int(&mut out, level);
+        for t in [0, 28, 2, 16, 9, 126, 0] {
+            int(&mut out, t);
+        }
+        string(&mut out, Some("shop"));
+        string(&mut out, Some("15.18"));
+        string(&mut out, Some("15.18"));
+        int(&mut out, 2);
+        toc_entry(
+            &mut out,
+            214,
+            "TABLE",
+            "users",
+            "CREATE TABLE public.users (\n    id integer,\n    email text\n);\n",
+        );
+        toc_entry(&mut out, 3344, "TABLE DATA", "users", "");
+        out.push(BLK_DATA);
+        int(&mut out, 3344);
+        let mut data = b"1\talice@example.com\n2\t\\N\n\\.\n\n".to_vec();
+        if level != 0 {
+            let mut compressed = Vec::new();
+            ZlibEncoder::new(data.as_slice())
+                .read_to_end(&mut compressed)
+                .await?;
+            data = compressed;
+        }
+        for chunk in data.chunks(10) {
+            int(&mut out, chunk.len() as i64);
+            out.extend(chunk);
+        }
+        int(&mut out, 0);
+        Ok(out)
+    }
+
+    #[tokio::test]
+    async fn custom_format() -> Result<()> {
+        for level in [0, -1] {
+            let (a, d) = simple_adapt_info(
+                &PathBuf::from("shop.pgdump"),
+                Box::pin(Cursor::new(archive(level).await?)),
+            );
+            let buf = adapted_to_vec(loop_adapt(&PgDumpAdapter::new(), d, a).await?).await?;
+            assert_eq!(
+                String::from_utf8(buf)?,
+                "PREFIX:database: shop
+PREFIX:toc: 214; TABLE public users postgres
+PREFIX:TABLE public.users: CREATE TABLE public.users (
+PREFIX:TABLE public.users:     id integer,
+PREFIX:TABLE public.users:     email text
+PREFIX:TABLE public.users: );
+PREFIX:toc: 3344; TABLE DATA public users postgres
+PREFIX:public.users: 1\talice@example.com
+PREFIX:public.users: 2\t\\N
+"
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plain_dump() -> Result<()> {
+        let sql = "CREATE TABLE users (id integer);\n\nCOPY users (id) FROM stdin;\n1\n\\.\n";
+        let (a, d) = simple_adapt_info(&PathBuf::from("shop.pgdump"), Box::pin(Cursor::new(sql)));
+        let buf = adapted_to_vec(loop_adapt(&PgDumpAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:CREATE TABLE users (id integer);
+PREFIX:COPY users (id) FROM stdin;
+PREFIX:1
+"
+        );
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@
 
 //impl<T> FileAdapter for T where T: RunFnAdapter {}
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_stream::stream;
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -19,9 +19,14 @@ use tokio_util::io::StreamReader;
 
 use crate::adapted_iter::AdaptedFilesIterBox;
 use crate::adapted_iter::one_file;
-use crate::matching::FastFileMatcher;
+use crate::config::{BinaryPolicy, PageStyle};
+use crate::matching::{FastFileMatcher, FileMatcher};
+use crate::preproc::loop_adapt;
 
-use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata};
+use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata, ReadBox, get_adapters_filtered};
+
+/// output for binary data with `BinaryPolicy::Placeholder`
+static BINARY_PLACEHOLDER: &str = "[rga: binary data]";
 
 fn add_newline(ar: impl AsyncRead + Send) -> impl AsyncRead + Send {
     ar.chain(Cursor::new(b"\n"))
@@ -36,6 +41,7 @@ impl GetMetadata for PostprocPrefix {
                 version: 1,
                 description: "Adds the line prefix to each line (e.g. the filename within a zip)".to_owned(),
                 recurses: false,
+                outputs_unchanged_files: false,
                 fast_matchers: vec![],
                 slow_matchers: None,
                 keep_fast_matchers_if_accurate: false,
@@ -52,13 +58,22 @@ impl FileAdapter for PostprocPrefix {
         a: super::AdaptInfo,
         _detection_reason: &crate::matching::FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let read = add_newline(postproc_prefix(
-            &a.line_prefix,
-            postproc_encoding(&a.line_prefix, a.inp).await?,
-        ));
+        let inp =
+            match postproc_encoding(&a.line_prefix, a.inp, &a.config.fallback_encodings).await? {
+                Decoded::Text(inp) => inp,
+                Decoded::Binary(inp) => return adapt_binary(AdaptInfo { inp, ..a }).await,
+            };
+        let read: ReadBox = if a.config.member_line_numbers && a.archive_recursion_depth > 0 {
+            Box::pin(add_newline(postproc_prefix_line_numbers(
+                &a.line_prefix,
+                inp,
+            )))
+        } else {
+            Box::pin(add_newline(postproc_prefix(&a.line_prefix, inp)))
+        };
         // keep adapt info (filename etc) except replace inp
         let ai = AdaptInfo {
-            inp: Box::pin(read),
+            inp: read,
             postprocess: false,
             ..a
         };
@@ -75,14 +90,24 @@ impl Read for ReadErr {
     }
 }*/
 
+/// Output of `postproc_encoding`
+enum Decoded {
+    /// utf-8 text
+    Text(ReadBox),
+    /// the unchanged input, which contains binary data
+    Binary(ReadBox),
+}
+
 /**
  * Detects and converts encodings other than utf-8 to utf-8.
- * If the input stream does not contain valid text, returns the string `[rga: binary data]` instead
+ * If the input is not valid utf-8, the given fallback encodings are tried in order.
+ * If the input stream does not contain valid text, returns it unchanged as `Decoded::Binary`.
  */
 async fn postproc_encoding(
     _line_prefix: &str,
     inp: Pin<Box<dyn AsyncRead + Send>>,
-) -> Result<Pin<Box<dyn AsyncRead + Send>>> {
+    fallback_encodings: &[String],
+) -> Result<Decoded> {
     // check for binary content in first 8kB
     // read the first 8kB into a buffer, check for null bytes, then return the buffer concatenated with the rest of the file
     let mut fourk = Vec::with_capacity(1 << 13);
@@ -92,42 +117,134 @@ async fn postproc_encoding(
     let has_binary = fourk.contains(&0u8);
 
     let enc = Encoding::for_bom(&fourk);
+    let fallback = if enc.is_none() && !has_binary && !is_utf8_start(&fourk) {
+        choose_fallback_encoding(&fourk, fallback_encodings)?
+    } else {
+        None
+    };
     let inp = Cursor::new(fourk).chain(beginning.into_inner());
     match enc {
         Some((enc, _)) if enc != encoding_rs::UTF_8 => {
             // detected UTF16LE or UTF16BE, convert to UTF8 in separate thread
             // TODO: parse these options from ripgrep's configuration
             let encoding = None; // detect bom but usually assume utf8
-            let bom_sniffing = true;
-            let mut decode_builder = DecodeReaderBytesBuilder::new();
-            // https://github.com/BurntSushi/ripgrep/blob/a7d26c8f144a4957b75f71087a66692d0b25759a/grep-searcher/src/searcher/mod.rs#L706
-            // this detects utf-16 BOMs and transcodes to utf-8 if they are present
-            // it does not detect any other char encodings. that would require https://github.com/hsivonen/chardetng or similar but then binary detection is hard (?)
-            let mut inp = decode_builder
-                .encoding(encoding)
-                .utf8_passthru(true)
-                .strip_bom(bom_sniffing)
-                .bom_override(true)
-                .bom_sniffing(bom_sniffing)
-                .build(SyncIoBridge::new(inp));
-            let oup = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
-                let mut oup = Vec::new();
-                std::io::Read::read_to_end(&mut inp, &mut oup)?;
-                Ok(oup)
-            })
-            .await??;
-            Ok(Box::pin(Cursor::new(oup)))
+            Ok(Decoded::Text(decode_to_utf8(inp, encoding).await?))
         }
         _ => {
             if has_binary {
                 log::debug!("detected binary");
-                return Ok(Box::pin(Cursor::new("[rga: binary data]")));
+                return Ok(Decoded::Binary(Box::pin(inp)));
+            }
+            if let Some(fallback) = fallback {
+                log::debug!("not utf-8, decoding as {}", fallback.name());
+                return Ok(Decoded::Text(decode_to_utf8(inp, Some(fallback)).await?));
             }
-            Ok(Box::pin(inp))
+            Ok(Decoded::Text(Box::pin(inp)))
         }
     }
 }
 
+/// Handles binary input according to the configured `BinaryPolicy`.
+async fn adapt_binary(mut a: AdaptInfo) -> Result<AdaptedFilesIterBox> {
+    let read: ReadBox = match a.config.binary {
+        BinaryPolicy::Skip => Box::pin(Cursor::new(Vec::new())),
+        BinaryPolicy::Placeholder => Box::pin(add_newline(postproc_prefix(
+            &a.line_prefix,
+            Cursor::new(BINARY_PLACEHOLDER),
+        ))),
+        BinaryPolicy::Passthrough => Box::pin(add_newline(postproc_prefix(&a.line_prefix, a.inp))),
+        BinaryPolicy::Adapter => {
+            let name = a
+                .config
+                .binary_adapter
+                .clone()
+                .context("--rga-binary=adapter requires --rga-binary-adapter")?;
+            // only enabled adapters, so the binary adapter cannot enable one that runs commands
+            let adapters = get_adapters_filtered(
+                a.config.custom_adapters.clone(),
+                &a.config.adapter_aliases,
+                &a.config.adapters,
+            )?;
+            let adapter = adapters
+                .iter()
+                .find(|adapter| adapter.metadata().name == name)
+                .with_context(|| {
+                    format!(
+                        "binary adapter {name} is not enabled (add it with --rga-adapters=+{name})"
+                    )
+                })?;
+            let detection_reason =
+                FileMatcher::Fast(FastFileMatcher::FileExtension("binary".to_string()));
+            // the output of the adapter is postprocessed again, which adds the line prefix.
+            // if that output is binary as well, it is passed on instead of being converted again and again
+            a.config.binary = BinaryPolicy::Passthrough;
+            return loop_adapt(adapter.as_ref(), detection_reason, a).await;
+        }
+    };
+    Ok(one_file(AdaptInfo {
+        inp: read,
+        postprocess: false,
+        ..a
+    }))
+}
+
+/// Transcodes the input to utf-8 in a separate thread. Uses the BOM if present, otherwise the given encoding (utf-8 if None).
+async fn decode_to_utf8(
+    inp: impl AsyncRead + Send + Unpin + 'static,
+    encoding: Option<&'static Encoding>,
+) -> Result<Pin<Box<dyn AsyncRead + Send>>> {
+    let bom_sniffing = true;
+    let mut decode_builder = DecodeReaderBytesBuilder::new();
+    // https://github.com/BurntSushi/ripgrep/blob/a7d26c8f144a4957b75f71087a66692d0b25759a/grep-searcher/src/searcher/mod.rs#L706
+    // this detects utf-16 BOMs and transcodes to utf-8 if they are present
+    // it does not detect any other char encodings. that would require https://github.com/hsivonen/chardetng or similar but then binary detection is hard (?)
+    let mut inp = decode_builder
+        .encoding(encoding)
+        .utf8_passthru(true)
+        .strip_bom(bom_sniffing)
+        .bom_override(true)
+        .bom_sniffing(bom_sniffing)
+        .build(SyncIoBridge::new(inp));
+    let oup = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+        let mut oup = Vec::new();
+        std::io::Read::read_to_end(&mut inp, &mut oup)?;
+        Ok(oup)
+    })
+    .await??;
+    Ok(Box::pin(Cursor::new(oup)))
+}
+
+/// Whether the beginning of a file is valid utf-8 (a multi-byte character cut off at the end is ok)
+fn is_utf8_start(beginning: &[u8]) -> bool {
+    match std::str::from_utf8(beginning) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Returns the first of the given encodings (by label) that can decode the beginning of a file without errors.
+fn choose_fallback_encoding(
+    beginning: &[u8],
+    labels: &[String],
+) -> Result<Option<&'static Encoding>> {
+    for label in labels {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .with_context(|| format!("unknown encoding {label:?} in fallback_encodings"))?;
+        let mut decoder = encoding.new_decoder_without_bom_handling();
+        let mut out = String::with_capacity(
+            decoder
+                .max_utf8_buffer_length_without_replacement(beginning.len())
+                .unwrap_or(beginning.len() * 4),
+        );
+        // last = false: a character cut off at the end of the buffer is not an error
+        let (result, _) = decoder.decode_to_string_without_replacement(beginning, &mut out, false);
+        if result == encoding_rs::DecoderResult::InputEmpty {
+            return Ok(Some(encoding));
+        }
+    }
+    Ok(None)
+}
+
 /// Adds the given prefix to each line in an `AsyncRead`.
 pub fn postproc_prefix<T: AsyncRead + Send>(
     line_prefix: &str,
@@ -155,6 +272,38 @@ pub fn postproc_prefix<T: AsyncRead + Send>(
     Box::pin(StreamReader::new(oup_stream))
 }
 
+/// Adds the given prefix and the line number (starting at one) to each line in an `AsyncRead`.
+///
+/// A trailing space of the prefix is moved after the line number, so `app.log: ` becomes `app.log:1: `.
+pub fn postproc_prefix_line_numbers<T: AsyncRead + Send>(
+    line_prefix: &str,
+    inp: T,
+) -> impl AsyncRead + Send + use<T> {
+    let base = line_prefix
+        .strip_suffix(' ')
+        .unwrap_or(line_prefix)
+        .to_string();
+    let inp_stream = ReaderStream::new(inp);
+    let oup_stream = stream! {
+        let mut line_number: u64 = 1;
+        yield Ok(Bytes::from(format!("{base}{line_number}: ")));
+        for await chunk in inp_stream {
+            let chunk = chunk?;
+            let mut out = Vec::with_capacity(chunk.len());
+            for (i, part) in chunk.split(|b| *b == b'\n').enumerate() {
+                if i > 0 {
+                    line_number += 1;
+                    out.push(b'\n');
+                    out.extend_from_slice(format!("{base}{line_number}: ").as_bytes());
+                }
+                out.extend_from_slice(part);
+            }
+            yield Ok(Bytes::from(out));
+        }
+    };
+    Box::pin(StreamReader::new(oup_stream))
+}
+
 #[derive(Default)]
 pub struct PostprocPageBreaks {}
 
@@ -166,6 +315,7 @@ impl GetMetadata for PostprocPageBreaks {
                 version: 1,
                 description: "Adds the page number to each line for an input file that specifies page breaks as ascii page break character.\nMainly to be used internally by the poppler adapter.".to_owned(),
                 recurses: false,
+                outputs_unchanged_files: false,
                 fast_matchers: vec![FastFileMatcher::FileExtension("asciipagebreaks".to_string())],
                 slow_matchers: None,
                 keep_fast_matchers_if_accurate: false,
@@ -182,10 +332,18 @@ impl FileAdapter for PostprocPageBreaks {
         a: super::AdaptInfo,
         _detection_reason: &crate::matching::FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let read = postproc_pagebreaks(postproc_encoding(&a.line_prefix, a.inp).await?);
+        let inp =
+            match postproc_encoding(&a.line_prefix, a.inp, &a.config.fallback_encodings).await? {
+                Decoded::Text(inp) => inp,
+                Decoded::Binary(inp) => return adapt_binary(AdaptInfo { inp, ..a }).await,
+            };
+        let read: ReadBox = match a.config.page_style {
+            PageStyle::Prefix => Box::pin(postproc_pagebreaks(inp)),
+            PageStyle::Heading => Box::pin(postproc_pageheadings(inp)),
+        };
         // keep adapt info (filename etc) except replace inp
         let ai = AdaptInfo {
-            inp: Box::pin(read),
+            inp: read,
             archive_recursion_depth: a.archive_recursion_depth + 1,
             filepath_hint: a
                 .filepath_hint
@@ -198,6 +356,72 @@ impl FileAdapter for PostprocPageBreaks {
         Ok(one_file(ai))
     }
 }
+/// Format the lines of the pages (or slides) of documents that are split by the adapter itself,
+/// in the same `--rga-page-style` as the page breaks handled by `postproc_pagebreaks` and `postproc_pageheadings`.
+///
+/// `label` is "Page" or "Slide", the pages are numbered starting at one.
+pub fn format_pages(
+    label: &str,
+    pages: Vec<Vec<String>>,
+    line_prefix: &str,
+    page_style: PageStyle,
+) -> String {
+    let mut out = String::new();
+    for (i, lines) in pages.into_iter().enumerate() {
+        let page = i + 1;
+        match page_style {
+            PageStyle::Prefix => {
+                for line in lines {
+                    out.push_str(&format!("{line_prefix}{label} {page}: {line}\n"));
+                }
+            }
+            PageStyle::Heading => {
+                out.push_str(&format!("{line_prefix}== {label} {page} ==\n"));
+                for line in lines {
+                    out.push_str(&format!("{line_prefix}{line}\n"));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Format the lines of PDF pages with the title of the section they are in (see `--rga-pdf-outline`):
+/// as `Page 42 [3.1 Results]: ` prefixes, or with the heading page style as a heading `== Page 42 [3.1 Results] ==`
+/// at the start of each page and section.
+pub fn format_sectioned_pages(
+    pages: Vec<Vec<(Option<&str>, &str)>>,
+    line_prefix: &str,
+    page_style: PageStyle,
+) -> String {
+    let mut out = String::new();
+    for (i, lines) in pages.into_iter().enumerate() {
+        let page = i + 1;
+        let label = |section: Option<&str>| match section {
+            Some(section) => format!("Page {page} [{section}]"),
+            None => format!("Page {page}"),
+        };
+        if lines.is_empty() && page_style == PageStyle::Heading {
+            out.push_str(&format!("{line_prefix}== Page {page} ==\n"));
+        }
+        let mut heading = None;
+        for (section, line) in lines {
+            let label = label(section);
+            match page_style {
+                PageStyle::Prefix => out.push_str(&format!("{line_prefix}{label}: {line}\n")),
+                PageStyle::Heading => {
+                    if heading.as_ref() != Some(&label) {
+                        out.push_str(&format!("{line_prefix}== {label} ==\n"));
+                        heading = Some(label);
+                    }
+                    out.push_str(&format!("{line_prefix}{line}\n"));
+                }
+            }
+        }
+    }
+    out
+}
+
 /// Adds the prefix "Page N: " to each line,
 /// where N starts at one and is incremented for each ASCII Form Feed character in the input stream.
 /// ASCII form feeds are the page delimiters output by `pdftotext`.
@@ -241,8 +465,45 @@ pub fn postproc_pagebreaks(input: impl AsyncRead + Send) -> impl AsyncRead + Sen
     Box::pin(StreamReader::new(output_stream))
 }
 
+/// Outputs a line "== Page N ==" at the start of each page and leaves all other lines unchanged,
+/// where N starts at one and is incremented for each ASCII Form Feed character in the input stream.
+pub fn postproc_pageheadings(input: impl AsyncRead + Send) -> impl AsyncRead + Send {
+    let input_stream = ReaderStream::new(input);
+    let output_stream = stream! {
+        let mut page_count: i32 = 1;
+        yield std::io::Result::Ok(Bytes::from(format!("== Page {page_count} ==\n")));
+        // only write the heading when there is more text on the page (pdftotext outputs a \x0c at the end of the last page)
+        let mut pending: Option<Bytes> = None;
+        let mut at_line_start = true;
+
+        for await read_chunk in input_stream {
+            let read_chunk = read_chunk?;
+            for (chunk_idx, page_chunk) in read_chunk.split(|b| *b == b'\x0c').enumerate() {
+                if chunk_idx != 0 {
+                    page_count += 1;
+                    if let Some(p) = pending.take() {
+                        yield Ok(p);
+                    }
+                    let newline = if at_line_start { "" } else { "\n" };
+                    pending = Some(Bytes::from(format!("{newline}== Page {page_count} ==\n")));
+                    at_line_start = true;
+                }
+                if !page_chunk.is_empty() {
+                    if let Some(p) = pending.take() {
+                        yield Ok(p);
+                    }
+                    at_line_start = page_chunk.ends_with(b"\n");
+                    yield Ok(Bytes::copy_from_slice(page_chunk));
+                }
+            }
+        }
+    };
+    Box::pin(StreamReader::new(output_stream))
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::adapters::custom::CustomAdapterConfig;
     use crate::preproc::loop_adapt;
     use crate::test_utils::*;
 
@@ -287,6 +548,23 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_with_pageheadings() -> Result<()> {
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new()
+            .read(b"Hello\nWo")
+            .read(b"rld\x0c")
+            .read(b"Foo Bar\n")
+            .read(b"\x0cTest\x0c")
+            .build();
+        postproc_pageheadings(mock).read_to_end(&mut output).await?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "== Page 1 ==\nHello\nWorld\n== Page 2 ==\nFoo Bar\n== Page 3 ==\nTest"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_pdf_twoblank() -> Result<()> {
         let adapter = poppler_adapter();
@@ -322,6 +600,23 @@ PREFIX:Page 3:
         assert_eq!(output, b"prefix: Hello\nprefix: World");
     }
 
+    #[tokio::test]
+    async fn test_postproc_prefix_line_numbers() -> Result<()> {
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new()
+            .read(b"Hello\nWo")
+            .read(b"rld\n\nFoo")
+            .build();
+        postproc_prefix_line_numbers("app.log: ", mock)
+            .read_to_end(&mut output)
+            .await?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "app.log:1: Hello\napp.log:2: World\napp.log:3: \napp.log:4: Foo"
+        );
+        Ok(())
+    }
+
     async fn test_from_strs(
         pagebreaks: bool,
         line_prefix: &str,
@@ -339,7 +634,10 @@ PREFIX:Page 3:
     ) -> Result<()> {
         let mut oup = Vec::new();
         let inp = Box::pin(Cursor::new(a));
-        let inp = postproc_encoding("", inp).await?;
+        let inp: ReadBox = match postproc_encoding("", inp, &[]).await? {
+            Decoded::Text(inp) => inp,
+            Decoded::Binary(_) => Box::pin(Cursor::new(BINARY_PLACEHOLDER)),
+        };
         if pagebreaks {
             postproc_pagebreaks(inp).read_to_end(&mut oup).await?;
         } else {
@@ -405,6 +703,144 @@ PREFIX:Page 3:
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_binary_policy() -> Result<()> {
+        for (policy, expected) in [
+            (BinaryPolicy::Skip, &b""[..]),
+            (
+                BinaryPolicy::Placeholder,
+                &b"PREFIX:[rga: binary data]\n"[..],
+            ),
+            (
+                BinaryPolicy::Passthrough,
+                &b"PREFIX:foo\0\nPREFIX:bar\n"[..],
+            ),
+        ] {
+            let (mut a, d) = simple_adapt_info(
+                &PathBuf::from("foo.bin"),
+                Box::pin(Cursor::new(b"foo\0\nbar")),
+            );
+            a.config.binary = policy;
+            let res = PostprocPrefix {}.adapt(a, &d).await?;
+            assert_eq!(adapted_to_vec(res).await?, expected, "{policy}");
+        }
+
+        // an adapter that outputs binary data itself
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("foo.bin"),
+            Box::pin(Cursor::new(b"foo\0\nbar")),
+        );
+        a.config.binary = BinaryPolicy::Adapter;
+        a.config.binary_adapter = Some("raw".to_string());
+        a.config.custom_adapters = Some(vec![CustomAdapterConfig {
+            name: "raw".to_string(),
+            description: "".to_string(),
+            disabled_by_default: None,
+            version: 1,
+            extensions: vec![],
+            mimetypes: None,
+            match_only_by_mime: None,
+            binary: "cat".to_string(),
+            args: vec![],
+            output_path_hint: None,
+            password_args: None,
+        }]);
+        let res = PostprocPrefix {}.adapt(a, &d).await?;
+        assert_eq!(adapted_to_vec(res).await?, b"PREFIX:foo\0\nPREFIX:bar\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_binary_adapter_must_be_enabled() -> Result<()> {
+        // restic runs a command and is disabled by default, the binary adapter must not enable it
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("foo.bin"),
+            Box::pin(Cursor::new(b"foo\0\nbar")),
+        );
+        a.config.binary = BinaryPolicy::Adapter;
+        a.config.binary_adapter = Some("restic".to_string());
+        let Err(err) = PostprocPrefix {}.adapt(a, &d).await else {
+            panic!("the disabled restic adapter was used");
+        };
+        assert!(
+            format!("{err:#}").contains("binary adapter restic is not enabled"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fallback_encodings() -> Result<()> {
+        async fn decode(inp: &'static [u8], fallback: &[&str]) -> Result<Vec<u8>> {
+            let fallback = fallback.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            let mut oup = Vec::new();
+            let Decoded::Text(mut inp) =
+                postproc_encoding("", Box::pin(Cursor::new(inp)), &fallback).await?
+            else {
+                anyhow::bail!("detected as binary");
+            };
+            inp.read_to_end(&mut oup).await?;
+            Ok(oup)
+        }
+        // "Привет" in windows-1251 and koi8-r
+        let cp1251: &[u8] = b"\xcf\xf0\xe8\xe2\xe5\xf2";
+        let koi8r: &[u8] = b"\xf0\xd2\xc9\xd7\xc5\xd4";
+        assert_eq!(
+            decode(cp1251, &["windows-1251"]).await?,
+            "Привет".as_bytes()
+        );
+        assert_eq!(
+            decode(koi8r, &["koi8-r", "windows-1251"]).await?,
+            "Привет".as_bytes()
+        );
+        // utf-8 is never decoded with a fallback
+        assert_eq!(
+            decode("Привет".as_bytes(), &["windows-1251"]).await?,
+            "Привет".as_bytes()
+        );
+        // "日本" in shift_jis is not valid euc-kr
+        let sjis: &[u8] = b"\x93\xfa\x96\x7b";
+        assert_eq!(
+            decode(sjis, &["euc-kr", "shift_jis"]).await?,
+            "日本".as_bytes()
+        );
+        // without fallbacks, the input is passed through
+        assert_eq!(decode(cp1251, &[]).await?, cp1251);
+        assert!(decode(cp1251, &["no-such-encoding"]).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sectioned_pages() {
+        let pages = vec![
+            vec![(None, "Title page")],
+            vec![
+                (None, "A Report"),
+                (Some("2.1 Setup"), "2.1 Setup"),
+                (Some("2.1 Setup"), "more"),
+            ],
+        ];
+        assert_eq!(
+            format_sectioned_pages(pages.clone(), "a.pdf: ", PageStyle::Prefix),
+            "a.pdf: Page 1: Title page
+a.pdf: Page 2: A Report
+a.pdf: Page 2 [2.1 Setup]: 2.1 Setup
+a.pdf: Page 2 [2.1 Setup]: more
+"
+        );
+        assert_eq!(
+            format_sectioned_pages(pages, "", PageStyle::Heading),
+            "== Page 1 ==
+Title page
+== Page 2 ==
+A Report
+== Page 2 [2.1 Setup] ==
+2.1 Setup
+more
+"
+        );
+    }
+
     /*#[test]
     fn chardet() -> Result<()> {
         let mut d = chardetng::EncodingDetector::new();
@@ -0,0 +1,219 @@
+use super::postproc::format_pages;
+use super::writing::WritingFileAdapter;
+use super::xml::{XmlEvent, XmlReader, local_name, ooxml_relationships};
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["pptx", "pptm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pptx".to_owned(),
+        version: 1,
+        description: "Reads the text and speaker notes of PowerPoint presentations (pptx).\nEach line is prefixed with its slide number like the page number of PDFs, e.g. Slide 7:".to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PptxAdapter;
+
+impl PptxAdapter {
+    pub fn new() -> PptxAdapter {
+        PptxAdapter
+    }
+}
+
+impl GetMetadata for PptxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// The text of the DrawingML paragraphs (`a:p`) of a slide, one line per paragraph
+fn paragraphs(xml: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut in_text = false;
+    // fields are slide numbers and dates
+    let mut in_field = false;
+    for event in XmlReader::new(xml) {
+        match &event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => match local_name(name) {
+                "t" => in_text = !self_closing,
+                "fld" => in_field = !self_closing,
+                "br" => lines.push(std::mem::take(&mut line)),
+                _ => {}
+            },
+            XmlEvent::End { name } => match local_name(name) {
+                "t" => in_text = false,
+                "fld" => in_field = false,
+                "p" => lines.push(std::mem::take(&mut line)),
+                _ => {}
+            },
+            XmlEvent::Text(text) if in_text && !in_field => line.push_str(text),
+            XmlEvent::Text(_) => {}
+        }
+    }
+    lines
+        .into_iter()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// The text of the slides (including their notes) in the order of the presentation
+fn slides_text(files: &[(String, Vec<u8>)]) -> Result<Vec<Vec<String>>> {
+    let get = |name: &str| {
+        files
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, content)| String::from_utf8_lossy(content))
+    };
+    let presentation = get("ppt/presentation.xml").context("ppt/presentation.xml not found")?;
+    let rels = ooxml_relationships(
+        &get("ppt/_rels/presentation.xml.rels").unwrap_or_default(),
+        "ppt",
+    );
+    let mut slides = Vec::new();
+    for slide in XmlReader::new(&presentation)
+        .filter(|e| matches!(e, XmlEvent::Start { name, .. } if local_name(name) == "sldId"))
+    {
+        let Some(path) = slide.attr("id").and_then(|id| rels.get(id)) else {
+            warn!("slide not found in presentation relationships");
+            continue;
+        };
+        let Some(xml) = get(path) else {
+            debug!("slide {path} missing");
+            continue;
+        };
+        let mut lines = paragraphs(&xml);
+        let (dir, file) = path.rsplit_once('/').unwrap_or(("", path));
+        let slide_rels = ooxml_relationships(
+            &get(&format!("{dir}/_rels/{file}.rels")).unwrap_or_default(),
+            dir,
+        );
+        if let Some(notes) = slide_rels
+            .values()
+            .find(|target| target.contains("/notesSlides/"))
+            .and_then(|target| get(target))
+        {
+            lines.extend(paragraphs(&notes));
+        }
+        slides.push(lines);
+    }
+    Ok(slides)
+}
+
+#[async_trait]
+impl WritingFileAdapter for PptxAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let files = zip::read_zip_files(inp, &filepath_hint, is_real_file).await?;
+        let out = format_pages(
+            "Slide",
+            slides_text(&files)?,
+            &line_prefix,
+            config.page_style,
+        );
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use async_zip::{Compression, ZipEntryBuilder, write::ZipFileWriter};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn slide(texts: &[&str]) -> String {
+        let paragraphs: String = texts
+            .iter()
+            .map(|t| format!("<a:p><a:r><a:rPr lang=\"en-US\"/><a:t>{t}</a:t></a:r></a:p>"))
+            .collect();
+        format!(
+            r#"<p:sld xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main"><p:cSld><p:spTree><p:sp><p:txBody>{paragraphs}<a:p><a:fld type="slidenum"><a:t>9</a:t></a:fld></a:p></p:txBody></p:sp></p:spTree></p:cSld></p:sld>"#
+        )
+    }
+
+    async fn create_pptx() -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        let files = [
+            (
+                "ppt/presentation.xml".to_string(),
+                r#"<p:presentation xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
+<p:sldIdLst><p:sldId id="256" r:id="rId3"/><p:sldId id="257" r:id="rId2"/></p:sldIdLst></p:presentation>"#
+                    .to_string(),
+            ),
+            (
+                "ppt/_rels/presentation.xml.rels".to_string(),
+                r#"<Relationships><Relationship Id="rId2" Target="slides/slide1.xml"/><Relationship Id="rId3" Target="slides/slide2.xml"/></Relationships>"#
+                    .to_string(),
+            ),
+            ("ppt/slides/slide1.xml".to_string(), slide(&["Second", "Q&amp;A"])),
+            ("ppt/slides/slide2.xml".to_string(), slide(&["Title slide"])),
+            (
+                "ppt/slides/_rels/slide2.xml.rels".to_string(),
+                r#"<Relationships><Relationship Id="rId1" Target="../notesSlides/notesSlide1.xml"/></Relationships>"#
+                    .to_string(),
+            ),
+            ("ppt/notesSlides/notesSlide1.xml".to_string(), slide(&["Remember to smile"])),
+        ];
+        for (name, content) in files {
+            let options = ZipEntryBuilder::new(name, Compression::Deflate);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    #[tokio::test]
+    async fn slides() -> Result<()> {
+        let adapter = PptxAdapter::new();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("talk.pptx"),
+            Box::pin(Cursor::new(create_pptx().await?)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&adapter, d, a).await?).await?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:Slide 1: Title slide
+PREFIX:Slide 1: Remember to smile
+PREFIX:Slide 2: Second
+PREFIX:Slide 2: Q&A
+"
+        );
+        Ok(())
+    }
+}
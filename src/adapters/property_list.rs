@@ -0,0 +1,163 @@
+use super::flatten::{Node, flatten};
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["plist", "bplist"];
+/// binary data in property lists longer than this is only output as its size
+const MAX_DATA_HEX_LEN: usize = 64;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "plist".to_owned(),
+        version: 1,
+        description: "Flattens property list files (XML and binary plists) into `key.path = value` lines, like the flatten adapter. Binary plists embedded in plists are flattened as well"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PlistAdapter;
+
+impl PlistAdapter {
+    pub fn new() -> PlistAdapter {
+        PlistAdapter
+    }
+}
+
+impl GetMetadata for PlistAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+impl From<plist::Value> for Node {
+    fn from(value: plist::Value) -> Node {
+        match value {
+            plist::Value::String(s) => Node::Scalar(s),
+            plist::Value::Array(items) => Node::Seq(items.into_iter().map(Node::from).collect()),
+            plist::Value::Dictionary(dict) => {
+                Node::Map(dict.into_iter().map(|(k, v)| (k, v.into())).collect())
+            }
+            plist::Value::Boolean(b) => Node::Scalar(b.to_string()),
+            plist::Value::Integer(i) => Node::Scalar(i.to_string()),
+            plist::Value::Real(r) => Node::Scalar(r.to_string()),
+            plist::Value::Date(d) => Node::Scalar(d.to_xml_format()),
+            plist::Value::Uid(u) => Node::Scalar(format!("UID({})", u.get())),
+            plist::Value::Data(data) => {
+                // e.g. archived objects and bookmarks
+                if data.starts_with(b"bplist") {
+                    if let Ok(value) = plist::Value::from_reader(std::io::Cursor::new(&data)) {
+                        return value.into();
+                    }
+                }
+                match std::str::from_utf8(&data) {
+                    Ok(text) if !text.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                        Node::Scalar(text.to_string())
+                    }
+                    _ if data.len() > MAX_DATA_HEX_LEN => {
+                        Node::Scalar(format!("[{} bytes]", data.len()))
+                    }
+                    _ => Node::Scalar(data.iter().map(|b| format!("{b:02x}")).collect()),
+                }
+            }
+            _ => Node::Scalar(String::new()),
+        }
+    }
+}
+
+/// Flattens a property list (XML or binary)
+fn flatten_plist(data: &[u8], line_prefix: &str) -> Result<String> {
+    let value = plist::Value::from_reader(std::io::Cursor::new(data)).context("invalid plist")?;
+    let mut out = String::new();
+    flatten(&Node::from(value), "", line_prefix, &mut out);
+    Ok(out)
+}
+
+/// The flattened `key.path = value` pairs of a binary plist embedded in other data, on one line separated by `; `
+pub fn plist_text(data: &[u8]) -> Option<String> {
+    let out = flatten_plist(data, "").ok()?;
+    Some(out.lines().collect::<Vec<_>>().join("; ")).filter(|s| !s.is_empty())
+}
+
+#[async_trait]
+impl WritingFileAdapter for PlistAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        oup.write_all(flatten_plist(&data, &line_prefix)?.as_bytes())
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn binary_plist() -> Result<()> {
+        let mut bookmark = plist::Dictionary::new();
+        bookmark.insert("path".into(), "/Users/alice/Documents/report.pdf".into());
+        let mut embedded = Vec::new();
+        plist::Value::Dictionary(bookmark).to_writer_binary(&mut embedded)?;
+        let mut dict = plist::Dictionary::new();
+        dict.insert("autohide".into(), true.into());
+        dict.insert("tilesize".into(), 48.into());
+        dict.insert(
+            "persistent-apps".into(),
+            plist::Value::Array(vec!["Safari".into(), "Mail".into()]),
+        );
+        dict.insert("bookmark".into(), plist::Value::Data(embedded.clone()));
+        dict.insert(
+            "checksum".into(),
+            plist::Value::Data(vec![0xde, 0xad, 0xbe, 0xef]),
+        );
+        let mut data = Vec::new();
+        plist::Value::Dictionary(dict).to_writer_binary(&mut data)?;
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("com.apple.dock.plist"),
+            Box::pin(Cursor::new(data)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&PlistAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:autohide = true
+PREFIX:tilesize = 48
+PREFIX:persistent-apps[0] = Safari
+PREFIX:persistent-apps[1] = Mail
+PREFIX:bookmark.path = /Users/alice/Documents/report.pdf
+PREFIX:checksum = deadbeef
+"
+        );
+        assert_eq!(
+            plist_text(&embedded).as_deref(),
+            Some("path = /Users/alice/Documents/report.pdf")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+use super::custom::map_exe_error;
+use super::writing::WritingFileAdapter;
+use super::*;
+use crate::config::ProtobufConfig;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["pb", "binpb", "protobuf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "protobuf".to_owned(),
+        version: 1,
+        description: "Decodes binary protobuf files, either a single message or a sequence of length-prefixed messages (prefixed with `message N: `).\nWith a schema configured in `protobuf` of the config file, the messages are decoded to text format with `protoc`. Otherwise, the fields are output by number like `protoc --decode_raw`.\nDisabled by default since protobuf files have no signature and `.pb` is used by other formats too, enable it with `--rga-adapters=+protobuf`"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ProtobufAdapter;
+
+impl ProtobufAdapter {
+    pub fn new() -> ProtobufAdapter {
+        ProtobufAdapter
+    }
+}
+
+impl GetMetadata for ProtobufAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have protoc (the protobuf compiler, package protobuf-compiler) installed.";
+
+// wire types
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LEN: u64 = 2;
+const START_GROUP: u64 = 3;
+const END_GROUP: u64 = 4;
+const FIXED32: u64 = 5;
+
+enum RawValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+    Group(Vec<(u64, RawValue<'a>)>),
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("unexpected end of protobuf message")?;
+        self.pos += len;
+        Ok(b)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.bytes(1)?[0];
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("invalid varint")
+    }
+
+    /// The fields up to the end of the data, or up to the end of the group `group`
+    fn fields(&mut self, group: Option<u64>) -> Result<Vec<(u64, RawValue<'a>)>> {
+        let mut fields = Vec::new();
+        while self.pos < self.data.len() {
+            let tag = self.varint()?;
+            let (number, wire_type) = (tag >> 3, tag & 7);
+            anyhow::ensure!(number > 0, "invalid field number 0");
+            let value = match wire_type {
+                VARINT => RawValue::Varint(self.varint()?),
+                FIXED64 => RawValue::Fixed64(u64::from_le_bytes(self.bytes(8)?.try_into()?)),
+                LEN => {
+                    let len = self.varint()?.try_into()?;
+                    RawValue::Bytes(self.bytes(len)?)
+                }
+                START_GROUP => RawValue::Group(self.fields(Some(number))?),
+                END_GROUP => {
+                    anyhow::ensure!(group == Some(number), "unexpected end of group");
+                    return Ok(fields);
+                }
+                FIXED32 => RawValue::Fixed32(u32::from_le_bytes(self.bytes(4)?.try_into()?)),
+                _ => anyhow::bail!("invalid wire type {wire_type}"),
+            };
+            fields.push((number, value));
+        }
+        anyhow::ensure!(group.is_none(), "unterminated group");
+        Ok(fields)
+    }
+}
+
+fn parse_raw(data: &[u8]) -> Result<Vec<(u64, RawValue<'_>)>> {
+    Decoder { data, pos: 0 }.fields(None)
+}
+
+/// The messages of a sequence of length-prefixed messages, if the data is one
+fn split_delimited(data: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut d = Decoder { data, pos: 0 };
+    let mut messages = Vec::new();
+    while d.pos < data.len() {
+        let len = d.varint().ok()?.try_into().ok()?;
+        messages.push(d.bytes(len).ok()?);
+    }
+    Some(messages)
+}
+
+/// The messages of the file, detecting whether it is delimited if not configured
+fn messages<'a>(data: &'a [u8], config: &ProtobufConfig) -> Result<(Vec<&'a [u8]>, bool)> {
+    let delimited = || split_delimited(data).context("invalid length-delimited protobuf messages");
+    match config.delimited {
+        Some(true) => Ok((delimited()?, true)),
+        Some(false) => Ok((vec![data], false)),
+        None => {
+            let single = parse_raw(data).is_ok();
+            match split_delimited(data) {
+                Some(messages)
+                    if (!single || messages.len() > 1)
+                        && messages.iter().all(|m| parse_raw(m).is_ok()) =>
+                {
+                    Ok((messages, true))
+                }
+                _ => Ok((vec![data], false)),
+            }
+        }
+    }
+}
+
+/// Printable text, which is output as a string even if it could be parsed as a message.
+///
+/// Nested messages usually start with a tag below 0x20 (fields 1 - 3), which is not printable
+fn as_text(data: &[u8]) -> Option<&str> {
+    let s = std::str::from_utf8(data).ok()?;
+    let printable = |c: char| !c.is_control() || c == '\n' || c == '\t' || c == '\r';
+    (s.chars().next().is_some_and(|c| !c.is_control()) && s.chars().all(printable)).then_some(s)
+}
+
+/// Like `escape_bytes`, but keeps non-ASCII characters
+fn escape_text(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            c if c.is_ascii() => out.push_str(&escape_bytes(&[c as u8])),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape_bytes(data: &[u8]) -> String {
+    let mut out = String::new();
+    for b in data {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x20..=0x7e => out.push(*b as char),
+            b => out.push_str(&format!("\\{b:03o}")),
+        }
+    }
+    out
+}
+
+/// The fields in the format of `protoc --decode_raw`
+fn write_raw(fields: &[(u64, RawValue)], indent: usize, out: &mut Vec<String>) {
+    let pad = "  ".repeat(indent);
+    for (number, value) in fields {
+        match value {
+            RawValue::Varint(v) => out.push(format!("{pad}{number}: {v}")),
+            RawValue::Fixed64(v) => out.push(format!("{pad}{number}: 0x{v:016x}")),
+            RawValue::Fixed32(v) => out.push(format!("{pad}{number}: 0x{v:08x}")),
+            RawValue::Bytes(b) => {
+                if let Some(text) = as_text(b) {
+                    out.push(format!("{pad}{number}: \"{}\"", escape_text(text)));
+                } else if let Some(nested) = parse_raw(b).ok().filter(|f| !f.is_empty()) {
+                    out.push(format!("{pad}{number} {{"));
+                    write_raw(&nested, indent + 1, out);
+                    out.push(format!("{pad}}}"));
+                } else {
+                    out.push(format!("{pad}{number}: \"{}\"", escape_bytes(b)));
+                }
+            }
+            RawValue::Group(fields) => {
+                out.push(format!("{pad}{number} {{"));
+                write_raw(fields, indent + 1, out);
+                out.push(format!("{pad}}}"));
+            }
+        }
+    }
+}
+
+fn decode_raw(messages: &[&[u8]]) -> Result<Vec<Vec<String>>> {
+    messages
+        .iter()
+        .map(|m| {
+            let mut lines = Vec::new();
+            write_raw(&parse_raw(m)?, 0, &mut lines);
+            Ok(lines)
+        })
+        .collect()
+}
+
+fn append_varint(mut v: u64, out: &mut Vec<u8>) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+/// Decode the messages with `protoc`, as the repeated field of a wrapper message
+async fn decode_with_schema(
+    messages: &[&[u8]],
+    config: &ProtobufConfig,
+    message_type: &str,
+) -> Result<Vec<Vec<String>>> {
+    let dir = tempfile::Builder::new().prefix("rga-protobuf-").tempdir()?;
+    let mut cmd = Command::new("protoc");
+    cmd.arg("-I").arg(dir.path());
+    let mut imports = String::new();
+    for file in &config.proto_files {
+        let file = Path::new(file);
+        let name = file.file_name().context("invalid .proto file name")?;
+        cmd.arg("-I")
+            .arg(file.parent().unwrap_or_else(|| Path::new(".")));
+        imports.push_str(&format!("import \"{}\";\n", name.to_string_lossy()));
+    }
+    for include in &config.include_paths {
+        cmd.arg("-I").arg(include);
+    }
+    let wrapper = format!(
+        "syntax = \"proto2\";\n{imports}message RgaMessages {{ repeated .{} message = 1; }}\n",
+        message_type.trim_start_matches('.')
+    );
+    tokio::fs::write(dir.path().join("rga_messages.proto"), wrapper).await?;
+    cmd.args(["--decode=RgaMessages", "rga_messages.proto"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut input = Vec::new();
+    for m in messages {
+        input.push(0x0a);
+        append_varint(m.len() as u64, &mut input);
+        input.extend_from_slice(m);
+    }
+    debug!("executing {:?}", cmd);
+    let mut child = cmd.spawn().map_err(|e| map_exe_error(e, "protoc", HELP))?;
+    let mut stdin = child.stdin.take().expect("is piped");
+    // write while protoc runs, its output may be larger than the pipe buffer
+    let writer = tokio::spawn(async move { stdin.write_all(&input).await });
+    let output = child.wait_with_output().await?;
+    writer.await??;
+    if !output.status.success() {
+        anyhow::bail!(
+            "protoc failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(split_text_format(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The messages of the text format output of the wrapper, `message { ... }` blocks with the fields indented
+fn split_text_format(text: &str) -> Vec<Vec<String>> {
+    let mut messages = Vec::new();
+    for line in text.lines() {
+        match line {
+            "message {" => messages.push(Vec::new()),
+            "}" => {}
+            line => {
+                if let Some(m) = messages.last_mut() {
+                    m.push(line.strip_prefix("  ").unwrap_or(line).to_string());
+                }
+            }
+        }
+    }
+    messages
+}
+
+#[async_trait]
+impl WritingFileAdapter for ProtobufAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let (messages, delimited) = messages(&data, &config.protobuf)?;
+        let decoded = match &config.protobuf.message_type {
+            Some(message_type) if !config.protobuf.proto_files.is_empty() => {
+                decode_with_schema(&messages, &config.protobuf, message_type).await?
+            }
+            _ => decode_raw(&messages)?,
+        };
+        let mut out = String::new();
+        for (i, lines) in decoded.iter().enumerate() {
+            for line in lines {
+                if delimited {
+                    out.push_str(&format!("{line_prefix}message {}: {line}\n", i + 1));
+                } else {
+                    out.push_str(&format!("{line_prefix}{line}\n"));
+                }
+            }
+        }
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    /// id: 150, name: "Alice", address { city: "Berlin" }, score (fixed32)
+    fn message() -> Vec<u8> {
+        let mut m = vec![0x08, 0x96, 0x01, 0x12, 5];
+        m.extend_from_slice(b"Alice");
+        m.extend([0x1a, 8, 0x0a, 6]);
+        m.extend_from_slice(b"Berlin");
+        m.extend([0x25, 1, 0, 0, 0]);
+        m
+    }
+
+    #[tokio::test]
+    async fn raw() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("user.pb"),
+            Box::pin(std::io::Cursor::new(message())),
+        );
+        let adapter: Box<dyn FileAdapter> = Box::<ProtobufAdapter>::default();
+        let res = adapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:1: 150
+PREFIX:2: \"Alice\"
+PREFIX:3 {
+PREFIX:  1: \"Berlin\"
+PREFIX:}
+PREFIX:4: 0x00000001
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn delimited() -> Result<()> {
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            append_varint(message().len() as u64, &mut data);
+            data.extend(message());
+        }
+        let config = ProtobufConfig::default();
+        let (found, delimited) = messages(&data, &config)?;
+        assert!(delimited);
+        assert_eq!(found, [message(), message()]);
+        let single = message();
+        let (found, delimited) = messages(&single, &config)?;
+        assert!(!delimited);
+        assert_eq!(found.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn text_format() {
+        assert_eq!(
+            split_text_format(
+                "message {\n  id: 1\n  address {\n    city: \"Berlin\"\n  }\n}\nmessage {\n  id: 2\n}\n"
+            ),
+            [
+                vec!["id: 1", "address {", "  city: \"Berlin\"", "}"],
+                vec!["id: 2"]
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use super::custom::map_exe_error;
+use super::sevenzip::archive_on_disk;
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::path::Path;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["pst", "ost"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pst".to_owned(),
+        version: 1,
+        description: "Converts Outlook mailboxes (.pst, .ost) with `readpst` and recurses into the messages, which are searched as mails with a `Folder/Subfolder/123.eml` prefix. Contacts and appointments are searched as vcf and ics files.\nMailboxes within archives are written to a temporary file first."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PstAdapter;
+
+impl PstAdapter {
+    pub fn new() -> PstAdapter {
+        PstAdapter
+    }
+}
+impl GetMetadata for PstAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have readpst (from libpst, package pst-utils) installed.";
+
+/// Sort key that orders the numbered files of readpst numerically (`2.eml` before `10.eml`)
+fn natural_key(name: &str) -> (u64, String) {
+    let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    (name[..digits].parse().unwrap_or(u64::MAX), name.to_string())
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| natural_key(&e.file_name().to_string_lossy()));
+    // the messages of a folder before its subfolders
+    let mut subdirs = Vec::new();
+    for e in entries {
+        if e.file_type()?.is_dir() {
+            subdirs.push(e.path());
+        } else {
+            out.push(e.path());
+        }
+    }
+    for d in subdirs {
+        collect_files(&d, out)?;
+    }
+    Ok(())
+}
+
+/// The files written by readpst, relative to the root folder of the mailbox
+fn message_files(dir: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    // readpst puts everything into a folder named after the mailbox ("Personal Folders", ...)
+    let roots: std::collections::HashSet<_> = files
+        .iter()
+        .filter_map(|f| f.strip_prefix(dir).ok()?.components().next())
+        .collect();
+    let single_root = files.iter().all(|f| f.parent() != Some(dir)) && roots.len() == 1;
+    let base = match roots.into_iter().next() {
+        Some(root) if single_root => dir.join(root),
+        _ => dir.to_path_buf(),
+    };
+    Ok(files
+        .into_iter()
+        .map(|f| {
+            let rel = f
+                .strip_prefix(&base)
+                .expect("below base")
+                .to_string_lossy()
+                .replace('\\', "/");
+            (f, rel)
+        })
+        .collect())
+}
+
+/// Convert the mailbox with readpst, one file per message
+async fn convert(mailbox: &Path, out: &Path) -> Result<()> {
+    let mut cmd = Command::new("readpst");
+    // -e: one file per message with extension, -b: no attachments with the RTF body, -q: quiet
+    cmd.args(["-e", "-b", "-q", "-o"]).arg(out).arg(mailbox);
+    debug!("executing {:?}", cmd);
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "readpst", HELP))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "readpst failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl FileAdapter for PstAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+        } = ai;
+        let (mailbox, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let out = tempfile::Builder::new().prefix("rga-pst-").tempdir()?;
+        convert(&mailbox, out.path()).await?;
+        drop(tmp);
+        let files = message_files(out.path())?;
+        let s = stream! {
+            // keep the converted files until all of them are read
+            let _out = out;
+            for (path, rel) in files {
+                let inp = tokio::fs::File::open(&path).await?;
+                yield Ok(AdaptInfo {
+                    line_prefix: format!("{line_prefix}{rel}: "),
+                    filepath_hint: PathBuf::from(rel),
+                    is_real_file: false,
+                    inp: Box::pin(inp),
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for f in [
+            "Personal Folders/Inbox/2.eml",
+            "Personal Folders/Inbox/10.eml",
+            "Personal Folders/Inbox/Project/1.eml",
+            "Personal Folders/Contacts/1.vcf",
+            "Personal Folders/1.eml",
+        ] {
+            let path = dir.path().join(f);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, "")?;
+        }
+        let files: Vec<String> = message_files(dir.path())?
+            .into_iter()
+            .map(|(_, rel)| rel)
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "1.eml",
+                "Contacts/1.vcf",
+                "Inbox/2.eml",
+                "Inbox/10.eml",
+                "Inbox/Project/1.eml"
+            ]
+        );
+        Ok(())
+    }
+}
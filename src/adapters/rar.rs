@@ -0,0 +1,254 @@
+use super::custom::{map_exe_error, spawn_output};
+use super::sevenzip::{ArchiveEntry, archive_on_disk};
+use super::*;
+use crate::print_bytes;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::path::Path;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["rar", "r00"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "rar".to_owned(),
+        version: 1,
+        description: "Lists a rar archive with `unrar` and recurses down into its contents.\nMulti-volume archives on disk (`x.part1.rar`, `x.part2.rar`, ... or `x.rar`, `x.r00`, ...) are searched as one archive from their first volume."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.rar".to_owned()),
+            FileMatcher::MimeType("application/x-rar".to_owned()),
+            FileMatcher::MimeType("application/x-rar-compressed".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RarAdapter;
+
+impl RarAdapter {
+    pub fn new() -> RarAdapter {
+        RarAdapter
+    }
+}
+impl GetMetadata for RarAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const HELP: &str = "Please make sure you have unrar installed.";
+
+/// Whether a file is a later volume of a multi-volume rar archive, which `unrar` reads together with the first volume
+fn is_later_volume(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    let name = name.to_ascii_lowercase();
+    if let Some(stem) = name.strip_suffix(".rar")
+        && let Some((_, number)) = stem.rsplit_once(".part")
+        && let Ok(number) = number.parse::<usize>()
+    {
+        return number > 1;
+    }
+    // old style volumes x.rar, x.r00, x.r01, ...
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    ext.len() == 3
+        && ext[..1].eq_ignore_ascii_case("r")
+        && ext[1..].bytes().all(|b| b.is_ascii_digit())
+        && path.with_extension("rar").is_file()
+}
+
+/// Parse the technical listing (`unrar lt`) of an archive. Directories are skipped.
+fn parse_listing(listing: &str) -> Vec<ArchiveEntry> {
+    let mut entries = Vec::new();
+    let mut path = None;
+    let mut is_dir = false;
+    let mut size = None;
+    let mut packed_size = None;
+    for line in listing.lines().chain([""]) {
+        // each entry starts with its name
+        let (key, value) = line
+            .split_once(": ")
+            .map(|(k, v)| (k.trim(), v))
+            .unwrap_or((line.trim(), ""));
+        if key == "Name" || line.trim().is_empty() {
+            if let Some(path) = path.take()
+                && !is_dir
+            {
+                entries.push(ArchiveEntry {
+                    path,
+                    size: size.take(),
+                    packed_size: packed_size.take(),
+                    encrypted: false,
+                });
+            }
+            (is_dir, size, packed_size) = (false, None, None);
+        }
+        match key {
+            "Name" => path = Some(value.to_string()),
+            "Type" => is_dir = value.trim() == "Directory",
+            "Size" => size = value.trim().parse().ok(),
+            "Packed size" => packed_size = value.trim().parse().ok(),
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// List the files of an archive with `unrar`
+async fn list(archive: &Path) -> Result<Vec<ArchiveEntry>> {
+    let mut cmd = Command::new("unrar");
+    cmd.args(["lt", "-p-", "--"]).arg(archive);
+    debug!("executing {:?}", cmd);
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "unrar", HELP))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "unrar lt failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_listing(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The command that writes a single file of an archive to stdout
+fn extract_command(archive: &Path, path: &str) -> Command {
+    let mut cmd = Command::new("unrar");
+    cmd.args(["p", "-inul", "-p-", "--"]).arg(archive).arg(path);
+    cmd
+}
+
+#[async_trait]
+impl FileAdapter for RarAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+        } = ai;
+        if is_real_file && is_later_volume(&filepath_hint) {
+            // searched together with the first volume
+            debug!(
+                "skipping later volume of rar archive {}",
+                filepath_hint.display()
+            );
+            return Ok(Box::pin(tokio_stream::empty::<Result<AdaptInfo>>()));
+        }
+        let (archive, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let entries = list(&archive).await?;
+        let s = stream! {
+            // keep the temporary file until all files are extracted
+            let _tmp = tmp;
+            for entry in entries {
+                debug!(
+                    "{}{}|{}: {} ({} packed)",
+                    line_prefix,
+                    filepath_hint.display(),
+                    entry.path,
+                    print_bytes(entry.size.unwrap_or(0) as f64),
+                    print_bytes(entry.packed_size.unwrap_or(0) as f64)
+                );
+                let inp = spawn_output(extract_command(&archive, &entry.path), "unrar", HELP)?;
+                yield Ok(AdaptInfo {
+                    line_prefix: format!("{}{}: ", line_prefix, entry.path),
+                    filepath_hint: PathBuf::from(entry.path),
+                    is_real_file: false,
+                    inp,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn listing() {
+        let listing = "
+UNRAR 6.21 freeware      Copyright (c) 1993-2023 Alexander Roshal
+
+Archive: docs.rar
+Details: RAR 5
+
+        Name: docs
+        Type: Directory
+  Attributes: drwxr-xr-x
+
+        Name: docs/notes: final.txt
+        Type: File
+        Size: 12
+ Packed size: 40
+       Ratio: 333%
+  Attributes: -rw-r--r--
+ Compression: RAR 5.0(v50) -m3 -md=128K
+
+        Name: data.csv
+        Type: File
+        Size: 1024
+ Packed size: 300
+";
+        assert_eq!(
+            parse_listing(listing),
+            [
+                ArchiveEntry {
+                    path: "docs/notes: final.txt".to_string(),
+                    size: Some(12),
+                    packed_size: Some(40),
+                    encrypted: false,
+                },
+                ArchiveEntry {
+                    path: "data.csv".to_string(),
+                    size: Some(1024),
+                    packed_size: Some(300),
+                    encrypted: false,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn later_volumes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["old.rar", "old.r00", "orphan.r00"] {
+            std::fs::write(dir.path().join(name), "")?;
+        }
+        let later = |name: &str| is_later_volume(&dir.path().join(name));
+        assert!(!later("x.part1.rar"));
+        assert!(!later("x.part01.rar"));
+        assert!(later("x.part2.rar"));
+        assert!(!later("old.rar"));
+        assert!(later("old.r00"));
+        assert!(!later("orphan.r00"));
+        Ok(())
+    }
+}
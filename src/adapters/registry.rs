@@ -0,0 +1,504 @@
+use super::binary::{u16_at, u32_at, utf16};
+use super::etl::to_hex;
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["hve"];
+/// hives usually have no extension, so they are matched by their names
+static HIVES: &[&str] = &[
+    "**/config/SYSTEM",
+    "**/config/SOFTWARE",
+    "**/config/SAM",
+    "**/config/SECURITY",
+    "**/config/DEFAULT",
+    "**/config/COMPONENTS",
+    "**/NTUSER.DAT",
+    "**/ntuser.dat",
+    "**/UsrClass.dat",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "registry".to_owned(),
+        version: 1,
+        description: "Reads Windows registry hives (SYSTEM, SOFTWARE, NTUSER.DAT, .hve) and outputs each value as `key\\path\\value = data`. Keys without values are output as their path. Changes still in the transaction logs (.LOG1, .LOG2) of a dirty hive are not applied.\nHives without extension are only found with `--rga-accurate`"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .chain(HIVES.iter().map(|g| FastFileMatcher::PathGlob(g.to_string())))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RegistryAdapter;
+
+impl RegistryAdapter {
+    pub fn new() -> RegistryAdapter {
+        RegistryAdapter
+    }
+}
+
+impl GetMetadata for RegistryAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// The cell offsets are relative to the hive bins, which follow the base block
+const BASE_BLOCK_LEN: usize = 4096;
+/// keys can't be nested deeper than this
+const MAX_DEPTH: usize = 512;
+/// binary values are truncated to this many bytes
+const MAX_BINARY_LEN: usize = 256;
+/// values larger than this are stored in segments (`db` cells) since hive version 1.4
+const MAX_VALUE_SEGMENT: usize = 16344;
+const KEY_COMP_NAME: u16 = 0x0020;
+const VALUE_COMP_NAME: u16 = 0x0001;
+const NO_CELL: u32 = 0xffff_ffff;
+
+const REG_SZ: u32 = 1;
+const REG_EXPAND_SZ: u32 = 2;
+const REG_BINARY: u32 = 3;
+const REG_DWORD: u32 = 4;
+const REG_DWORD_BIG_ENDIAN: u32 = 5;
+const REG_LINK: u32 = 6;
+const REG_MULTI_SZ: u32 = 7;
+const REG_QWORD: u32 = 11;
+
+/// Names are stored as Latin-1 if they only contain such characters
+fn decode_name(b: &[u8], compressed: bool) -> String {
+    if compressed {
+        b.iter().map(|c| *c as char).collect()
+    } else {
+        utf16(b)
+    }
+}
+
+/// A binary value that contains a UTF-16 string, as some applications store them
+fn binary_text(b: &[u8]) -> Option<String> {
+    let text = utf16(b);
+    let text = text.trim_end_matches('\0');
+    let printable =
+        !text.is_empty() && b.len() % 2 == 0 && text.chars().all(|c| !c.is_control() || c == '\t');
+    printable.then(|| text.to_string())
+}
+
+/// Renders the data of a value on one line
+fn render_data(value_type: u32, data: &[u8]) -> String {
+    let int = |n: usize| -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        bytes[..n].copy_from_slice(data.get(..n)?);
+        Some(u64::from_le_bytes(bytes))
+    };
+    match value_type {
+        REG_SZ | REG_EXPAND_SZ | REG_LINK => utf16(data)
+            .trim_end_matches('\0')
+            .replace(['\r', '\n'], " "),
+        REG_MULTI_SZ => utf16(data)
+            .split('\0')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+        REG_DWORD if data.len() >= 4 => {
+            let v = int(4).unwrap();
+            format!("0x{v:08x} ({v})")
+        }
+        REG_DWORD_BIG_ENDIAN if data.len() >= 4 => {
+            let v = u32::from_be_bytes(data[..4].try_into().unwrap());
+            format!("0x{v:08x} ({v})")
+        }
+        REG_QWORD if data.len() >= 8 => {
+            let v = int(8).unwrap();
+            format!("0x{v:016x} ({v})")
+        }
+        _ => {
+            if let Some(text) = binary_text(data).filter(|_| value_type == REG_BINARY) {
+                return text;
+            }
+            if data.len() > MAX_BINARY_LEN {
+                format!(
+                    "{}... ({} bytes)",
+                    to_hex(&data[..MAX_BINARY_LEN]),
+                    data.len()
+                )
+            } else {
+                to_hex(data)
+            }
+        }
+    }
+}
+
+struct Key {
+    name: String,
+    subkeys: u32,
+    subkey_list: u32,
+    values: u32,
+    value_list: u32,
+}
+
+struct Hive {
+    data: Vec<u8>,
+    /// whether large values are stored in segments
+    segmented_values: bool,
+}
+
+impl Hive {
+    /// The key node (`nk` cell) at the offset
+    fn key(&self, offset: u32) -> Result<Key> {
+        let cell = self.cell(offset)?;
+        if !cell.starts_with(b"nk") {
+            anyhow::bail!("invalid key at 0x{offset:x}");
+        }
+        let field = |pos| u32_at(cell, pos).context("truncated key");
+        let flags = u16_at(cell, 2).context("truncated key")?;
+        let name_len = u16_at(cell, 72).context("truncated key")? as usize;
+        Ok(Key {
+            name: decode_name(
+                cell.get(76..76 + name_len).context("truncated key name")?,
+                flags & KEY_COMP_NAME != 0,
+            ),
+            subkeys: field(20)?,
+            subkey_list: field(28)?,
+            values: field(36)?,
+            value_list: field(40)?,
+        })
+    }
+
+    /// The data of the cell at the offset
+    fn cell(&self, offset: u32) -> Result<&[u8]> {
+        let pos = BASE_BLOCK_LEN + offset as usize;
+        let size = u32_at(&self.data, pos).context("cell offset out of range")? as i32;
+        // allocated cells have a negative size
+        let len = size.unsigned_abs() as usize;
+        self.data
+            .get(pos + 4..pos + len.max(4))
+            .with_context(|| format!("invalid cell at 0x{offset:x}"))
+    }
+
+    /// The key nodes listed by a subkey list, which may be split into several lists (`ri`)
+    fn subkeys(&self, list: u32, depth: usize, out: &mut Vec<u32>) -> Result<()> {
+        if depth > 2 {
+            anyhow::bail!("nested subkey index");
+        }
+        let cell = self.cell(list)?;
+        let count = u16_at(cell, 2).context("truncated subkey list")? as usize;
+        let (stride, nested) = match cell.get(..2) {
+            Some(b"lf") | Some(b"lh") => (8, false),
+            Some(b"li") => (4, false),
+            Some(b"ri") => (4, true),
+            _ => anyhow::bail!("unknown subkey list at 0x{list:x}"),
+        };
+        for i in 0..count {
+            let offset = u32_at(cell, 4 + i * stride).context("truncated subkey list")?;
+            if nested {
+                self.subkeys(offset, depth + 1, out)?;
+            } else {
+                out.push(offset);
+            }
+        }
+        Ok(())
+    }
+
+    /// The name and rendered data of a value (`vk` cell)
+    fn value(&self, offset: u32) -> Result<(String, String)> {
+        let cell = self.cell(offset)?;
+        if !cell.starts_with(b"vk") {
+            anyhow::bail!("invalid value at 0x{offset:x}");
+        }
+        let name_len = u16_at(cell, 2).context("truncated value")? as usize;
+        let size = u32_at(cell, 4).context("truncated value")?;
+        let data_offset = u32_at(cell, 8).context("truncated value")?;
+        let value_type = u32_at(cell, 12).context("truncated value")?;
+        let flags = u16_at(cell, 16).context("truncated value")?;
+        let name = match cell
+            .get(20..20 + name_len)
+            .context("truncated value name")?
+        {
+            b"" => "(Default)".to_string(),
+            n => decode_name(n, flags & VALUE_COMP_NAME != 0),
+        };
+        let len = (size & 0x7fff_ffff) as usize;
+        let data = if size & 0x8000_0000 != 0 {
+            // stored in the data offset field
+            data_offset.to_le_bytes()[..len.min(4)].to_vec()
+        } else if len == 0 {
+            Vec::new()
+        } else {
+            let cell = self.cell(data_offset)?;
+            if len > MAX_VALUE_SEGMENT && self.segmented_values && cell.starts_with(b"db") {
+                let segments = u16_at(cell, 2).context("truncated big data")? as usize;
+                let list = self.cell(u32_at(cell, 4).context("truncated big data")?)?;
+                let mut data = Vec::with_capacity(len);
+                for i in 0..segments {
+                    let segment = u32_at(list, i * 4).context("truncated big data list")?;
+                    let segment = self.cell(segment)?;
+                    let n = segment.len().min(MAX_VALUE_SEGMENT).min(len - data.len());
+                    data.extend_from_slice(&segment[..n]);
+                }
+                data
+            } else {
+                cell.get(..len).unwrap_or(cell).to_vec()
+            }
+        };
+        Ok((name, render_data(value_type, &data)))
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for RegistryAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        if !data.starts_with(b"regf") || data.len() < BASE_BLOCK_LEN {
+            anyhow::bail!("not a registry hive");
+        }
+        let minor_version = u32_at(&data, 0x18).unwrap();
+        let root = u32_at(&data, 0x24).unwrap();
+        let hive = Hive {
+            data,
+            segmented_values: minor_version >= 4,
+        };
+        let mut seen = HashSet::new();
+        // the path of the root key is empty, its name is not meaningful (e.g. `CMI-CreateHive{...}`)
+        let mut stack = vec![(root, None::<String>, 0)];
+        let mut out = String::new();
+        while let Some((offset, parent, depth)) = stack.pop() {
+            if depth > MAX_DEPTH || !seen.insert(offset) {
+                continue;
+            }
+            let key = match hive.key(offset) {
+                Ok(key) => key,
+                Err(e) => {
+                    log::debug!("{e:#}");
+                    continue;
+                }
+            };
+            let path = match &parent {
+                None => String::new(),
+                Some(p) if p.is_empty() => key.name.clone(),
+                Some(p) => format!("{p}\\{}", key.name),
+            };
+            let mut values = Vec::new();
+            if key.values > 0 && key.value_list != NO_CELL {
+                let list = hive.cell(key.value_list)?;
+                for i in 0..key.values as usize {
+                    let Some(value) = u32_at(list, i * 4) else {
+                        break;
+                    };
+                    match hive.value(value) {
+                        Ok(v) => values.push(v),
+                        Err(e) => log::debug!("{path}: {e:#}"),
+                    }
+                }
+            }
+            for (name, data) in &values {
+                let sep = if path.is_empty() { "" } else { "\\" };
+                writeln!(out, "{line_prefix}{path}{sep}{name} = {data}").unwrap();
+            }
+            if values.is_empty() && !path.is_empty() {
+                writeln!(out, "{line_prefix}{path}").unwrap();
+            }
+            if key.subkeys > 0 && key.subkey_list != NO_CELL {
+                let mut subkeys = Vec::new();
+                if let Err(e) = hive.subkeys(key.subkey_list, 0, &mut subkeys) {
+                    log::debug!("{path}: {e:#}");
+                }
+                // in reverse, so the subkeys are output in the order of the list (sorted by name)
+                stack.extend(
+                    subkeys
+                        .into_iter()
+                        .rev()
+                        .map(|s| (s, Some(path.clone()), depth + 1)),
+                );
+            }
+            if out.len() > 1 << 16 {
+                oup.write_all(std::mem::take(&mut out).as_bytes()).await?;
+            }
+        }
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    /// Appends a cell to the hive bins, returning its offset
+    fn cell(bins: &mut Vec<u8>, data: &[u8]) -> u32 {
+        let offset = bins.len() as u32;
+        let len = (data.len() + 4).div_ceil(8) * 8;
+        bins.extend((-(len as i32)).to_le_bytes());
+        bins.extend(data);
+        bins.resize(offset as usize + len, 0);
+        offset
+    }
+
+    fn key(bins: &mut Vec<u8>, name: &str, subkeys: &[u32], values: &[u32]) -> u32 {
+        let subkey_list = if subkeys.is_empty() {
+            NO_CELL
+        } else {
+            let mut list = b"lf".to_vec();
+            list.extend((subkeys.len() as u16).to_le_bytes());
+            for s in subkeys {
+                list.extend(s.to_le_bytes());
+                list.extend([0; 4]);
+            }
+            cell(bins, &list)
+        };
+        let value_list = if values.is_empty() {
+            NO_CELL
+        } else {
+            cell(
+                bins,
+                &values
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut nk = vec![0u8; 76];
+        nk[..2].copy_from_slice(b"nk");
+        nk[2..4].copy_from_slice(&KEY_COMP_NAME.to_le_bytes());
+        nk[20..24].copy_from_slice(&(subkeys.len() as u32).to_le_bytes());
+        nk[28..32].copy_from_slice(&subkey_list.to_le_bytes());
+        nk[36..40].copy_from_slice(&(values.len() as u32).to_le_bytes());
+        nk[40..44].copy_from_slice(&value_list.to_le_bytes());
+        nk[72..74].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        nk.extend(name.as_bytes());
+        cell(bins, &nk)
+    }
+
+    fn value(bins: &mut Vec<u8>, name: &str, value_type: u32, data: &[u8]) -> u32 {
+        let (size, offset) = if data.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..data.len()].copy_from_slice(data);
+            (data.len() as u32 | 0x8000_0000, u32::from_le_bytes(inline))
+        } else {
+            (data.len() as u32, cell(bins, data))
+        };
+        let mut vk = b"vk".to_vec();
+        vk.extend((name.len() as u16).to_le_bytes());
+        vk.extend(size.to_le_bytes());
+        vk.extend(offset.to_le_bytes());
+        vk.extend(value_type.to_le_bytes());
+        vk.extend(VALUE_COMP_NAME.to_le_bytes());
+        vk.extend([0, 0]);
+        vk.extend(name.as_bytes());
+        cell(bins, &vk)
+    }
+
+    fn utf16z(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain([0])
+            .flat_map(|u| u.to_le_bytes())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn hive() -> Result<()> {
+        let mut bins = b"hbin".to_vec();
+        bins.resize(32, 0);
+        let values = [
+            value(&mut bins, "", REG_SZ, &utf16z("default")),
+            value(
+                &mut bins,
+                "OneDrive",
+                REG_SZ,
+                &utf16z("C:\\OneDrive.exe /background"),
+            ),
+            value(&mut bins, "Enabled", REG_DWORD, &1u32.to_le_bytes()),
+            value(
+                &mut bins,
+                "Paths",
+                REG_MULTI_SZ,
+                &[utf16z("C:\\a"), utf16z("D:\\b"), utf16z("")].concat(),
+            ),
+            value(
+                &mut bins,
+                "Key",
+                REG_BINARY,
+                &[0xde, 0xad, 0xbe, 0xef, 0x00],
+            ),
+            value(
+                &mut bins,
+                "LastDocument",
+                REG_BINARY,
+                &utf16z("report.docx"),
+            ),
+        ];
+        let run = key(&mut bins, "Run", &[], &values);
+        let empty = key(&mut bins, "Empty", &[], &[]);
+        let software = key(&mut bins, "Software", &[empty, run], &[]);
+        let root = key(
+            &mut bins,
+            "CMI-CreateHive{6A1C4018-979D-4291-A7DC-7AED1C75B67C}",
+            &[software],
+            &[],
+        );
+        bins.resize(4096, 0);
+
+        let mut file = b"regf".to_vec();
+        file.resize(BASE_BLOCK_LEN, 0);
+        file[0x14..0x18].copy_from_slice(&1u32.to_le_bytes());
+        file[0x18..0x1c].copy_from_slice(&5u32.to_le_bytes());
+        file[0x24..0x28].copy_from_slice(&root.to_le_bytes());
+        file.extend(bins);
+        let (a, d) = simple_adapt_info(&PathBuf::from("NTUSER.DAT"), Box::pin(Cursor::new(file)));
+        let buf = adapted_to_vec(loop_adapt(&RegistryAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:Software
+PREFIX:Software\\Empty
+PREFIX:Software\\Run\\(Default) = default
+PREFIX:Software\\Run\\OneDrive = C:\\OneDrive.exe /background
+PREFIX:Software\\Run\\Enabled = 0x00000001 (1)
+PREFIX:Software\\Run\\Paths = C:\\a, D:\\b
+PREFIX:Software\\Run\\Key = deadbeef00
+PREFIX:Software\\Run\\LastDocument = report.docx
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hives_without_extension() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = dir.path().join("Windows/System32/config");
+        std::fs::create_dir_all(&config)?;
+        let software = config.join("SOFTWARE");
+        let exported = dir.path().join("backup.hve");
+        std::fs::write(&software, "")?;
+        std::fs::write(&exported, "")?;
+        let adapter: Arc<dyn FileAdapter> = Arc::new(RegistryAdapter::new());
+        assert!(found_by(adapter.clone(), &exported, false)?);
+        // not selected by the pre-glob, only found with --rga-accurate
+        assert!(!found_by(adapter.clone(), &software, false)?);
+        assert!(found_by(adapter, &software, true)?);
+        Ok(())
+    }
+}
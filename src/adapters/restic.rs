@@ -0,0 +1,232 @@
+use super::custom::spawn_output;
+use super::*;
+use crate::adapted_iter::one_file;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &["restic"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "restic".to_owned(),
+        version: 1,
+        description: "Searches a snapshot of a restic backup repository without restoring it.\nMatches `.restic` files that describe the snapshot, e.g.\n```toml\nrepository = \"/srv/restic-repo\" # anything restic -r accepts\npassword_file = \"/home/me/.config/restic/password\" # relative to the .restic file, or backup.restic_password_command in the rga config, or RESTIC_PASSWORD in the environment\nsnapshot = \"latest\" # default\npath = \"/home/me/Documents\" # default: /\n```\nStreams the snapshot with `restic dump --archive tar` and recurses into the files. The result is cached until the `.restic` file changes, so use a snapshot id instead of `latest` for caching.\nOnly `.restic` files on disk are searched, not ones within archives. Disabled by default, enable it with `--rga-adapters=+restic`."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        disabled_by_default: true,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+
+/// The contents of a `.restic` file
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct ResticSnapshot {
+    /// the repository as given to `restic -r`. Relative paths are relative to the `.restic` file
+    repository: String,
+    /// relative paths are relative to the `.restic` file
+    password_file: Option<String>,
+    #[serde(default = "default_snapshot")]
+    snapshot: String,
+    /// the directory within the snapshot to search
+    #[serde(default = "default_path")]
+    path: String,
+}
+
+fn default_snapshot() -> String {
+    "latest".to_string()
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+impl ResticSnapshot {
+    /// The command that writes the snapshot as tar to stdout. `base` is the directory of the `.restic` file
+    fn dump_command(&self, base: &Path, password_command: Option<&str>) -> Command {
+        // local repositories don't have a `backend:` prefix
+        let repository = if self.repository.contains(':') {
+            self.repository.clone().into()
+        } else {
+            base.join(&self.repository).into_os_string()
+        };
+        let mut cmd = Command::new("restic");
+        cmd.arg("--repo").arg(repository).arg("--quiet");
+        if let Some(password_file) = &self.password_file {
+            cmd.arg("--password-file").arg(base.join(password_file));
+        }
+        // only from the user's config: running commands named by a scanned file is not safe
+        if let Some(password_command) = password_command {
+            cmd.arg("--password-command").arg(password_command);
+        }
+        // the snapshot and path come from the scanned file, they must not be parsed as options
+        cmd.args([
+            "dump",
+            "--archive",
+            "tar",
+            "--",
+            self.snapshot.as_str(),
+            self.path.as_str(),
+        ]);
+        cmd
+    }
+}
+
+#[derive(Default)]
+pub struct ResticAdapter;
+
+impl ResticAdapter {
+    pub fn new() -> ResticAdapter {
+        ResticAdapter
+    }
+}
+impl GetMetadata for ResticAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for ResticAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            is_real_file,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+        } = ai;
+        if !is_real_file {
+            // don't access repositories named by files of downloaded archives
+            anyhow::bail!("restic files are only searched on disk, not within archives");
+        }
+        let mut descriptor = String::new();
+        inp.read_to_string(&mut descriptor).await?;
+        let snapshot: ResticSnapshot = toml::from_str(&descriptor)
+            .with_context(|| format!("invalid restic file {}", filepath_hint.display()))?;
+        let base = filepath_hint.parent().unwrap_or(Path::new(""));
+        let cmd = snapshot.dump_command(base, config.backup.restic_password_command.as_deref());
+        debug!("executing {:?}", cmd);
+        let output = spawn_output(cmd, "restic", "Please make sure you have restic installed.")?;
+        Ok(one_file(AdaptInfo {
+            filepath_hint: filepath_hint.with_extension("restic.tar"),
+            inp: output,
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+            passwords,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::simple_adapt_info;
+    use pretty_assertions::assert_eq;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn dump_command() -> Result<()> {
+        let snapshot: ResticSnapshot = toml::from_str(
+            r#"
+            repository = "repo"
+            password_file = "/etc/restic-pw"
+            snapshot = "4bba301e"
+            "#,
+        )?;
+        let cmd = snapshot.dump_command(Path::new("/backups"), None);
+        let args: Vec<&OsStr> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--repo",
+                "/backups/repo",
+                "--quiet",
+                "--password-file",
+                "/etc/restic-pw",
+                "dump",
+                "--archive",
+                "tar",
+                "--",
+                "4bba301e",
+                "/"
+            ]
+        );
+        let remote: ResticSnapshot = toml::from_str(r#"repository = "sftp:host:/repo""#)?;
+        let cmd = remote.dump_command(Path::new("/backups"), Some("pass show restic"));
+        let args: Vec<&OsStr> = cmd.as_std().get_args().collect();
+        assert_eq!(args[1], "sftp:host:/repo");
+        assert_eq!(args[3..5], ["--password-command", "pass show restic"]);
+        Ok(())
+    }
+
+    #[test]
+    fn options_in_descriptor() -> Result<()> {
+        let snapshot: ResticSnapshot = toml::from_str(
+            r#"
+            repository = "repo"
+            password_file = "pw"
+            snapshot = "--password-command=touch /tmp/pwned"
+            "#,
+        )?;
+        let cmd = snapshot.dump_command(Path::new("/backups"), None);
+        let args: Vec<&OsStr> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args[3..],
+            [
+                "--password-file",
+                "/backups/pw",
+                "dump",
+                "--archive",
+                "tar",
+                "--",
+                "--password-command=touch /tmp/pwned",
+                "/"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_password_command_in_descriptor() {
+        let res = toml::from_str::<ResticSnapshot>(
+            r#"
+            repository = "repo"
+            password_command = "touch /tmp/pwned"
+            "#,
+        );
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn only_on_disk() -> Result<()> {
+        let descriptor = "repository = \"repo\"";
+        let (a, d) = simple_adapt_info(
+            Path::new("backup.restic"),
+            Box::pin(std::io::Cursor::new(descriptor)),
+        );
+        assert!(ResticAdapter::new().adapt(a, &d).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+use super::binary::u32_be;
+use super::decompress::decompress_any;
+use super::*;
+use crate::adapted_iter::{MemberHeaders, SequentialMember, sequential_members, skip_bytes};
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["rpm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "rpm".to_owned(),
+        version: 1,
+        description: "Reads RPM packages. Outputs the package metadata (name, version, summary, description, dependencies, ...) prefixed with `header: ` and recurses into the files of the payload"
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-rpm".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RpmAdapter;
+
+impl RpmAdapter {
+    pub fn new() -> RpmAdapter {
+        RpmAdapter
+    }
+}
+impl GetMetadata for RpmAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const LEAD_MAGIC: &[u8] = b"\xed\xab\xee\xdb";
+const LEAD_SIZE: usize = 96;
+const HEADER_MAGIC: &[u8] = b"\x8e\xad\xe8\x01";
+/// headers larger than this are considered invalid
+const MAX_HEADER_SIZE: usize = 256 * 1024 * 1024;
+
+/// Tags of the main header that are output, with their label
+static TAGS: &[(u32, &str)] = &[
+    (1000, "Name"),
+    (1001, "Version"),
+    (1002, "Release"),
+    (1022, "Architecture"),
+    (1004, "Summary"),
+    (1005, "Description"),
+    (1014, "License"),
+    (1016, "Group"),
+    (1020, "URL"),
+    (1011, "Vendor"),
+    (1015, "Packager"),
+    (1010, "Distribution"),
+    (1007, "Build Host"),
+    (1044, "Source RPM"),
+    (1047, "Provides"),
+    (1049, "Requires"),
+];
+const TAG_PAYLOAD_COMPRESSOR: u32 = 1125;
+
+/// A header structure (signature or main header) of an RPM package
+struct RpmHeader {
+    /// (tag, type, offset, count)
+    entries: Vec<(u32, u32, usize, usize)>,
+    store: Vec<u8>,
+}
+
+impl RpmHeader {
+    /// Read a header structure. The signature header is padded to 8 bytes
+    async fn read(inp: &mut ReadBox, pad: bool) -> Result<RpmHeader> {
+        let mut intro = [0u8; 16];
+        inp.read_exact(&mut intro).await?;
+        anyhow::ensure!(&intro[..4] == HEADER_MAGIC, "invalid rpm header");
+        let be = |b: &[u8], i: usize| u32_be(b, i).context("truncated rpm header");
+        let (count, store_size) = (be(&intro, 8)? as usize, be(&intro, 12)? as usize);
+        let size = count * 16 + store_size;
+        anyhow::ensure!(size <= MAX_HEADER_SIZE, "rpm header too large");
+        let mut data = vec![0u8; size];
+        inp.read_exact(&mut data).await?;
+        if pad {
+            skip_bytes(inp, ((8 - size % 8) % 8) as u64).await?;
+        }
+        let store = data.split_off(count * 16);
+        let entries = data
+            .chunks_exact(16)
+            .map(|e| {
+                Ok((
+                    be(e, 0)?,
+                    be(e, 4)?,
+                    be(e, 8)? as usize,
+                    be(e, 12)? as usize,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(RpmHeader { entries, store })
+    }
+
+    /// The strings of a tag of type STRING, STRING_ARRAY or I18NSTRING
+    fn strings(&self, tag: u32) -> Vec<String> {
+        let Some(&(_, kind, offset, count)) = self.entries.iter().find(|e| e.0 == tag) else {
+            return Vec::new();
+        };
+        let count = match kind {
+            // STRING
+            6 => 1,
+            // STRING_ARRAY
+            8 => count,
+            // I18NSTRING: the translations, only the first (C locale) is used
+            9 => 1,
+            _ => return Vec::new(),
+        };
+        self.store
+            .get(offset..)
+            .unwrap_or_default()
+            .split(|b| *b == 0)
+            .take(count)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect()
+    }
+
+    /// The metadata of the package as `Label: value` lines
+    fn text(&self) -> String {
+        let mut lines = Vec::new();
+        for (tag, label) in TAGS {
+            let values = self.strings(*tag);
+            if !values.is_empty() {
+                lines.push(format!("{label}: {}", values.join(", ")));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// Reads the headers of a cpio archive in the "new ASCII" format, as used for the payload of RPM packages
+struct CpioHeaders;
+
+#[async_trait]
+impl MemberHeaders for CpioHeaders {
+    async fn next_member(&mut self, inp: &mut ReadBox) -> Result<Option<SequentialMember>> {
+        loop {
+            let mut header = [0u8; 110];
+            inp.read_exact(&mut header).await?;
+            // 070702 is the variant with checksums
+            anyhow::ensure!(
+                &header[..6] == b"070701" || &header[..6] == b"070702",
+                "unsupported cpio format"
+            );
+            let field = |i: usize| {
+                std::str::from_utf8(&header[6 + i * 8..14 + i * 8])
+                    .ok()
+                    .and_then(|f| u64::from_str_radix(f, 16).ok())
+                    .context("invalid cpio header")
+            };
+            let mode = field(1)?;
+            let size = field(6)?;
+            let name_size = field(11)?;
+            anyhow::ensure!(name_size <= 64 * 1024, "invalid cpio header");
+            let mut name = vec![0u8; name_size as usize];
+            inp.read_exact(&mut name).await?;
+            let name =
+                String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(&name)).into_owned();
+            // the name and the data are aligned to 4 bytes
+            skip_bytes(inp, (4 - (110 + name_size) % 4) % 4).await?;
+            let padding = (4 - size % 4) % 4;
+            if name == "TRAILER!!!" {
+                return Ok(None);
+            }
+            // regular files only, symlinks store their target as data
+            if mode & 0o170000 != 0o100000 {
+                skip_bytes(inp, size + padding).await?;
+                continue;
+            }
+            return Ok(Some(SequentialMember {
+                path: name,
+                size,
+                padding,
+            }));
+        }
+    }
+}
+
+#[async_trait]
+impl FileAdapter for RpmAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            mut inp,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+            ..
+        } = ai;
+        let mut lead = [0u8; LEAD_SIZE];
+        inp.read_exact(&mut lead).await?;
+        anyhow::ensure!(lead.starts_with(LEAD_MAGIC), "not an rpm package");
+        RpmHeader::read(&mut inp, true).await?;
+        let header = RpmHeader::read(&mut inp, false).await?;
+        let compressor = header
+            .strings(TAG_PAYLOAD_COMPRESSOR)
+            .pop()
+            .unwrap_or_else(|| "gzip".to_string());
+        let payload = match compressor.as_str() {
+            "identity" => inp,
+            compressor => {
+                let ext = match compressor {
+                    "gzip" => "gz",
+                    "bzip2" => "bz2",
+                    "zstd" => "zst",
+                    other => other,
+                };
+                decompress_any(&FastFileMatcher::FileExtension(ext.to_string()).into(), inp)
+                    .with_context(|| format!("unsupported rpm payload compression {compressor}"))?
+            }
+        };
+        let metadata = header.text();
+        let members = sequential_members(payload, CpioHeaders);
+        let s = stream! {
+            yield Ok(AdaptInfo {
+                line_prefix: format!("{line_prefix}header: "),
+                filepath_hint: PathBuf::from("header.txt"),
+                is_real_file: false,
+                inp: Box::pin(Cursor::new(metadata)),
+                archive_recursion_depth: archive_recursion_depth + 1,
+                postprocess,
+                config: config.clone(),
+                passwords: passwords.clone(),
+            });
+            for await member in members {
+                let (path, inp) = member?;
+                yield Ok(AdaptInfo {
+                    line_prefix: format!("{line_prefix}{path}: "),
+                    filepath_hint: PathBuf::from(path),
+                    is_real_file: false,
+                    inp,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                    passwords: passwords.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    fn header(entries: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut store = Vec::new();
+        for (tag, kind, data) in entries {
+            let count = match kind {
+                8 => data.iter().filter(|b| **b == 0).count(),
+                _ => 1,
+            };
+            for v in [*tag, *kind, store.len() as u32, count as u32] {
+                index.extend_from_slice(&v.to_be_bytes());
+            }
+            store.extend_from_slice(data);
+        }
+        let mut out = HEADER_MAGIC.to_vec();
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        out.extend_from_slice(&(store.len() as u32).to_be_bytes());
+        out.extend(index);
+        out.extend(store);
+        out
+    }
+
+    fn cpio_entry(name: &str, mode: u32, data: &[u8]) -> Vec<u8> {
+        let name_size = name.len() + 1;
+        let mut out = format!(
+            "070701{:08x}{mode:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{name_size:08x}{:08x}{name}\0",
+            1, 0, 0, 1, 0, data.len(), 0, 0, 0, 0, 0
+        )
+        .into_bytes();
+        out.resize(out.len().div_ceil(4) * 4, 0);
+        out.extend_from_slice(data);
+        out.resize(out.len().div_ceil(4) * 4, 0);
+        out
+    }
+
+    #[tokio::test]
+    async fn package() -> Result<()> {
+        let mut rpm = LEAD_MAGIC.to_vec();
+        rpm.resize(LEAD_SIZE, 0);
+        let mut signature = header(&[(1000, 7, b"\x01\x02\x03")]);
+        signature.resize(signature.len().div_ceil(8) * 8, 0);
+        rpm.extend(signature);
+        rpm.extend(header(&[
+            (1000, 6, b"hello\0"),
+            (1001, 6, b"1.0\0"),
+            (1004, 9, b"Says hello\0"),
+            (1049, 8, b"libc.so.6\0bash\0"),
+            (TAG_PAYLOAD_COMPRESSOR, 6, b"identity\0"),
+        ]));
+        rpm.extend(cpio_entry("./usr/share/hello", 0o40755, b""));
+        rpm.extend(cpio_entry(
+            "./usr/share/hello/greeting.txt",
+            0o100644,
+            b"hi there\n",
+        ));
+        rpm.extend(cpio_entry(
+            "./usr/share/hello/link",
+            0o120777,
+            b"greeting.txt",
+        ));
+        rpm.extend(cpio_entry("TRAILER!!!", 0, b""));
+
+        let (a, d) = simple_adapt_info(&PathBuf::from("hello.rpm"), Box::pin(Cursor::new(rpm)));
+        let buf = adapted_to_vec(loop_adapt(&RpmAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:header: Name: hello
+PREFIX:header: Version: 1.0
+PREFIX:header: Summary: Says hello
+PREFIX:header: Requires: libc.so.6, bash
+PREFIX:./usr/share/hello/greeting.txt: hi there
+PREFIX:./usr/share/hello/greeting.txt: 
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use super::writing::WritingFileAdapter;
+use super::*;
+use anyhow::Result;
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["rtf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "rtf".to_owned(),
+        version: 1,
+        description: "Extracts the plain text of RTF documents".to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("text/rtf".to_owned()),
+            FileMatcher::MimeType("application/rtf".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RtfAdapter;
+
+impl RtfAdapter {
+    pub fn new() -> RtfAdapter {
+        RtfAdapter
+    }
+}
+
+impl GetMetadata for RtfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Destinations (groups starting with these control words) that don't contain document text
+const SKIPPED_DESTINATIONS: &[&str] = &[
+    "colortbl",
+    "datastore",
+    "fldinst",
+    "fonttbl",
+    "generator",
+    "info",
+    "latentstyles",
+    "listoverridetable",
+    "listtable",
+    "mmathPr",
+    "nonshppict",
+    "objdata",
+    "pict",
+    "revtbl",
+    "rsidtbl",
+    "stylesheet",
+    "themedata",
+    "colorschememapping",
+    "xmlnstbl",
+];
+
+#[derive(Clone)]
+struct GroupState {
+    /// inside a destination without text
+    skip: bool,
+    /// number of fallback characters after a \u character (\uc)
+    unicode_skip: usize,
+}
+
+struct RtfText {
+    out: String,
+    /// bytes of the document code page that are not decoded yet
+    pending: Vec<u8>,
+    encoding: &'static Encoding,
+}
+
+impl RtfText {
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            let (text, _) = self.encoding.decode_without_bom_handling(&self.pending);
+            self.out.push_str(&text);
+            self.pending.clear();
+        }
+    }
+
+    fn push(&mut self, s: &str) {
+        self.flush();
+        self.out.push_str(s);
+    }
+}
+
+/// Extract the text of an RTF document, with one line per paragraph
+pub fn rtf_to_text(data: &[u8]) -> String {
+    let mut text = RtfText {
+        out: String::new(),
+        pending: Vec::new(),
+        encoding: encoding_rs::WINDOWS_1252,
+    };
+    let mut stack: Vec<GroupState> = Vec::new();
+    let mut state = GroupState {
+        skip: false,
+        unicode_skip: 1,
+    };
+    // fallback characters left to skip after a \u character
+    let mut skip_chars = 0;
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'{' => {
+                stack.push(state.clone());
+                i += 1;
+            }
+            b'}' => {
+                if let Some(s) = stack.pop() {
+                    state = s;
+                }
+                skip_chars = 0;
+                i += 1;
+            }
+            b'\r' | b'\n' => i += 1,
+            b'\\' => {
+                let Some(&c) = data.get(i + 1) else {
+                    break;
+                };
+                i += 2;
+                if c.is_ascii_alphabetic() {
+                    let start = i - 1;
+                    while i < data.len() && data[i].is_ascii_alphabetic() {
+                        i += 1;
+                    }
+                    let word = std::str::from_utf8(&data[start..i]).unwrap_or_default();
+                    let param_start = i;
+                    if i < data.len() && data[i] == b'-' {
+                        i += 1;
+                    }
+                    while i < data.len() && data[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                    let param: Option<i64> = std::str::from_utf8(&data[param_start..i])
+                        .ok()
+                        .and_then(|p| p.parse().ok());
+                    // a space after a control word belongs to it
+                    if i < data.len() && data[i] == b' ' {
+                        i += 1;
+                    }
+                    if SKIPPED_DESTINATIONS.contains(&word) {
+                        state.skip = true;
+                        continue;
+                    }
+                    match word {
+                        "bin" => {
+                            // binary data
+                            i += param.unwrap_or(0).max(0) as usize;
+                            continue;
+                        }
+                        "ansicpg" => {
+                            if let Some(encoding) = param.and_then(|p| {
+                                Encoding::for_label(format!("windows-{p}").as_bytes())
+                            }) {
+                                text.encoding = encoding;
+                            }
+                            continue;
+                        }
+                        "uc" => {
+                            state.unicode_skip = param.unwrap_or(1).max(0) as usize;
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    if state.skip {
+                        continue;
+                    }
+                    let s = match word {
+                        "par" | "line" | "sect" | "page" | "row" => "\n",
+                        "tab" | "cell" => "\t",
+                        "emdash" => "—",
+                        "endash" => "–",
+                        "bullet" => "•",
+                        "lquote" => "‘",
+                        "rquote" => "’",
+                        "ldblquote" => "“",
+                        "rdblquote" => "”",
+                        "emspace" | "enspace" | "qmspace" => " ",
+                        "u" => {
+                            // negative for code points above 32767
+                            let code = param.unwrap_or(0);
+                            let code = if code < 0 { code + 65536 } else { code };
+                            if let Some(c) = char::from_u32(code as u32) {
+                                text.push(c.encode_utf8(&mut [0; 4]));
+                            }
+                            skip_chars = state.unicode_skip;
+                            continue;
+                        }
+                        _ => continue,
+                    };
+                    skip_chars = 0;
+                    text.push(s);
+                } else {
+                    match c {
+                        b'\'' => {
+                            let byte = data
+                                .get(i..i + 2)
+                                .and_then(|h| std::str::from_utf8(h).ok())
+                                .and_then(|h| u8::from_str_radix(h, 16).ok());
+                            i += 2;
+                            if state.skip {
+                                continue;
+                            }
+                            if skip_chars > 0 {
+                                skip_chars -= 1;
+                            } else if let Some(byte) = byte {
+                                text.pending.push(byte);
+                            }
+                        }
+                        // ignorable destination
+                        b'*' => state.skip = true,
+                        _ if state.skip => {}
+                        b'~' => text.push("\u{a0}"),
+                        b'_' => text.push("-"),
+                        b'\\' | b'{' | b'}' => text.pending.push(c),
+                        b'\r' | b'\n' => text.push("\n"),
+                        // optional hyphen and others
+                        _ => {}
+                    }
+                }
+            }
+            b => {
+                i += 1;
+                if state.skip {
+                    continue;
+                }
+                if skip_chars > 0 {
+                    skip_chars -= 1;
+                } else {
+                    text.pending.push(b);
+                }
+            }
+        }
+    }
+    text.flush();
+    text.out
+}
+
+#[async_trait]
+impl WritingFileAdapter for RtfAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut out = String::new();
+        for line in rtf_to_text(&data).lines() {
+            let line = line.trim_end();
+            if !line.is_empty() {
+                out.push_str(&format!("{line_prefix}{line}\n"));
+            }
+        }
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn text() -> Result<()> {
+        let rtf = br#"{\rtf1\ansi\ansicpg1252\deff0{\fonttbl{\f0\fswiss Helvetica;}}{\colortbl;\red255\green0\blue0;}
+{\*\generator Riched20 10.0;}{\info{\title Secret title}}\viewkind4\uc1
+\pard\f0\fs24 Hello \b world\b0 !\par
+Caf\'e9 \u8364?5 and \{braces\}\tab tabbed\line
+{\*\bkmkstart x}{\field{\*\fldinst HYPERLINK "http://example.com"}{\fldrslt link text}}\par
+{\pict\pngblip 89504e47}\par
+}"#;
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("doc.rtf"),
+            Box::pin(Cursor::new(rtf.to_vec())),
+        );
+        let buf = adapted_to_vec(loop_adapt(&RtfAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:Hello world!\nPREFIX:Café €5 and {braces}\ttabbed\nPREFIX:link text\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,524 @@
+use super::custom::{map_exe_error, spawn_output};
+use super::*;
+use crate::passwords::{UsedPassword, archive_password_candidates, prompt_password};
+use crate::print_bytes;
+
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::path::Path;
+use tokio::process::Command;
+
+/// cab and msi (Windows installers, compound files), macOS disk images and their file systems are read with 7z as well.
+/// p7zip 16.02 reads HFS+, APFS needs 7-Zip 21.07 or newer.
+static EXTENSIONS: &[&str] = &["7z", "cab", "msi", "dmg", "hfs", "hfsx", "apfs"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "7z".to_owned(),
+        version: 4,
+        description: "Lists a 7z archive, Microsoft cabinet (cab), Windows installer (msi) or macOS disk image (dmg) with `7z` and recurses down into its contents (the files of cab files, the streams of msi files, the HFS+ / APFS partitions of disk images and their files), extracting one file at a time to stdout.\nAPFS needs 7-Zip 21.07 or newer, older versions (like p7zip 16.02) only read HFS+.\nEncrypted archives are opened with the passwords of `--rga-archive-password` and `--rga-password-file`.\nArchives within other archives are written to a temporary file first, since 7z needs to seek."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-7z-compressed".to_owned()),
+            FileMatcher::MimeType("application/vnd.ms-cab-compressed".to_owned()),
+            FileMatcher::MimeType("application/x-msi".to_owned()),
+            FileMatcher::MimeType("application/x-apple-diskimage".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SevenZipAdapter;
+
+impl SevenZipAdapter {
+    pub fn new() -> SevenZipAdapter {
+        SevenZipAdapter
+    }
+}
+impl GetMetadata for SevenZipAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+pub const HELP: &str = "Please make sure you have 7-Zip (7z) installed.";
+
+/// A file in an archive, as listed by `7z l -slt`
+#[derive(Debug, PartialEq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub size: Option<u64>,
+    pub packed_size: Option<u64>,
+    pub encrypted: bool,
+}
+
+/// Parse the technical listing (`7z l -slt -ba`) of an archive. Directories are skipped.
+pub fn parse_listing(listing: &str) -> Vec<ArchiveEntry> {
+    let mut entries = Vec::new();
+    let mut path = None;
+    let mut is_dir = false;
+    let mut size = None;
+    let mut packed_size = None;
+    let mut encrypted = false;
+    // entries are separated by empty lines
+    for line in listing.lines().chain([""]) {
+        if line.trim().is_empty() {
+            if let Some(path) = path.take()
+                && !is_dir
+            {
+                entries.push(ArchiveEntry {
+                    path,
+                    size: size.take(),
+                    packed_size: packed_size.take(),
+                    encrypted,
+                });
+            }
+            (is_dir, size, packed_size, encrypted) = (false, None, None, false);
+            continue;
+        }
+        let Some((key, value)) = line.split_once(" =") else {
+            continue;
+        };
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match key {
+            "Path" => path = Some(value.to_string()),
+            "Folder" => is_dir = value == "+",
+            // e.g. `D....` or `D_ drwxr-xr-x` for directories
+            "Attributes" => is_dir |= value.starts_with('D'),
+            "Size" => size = value.parse().ok(),
+            "Packed Size" => packed_size = value.parse().ok(),
+            "Encrypted" => encrypted = value == "+",
+            _ => {}
+        }
+    }
+    entries
+}
+
+/// Get a path of the archive that `7z` can open, copying it to a temporary file if it is within another archive.
+///
+/// The temporary file is deleted when dropped.
+pub async fn archive_on_disk(
+    mut inp: ReadBox,
+    filepath_hint: &Path,
+    is_real_file: bool,
+) -> Result<(PathBuf, Option<tempfile::TempPath>)> {
+    if is_real_file {
+        return Ok((filepath_hint.to_path_buf(), None));
+    }
+    let suffix = filepath_hint
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let tmp = tempfile::Builder::new()
+        .prefix("rga-")
+        .suffix(&suffix)
+        .tempfile()?
+        .into_temp_path();
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    tokio::io::copy(&mut inp, &mut file).await?;
+    Ok((tmp.to_path_buf(), Some(tmp)))
+}
+
+/// Switches for reading an archive with 7z
+#[derive(Debug, Default, Clone)]
+pub struct Switches {
+    /// Without password, 7z fails on encrypted archives since its stdin is not a terminal.
+    /// 7z has no other way to take it than `-p`, so it is visible in the process list
+    pub password: Option<String>,
+    /// The code page of the names of zip entries that are not marked as UTF-8
+    pub code_page: Option<u16>,
+}
+
+impl Switches {
+    fn add_to(&self, cmd: &mut Command) {
+        if let Some(code_page) = self.code_page {
+            cmd.arg(format!("-mcp={code_page}"));
+        }
+        if let Some(password) = &self.password {
+            cmd.arg(format!("-p{password}"));
+        }
+    }
+}
+
+/// List the files of an archive with `7z`
+pub async fn list(archive: &Path, switches: &Switches) -> Result<Vec<ArchiveEntry>> {
+    let mut cmd = Command::new("7z");
+    cmd.args(["l", "-slt", "-ba"]);
+    // not logged with the password
+    debug!("executing {:?} -- {}", cmd, archive.display());
+    switches.add_to(&mut cmd);
+    cmd.arg("--").arg(archive);
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "7z", HELP))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "7z l failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_listing(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The command that writes a single file of an archive to stdout
+pub fn extract_command(archive: &Path, path: &str, switches: &Switches) -> Command {
+    let mut cmd = Command::new("7z");
+    // -spd: file names are not wildcards
+    cmd.args(["x", "-so", "-spd"]);
+    switches.add_to(&mut cmd);
+    cmd.arg("--").arg(archive).arg(path);
+    cmd
+}
+
+/// Check a password by testing the smallest encrypted file (`7z t`), or by listing the archive if its file names are encrypted too.
+async fn password_works(
+    archive: &Path,
+    entries: Option<&[ArchiveEntry]>,
+    switches: &Switches,
+) -> bool {
+    let Some(entries) = entries else {
+        return list(archive, switches).await.is_ok();
+    };
+    let Some(entry) = entries
+        .iter()
+        .filter(|e| e.encrypted)
+        .min_by_key(|e| e.size.unwrap_or(u64::MAX))
+    else {
+        return true;
+    };
+    let mut cmd = Command::new("7z");
+    cmd.args(["t", "-spd"]);
+    switches.add_to(&mut cmd);
+    cmd.arg("--").arg(archive).arg(&entry.path);
+    cmd.output().await.is_ok_and(|o| o.status.success())
+}
+
+/// List an archive that might be encrypted, and find its password: one of the candidates
+/// (see `archive_password_candidates`), or one entered on the terminal with `--rga-archive-password-prompt`.
+///
+/// `name` is the path of the archive including the line prefix, under which the used password is recorded.
+pub async fn list_encrypted(
+    archive: &Path,
+    name: &str,
+    code_page: Option<u16>,
+    config: &RgaConfig,
+    passwords: &PasswordState,
+) -> Result<(Vec<ArchiveEntry>, Switches)> {
+    let mut switches = Switches {
+        password: None,
+        code_page,
+    };
+    let listed = match list(archive, &switches).await {
+        Ok(entries) if !entries.iter().any(|e| e.encrypted) => return Ok((entries, switches)),
+        Ok(entries) => Some(entries),
+        // if the file names are encrypted, the archive can't be listed without password:
+        // "Cannot open encrypted archive. Wrong password?"
+        Err(e) if format!("{e:#}").contains("encrypted archive") => None,
+        Err(e) => return Err(e),
+    };
+    let candidates = archive_password_candidates(config, passwords)?;
+    let mut found = None;
+    for (i, password) in candidates.into_iter().enumerate() {
+        switches.password = Some(password);
+        if password_works(archive, listed.as_deref(), &switches).await {
+            found = Some(UsedPassword::Candidate(i + 1));
+            break;
+        }
+    }
+    if found.is_none() && config.archive_password_prompt {
+        // a few attempts for typos
+        for _ in 0..3 {
+            let what = name.to_string();
+            let Some(password) =
+                tokio::task::spawn_blocking(move || prompt_password(&what)).await??
+            else {
+                break;
+            };
+            passwords.add_prompted(password.clone());
+            switches.password = Some(password);
+            if password_works(archive, listed.as_deref(), &switches).await {
+                found = Some(UsedPassword::Prompt);
+                break;
+            }
+        }
+    }
+    let Some(used) = found else {
+        anyhow::bail!(
+            "{name} is encrypted and none of the given passwords worked (see --rga-archive-password, --rga-password-file and --rga-archive-password-prompt)"
+        );
+    };
+    debug!("password worked for {name}");
+    passwords.record_used(name, used);
+    let entries = match listed {
+        Some(entries) => entries,
+        None => list(archive, &switches).await?,
+    };
+    Ok((entries, switches))
+}
+
+/// Recurse into the files of an archive on disk with `7z`, one at a time.
+/// Also used for zip files that are encrypted or have names that are not UTF-8 (in the given code page).
+#[allow(clippy::too_many_arguments)]
+pub async fn adapt_archive(
+    archive: PathBuf,
+    tmp: Option<tempfile::TempPath>,
+    code_page: Option<u16>,
+    filepath_hint: &Path,
+    line_prefix: String,
+    archive_recursion_depth: i32,
+    postprocess: bool,
+    config: RgaConfig,
+    passwords: PasswordState,
+) -> Result<AdaptedFilesIterBox> {
+    let (entries, switches) = list_encrypted(
+        &archive,
+        &format!("{}{}", line_prefix, filepath_hint.display()),
+        code_page,
+        &config,
+        &passwords,
+    )
+    .await?;
+    let filepath_hint = filepath_hint.to_path_buf();
+    let s = stream! {
+        // keep the temporary file until all files are extracted
+        let _tmp = tmp;
+        for entry in entries {
+            debug!(
+                "{}{}|{}: {} ({} packed)",
+                line_prefix,
+                filepath_hint.display(),
+                entry.path,
+                print_bytes(entry.size.unwrap_or(0) as f64),
+                print_bytes(entry.packed_size.unwrap_or(0) as f64)
+            );
+            let inp = spawn_output(
+                extract_command(&archive, &entry.path, &switches),
+                "7z",
+                HELP,
+            )?;
+            yield Ok(AdaptInfo {
+                line_prefix: format!("{}{}: ", line_prefix, entry.path),
+                filepath_hint: PathBuf::from(entry.path),
+                is_real_file: false,
+                inp,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                postprocess,
+                config: config.clone(),
+                passwords: passwords.clone(),
+            });
+        }
+    };
+    Ok(Box::pin(s))
+}
+
+#[async_trait]
+impl FileAdapter for SevenZipAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            archive_recursion_depth,
+            postprocess,
+            line_prefix,
+            config,
+            passwords,
+        } = ai;
+        let (archive, tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        adapt_archive(
+            archive,
+            tmp,
+            None,
+            &filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    /// The tests that run 7z are skipped if it is not installed
+    fn has_7z() -> bool {
+        let installed = std::process::Command::new("7z").output().is_ok();
+        if !installed {
+            eprintln!("7z not installed, skipping test");
+        }
+        installed
+    }
+
+    /// Adapt the file with the 7z adapter, returns the contents of its files
+    async fn adapt_file(path: &Path) -> Result<String> {
+        let (a, d) = simple_fs_adapt_info(path).await?;
+        let files = SevenZipAdapter::new().adapt(a, &d).await?;
+        Ok(String::from_utf8(adapted_to_vec(files).await?)?)
+    }
+
+    /// An uncompressed cabinet with one file
+    fn create_cab(name: &str, data: &[u8]) -> Vec<u8> {
+        let header_len = 36;
+        let folder_len = 8;
+        let file_len = 16 + name.len() + 1;
+        let data_start = header_len + folder_len + file_len;
+        let total = data_start + 8 + data.len();
+        let mut cab = Vec::new();
+        cab.extend(b"MSCF");
+        cab.extend(0u32.to_le_bytes());
+        cab.extend((total as u32).to_le_bytes());
+        cab.extend(0u32.to_le_bytes());
+        // offset of the first file entry
+        cab.extend(((header_len + folder_len) as u32).to_le_bytes());
+        cab.extend(0u32.to_le_bytes());
+        // version 1.3, one folder, one file, no flags, set id, cabinet number
+        cab.extend([3, 1]);
+        for v in [1u16, 1, 0, 0, 0] {
+            cab.extend(v.to_le_bytes());
+        }
+        // folder: offset of its data block, one data block, no compression
+        cab.extend((data_start as u32).to_le_bytes());
+        cab.extend(1u16.to_le_bytes());
+        cab.extend(0u16.to_le_bytes());
+        // file: size, offset in the folder, folder, date, time, attributes, name
+        cab.extend((data.len() as u32).to_le_bytes());
+        cab.extend(0u32.to_le_bytes());
+        for v in [0u16, 0x5821, 0, 0x20] {
+            cab.extend(v.to_le_bytes());
+        }
+        cab.extend(name.as_bytes());
+        cab.push(0);
+        // data block without checksum
+        cab.extend(0u32.to_le_bytes());
+        cab.extend((data.len() as u16).to_le_bytes());
+        cab.extend((data.len() as u16).to_le_bytes());
+        cab.extend(data);
+        cab
+    }
+
+    #[tokio::test]
+    async fn cab() -> Result<()> {
+        if !has_7z() {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("driver.cab");
+        std::fs::write(&path, create_cab("readme.txt", b"hello from the cabinet\n"))?;
+        assert_eq!(adapt_file(&path).await?, "hello from the cabinet\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn msi() -> Result<()> {
+        if !has_7z() {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("setup.msi");
+        let cfb = create_cfb(&[("Binary.license", b"hello from the installer\n")]);
+        std::fs::write(&path, cfb)?;
+        assert_eq!(adapt_file(&path).await?, "hello from the installer\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dmg() -> Result<()> {
+        if !has_7z() {
+            return Ok(());
+        }
+        // an uncompressed UDIF image with a single partition that contains text instead of a file system
+        let partition = adapt_file(&test_data_dir().join("hello.dmg")).await?;
+        assert!(
+            partition.starts_with("hello from the disk image\n"),
+            "{partition:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn listing() {
+        let listing = "Path = docs
+Size = 0
+Packed Size = 0
+Modified = 2024-05-01 10:00:00
+Attributes = D_ drwxr-xr-x
+Folder = +
+
+Path = docs/notes.txt
+Size = 12
+Packed Size = 40
+Modified = 2024-05-01 10:00:00
+Attributes = A_ -rw-r--r--
+Encrypted = +
+Folder = -
+
+Path = data.csv
+Size = 1024
+Packed Size =
+Folder = -
+";
+        assert_eq!(
+            parse_listing(listing),
+            [
+                ArchiveEntry {
+                    path: "docs/notes.txt".to_string(),
+                    size: Some(12),
+                    packed_size: Some(40),
+                    encrypted: true,
+                },
+                ArchiveEntry {
+                    path: "data.csv".to_string(),
+                    size: Some(1024),
+                    packed_size: None,
+                    encrypted: false,
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn extract() {
+        let cmd = extract_command(Path::new("/tmp/a.7z"), "docs/*.txt", &Switches::default());
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(args, ["x", "-so", "-spd", "--", "/tmp/a.7z", "docs/*.txt"]);
+        let switches = Switches {
+            password: Some("secret".to_string()),
+            code_page: Some(932),
+        };
+        let cmd = extract_command(Path::new("/tmp/a.zip"), "-notes.txt", &switches);
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "x",
+                "-so",
+                "-spd",
+                "-mcp=932",
+                "-psecret",
+                "--",
+                "/tmp/a.zip",
+                "-notes.txt"
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::adapted_iter::one_file;
+
+use super::*;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["exe", "sfx"];
+static MIME_TYPES: &[&str] = &[
+    "application/x-ms-dos-executable",
+    "application/x-msdownload",
+    "application/x-executable",
+];
+
+/// Signatures of archives that self-extracting executables contain, with the extension of the archive
+static SIGNATURES: &[(&[u8], &str)] = &[
+    (b"PK\x03\x04", "zip"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"Rar!\x1a\x07", "rar"),
+];
+
+/// only the start of executables is searched for an archive, the stubs of SFX archives are small
+const MAX_STUB_SIZE: usize = 16 * 1024 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "sfx".to_owned(),
+        version: 1,
+        description: "Finds the zip, 7z or rar archive within self-extracting executables (archives appended to a PE / ELF stub) and searches it with the matching archive adapter.\nExecutables without archive are passed on as binary data.\nDisabled by default since any executable might contain these signatures, enable it with `--rga-adapters=+sfx`."
+            .to_owned(),
+        recurses: true,
+        outputs_unchanged_files: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            MIME_TYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        disabled_by_default: true,
+        keep_fast_matchers_if_accurate: true
+    };
+}
+
+#[derive(Default)]
+pub struct SfxAdapter;
+
+impl SfxAdapter {
+    pub fn new() -> SfxAdapter {
+        SfxAdapter
+    }
+}
+impl GetMetadata for SfxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Search the start of the input for an archive signature.
+///
+/// Returns the extension of the archive and the input starting at the archive, or None and the whole input.
+async fn find_payload(mut inp: ReadBox) -> Result<(Option<&'static str>, ReadBox)> {
+    let max_sig_len = SIGNATURES.iter().map(|(s, _)| s.len()).max().unwrap_or(0);
+    let mut buf = Vec::new();
+    let mut chunk = vec![0u8; 1 << 16];
+    loop {
+        // signatures might span chunks
+        let searched = buf.len().saturating_sub(max_sig_len - 1);
+        let n = inp.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        let found = SIGNATURES
+            .iter()
+            .filter_map(|(sig, ext)| {
+                memchr::memmem::find(&buf[searched..], sig).map(|pos| (searched + pos, *ext))
+            })
+            .min();
+        if let Some((pos, ext)) = found {
+            debug!("found {ext} archive at offset {pos}");
+            let payload = buf.split_off(pos);
+            return Ok((Some(ext), Box::pin(Cursor::new(payload).chain(inp))));
+        }
+        if buf.len() >= MAX_STUB_SIZE {
+            break;
+        }
+    }
+    Ok((None, Box::pin(Cursor::new(buf).chain(inp))))
+}
+
+#[async_trait]
+impl FileAdapter for SfxAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            passwords,
+            ..
+        } = ai;
+        let (ext, inp) = find_payload(inp).await?;
+        let mut filepath_hint = filepath_hint.into_os_string();
+        // the binary policy applies to executables without archive
+        filepath_hint.push(".");
+        filepath_hint.push(ext.unwrap_or("bin"));
+        Ok(one_file(AdaptInfo {
+            filepath_hint: filepath_hint.into(),
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            inp,
+            line_prefix,
+            postprocess,
+            config,
+            passwords,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[tokio::test]
+    async fn payload_across_chunks() -> Result<()> {
+        let mock = Builder::new()
+            .read(b"MZ\x90\x00stub 7z\xbc")
+            .read(b"\xaf\x27\x1c\x00\x04payload")
+            .build();
+        let (ext, mut payload) = find_payload(Box::pin(mock)).await?;
+        assert_eq!(ext, Some("7z"));
+        let mut oup = Vec::new();
+        payload.read_to_end(&mut oup).await?;
+        assert_eq!(oup, b"7z\xbc\xaf\x27\x1c\x00\x04payload");
+
+        let (ext, mut inp) = find_payload(Box::pin(Cursor::new(b"MZ plain exe"))).await?;
+        assert_eq!(ext, None);
+        let mut oup = Vec::new();
+        inp.read_to_end(&mut oup).await?;
+        assert_eq!(oup, b"MZ plain exe");
+        Ok(())
+    }
+}
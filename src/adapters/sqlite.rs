@@ -1,3 +1,4 @@
+use super::property_list::plist_text;
 use super::{writing::WritingFileAdapter, *};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -5,7 +6,7 @@ use lazy_static::lazy_static;
 use log::*;
 use rusqlite::types::ValueRef;
 use rusqlite::*;
-use std::{convert::TryInto, io::Write};
+use std::{convert::TryInto, io::Write, path::Path};
 use tokio::io::AsyncWrite;
 
 use tokio_util::io::SyncIoBridge;
@@ -15,11 +16,12 @@ static EXTENSIONS: &[&str] = &["db", "db3", "sqlite", "sqlite3"];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "sqlite".to_owned(),
-        version: 1,
+        version: 2,
         description:
-            "Uses sqlite bindings to convert sqlite databases into a simple plain text format"
+            "Uses sqlite bindings to convert sqlite databases into a simple plain text format. Includes views and full text search tables, and reads databases in WAL mode with their journal. Blobs with text (or binary plists) are output as text with --rga-sqlite-text-blobs"
                 .to_owned(),
         recurses: false, // set to true if we decide to make sqlite blobs searchable (gz blob in db is kinda common I think)
+        outputs_unchanged_files: false,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -46,28 +48,100 @@ impl GetMetadata for SqliteAdapter {
     }
 }
 
-fn format_blob(b: ValueRef) -> String {
+/// Whether the decoded blob looks like text
+fn is_text(s: &str) -> bool {
+    !s.is_empty()
+        && !s
+            .chars()
+            .any(|c| c == char::REPLACEMENT_CHARACTER || (c.is_control() && !c.is_whitespace()))
+}
+
+/// The text of a blob containing UTF-8 or UTF-16 text (with or without byte order mark),
+/// or the flattened contents of a binary plist (common in macOS and iOS databases)
+fn blob_text(b: &[u8]) -> Option<String> {
+    if b.starts_with(b"bplist") {
+        return plist_text(b);
+    }
+    if let Some(s) = std::str::from_utf8(b).ok().filter(|s| is_text(s)) {
+        return Some(s.to_string());
+    }
+    let (encoding, data) = match b {
+        [0xff, 0xfe, rest @ ..] => (encoding_rs::UTF_16LE, rest),
+        [0xfe, 0xff, rest @ ..] => (encoding_rs::UTF_16BE, rest),
+        _ if b.len() % 2 == 0 => {
+            // mostly ascii text has every other byte zero
+            let zeros = |offset: usize| {
+                b.iter()
+                    .skip(offset)
+                    .step_by(2)
+                    .filter(|x| **x == 0)
+                    .count()
+            };
+            let half = b.len() / 4;
+            if zeros(1) > half {
+                (encoding_rs::UTF_16LE, b)
+            } else if zeros(0) > half {
+                (encoding_rs::UTF_16BE, b)
+            } else {
+                return None;
+            }
+        }
+        _ => return None,
+    };
+    let (s, had_errors) = encoding.decode_without_bom_handling(data);
+    (!had_errors && is_text(&s)).then(|| s.into_owned())
+}
+
+fn quote_text(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn format_blob(b: ValueRef, text_blobs: bool) -> String {
     use ValueRef::*;
     match b {
         Null => "NULL".to_owned(),
         Integer(i) => format!("{}", i),
         Real(i) => format!("{}", i),
-        Text(i) => format!("'{}'", String::from_utf8_lossy(i).replace('\'', "''")),
-        Blob(b) => format!(
-            "[blob {}B]",
-            size_format::SizeFormatterSI::new(
-                // can't be larger than 2GB anyways
-                b.len().try_into().unwrap()
-            )
-        ),
+        Text(i) => quote_text(&String::from_utf8_lossy(i)),
+        Blob(b) => match blob_text(b).filter(|_| text_blobs) {
+            Some(text) => quote_text(&text),
+            None => format!(
+                "[blob {}B]",
+                size_format::SizeFormatterSI::new(
+                    // can't be larger than 2GB anyways
+                    b.len().try_into().unwrap()
+                )
+            ),
+        },
     }
 }
 
+/// Open the database without modifying it.
+///
+/// Databases in WAL mode are copied with their journal to a temporary directory,
+/// because reading the journal needs write access to the shared memory file next to the database.
+fn open_database(path: &Path) -> Result<(Connection, Option<tempfile::TempDir>)> {
+    let mut wal = path.as_os_str().to_owned();
+    wal.push("-wal");
+    let wal = PathBuf::from(wal);
+    if !wal.exists() {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        return Ok((conn, None));
+    }
+    debug!("copying {} with its write-ahead log", path.display());
+    let dir = tempfile::tempdir()?;
+    let copy = dir.path().join("db.sqlite3");
+    std::fs::copy(path, &copy)?;
+    std::fs::copy(&wal, dir.path().join("db.sqlite3-wal"))?;
+    Ok((Connection::open(&copy)?, Some(dir)))
+}
+
 fn synchronous_dump_sqlite(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
     let AdaptInfo {
         is_real_file,
         filepath_hint,
         line_prefix,
+        config,
         ..
     } = ai;
     if !is_real_file {
@@ -77,10 +151,16 @@ fn synchronous_dump_sqlite(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
         return Ok(());
     }
     let inp_fname = filepath_hint;
-    let conn = Connection::open_with_flags(&inp_fname, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    let (conn, _tmp_dir) = open_database(&inp_fname)
         .with_context(|| format!("opening sqlite connection to {}", inp_fname.display()))?;
+    // tables, views and virtual (e.g. full text search) tables, but not the tables that store the data of virtual tables
     let tables: Vec<String> = conn
-        .prepare("select name from sqlite_master where type='table'")
+        .prepare(
+            "select m.name from sqlite_master m
+            join pragma_table_list t on t.schema = 'main' and t.name = m.name
+            where t.type in ('table', 'view', 'virtual') and m.name not like 'sqlite\\_%' escape '\\'
+            order by m.rowid",
+        )
         .context("while preparing query")?
         .query_map([], |r| r.get::<_, String>(0))
         .context("while executing query")?
@@ -89,10 +169,18 @@ fn synchronous_dump_sqlite(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
     debug!("db has {} tables", tables.len());
     for table in tables {
         // can't use query param at that position
-        let mut sel = conn.prepare(&format!(
+        let sel = conn.prepare(&format!(
             "select * from {}",
             rusqlite::vtab::escape_double_quote(&table)
-        ))?;
+        ));
+        let mut sel = match sel {
+            Ok(sel) => sel,
+            // e.g. virtual tables of modules that aren't compiled in
+            Err(e) => {
+                writeln!(s, "{line_prefix}[rga: skipping table {table}: {e}]")?;
+                continue;
+            }
+        };
         let col_names: Vec<String> = sel
             .column_names()
             .into_iter()
@@ -106,7 +194,13 @@ fn synchronous_dump_sqlite(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
             let row_str = col_names
                 .iter()
                 .enumerate()
-                .map(|(i, e)| Ok(format!("{}={}", e, format_blob(row.get_ref(i)?))))
+                .map(|(i, e)| {
+                    Ok(format!(
+                        "{}={}",
+                        e,
+                        format_blob(row.get_ref(i)?, config.sqlite_text_blobs)
+                    ))
+                })
                 .collect::<Result<Vec<String>>>()?
                 .join(", ");
             writeln!(s, "{line_prefix}{table}: {row_str}",)?;
@@ -137,7 +231,7 @@ impl WritingFileAdapter for SqliteAdapter {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test_utils::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
     use pretty_assertions::assert_eq;
 
     #[tokio::test]
@@ -145,7 +239,7 @@ mod test {
         let adapter: Box<dyn FileAdapter> = Box::<SqliteAdapter>::default();
         let fname = test_data_dir().join("hello.sqlite3");
         let (a, d) = simple_fs_adapt_info(&fname).await?;
-        let res = adapter.adapt(a, &d).await?;
+        let res = loop_adapt(adapter.as_ref(), d, a).await?;
 
         let buf = adapted_to_vec(res).await?;
 
@@ -156,4 +250,42 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn views_fts_wal_and_blobs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("notes.db");
+        // kept open so the changes stay in the write-ahead log
+        let writer = Connection::open(&fname)?;
+        writer.execute_batch(
+            "pragma journal_mode = wal;
+            create table notes(id integer primary key, body text, data blob);
+            insert into notes values (1, 'hello', cast('blob text' as blob));
+            insert into notes values (2, 'bye', x'610062006300');
+            create view long_notes as select body from notes where id = 1;
+            create virtual table search using fts5(title, content);
+            insert into search values ('Doc', 'full text searchable');",
+        )?;
+        let mut prefs = plist::Dictionary::new();
+        prefs.insert("theme".into(), "dark".into());
+        let mut bplist = Vec::new();
+        plist::Value::Dictionary(prefs).to_writer_binary(&mut bplist)?;
+        writer.execute("insert into notes values (3, 'prefs', ?1)", [bplist])?;
+        assert!(dir.path().join("notes.db-wal").exists());
+        let adapter: Box<dyn FileAdapter> = Box::<SqliteAdapter>::default();
+        let (mut a, d) = simple_fs_adapt_info(&fname).await?;
+        a.config.sqlite_text_blobs = true;
+        let buf = adapted_to_vec(adapter.adapt(a, &d).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:notes: id=1, body='hello', data='blob text'
+PREFIX:notes: id=2, body='bye', data='abc'
+PREFIX:notes: id=3, body='prefs', data='theme = dark'
+PREFIX:long_notes: body='hello'
+PREFIX:search: title='Doc', content='full text searchable'
+"
+        );
+        drop(writer);
+        Ok(())
+    }
 }
@@ -0,0 +1,259 @@
+use crate::adapted_iter::one_file;
+
+use super::*;
+
+use anyhow::Result;
+use async_stream::stream;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use std::fmt::Write;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "strings".to_owned(),
+        version: 1,
+        description: "Extracts runs of printable ASCII and UTF-16LE characters from binary data (like `strings`), one per line prefixed with their offset. Runs longer than 4096 characters are split into several lines.\nThe minimum length is set with `--rga-strings-min-length`.\nDoes not match any files by itself, use it for binary files that no other adapter handles with `--rga-adapters=+strings --rga-binary=adapter --rga-binary-adapter=strings`."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: vec![],
+        slow_matchers: None,
+        disabled_by_default: true,
+        keep_fast_matchers_if_accurate: false
+    };
+}
+
+#[derive(Default)]
+pub struct StringsAdapter;
+
+impl StringsAdapter {
+    pub fn new() -> StringsAdapter {
+        StringsAdapter
+    }
+}
+impl GetMetadata for StringsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Longer runs are split into several lines, so a large text file doesn't end up in memory as a single line
+const MAX_RUN_LEN: usize = 4096;
+
+fn is_printable(b: u8) -> bool {
+    b.is_ascii_graphic() || b == b' ' || b == b'\t'
+}
+
+/// A run of printable characters
+#[derive(Default)]
+struct Run {
+    /// offset of the first byte
+    start: u64,
+    text: String,
+}
+
+/// Finds printable strings in a stream of bytes, which can be fed in chunks.
+struct StringsScanner {
+    min_len: usize,
+    /// offset of the next byte
+    offset: u64,
+    prev: Option<u8>,
+    ascii: Run,
+    /// UTF-16LE runs starting at even and odd offsets
+    utf16: [Run; 2],
+    out: String,
+    /// whether a line was output already
+    written: bool,
+}
+
+impl StringsScanner {
+    fn new(min_len: usize) -> StringsScanner {
+        StringsScanner {
+            min_len: min_len.clamp(1, MAX_RUN_LEN),
+            offset: 0,
+            prev: None,
+            ascii: Run::default(),
+            utf16: Default::default(),
+            out: String::new(),
+            written: false,
+        }
+    }
+
+    /// Output the run if it is long enough and start a new one.
+    ///
+    /// Lines are separated by newlines, the last line does not end with a newline.
+    fn flush(min_len: usize, run: &mut Run, out: &mut String, written: &mut bool) {
+        if run.text.len() >= min_len {
+            if *written {
+                out.push('\n');
+            }
+            write!(out, "{:08x}: {}", run.start, run.text).unwrap();
+            *written = true;
+        }
+        run.text.clear();
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if is_printable(b) {
+                if self.ascii.text.is_empty() {
+                    self.ascii.start = self.offset;
+                }
+                self.ascii.text.push(b as char);
+                if self.ascii.text.len() == MAX_RUN_LEN {
+                    Self::flush(
+                        self.min_len,
+                        &mut self.ascii,
+                        &mut self.out,
+                        &mut self.written,
+                    );
+                }
+            } else {
+                Self::flush(
+                    self.min_len,
+                    &mut self.ascii,
+                    &mut self.out,
+                    &mut self.written,
+                );
+            }
+            if let Some(prev) = self.prev {
+                // the character that started at the previous byte
+                let start = self.offset - 1;
+                let run = &mut self.utf16[(start % 2) as usize];
+                if is_printable(prev) && b == 0 {
+                    if run.text.is_empty() {
+                        run.start = start;
+                    }
+                    run.text.push(prev as char);
+                    if run.text.len() == MAX_RUN_LEN {
+                        Self::flush(self.min_len, run, &mut self.out, &mut self.written);
+                    }
+                } else {
+                    Self::flush(self.min_len, run, &mut self.out, &mut self.written);
+                }
+            }
+            self.prev = Some(b);
+            self.offset += 1;
+        }
+    }
+
+    fn finish(&mut self) {
+        Self::flush(
+            self.min_len,
+            &mut self.ascii,
+            &mut self.out,
+            &mut self.written,
+        );
+        for run in self.utf16.iter_mut() {
+            Self::flush(self.min_len, run, &mut self.out, &mut self.written);
+        }
+    }
+
+    fn take_output(&mut self) -> Option<Bytes> {
+        if self.out.is_empty() {
+            None
+        } else {
+            Some(Bytes::from(std::mem::take(&mut self.out)))
+        }
+    }
+}
+
+/// Extracts printable strings of at least `min_len` characters from the input, one per line prefixed with their offset.
+pub fn extract_strings(inp: ReadBox, min_len: usize) -> ReadBox {
+    let s = stream! {
+        let mut scanner = StringsScanner::new(min_len);
+        for await chunk in ReaderStream::new(inp) {
+            scanner.feed(&chunk?);
+            if let Some(out) = scanner.take_output() {
+                yield std::io::Result::Ok(out);
+            }
+        }
+        scanner.finish();
+        if let Some(out) = scanner.take_output() {
+            yield Ok(out);
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+/// Like `extract_strings`, but synchronous and with every line prefixed with `line_prefix`.
+pub fn write_strings(
+    mut inp: impl std::io::Read,
+    min_len: usize,
+    line_prefix: &str,
+    mut out: impl std::io::Write,
+) -> Result<()> {
+    use std::io::Write as _;
+    let mut scanner = StringsScanner::new(min_len);
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = inp.read(&mut buf)?;
+        if n == 0 {
+            scanner.finish();
+        } else {
+            scanner.feed(&buf[..n]);
+        }
+        if let Some(text) = scanner.take_output() {
+            for line in std::str::from_utf8(&text)?.split('\n') {
+                if !line.is_empty() {
+                    writeln!(out, "{line_prefix}{line}")?;
+                }
+            }
+        }
+        if n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+#[async_trait]
+impl FileAdapter for StringsAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let min_len = ai.config.strings_min_length.0;
+        Ok(one_file(AdaptInfo {
+            is_real_file: false,
+            archive_recursion_depth: ai.archive_recursion_depth + 1,
+            inp: extract_strings(ai.inp, min_len),
+            ..ai
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::AsyncReadExt;
+    use tokio_test::io::Builder;
+
+    #[tokio::test]
+    async fn strings() -> Result<()> {
+        let mock = Builder::new()
+            .read(b"\x7fELF\x00\x01hello wor")
+            .read(b"ld\x00\xffw\x00i\x00d\x00e\x00\x00ab\x00")
+            .build();
+        let mut oup = String::new();
+        extract_strings(Box::pin(mock), 4)
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "00000006: hello world\n00000013: wide");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn long_run() -> Result<()> {
+        let text = "a".repeat(MAX_RUN_LEN + 10);
+        let mut oup = String::new();
+        extract_strings(Box::pin(std::io::Cursor::new(text)), 4)
+            .read_to_string(&mut oup)
+            .await?;
+        let lines: Vec<_> = oup.lines().map(|l| (&l[..8], l.len() - 10)).collect();
+        assert_eq!(lines, [("00000000", MAX_RUN_LEN), ("00001000", 10)]);
+        Ok(())
+    }
+}
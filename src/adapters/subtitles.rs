@@ -0,0 +1,284 @@
+use super::writing::WritingFileAdapter;
+use super::xml::unescape;
+use super::*;
+use anyhow::Result;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use lazy_static::lazy_static;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["srt", "vtt", "ass", "ssa"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "subtitles".to_owned(),
+        version: 1,
+        description: "Reads SubRip (.srt), WebVTT (.vtt) and SubStation Alpha (.ass, .ssa) subtitles. Outputs the text without styling, each line prefixed with the start time of its cue (e.g. `00:12:34: Hello`)"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("text/vtt".to_owned()),
+            FileMatcher::MimeType("application/x-subrip".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SubtitlesAdapter;
+
+impl SubtitlesAdapter {
+    pub fn new() -> SubtitlesAdapter {
+        SubtitlesAdapter
+    }
+}
+
+impl GetMetadata for SubtitlesAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Subtitles are often in the legacy encoding of their language. Tries UTF-8 (or the encoding of the BOM),
+/// then the fallback encodings, then Windows-1252
+fn decode(data: &[u8], fallback_encodings: &[String]) -> Result<String> {
+    let (text, _, had_errors) = UTF_8.decode(data);
+    if !had_errors {
+        return Ok(text.into_owned());
+    }
+    for label in fallback_encodings {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .with_context(|| format!("unknown encoding {label:?} in fallback_encodings"))?;
+        if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(data) {
+            return Ok(text.into_owned());
+        }
+    }
+    Ok(WINDOWS_1252.decode(data).0.into_owned())
+}
+
+/// `hh:mm:ss` from the start of a cue: `01:02:03,456` (SubRip), `02:03.456` (WebVTT) or `1:02:03.45` (SubStation Alpha)
+fn cue_time(time: &str) -> Option<String> {
+    let parts: Vec<u64> = time
+        .trim()
+        .split(':')
+        .map(|p| p.split(['.', ',']).next().unwrap_or_default().parse().ok())
+        .collect::<Option<_>>()?;
+    let (h, m, s) = match parts[..] {
+        [h, m, s] => (h, m, s),
+        [m, s] => (0, m, s),
+        _ => return None,
+    };
+    Some(format!("{h:02}:{m:02}:{s:02}"))
+}
+
+/// Removes the tags of SubRip and WebVTT (`<i>`, `<font color=..>`, `<v Speaker>`) and SubStation Alpha
+/// override blocks (`{\an8}`), which are also common in SubRip files
+fn strip_markup(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut closing = None;
+    for c in text.chars() {
+        match closing {
+            Some(end) if c == end => closing = None,
+            Some(_) => {}
+            None if c == '<' => closing = Some('>'),
+            None if c == '{' => closing = Some('}'),
+            None => out.push(c),
+        }
+    }
+    unescape(out.trim()).into_owned()
+}
+
+/// SubRip and WebVTT: cues of a timing line (`start --> end`) followed by the text lines, separated by blank lines.
+/// Cue numbers and identifiers, the WebVTT header, notes and style blocks have no timing line and are skipped
+fn srt_to_text(text: &str, line_prefix: &str) -> String {
+    let mut out = String::new();
+    let mut time = None;
+    for line in text.lines() {
+        let start = line
+            .split_once("-->")
+            .and_then(|(start, _)| cue_time(start));
+        if start.is_some() {
+            time = start;
+        } else if line.trim().is_empty() {
+            time = None;
+        } else if let Some(time) = &time {
+            let line = strip_markup(line);
+            if !line.is_empty() {
+                out.push_str(&format!("{line_prefix}{time}: {line}\n"));
+            }
+        }
+    }
+    out
+}
+
+/// SubStation Alpha: `Dialogue:` lines of the `[Events]` section, with the fields given by its `Format:` line
+fn ass_to_text(text: &str, line_prefix: &str) -> String {
+    let mut out = String::new();
+    let mut in_events = false;
+    // the text is the last field and may contain commas
+    let mut fields = vec![
+        "layer", "start", "end", "style", "name", "marginl", "marginr", "marginv", "effect", "text",
+    ];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+        if let Some(format) = line.strip_prefix("Format:") {
+            fields = format.split(',').map(|f| f.trim()).collect();
+            continue;
+        }
+        let Some(dialogue) = line.strip_prefix("Dialogue:") else {
+            continue;
+        };
+        let values: Vec<&str> = dialogue.splitn(fields.len(), ',').collect();
+        let field = |name: &str| {
+            let i = fields.iter().position(|f| f.eq_ignore_ascii_case(name))?;
+            values.get(i).copied()
+        };
+        let (Some(start), Some(text)) = (field("start").and_then(cue_time), field("text")) else {
+            continue;
+        };
+        // \N and \n are line breaks, \h is a non-breaking space
+        let text = text
+            .replace("\\N", "\n")
+            .replace("\\n", "\n")
+            .replace("\\h", " ");
+        for line in text.lines() {
+            let line = strip_markup(line);
+            if !line.is_empty() {
+                out.push_str(&format!("{line_prefix}{start}: {line}\n"));
+            }
+        }
+    }
+    out
+}
+
+fn subtitles_to_text(text: &str, line_prefix: &str) -> String {
+    let is_ass = text
+        .lines()
+        .any(|l| l.trim().eq_ignore_ascii_case("[events]"));
+    if is_ass {
+        ass_to_text(text, line_prefix)
+    } else {
+        srt_to_text(text, line_prefix)
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for SubtitlesAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let text = decode(&data, &config.fallback_encodings)?;
+        let out = subtitles_to_text(&text, &line_prefix);
+        oup.write_all(out.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    async fn adapt(filename: &str, data: &[u8]) -> Result<String> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from(filename),
+            Box::pin(Cursor::new(data.to_vec())),
+        );
+        let buf = adapted_to_vec(loop_adapt(&SubtitlesAdapter::new(), d, a).await?).await?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[tokio::test]
+    async fn srt() -> Result<()> {
+        let srt = "1
+00:12:34,500 --> 00:12:36,000
+<i>Where are you going?</i>
+
+2
+00:12:36,200 --> 00:12:39,000
+{\\an8}To the <font color=\"#ffff00\">station</font>,
+before it's too late.
+";
+        assert_eq!(
+            adapt("movie.srt", srt.as_bytes()).await?,
+            "PREFIX:00:12:34: Where are you going?
+PREFIX:00:12:36: To the station,
+PREFIX:00:12:36: before it's too late.
+"
+        );
+        // Windows-1252
+        assert_eq!(
+            adapt(
+                "film.srt",
+                b"1\r\n00:00:01,000 --> 00:00:02,000\r\nCaf\xe9\r\n"
+            )
+            .await?,
+            "PREFIX:00:00:01: Caf\u{e9}\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vtt() -> Result<()> {
+        let vtt = "WEBVTT
+
+NOTE this is not a cue
+
+intro
+01:05.000 --> 01:07.500 align:start
+<v Mary>Fish &amp; chips?</v>
+";
+        assert_eq!(
+            adapt("talk.vtt", vtt.as_bytes()).await?,
+            "PREFIX:00:01:05: Fish & chips?\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ass() -> Result<()> {
+        let ass = "[Script Info]
+Title: Example
+
+[V4+ Styles]
+Format: Name, Fontname, Fontsize
+Style: Default,Arial,20
+
+[Events]
+Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+Comment: 0,0:00:00.00,0:00:01.00,Default,,0,0,0,,not shown
+Dialogue: 0,1:02:03.45,1:02:05.00,Default,Bob,0,0,0,,{\\i1}Wait,{\\i0} what?\\NNo way.
+";
+        assert_eq!(
+            adapt("anime.ass", ass.as_bytes()).await?,
+            "PREFIX:01:02:03: Wait, what?
+PREFIX:01:02:03: No way.
+"
+        );
+        Ok(())
+    }
+}
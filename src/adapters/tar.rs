@@ -2,27 +2,31 @@ use crate::{
     adapted_iter::AdaptedFilesIterBox,
     adapters::AdapterMeta,
     matching::{FastFileMatcher, FileMatcher},
-    print_bytes,
+    print_bytes, print_unix_time,
 };
 use anyhow::*;
 use async_stream::stream;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use log::*;
-use std::path::PathBuf;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 
+use tokio::io::AsyncReadExt;
 use tokio_stream::StreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
 
-use super::{AdaptInfo, FileAdapter, GetMetadata};
+use super::{AdaptInfo, FileAdapter, GetMetadata, ReadBox};
 
 static EXTENSIONS: &[&str] = &["tar"];
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "tar".to_owned(),
-        version: 1,
-        description: "Reads a tar file as a stream and recurses down into its contents".to_owned(),
+        version: 3,
+        description: "Reads a tar file as a stream and recurses down into its contents.\nSupports PAX and GNU extensions (long names, sparse files). With `--rga-tar-metadata`, outputs the modification time, owner and permissions of each file".to_owned(),
         recurses: true,
+        outputs_unchanged_files: true,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -46,6 +50,106 @@ impl GetMetadata for TarAdapter {
     }
 }
 
+/// A line with the modification time, owner and permissions of an entry, see `--rga-tar-metadata`
+fn entry_metadata(header: &tokio_tar::Header) -> String {
+    let mut fields = Vec::new();
+    if let Ok(mtime) = header.mtime() {
+        fields.push(format!("modified {}", print_unix_time(mtime as i64)));
+    }
+    let user = match header.username() {
+        Ok(Some(name)) if !name.is_empty() => Some(name.to_string()),
+        _ => header.uid().ok().map(|uid| uid.to_string()),
+    };
+    let group = match header.groupname() {
+        Ok(Some(name)) if !name.is_empty() => Some(name.to_string()),
+        _ => header.gid().ok().map(|gid| gid.to_string()),
+    };
+    if let (Some(user), Some(group)) = (user, group) {
+        fields.push(format!("owner {user}:{group}"));
+    }
+    if let Ok(mode) = header.mode() {
+        fields.push(format!("mode {:o}", mode & 0o7777));
+    }
+    format!("[tar] {}\n", fields.join(", "))
+}
+
+/// GNU tar stores sparse files in PAX archives under a `GNUSparseFile.N` directory, the real name
+/// is only in the PAX header (`GNU.sparse.name`). Returns the name without that directory
+fn sparse_file_name(path: &Path) -> Option<PathBuf> {
+    let mut found = false;
+    let name = path
+        .components()
+        .filter(|c| {
+            let is_sparse = c
+                .as_os_str()
+                .to_str()
+                .and_then(|c| c.strip_prefix("GNUSparseFile."))
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+            found |= is_sparse;
+            !is_sparse
+        })
+        .collect();
+    found.then_some(name)
+}
+
+fn next_number(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let end = *pos + data[*pos..].iter().position(|b| *b == b'\n')?;
+    let n = std::str::from_utf8(&data[*pos..end]).ok()?.parse().ok()?;
+    *pos = end + 1;
+    Some(n)
+}
+
+/// Sparse files in the PAX format 1.0 start with a map of their data: the number of segments and
+/// the offset and size of each as decimal lines, padded with zeros to 512 bytes.
+/// Returns the length of the map, None if the data doesn't start with a complete map
+fn sparse_map_len(data: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    let count = next_number(data, &mut pos)?;
+    for _ in 0..count.checked_mul(2)? {
+        next_number(data, &mut pos)?;
+    }
+    let len = pos.div_ceil(512) * 512;
+    data.get(pos..len)?.iter().all(|b| *b == 0).then_some(len)
+}
+
+/// Larger maps are not expected, the data is then passed on unchanged
+const MAX_SPARSE_MAP: usize = 1024 * 1024;
+
+/// Removes the sparse map (see `sparse_map_len`), the rest are the data segments without the holes.
+/// Sparse files in the PAX format 0.x have the map in the header and are passed on unchanged
+async fn strip_sparse_map(mut inp: ReadBox) -> std::io::Result<ReadBox> {
+    let mut start = Vec::new();
+    loop {
+        let read = (&mut inp).take(512).read_to_end(&mut start).await?;
+        if let Some(len) = sparse_map_len(&start) {
+            start.drain(..len);
+            break;
+        }
+        let is_map = start
+            .iter()
+            .all(|b| b.is_ascii_digit() || *b == b'\n' || *b == 0);
+        if read < 512 || start.len() >= MAX_SPARSE_MAP || !is_map {
+            break;
+        }
+    }
+    Ok(Box::pin(Cursor::new(start).chain(inp)))
+}
+
+/// tokio_tar fills the holes of GNU sparse files with zeros. They are left out, so that large sparse
+/// files (e.g. disk images) only output their data. Holes are read separately from the data, so a
+/// chunk is either all hole or all data
+fn without_holes(inp: ReadBox) -> ReadBox {
+    let s = stream! {
+        for await chunk in ReaderStream::new(inp) {
+            let chunk = chunk?;
+            if chunk.iter().any(|b| *b != 0) {
+                yield std::io::Result::Ok(chunk);
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
 #[async_trait]
 impl FileAdapter for TarAdapter {
     async fn adapt(
@@ -59,6 +163,7 @@ impl FileAdapter for TarAdapter {
             line_prefix,
             archive_recursion_depth,
             config,
+            passwords,
             postprocess,
             ..
         } = ai;
@@ -68,8 +173,17 @@ impl FileAdapter for TarAdapter {
         let s = stream! {
             while let Some(entry) = entries.next().await {
                 let file = entry?;
-                if tokio_tar::EntryType::Regular == file.header().entry_type() {
+                let entry_type = file.header().entry_type();
+                // the holes of sparse files are filled in by tokio_tar, contiguous files are regular files
+                let is_file = entry_type.is_file() || entry_type.is_gnu_sparse() || entry_type.is_contiguous();
+                // old tar versions mark directories only by a trailing slash
+                if is_file && !file.path_bytes().ends_with(b"/") {
+                    // includes PAX and GNU long names
                     let path = PathBuf::from(file.path()?.to_owned());
+                    let (path, is_pax_sparse) = match sparse_file_name(&path) {
+                        Some(name) => (name, true),
+                        None => (path, false),
+                    };
                     debug!(
                         "{}|{}: {}",
                         filepath_hint.display(),
@@ -77,13 +191,35 @@ impl FileAdapter for TarAdapter {
                         print_bytes(file.header().size().unwrap_or(0) as f64),
                     );
                     let line_prefix = &format!("{}{}: ", line_prefix, path.display());
+                    if config.tar_metadata {
+                        let mut metadata_path = path.clone().into_os_string();
+                        metadata_path.push(".tar-metadata");
+                        yield Ok(AdaptInfo {
+                            filepath_hint: metadata_path.into(),
+                            is_real_file: false,
+                            archive_recursion_depth: archive_recursion_depth + 1,
+                            inp: Box::pin(Cursor::new(entry_metadata(file.header()))),
+                            line_prefix: line_prefix.to_string(),
+                            config: config.clone(),
+                            passwords: passwords.clone(),
+                            postprocess,
+                        });
+                    }
+                    let mut inp: ReadBox = Box::pin(file);
+                    if entry_type.is_gnu_sparse() {
+                        inp = without_holes(inp);
+                    }
+                    if is_pax_sparse {
+                        inp = strip_sparse_map(inp).await?;
+                    }
                     let ai2: AdaptInfo = AdaptInfo {
                         filepath_hint: path,
                         is_real_file: false,
                         archive_recursion_depth: archive_recursion_depth + 1,
-                        inp: Box::pin(file),
+                        inp,
                         line_prefix: line_prefix.to_string(),
                         config: config.clone(),
+                        passwords: passwords.clone(),
                         postprocess,
                     };
                     yield Ok(ai2);
@@ -129,4 +265,75 @@ PREFIX:dir/file-a.pdf: Page 1:
         );
         Ok(())
     }
+    #[tokio::test]
+    async fn metadata() -> Result<()> {
+        assert_eq!(print_unix_time(951782400), "2000-02-29 00:00:00 UTC");
+        let filepath = test_data_dir().join("hello.tar");
+        let (mut a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
+        a.config.tar_metadata = true;
+        let r = loop_adapt(&TarAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o.lines().next(),
+            Some(
+                "PREFIX:dir/file-b.pdf: [tar] modified 2022-12-26 17:59:10 UTC, owner phire:phire, mode 644"
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_names() {
+        assert_eq!(
+            sparse_file_name(Path::new("./GNUSparseFile.1234/disk.img")),
+            Some(PathBuf::from("./disk.img"))
+        );
+        assert_eq!(
+            sparse_file_name(Path::new("dir/GNUSparseFile.0/disk.img")),
+            Some(PathBuf::from("dir/disk.img"))
+        );
+        assert_eq!(
+            sparse_file_name(Path::new("dir/GNUSparseFile.x/disk.img")),
+            None
+        );
+        assert_eq!(sparse_file_name(Path::new("dir/disk.img")), None);
+    }
+
+    #[tokio::test]
+    async fn sparse_map() -> Result<()> {
+        let mut data = b"2\n0\n5\n1048576\n6\n".to_vec();
+        assert_eq!(sparse_map_len(&data), None);
+        data.resize(512, 0);
+        assert_eq!(sparse_map_len(&data), Some(512));
+        data.extend_from_slice(b"hello world\n");
+        let mut o = String::new();
+        strip_sparse_map(Box::pin(Cursor::new(data)))
+            .await?
+            .read_to_string(&mut o)
+            .await?;
+        assert_eq!(o, "hello world\n");
+
+        // PAX format 0.x, the map is in the header
+        let mut o = String::new();
+        strip_sparse_map(Box::pin(Cursor::new(b"1\n2\n3\nfour\n".to_vec())))
+            .await?
+            .read_to_string(&mut o)
+            .await?;
+        assert_eq!(o, "1\n2\n3\nfour\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn holes() -> Result<()> {
+        let hole: ReadBox = Box::pin(Cursor::new(vec![0; 1 << 20]));
+        let inp: ReadBox = Box::pin(
+            Cursor::new(b"hello ".to_vec())
+                .chain(hole)
+                .chain(Cursor::new(b"world".to_vec())),
+        );
+        let mut o = String::new();
+        without_holes(inp).read_to_string(&mut o).await?;
+        assert_eq!(o, "hello world");
+        Ok(())
+    }
 }
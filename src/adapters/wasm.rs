@@ -0,0 +1,357 @@
+use super::{strings::write_strings, writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["wasm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "wasm".to_owned(),
+        version: 1,
+        description: "Reads WebAssembly modules and outputs the module and function names of the name section, the imports, the exports, the names of custom sections and the printable strings of the data segments."
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/wasm".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct WasmAdapter;
+
+impl WasmAdapter {
+    pub fn new() -> WasmAdapter {
+        WasmAdapter
+    }
+}
+impl GetMetadata for WasmAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAGIC: &[u8] = b"\0asm";
+
+// section ids
+const CUSTOM_SECTION: u8 = 0;
+const IMPORT_SECTION: u8 = 2;
+const EXPORT_SECTION: u8 = 7;
+const DATA_SECTION: u8 = 11;
+
+// subsections of the name section
+const MODULE_NAME: u8 = 0;
+const FUNCTION_NAMES: u8 = 1;
+
+fn kind_name(kind: u8) -> &'static str {
+    match kind {
+        0 => "function",
+        1 => "table",
+        2 => "memory",
+        3 => "global",
+        4 => "tag",
+        _ => "unknown",
+    }
+}
+
+/// Reads the binary encoding of a module
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data, pos: 0 }
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let b = *self
+            .data
+            .get(self.pos)
+            .context("unexpected end of wasm module")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let b = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("unexpected end of wasm module")?;
+        self.pos += len;
+        Ok(b)
+    }
+
+    /// An unsigned LEB128 integer
+    fn uleb(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        anyhow::bail!("invalid LEB128 integer")
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        Ok(self.uleb()?.try_into()?)
+    }
+
+    fn name(&mut self) -> Result<String> {
+        let len = self.len()?;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    /// A table or memory limit
+    fn skip_limits(&mut self) -> Result<()> {
+        let flags = self.byte()?;
+        self.uleb()?;
+        if flags & 1 != 0 {
+            self.uleb()?;
+        }
+        Ok(())
+    }
+
+    /// A constant expression, as used for the offset of data segments
+    fn skip_const_expr(&mut self) -> Result<()> {
+        loop {
+            match self.byte()? {
+                // end
+                0x0b => return Ok(()),
+                // i32.const, i64.const, global.get
+                0x41 | 0x42 | 0x23 => {
+                    self.uleb()?;
+                }
+                // extended constant expressions: add, sub, mul
+                0x6a | 0x6b | 0x6c | 0x7c | 0x7d | 0x7e => {}
+                op => anyhow::bail!("unsupported instruction 0x{op:02x} in constant expression"),
+            }
+        }
+    }
+}
+
+fn write_imports(d: &mut Decoder, p: &str, out: &mut impl Write) -> Result<()> {
+    for _ in 0..d.uleb()? {
+        let module = d.name()?;
+        let field = d.name()?;
+        let kind = d.byte()?;
+        match kind {
+            // function type index, tag attribute and type index
+            0 => {
+                d.uleb()?;
+            }
+            4 => {
+                d.byte()?;
+                d.uleb()?;
+            }
+            // reference type and limits
+            1 => {
+                d.byte()?;
+                d.skip_limits()?;
+            }
+            2 => d.skip_limits()?,
+            // value type and mutability
+            3 => {
+                d.bytes(2)?;
+            }
+            _ => anyhow::bail!("unknown import kind {kind}"),
+        }
+        writeln!(out, "{p}import: {module}.{field} ({})", kind_name(kind))?;
+    }
+    Ok(())
+}
+
+fn write_exports(d: &mut Decoder, p: &str, out: &mut impl Write) -> Result<()> {
+    for _ in 0..d.uleb()? {
+        let name = d.name()?;
+        let kind = d.byte()?;
+        d.uleb()?;
+        writeln!(out, "{p}export: {name} ({})", kind_name(kind))?;
+    }
+    Ok(())
+}
+
+fn write_names(d: &mut Decoder, p: &str, out: &mut impl Write) -> Result<()> {
+    while !d.at_end() {
+        let id = d.byte()?;
+        let len = d.len()?;
+        let mut sub = Decoder::new(d.bytes(len)?);
+        match id {
+            MODULE_NAME => writeln!(out, "{p}module: {}", sub.name()?)?,
+            FUNCTION_NAMES => {
+                for _ in 0..sub.uleb()? {
+                    sub.uleb()?;
+                    writeln!(out, "{p}function: {}", sub.name()?)?;
+                }
+            }
+            // local, label, type, ... names
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_data(d: &mut Decoder, min_len: usize, p: &str, out: &mut impl Write) -> Result<()> {
+    for i in 0..d.uleb()? {
+        match d.uleb()? {
+            // active segment of memory 0
+            0 => d.skip_const_expr()?,
+            // passive segment
+            1 => {}
+            // active segment with memory index
+            2 => {
+                d.uleb()?;
+                d.skip_const_expr()?;
+            }
+            flags => anyhow::bail!("unknown data segment flags {flags}"),
+        }
+        let len = d.len()?;
+        write_strings(d.bytes(len)?, min_len, &format!("{p}data {i} "), &mut *out)?;
+    }
+    Ok(())
+}
+
+fn dump_wasm(data: &[u8], min_len: usize, p: &str, mut out: impl Write) -> Result<()> {
+    let mut d = Decoder::new(data);
+    anyhow::ensure!(d.bytes(4).ok() == Some(MAGIC), "not a wasm module");
+    let version = u32::from_le_bytes(d.bytes(4)?.try_into()?);
+    // components (version 0x1000d) have a different structure
+    anyhow::ensure!(version == 1, "unsupported wasm version 0x{version:x}");
+    while !d.at_end() {
+        let id = d.byte()?;
+        let len = d.len()?;
+        let mut section = Decoder::new(d.bytes(len)?);
+        match id {
+            CUSTOM_SECTION => {
+                let name = section.name()?;
+                if name == "name" {
+                    write_names(&mut section, p, &mut out)
+                        .context("invalid name section, ignoring the rest of it")
+                        .unwrap_or_else(|e| warn!("{e:#}"));
+                } else {
+                    writeln!(out, "{p}custom section: {name}")?;
+                }
+            }
+            IMPORT_SECTION => write_imports(&mut section, p, &mut out)?,
+            EXPORT_SECTION => write_exports(&mut section, p, &mut out)?,
+            DATA_SECTION => write_data(&mut section, min_len, p, &mut out)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for WasmAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            mut inp,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let oup = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(move || {
+            dump_wasm(&data, config.strings_min_length.0, &line_prefix, oup)
+        })
+        .await?
+        .context("in synchronous wasm task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    fn name(s: &str) -> Vec<u8> {
+        let mut v = vec![s.len() as u8];
+        v.extend_from_slice(s.as_bytes());
+        v
+    }
+
+    fn section(id: u8, content: Vec<u8>) -> Vec<u8> {
+        let mut v = vec![id, content.len() as u8];
+        v.extend(content);
+        v
+    }
+
+    #[tokio::test]
+    async fn module() -> Result<()> {
+        let mut m = MAGIC.to_vec();
+        m.extend_from_slice(&1u32.to_le_bytes());
+        // type section: () -> ()
+        m.extend(section(1, vec![1, 0x60, 0, 0]));
+        let mut imports = vec![1];
+        imports.extend(name("env"));
+        imports.extend(name("log"));
+        imports.extend([0, 0]);
+        m.extend(section(IMPORT_SECTION, imports));
+        let mut exports = vec![2];
+        exports.extend(name("greet"));
+        exports.extend([0, 1]);
+        exports.extend(name("memory"));
+        exports.extend([2, 0]);
+        m.extend(section(EXPORT_SECTION, exports));
+        // one active segment at offset 16
+        let mut data = vec![1, 0, 0x41, 16, 0x0b, 14];
+        data.extend_from_slice(b"\0hello, world\0");
+        m.extend(section(DATA_SECTION, data));
+        let mut names = name("name");
+        let mut module_name = name("greeter");
+        names.push(MODULE_NAME);
+        names.push(module_name.len() as u8);
+        names.append(&mut module_name);
+        let mut function_names = vec![2, 0];
+        function_names.extend(name("log"));
+        function_names.push(1);
+        function_names.extend(name("say_hello"));
+        names.push(FUNCTION_NAMES);
+        names.push(function_names.len() as u8);
+        names.extend(function_names);
+        m.extend(section(CUSTOM_SECTION, names));
+
+        let adapter: Box<dyn FileAdapter> = Box::<WasmAdapter>::default();
+        let (a, d) = simple_adapt_info(
+            std::path::Path::new("greeter.wasm"),
+            Box::pin(std::io::Cursor::new(m)),
+        );
+        let res = adapter.adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:import: env.log (function)
+PREFIX:export: greet (function)
+PREFIX:export: memory (memory)
+PREFIX:data 0 00000001: hello, world
+PREFIX:module: greeter
+PREFIX:function: log
+PREFIX:function: say_hello
+"
+        );
+        Ok(())
+    }
+}
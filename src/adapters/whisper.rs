@@ -0,0 +1,160 @@
+use super::sevenzip::archive_on_disk;
+use super::{custom::map_exe_error, writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::*;
+use regex::Regex;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+
+static EXTENSIONS: &[&str] = &[
+    "mp3", "wav", "m4a", "ogg", "opus", "flac", "aac", "wma", "amr", "mp4", "mkv", "webm", "mov",
+    "avi",
+];
+
+const HELP: &str =
+    "Please install whisper.cpp (whisper-cli) or set whisper.engine_command in the config file.";
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "whisper".to_owned(),
+        version: 1,
+        description: "Transcribes the speech in audio and video files with whisper.cpp (or whisper.engine_command), each line prefixed with its start and end time. The audio is converted with ffmpeg.\nVery slow, so it is disabled by default, enable it with --rga-adapters=+whisper and --rga-whisper-model. Use it with the cache, so files are only transcribed once. Enabled, it is used instead of the ffmpeg adapter for the files both read"
+            .to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("^audio/".to_owned()),
+            FileMatcher::MimeType("^video/".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true
+    };
+    // whisper.cpp: `[00:00:01.000 --> 00:00:04.500]   text`, openai-whisper: `[00:01.000 --> 00:04.500] text`
+    static ref SEGMENT: Regex = Regex::new(r"^\[([\d:.]+) --> ([\d:.]+)\]\s*(.*)$").unwrap();
+}
+
+#[derive(Default, Clone)]
+pub struct WhisperAdapter;
+
+impl WhisperAdapter {
+    pub fn new() -> WhisperAdapter {
+        WhisperAdapter
+    }
+}
+impl GetMetadata for WhisperAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// The segments of the output of whisper as lines like `00:00:01.000 --> 00:00:04.500: text`,
+/// the same format as the subtitles of the ffmpeg adapter
+fn transcript(output: &str, line_prefix: &str) -> String {
+    let mut out = String::new();
+    for line in output.lines() {
+        let Some(caps) = SEGMENT.captures(line.trim()) else {
+            continue;
+        };
+        let text = caps[3].trim();
+        if !text.is_empty() {
+            out.push_str(&format!(
+                "{line_prefix}{} --> {}: {text}\n",
+                &caps[1], &caps[2]
+            ));
+        }
+    }
+    out
+}
+
+#[async_trait]
+impl WritingFileAdapter for WhisperAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            config,
+            ..
+        } = ai;
+        // ffmpeg needs to seek in most containers
+        let (path, _tmp) = archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+        let wav = tempfile::Builder::new()
+            .prefix("rga-")
+            .suffix(".wav")
+            .tempfile()?
+            .into_temp_path();
+        // whisper needs 16 kHz mono audio
+        let convert = Command::new("ffmpeg")
+            .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+            .arg(&path)
+            .args(["-vn", "-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le"])
+            .arg(&wav)
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, "ffmpeg", "Make sure you have ffmpeg installed."))?;
+        if !convert.status.success() {
+            anyhow::bail!(
+                "ffmpeg failed to extract the audio: {:?}\n{}",
+                convert.status,
+                String::from_utf8_lossy(&convert.stderr)
+            );
+        }
+        let (binary, args) = config.whisper.command(&wav)?;
+        let mut cmd = Command::new(&binary);
+        cmd.args(args);
+        debug!("executing {:?}", cmd);
+        let output = cmd
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, &binary, HELP))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{binary} failed: {:?}\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let text = transcript(&String::from_utf8_lossy(&output.stdout), &line_prefix);
+        oup.write_all(text.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn segments() {
+        let whisper_cpp = "
+[00:00:00.000 --> 00:00:03.240]   Welcome to the weekly meeting.
+[00:00:03.240 --> 00:00:05.000]
+[00:00:05.000 --> 00:00:08.120]   First item is the release.
+";
+        assert_eq!(
+            transcript(whisper_cpp, "PREFIX:"),
+            "PREFIX:00:00:00.000 --> 00:00:03.240: Welcome to the weekly meeting.
+PREFIX:00:00:05.000 --> 00:00:08.120: First item is the release.
+"
+        );
+        let openai_whisper = "Detected language: English
+[00:00.000 --> 00:02.500]  Remember to buy milk.
+";
+        assert_eq!(
+            transcript(openai_whisper, "PREFIX:"),
+            "PREFIX:00:00.000 --> 00:02.500: Remember to buy milk.\n"
+        );
+    }
+}
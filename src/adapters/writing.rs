@@ -7,6 +7,10 @@ use anyhow::{Context, Result};
 use async_trait::async_trait;
 use tokio::io::{AsyncReadExt, AsyncWrite};
 
+/// An adapter that writes its output as text to a stream.
+///
+/// The adapter writes the line prefix itself at the start of each line it outputs (`{line_prefix}...`),
+/// so the output is not postprocessed again.
 #[async_trait]
 pub trait WritingFileAdapter: GetMetadata + Send + Sync + Clone {
     async fn adapt_write(
@@ -56,9 +60,9 @@ where
         let d2 = detection_reason.clone();
         let archive_recursion_depth = a.archive_recursion_depth + 1;
         let filepath_hint = format!("{}.txt", a.filepath_hint.to_string_lossy());
-        let postprocess = a.postprocess;
         let line_prefix = a.line_prefix.clone();
         let config = a.config.clone();
+        let passwords = a.passwords.clone();
         let joiner = tokio::spawn(async move {
             let x = d2;
             T::adapt_write(a, &x, Box::pin(w))
@@ -72,9 +76,77 @@ where
             filepath_hint: filepath_hint.into(),
             archive_recursion_depth,
             config,
+            passwords,
             inp: Box::pin(r.chain(join_handle_to_stream(joiner))),
             line_prefix,
-            postprocess,
+            // the adapter already wrote the line prefix and outputs utf-8 text
+            postprocess: false,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        adapters::AdapterMeta,
+        matching::{FastFileMatcher, FileMatcher},
+        preproc::loop_adapt,
+        test_utils::*,
+    };
+    use lazy_static::lazy_static;
+    use pretty_assertions::assert_eq;
+    use std::{io::Cursor, path::PathBuf};
+    use tokio::io::AsyncWriteExt;
+
+    #[derive(Clone)]
+    struct UppercaseAdapter;
+
+    lazy_static! {
+        static ref METADATA: AdapterMeta = AdapterMeta {
+            name: "uppercase".to_owned(),
+            version: 1,
+            description: "".to_owned(),
+            recurses: false,
+            outputs_unchanged_files: false,
+            fast_matchers: vec![FastFileMatcher::FileExtension("up".to_owned())],
+            slow_matchers: None,
+            keep_fast_matchers_if_accurate: false,
+            disabled_by_default: false,
+        };
+    }
+
+    impl GetMetadata for UppercaseAdapter {
+        fn metadata(&self) -> &AdapterMeta {
+            &METADATA
+        }
+    }
+
+    #[async_trait]
+    impl WritingFileAdapter for UppercaseAdapter {
+        async fn adapt_write(
+            mut a: AdaptInfo,
+            _detection_reason: &FileMatcher,
+            mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+        ) -> Result<()> {
+            let mut text = String::new();
+            a.inp.read_to_string(&mut text).await?;
+            for line in text.lines() {
+                let line = format!("{}{}\n", a.line_prefix, line.to_uppercase());
+                oup.write_all(line.as_bytes()).await?;
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn prefixed_once() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("shout.up"),
+            Box::pin(Cursor::new(b"hello\nworld\n".to_vec())),
+        );
+        let buf = adapted_to_vec(loop_adapt(&UppercaseAdapter, d, a).await?).await?;
+        assert_eq!(String::from_utf8(buf)?, "PREFIX:HELLO\nPREFIX:WORLD\n");
+        Ok(())
+    }
+}
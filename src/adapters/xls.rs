@@ -0,0 +1,629 @@
+//! Legacy Excel workbooks (BIFF5 and BIFF8, Excel 5 to 2003), the `Workbook` (or `Book`) stream of an OLE compound file.
+//!
+//! The rows are output in the same format as the xlsx adapter.
+
+use super::binary::{f64_at, u16_at, u32_at, utf16};
+use super::cfb::CompoundFile;
+use super::writing::WritingFileAdapter;
+use super::xlsx::write_row;
+use super::*;
+use anyhow::Result;
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::BTreeMap;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["xls", "xlt"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "xls".to_owned(),
+        version: 1,
+        description: "Reads legacy Excel spreadsheets (xls, Excel 5 to 2003). Outputs one line per row with its cells separated by tabs, prefixed with the sheet and cell of the row, e.g. Sheet1!A12:".to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.ms-excel".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct XlsAdapter;
+
+impl XlsAdapter {
+    pub fn new() -> XlsAdapter {
+        XlsAdapter
+    }
+}
+
+impl GetMetadata for XlsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+// record types
+const FORMULA: u16 = 0x0006;
+const EOF: u16 = 0x000a;
+const FILEPASS: u16 = 0x002f;
+const CONTINUE: u16 = 0x003c;
+const CODEPAGE: u16 = 0x0042;
+const BOUNDSHEET: u16 = 0x0085;
+const MULRK: u16 = 0x00bd;
+const RSTRING: u16 = 0x00d6;
+const SST: u16 = 0x00fc;
+const LABELSST: u16 = 0x00fd;
+const NUMBER: u16 = 0x0203;
+const LABEL: u16 = 0x0204;
+const BOOLERR: u16 = 0x0205;
+const STRING: u16 = 0x0207;
+const RK: u16 = 0x027e;
+const BOF: u16 = 0x0809;
+
+const BIFF8: u16 = 0x0600;
+
+// sheet types of BOUNDSHEET records, charts and VBA modules have no cells
+const WORKSHEET: u8 = 0;
+const MACRO_SHEET: u8 = 1;
+
+/// The (type, data) of the records of a substream starting at `pos`, until the end of the stream
+fn records(stream: &[u8], mut pos: usize) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let kind = u16_at(stream, pos)?;
+        let len = u16_at(stream, pos + 2)? as usize;
+        let data = stream.get(pos + 4..pos + 4 + len)?;
+        pos += 4 + len;
+        Some((kind, data))
+    })
+}
+
+/// The encoding of the byte strings of BIFF5 workbooks from the code page of the CODEPAGE record
+fn code_page_encoding(code_page: u16) -> &'static Encoding {
+    match code_page {
+        932 => encoding_rs::SHIFT_JIS,
+        936 => encoding_rs::GBK,
+        949 => encoding_rs::EUC_KR,
+        950 => encoding_rs::BIG5,
+        10000 | 32768 => encoding_rs::MACINTOSH,
+        p => Encoding::for_label(format!("windows-{p}").as_bytes())
+            .unwrap_or(encoding_rs::WINDOWS_1252),
+    }
+}
+
+/// Characters of a BIFF8 string, either the low bytes of UTF-16 code units (`high_byte` not set) or UTF-16LE
+fn biff8_chars(data: &[u8], high_byte: bool) -> String {
+    if high_byte {
+        utf16(data)
+    } else {
+        data.iter().map(|&b| b as char).collect()
+    }
+}
+
+/// The data of a record and its CONTINUE records.
+///
+/// When the characters of a string are split, each CONTINUE record starts with the flags of the rest of the characters.
+struct Continued<'a> {
+    segments: Vec<&'a [u8]>,
+    segment: usize,
+    pos: usize,
+}
+
+impl<'a> Continued<'a> {
+    fn new(data: &'a [u8]) -> Continued<'a> {
+        Continued {
+            segments: vec![data],
+            segment: 0,
+            pos: 0,
+        }
+    }
+
+    /// Remaining bytes of the current segment, moving to the next one if it is exhausted and `advance` is set
+    fn current(&mut self, advance: bool) -> Option<&'a [u8]> {
+        let segment = self.segments.get(self.segment)?;
+        if self.pos < segment.len() || !advance {
+            return Some(&segment[self.pos..]);
+        }
+        self.segment += 1;
+        self.pos = 0;
+        self.segments.get(self.segment).copied()
+    }
+
+    fn bytes(&mut self, mut n: usize) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(n);
+        while n > 0 {
+            let current = self.current(true)?;
+            let take = n.min(current.len());
+            out.extend_from_slice(&current[..take]);
+            self.pos += take;
+            n -= take;
+        }
+        Some(out)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        u16_at(&self.bytes(2)?, 0)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        u32_at(&self.bytes(4)?, 0)
+    }
+
+    /// A string of the shared string table (XLUnicodeRichExtendedString), without formatting runs and phonetic data
+    fn rich_string(&mut self) -> Option<String> {
+        let mut remaining = self.u16()? as usize;
+        let flags = self.u8()?;
+        let runs = if flags & 0x08 != 0 { self.u16()? } else { 0 };
+        let ext = if flags & 0x04 != 0 { self.u32()? } else { 0 };
+        let mut high_byte = flags & 0x01 != 0;
+        let mut text = String::new();
+        while remaining > 0 {
+            let mut current = self.current(false)?;
+            if current.is_empty() {
+                current = self.current(true)?;
+                high_byte = current.first()? & 0x01 != 0;
+                self.pos += 1;
+                current = &current[1..];
+            }
+            let width = if high_byte { 2 } else { 1 };
+            let count = remaining.min(current.len() / width);
+            if count == 0 {
+                // a character split across records
+                return None;
+            }
+            text.push_str(&biff8_chars(&current[..count * width], high_byte));
+            self.pos += count * width;
+            remaining -= count;
+        }
+        self.bytes(runs as usize * 4 + ext as usize)?;
+        Some(text)
+    }
+}
+
+/// The shared string table of an SST record and its CONTINUE records
+fn shared_strings(mut sst: Continued) -> Vec<String> {
+    let mut strings = Vec::new();
+    let Some(count) = sst.bytes(8).and_then(|header| u32_at(&header, 4)) else {
+        return strings;
+    };
+    for _ in 0..count {
+        match sst.rich_string() {
+            Some(s) => strings.push(s),
+            None => {
+                debug!("truncated shared string table");
+                break;
+            }
+        }
+    }
+    strings
+}
+
+struct Workbook {
+    biff8: bool,
+    encoding: &'static Encoding,
+    /// (name, sheet type, offset of the BOF record of the sheet)
+    sheets: Vec<(String, u8, usize)>,
+    shared: Vec<String>,
+}
+
+impl Workbook {
+    /// Read the workbook globals substream at the start of the stream
+    fn parse(stream: &[u8]) -> Result<Workbook> {
+        let mut records = records(stream, 0);
+        let (kind, bof) = records.next().context("empty workbook stream")?;
+        anyhow::ensure!(
+            kind == BOF,
+            "workbook stream does not start with a BOF record"
+        );
+        let version = u16_at(bof, 0).context("truncated BOF record")?;
+        let mut workbook = Workbook {
+            biff8: version == BIFF8,
+            encoding: encoding_rs::WINDOWS_1252,
+            sheets: Vec::new(),
+            shared: Vec::new(),
+        };
+        let mut sst: Option<Continued> = None;
+        let mut previous = kind;
+        for (kind, data) in records {
+            match kind {
+                EOF => break,
+                FILEPASS => anyhow::bail!("encrypted workbooks are not supported"),
+                CODEPAGE => {
+                    if let Some(code_page) = u16_at(data, 0) {
+                        workbook.encoding = code_page_encoding(code_page);
+                    }
+                }
+                BOUNDSHEET => {
+                    let (Some(offset), Some(&sheet_type)) = (u32_at(data, 0), data.get(5)) else {
+                        continue;
+                    };
+                    let name = workbook.short_string(&data[6..]).unwrap_or_default();
+                    workbook.sheets.push((name, sheet_type, offset as usize));
+                }
+                SST => sst = Some(Continued::new(data)),
+                CONTINUE if previous == SST => {
+                    if let Some(sst) = &mut sst {
+                        sst.segments.push(data);
+                    }
+                }
+                _ => {}
+            }
+            // CONTINUE records extend the last other record
+            if kind != CONTINUE {
+                previous = kind;
+            }
+        }
+        if let Some(sst) = sst {
+            workbook.shared = shared_strings(sst);
+        }
+        Ok(workbook)
+    }
+
+    /// A string with an 8 bit length (sheet names)
+    fn short_string(&self, data: &[u8]) -> Option<String> {
+        let len = *data.first()? as usize;
+        if self.biff8 {
+            let high_byte = data.get(1)? & 0x01 != 0;
+            let width = if high_byte { 2 } else { 1 };
+            Some(biff8_chars(data.get(2..2 + len * width)?, high_byte))
+        } else {
+            Some(self.bytes_string(data.get(1..1 + len)?))
+        }
+    }
+
+    /// A string with a 16 bit length (labels and results of formulas), formatting runs following it are ignored
+    fn string(&self, data: &[u8]) -> Option<String> {
+        let len = u16_at(data, 0)? as usize;
+        if self.biff8 {
+            let high_byte = data.get(2)? & 0x01 != 0;
+            let width = if high_byte { 2 } else { 1 };
+            // the characters of long labels may continue in a CONTINUE record, which is ignored
+            let chars = data.get(3..)?;
+            let end = chars.len().min(len * width) / width * width;
+            Some(biff8_chars(&chars[..end], high_byte))
+        } else {
+            Some(self.bytes_string(data.get(2..2 + len)?))
+        }
+    }
+
+    fn bytes_string(&self, data: &[u8]) -> String {
+        self.encoding
+            .decode_without_bom_handling(data)
+            .0
+            .into_owned()
+    }
+}
+
+/// The value of an RK number, a compressed integer or float with an optional factor of 1/100
+fn rk_number(rk: u32) -> f64 {
+    let n = if rk & 0x02 != 0 {
+        ((rk as i32) >> 2) as f64
+    } else {
+        f64::from_bits(((rk & 0xffff_fffc) as u64) << 32)
+    };
+    if rk & 0x01 != 0 { n / 100.0 } else { n }
+}
+
+fn bool_or_error(value: u8, is_error: bool) -> String {
+    if !is_error {
+        return if value != 0 { "TRUE" } else { "FALSE" }.to_string();
+    }
+    match value {
+        0x00 => "#NULL!",
+        0x07 => "#DIV/0!",
+        0x0f => "#VALUE!",
+        0x17 => "#REF!",
+        0x1d => "#NAME?",
+        0x24 => "#NUM!",
+        0x2a => "#N/A",
+        _ => "#ERROR!",
+    }
+    .to_string()
+}
+
+/// The cell values of the worksheet substream at `offset`, by (row, column)
+fn sheet_cells(
+    workbook: &Workbook,
+    stream: &[u8],
+    offset: usize,
+) -> Result<BTreeMap<(u16, u16), String>> {
+    let mut cells = BTreeMap::new();
+    let mut records = records(stream, offset);
+    anyhow::ensure!(
+        records.next().map(|(kind, _)| kind) == Some(BOF),
+        "sheet does not start with a BOF record"
+    );
+    // embedded charts are nested substreams
+    let mut depth = 0;
+    // the cell of a formula whose string result follows in a STRING record
+    let mut string_result = None;
+    for (kind, data) in records {
+        match kind {
+            BOF => depth += 1,
+            EOF if depth == 0 => break,
+            EOF => depth -= 1,
+            _ => {}
+        }
+        if depth > 0 {
+            continue;
+        }
+        if kind == STRING {
+            if let (Some(cell), Some(s)) = (string_result.take(), workbook.string(data)) {
+                cells.insert(cell, s);
+            }
+            continue;
+        }
+        if data.len() < 6 {
+            continue;
+        }
+        let cell = (u16_at(data, 0).unwrap(), u16_at(data, 2).unwrap());
+        let value = match kind {
+            LABELSST => u32_at(data, 6).and_then(|i| workbook.shared.get(i as usize).cloned()),
+            LABEL | RSTRING => workbook.string(&data[6..]),
+            NUMBER => f64_at(data, 6).map(|n| n.to_string()),
+            RK => u32_at(data, 6).map(|rk| rk_number(rk).to_string()),
+            MULRK => {
+                // (format, RK) pairs and the last column
+                let values: Option<Vec<_>> = data[4..data.len() - 2]
+                    .chunks_exact(6)
+                    .enumerate()
+                    .map(|(i, rk)| {
+                        let column = u16::try_from(i).ok().and_then(|i| cell.1.checked_add(i))?;
+                        Some(((cell.0, column), rk_number(u32_at(rk, 2)?).to_string()))
+                    })
+                    .collect();
+                // the columns of a corrupt record can run past the last one, skip it
+                cells.extend(values.into_iter().flatten());
+                None
+            }
+            BOOLERR => match (data.get(6), data.get(7)) {
+                (Some(&value), Some(&is_error)) => Some(bool_or_error(value, is_error != 0)),
+                _ => None,
+            },
+            FORMULA => match data.get(6..14) {
+                // results that are not numbers are marked by 0xffff in the last two bytes
+                Some(result) if result[6..] == [0xff, 0xff] => match result[0] {
+                    0 => {
+                        string_result = Some(cell);
+                        None
+                    }
+                    1 => Some(bool_or_error(result[2], false)),
+                    2 => Some(bool_or_error(result[2], true)),
+                    _ => None,
+                },
+                Some(_) => f64_at(data, 6).map(|n| n.to_string()),
+                None => None,
+            },
+            _ => None,
+        };
+        if let Some(value) = value {
+            cells.insert(cell, value);
+        }
+    }
+    Ok(cells)
+}
+
+/// Convert the workbook stream to text, the sheets in the order of the workbook
+fn workbook_text(stream: &[u8], line_prefix: &str) -> Result<String> {
+    let workbook = Workbook::parse(stream)?;
+    let mut out = String::new();
+    for (name, sheet_type, offset) in &workbook.sheets {
+        if *sheet_type != WORKSHEET && *sheet_type != MACRO_SHEET {
+            continue;
+        }
+        let cells = match sheet_cells(&workbook, stream, *offset) {
+            Ok(cells) => cells,
+            Err(e) => {
+                warn!("could not read sheet {name}: {e:#}");
+                continue;
+            }
+        };
+        let mut row_cells: Vec<(usize, String)> = Vec::new();
+        let mut cells = cells.into_iter().peekable();
+        while let Some(((row, column), text)) = cells.next() {
+            let text = text.replace(['\r', '\n', '\t'], " ");
+            if !text.trim().is_empty() {
+                row_cells.push((column as usize, text));
+            }
+            if cells
+                .peek()
+                .is_none_or(|((next_row, _), _)| *next_row != row)
+            {
+                write_row(&row_cells, name, row as usize + 1, line_prefix, &mut out);
+                row_cells.clear();
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for XlsAdapter {
+    async fn adapt_write(
+        mut ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut content = Vec::new();
+        ai.inp.read_to_end(&mut content).await?;
+        let cf = CompoundFile::parse(content)?;
+        let stream = cf
+            .child(0, "Workbook")
+            .or_else(|| cf.child(0, "Book"))
+            .context("no Workbook stream, not an Excel workbook")?;
+        let text = workbook_text(&cf.read(stream)?, &ai.line_prefix)?;
+        oup.write_all(text.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn record(kind: u16, data: &[u8]) -> Vec<u8> {
+        let mut r = kind.to_le_bytes().to_vec();
+        r.extend((data.len() as u16).to_le_bytes());
+        r.extend(data);
+        r
+    }
+
+    fn cell(kind: u16, row: u16, column: u16, value: &[u8]) -> Vec<u8> {
+        let mut data = [row.to_le_bytes(), column.to_le_bytes(), [0, 0]].concat();
+        data.extend(value);
+        record(kind, &data)
+    }
+
+    /// An XLUnicodeString with 8 bit characters
+    fn label(s: &str) -> Vec<u8> {
+        [&(s.len() as u16).to_le_bytes()[..], &[0], s.as_bytes()].concat()
+    }
+
+    fn bof(substream: u16) -> Vec<u8> {
+        record(
+            BOF,
+            &[&BIFF8.to_le_bytes()[..], &substream.to_le_bytes(), &[0; 12]].concat(),
+        )
+    }
+
+    /// A BIFF8 workbook stream with two worksheets and a chart
+    fn workbook() -> Vec<u8> {
+        let mut data = cell(LABELSST, 0, 0, &0u32.to_le_bytes());
+        data.extend(cell(LABEL, 0, 1, &label("Amount")));
+        data.extend(cell(LABELSST, 2, 1, &1u32.to_le_bytes()));
+        data.extend(cell(NUMBER, 2, 3, &12.5f64.to_le_bytes()));
+        data.extend(cell(BOOLERR, 2, 4, &[1, 0]));
+        // 1234 and 12.34
+        let mut mulrk = [3u16.to_le_bytes(), 0u16.to_le_bytes()].concat();
+        for rk in [(1234 << 2) | 2u32, (1234 << 2) | 3] {
+            mulrk.extend([0, 0]);
+            mulrk.extend(rk.to_le_bytes());
+        }
+        mulrk.extend(1u16.to_le_bytes());
+        data.extend(record(MULRK, &mulrk));
+        data.extend(cell(RK, 3, 2, &0x405e_c000u32.to_le_bytes()));
+        data.extend(cell(BOOLERR, 4, 0, &[0x07, 1]));
+        // an embedded chart
+        data.extend(bof(0x20));
+        data.extend(cell(NUMBER, 9, 9, &1f64.to_le_bytes()));
+        data.extend(record(EOF, &[]));
+        data.extend(cell(LABEL, 5, 0, &label("")));
+        data.extend(record(EOF, &[]));
+        let mut data_sheet = bof(0x10);
+        data_sheet.extend(data);
+
+        let mut summary = bof(0x10);
+        let mut formula = vec![0, 0, 0, 0, 0, 0, 0xff, 0xff];
+        formula.extend([0; 6]);
+        summary.extend(cell(FORMULA, 11, 26, &formula));
+        summary.extend(record(STRING, &label("12.5 total")));
+        summary.extend(record(EOF, &[]));
+
+        let chart = [bof(0x20), record(EOF, &[])].concat();
+
+        // "Name", then "Ali|ce & Bob" and "東京" split across CONTINUE records
+        let mut sst = [3u32.to_le_bytes(), 3u32.to_le_bytes()].concat();
+        sst.extend(label("Name"));
+        sst.extend([11, 0, 0]);
+        sst.extend(b"Ali");
+        let mut continued = vec![1];
+        continued.extend("ce & Bob".encode_utf16().flat_map(u16::to_le_bytes));
+        continued.extend([2, 0, 1]);
+        continued.extend("東".encode_utf16().flat_map(u16::to_le_bytes));
+        let mut last = vec![1];
+        last.extend("京".encode_utf16().flat_map(u16::to_le_bytes));
+
+        let globals = |offsets: [usize; 3]| {
+            let mut g = bof(0x05);
+            g.extend(record(CODEPAGE, &1200u16.to_le_bytes()));
+            for (name, sheet_type, offset) in [
+                ("Summary", WORKSHEET, offsets[0]),
+                ("Data", WORKSHEET, offsets[1]),
+                ("Chart1", 2, offsets[2]),
+            ] {
+                let mut b = (offset as u32).to_le_bytes().to_vec();
+                b.extend([0, sheet_type, name.len() as u8, 0]);
+                b.extend(name.as_bytes());
+                g.extend(record(BOUNDSHEET, &b));
+            }
+            g.extend(record(SST, &sst));
+            g.extend(record(CONTINUE, &continued));
+            g.extend(record(CONTINUE, &last));
+            g.extend(record(EOF, &[]));
+            g
+        };
+        let start = globals([0; 3]).len();
+        let offsets = [
+            start,
+            start + summary.len(),
+            start + summary.len() + data_sheet.len(),
+        ];
+        [globals(offsets), summary, data_sheet, chart].concat()
+    }
+
+    #[tokio::test]
+    async fn sheets() -> Result<()> {
+        let adapter = XlsAdapter::new();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("book.xls"),
+            Box::pin(Cursor::new(create_cfb(&[("Workbook", &workbook())]))),
+        );
+        let buf = adapted_to_vec(loop_adapt(&adapter, d, a).await?).await?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:Summary!AA12: 12.5 total
+PREFIX:Data!A1: Name\tAmount
+PREFIX:Data!B3: Alice & Bob\t\t12.5\tTRUE
+PREFIX:Data!A4: 1234\t12.34\t123
+PREFIX:Data!A5: #DIV/0!
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mulrk_column_overflow() -> Result<()> {
+        let workbook = Workbook::parse(&workbook())?;
+        let mut mulrk = [0u16.to_le_bytes(), u16::MAX.to_le_bytes()].concat();
+        for rk in [(1 << 2) | 2u32, (2 << 2) | 2] {
+            mulrk.extend([0, 0]);
+            mulrk.extend(rk.to_le_bytes());
+        }
+        mulrk.extend(0u16.to_le_bytes());
+        let mut stream = bof(0x10);
+        stream.extend(record(MULRK, &mulrk));
+        stream.extend(cell(RK, 1, 0, &((3 << 2) | 2u32).to_le_bytes()));
+        stream.extend(record(EOF, &[]));
+
+        let cells = sheet_cells(&workbook, &stream, 0)?;
+        assert_eq!(
+            cells.into_iter().collect::<Vec<_>>(),
+            [((1, 0), "3".to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn strings() -> Result<()> {
+        let workbook = Workbook::parse(&workbook())?;
+        assert_eq!(workbook.shared, ["Name", "Alice & Bob", "東京"]);
+        assert_eq!(rk_number(0x3ff0_0001), 0.01);
+        assert_eq!(rk_number((-5i32 << 2) as u32 | 2), -5.0);
+        Ok(())
+    }
+}
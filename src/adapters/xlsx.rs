@@ -0,0 +1,327 @@
+use super::writing::WritingFileAdapter;
+use super::xml::{XmlEvent, XmlReader, local_name, ooxml_relationships};
+use super::*;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use log::*;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["xlsx", "xlsm"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "xlsx".to_owned(),
+        version: 1,
+        description: "Reads Excel spreadsheets (xlsx). Outputs one line per row with its cells separated by tabs, prefixed with the sheet and cell of the row, e.g. Sheet1!A12:".to_owned(),
+        recurses: false,
+        outputs_unchanged_files: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct XlsxAdapter;
+
+impl XlsxAdapter {
+    pub fn new() -> XlsxAdapter {
+        XlsxAdapter
+    }
+}
+
+impl GetMetadata for XlsxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// The text of the items of the shared string table (`xl/sharedStrings.xml`)
+fn shared_strings(xml: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    let mut in_text = false;
+    // phonetic runs (furigana) repeat the text
+    let mut in_phonetic = false;
+    for event in XmlReader::new(xml) {
+        match &event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => match local_name(name) {
+                "si" if !self_closing => current.clear(),
+                "si" => strings.push(String::new()),
+                "t" => in_text = !self_closing,
+                "rPh" => in_phonetic = !self_closing,
+                _ => {}
+            },
+            XmlEvent::End { name } => match local_name(name) {
+                "si" => strings.push(std::mem::take(&mut current)),
+                "t" => in_text = false,
+                "rPh" => in_phonetic = false,
+                _ => {}
+            },
+            XmlEvent::Text(text) if in_text && !in_phonetic => current.push_str(text),
+            XmlEvent::Text(_) => {}
+        }
+    }
+    strings
+}
+
+/// Zero-based column index of a cell reference like `AB12`
+fn column_index(cell: &str) -> Option<usize> {
+    let letters: Vec<u8> = cell.bytes().take_while(u8::is_ascii_alphabetic).collect();
+    if letters.is_empty() {
+        return None;
+    }
+    let n = letters.iter().fold(0usize, |n, c| {
+        n * 26 + (c.to_ascii_uppercase() - b'A') as usize + 1
+    });
+    Some(n - 1)
+}
+
+/// Column name of a zero-based column index (0 -> A, 27 -> AB)
+pub fn column_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ascii")
+}
+
+/// Output a row as `{prefix}{sheet}!{first cell}: {cells separated by tabs}`, the cells are (column, text) sorted by column
+pub fn write_row(
+    cells: &[(usize, String)],
+    sheet: &str,
+    row_number: usize,
+    line_prefix: &str,
+    out: &mut String,
+) {
+    let Some(&(first, _)) = cells.first() else {
+        return;
+    };
+    out.push_str(&format!(
+        "{line_prefix}{sheet}!{}{row_number}: ",
+        column_name(first)
+    ));
+    let mut previous = first;
+    for (i, (column, text)) in cells.iter().enumerate() {
+        if i > 0 {
+            // keep empty cells so the columns line up
+            out.push_str(&"\t".repeat(column.saturating_sub(previous).max(1)));
+        }
+        out.push_str(text);
+        previous = *column;
+    }
+    out.push('\n');
+}
+
+/// Output the rows of a worksheet as `{prefix}{sheet}!{first cell}: {cells separated by tabs}`
+fn write_sheet(xml: &str, sheet: &str, shared: &[String], line_prefix: &str, out: &mut String) {
+    let mut row_number = 0;
+    // (column, value)
+    let mut cells: Vec<(usize, String)> = Vec::new();
+    let mut cell_type = String::new();
+    let mut column = 0;
+    let mut value = String::new();
+    let mut inline = String::new();
+    // the element whose text is collected (v or t of an inline string)
+    let mut in_value = false;
+    let mut in_inline = false;
+    for event in XmlReader::new(xml) {
+        match &event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => match local_name(name) {
+                "row" => {
+                    row_number = event
+                        .attr("r")
+                        .and_then(|r| r.parse().ok())
+                        .unwrap_or(row_number + 1);
+                    cells.clear();
+                }
+                "c" => {
+                    column = event
+                        .attr("r")
+                        .and_then(column_index)
+                        .unwrap_or_else(|| cells.last().map(|(c, _)| c + 1).unwrap_or(0));
+                    cell_type = event.attr("t").unwrap_or("n").to_string();
+                    value.clear();
+                    inline.clear();
+                }
+                "v" => in_value = !self_closing,
+                "t" => in_inline = !self_closing,
+                _ => {}
+            },
+            XmlEvent::Text(text) if in_value => value.push_str(text),
+            XmlEvent::Text(text) if in_inline => inline.push_str(text),
+            XmlEvent::Text(_) => {}
+            XmlEvent::End { name } => match local_name(name) {
+                "v" => in_value = false,
+                "t" => in_inline = false,
+                "c" => {
+                    let text = match cell_type.as_str() {
+                        "s" => value
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|i| shared.get(i))
+                            .cloned()
+                            .unwrap_or_default(),
+                        "inlineStr" => std::mem::take(&mut inline),
+                        "b" => match value.trim() {
+                            "1" => "TRUE".to_string(),
+                            _ => "FALSE".to_string(),
+                        },
+                        _ => std::mem::take(&mut value),
+                    };
+                    let text = text.replace(['\r', '\n', '\t'], " ");
+                    if !text.trim().is_empty() {
+                        cells.push((column, text));
+                    }
+                }
+                "row" => write_row(&cells, sheet, row_number, line_prefix, out),
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Convert a workbook to text, the sheets in the order of the workbook
+fn workbook_text(files: &[(String, Vec<u8>)], line_prefix: &str) -> Result<String> {
+    let get = |name: &str| {
+        files
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, content)| String::from_utf8_lossy(content))
+    };
+    let workbook = get("xl/workbook.xml").context("xl/workbook.xml not found")?;
+    let rels = ooxml_relationships(&get("xl/_rels/workbook.xml.rels").unwrap_or_default(), "xl");
+    let shared = get("xl/sharedStrings.xml")
+        .map(|s| shared_strings(&s))
+        .unwrap_or_default();
+    let mut out = String::new();
+    for sheet in XmlReader::new(&workbook)
+        .filter(|e| matches!(e, XmlEvent::Start { name, .. } if local_name(name) == "sheet"))
+    {
+        let name = sheet.attr("name").unwrap_or_default();
+        let Some(path) = sheet.attr("id").and_then(|id| rels.get(id)) else {
+            warn!("sheet {name} not found in workbook relationships");
+            continue;
+        };
+        match get(path) {
+            Some(xml) => write_sheet(&xml, name, &shared, line_prefix, &mut out),
+            None => debug!("{path} of sheet {name} missing"),
+        }
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl WritingFileAdapter for XlsxAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let AdaptInfo {
+            inp,
+            filepath_hint,
+            is_real_file,
+            line_prefix,
+            ..
+        } = ai;
+        let files = zip::read_zip_files(inp, &filepath_hint, is_real_file).await?;
+        let text = workbook_text(&files, &line_prefix)?;
+        oup.write_all(text.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use async_zip::{Compression, ZipEntryBuilder, write::ZipFileWriter};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    async fn create_xlsx() -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        let files = [
+            (
+                "xl/workbook.xml",
+                r#"<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
+<sheets><sheet name="Summary" sheetId="2" r:id="rId2"/><sheet name="Data" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="/xl/worksheets/sheet2.xml"/>
+<Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/>
+</Relationships>"#,
+            ),
+            (
+                "xl/sharedStrings.xml",
+                r#"<sst><si><t>Name</t></si><si><r><t>Ali</t></r><r><t xml:space="preserve">ce &amp; Bob</t></r></si></sst>"#,
+            ),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet><sheetData>
+<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="inlineStr"><is><t>Amount</t></is></c></row>
+<row r="3"><c r="B3" t="s"><v>1</v></c><c r="D3"><v>12.5</v></c><c r="E3" t="b"><v>1</v></c></row>
+<row r="4"><c r="A4"/></row>
+</sheetData></worksheet>"#,
+            ),
+            (
+                "xl/worksheets/sheet2.xml",
+                r#"<worksheet><sheetData><row r="12"><c r="AA12" t="str"><f>SUM(Data!D3)</f><v>12.5</v></c></row></sheetData></worksheet>"#,
+            ),
+        ];
+        for (name, content) in files {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Deflate);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    #[tokio::test]
+    async fn sheets() -> Result<()> {
+        let adapter = XlsxAdapter::new();
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("book.xlsx"),
+            Box::pin(Cursor::new(create_xlsx().await?)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&adapter, d, a).await?).await?;
+
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:Summary!AA12: 12.5
+PREFIX:Data!A1: Name\tAmount
+PREFIX:Data!B3: Alice & Bob\t\t12.5\tTRUE
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn columns() {
+        for (name, index) in [("A", 0), ("Z", 25), ("AA", 26), ("AB", 27), ("XFD", 16383)] {
+            assert_eq!(column_index(&format!("{name}7")), Some(index));
+            assert_eq!(column_name(index), name);
+        }
+    }
+}
@@ -0,0 +1,439 @@
+//! A minimal XML / XHTML tokenizer for the adapters of zip-based document formats.
+//!
+//! It doesn't validate anything and doesn't resolve namespaces, it only needs to be good enough to get the text out of documents.
+
+use std::borrow::Cow;
+
+#[derive(Debug, PartialEq)]
+pub enum XmlEvent<'a> {
+    Start {
+        name: &'a str,
+        attrs: Vec<(&'a str, String)>,
+        self_closing: bool,
+    },
+    End {
+        name: &'a str,
+    },
+    Text(Cow<'a, str>),
+}
+
+impl XmlEvent<'_> {
+    /// The value of the attribute, ignoring its namespace prefix
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        match self {
+            XmlEvent::Start { attrs, .. } => attrs
+                .iter()
+                .find(|(k, _)| local_name(k) == name)
+                .map(|(_, v)| v.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// The element name without the namespace prefix, e.g. `title` for `dc:title`
+pub fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+pub struct XmlReader<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> XmlReader<'a> {
+    pub fn new(s: &'a str) -> XmlReader<'a> {
+        XmlReader {
+            s: s.trim_start_matches('\u{feff}'),
+            pos: 0,
+        }
+    }
+
+    fn parse_tag(tag: &'a str) -> XmlEvent<'a> {
+        if let Some(name) = tag.strip_prefix('/') {
+            return XmlEvent::End { name: name.trim() };
+        }
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(tag.len());
+        let mut attrs = Vec::new();
+        let mut rest = &tag[name_end..];
+        while let Some(eq) = rest.find('=') {
+            let key = rest[..eq].trim();
+            let value = rest[eq + 1..].trim_start();
+            let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                break;
+            };
+            let Some(end) = value[1..].find(quote) else {
+                break;
+            };
+            attrs.push((key, unescape(&value[1..end + 1]).into_owned()));
+            rest = &value[end + 2..];
+        }
+        XmlEvent::Start {
+            name: &tag[..name_end],
+            attrs,
+            self_closing,
+        }
+    }
+}
+
+impl<'a> Iterator for XmlReader<'a> {
+    type Item = XmlEvent<'a>;
+
+    fn next(&mut self) -> Option<XmlEvent<'a>> {
+        loop {
+            let rest = &self.s[self.pos..];
+            if rest.is_empty() {
+                return None;
+            }
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.pos += end;
+                return Some(XmlEvent::Text(unescape(&rest[..end])));
+            }
+            // comments, processing instructions and CDATA can contain '>'
+            if let Some((start, end)) = [("<!--", "-->"), ("<?", "?>"), ("<![CDATA[", "]]>")]
+                .into_iter()
+                .find(|(start, _)| rest.starts_with(start))
+            {
+                let body = &rest[start.len()..];
+                let len = body.find(end).unwrap_or(body.len());
+                self.pos += (start.len() + len + end.len()).min(rest.len());
+                if start == "<![CDATA[" {
+                    return Some(XmlEvent::Text(Cow::Borrowed(&body[..len])));
+                }
+                continue;
+            }
+            if rest.starts_with("<!") {
+                // doctype, may contain an internal subset in brackets
+                let end = match (rest.find('['), rest.find('>')) {
+                    (Some(b), Some(e)) if b < e => rest.find("]>").map(|i| i + 2),
+                    (_, e) => e.map(|i| i + 1),
+                };
+                self.pos += end.unwrap_or(rest.len());
+                continue;
+            }
+            let mut quote = None;
+            let end = rest
+                .char_indices()
+                .skip(1)
+                .find(|&(_, c)| match quote {
+                    Some(q) if c == q => {
+                        quote = None;
+                        false
+                    }
+                    Some(_) => false,
+                    None if c == '"' || c == '\'' => {
+                        quote = Some(c);
+                        false
+                    }
+                    None => c == '>',
+                })
+                .map(|(i, _)| i);
+            let Some(end) = end else {
+                self.pos = self.s.len();
+                return None;
+            };
+            self.pos += end + 1;
+            return Some(XmlReader::parse_tag(rest[1..end].trim()));
+        }
+    }
+}
+
+fn named_entity(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "amp" => "&",
+        "lt" => "<",
+        "gt" => ">",
+        "quot" => "\"",
+        "apos" => "'",
+        // the html entities that are common in ebooks
+        "nbsp" => "\u{a0}",
+        "shy" => "\u{ad}",
+        "ndash" => "–",
+        "mdash" => "—",
+        "hellip" => "…",
+        "lsquo" => "‘",
+        "rsquo" => "’",
+        "ldquo" => "“",
+        "rdquo" => "”",
+        "laquo" => "«",
+        "raquo" => "»",
+        "copy" => "©",
+        "reg" => "®",
+        "trade" => "™",
+        "deg" => "°",
+        "middot" => "·",
+        "bull" => "•",
+        "times" => "×",
+        "euro" => "€",
+        _ => return None,
+    })
+}
+
+/// Replace the character and entity references in text or attribute values
+pub fn unescape(s: &str) -> Cow<'_, str> {
+    if !s.contains('&') {
+        return Cow::Borrowed(s);
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let replacement = rest[1..].find(';').filter(|&i| i <= 32).and_then(|semi| {
+            let name = &rest[1..semi + 1];
+            let c = if let Some(hex) = name.strip_prefix("#x").or(name.strip_prefix("#X")) {
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+            } else if let Some(dec) = name.strip_prefix('#') {
+                dec.parse().ok().and_then(char::from_u32)
+            } else {
+                None
+            };
+            let text = match c {
+                Some(c) => c.to_string(),
+                None => named_entity(name)?.to_string(),
+            };
+            Some((text, semi + 2))
+        });
+        match replacement {
+            Some((text, len)) => {
+                out.push_str(&text);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// Resolve a (percent-encoded) link in a zip-based document relative to the directory of the file it is in
+pub fn resolve_href(base_dir: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut decoded = Vec::with_capacity(href.len());
+    let mut bytes = href.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex: Vec<u8> = bytes.clone().take(2).collect();
+            if let Some(v) = std::str::from_utf8(&hex)
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                decoded.push(v);
+                bytes.nth(1);
+                continue;
+            }
+        }
+        decoded.push(b);
+    }
+    let href = String::from_utf8_lossy(&decoded);
+    let mut parts: Vec<&str> = if href.starts_with('/') {
+        vec![]
+    } else {
+        base_dir.split('/').filter(|p| !p.is_empty()).collect()
+    };
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Parse an Office Open XML relationships part (`_rels/*.rels`) into relationship id -> target path in the zip file.
+///
+/// `base_dir` is the directory of the part the relationships belong to.
+pub fn ooxml_relationships(
+    rels: &str,
+    base_dir: &str,
+) -> std::collections::HashMap<String, String> {
+    XmlReader::new(rels)
+        .filter(|e| matches!(e, XmlEvent::Start { name, .. } if local_name(name) == "Relationship"))
+        .filter(|e| e.attr("TargetMode") != Some("External"))
+        .filter_map(|e| {
+            Some((
+                e.attr("Id")?.to_string(),
+                resolve_href(base_dir, e.attr("Target")?),
+            ))
+        })
+        .collect()
+}
+
+/// Elements that start a new line in the text output
+const HTML_BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+fn end_line(line: &mut String, out: &mut String) {
+    let trimmed = line.trim();
+    if !trimmed.is_empty() {
+        out.push_str(trimmed);
+        out.push('\n');
+    }
+    line.clear();
+}
+
+/// Convert (X)HTML to plain text with one line per paragraph.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut line = String::new();
+    // depth in elements whose content is not text (head, script, style, title)
+    let mut skip_depth = 0;
+    let mut pre_depth = 0;
+    for event in XmlReader::new(html) {
+        match &event {
+            XmlEvent::Start {
+                name, self_closing, ..
+            } => {
+                let self_closing = *self_closing;
+                let name = local_name(name).to_ascii_lowercase();
+                if matches!(name.as_str(), "head" | "script" | "style" | "title") {
+                    if !self_closing {
+                        skip_depth += 1;
+                    }
+                } else if HTML_BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    end_line(&mut line, &mut out);
+                    if name == "pre" && !self_closing {
+                        pre_depth += 1;
+                    }
+                } else if matches!(name.as_str(), "img" | "image")
+                    && let Some(alt) = event.attr("alt")
+                {
+                    line.push_str(alt);
+                }
+            }
+            XmlEvent::End { name } => {
+                let name = local_name(name).to_ascii_lowercase();
+                if matches!(name.as_str(), "head" | "script" | "style" | "title") {
+                    skip_depth = (skip_depth - 1).max(0);
+                } else if HTML_BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    end_line(&mut line, &mut out);
+                    if name == "pre" {
+                        pre_depth = (pre_depth - 1).max(0);
+                    }
+                }
+            }
+            XmlEvent::Text(text) if skip_depth == 0 => {
+                if pre_depth > 0 {
+                    let mut lines = text.split('\n');
+                    line.push_str(lines.next().unwrap_or_default());
+                    for l in lines {
+                        end_line(&mut line, &mut out);
+                        line.push_str(l);
+                    }
+                    continue;
+                }
+                // collapse whitespace like a browser
+                let starts_with_space = text.starts_with(|c: char| c.is_ascii_whitespace());
+                if starts_with_space && !line.ends_with(' ') && !line.is_empty() {
+                    line.push(' ');
+                }
+                let mut words = text.split_ascii_whitespace().peekable();
+                while let Some(word) = words.next() {
+                    line.push_str(word);
+                    if words.peek().is_some() {
+                        line.push(' ');
+                    }
+                }
+                if text.ends_with(|c: char| c.is_ascii_whitespace()) && !line.is_empty() {
+                    line.push(' ');
+                }
+            }
+            XmlEvent::Text(_) => {}
+        }
+    }
+    end_line(&mut line, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn html() {
+        let html = r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>Ignored</title><style>p { color: red }</style></head>
+<body><h1>Chapter  1</h1>
+<!-- a comment with <p> -->
+<p>It was a <em>dark</em>
+and stormy&nbsp;night &amp; &#x2014;<br/>more</p>
+<p><img src="a.png" alt="a picture"/></p><pre>line 1
+  line 2</pre></body></html>"#;
+        assert_eq!(
+            html_to_text(html),
+            "Chapter 1\nIt was a dark and stormy\u{a0}night & —\nmore\na picture\nline 1\nline 2\n"
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        let events: Vec<_> =
+            XmlReader::new(r#"<a:b c="1 &lt; 2" d='x>y'/>t<![CDATA[<raw>]]></a:b>"#).collect();
+        assert_eq!(events[0].attr("c"), Some("1 < 2"));
+        assert_eq!(events[0].attr("d"), Some("x>y"));
+        assert_eq!(
+            &events[1..],
+            &[
+                XmlEvent::Text("t".into()),
+                XmlEvent::Text("<raw>".into()),
+                XmlEvent::End { name: "a:b" }
+            ]
+        );
+    }
+
+    #[test]
+    fn href() {
+        assert_eq!(
+            resolve_href("OEBPS", "Text/a%20b.xhtml#p1"),
+            "OEBPS/Text/a b.xhtml"
+        );
+        assert_eq!(
+            resolve_href("OEBPS/Text", "../Images/x.png"),
+            "OEBPS/Images/x.png"
+        );
+        assert_eq!(resolve_href("", "/c.xhtml"), "c.xhtml");
+    }
+}
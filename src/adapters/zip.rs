@@ -1,20 +1,25 @@
+use super::binary::{u16_at, u32_at, u64_at};
 use super::*;
 use crate::print_bytes;
 use anyhow::*;
 use async_stream::stream;
+use encoding_rs::Encoding;
 use lazy_static::lazy_static;
 use log::*;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
 
 // TODO: allow users to configure file extensions instead of hard coding the list
 // https://github.com/phiresky/ripgrep-all/pull/208#issuecomment-2173241243
-static EXTENSIONS: &[&str] = &["zip", "jar", "xpi", "kra", "snagx"];
+static EXTENSIONS: &[&str] = &["zip", "jar", "xpi", "kra", "snagx", "npz"];
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "zip".to_owned(),
-        version: 1,
-        description: "Reads a zip file as a stream and recurses down into its contents".to_owned(),
+        version: 4,
+        description: "Reads a zip file as a stream and recurses down into its contents.\nEncrypted zip files, Zip64 archives (over 4 GB or 65535 files) and zip files with names that are not UTF-8 (decoded with --rga-fallback-encodings or CP437) are read with `7z` (see the 7z adapter for the passwords)".to_owned(),
         recurses: true,
+        outputs_unchanged_files: true,
         fast_matchers: EXTENSIONS
             .iter()
             .map(|s| FastFileMatcher::FileExtension(s.to_string()))
@@ -38,6 +43,215 @@ impl GetMetadata for ZipAdapter {
     }
 }
 
+const LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+const CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x05\x06";
+const ZIP64_END_OF_CENTRAL_DIRECTORY: &[u8] = b"PK\x06\x06";
+const ZIP64_LOCATOR: &[u8] = b"PK\x06\x07";
+/// CP437, the original encoding of names in zip files
+const DEFAULT_CODE_PAGE: u16 = 437;
+
+/// Whether the first file of a zip archive is encrypted (bit 0 of the flags in its local file header)
+fn is_encrypted(header: &[u8]) -> bool {
+    header.len() >= 8 && header.starts_with(LOCAL_HEADER) && header[6] & 1 != 0
+}
+
+/// The name of a zip entry as stored in its header
+#[derive(Debug)]
+struct RawName {
+    name: Vec<u8>,
+    flags: u16,
+    extra: Vec<u8>,
+}
+
+impl RawName {
+    /// Whether the name is marked as UTF-8 (bit 11 of the flags), has an Info-ZIP Unicode Path extra field (0x7075)
+    /// or is valid UTF-8 anyways (e.g. ASCII, or written by a tool that doesn't set the flag)
+    fn is_utf8(&self) -> bool {
+        self.flags & (1 << 11) != 0
+            || has_extra_field(&self.extra, 0x7075)
+            || std::str::from_utf8(&self.name).is_ok()
+    }
+
+    /// Whether the sizes or offset of the file are in a Zip64 extended information extra field (0x0001), i.e. over 4 GB
+    fn is_zip64(&self) -> bool {
+        has_extra_field(&self.extra, 0x0001)
+    }
+}
+
+fn has_extra_field(mut extra: &[u8], id: u16) -> bool {
+    while let (Some(field_id), Some(len)) = (u16_at(extra, 0), u16_at(extra, 2)) {
+        if field_id == id {
+            return true;
+        }
+        let len = len as usize;
+        extra = extra.get(4 + len..).unwrap_or_default();
+    }
+    false
+}
+
+/// The name of the first file from the local header at the start of a zip file
+fn local_header_name(header: &[u8]) -> Option<RawName> {
+    if !header.starts_with(LOCAL_HEADER) {
+        return None;
+    }
+    let name_end = 30 + u16_at(header, 26)? as usize;
+    let extra_end = name_end + u16_at(header, 28)? as usize;
+    Some(RawName {
+        name: header.get(30..name_end)?.to_vec(),
+        flags: u16_at(header, 6)?,
+        extra: header.get(name_end..extra_end)?.to_vec(),
+    })
+}
+
+fn parse_central_directory(mut cd: &[u8]) -> Vec<RawName> {
+    let mut names = Vec::new();
+    while cd.starts_with(CENTRAL_HEADER) {
+        let (Some(flags), Some(name_len), Some(extra_len), Some(comment_len)) = (
+            u16_at(cd, 8),
+            u16_at(cd, 28),
+            u16_at(cd, 30),
+            u16_at(cd, 32),
+        ) else {
+            break;
+        };
+        let name_end = 46 + name_len as usize;
+        let extra_end = name_end + extra_len as usize;
+        let comment_end = extra_end + comment_len as usize;
+        let (Some(name), Some(extra)) = (cd.get(46..name_end), cd.get(name_end..extra_end)) else {
+            break;
+        };
+        names.push(RawName {
+            name: name.to_vec(),
+            flags,
+            extra: extra.to_vec(),
+        });
+        cd = cd.get(comment_end..).unwrap_or_default();
+    }
+    names
+}
+
+/// Where the central directory of a zip file is, from the records at its end
+#[derive(Debug, PartialEq)]
+enum CentralDirectoryEnd {
+    Plain {
+        size: u64,
+        offset: u64,
+    },
+    /// Zip64 archives (over 4 GB or 65535 files) have another end record at the given offset
+    Zip64 {
+        record_offset: u64,
+    },
+}
+
+/// Find the end of central directory record (22 bytes, ending with a comment of up to 64 KB) in the end of a zip file
+fn central_directory_end(tail: &[u8]) -> Result<CentralDirectoryEnd> {
+    let start = tail
+        .windows(4)
+        .rposition(|w| w == END_OF_CENTRAL_DIRECTORY)
+        .context("no end of central directory")?;
+    let eocd = &tail[start..];
+    // the zip64 locator (20 bytes) comes right before
+    if let Some(locator) = start.checked_sub(20).map(|l| &tail[l..start])
+        && locator.starts_with(ZIP64_LOCATOR)
+    {
+        return Ok(CentralDirectoryEnd::Zip64 {
+            record_offset: u64_at(locator, 8).context("truncated zip64 locator")?,
+        });
+    }
+    let truncated = || anyhow!("truncated end of central directory");
+    Ok(CentralDirectoryEnd::Plain {
+        size: u32_at(eocd, 12).ok_or_else(truncated)?.into(),
+        offset: u32_at(eocd, 16).ok_or_else(truncated)?.into(),
+    })
+}
+
+/// The central directory at the end of a zip file
+struct CentralDirectory {
+    names: Vec<RawName>,
+    zip64: bool,
+}
+
+async fn read_central_directory(path: &Path) -> Result<CentralDirectory> {
+    use tokio::io::{AsyncSeekExt, SeekFrom};
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let tail_len = len.min(22 + 20 + 0xffff);
+    file.seek(SeekFrom::Start(len - tail_len)).await?;
+    let mut tail = Vec::new();
+    (&mut file).read_to_end(&mut tail).await?;
+    let end = central_directory_end(&tail)?;
+    let (size, offset) = match end {
+        CentralDirectoryEnd::Plain { size, offset } => (size, offset),
+        CentralDirectoryEnd::Zip64 { record_offset } => {
+            file.seek(SeekFrom::Start(record_offset)).await?;
+            let mut record = Vec::new();
+            (&mut file).take(56).read_to_end(&mut record).await?;
+            ensure!(
+                record.starts_with(ZIP64_END_OF_CENTRAL_DIRECTORY),
+                "invalid zip64 end of central directory"
+            );
+            let truncated = || anyhow!("truncated zip64 end of central directory");
+            (
+                u64_at(&record, 40).ok_or_else(truncated)?,
+                u64_at(&record, 48).ok_or_else(truncated)?,
+            )
+        }
+    };
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut cd = Vec::new();
+    file.take(size).read_to_end(&mut cd).await?;
+    Ok(CentralDirectory {
+        names: parse_central_directory(&cd),
+        zip64: matches!(end, CentralDirectoryEnd::Zip64 { .. }),
+    })
+}
+
+/// The Windows code page of an encoding, for `7z -mcp`
+fn code_page(encoding: &'static Encoding) -> Option<u16> {
+    let name = encoding.name();
+    Some(match name {
+        "Shift_JIS" => 932,
+        "GBK" => 936,
+        "gb18030" => 54936,
+        "EUC-KR" => 949,
+        "Big5" => 950,
+        "EUC-JP" => 20932,
+        "IBM866" => 866,
+        "KOI8-R" => 20866,
+        "KOI8-U" => 21866,
+        "macintosh" => 10000,
+        _ => match (
+            name.strip_prefix("windows-"),
+            name.strip_prefix("ISO-8859-"),
+        ) {
+            (Some(n), _) => n.parse().ok()?,
+            (_, Some(n)) => 28590 + n.parse::<u16>().ok()?,
+            _ => return None,
+        },
+    })
+}
+
+/// The code page of the names that are not UTF-8: that of the first of the fallback encodings
+/// that decodes all of them without errors, or CP437
+fn name_code_page(names: &[&[u8]], labels: &[String]) -> Result<u16> {
+    for label in labels {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .with_context(|| format!("unknown encoding {label:?} in fallback_encodings"))?;
+        let Some(code_page) = code_page(encoding) else {
+            continue;
+        };
+        if names.iter().all(|name| {
+            encoding
+                .decode_without_bom_handling_and_without_replacement(name)
+                .is_some()
+        }) {
+            return Ok(code_page);
+        }
+    }
+    Ok(DEFAULT_CODE_PAGE)
+}
+
 #[async_trait]
 impl FileAdapter for ZipAdapter {
     async fn adapt(
@@ -47,15 +261,82 @@ impl FileAdapter for ZipAdapter {
     ) -> Result<AdaptedFilesIterBox> {
         // let (s, r) = mpsc::channel(1);
         let AdaptInfo {
-            inp,
+            mut inp,
             filepath_hint,
             archive_recursion_depth,
             postprocess,
             line_prefix,
             config,
+            passwords,
             is_real_file,
             ..
         } = ai;
+        if is_real_file && multivolume::is_last_zip_volume(&filepath_hint) {
+            // searched together with the other volumes by the multivolume adapter
+            debug!(
+                "skipping last volume of split zip {}",
+                filepath_hint.display()
+            );
+            return Ok(Box::pin(tokio_stream::empty::<Result<AdaptInfo>>()));
+        }
+        let mut header = Vec::new();
+        (&mut inp).take(30).read_to_end(&mut header).await?;
+        if header.starts_with(LOCAL_HEADER)
+            && let (Some(name_len), Some(extra_len)) = (u16_at(&header, 26), u16_at(&header, 28))
+        {
+            let names_len = u64::from(name_len) + u64::from(extra_len);
+            (&mut inp).take(names_len).read_to_end(&mut header).await?;
+        }
+        let inp: ReadBox = Box::pin(std::io::Cursor::new(header.clone()).chain(inp));
+        let (names, zip64) = if is_real_file {
+            match read_central_directory(&filepath_hint).await {
+                Ok(cd) => (cd.names, cd.zip64),
+                Err(e) => {
+                    debug!(
+                        "could not read the central directory of {}: {:#}",
+                        filepath_hint.display(),
+                        e
+                    );
+                    (Vec::new(), false)
+                }
+            }
+        } else {
+            // zip files within other archives (and split zip files) are streamed, so only the first file is known
+            (local_header_name(&header).into_iter().collect(), false)
+        };
+        let zip64 = zip64 || names.iter().any(RawName::is_zip64);
+        let non_utf8: Vec<&[u8]> = names
+            .iter()
+            .filter(|n| !n.is_utf8())
+            .map(|n| n.name.as_slice())
+            .collect();
+        let code_page = match non_utf8.is_empty() {
+            true => None,
+            false => Some(name_code_page(&non_utf8, &config.fallback_encodings)?),
+        };
+        // async_zip can neither decrypt nor read names that are not UTF-8 or Zip64 archives, so these zip files are read with 7z
+        if is_encrypted(&header) || code_page.is_some() || zip64 {
+            debug!(
+                "reading {} with 7z (code page {:?}, zip64: {})",
+                filepath_hint.display(),
+                code_page,
+                zip64
+            );
+            let (archive, tmp) =
+                sevenzip::archive_on_disk(inp, &filepath_hint, is_real_file).await?;
+            return sevenzip::adapt_archive(
+                archive,
+                tmp,
+                code_page,
+                &filepath_hint,
+                line_prefix,
+                archive_recursion_depth,
+                postprocess,
+                config,
+                passwords,
+            )
+            .await;
+        }
         if is_real_file {
             use async_zip::read::fs::ZipFileReader;
 
@@ -95,6 +376,7 @@ impl FileAdapter for ZipAdapter {
                         archive_recursion_depth: archive_recursion_depth + 1,
                         postprocess,
                         config: config.clone(),
+                        passwords: passwords.clone(),
                     });
                 }
             };
@@ -143,6 +425,7 @@ impl FileAdapter for ZipAdapter {
                             archive_recursion_depth: archive_recursion_depth + 1,
                             postprocess,
                             config: config.clone(),
+                            passwords: passwords.clone(),
                         });
                         zip = entry.done().await.context("going to next file in zip but entry was not read fully")?;
 
@@ -155,6 +438,49 @@ impl FileAdapter for ZipAdapter {
     }
 }
 
+/// Read all files of a zip archive into memory.
+///
+/// For document formats that are zip files whose parts reference each other (e.g. EPUB), so they can't be streamed.
+pub async fn read_zip_files(
+    inp: ReadBox,
+    filepath_hint: &std::path::Path,
+    is_real_file: bool,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+    if is_real_file {
+        use async_zip::read::fs::ZipFileReader;
+        let zip = ZipFileReader::new(filepath_hint).await?;
+        for i in 0..zip.file().entries().len() {
+            let name = zip.get_entry(i)?.filename().to_string();
+            if name.ends_with('/') {
+                continue;
+            }
+            let reader = zip.entry(i).await?;
+            tokio::pin!(reader);
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).await?;
+            files.push((name, content));
+        }
+    } else {
+        use async_zip::read::stream::ZipFileReader;
+        let mut zip = ZipFileReader::new(inp);
+        while let Some(mut entry) = zip.next_entry().await? {
+            let name = entry.entry().filename().to_string();
+            let mut content = Vec::new();
+            {
+                let reader = entry.reader();
+                tokio::pin!(reader);
+                reader.read_to_end(&mut content).await?;
+            }
+            if !name.ends_with('/') {
+                files.push((name, content));
+            }
+            zip = entry.done().await?;
+        }
+    }
+    Ok(files)
+}
+
 /*struct ZipAdaptIter {
     inp: AdaptInfo,
 }
@@ -221,6 +547,101 @@ mod test {
         Ok(cursor.into_inner())
     }
 
+    #[tokio::test]
+    async fn encrypted() -> Result<()> {
+        let mut zip = create_zip("a.txt", "text", false).await?;
+        assert!(!is_encrypted(&zip));
+        zip[6] |= 1;
+        assert!(is_encrypted(&zip));
+        assert!(!is_encrypted(b"PK\x03"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn names() -> Result<()> {
+        let zip = create_zip("a.txt", "text", false).await?;
+        let name = local_header_name(&zip).context("no local header")?;
+        assert_eq!(name.name, b"a.txt");
+        assert!(name.is_utf8());
+        let cd_start = zip
+            .windows(4)
+            .position(|w| w == CENTRAL_HEADER)
+            .context("no central directory")?;
+        let names = parse_central_directory(&zip[cd_start..]);
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0].name, b"a.txt");
+
+        // "日本.txt" in Shift_JIS, without UTF-8 flag
+        let sjis = RawName {
+            name: b"\x93\xfa\x96\x7b.txt".to_vec(),
+            flags: 0,
+            extra: Vec::new(),
+        };
+        assert!(!sjis.is_utf8());
+        let labels = ["utf-8".to_string(), "shift_jis".to_string()];
+        assert_eq!(name_code_page(&[&sjis.name], &labels)?, 932);
+        assert_eq!(name_code_page(&[&sjis.name], &[])?, 437);
+        // with an Info-ZIP Unicode Path extra field
+        let unicode = RawName {
+            extra: b"\x75\x70\x03\x00\x01ab".to_vec(),
+            ..sjis
+        };
+        assert!(unicode.is_utf8());
+        assert!(!unicode.is_zip64());
+        assert_eq!(code_page(encoding_rs::WINDOWS_1251), Some(1251));
+        assert_eq!(code_page(encoding_rs::ISO_8859_2), Some(28592));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn zip64() -> Result<()> {
+        let zip = create_zip("a.txt", "text", false).await?;
+        let cd_start = zip
+            .windows(4)
+            .position(|w| w == CENTRAL_HEADER)
+            .context("no central directory")?;
+        assert_eq!(
+            central_directory_end(&zip)?,
+            CentralDirectoryEnd::Plain {
+                size: (zip.len() - 22 - cd_start) as u64,
+                offset: cd_start as u64
+            }
+        );
+
+        let mut tail = Vec::new();
+        tail.extend_from_slice(ZIP64_LOCATOR);
+        tail.extend_from_slice(&0u32.to_le_bytes());
+        tail.extend_from_slice(&5_000_000_000u64.to_le_bytes());
+        tail.extend_from_slice(&1u32.to_le_bytes());
+        tail.extend_from_slice(END_OF_CENTRAL_DIRECTORY);
+        tail.extend_from_slice(&[0xff; 18]);
+        assert_eq!(
+            central_directory_end(&tail)?,
+            CentralDirectoryEnd::Zip64 {
+                record_offset: 5_000_000_000
+            }
+        );
+
+        // a file over 4 GB, with the sizes in the extra field
+        let big = RawName {
+            name: b"big.bin".to_vec(),
+            flags: 0,
+            extra: [&[1, 0, 16, 0][..], &[0; 16]].concat(),
+        };
+        assert!(big.is_zip64());
+
+        // malformed archives are errors instead of panics
+        let truncated = RawName {
+            name: b"a.txt".to_vec(),
+            flags: 0,
+            extra: vec![0x75, 0x70, 0xff],
+        };
+        assert!(!truncated.is_zip64());
+        assert!(central_directory_end(&[END_OF_CENTRAL_DIRECTORY, &[0; 8]].concat()).is_err());
+        assert!(local_header_name(&[LOCAL_HEADER, &[0; 8]].concat()).is_none());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn only_seek_zip_fs() -> Result<()> {
         let zip = test_data_dir().join("only-seek-zip.zip");
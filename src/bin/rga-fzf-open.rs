@@ -1,34 +1,215 @@
-use anyhow::Context;
+use anyhow::{Context, Result};
+use rga::adapters::*;
+use rga::config::{RgaConfig, parse_args};
+use rga::expand::expand_str_ez;
+use rga::matching::*;
+use rga::passwords::PasswordState;
+use ripgrep_all as rga;
+use tokio_stream::StreamExt;
 
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-// TODO: add --rg-params=..., --rg-preview-params=... and --fzf-params=... params
-// TODO: remove passthrough_args
-fn main() -> anyhow::Result<()> {
+/// Command used to open a PDF at a specific page.
+/// Placeholders: `$file`, `$page` (1-based) and `$query`. Arguments are split on whitespace.
+static PDF_VIEWER_ENV: &str = "RGA_FZF_PDF_VIEWER";
+static DEFAULT_PDF_VIEWER: &str = "evince --page-index=$page --find=$query $file";
+
+/// Get the first matching line of the file as rga outputs it (including page and inner path prefixes).
+fn first_match(query: &str, fname: &Path) -> Result<Option<String>> {
+    let exe = std::env::current_exe().context("Could not get executable location")?;
+    let output = Command::new(exe.with_file_name("rga"))
+        .args([
+            "--no-filename",
+            "--no-heading",
+            "--color=never",
+            "--max-count=1",
+            "--",
+            query,
+        ])
+        .arg(fname)
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(String::from))
+}
+
+/// Parse the "Page N: " (or with `--rga-pdf-outline` "Page N [section]: ") prefix added by the postprocpagebreaks adapter
+fn page_of(line: &str) -> Option<u32> {
+    let rest = line.strip_prefix("Page ")?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    if !(rest[digits..].starts_with(": ") || rest[digits..].starts_with(" [")) {
+        return None;
+    }
+    rest[..digits].parse().ok()
+}
+
+fn open_pdf(query: &str, fname: &Path, page: u32) -> Result<bool> {
+    use std::io::ErrorKind::*;
+    let viewer = std::env::var(PDF_VIEWER_ENV).unwrap_or_else(|_| DEFAULT_PDF_VIEWER.to_string());
+    let page = page.to_string();
+    let file = fname.to_string_lossy();
+    let args = viewer
+        .split_whitespace()
+        .map(|arg| {
+            expand_str_ez(arg, |s| match s {
+                "file" => Ok(Cow::Borrowed(file.as_ref())),
+                "page" => Ok(Cow::Borrowed(page.as_str())),
+                "query" => Ok(Cow::Borrowed(query)),
+                e => Err(anyhow::format_err!(
+                    "unknown replacer ${{{e}}} in {PDF_VIEWER_ENV}"
+                )),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (exe, args) = args.split_first().context("pdf viewer command empty")?;
+    Command::new(exe).args(args).spawn().map_or_else(
+        |err| match err.kind() {
+            NotFound => Ok(false),
+            _ => Err(err.into()),
+        },
+        |_| Ok(true),
+    )
+}
+
+/// Extract the file within the archive that the matching line belongs to into a temporary directory.
+///
+/// Returns the path of the extracted file and the rest of the line after the inner path prefix.
+async fn extract_inner(
+    config: &RgaConfig,
+    adapter: &dyn FileAdapter,
+    detection_reason: &FileMatcher,
+    fname: &Path,
+    line: &str,
+) -> Result<Option<(PathBuf, String)>> {
+    let ai = AdaptInfo {
+        inp: Box::pin(tokio::fs::File::open(fname).await?),
+        filepath_hint: fname.to_path_buf(),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: false,
+        config: config.clone(),
+        passwords: PasswordState::default(),
+    };
+    let mut entries = adapter.adapt(ai, detection_reason).await?;
+    while let Some(entry) = entries.next().await {
+        let mut entry = entry?;
+        let Some(rest) = line.strip_prefix(&entry.line_prefix) else {
+            // entries must be read fully, some archive readers hang otherwise
+            tokio::io::copy(&mut entry.inp, &mut tokio::io::sink()).await?;
+            continue;
+        };
+        // keep the directory around after we exit, the opened program still needs the file
+        let dir = tempfile::Builder::new()
+            .prefix("rga-fzf-")
+            .tempdir()?
+            .into_path();
+        let target = dir.join(entry.filepath_hint.file_name().context("no file name")?);
+        let mut out = tokio::fs::File::create(&target).await?;
+        tokio::io::copy(&mut entry.inp, &mut out).await?;
+        return Ok(Some((target, rest.to_string())));
+    }
+    Ok(None)
+}
+
+/// Open the file at the location of the matching line, descending into archives if necessary.
+async fn open_at(
+    config: &RgaConfig,
+    query: &str,
+    mut fname: PathBuf,
+    mut line: Option<String>,
+) -> Result<()> {
+    let adapters = get_adapters_filtered(
+        config.custom_adapters.clone(),
+        &config.adapter_aliases,
+        &config.adapters,
+    )?;
+    // without the contents, files can only be matched by name
+    let matcher = adapter_matcher(&adapters, false)?;
+    while let Some(l) = &line {
+        let Some((adapter, detection_reason)) = matcher(FileMeta {
+            mimetype: None,
+            path: Some(fname.clone()),
+            lossy_filename: fname
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        }) else {
+            break;
+        };
+        let meta = adapter.metadata();
+        if meta.outputs_unchanged_files {
+            match extract_inner(config, adapter.as_ref(), &detection_reason, &fname, l).await? {
+                Some((inner, rest)) => {
+                    fname = inner;
+                    line = Some(rest);
+                    continue;
+                }
+                None => break,
+            }
+        }
+        if meta.name == "poppler"
+            && let Some(page) = page_of(l)
+            && open_pdf(query, &fname, page)?
+        {
+            return Ok(());
+        }
+        break;
+    }
+    Ok(open::that_detached(&fname)?)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let mut args = std::env::args().skip(1);
     let query = args.next().context("no query")?;
     let fname = args.next().context("no filename")?;
     // let instance_id = std::env::var("RGA_FZF_INSTANCE").unwrap_or("unk".to_string());
 
-    if fname.ends_with(".pdf") {
-        use std::io::ErrorKind::*;
-
-        let worked = Command::new("evince")
-            .arg("--find")
-            .arg(&query)
-            .arg(&fname)
-            .spawn()
-            .map_or_else(
-                |err| match err.kind() {
-                    NotFound => Ok(false),
-                    _ => Err(err),
-                },
-                |_| Ok(true),
-            )?;
-        if worked {
-            return Ok(());
-        }
+    let fname = PathBuf::from(fname);
+    // the config files and RGA_CONFIG of the rga that found the file
+    let config = parse_args(["rga-fzf-open"], false, std::slice::from_ref(&fname))?;
+    let line = first_match(&query, &fname)?;
+    log::debug!("first match: {:?}", line);
+    open_at(&config, &query, fname, line).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn page_prefix() {
+        assert_eq!(page_of("Page 12: hello"), Some(12));
+        assert_eq!(page_of("Page 3 [2.1 Results]: hello"), Some(3));
+        assert_eq!(page_of("Page one: hello"), None);
+        assert_eq!(page_of("dir/a.pdf: Page 1: hello"), None);
+    }
+
+    #[tokio::test]
+    async fn extract_matched_member() -> Result<()> {
+        let archive = Path::new(env!("CARGO_MANIFEST_DIR")).join("exampledir/test/hello.tar");
+        let detection_reason = FileMatcher::Fast(FastFileMatcher::FileExtension("tar".to_string()));
+        let (inner, rest) = extract_inner(
+            &RgaConfig::default(),
+            &tar::TarAdapter::new(),
+            &detection_reason,
+            &archive,
+            "dir/file-a.pdf: Page 1: hello world",
+        )
+        .await?
+        .context("no member matched")?;
+        assert_eq!(inner.file_name(), Some(std::ffi::OsStr::new("file-a.pdf")));
+        assert_eq!(std::fs::metadata(&inner)?.len(), 53687);
+        assert_eq!(rest, "Page 1: hello world");
+        assert_eq!(page_of(&rest), Some(1));
+        std::fs::remove_dir_all(inner.parent().context("no parent")?)?;
+        Ok(())
     }
-    Ok(open::that_detached(&fname)?)
 }
@@ -1,43 +1,94 @@
 use rga::adapters::*;
+use rga::config::spawned_by_rga;
+use rga::ntfs_streams::alternate_streams;
+use rga::passwords::PasswordState;
 use rga::preproc::*;
 use rga::print_dur;
+use rga::xattrs::xattr_lines;
 use ripgrep_all as rga;
 
 use anyhow::Context;
 use log::debug;
 use std::time::Instant;
 use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncWriteExt};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
     let mut arg_arr: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    // rg calls `rga-preproc FILE`, rga adds its own arguments before the file name
+    if arg_arr.len() < 2 {
+        anyhow::bail!("usage: rga-preproc [RGA OPTIONS] FILE");
+    }
     let last = arg_arr.pop().expect("No filename specified");
-    let config = rga::config::parse_args(arg_arr, true)?;
+    // not started by rga, but used directly as `--pre` command of rg
+    let stock_rg = !spawned_by_rga();
     //clap::App::new("rga-preproc").arg(Arg::from_usage())
     let path = {
         let filepath = last;
         std::env::current_dir()?.join(filepath)
     };
+    let config = rga::config::parse_args(arg_arr, true, std::slice::from_ref(&path))?;
 
-    let i = File::open(&path)
+    let mut i = File::open(&path)
         .await
         .context("Specified input file not found")?;
     let mut o = tokio::io::stdout();
-    let ai = AdaptInfo {
-        inp: Box::pin(i),
-        filepath_hint: path,
-        is_real_file: true,
-        line_prefix: "".to_string(),
-        archive_recursion_depth: 0,
-        postprocess: !config.no_prefix_filenames,
-        config,
+    let streams = if config.ntfs_streams {
+        alternate_streams(&path)?
+    } else {
+        Vec::new()
+    };
+    // without a (matching) `--pre-glob` (stock rg or `--rga-ntfs-streams` / `--rga-xattrs`), let rg search other files as is
+    if (stock_rg || config.searches_file_metadata())
+        && !config.preprocesses_unmatched_files()
+        && !matches_any_adapter(&config, &path)?
+    {
+        debug!("no adapter matches {}, passing through", path.display());
+        copy_to_stdout(&mut i, &mut o).await?;
+    } else {
+        let ai = AdaptInfo {
+            inp: Box::pin(i),
+            filepath_hint: path.clone(),
+            is_real_file: true,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: !config.no_prefix_filenames,
+            config: config.clone(),
+            passwords: PasswordState::default(),
+        };
+
+        let start = Instant::now();
+        let mut oup = rga_preproc(ai).await.context("during preprocessing")?;
+        debug!("finding and starting adapter took {}", print_dur(start));
+        copy_to_stdout(&mut oup, &mut o).await?;
+        debug!("running adapter took {} total", print_dur(start));
+    }
+    let xattrs = if config.xattrs {
+        xattr_lines(&path)?
+    } else {
+        String::new()
     };
+    if !streams.is_empty() || !xattrs.is_empty() {
+        // the output of the file might not end with a newline
+        o.write_all(b"\n").await?;
+    }
+    o.write_all(xattrs.as_bytes()).await?;
+    for stream in streams {
+        let mut oup = rga_preproc_ntfs_stream(config.clone(), &path, &stream)
+            .await
+            .with_context(|| format!("during preprocessing of stream {stream}"))?;
+        copy_to_stdout(&mut oup, &mut o).await?;
+    }
+    Ok(())
+}
 
-    let start = Instant::now();
-    let mut oup = rga_preproc(ai).await.context("during preprocessing")?;
-    debug!("finding and starting adapter took {}", print_dur(start));
-    let res = tokio::io::copy(&mut oup, &mut o).await;
+async fn copy_to_stdout(
+    inp: &mut (impl AsyncRead + Unpin),
+    o: &mut tokio::io::Stdout,
+) -> anyhow::Result<()> {
+    let res = tokio::io::copy(inp, o).await;
     if let Err(e) = res {
         if e.kind() == std::io::ErrorKind::BrokenPipe {
             // happens if e.g. ripgrep detects binary data in the pipe so it cancels reading
@@ -46,6 +97,5 @@ async fn main() -> anyhow::Result<()> {
             Err(e).context("copying adapter output to stdout")?;
         }
     }
-    debug!("running adapter took {} total", print_dur(start));
     Ok(())
 }
@@ -1,18 +1,28 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rga::adapters::custom::map_exe_error;
 use rga::adapters::*;
-use rga::config::{RgaConfig, split_args};
+use rga::config::{RgaConfig, check_config_files, rg_path_args, rg_search_paths, split_args};
+use rga::editor_server;
+use rga::failures::{FailureSummary, RGA_FAILURE_LOG};
+use rga::git_history;
 use rga::matching::*;
+use rga::passwords::PasswordState;
+use rga::preproc::rga_locate;
 use rga::print_dur;
+use rga::report;
+use rga::semantic;
 use ripgrep_all as rga;
 use structopt::StructOpt;
 
 use schemars::schema_for;
+use std::ffi::OsString;
+use std::path::Path;
 use std::process::Command;
 use std::time::Instant;
 
 fn list_adapters(args: RgaConfig) -> Result<()> {
-    let (enabled_adapters, disabled_adapters) = get_all_adapters(args.custom_adapters);
+    let (enabled_adapters, disabled_adapters) =
+        get_all_adapters(args.custom_adapters, &args.adapter_aliases);
 
     println!("Adapters:\n");
     let print = |adapter: std::sync::Arc<dyn FileAdapter>| {
@@ -22,6 +32,13 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
             .iter()
             .map(|m| match m {
                 FastFileMatcher::FileExtension(ext) => format!(".{ext}"),
+                FastFileMatcher::DirectoryShape(names) => {
+                    format!("files in {}/", names.join("/, "))
+                }
+                FastFileMatcher::ExtensionWithSibling(ext, sibling) => {
+                    format!(".{ext} next to {sibling}")
+                }
+                FastFileMatcher::PathGlob(glob) => format!("files matching {glob}"),
             })
             .collect::<Vec<_>>()
             .join(", ");
@@ -56,6 +73,226 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
     }
     Ok(())
 }
+fn locate(config: RgaConfig, args: &[OsString]) -> Result<()> {
+    if args.iter().any(|a| a == "-b" || a == "--byte-offset") {
+        // the byte offset counts the prefixes of all previous lines, the location map only knows about lines
+        anyhow::bail!(
+            "--rga-locate doesn't translate byte offsets (-b), pass the line and column (rg --column) instead"
+        );
+    }
+    let [file, line] = args else {
+        anyhow::bail!("usage: rga --rga-locate FILE LINE[:COLUMN]");
+    };
+    let (line, column) = parse_line_column(&line.to_string_lossy())?;
+    let path = std::env::current_dir()?.join(file);
+    let rt = tokio::runtime::Runtime::new()?;
+    let location = rt.block_on(async {
+        let inp = tokio::fs::File::open(&path)
+            .await
+            .context("Specified input file not found")?;
+        let ai = AdaptInfo {
+            inp: Box::pin(inp),
+            filepath_hint: path,
+            is_real_file: true,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: !config.no_prefix_filenames,
+            config,
+            passwords: PasswordState::default(),
+        };
+        rga_locate(ai, line, column).await
+    })?;
+    println!("{}", serde_json::to_string(&location)?);
+    Ok(())
+}
+
+/// Parse the `LINE[:COLUMN]` argument of `--rga-locate`
+fn parse_line_column(arg: &str) -> Result<(u64, Option<u64>)> {
+    let (line, column) = match arg.split_once(':') {
+        Some((line, column)) => (
+            line,
+            Some(column.parse().context("COLUMN must be a number")?),
+        ),
+        None => (arg, None),
+    };
+    let line = line.parse().context("LINE must be a number")?;
+    Ok((line, column))
+}
+
+/// `--rga-semantic`: print the chunks of the files listed by `rg --files ARGS` that are most similar to the query
+fn semantic(config: RgaConfig, query: &str, args: &[OsString]) -> Result<()> {
+    let adapters = get_adapters_filtered(
+        config.custom_adapters.clone(),
+        &config.adapter_aliases,
+        &config.adapters,
+    )?;
+    let matcher = adapter_matcher(&adapters, false)?;
+    let output = Command::new("rg")
+        .arg("--files")
+        .args(args)
+        .output()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    let files: Vec<std::path::PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(std::path::PathBuf::from)
+        .filter(|path| {
+            let lossy_filename = path
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let adapted = matcher(FileMeta {
+                lossy_filename,
+                mimetype: None,
+                path: Some(path.clone()),
+            })
+            .is_some();
+            // plain text files are passed through, skip other binary files
+            adapted || is_text_file(path)
+        })
+        .collect();
+    log::debug!("semantic search in {} files", files.len());
+    let rt = tokio::runtime::Runtime::new()?;
+    let hits = rt.block_on(semantic::semantic_search(&config, query, &files))?;
+    for hit in hits {
+        let mut location = String::new();
+        if let Some(member) = &hit.chunk.member {
+            location.push_str(&format!("{member}: "));
+        }
+        if let Some(page) = hit.chunk.page {
+            location.push_str(&format!("Page {page}: "));
+        }
+        let snippet = hit
+            .chunk
+            .text
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{}:{}: {location}({:.3}) {snippet}",
+            hit.path.display(),
+            hit.chunk.line,
+            hit.score
+        );
+    }
+    Ok(())
+}
+
+/// `--rga-git-history`: search the adapted contents of every blob in the history of the current repository
+fn git_history(config: RgaConfig, args: &[OsString]) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+    if !rg_path_args(args).is_empty() {
+        // the blobs are read from the repository, rg only searches its stdin
+        anyhow::bail!(
+            "--rga-git-history searches the repository of the current directory and can't be combined with paths to search"
+        );
+    }
+    // adapter failures of the blobs are recorded like those of rga-preproc
+    let failure_log = tempfile::NamedTempFile::new()?;
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { std::env::set_var(RGA_FAILURE_LOG, failure_log.path()) };
+    let mut child = Command::new("rg")
+        .args(["--no-line-number", "--no-filename"])
+        .args(args)
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    let stdin = child.stdin.take().context("rg stdin")?;
+    let rt = tokio::runtime::Runtime::new()?;
+    let res = rt.block_on(async {
+        let mut stdin = tokio::process::ChildStdin::from_std(stdin)?;
+        git_history::write_history(&config, &std::env::current_dir()?, &mut stdin).await?;
+        stdin.shutdown().await?;
+        Ok::<_, anyhow::Error>(())
+    });
+    if let Err(e) = res {
+        // rg exits early with e.g. --max-count or --quiet
+        let broken_pipe = e.chain().any(|e| {
+            e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+        });
+        if !broken_pipe {
+            return Err(e);
+        }
+    }
+    let code = child.wait()?.code().unwrap_or(1);
+    exit_with_failures(failure_log, &[code])
+}
+
+fn is_text_file(path: &Path) -> bool {
+    use std::io::Read;
+    let mut buf = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|f| f.take(8192).read_to_end(&mut buf))
+        .is_ok_and(|_| !buf.contains(&0))
+}
+
+/// A flag of rg, parsed from `rg --help`
+#[derive(Debug, PartialEq)]
+struct RgFlag {
+    short: Option<char>,
+    long: String,
+    takes_value: bool,
+}
+
+/// Parse the flags from the output of `rg --help`.
+///
+/// Flags are listed indented by four spaces, e.g. `    -m NUM, --max-count=NUM` or `    -i, --ignore-case` (rg >= 14)
+/// or `    -m, --max-count <NUM>` (older). Descriptions are indented further.
+fn parse_rg_help(help: &str) -> Result<Vec<RgFlag>> {
+    let flag_re = regex::Regex::new(
+        r"^ {4}(?:-([a-zA-Z0-9.])(?: [A-Z][A-Z0-9+?_-]*)?, )?--([a-z0-9][a-z0-9-]*)(=| <)?",
+    )?;
+    Ok(help
+        .lines()
+        .filter_map(|line| flag_re.captures(line))
+        .map(|c| RgFlag {
+            short: c.get(1).and_then(|s| s.as_str().chars().next()),
+            long: c[2].to_string(),
+            takes_value: c.get(3).is_some(),
+        })
+        .collect())
+}
+
+fn rg_flags() -> Result<Vec<RgFlag>> {
+    let output = Command::new("rg")
+        .arg("--help")
+        .output()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    parse_rg_help(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn print_completions(shell: &str) -> Result<()> {
+    use structopt::clap::{Arg, Shell};
+    let shell: Shell = shell.parse().map_err(|e| anyhow::format_err!("{}", e))?;
+    // clap borrows the names of the flags, so they need to outlive the app
+    let rg_flags = rg_flags()?;
+    let mut app = RgaConfig::clap();
+    let mut seen = std::collections::HashSet::new();
+    for flag in &rg_flags {
+        // -h/--help and -V/--version are already handled by rga, and all other flags of rga start with --rga- or --rg-
+        if flag.long == "help"
+            || flag.long == "version"
+            || flag.long.starts_with("rg-")
+            || flag.long.starts_with("rga-")
+            || !seen.insert(&flag.long)
+        {
+            continue;
+        }
+        let mut arg = Arg::with_name(&flag.long)
+            .long(&flag.long)
+            .help("ripgrep option")
+            .takes_value(flag.takes_value)
+            .multiple(true);
+        if let Some(short) = flag.short.filter(|s| *s != 'h' && *s != 'V') {
+            arg = arg.short(short.to_string());
+        }
+        app = app.arg(arg);
+    }
+    app.gen_completions_to("rga", shell, &mut std::io::stdout());
+    Ok(())
+}
+
 fn main() -> anyhow::Result<()> {
     // set debugging as early as possible
     if std::env::args().any(|e| e == "--debug") {
@@ -67,6 +304,13 @@ fn main() -> anyhow::Result<()> {
 
     let (config, mut passthrough_args) = split_args(false)?;
 
+    if config.check_config {
+        let ok = check_config_files(
+            config.config_file_path.as_ref(),
+            &rg_search_paths(&passthrough_args),
+        )?;
+        std::process::exit(if ok { 0 } else { 1 });
+    }
     if config.print_config_schema {
         println!("{}", serde_json::to_string_pretty(&schema_for!(RgaConfig))?);
         return Ok(());
@@ -74,72 +318,230 @@ fn main() -> anyhow::Result<()> {
     if config.list_adapters {
         return list_adapters(config);
     }
-    if let Some(path) = config.fzf_path {
+    if config.print_pre_glob {
+        let adapters = get_adapters_filtered(
+            config.custom_adapters.clone(),
+            &config.adapter_aliases,
+            &config.adapters,
+        )?;
+        println!(
+            "{}",
+            pre_glob(&adapters, config.preprocesses_unmatched_files())
+        );
+        return Ok(());
+    }
+    if config.locate {
+        return locate(config, &passthrough_args);
+    }
+    if let Some(query) = config.semantic_query.clone() {
+        return semantic(config, &query, &passthrough_args);
+    }
+    if config.git_history {
+        return git_history(config, &passthrough_args);
+    }
+    if let Some(shell) = &config.completions {
+        return print_completions(shell);
+    }
+    if let Some(path) = &config.fzf_path {
         if path == "_" {
             // fzf found no result, ignore everything and return
             println!("[no file found]");
             return Ok(());
         }
-        passthrough_args.push(std::ffi::OsString::from(&path[1..]));
+        passthrough_args.push(OsString::from(&path[1..]));
     }
 
-    if passthrough_args.is_empty() {
+    if passthrough_args.is_empty() && !config.editor_server {
         // rg would show help. Show own help instead.
         RgaConfig::clap().print_help()?;
         println!();
         return Ok(());
     }
 
-    let adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
+    let adapters = get_adapters_filtered(
+        config.custom_adapters.clone(),
+        &config.adapter_aliases,
+        &config.adapters,
+    )?;
 
-    let pre_glob = if !config.accurate {
-        let extensions = adapters
-            .iter()
-            .flat_map(|a| &a.metadata().fast_matchers)
-            .flat_map(|m| match m {
-                FastFileMatcher::FileExtension(ext) => vec![ext.clone(), ext.to_ascii_uppercase()],
-            })
-            .collect::<Vec<_>>()
-            .join(",");
-        format!("*.{{{extensions}}}")
+    let pre_glob = if config.searches_file_metadata() {
+        // any file can have streams / attributes
+        "*".to_string()
     } else {
-        "*".to_owned()
+        pre_glob(&adapters, config.preprocesses_unmatched_files())
     };
 
     add_exe_to_path()?;
 
-    let rg_args = vec![
+    let mut rg_args = vec![
         "--no-line-number",
         // smart case by default because within weird files
         // we probably can't really trust casing anyways
         "--smart-case",
     ];
+    if config.sort {
+        rg_args.push("--sort=path");
+    }
+    if config.output.is_some() {
+        rg_args.extend(["--json", "--line-number"]);
+    }
 
     let exe = std::env::current_exe().expect("Could not get executable location");
     let preproc_exe = exe.with_file_name("rga-preproc");
 
-    let before = Instant::now();
-    let mut cmd = Command::new("rg");
-    cmd.args(rg_args)
-        .arg("--pre")
-        .arg(preproc_exe)
-        .arg("--pre-glob")
-        .arg(pre_glob)
-        .args(passthrough_args);
-    log::debug!("rg command to run: {:?}", cmd);
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    if config.editor_server {
+        let mut base_args: Vec<OsString> = rg_args.iter().map(OsString::from).collect();
+        base_args.extend([
+            "--pre".into(),
+            preproc_exe.into(),
+            "--pre-glob".into(),
+            pre_glob.into(),
+        ]);
+        base_args.extend(passthrough_args);
+        let rt = tokio::runtime::Runtime::new()?;
+        return rt.block_on(editor_server::serve(config, base_args));
+    }
+
+    if config.locate_prefilter.is_some() && !rg_path_args(&passthrough_args).is_empty() {
+        // rg would search the paths in addition to the files found by locate
+        anyhow::bail!("--rga-locate-prefilter can't be combined with paths to search");
+    }
 
-    let result = child.wait()?;
+    // rga-preproc processes append adapter failures to this file, summarized after rg exits
+    let failure_log = tempfile::NamedTempFile::new()?;
+
+    let batches = match &config.locate_prefilter {
+        Some(pattern) => match locate_candidates(pattern)? {
+            Some(files) => {
+                log::debug!("locate prefilter found {} files", files.len());
+                files
+                    .chunks(PREFILTER_BATCH_SIZE)
+                    .map(<[OsString]>::to_vec)
+                    .collect()
+            }
+            None => {
+                eprintln!(
+                    "rga: no locate tool (plocate, locate, es) found, ignoring --rga-locate-prefilter"
+                );
+                vec![vec![]]
+            }
+        },
+        None => vec![vec![]],
+    };
+
+    // the report rows of all batches go below one header
+    let report = match config.output {
+        Some(format) => {
+            report::write_header(format, &mut std::io::stdout())?;
+            Some((format, tokio::runtime::Runtime::new()?))
+        }
+        None => None,
+    };
+
+    let before = Instant::now();
+    let mut codes = Vec::new();
+    for batch in batches {
+        let mut cmd = Command::new("rg");
+        cmd.args(&rg_args)
+            .arg("--pre")
+            .arg(&preproc_exe)
+            .arg("--pre-glob")
+            .arg(&pre_glob)
+            .args(&passthrough_args)
+            .args(batch)
+            .env(RGA_FAILURE_LOG, failure_log.path());
+        log::debug!("rg command to run: {:?}", cmd);
+        if let Some((format, rt)) = &report {
+            codes.push(rt.block_on(report::write_report(&config, *format, cmd.into()))?);
+            continue;
+        }
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+        codes.push(child.wait()?.code().unwrap_or(1));
+    }
 
     log::debug!("running rg took {}", print_dur(before));
-    if !result.success() {
-        std::process::exit(result.code().unwrap_or(1));
+    exit_with_failures(failure_log, &codes)
+}
+
+/// Print the summary of the adapter failures and exit with the combined exit code of the rg runs
+fn exit_with_failures(failure_log: tempfile::NamedTempFile, codes: &[i32]) -> Result<()> {
+    let failures = FailureSummary::read(failure_log.path())?;
+    // process::exit does not run destructors
+    failure_log.close()?;
+    failures.write(std::io::stderr())?;
+    let code = failures.exit_code(combine_exit_codes(codes));
+    if code != 0 {
+        std::process::exit(code);
     }
     Ok(())
 }
 
+/// locate tools to try for `--rga-locate-prefilter`, with the arguments before the pattern
+static LOCATE_TOOLS: &[(&str, &[&str])] = &[
+    ("plocate", &["-0", "--"]),
+    ("locate", &["-0", "--"]),
+    // Everything (Windows)
+    ("es", &[]),
+];
+
+/// rg is run once per batch of files found by the locate prefilter, to stay below the command line length limit
+const PREFILTER_BATCH_SIZE: usize = 1000;
+
+/// Find the existing files matching the pattern using the first installed locate tool.
+///
+/// Returns None if no locate tool is installed.
+fn locate_candidates(pattern: &str) -> Result<Option<Vec<OsString>>> {
+    for (tool, args) in LOCATE_TOOLS {
+        let output = match Command::new(tool).args(*args).arg(pattern).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("running {tool}")),
+        };
+        // locate exits with 1 without output on stderr if nothing was found
+        if !output.status.success() && !output.stderr.is_empty() {
+            anyhow::bail!(
+                "{tool} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let separator = if args.contains(&"-0") { b'\0' } else { b'\n' };
+        return Ok(Some(existing_files(&output.stdout, separator)));
+    }
+    Ok(None)
+}
+
+/// The files listed in the output of a locate tool that still exist
+fn existing_files(output: &[u8], separator: u8) -> Vec<OsString> {
+    output
+        .split(|b| *b == separator)
+        .filter(|path| !path.is_empty())
+        .map(os_string_from_bytes)
+        // the index may be outdated
+        .filter(|path| Path::new(path).is_file())
+        .collect()
+}
+
+#[cfg(unix)]
+fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    std::os::unix::ffi::OsStringExt::from_vec(bytes.to_vec())
+}
+
+#[cfg(not(unix))]
+fn os_string_from_bytes(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).trim_end_matches('\r'))
+}
+
+/// exit code of rga when rg was run multiple times: success if any run found matches
+fn combine_exit_codes(codes: &[i32]) -> i32 {
+    if codes.contains(&0) {
+        0
+    } else {
+        codes.iter().copied().find(|c| *c != 1).unwrap_or(1)
+    }
+}
+
 /// add the directory that contains `rga` to PATH, so rga-preproc can find pandoc etc (if we are on Windows where we include dependent binaries)
 fn add_exe_to_path() -> Result<()> {
     use std::env;
@@ -158,3 +560,116 @@ fn add_exe_to_path() -> Result<()> {
     unsafe { env::set_var("PATH", new_path) };
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn flag(short: Option<char>, long: &str, takes_value: bool) -> RgFlag {
+        RgFlag {
+            short,
+            long: long.to_string(),
+            takes_value,
+        }
+    }
+
+    #[test]
+    fn rg_14_help() -> Result<()> {
+        // excerpt of `rg --help` of ripgrep 14.1.1
+        let help = r#"INPUT OPTIONS:
+    -e PATTERN, --regexp=PATTERN
+        A pattern to search for. This option can be provided multiple times,
+        where all patterns given are searched, in addition to any patterns
+        provided by -f/--file.
+
+    -f PATTERNFILE, --file=PATTERNFILE
+        Search for patterns from the given file, with one pattern per line.
+
+    --pre=COMMAND
+        For each input PATH, this flag causes ripgrep to search the standard
+
+    -i, --ignore-case
+        When this flag is provided, all patterns will be searched case
+
+    --dfa-size-limit=NUM+SUFFIX?
+        The upper size limit of the regex DFA.
+
+    -m NUM, --max-count=NUM
+        Limit the number of matching lines per file searched to NUM.
+
+    --multiline-dotall
+        This flag enables "dot all" mode in all regex patterns. This causes .
+        to match line terminators when multiline searching is enabled. The
+        --multiline flag may be passed to make the "dot all" behavior
+
+    -A NUM, --after-context=NUM
+        Show NUM lines after each match.
+"#;
+        assert_eq!(
+            parse_rg_help(help)?,
+            [
+                flag(Some('e'), "regexp", true),
+                flag(Some('f'), "file", true),
+                flag(None, "pre", true),
+                flag(Some('i'), "ignore-case", false),
+                flag(None, "dfa-size-limit", true),
+                flag(Some('m'), "max-count", true),
+                flag(None, "multiline-dotall", false),
+                flag(Some('A'), "after-context", true),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rg_13_help() -> Result<()> {
+        // excerpt of `rg --help` of ripgrep 13
+        let help = r#"OPTIONS:
+    -A, --after-context <NUM>
+            Show NUM lines after each match.
+
+    -i, --ignore-case
+            Searches case insensitively.
+"#;
+        assert_eq!(
+            parse_rg_help(help)?,
+            [
+                flag(Some('A'), "after-context", true),
+                flag(Some('i'), "ignore-case", false),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn locate_line_column() -> Result<()> {
+        assert_eq!(parse_line_column("12")?, (12, None));
+        assert_eq!(parse_line_column("12:7")?, (12, Some(7)));
+        assert!(parse_line_column("12:").is_err());
+        assert!(parse_line_column("x:7").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn locate_existing_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let a = dir.path().join("a.pdf");
+        std::fs::write(&a, "")?;
+        let missing = dir.path().join("deleted.pdf");
+        let mut output = Vec::new();
+        for path in [&a, &missing, dir.path()] {
+            output.extend(path.to_str().unwrap().as_bytes());
+            output.push(b'\0');
+        }
+        assert_eq!(existing_files(&output, b'\0'), [a.into_os_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn git_history_without_paths() {
+        let args = ["-i", "pattern", "docs"].map(OsString::from);
+        let err = git_history(RgaConfig::default(), &args).unwrap_err();
+        assert!(err.to_string().contains("can't be combined with paths"));
+    }
+}
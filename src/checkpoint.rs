@@ -0,0 +1,153 @@
+//! Checkpoints of the adaptation of large files.
+//!
+//! While a file is adapted and written to the cache, the output of the entries of the file (e.g. mails in a PST, files in a disk image)
+//! that were completed so far is regularly stored in the cache.
+//! If the run is interrupted, the next run outputs the stored output and skips the completed entries instead of starting over.
+
+use crate::adapters::ReadBox;
+use crate::location::LocationMap;
+use crate::preproc_cache::{CacheKey, PreprocCache, open_cache_db};
+use anyhow::Result;
+use async_compression::tokio::write::ZstdEncoder;
+use async_stream::stream;
+use log::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// minimum time between checkpoints, so only long running adaptations write them
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Progress of adapting the entries of a file, see `loop_adapt_resumable`
+#[derive(Default)]
+pub struct Progress {
+    /// number of entries to skip because their output is in the checkpoint
+    pub skip: u64,
+    /// number of entries that were completely output
+    pub completed: AtomicU64,
+}
+
+/// Stored state of an interrupted adaptation
+pub struct Checkpoint {
+    pub completed_entries: u64,
+    /// the location map of `output`
+    pub location_map: LocationMap,
+    /// zstd compressed output of the completed entries
+    pub output_zstd: Vec<u8>,
+}
+
+pub struct CheckpointWriter {
+    pub cache_path: PathBuf,
+    pub key: CacheKey,
+    pub compression_level: i32,
+    /// don't write checkpoints larger than this, they would not be cached at the end either
+    pub max_blob_len: usize,
+}
+
+async fn compress(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    let mut encoder =
+        ZstdEncoder::with_quality(Vec::new(), async_compression::Level::Precise(level));
+    encoder.write_all(data).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner())
+}
+
+impl CheckpointWriter {
+    async fn write(&self, checkpoint: &Checkpoint) -> Result<()> {
+        let mut cache = open_cache_db(&self.cache_path).await?;
+        cache.set_checkpoint(&self.key, checkpoint).await
+    }
+
+    /// Pass through the output of the entries (after the output `resumed` from a checkpoint),
+    /// writing checkpoints between entries.
+    pub fn tap(
+        self,
+        inp: ReadBox,
+        resumed: Vec<u8>,
+        progress: Arc<Progress>,
+        location_map: Arc<Mutex<LocationMap>>,
+    ) -> ReadBox {
+        let s = stream! {
+            let mut output = resumed;
+            let mut last_checkpoint = Instant::now();
+            let mut prev_completed = progress.skip;
+            let mut enabled = true;
+            for await bytes in ReaderStream::new(inp) {
+                if let Ok(bytes) = &bytes {
+                    let completed = progress.completed.load(Ordering::SeqCst);
+                    // the progress changed since the previous chunk,
+                    // so the output so far is exactly the output of the completed entries
+                    let boundary = completed != prev_completed;
+                    prev_completed = completed;
+                    if enabled && boundary && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                        let lines = output.iter().filter(|b| **b == b'\n').count() as u64;
+                        let mut map = location_map.lock().expect("poisoned").clone();
+                        // the members of entries that start with this chunk
+                        map.members.retain(|m| m.first_line <= lines);
+                        match compress(&output, self.compression_level).await {
+                            Ok(output_zstd) if output_zstd.len() <= self.max_blob_len => {
+                                let checkpoint = Checkpoint {
+                                    completed_entries: completed,
+                                    location_map: map,
+                                    output_zstd,
+                                };
+                                debug!("writing checkpoint after {completed} entries");
+                                if let Err(e) = self.write(&checkpoint).await {
+                                    warn!("could not write checkpoint: {e:#}");
+                                }
+                            }
+                            Ok(_) => {
+                                debug!("output too large for checkpoints");
+                                enabled = false;
+                                output = Vec::new();
+                            }
+                            Err(e) => warn!("could not compress checkpoint: {e:#}"),
+                        }
+                        last_checkpoint = Instant::now();
+                    }
+                    if enabled {
+                        output.extend_from_slice(bytes);
+                    }
+                }
+                yield bytes;
+            }
+        };
+        Box::pin(StreamReader::new(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::location::concat_read_streams_with_locations;
+    use crate::preproc::loop_adapt_resumable;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use tokio::fs::File;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn resume_skips_completed_entries() -> Result<()> {
+        let filepath = test_data_dir().join("hello.tar");
+        let (a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
+        let adapter = crate::adapters::tar::TarAdapter::new();
+        let progress = Arc::new(Progress {
+            skip: 1,
+            ..Default::default()
+        });
+        let map = Arc::new(Mutex::new(LocationMap::default()));
+        let out = loop_adapt_resumable(&adapter, d, a, progress.clone()).await?;
+        let mut oup = String::new();
+        concat_read_streams_with_locations(out, map, 1, false)
+            .read_to_string(&mut oup)
+            .await?;
+        // the first entry (dir/file-b.pdf) is skipped
+        assert!(!oup.contains("file-b.pdf"), "{oup}");
+        assert!(oup.contains("file-a.pdf"), "{oup}");
+        assert_eq!(progress.completed.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
-use crate::{adapters::custom::CustomAdapterConfig, project_dirs};
+use crate::{
+    adapters::{alias::AdapterAliasConfig, custom::CustomAdapterConfig},
+    project_dirs,
+};
 use anyhow::{Context, Result};
 use derive_more::FromStr;
 use log::*;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 use std::io::Read;
+use std::path::Path;
 use std::{fs::File, io::Write, iter::IntoIterator, path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
@@ -42,6 +47,112 @@ impl Default for MaxArchiveRecursion {
     }
 }
 
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct MaxExpansionRatio(pub u64);
+
+impl std::fmt::Display for MaxExpansionRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Default for MaxExpansionRatio {
+    fn default() -> Self {
+        MaxExpansionRatio(1000)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct OcrDpi(pub u32);
+
+impl std::fmt::Display for OcrDpi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Default for OcrDpi {
+    fn default() -> Self {
+        OcrDpi(300)
+    }
+}
+
+/// Defines a tesseract option that is a number in the given range
+macro_rules! ocr_mode {
+    ($(#[$meta:meta])* $name:ident, $what:literal, $max:literal) => {
+        $(#[$meta])*
+        #[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+        #[serde(try_from = "u8")]
+        pub struct $name(pub u8);
+
+        impl TryFrom<u8> for $name {
+            type Error = String;
+            fn try_from(value: u8) -> Result<Self, Self::Error> {
+                if value > $max {
+                    return Err(format!(
+                        "{} must be between 0 and {}, got {}",
+                        $what, $max, value
+                    ));
+                }
+                Ok($name(value))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = anyhow::Error;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let value = u8::from_str(s).with_context(|| format!("invalid {}", $what))?;
+                $name::try_from(value).map_err(|e| anyhow::format_err!(e))
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+    };
+}
+
+ocr_mode!(
+    /// Tesseract page segmentation mode (`--psm`, 0 - 13)
+    OcrPageSegmentationMode,
+    "page segmentation mode",
+    13
+);
+ocr_mode!(
+    /// Tesseract OCR engine mode (`--oem`, 0 - 3)
+    OcrEngineMode,
+    "OCR engine mode",
+    3
+);
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct StringsMinLength(pub usize);
+
+impl std::fmt::Display for StringsMinLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Default for StringsMinLength {
+    fn default() -> Self {
+        StringsMinLength(4)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct MaxRows(pub usize);
+
+impl std::fmt::Display for MaxRows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+impl Default for MaxRows {
+    fn default() -> Self {
+        MaxRows(100_000)
+    }
+}
+
 #[derive(JsonSchema, Debug, Serialize, Deserialize, Clone, PartialEq, FromStr)]
 pub struct CachePath(pub String);
 
@@ -75,23 +186,150 @@ impl Default for CacheMaxBlobLen {
 impl FromStr for CacheMaxBlobLen {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let suffix = s.chars().last();
-        if let Some(suffix) = suffix {
-            Ok(CacheMaxBlobLen(match suffix {
-                'k' | 'M' | 'G' => usize::from_str(s.trim_end_matches(suffix))
-                    .with_context(|| "Could not parse int".to_string())
-                    .map(|e| {
-                        e * match suffix {
-                            'k' => 1000,
-                            'M' => 1_000_000,
-                            'G' => 1_000_000_000,
-                            _ => panic!("impossible"),
-                        }
-                    }),
-                _ => usize::from_str(s).with_context(|| "Could not parse int".to_string()),
-            }?))
-        } else {
-            Err(anyhow::format_err!("empty byte input"))
+        parse_byte_size(s).map(CacheMaxBlobLen)
+    }
+}
+
+/// Parse a number of bytes with an optional suffix k, M or G
+fn parse_byte_size(s: &str) -> Result<usize> {
+    let suffix = s.chars().last();
+    if let Some(suffix) = suffix {
+        match suffix {
+            'k' | 'M' | 'G' => usize::from_str(s.trim_end_matches(suffix))
+                .with_context(|| "Could not parse int".to_string())
+                .map(|e| {
+                    e * match suffix {
+                        'k' => 1000,
+                        'M' => 1_000_000,
+                        'G' => 1_000_000_000,
+                        _ => panic!("impossible"),
+                    }
+                }),
+            _ => usize::from_str(s).with_context(|| "Could not parse int".to_string()),
+        }
+    } else {
+        Err(anyhow::format_err!("empty byte input"))
+    }
+}
+
+/// A number of bytes. On the command line, the suffixes k, M and G are allowed.
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct ByteSize(pub usize);
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_byte_size(s).map(ByteSize)
+    }
+}
+
+/// How page numbers (e.g. of PDFs) are marked in the output
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PageStyle {
+    /// prefix every line with `Page N: `
+    #[default]
+    Prefix,
+    /// output a line `== Page N ==` at the start of each page and leave the content lines unchanged
+    Heading,
+}
+
+impl std::fmt::Display for PageStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PageStyle::Prefix => "prefix",
+            PageStyle::Heading => "heading",
+        })
+    }
+}
+
+impl FromStr for PageStyle {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefix" => Ok(PageStyle::Prefix),
+            "heading" => Ok(PageStyle::Heading),
+            _ => Err(anyhow::format_err!(
+                "unknown page style {s:?}, expected prefix or heading"
+            )),
+        }
+    }
+}
+
+/// Format of a match report, see `--rga-output`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// comma separated values, quoted as in RFC 4180
+    Csv,
+    /// tab separated values, tabs and newlines within fields are replaced by spaces
+    Tsv,
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+        })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(anyhow::format_err!(
+                "unknown output format {s:?}, expected csv or tsv"
+            )),
+        }
+    }
+}
+
+/// What to do with files (or files within archives) that contain binary data and that no adapter handles
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryPolicy {
+    /// output nothing
+    Skip,
+    /// output the line `[rga: binary data]`
+    #[default]
+    Placeholder,
+    /// pass the binary data on to rg unchanged (rg then reports matches as "binary file matches")
+    Passthrough,
+    /// convert the data with the adapter given by `binary_adapter`
+    Adapter,
+}
+
+impl std::fmt::Display for BinaryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BinaryPolicy::Skip => "skip",
+            BinaryPolicy::Placeholder => "placeholder",
+            BinaryPolicy::Passthrough => "passthrough",
+            BinaryPolicy::Adapter => "adapter",
+        })
+    }
+}
+
+impl FromStr for BinaryPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(BinaryPolicy::Skip),
+            "placeholder" => Ok(BinaryPolicy::Placeholder),
+            "passthrough" => Ok(BinaryPolicy::Passthrough),
+            "adapter" => Ok(BinaryPolicy::Adapter),
+            _ => Err(anyhow::format_err!(
+                "unknown binary policy {s:?}, expected skip, placeholder, passthrough or adapter"
+            )),
         }
     }
 }
@@ -142,117 +380,892 @@ pub struct RgaConfig {
     #[structopt(flatten)]
     pub cache: CacheConfig,
 
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub ocr: OcrConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub whisper: WhisperConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub pdf: PdfConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub protobuf: ProtobufConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub backup: BackupConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub semantic: SemanticConfig,
+
     /// Maximum depth of nested archives to recurse into.
     ///
     /// When searching in archives, rga will recurse into archives inside archives.
-    /// This option limits the depth.
+    /// This option limits the depth. Deeper archives are replaced by a line `[rga: skipped, exceeds limits (...)]`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-archive-recursion",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_archive_recursion: MaxArchiveRecursion,
+
+    /// Maximum total size of the files extracted from one file, including decompressed streams and nested archives.
+    ///
+    /// Protects against zip bombs: when it is reached, the rest of the archive is skipped with a line `[rga: skipped, exceeds limits (...)]`.
+    /// Unlimited by default.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-max-decompressed-size",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_decompressed_size: Option<ByteSize>,
+
+    /// Maximum ratio of the total size of the files extracted from one file to the size of the file.
+    ///
+    /// Like `--rga-max-decompressed-size`, but relative to the size of the file. Only checked once more than 16 MB were extracted,
+    /// so small files that compress well are not affected. 0 disables the check.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-expansion-ratio",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_expansion_ratio: MaxExpansionRatio,
+
+    /// Don't prefix lines of files within archive with the path inside the archive.
+    ///
+    /// Inside archives, by default rga prefixes the content of each file with the file path within the archive.
+    /// This is usually useful, but can cause problems because then the inner path is also searched for the pattern.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-no-prefix-filenames")]
+    pub no_prefix_filenames: bool,
+
+    /// Prefix lines of plain text files within archives with their line number in that file.
+    ///
+    /// rg's own line numbers count lines of the whole adapted output of the archive, which is not very useful.
+    /// With this flag, lines of text files within archives look like `app.log:4312: content`
+    /// where 4312 is the real line number within app.log.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-member-line-numbers")]
+    pub member_line_numbers: bool,
+
+    /// Encodings to try if a text file is not valid UTF-8.
+    ///
+    /// Comma-separated list of encoding labels (e.g. `windows-1251,koi8-r,shift_jis`), tried in order.
+    /// The first encoding that can decode the beginning of the file without errors is used for the whole file.
+    /// Note that single-byte encodings like windows-1251 can decode any input, so they should come last.
+    /// If none match (or the list is empty), the file is passed to rg unchanged.
+    /// Unknown labels are an error when the config is loaded and are reported by `--rga-check-config`.
+    ///
+    /// Also used for the names of files in zip archives that are not marked as UTF-8, which are decoded as CP437 if none match.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-fallback-encodings",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub fallback_encodings: Vec<String>,
+
+    /// How to mark page numbers in the output of paged documents (e.g. PDF and DjVu files).
+    ///
+    /// - `prefix` (default): prefix every line with `Page N: `
+    /// - `heading`: output a separate line `== Page N ==` at the start of each page.
+    ///   Content lines are unchanged, so patterns anchored at the start of the line work and context lines are easier to read.
+    ///   Page numbers are then not shown next to matches, use `-B` or `--rga-locate` (which finds the last heading before the match) to find them.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-page-style",
+        require_equals = true,
+        possible_values = &["prefix", "heading"],
+        hidden_short_help = true
+    )]
+    pub page_style: PageStyle,
+
+    /// What to do with binary data that no adapter handles.
+    ///
+    /// Binary data is detected by null bytes in the first 8KiB of a file.
+    /// - `skip`: output nothing
+    /// - `placeholder` (default): output `[rga: binary data]`
+    /// - `passthrough`: pass the data to rg unchanged and let rg decide (see rg's `--binary` and `--text`)
+    /// - `adapter`: convert the data with the adapter given by `--rga-binary-adapter`.
+    ///   Binary data in the output of that adapter is passed to rg unchanged.
+    ///   Like with `--rga-accurate`, every file is then read by rga-preproc, since any file can be binary.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-binary",
+        require_equals = true,
+        possible_values = &["skip", "placeholder", "passthrough", "adapter"],
+        hidden_short_help = true
+    )]
+    pub binary: BinaryPolicy,
+
+    /// Name of the adapter that converts binary data if `--rga-binary=adapter` is set.
+    ///
+    /// The adapter has to be enabled, e.g. with `--rga-adapters=+hexdump`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-binary-adapter",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub binary_adapter: Option<String>,
+
+    /// Minimum length of the strings extracted by the `strings` adapter.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-strings-min-length",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub strings_min_length: StringsMinLength,
+
+    /// Maximum number of rows output per file by the adapters for data files (e.g. parquet).
+    ///
+    /// Further rows are skipped, with a line `[rga: only the first N rows are output]` at the end.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-rows",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_rows: MaxRows,
+
+    /// Only output these columns of parquet files, e.g. `title,description`. All columns by default.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-parquet-columns",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub parquet_columns: Vec<String>,
+
+    /// Maximum size of the adapted output of a file.
+    ///
+    /// If an adapter outputs more, the output is cut off and ends with a line `[rga: output truncated at N MB]`.
+    /// The truncated output is cached like any other output.
+    /// Unlimited by default.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-max-output-size",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_output_size: Option<ByteSize>,
+
+    /// Maximum size of the adapted output per adapter, e.g. `{"ffmpeg": 1000000}`.
+    ///
+    /// Overrides `max_output_size` for files handled by the given adapters.
+    /// Also applies to files within archives, to each file the adapter outputs for them.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub adapter_max_output_size: BTreeMap<String, ByteSize>,
+
+    /// Also search the NTFS alternate data streams of files (Windows only).
+    ///
+    /// The content of each stream (e.g. `Zone.Identifier`, which records where a file was downloaded from)
+    /// is output after the content of the file, with lines prefixed by `file.docx:streamname: `.
+    /// Streams are adapted like files with the same name, but not cached.
+    /// All files are passed to rga-preproc (no `--pre-glob`), which makes searches slower.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-ntfs-streams", hidden_short_help = true)]
+    pub ntfs_streams: bool,
+
+    /// Also search the extended attributes of files (e.g. `user.*` attributes on Linux, Finder comments, tags and quarantine info on macOS).
+    ///
+    /// Each attribute is output after the content of the file as lines `xattr:NAME: VALUE`.
+    /// Attributes with binary values are skipped.
+    /// All files are passed to rga-preproc (no `--pre-glob`), which makes searches slower.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-xattrs", hidden_short_help = true)]
+    pub xattrs: bool,
+
+    /// File with passwords to try for encrypted documents, one per line.
+    ///
+    /// Adapters that support encrypted files (e.g. poppler for PDFs, zip and 7z archives) try the passwords in order until one works.
+    /// Encrypted Office documents are not supported, rga does not implement their decryption.
+    /// Which password worked for each file is stored in the cache next to its location map.
+    /// pdftotext and 7z only take passwords as arguments, so while they run, other users of the machine
+    /// can see the passwords being tried in the process list.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-password-file",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub password_file: Option<String>,
+
+    /// Password for encrypted zip and 7z archives. Can be given multiple times.
+    ///
+    /// The passwords are tried in order, before the ones of `--rga-password-file`.
+    /// 7z only takes passwords as argument, so while it runs, other users of the machine
+    /// can see the password being tried in the process list.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-archive-password",
+        require_equals = true,
+        number_of_values = 1,
+        hidden_short_help = true
+    )]
+    pub archive_passwords: Vec<String>,
+
+    /// Ask for the password on the terminal if none of the given passwords opens an encrypted archive.
+    ///
+    /// Entered passwords are also tried for the archives within it. Since the extracted text is cached,
+    /// you are only asked again when the archive changes.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-archive-password-prompt", hidden_short_help = true)]
+    pub archive_password_prompt: bool,
+
+    /// Only search documents in the given languages, e.g. `de,en` (ISO 639-1 or 639-3 codes).
+    ///
+    /// The language is detected from the start of the adapted output of each file, including each file within archives, and stored in the cache.
+    /// Documents whose language can't be detected reliably (e.g. because they contain little text) are always searched.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-lang",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub lang: Vec<String>,
+
+    /// Output the modification time, owner and permissions of each file in tar archives.
+    ///
+    /// Output as a line `[tar] modified 2024-05-01 12:00:00 UTC, owner me:users, mode 644` prefixed with the path of the file, before its content.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-tar-metadata", hidden_short_help = true)]
+    pub tar_metadata: bool,
+
+    /// Output blobs in sqlite databases that contain UTF-8 or UTF-16 text as text,
+    /// and binary plists as their flattened `key = value` pairs.
+    ///
+    /// By default, blobs are output as `[blob 12kB]`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-sqlite-text-blobs", hidden_short_help = true)]
+    pub sqlite_text_blobs: bool,
+
+    /// Replace the values of identifying attributes in DICOM files with `[redacted]`.
+    ///
+    /// Redacts person names, patient IDs, birth date, age, addresses and phone numbers, institution and station names,
+    /// accession number, study ID, instance UIDs and private tags. The tag names are still output.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-dicom-redact", hidden_short_help = true)]
+    pub dicom_redact: bool,
+
+    /// Output results in a deterministic order.
+    ///
+    /// Files are searched in order of their path (this passes `--sort=path` to rg),
+    /// and the files within archives are sorted by their path within the archive.
+    /// Useful for comparing the output of different runs.
+    /// rg then searches on a single thread, so the files are also preprocessed one after another,
+    /// which is much slower for many files that need an adapter (unless they are cached).
+    /// Up to 64 MiB of the contents of an archive are buffered in memory for sorting, the files after that are output in archive order.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-sort", hidden_short_help = true)]
+    pub sort: bool,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub custom_adapters: Option<Vec<CustomAdapterConfig>>,
+
+    /// Makes existing adapters also handle other file extensions / mime types,
+    /// e.g. `[{"adapter": "poppler", "extensions": ["ai"]}]`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub adapter_aliases: Vec<AdapterAliasConfig>,
+
+    #[serde(skip)]
+    #[structopt(long = "--rga-config-file", require_equals = true)]
+    pub config_file_path: Option<String>,
+
+    /// Allow the project-local `.rga.toml` to set options that run commands or name files outside the project.
+    ///
+    /// Without this, keys like `custom_adapters`, `adapters` or `ocr.engine_command` in a `.rga.toml` are ignored with a warning,
+    /// since searching an untrusted repository must not run commands from it.
+    /// Can only be set on the command line or in the global config file.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-trust-project-config", hidden_short_help = true)]
+    pub trust_project_config: bool,
+
+    /// Same as passing path directly, except if argument is empty.
+    ///
+    /// Kinda hacky, but if no file is found, `fzf` calls `rga` with empty string as path, which causes "No such file or directory from rg".
+    /// So filter those cases and return specially.
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-fzf-path", require_equals = true, hidden = true)]
+    pub fzf_path: Option<String>,
+
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-list-adapters", help = "List all known adapters")]
+    pub list_adapters: bool,
+
+    /// Print the glob of files that rga handles and exit.
+    ///
+    /// For using rga-preproc with a stock ripgrep: `rg --pre rga-preproc --pre-glob "$(rga --rga-print-pre-glob)" PATTERN`
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-print-pre-glob", hidden_short_help = true)]
+    pub print_pre_glob: bool,
+
+    /// Print the location in the original document of a line of adapted output and exit.
+    ///
+    /// Usage: `rga --rga-locate FILE LINE[:COLUMN]`, where LINE is the line number (1-based) in the adapted output of FILE
+    /// and COLUMN the column as output by `rg --column`. Byte offsets (`rg -b`) are not supported.
+    /// Prints a JSON object with the path within archives (`member`), the `page` (if known), the line within that member,
+    /// the column without the prefixes added by rga and the text of the line.
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-locate", hidden_short_help = true)]
+    pub locate: bool,
+
+    /// Check the config files for errors and exit.
+    ///
+    /// Validates the global config file and the project-local `.rga.toml` (if any) against the config schema,
+    /// printing the location of every problem found.
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-check-config")]
+    pub check_config: bool,
+
+    #[serde(skip)] // CLI only
+    #[structopt(
+        long = "--rga-print-config-schema",
+        help = "Print the JSON Schema of the configuration file"
+    )]
+    pub print_config_schema: bool,
+
+    /// Print shell completions for rga and exit.
+    ///
+    /// The completions include the flags of the installed ripgrep version.
+    #[serde(skip)] // CLI only
+    #[structopt(
+        long = "--rga-completions",
+        require_equals = true,
+        possible_values = &["bash", "zsh", "fish", "powershell"],
+        hidden_short_help = true
+    )]
+    pub completions: Option<String>,
+
+    /// Use the index of plocate / mlocate (or Everything on Windows) to find the files to search instead of walking directories.
+    ///
+    /// The value is passed to `locate` as the pattern, e.g. `--rga-locate-prefilter='/home/me/papers/*.pdf'`.
+    /// Only existing files from the result are searched, so no paths to search can be given.
+    /// If no locate tool is installed, rga warns and searches normally.
+    #[serde(skip)] // CLI only
+    #[structopt(
+        long = "--rga-locate-prefilter",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub locate_prefilter: Option<String>,
+
+    /// Search by meaning instead of by regex: output the chunks of text most similar to the given query.
+    ///
+    /// Usage: `rga --rga-semantic="invoices about server hosting" [PATH ...]`.
+    /// The text of all files is split into chunks, which are embedded with `semantic.embed_command` from the config file.
+    /// The embeddings are stored in the cache directory, so only new or changed files are embedded again.
+    #[serde(skip)] // CLI only
+    #[structopt(
+        long = "--rga-semantic",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub semantic_query: Option<String>,
+
+    /// Search all versions of the files in the git repository of the current directory instead of the files on disk.
+    ///
+    /// Every blob reachable from a branch, tag or HEAD is searched once with the normal adapters, prefixed with the
+    /// abbreviated id of the oldest commit that contains it and its path (`a1b2c3d:docs/report.pdf: Page 1: ...`).
+    /// Paths to search are rejected. Failures of adapters are summarized and change the exit code like in normal searches.
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-git-history", hidden_short_help = true)]
+    pub git_history: bool,
+
+    /// Output a report with one row per match instead of the rg output.
+    ///
+    /// The columns are the file path, the path within archives, the page, the line number within that file,
+    /// the matched line and the matched text.
+    /// - `csv`: comma separated, for spreadsheet applications like Excel.
+    ///   Cells starting with `=`, `+`, `-` or `@` are prefixed with `'`, so they aren't run as formulas
+    /// - `tsv`: tab separated
+    #[serde(skip)] // CLI only
+    #[structopt(
+        long = "--rga-output",
+        require_equals = true,
+        possible_values = &["csv", "tsv"],
+        hidden_short_help = true
+    )]
+    pub output: Option<OutputFormat>,
+
+    /// Run a server for editor plugins that reads search requests as JSON lines from stdin and writes structured results to stdout.
+    ///
+    /// Each request looks like `{"id": 1, "query": "regex", "paths": ["."], "args": ["-i"]}`.
+    /// Each match is output as `{"type": "match", "id": 1, "path": ..., "member": ..., "page": ..., "text": ...}`,
+    /// the end of a request as `{"type": "done", "id": 1, "matches": 3}`.
+    /// Other rga options given on the command line apply to all requests.
+    #[serde(skip)] // CLI only
+    #[structopt(long = "--rga-editor-server", hidden_short_help = true)]
+    pub editor_server: bool,
+
+    #[serde(skip)] // CLI only
+    #[structopt(long, help = "Show help for ripgrep itself")]
+    pub rg_help: bool,
+
+    #[serde(skip)] // CLI only
+    #[structopt(long, help = "Show version of ripgrep itself")]
+    pub rg_version: bool,
+}
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct CacheConfig {
+    /// Disable caching of results.
+    ///
+    /// By default, rga caches the extracted text, if it is small enough, to a database.
+    /// This way, repeated searches on the same set of files will be much faster.
+    /// The location of the DB varies by platform:
+    /// - `${XDG_CACHE_DIR-~/.cache}/ripgrep-all` on Linux
+    /// - `~/Library/Caches/ripgrep-all` on macOS
+    /// - `C:\Users\username\AppData\Local\ripgrep-all` on Windows
+    ///
+    /// If you pass this flag, all caching will be disabled.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-no-cache")]
+    pub disabled: bool,
+
+    /// Max compressed size to cache.
+    ///
+    /// Longest byte length (after compression) to store in cache.
+    /// Longer adapter outputs will not be cached and recomputed every time.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-max-blob-len",
+        hidden_short_help = true,
+        require_equals = true,
+        // parse(try_from_str = parse_readable_bytes_str)
+    )]
+    pub max_blob_len: CacheMaxBlobLen,
+
+    /// ZSTD compression level to apply to adapter outputs before storing in cache DB.
+    ///
+    /// Ranges from 1 - 22.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-compression-level",
+        hidden_short_help = true,
+        require_equals = true,
+        help = ""
+    )]
+    pub compression_level: CacheCompressionLevel,
+
+    /// Path to store cache DB.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-path",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub path: CachePath,
+}
+
+/// Passwords of the backup repositories searched by the `restic` and `borg` adapters
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct BackupConfig {
+    /// Command that outputs the password of restic repositories, passed to `restic --password-command`,
+    /// e.g. `"pass show restic"`. Without it, restic uses `password_file` of the `.restic` file or `RESTIC_PASSWORD`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub restic_password_command: Option<String>,
+
+    /// Command that outputs the passphrase of borg repositories (`BORG_PASSCOMMAND`), e.g. `"pass show borg"`.
+    /// Without it, borg uses the `BORG_PASSPHRASE` or `BORG_PASSCOMMAND` of the environment.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub borg_passcommand: Option<String>,
+}
+
+/// Schemas for decoding protobuf files (the adapter is enabled with `--rga-adapters=+protobuf`)
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct ProtobufConfig {
+    /// `.proto` files that define the message type, e.g. `["/home/me/schemas/events.proto"]`.
+    ///
+    /// Without schema, the fields of protobuf files are output by number (like `protoc --decode_raw`).
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub proto_files: Vec<String>,
+
+    /// Directories to search for the imports of the `.proto` files, in addition to their own directories.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub include_paths: Vec<String>,
+
+    /// Fully qualified name of the message type of the files, e.g. `mycompany.events.Event`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub message_type: Option<String>,
+
+    /// Whether the files contain a sequence of messages, each prefixed with its length.
+    ///
+    /// Detected from the content if not set.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub delimited: Option<bool>,
+}
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct OcrConfig {
+    /// Languages to use for OCR.
+    ///
+    /// Tesseract language codes, for example `eng,deu,chi_sim`. The corresponding traineddata files must be installed.
+    /// Defaults to English.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-languages",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub languages: Vec<String>,
+
+    /// Tesseract page segmentation mode (0 - 13).
+    ///
+    /// If not set, tesseract's default (3, fully automatic page segmentation) is used.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-psm",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub page_segmentation_mode: Option<OcrPageSegmentationMode>,
+
+    /// Tesseract OCR engine mode (0 - 3).
+    ///
+    /// If not set, tesseract's default (3, based on what is available) is used.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-oem",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub engine_mode: Option<OcrEngineMode>,
+
+    /// Resolution used when rasterizing PDF pages for OCR.
+    ///
+    /// Also passed to the OCR engine for images that don't specify their resolution.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-ocr-dpi",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub dpi: OcrDpi,
+
+    /// OCR the pages of PDFs that contain no text (scanned documents).
+    ///
+    /// The pages are rasterized with pdftoppm (poppler-utils) at the configured DPI. Slow, so it is off by default.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-ocr-pdf", hidden_short_help = true)]
+    pub pdf: bool,
+
+    /// Use a different OCR engine instead of tesseract.
+    ///
+    /// The first element is the binary to run, the rest are its arguments.
+    /// The image is passed on stdin, the recognized text is expected on stdout.
+    /// Placeholders:
+    /// - `$languages`: the configured languages joined with `+`
+    /// - `$dpi`: the configured DPI
+    ///
+    /// For example `["my-ocr", "--lang=$languages", "-"]`
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub engine_command: Option<Vec<String>>,
+
+    /// OCR settings of single adapters, overriding the ones above.
+    ///
+    /// The adapters using OCR are `ocr` for images and `poppler` for the pages of scanned PDFs (`--rga-ocr-pdf`),
+    /// e.g. `{"poppler": {"languages": ["deu"], "dpi": 200}}`
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub adapters: BTreeMap<String, OcrAdapterConfig>,
+}
+
+/// OCR settings of one adapter, see `OcrConfig` for their meaning. Unset values are taken from `OcrConfig`.
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct OcrAdapterConfig {
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub languages: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub page_segmentation_mode: Option<OcrPageSegmentationMode>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub engine_mode: Option<OcrEngineMode>,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub dpi: Option<OcrDpi>,
+}
+
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct WhisperConfig {
+    /// The whisper.cpp model file for the whisper adapter, e.g. `~/models/ggml-base.bin`.
+    ///
+    /// Models can be downloaded with the `download-ggml-model.sh` script of whisper.cpp.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-max-archive-recursion",
+        long = "--rga-whisper-model",
         require_equals = true,
         hidden_short_help = true
     )]
-    pub max_archive_recursion: MaxArchiveRecursion,
+    pub model: Option<String>,
 
-    /// Don't prefix lines of files within archive with the path inside the archive.
-    ///
-    /// Inside archives, by default rga prefixes the content of each file with the file path within the archive.
-    /// This is usually useful, but can cause problems because then the inner path is also searched for the pattern.
+    /// Spoken language for the whisper adapter, e.g. `en` or `de`. Detected automatically if not set.
     #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(long = "--rga-no-prefix-filenames")]
-    pub no_prefix_filenames: bool,
+    #[structopt(
+        long = "--rga-whisper-language",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub language: Option<String>,
 
+    /// Use a different speech recognition program instead of whisper.cpp (`whisper-cli`).
+    ///
+    /// The first element is the binary to run, the rest are its arguments.
+    /// The segments are expected on stdout as lines like `[00:00:01.000 --> 00:00:04.500] text`, which is what whisper.cpp and openai-whisper output.
+    /// Placeholders:
+    /// - `$input`: the audio as 16 kHz mono WAV file
+    /// - `$model`: the configured model
+    /// - `$language`: the configured language or `auto`
+    ///
+    /// For example `["whisper", "--model", "base", "--output_dir", "/tmp/whisper", "$input"]`
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(skip)] // config file only
-    pub custom_adapters: Option<Vec<CustomAdapterConfig>>,
+    pub engine_command: Option<Vec<String>>,
+}
 
-    #[serde(skip)]
-    #[structopt(long = "--rga-config-file", require_equals = true)]
-    pub config_file_path: Option<String>,
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PdfConfig {
+    /// Output the fields of PDF forms (AcroForm and XFA) with their filled-in values.
+    ///
+    /// Output as a line `form: name = value` on the page of the field, after the text of the page.
+    /// XFA fields have no page and are output on the first page. Needs qpdf.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-pdf-forms", hidden_short_help = true)]
+    pub forms: bool,
 
-    /// Same as passing path directly, except if argument is empty.
+    /// Recurse into the files embedded in PDFs, e.g. the XML data of ZUGFeRD / Factur-X invoices.
     ///
-    /// Kinda hacky, but if no file is found, `fzf` calls `rga` with empty string as path, which causes "No such file or directory from rg".
-    /// So filter those cases and return specially.
-    #[serde(skip)] // CLI only
-    #[structopt(long = "--rga-fzf-path", require_equals = true, hidden = true)]
-    pub fzf_path: Option<String>,
+    /// Their lines are prefixed with the name of the attachment. Needs qpdf.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-pdf-attachments", hidden_short_help = true)]
+    pub attachments: bool,
 
-    #[serde(skip)] // CLI only
-    #[structopt(long = "--rga-list-adapters", help = "List all known adapters")]
-    pub list_adapters: bool,
+    /// Add the title of the section from the outline (bookmarks) of PDFs to the page prefixes: `Page 42 [3.1 Results]: `.
+    ///
+    /// With `--rga-page-style=heading`, a heading is output at the start of each section. Needs qpdf.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-pdf-outline", hidden_short_help = true)]
+    pub outline: bool,
 
-    #[serde(skip)] // CLI only
+    /// Password for encrypted PDFs, the user or the owner password.
+    ///
+    /// Tried before the passwords of `--rga-password-file`.
+    /// pdftotext only takes it as argument (`-opw`, `-upw`), so it is visible in the process list while pdftotext runs.
+    #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        long = "--rga-print-config-schema",
-        help = "Print the JSON Schema of the configuration file"
+        long = "--rga-pdf-password",
+        require_equals = true,
+        hidden_short_help = true
     )]
-    pub print_config_schema: bool,
-
-    #[serde(skip)] // CLI only
-    #[structopt(long, help = "Show help for ripgrep itself")]
-    pub rg_help: bool,
+    pub password: Option<String>,
 
-    #[serde(skip)] // CLI only
-    #[structopt(long, help = "Show version of ripgrep itself")]
-    pub rg_version: bool,
+    /// Command that outputs passwords for encrypted PDFs, one per line, e.g. to read them from a keyring:
+    /// `["secret-tool", "lookup", "service", "ripgrep-all"]`.
+    ///
+    /// Run once, the passwords are tried after `--rga-pdf-password`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(skip)] // config file only
+    pub password_command: Option<Vec<String>>,
 }
 
 #[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
-pub struct CacheConfig {
-    /// Disable caching of results.
+pub struct SemanticConfig {
+    /// Program that computes the embeddings for `--rga-semantic`.
     ///
-    /// By default, rga caches the extracted text, if it is small enough, to a database.
-    /// This way, repeated searches on the same set of files will be much faster.
-    /// The location of the DB varies by platform:
-    /// - `${XDG_CACHE_DIR-~/.cache}/ripgrep-all` on Linux
-    /// - `~/Library/Caches/ripgrep-all` on macOS
-    /// - `C:\Users\username\AppData\Local\ripgrep-all` on Windows
+    /// The first element is the binary to run, the rest are its arguments.
+    /// It gets one JSON string per line on stdin and must output one JSON array of numbers (the embedding) per line on stdout, in the same order.
+    /// This can be a small script around a local model (e.g. with sentence-transformers, llama.cpp or ollama) or an embedding API.
     ///
-    /// If you pass this flag, all caching will be disabled.
+    /// For example `["python3", "/home/me/embed.py"]`
     #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(long = "--rga-no-cache")]
-    pub disabled: bool,
+    #[structopt(skip)] // config file only
+    pub embed_command: Option<Vec<String>>,
 
-    /// Max compressed size to cache.
-    ///
-    /// Longest byte length (after compression) to store in cache.
-    /// Longer adapter outputs will not be cached and recomputed every time.
-    ///
-    /// Allowed suffixes on command line: k M G
+    /// Number of results of `--rga-semantic`. Defaults to 10.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-cache-max-blob-len",
-        hidden_short_help = true,
+        long = "--rga-semantic-results",
         require_equals = true,
-        // parse(try_from_str = parse_readable_bytes_str)
+        hidden_short_help = true
     )]
-    pub max_blob_len: CacheMaxBlobLen,
+    pub results: Option<usize>,
 
-    /// ZSTD compression level to apply to adapter outputs before storing in cache DB.
+    /// Maximum number of lines per chunk of text for `--rga-semantic`. Defaults to 10.
     ///
-    /// Ranges from 1 - 22.
+    /// Chunks also end at page breaks and between the files in archives.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
-        default_value,
-        long = "--rga-cache-compression-level",
-        hidden_short_help = true,
+        long = "--rga-semantic-chunk-lines",
         require_equals = true,
-        help = ""
+        hidden_short_help = true
     )]
-    pub compression_level: CacheCompressionLevel,
+    pub chunk_lines: Option<usize>,
+}
 
-    /// Path to store cache DB.
-    #[serde(default, skip_serializing_if = "is_default")]
-    #[structopt(
-        default_value,
-        long = "--rga-cache-path",
-        hidden_short_help = true,
-        require_equals = true
-    )]
-    pub path: CachePath,
+impl RgaConfig {
+    /// Whether metadata that any file can have is searched, so all files have to be passed to rga-preproc
+    pub fn searches_file_metadata(&self) -> bool {
+        self.ntfs_streams || self.xattrs
+    }
+
+    /// Whether files that no adapter matches by name still have to go through rga-preproc:
+    /// with mime type matching, or to convert binary files with `--rga-binary=adapter`
+    pub fn preprocesses_unmatched_files(&self) -> bool {
+        self.accurate || self.binary == BinaryPolicy::Adapter
+    }
+
+    /// The maximum output size for files handled by the given adapter, if any
+    pub fn max_output_size_for(&self, adapter: &str) -> Option<usize> {
+        self.adapter_max_output_size
+            .get(adapter)
+            .or(self.max_output_size.as_ref())
+            .map(|s| s.0)
+    }
+
+    /// Values that can be deserialized but not used, each prefixed with the path of the offending key
+    pub fn invalid_values(&self) -> Vec<String> {
+        self.fallback_encodings
+            .iter()
+            .enumerate()
+            .filter(|(_, label)| {
+                encoding_rs::Encoding::for_label(label.trim().as_bytes()).is_none()
+            })
+            .map(|(i, label)| format!("fallback_encodings[{i}]: unknown encoding {label:?}"))
+            .collect()
+    }
+}
+
+impl WhisperConfig {
+    /// Program and arguments to transcribe the given WAV file, writing the segments to stdout.
+    pub fn command(&self, input: &std::path::Path) -> Result<(String, Vec<String>)> {
+        let input = input.to_string_lossy();
+        let language = self.language.as_deref().unwrap_or("auto");
+        if let Some(command) = &self.engine_command {
+            let (binary, args) = command
+                .split_first()
+                .context("whisper.engine_command must not be empty")?;
+            let model = self.model.as_deref().unwrap_or_default();
+            let args = args
+                .iter()
+                .map(|arg| {
+                    crate::expand::expand_str_ez(arg, |s| match s {
+                        "input" => Ok(std::borrow::Cow::Borrowed(input.as_ref())),
+                        "model" => Ok(std::borrow::Cow::Borrowed(model)),
+                        "language" => Ok(std::borrow::Cow::Borrowed(language)),
+                        e => Err(anyhow::format_err!("unknown replacer ${{{e}}}")),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Ok((binary.clone(), args));
+        }
+        let model = self.model.as_deref().context(
+            "Please set --rga-whisper-model to a whisper.cpp model file (e.g. ggml-base.bin) or whisper.engine_command in the config file.",
+        )?;
+        Ok((
+            "whisper-cli".to_string(),
+            ["-m", model, "-l", language, "-f", &*input]
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        ))
+    }
+}
+
+impl OcrConfig {
+    /// The OCR settings of the adapter with the given name, see `adapters`
+    pub fn for_adapter(&self, adapter: &str) -> OcrConfig {
+        let mut ocr = self.clone();
+        if let Some(o) = self.adapters.get(adapter) {
+            if let Some(languages) = &o.languages {
+                ocr.languages = languages.clone();
+            }
+            ocr.page_segmentation_mode = o.page_segmentation_mode.or(ocr.page_segmentation_mode);
+            ocr.engine_mode = o.engine_mode.or(ocr.engine_mode);
+            ocr.dpi = o.dpi.unwrap_or(ocr.dpi);
+        }
+        ocr
+    }
+
+    /// the configured languages in tesseract format (`eng+deu`)
+    pub fn languages_arg(&self) -> String {
+        if self.languages.is_empty() {
+            "eng".to_string()
+        } else {
+            self.languages.join("+")
+        }
+    }
+
+    /// Program and arguments to run OCR on an image from stdin, writing the text to stdout.
+    pub fn command(&self) -> Result<(String, Vec<String>)> {
+        if let Some(command) = &self.engine_command {
+            let (binary, args) = command
+                .split_first()
+                .context("ocr.engine_command must not be empty")?;
+            let languages = self.languages_arg();
+            let dpi = self.dpi.to_string();
+            let args = args
+                .iter()
+                .map(|arg| {
+                    crate::expand::expand_str_ez(arg, |s| match s {
+                        "languages" => Ok(std::borrow::Cow::Borrowed(languages.as_str())),
+                        "dpi" => Ok(std::borrow::Cow::Borrowed(dpi.as_str())),
+                        e => Err(anyhow::format_err!("unknown replacer ${{{e}}}")),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Ok((binary.clone(), args));
+        }
+        let mut args = vec![
+            "stdin".to_string(),
+            "stdout".to_string(),
+            "-l".to_string(),
+            self.languages_arg(),
+            "--dpi".to_string(),
+            self.dpi.to_string(),
+        ];
+        if let Some(psm) = self.page_segmentation_mode {
+            args.extend(["--psm".to_string(), psm.to_string()]);
+        }
+        if let Some(oem) = self.engine_mode {
+            args.extend(["--oem".to_string(), oem.to_string()]);
+        }
+        Ok(("tesseract".to_string(), args))
+    }
 }
 
 static RGA_CONFIG: &str = "RGA_CONFIG";
@@ -271,33 +1284,395 @@ fn json_merge(a: &mut Value, b: &Value) {
     }
 }
 
+/// name of the project-local config file, searched for in the search root and all its parents
+static PROJECT_CONFIG_FILENAME: &str = ".rga.toml";
+
+/// Keys that make rga run commands, enable the adapters that do, or read and write files outside the project.
+/// Ignored in project-local config files unless `trust_project_config` is set
+static PROJECT_CONFIG_UNTRUSTED_KEYS: &[&str] = &[
+    "custom_adapters",
+    "adapters",
+    "adapter_aliases",
+    "binary",
+    "binary_adapter",
+    "password_file",
+    "protobuf.proto_files",
+    "protobuf.include_paths",
+    "cache.path",
+    "ocr.engine_command",
+    "whisper.engine_command",
+    "whisper.model",
+    "pdf.password_command",
+    "backup.restic_password_command",
+    "backup.borg_passcommand",
+    "semantic.embed_command",
+    "trust_project_config",
+];
+
+/// short rg flags that take a value
+static RG_SHORT_VALUE_FLAGS: &str = "ABCEMTdefgjmrt";
+
+/// long rg flags that take the following argument as value (if not given as `--flag=value`)
+static RG_VALUE_FLAGS: &[&str] = &[
+    "--after-context",
+    "--before-context",
+    "--color",
+    "--colors",
+    "--context",
+    "--context-separator",
+    "--dfa-size-limit",
+    "--encoding",
+    "--engine",
+    "--field-context-separator",
+    "--field-match-separator",
+    "--file",
+    "--glob",
+    "--hostname-bin",
+    "--hyperlink-format",
+    "--iglob",
+    "--ignore-file",
+    "--max-columns",
+    "--max-count",
+    "--max-depth",
+    "--max-filesize",
+    "--path-separator",
+    "--pre",
+    "--pre-glob",
+    "--regex-size-limit",
+    "--regexp",
+    "--replace",
+    "--sort",
+    "--sortr",
+    "--threads",
+    "--type",
+    "--type-add",
+    "--type-clear",
+    "--type-not",
+];
+
+/// The paths searched by rg with the given arguments. Defaults to the current directory.
+pub fn rg_search_paths(rg_args: &[OsString]) -> Vec<PathBuf> {
+    let mut paths = rg_path_args(rg_args);
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+    paths
+}
+
+/// The paths given to rg: the positional arguments, except for the pattern
+/// (which is the first one unless given with `-e` / `-f`)
+pub fn rg_path_args(rg_args: &[OsString]) -> Vec<PathBuf> {
+    let mut positional = vec![];
+    let mut pattern_given = false;
+    let mut args = rg_args.iter();
+    while let Some(os_arg) = args.next() {
+        let arg = match os_arg.to_str() {
+            Some(arg) if arg.starts_with('-') && arg.len() > 1 => arg,
+            // not a flag (args that are not unicode can only be paths)
+            _ => {
+                positional.push(PathBuf::from(os_arg));
+                continue;
+            }
+        };
+        if arg == "--" {
+            positional.extend(args.by_ref().map(PathBuf::from));
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline_value) = match long.split_once('=') {
+                Some((name, _)) => (name, true),
+                None => (long, false),
+            };
+            pattern_given |= name == "regexp" || name == "file";
+            if !inline_value && RG_VALUE_FLAGS.contains(&arg) {
+                args.next();
+            }
+        } else {
+            // in a group of short flags (`-iA3`), a flag that takes a value takes the rest of the group
+            let value_flag = arg
+                .char_indices()
+                .skip(1)
+                .find(|(_, c)| RG_SHORT_VALUE_FLAGS.contains(*c));
+            if let Some((i, c)) = value_flag {
+                pattern_given |= c == 'e' || c == 'f';
+                if i + c.len_utf8() == arg.len() {
+                    args.next();
+                }
+            }
+        }
+    }
+    if !pattern_given && !positional.is_empty() {
+        positional.remove(0);
+    }
+    positional
+}
+
+fn global_config_filename(path_override: Option<&String>) -> Result<PathBuf> {
+    Ok(match path_override {
+        Some(p) => PathBuf::from(p),
+        None => project_dirs()?.config_dir().join("config.jsonc"),
+    })
+}
+
+/// Parse a config file into a json value. `.toml` files are parsed as TOML, everything else as JSON with comments.
+///
+/// Errors contain the line and column of syntax errors.
+fn parse_config_str(path: &Path, contents: &str) -> Result<Value> {
+    if path.extension().is_some_and(|e| e == "toml") {
+        Ok(toml::from_str(contents)?)
+    } else {
+        let mut s = String::new();
+        json_comments::StripComments::new(contents.as_bytes())
+            .read_to_string(&mut s)
+            .context("strip comments")?;
+        Ok(serde_json::from_str(&s)?)
+    }
+}
+
+fn read_config_value(path: &Path) -> Result<Value> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Could not read config file {}", path.display()))?;
+    let value = parse_config_str(path, &contents)
+        .with_context(|| format!("Could not parse config file {}", path.display()))?;
+    for problem in unknown_config_keys(&value) {
+        warn!("{}: {}", path.display(), problem);
+    }
+    // just for error messages, actual deserialization happens after merging with cmd args
+    deserialize_config_value(&value)
+        .with_context(|| format!("Error in config file {}", path.display()))?;
+    Ok(value)
+}
+
+/// Find the project-local config file by walking upward from the given directory.
+fn find_project_config_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILENAME))
+        .find(|p| p.is_file())
+}
+
+/// Find the project-local config file of the given search paths.
+///
+/// If the search paths belong to different projects, none of their config files is used.
+fn find_search_project_config_file(search_paths: &[PathBuf]) -> Result<Option<PathBuf>> {
+    let cwd = std::env::current_dir()?;
+    let mut found = search_paths.iter().map(|p| {
+        let p = cwd.join(p);
+        // search paths can be files as well
+        let start = if p.is_dir() {
+            p.as_path()
+        } else {
+            p.parent().unwrap_or(&p)
+        };
+        find_project_config_file(start)
+    });
+    let first = found.next().flatten();
+    if found.any(|f| f != first) {
+        warn!(
+            "the search paths are in different projects, ignoring their {PROJECT_CONFIG_FILENAME} files"
+        );
+        return Ok(None);
+    }
+    Ok(first)
+}
+
+/// Remove the keys that run commands from a project-local config, see `PROJECT_CONFIG_UNTRUSTED_KEYS`.
+///
+/// Returns the removed keys.
+fn remove_untrusted_keys(value: &mut Value) -> Vec<&'static str> {
+    let mut removed = vec![];
+    for key in PROJECT_CONFIG_UNTRUSTED_KEYS {
+        let (parent, name) = match key.split_once('.') {
+            Some((section, name)) => (value.get_mut(section), name),
+            None => (Some(&mut *value), *key),
+        };
+        let removed_key = parent
+            .and_then(Value::as_object_mut)
+            .and_then(|o| o.remove(name))
+            .is_some();
+        if removed_key {
+            removed.push(*key);
+        }
+    }
+    removed
+}
+
+fn read_project_config_file(
+    search_paths: &[PathBuf],
+    trusted: bool,
+) -> Result<Option<(String, Value)>> {
+    let Some(path) = find_search_project_config_file(search_paths)? else {
+        return Ok(None);
+    };
+    let mut value = read_config_value(&path)?;
+    if !trusted {
+        for key in remove_untrusted_keys(&mut value) {
+            warn!(
+                "{}: ignoring {key}, which is only allowed in the global config (or pass --rga-trust-project-config)",
+                path.display()
+            );
+        }
+    }
+    Ok(Some((path.to_string_lossy().into_owned(), value)))
+}
+
+/// Validate a config value from a single source against the config schema.
+///
+/// Returns a list of problems, each prefixed with the path of the offending key (e.g. `cache.max_blob_len`).
+pub fn validate_config_value(value: &Value) -> Vec<String> {
+    let mut problems = unknown_config_keys(value);
+    match deserialize_config_value(value) {
+        Ok(config) => problems.extend(config.invalid_values()),
+        Err(e) => problems.push(format!("{e}")),
+    }
+    problems
+}
+
+fn deserialize_config_value(value: &Value) -> Result<RgaConfig> {
+    serde_path_to_error::deserialize::<_, RgaConfig>(value).map_err(|e| {
+        anyhow::format_err!(
+            "{}: {}",
+            display_config_path(&e.path().to_string()),
+            e.inner()
+        )
+    })
+}
+
+fn unknown_config_keys(value: &Value) -> Vec<String> {
+    let mut problems = vec![];
+    let root = schemars::schema_for!(RgaConfig);
+    let root_schema = schemars::schema::Schema::Object(root.schema);
+    find_unknown_keys(value, &root_schema, &root.definitions, "", &mut problems);
+    problems
+}
+
+fn display_config_path(path: &str) -> &str {
+    if path.is_empty() || path == "." {
+        "(root)"
+    } else {
+        path
+    }
+}
+
+fn find_unknown_keys(
+    value: &Value,
+    schema: &schemars::schema::Schema,
+    definitions: &schemars::Map<String, schemars::schema::Schema>,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    use schemars::schema::{Schema, SingleOrVec};
+    let Schema::Object(schema) = schema else {
+        return;
+    };
+    if let Some(reference) = &schema.reference {
+        if let Some(s) = reference
+            .strip_prefix("#/definitions/")
+            .and_then(|name| definitions.get(name))
+        {
+            find_unknown_keys(value, s, definitions, path, problems);
+        }
+        return;
+    }
+    if let Some(subschemas) = &schema.subschemas {
+        for s in subschemas.all_of.iter().flatten() {
+            find_unknown_keys(value, s, definitions, path, problems);
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            let Some(object) = &schema.object else {
+                return;
+            };
+            for (key, v) in map {
+                let key_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                let value_schema = object.properties.get(key).or(
+                    // the values of maps like `adapter_max_output_size`
+                    match object.additional_properties.as_deref() {
+                        Some(s @ Schema::Object(_)) => Some(s),
+                        _ => None,
+                    },
+                );
+                match value_schema {
+                    Some(s) => find_unknown_keys(v, s, definitions, &key_path, problems),
+                    // the default config file references its schema
+                    None if path.is_empty() && key == "$schema" => {}
+                    None => problems.push(format!("{key_path}: unknown config key")),
+                }
+            }
+        }
+        Value::Array(arr) => {
+            if let Some(SingleOrVec::Single(item_schema)) =
+                schema.array.as_ref().and_then(|a| a.items.as_ref())
+            {
+                for (i, v) in arr.iter().enumerate() {
+                    find_unknown_keys(
+                        v,
+                        item_schema,
+                        definitions,
+                        &format!("{path}[{i}]"),
+                        problems,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Validate the global config file and the project-local config file of the search paths, printing every problem with its location.
+///
+/// Returns false if any problems were found.
+pub fn check_config_files(
+    path_override: Option<&String>,
+    search_paths: &[PathBuf],
+) -> Result<bool> {
+    let global = global_config_filename(path_override)?;
+    let project = find_search_project_config_file(search_paths)?;
+    let mut ok = true;
+    for file in std::iter::once(&global).chain(&project) {
+        if !file.exists() {
+            println!("{}: not found", file.display());
+            continue;
+        }
+        let contents = std::fs::read_to_string(file)
+            .with_context(|| format!("Could not read config file {}", file.display()))?;
+        let problems = match parse_config_str(file, &contents) {
+            Ok(mut value) => {
+                if project.as_ref() == Some(file) {
+                    for key in remove_untrusted_keys(&mut value) {
+                        println!(
+                            "{}: {key}: only used with --rga-trust-project-config",
+                            file.display()
+                        );
+                    }
+                }
+                validate_config_value(&value)
+            }
+            Err(e) => vec![format!("{e}")],
+        };
+        if problems.is_empty() {
+            println!("{}: ok", file.display());
+        }
+        for problem in problems {
+            ok = false;
+            println!("{}: {}", file.display(), problem);
+        }
+    }
+    Ok(ok)
+}
+
 fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
     let proj = project_dirs()?;
     let config_dir = proj.config_dir();
-    let config_filename = path_override
-        .as_ref()
-        .map(PathBuf::from)
-        .unwrap_or_else(|| config_dir.join("config.jsonc"));
+    let config_filename = global_config_filename(path_override.as_ref())?;
     let config_filename_str = config_filename.to_string_lossy().into_owned();
     if config_filename.exists() {
-        let config_file_contents = {
-            let raw = std::fs::read_to_string(config_filename).with_context(|| {
-                format!("Could not read config file json {config_filename_str}")
-            })?;
-            let mut s = String::new();
-            json_comments::StripComments::new(raw.as_bytes())
-                .read_to_string(&mut s)
-                .context("strip comments")?;
-            s
-        };
-        {
-            // just for error messages, actual deserialization happens after merging with cmd args
-            serde_json::from_str::<RgaConfig>(&config_file_contents).with_context(|| {
-                format!("Error in config file {config_filename_str}: {config_file_contents}")
-            })?;
-        }
-        let config_json: serde_json::Value =
-            serde_json::from_str(&config_file_contents).context("Could not parse config json")?;
+        let config_json = read_config_value(&config_filename)?;
         Ok((config_filename_str, config_json))
     } else if let Some(p) = path_override.as_ref() {
         Err(anyhow::anyhow!("Config file not found: {}", p))?
@@ -326,7 +1701,17 @@ fn read_config_env() -> Result<Value> {
         serde_json::to_value(RgaConfig::default()).context("could not create default config")
     }
 }
-pub fn parse_args<I>(args: I, is_rga_preproc: bool) -> Result<RgaConfig>
+/// Whether this process was (indirectly) started by `rga`, which passes its config in the environment.
+///
+/// rga-preproc can also be used directly as `--pre` command of rg, then it reads the config files itself.
+pub fn spawned_by_rga() -> bool {
+    std::env::var_os(RGA_CONFIG).is_some()
+}
+
+/// Parse the rga arguments and merge them with the config files.
+///
+/// `search_paths` are the paths searched by rg, used to find the project-local config file.
+pub fn parse_args<I>(args: I, is_rga_preproc: bool, search_paths: &[PathBuf]) -> Result<RgaConfig>
 where
     I: IntoIterator,
     I::Item: Into<OsString> + Clone,
@@ -334,27 +1719,43 @@ where
     // TODO: don't read config file in rga-preproc for performance (called for every file)
 
     let arg_matches: RgaConfig = RgaConfig::from_iter(args);
+    if arg_matches.check_config {
+        // don't read the config files here, they might be broken
+        return Ok(arg_matches);
+    }
     let args_config = serde_json::to_value(&arg_matches)?;
 
     let merged_config = {
-        if is_rga_preproc {
+        if is_rga_preproc && spawned_by_rga() {
             // only read from env and args
             let mut merged_config = read_config_env()?;
             json_merge(&mut merged_config, &args_config);
             log::debug!("Config: {}", serde_json::to_string(&merged_config)?);
             merged_config
         } else {
-            // read from config file, env and args
+            // read from config file, project config file, env and args
             let (config_filename, config_file_config) =
                 read_config_file(arg_matches.config_file_path)?;
             let env_var_config = read_config_env()?;
+            // a project config can't trust itself
+            let trusted = [&config_file_config, &env_var_config, &args_config]
+                .iter()
+                .any(|c| c.get("trust_project_config") == Some(&Value::Bool(true)));
+            let project_config = read_project_config_file(search_paths, trusted)?;
             let mut merged_config = config_file_config.clone();
+            if let Some((_, project_config)) = &project_config {
+                json_merge(&mut merged_config, project_config);
+            }
             json_merge(&mut merged_config, &env_var_config);
             json_merge(&mut merged_config, &args_config);
             log::debug!(
-                "Configs:\n{}: {}\n{}: {}\nArgs: {}\nMerged: {}",
+                "Configs:\n{}: {}\n{}: {}\n{}: {}\nArgs: {}\nMerged: {}",
                 config_filename,
                 serde_json::to_string_pretty(&config_file_config)?,
+                project_config
+                    .as_ref()
+                    .map_or(PROJECT_CONFIG_FILENAME, |(name, _)| name.as_str()),
+                serde_json::to_string_pretty(&project_config.as_ref().map(|(_, c)| c))?,
                 RGA_CONFIG,
                 serde_json::to_string_pretty(&env_var_config)?,
                 serde_json::to_string_pretty(&args_config)?,
@@ -382,10 +1783,22 @@ where
         // readd values with [serde(skip)]
         res.fzf_path = arg_matches.fzf_path;
         res.list_adapters = arg_matches.list_adapters;
+        res.print_pre_glob = arg_matches.print_pre_glob;
         res.print_config_schema = arg_matches.print_config_schema;
+        res.completions = arg_matches.completions;
+        res.locate = arg_matches.locate;
+        res.locate_prefilter = arg_matches.locate_prefilter;
+        res.editor_server = arg_matches.editor_server;
+        res.output = arg_matches.output;
+        res.semantic_query = arg_matches.semantic_query;
+        res.git_history = arg_matches.git_history;
         res.rg_help = arg_matches.rg_help;
         res.rg_version = arg_matches.rg_version;
     }
+    if let Some(problem) = res.invalid_values().first() {
+        // otherwise only reported for the first file that isn't UTF-8
+        anyhow::bail!("invalid config: {problem}");
+    }
     Ok(res)
 }
 
@@ -416,7 +1829,12 @@ pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
             }
         });
     debug!("rga (our) args: {:?}", our_args);
-    let matches = parse_args(our_args, is_rga_preproc).context("Could not parse config")?;
+    let matches = parse_args(
+        our_args,
+        is_rga_preproc,
+        &rg_search_paths(&passthrough_args),
+    )
+    .context("Could not parse config")?;
     if matches.rg_help {
         passthrough_args.insert(0, "--help".into());
     }
@@ -426,3 +1844,256 @@ pub fn split_args(is_rga_preproc: bool) -> Result<(RgaConfig, Vec<OsString>)> {
     debug!("rga (passthrough) args: {:?}", passthrough_args);
     Ok((matches, passthrough_args))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn validate() -> Result<()> {
+        let value = parse_config_str(
+            Path::new(".rga.toml"),
+            r#"
+                accurate = true
+                adapters = ["+mail"]
+                foo = 1
+
+                [cache]
+                max_blob_len = "big"
+            "#,
+        )?;
+        assert_eq!(
+            validate_config_value(&value),
+            vec![
+                "foo: unknown config key".to_string(),
+                "cache.max_blob_len: invalid type: string \"big\", expected usize".to_string()
+            ]
+        );
+
+        let value = parse_config_str(
+            Path::new("config.jsonc"),
+            r#"{
+                // comment
+                "$schema": "./config.v1.schema.json",
+                "custom_adapters": [{"name": "x", "description": "y", "version": 1, "extensions": [], "binary": "cat", "args": [], "foo": 1}]
+            }"#,
+        )?;
+        assert_eq!(
+            validate_config_value(&value),
+            vec!["custom_adapters[0].foo: unknown config key".to_string()]
+        );
+
+        let value = serde_json::json!({"fallback_encodings": ["windows-1251", "klingon"]});
+        assert_eq!(
+            validate_config_value(&value),
+            vec!["fallback_encodings[1]: unknown encoding \"klingon\"".to_string()]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ocr_command() -> Result<()> {
+        let ocr = OcrConfig {
+            languages: vec!["eng".to_string(), "deu".to_string()],
+            page_segmentation_mode: Some(OcrPageSegmentationMode(6)),
+            ..Default::default()
+        };
+        assert_eq!(
+            ocr.command()?,
+            (
+                "tesseract".to_string(),
+                strs(&[
+                    "stdin", "stdout", "-l", "eng+deu", "--dpi", "300", "--psm", "6"
+                ])
+            )
+        );
+        let ocr = OcrConfig {
+            engine_command: Some(strs(&["my-ocr", "--lang=$languages", "-"])),
+            ..Default::default()
+        };
+        assert_eq!(
+            ocr.command()?,
+            ("my-ocr".to_string(), strs(&["--lang=eng", "-"]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ocr_per_adapter() -> Result<()> {
+        let value = parse_config_str(
+            Path::new(".rga.toml"),
+            r#"
+                [ocr]
+                languages = ["eng"]
+                page_segmentation_mode = 6
+
+                [ocr.adapters.poppler]
+                languages = ["deu", "fra"]
+                dpi = 200
+            "#,
+        )?;
+        let ocr = deserialize_config_value(&value)?.ocr;
+        let pdf = ocr.for_adapter("poppler");
+        assert_eq!(pdf.languages_arg(), "deu+fra");
+        assert_eq!(pdf.dpi, OcrDpi(200));
+        assert_eq!(pdf.page_segmentation_mode, Some(OcrPageSegmentationMode(6)));
+        let images = ocr.for_adapter("ocr");
+        assert_eq!(images.languages_arg(), "eng");
+        assert_eq!(images.dpi, OcrDpi(300));
+        Ok(())
+    }
+
+    #[test]
+    fn ocr_mode_range() -> Result<()> {
+        let value = parse_config_str(
+            Path::new(".rga.toml"),
+            "[ocr.adapters.ocr]\nengine_mode = 4",
+        )?;
+        assert_eq!(
+            validate_config_value(&value),
+            ["ocr.adapters.ocr.engine_mode: OCR engine mode must be between 0 and 3, got 4"]
+        );
+        assert!(OcrPageSegmentationMode::from_str("13").is_ok());
+        assert!(OcrPageSegmentationMode::from_str("14").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn whisper_command() -> Result<()> {
+        let input = std::path::Path::new("/tmp/rga-audio.wav");
+        assert!(WhisperConfig::default().command(input).is_err());
+        let whisper = WhisperConfig {
+            model: Some("ggml-base.bin".to_string()),
+            language: Some("de".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            whisper.command(input)?,
+            (
+                "whisper-cli".to_string(),
+                strs(&[
+                    "-m",
+                    "ggml-base.bin",
+                    "-l",
+                    "de",
+                    "-f",
+                    "/tmp/rga-audio.wav"
+                ])
+            )
+        );
+        let whisper = WhisperConfig {
+            engine_command: Some(strs(&["whisper", "--language=$language", "$input"])),
+            ..Default::default()
+        };
+        assert_eq!(
+            whisper.command(input)?,
+            (
+                "whisper".to_string(),
+                strs(&["--language=auto", "/tmp/rga-audio.wav"])
+            )
+        );
+        Ok(())
+    }
+
+    fn strs(arr: &[&str]) -> Vec<String> {
+        arr.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn syntax_error_location() {
+        let e =
+            parse_config_str(Path::new("config.jsonc"), "{\n  \"accurate\": tru\n}").unwrap_err();
+        assert!(e.to_string().contains("line 2"), "{e}");
+    }
+
+    #[test]
+    fn project_config_upward() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let nested = dir.path().join("a/b");
+        std::fs::create_dir_all(&nested)?;
+        assert_eq!(find_project_config_file(&nested), None);
+        std::fs::write(dir.path().join(PROJECT_CONFIG_FILENAME), "accurate = true")?;
+        assert_eq!(
+            find_project_config_file(&nested),
+            Some(dir.path().join(PROJECT_CONFIG_FILENAME))
+        );
+        std::fs::write(nested.join("notes.txt"), "")?;
+        assert_eq!(
+            find_search_project_config_file(&[nested.join("notes.txt"), nested.clone()])?,
+            Some(dir.path().join(PROJECT_CONFIG_FILENAME))
+        );
+        // a search path in another project
+        let other = tempfile::tempdir()?;
+        assert_eq!(
+            find_search_project_config_file(&[nested, other.path().to_path_buf()])?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn search_paths() {
+        fn paths(args: &[&str]) -> Vec<PathBuf> {
+            let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+            rg_search_paths(&args)
+        }
+        assert_eq!(paths(&["foo"]), [PathBuf::from(".")]);
+        assert_eq!(
+            paths(&["-i", "foo", "src", "docs"]),
+            [PathBuf::from("src"), PathBuf::from("docs")]
+        );
+        assert_eq!(
+            paths(&["-g", "*.pdf", "--max-count", "3", "foo", "papers"]),
+            [PathBuf::from("papers")]
+        );
+        assert_eq!(
+            paths(&["-e", "foo", "--type=pdf", "papers"]),
+            [PathBuf::from("papers")]
+        );
+        assert_eq!(
+            paths(&["-iA3", "foo", "--", "-weird-dir"]),
+            [PathBuf::from("-weird-dir")]
+        );
+        assert!(rg_path_args(&[OsString::from("foo")]).is_empty());
+    }
+
+    #[test]
+    fn untrusted_project_config() -> Result<()> {
+        let mut value = parse_config_str(
+            Path::new(".rga.toml"),
+            r#"
+                accurate = true
+                adapters = ["+restic"]
+                trust_project_config = true
+                binary = "adapter"
+                binary_adapter = "restic"
+                custom_adapters = [{name = "x", description = "y", version = 1, extensions = ["x"], binary = "sh", args = []}]
+
+                [ocr]
+                languages = ["deu"]
+                engine_command = ["sh", "-c", "evil"]
+
+                [whisper]
+                model = "/tmp/evil.bin"
+            "#,
+        )?;
+        assert_eq!(
+            remove_untrusted_keys(&mut value),
+            [
+                "custom_adapters",
+                "adapters",
+                "binary",
+                "binary_adapter",
+                "ocr.engine_command",
+                "whisper.model",
+                "trust_project_config"
+            ]
+        );
+        assert_eq!(
+            value,
+            serde_json::json!({"accurate": true, "ocr": {"languages": ["deu"]}, "whisper": {}})
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+//! A long-running server for editor plugins, see `--rga-editor-server`.
+//!
+//! Protocol: newline-delimited JSON over stdio.
+//! Each request is one line: `{"id": 1, "query": "regex", "paths": ["."], "args": ["-i"]}`
+//! (`paths` and `args` are optional, `args` are passed to rg).
+//! For each request the server writes one line per match:
+//! `{"type": "match", "id": 1, "path": "a.zip", "line": 3, "member": "dir/b.pdf", "page": 2, "member_line": 1, "prefix_len": 19, "column": 6, "text": "hello"}`
+//! followed by `{"type": "done", "id": 1, "matches": 1}`, or `{"type": "error", "id": 1, "message": "..."}` if the request failed.
+//! Requests are handled one after the other.
+
+use crate::config::RgaConfig;
+use crate::location::{MatchLocator, SourceLocation, parse_rg_match};
+use anyhow::{Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ffi::OsString;
+use std::process::Stdio;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, Command};
+
+#[derive(Deserialize, Debug)]
+struct Request {
+    id: Value,
+    query: String,
+    #[serde(default)]
+    paths: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Response {
+    Match {
+        id: Value,
+        path: String,
+        /// line number in the adapted output
+        line: u64,
+        #[serde(flatten)]
+        location: SourceLocation,
+    },
+    Done {
+        id: Value,
+        matches: u64,
+    },
+    Error {
+        id: Value,
+        message: String,
+    },
+}
+
+struct Server {
+    config: RgaConfig,
+    /// arguments for rg that make it use rga-preproc
+    rg_base_args: Vec<OsString>,
+}
+
+impl Server {
+    async fn handle(&self, req: &Request, out: &mut (impl AsyncWriteExt + Unpin)) -> Result<u64> {
+        let mut cmd = Command::new("rg");
+        cmd.args(&self.rg_base_args)
+            .args(["--json", "--line-number"])
+            .args(&req.args)
+            .arg("--")
+            .arg(&req.query);
+        if req.paths.is_empty() {
+            cmd.arg(".");
+        } else {
+            cmd.args(&req.paths);
+        }
+        debug!("rg command to run: {:?}", cmd);
+        let mut child = cmd
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("could not spawn rg")?;
+        let matches = match self.write_matches(req, &mut child, out).await {
+            Ok(matches) => matches,
+            Err(e) => {
+                // don't leave rg running (or a zombie) until the next request
+                child.kill().await.ok();
+                return Err(e);
+            }
+        };
+        let status = child.wait().await?;
+        // 1 means no matches
+        if !matches!(status.code(), Some(0 | 1)) {
+            anyhow::bail!("rg exited with {status}");
+        }
+        Ok(matches)
+    }
+
+    /// Answer the requests, one per line, until the input ends
+    async fn serve(
+        &self,
+        input: impl AsyncBufRead + Unpin,
+        out: &mut (impl AsyncWriteExt + Unpin),
+    ) -> Result<()> {
+        let mut requests = input.lines();
+        while let Some(line) = requests.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let req: Request = match serde_json::from_str(&line) {
+                Ok(req) => req,
+                Err(e) => {
+                    let message = format!("invalid request: {e}");
+                    write_response(
+                        out,
+                        &Response::Error {
+                            id: Value::Null,
+                            message,
+                        },
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+            let res = match self.handle(&req, out).await {
+                Ok(matches) => Response::Done {
+                    id: req.id,
+                    matches,
+                },
+                Err(e) => Response::Error {
+                    id: req.id,
+                    message: format!("{e:#}"),
+                },
+            };
+            write_response(out, &res).await?;
+        }
+        Ok(())
+    }
+
+    /// Write a response for each match rg outputs, returns the number of matches
+    async fn write_matches(
+        &self,
+        req: &Request,
+        child: &mut Child,
+        out: &mut (impl AsyncWriteExt + Unpin),
+    ) -> Result<u64> {
+        let stdout = child.stdout.take().context("no stdout")?;
+        let mut lines = BufReader::new(stdout).lines();
+        // the cache stays warm between requests, so getting the location maps is cheap
+        let mut locator = MatchLocator::new(self.config.clone());
+        let mut matches = 0;
+        while let Some(line) = lines.next_line().await? {
+            let Some(m) = parse_rg_match(&line)? else {
+                continue;
+            };
+            let location = locator.locate(&m).await;
+            write_response(
+                out,
+                &Response::Match {
+                    id: req.id.clone(),
+                    path: m.path,
+                    line: m.line,
+                    location,
+                },
+            )
+            .await?;
+            matches += 1;
+        }
+        Ok(matches)
+    }
+}
+
+async fn write_response(out: &mut (impl AsyncWriteExt + Unpin), res: &Response) -> Result<()> {
+    let mut line = serde_json::to_vec(res)?;
+    line.push(b'\n');
+    out.write_all(&line).await?;
+    out.flush().await?;
+    Ok(())
+}
+
+/// Run the editor server on stdin / stdout until stdin is closed.
+///
+/// `rg_base_args` are the arguments that make rg use rga-preproc (`--pre` etc).
+pub async fn serve(config: RgaConfig, rg_base_args: Vec<OsString>) -> Result<()> {
+    let server = Server {
+        config,
+        rg_base_args,
+    };
+    let stdin = BufReader::new(tokio::io::stdin());
+    server.serve(stdin, &mut tokio::io::stdout()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// The tests that run rg are skipped if it is not installed
+    fn has_rg() -> bool {
+        let installed = std::process::Command::new("rg").output().is_ok();
+        if !installed {
+            eprintln!("rg not installed, skipping test");
+        }
+        installed
+    }
+
+    /// Serve the requests with plain rg, returns the responses
+    async fn serve_requests(requests: &str) -> Result<Vec<Value>> {
+        let server = Server {
+            config: RgaConfig::default(),
+            rg_base_args: Vec::new(),
+        };
+        let mut out = Vec::new();
+        server.serve(requests.as_bytes(), &mut out).await?;
+        String::from_utf8(out)?
+            .lines()
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn malformed_request() -> Result<()> {
+        let responses = serve_requests("{\"id\": 1, \"query\": \n\n").await?;
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["type"], "error");
+        assert_eq!(responses[0]["id"], Value::Null);
+        assert!(
+            responses[0]["message"]
+                .as_str()
+                .unwrap_or_default()
+                .starts_with("invalid request: ")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn matches_and_done() -> Result<()> {
+        if !has_rg() {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "hello\nworld\nhello again\n")?;
+        let req = serde_json::json!({"id": 7, "query": "hello", "paths": [path]});
+        let responses = serve_requests(&format!("{req}\n")).await?;
+        let lines: Vec<_> = responses
+            .iter()
+            .map(|r| (r["type"].clone(), r["line"].clone()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (Value::from("match"), Value::from(1)),
+                (Value::from("match"), Value::from(3)),
+                (Value::from("done"), Value::Null),
+            ]
+        );
+        assert_eq!(responses[2]["id"], 7);
+        assert_eq!(responses[2]["matches"], 2);
+        assert_eq!(responses[1]["text"], "hello again");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rg_error() -> Result<()> {
+        if !has_rg() {
+            return Ok(());
+        }
+        let dir = tempfile::tempdir()?;
+        let missing = dir.path().join("missing");
+        let req = serde_json::json!({"id": "a", "query": "hello", "paths": [missing]});
+        // the server keeps answering requests after rg failed
+        let responses = serve_requests(&format!("{req}\n{req}\n")).await?;
+        assert_eq!(responses.len(), 2);
+        for res in responses {
+            assert_eq!(res["type"], "error");
+            assert_eq!(res["id"], "a");
+            assert!(
+                res["message"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .starts_with("rg exited with")
+            );
+        }
+        Ok(())
+    }
+}
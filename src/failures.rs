@@ -0,0 +1,267 @@
+use crate::adapters::ReadBox;
+use anyhow::{Context, Result};
+use async_stream::stream;
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Environment variable that `rga` sets to the path of the failure log when spawning rg.
+/// Every `rga-preproc` process appends one JSON line per failed file to this file.
+pub static RGA_FAILURE_LOG: &str = "RGA_FAILURE_LOG";
+
+/// rg found matches, but some files could not be processed by their adapter.
+pub const EXIT_MATCHES_WITH_FAILURES: i32 = 3;
+/// rg did not find any matches and some files could not be processed by their adapter.
+/// Distinct from the generic error exit code of rg (2), which is passed on unchanged.
+pub const EXIT_NO_MATCHES_WITH_FAILURES: i32 = 4;
+
+/// how many example paths to show per adapter in the summary
+const MAX_EXAMPLES: usize = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FailureKind {
+    /// an external program could not be started (usually not installed)
+    Spawn,
+    Timeout,
+    /// the input could not be decoded (corrupt archive, truncated file, ...)
+    Corrupt,
+    Other,
+}
+
+impl std::fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FailureKind::Spawn => "could not spawn",
+            FailureKind::Timeout => "timeout",
+            FailureKind::Corrupt => "corrupt input",
+            FailureKind::Other => "error",
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdapterFailure {
+    pub adapter: String,
+    pub path: String,
+    pub kind: FailureKind,
+    /// one-line error message (outermost context and root cause)
+    pub message: String,
+}
+
+impl AdapterFailure {
+    pub fn new(adapter: &str, path: &Path, err: &anyhow::Error) -> AdapterFailure {
+        let root = err.root_cause().to_string();
+        let outer = err.to_string();
+        let message = if root == outer {
+            outer
+        } else {
+            format!("{outer}: {root}")
+        };
+        AdapterFailure {
+            adapter: adapter.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            kind: classify(err),
+            message: message.replace('\n', " "),
+        }
+    }
+}
+
+fn classify(err: &anyhow::Error) -> FailureKind {
+    use std::io::ErrorKind::*;
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            match e.kind() {
+                NotFound | PermissionDenied => return FailureKind::Spawn,
+                TimedOut => return FailureKind::Timeout,
+                InvalidData | UnexpectedEof => return FailureKind::Corrupt,
+                _ => {}
+            }
+        }
+    }
+    FailureKind::Other
+}
+
+/// returns true if this process was started by `rga` with failure collection active.
+pub fn collecting_failures() -> bool {
+    std::env::var_os(RGA_FAILURE_LOG).is_some()
+}
+
+/// Append the failure to the failure log given by the environment, if any.
+pub fn record_failure(failure: &AdapterFailure) -> Result<()> {
+    let Some(path) = std::env::var_os(RGA_FAILURE_LOG) else {
+        return Ok(());
+    };
+    let mut line = serde_json::to_vec(failure)?;
+    line.push(b'\n');
+    // a single write in append mode so concurrent rga-preproc processes don't interleave lines
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(&line))
+        .with_context(|| format!("writing to failure log {}", Path::new(&path).display()))
+}
+
+/// Record an error of the given adapter instead of failing the whole file.
+///
+/// Returns an empty reader, so that rg only sees a file without content.
+pub fn record_adapter_error(adapter: &str, path: &Path, err: anyhow::Error) -> ReadBox {
+    warn!("{}: adapter {} failed: {:?}", path.display(), adapter, err);
+    if let Err(e) = record_failure(&AdapterFailure::new(adapter, path, &err)) {
+        warn!("{:?}", e);
+    }
+    Box::pin(std::io::Cursor::new(Vec::new()))
+}
+
+/// Wrap the output of an adapter so that read errors are recorded in the failure log and end the stream
+/// (keeping the output produced so far) instead of being passed on to rg.
+pub fn record_stream_errors(adapter: String, path: PathBuf, inp: ReadBox) -> ReadBox {
+    let s = stream! {
+        for await chunk in ReaderStream::new(inp) {
+            match chunk {
+                Ok(chunk) => yield std::io::Result::Ok(chunk),
+                Err(e) => {
+                    record_adapter_error(&adapter, &path, anyhow::Error::from(e));
+                    break;
+                }
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+#[derive(Default)]
+pub struct FailureSummary {
+    /// adapter name -> failures
+    by_adapter: BTreeMap<String, Vec<AdapterFailure>>,
+}
+
+impl FailureSummary {
+    pub fn read(path: &Path) -> Result<FailureSummary> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("reading failure log {}", path.display()))?;
+        let mut summary = FailureSummary::default();
+        for line in content.lines().filter(|l| !l.is_empty()) {
+            let failure: AdapterFailure = serde_json::from_str(line)
+                .with_context(|| format!("invalid line in failure log: {line}"))?;
+            summary
+                .by_adapter
+                .entry(failure.adapter.clone())
+                .or_default()
+                .push(failure);
+        }
+        Ok(summary)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_adapter.is_empty()
+    }
+
+    pub fn count(&self) -> usize {
+        self.by_adapter.values().map(Vec::len).sum()
+    }
+
+    /// exit code for rga given the exit code of rg
+    pub fn exit_code(&self, rg_code: i32) -> i32 {
+        match (self.is_empty(), rg_code) {
+            (true, code) => code,
+            (false, 0) => EXIT_MATCHES_WITH_FAILURES,
+            (false, 1) => EXIT_NO_MATCHES_WITH_FAILURES,
+            (false, code) => code,
+        }
+    }
+
+    pub fn write(&self, mut w: impl Write) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            w,
+            "rga: {} file(s) could not be processed completely:",
+            self.count()
+        )?;
+        for (adapter, failures) in &self.by_adapter {
+            let mut kinds: BTreeMap<FailureKind, usize> = BTreeMap::new();
+            for f in failures {
+                *kinds.entry(f.kind).or_default() += 1;
+            }
+            let kinds = kinds
+                .iter()
+                .map(|(k, n)| format!("{n} {k}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(w, "  {adapter}: {} ({kinds})", failures.len())?;
+            for f in failures.iter().take(MAX_EXAMPLES) {
+                writeln!(w, "    {}: {}", f.path, f.message)?;
+            }
+            if failures.len() > MAX_EXAMPLES {
+                writeln!(w, "    ... and {} more", failures.len() - MAX_EXAMPLES)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn failure(adapter: &str, path: &str, kind: FailureKind) -> AdapterFailure {
+        AdapterFailure {
+            adapter: adapter.to_string(),
+            path: path.to_string(),
+            kind,
+            message: "oops".to_string(),
+        }
+    }
+
+    #[test]
+    fn summary() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("failures.jsonl");
+        let mut content = Vec::new();
+        for f in [
+            failure("poppler", "a.pdf", FailureKind::Spawn),
+            failure("zip", "b.zip", FailureKind::Corrupt),
+            failure("poppler", "c.pdf", FailureKind::Spawn),
+        ] {
+            serde_json::to_writer(&mut content, &f)?;
+            content.push(b'\n');
+        }
+        std::fs::write(&log, content)?;
+
+        let summary = FailureSummary::read(&log)?;
+        assert_eq!(summary.count(), 3);
+        assert_eq!(summary.exit_code(0), EXIT_MATCHES_WITH_FAILURES);
+        assert_eq!(summary.exit_code(1), 4);
+        assert_eq!(summary.exit_code(2), 2);
+        assert_eq!(FailureSummary::default().exit_code(1), 1);
+
+        let mut out = Vec::new();
+        summary.write(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "rga: 3 file(s) could not be processed completely:
+  poppler: 2 (2 could not spawn)
+    a.pdf: oops
+    c.pdf: oops
+  zip: 1 (1 corrupt input)
+    b.zip: oops
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn classify_io() {
+        let e = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+            .context("reading zip");
+        assert_eq!(classify(&e), FailureKind::Corrupt);
+        assert_eq!(classify(&anyhow::format_err!("foo")), FailureKind::Other);
+    }
+}
@@ -0,0 +1,372 @@
+//! Search all versions of the files in a git repository, see `--rga-git-history`.
+//!
+//! The objects are read directly from the packfiles and loose objects of the repository. Every blob reachable from the refs
+//! is adapted once, attributed to the oldest commit (by commit time) that contains it and its path in that commit.
+
+use crate::adapters::git::{
+    MODE_SUBMODULE, MODE_TREE, Object, ObjectId, ObjectKind, Pack, SHORT_ID_LENGTH, hex, parse_hex,
+    parse_loose_object, parse_tree,
+};
+use crate::config::RgaConfig;
+use crate::failures::{collecting_failures, record_adapter_error};
+use crate::preproc::rga_preproc_member;
+use anyhow::{Context, Result};
+use log::*;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The git directory of the repository containing `dir`, or `dir` itself for bare repositories
+pub fn find_git_dir(dir: &Path) -> Result<PathBuf> {
+    for dir in dir.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Ok(dot_git);
+        }
+        // worktrees and submodules have a file pointing to the git directory
+        if dot_git.is_file() {
+            let content = std::fs::read_to_string(&dot_git)?;
+            if let Some(git_dir) = content.strip_prefix("gitdir:") {
+                return Ok(dir.join(git_dir.trim()));
+            }
+        }
+        if dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir() {
+            return Ok(dir.to_path_buf());
+        }
+    }
+    anyhow::bail!("{} is not in a git repository", dir.display())
+}
+
+struct Commit {
+    id: ObjectId,
+    tree: ObjectId,
+    time: i64,
+}
+
+/// A blob of the history, with the commit and path it was found at
+pub struct HistoryBlob {
+    pub id: ObjectId,
+    pub commit: ObjectId,
+    pub path: String,
+}
+
+pub struct Repository {
+    git_dir: PathBuf,
+    /// the directory with the objects and refs, which is shared by all worktrees
+    common_dir: PathBuf,
+    packs: Vec<Pack>,
+}
+
+impl Repository {
+    pub async fn open(git_dir: &Path) -> Result<Repository> {
+        let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(dir) => git_dir.join(dir.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        };
+        let mut packs = Vec::new();
+        if let Ok(entries) = std::fs::read_dir(common_dir.join("objects/pack")) {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|e| e == "pack") {
+                    let data = tokio::fs::read(&path).await?;
+                    let pack = Pack::read(data)
+                        .await
+                        .with_context(|| format!("reading {}", path.display()))?;
+                    packs.push(pack);
+                }
+            }
+        }
+        Ok(Repository {
+            git_dir: git_dir.to_path_buf(),
+            common_dir,
+            packs,
+        })
+    }
+
+    pub async fn object(&mut self, id: &ObjectId) -> Result<Option<Arc<Object>>> {
+        for pack in &mut self.packs {
+            if let Some(object) = pack.get(id).await? {
+                return Ok(Some(object));
+            }
+        }
+        let hex = hex(id);
+        let path = self
+            .common_dir
+            .join("objects")
+            .join(&hex[..2])
+            .join(&hex[2..]);
+        match tokio::fs::read(&path).await {
+            Ok(raw) => Ok(Some(Arc::new(parse_loose_object(&raw).await?))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    /// The objects the refs (branches, tags, remotes, stash) and a detached HEAD point to
+    fn ref_targets(&self) -> Result<Vec<ObjectId>> {
+        let mut targets = Vec::new();
+        if let Ok(packed) = std::fs::read_to_string(self.common_dir.join("packed-refs")) {
+            // `^` lines are the peeled targets of tags, which are found from the tags
+            targets.extend(
+                packed
+                    .lines()
+                    .filter(|l| !l.starts_with('#') && !l.starts_with('^'))
+                    .filter_map(|l| parse_hex(l.split(' ').next()?.as_bytes())),
+            );
+        }
+        let mut dirs = vec![self.common_dir.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    // symbolic refs point to other refs, which are found on their own
+                    targets.extend(parse_hex(std::fs::read_to_string(&path)?.trim().as_bytes()));
+                }
+            }
+        }
+        let head = std::fs::read_to_string(self.git_dir.join("HEAD"))?;
+        targets.extend(parse_hex(head.trim().as_bytes()));
+        Ok(targets)
+    }
+
+    /// All commits reachable from the given objects, oldest first
+    async fn commits(&mut self, targets: Vec<ObjectId>) -> Result<Vec<Commit>> {
+        let mut commits = Vec::new();
+        let mut seen = HashSet::new();
+        let mut stack = targets;
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let Some(object) = self.object(&id).await? else {
+                // e.g. in shallow clones
+                debug!("object {} is missing", hex(&id));
+                continue;
+            };
+            let headers = object
+                .data
+                .split(|b| *b == b'\n')
+                .take_while(|l| !l.is_empty());
+            let mut tree = None;
+            let mut time = 0;
+            for line in headers {
+                let (key, value) = line.split_at(line.iter().position(|b| *b == b' ').unwrap_or(0));
+                let value = value.get(1..).unwrap_or_default();
+                match (object.kind, key) {
+                    (ObjectKind::Tag, b"object") | (ObjectKind::Commit, b"parent") => {
+                        stack.extend(parse_hex(value));
+                    }
+                    (ObjectKind::Commit, b"tree") => tree = parse_hex(value),
+                    (ObjectKind::Commit, b"committer") => {
+                        // `name <email> time timezone`
+                        time = String::from_utf8_lossy(value)
+                            .rsplit(' ')
+                            .nth(1)
+                            .and_then(|t| t.parse().ok())
+                            .unwrap_or_default();
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(tree) = tree {
+                commits.push(Commit { id, tree, time });
+            }
+        }
+        commits.sort_by_key(|c| c.time);
+        Ok(commits)
+    }
+
+    /// Every blob reachable from the refs, with the oldest commit containing it
+    pub async fn blobs(&mut self) -> Result<Vec<HistoryBlob>> {
+        let targets = self.ref_targets()?;
+        let commits = self.commits(targets).await?;
+        let mut blobs = Vec::new();
+        let mut seen_trees = HashSet::new();
+        let mut seen_blobs = HashSet::new();
+        for commit in &commits {
+            let mut stack = vec![(commit.tree, String::new())];
+            while let Some((tree, dir)) = stack.pop() {
+                if !seen_trees.insert(tree) {
+                    continue;
+                }
+                let Some(object) = self.object(&tree).await? else {
+                    debug!("tree {} is missing", hex(&tree));
+                    continue;
+                };
+                for entry in parse_tree(&object.data)? {
+                    let path = if dir.is_empty() {
+                        entry.name
+                    } else {
+                        format!("{dir}/{}", entry.name)
+                    };
+                    match entry.mode {
+                        MODE_TREE => stack.push((entry.id, path)),
+                        MODE_SUBMODULE => {}
+                        _ => {
+                            if seen_blobs.insert(entry.id) {
+                                blobs.push(HistoryBlob {
+                                    id: entry.id,
+                                    commit: commit.id,
+                                    path,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        debug!(
+            "{} blobs in {} commits of {}",
+            blobs.len(),
+            commits.len(),
+            self.git_dir.display()
+        );
+        Ok(blobs)
+    }
+}
+
+/// the adapter name of the failures of blobs in the failure summary, the adapter of a blob is chosen within `rga_preproc_member`
+const HISTORY_ADAPTER: &str = "git-history";
+
+/// Writes the adapted output of every blob of the history of the repository containing `dir`,
+/// each line prefixed with the abbreviated commit id and the path (`a1b2c3d:docs/report.pdf: `)
+pub async fn write_history(
+    config: &RgaConfig,
+    dir: &Path,
+    oup: &mut (impl AsyncWrite + Unpin),
+) -> Result<()> {
+    let git_dir = find_git_dir(dir)?;
+    let mut repo = Repository::open(&git_dir).await?;
+    let mut buf = vec![0; 1 << 16];
+    for blob in repo.blobs().await? {
+        let Some(object) = repo.object(&blob.id).await? else {
+            debug!("blob {} is missing", hex(&blob.id));
+            continue;
+        };
+        let commit = &hex(&blob.commit)[..SHORT_ID_LENGTH];
+        let inp = Box::pin(Cursor::new(object.data.clone()));
+        let line_prefix = format!("{commit}:{}: ", blob.path);
+        let mut out =
+            match rga_preproc_member(config.clone(), PathBuf::from(&blob.path), line_prefix, inp)
+                .await
+            {
+                Ok(out) => out,
+                // summarized by rga after rg exits
+                Err(e) if collecting_failures() => {
+                    let path = PathBuf::from(format!("{commit}:{}", blob.path));
+                    record_adapter_error(HISTORY_ADAPTER, &path, e)
+                }
+                Err(e) => {
+                    eprintln!("rga: {commit}:{}: {e:#}", blob.path);
+                    continue;
+                }
+            };
+        // the output of a blob might not end with a newline
+        let mut last = b'\n';
+        loop {
+            let n = out.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            oup.write_all(&buf[..n]).await?;
+            last = buf[n - 1];
+        }
+        if last != b'\n' {
+            oup.write_all(b"\n").await?;
+        }
+    }
+    oup.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::git::object_id;
+    use async_compression::tokio::bufread::ZlibEncoder;
+    use pretty_assertions::assert_eq;
+
+    /// Writes a loose object, returning its id
+    async fn write_object(git_dir: &Path, kind: ObjectKind, data: &[u8]) -> Result<ObjectId> {
+        let id = object_id(kind, data);
+        let header = format!(
+            "{} {}\0",
+            ["commit", "tree", "blob"][kind as usize],
+            data.len()
+        );
+        let mut compressed = Vec::new();
+        ZlibEncoder::new([header.as_bytes(), data].concat().as_slice())
+            .read_to_end(&mut compressed)
+            .await?;
+        let hex = hex(&id);
+        let dir = git_dir.join("objects").join(&hex[..2]);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(&hex[2..]), compressed)?;
+        Ok(id)
+    }
+
+    async fn write_commit(
+        git_dir: &Path,
+        files: &[(&str, &str)],
+        parent: Option<ObjectId>,
+        time: i64,
+    ) -> Result<ObjectId> {
+        let mut tree = Vec::new();
+        for (name, content) in files {
+            let blob = write_object(git_dir, ObjectKind::Blob, content.as_bytes()).await?;
+            tree.extend(format!("100644 {name}\0").as_bytes());
+            tree.extend(blob);
+        }
+        let tree = write_object(git_dir, ObjectKind::Tree, &tree).await?;
+        let mut commit = format!("tree {}\n", hex(&tree));
+        if let Some(parent) = parent {
+            commit.push_str(&format!("parent {}\n", hex(&parent)));
+        }
+        commit.push_str(&format!(
+            "committer A <a@example.com> {time} +0000\n\nversion\n"
+        ));
+        write_object(git_dir, ObjectKind::Commit, commit.as_bytes()).await
+    }
+
+    #[tokio::test]
+    async fn history() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let git_dir = dir.path().join(".git");
+        std::fs::create_dir_all(git_dir.join("refs/heads"))?;
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n")?;
+        let first = write_commit(&git_dir, &[("report.txt", "status: draft\n")], None, 1).await?;
+        let second = write_commit(
+            &git_dir,
+            &[("notes.txt", "todo\n"), ("report.txt", "status: final\n")],
+            Some(first),
+            2,
+        )
+        .await?;
+        std::fs::write(
+            git_dir.join("refs/heads/main"),
+            format!("{}\n", hex(&second)),
+        )?;
+
+        let subdir = dir.path().join("docs");
+        std::fs::create_dir(&subdir)?;
+        assert_eq!(find_git_dir(&subdir)?, git_dir);
+
+        let mut out = Vec::new();
+        write_history(&RgaConfig::default(), &subdir, &mut out).await?;
+        let (first, second) = (&hex(&first)[..7], &hex(&second)[..7]);
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!(
+                "{first}:report.txt: status: draft
+{first}:report.txt: \n{second}:notes.txt: todo
+{second}:notes.txt: \n{second}:report.txt: status: final
+{second}:report.txt: \n"
+            )
+        );
+        Ok(())
+    }
+}
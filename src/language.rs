@@ -0,0 +1,228 @@
+//! Language detection of adapted documents, see `--rga-lang`.
+
+use crate::adapters::ReadBox;
+use crate::location::{LocationMap, strip_line_prefix};
+use anyhow::Result;
+use async_stream::stream;
+use bytes::Bytes;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// how much of the start of the adapted output of a file is used to detect the language
+pub const SAMPLE_SIZE: usize = 64 * 1024;
+
+/// ISO 639-1 codes of the languages whatlang detects (which uses ISO 639-3 codes)
+static ISO_639_1: &[(&str, &str)] = &[
+    ("af", "afr"),
+    ("ak", "aka"),
+    ("am", "amh"),
+    ("ar", "ara"),
+    ("az", "aze"),
+    ("be", "bel"),
+    ("bg", "bul"),
+    ("bn", "ben"),
+    ("ca", "cat"),
+    ("cs", "ces"),
+    ("da", "dan"),
+    ("de", "deu"),
+    ("el", "ell"),
+    ("en", "eng"),
+    ("eo", "epo"),
+    ("es", "spa"),
+    ("et", "est"),
+    ("fa", "pes"),
+    ("fi", "fin"),
+    ("fr", "fra"),
+    ("gu", "guj"),
+    ("he", "heb"),
+    ("hi", "hin"),
+    ("hr", "hrv"),
+    ("hu", "hun"),
+    ("hy", "hye"),
+    ("id", "ind"),
+    ("it", "ita"),
+    ("ja", "jpn"),
+    ("jv", "jav"),
+    ("ka", "kat"),
+    ("km", "khm"),
+    ("kn", "kan"),
+    ("ko", "kor"),
+    ("la", "lat"),
+    ("lt", "lit"),
+    ("lv", "lav"),
+    ("mk", "mkd"),
+    ("ml", "mal"),
+    ("mr", "mar"),
+    ("my", "mya"),
+    ("nb", "nob"),
+    ("ne", "nep"),
+    ("nl", "nld"),
+    ("no", "nob"),
+    ("or", "ori"),
+    ("pa", "pan"),
+    ("pl", "pol"),
+    ("pt", "por"),
+    ("ro", "ron"),
+    ("ru", "rus"),
+    ("si", "sin"),
+    ("sk", "slk"),
+    ("sl", "slv"),
+    ("sn", "sna"),
+    ("sr", "srp"),
+    ("sv", "swe"),
+    ("ta", "tam"),
+    ("te", "tel"),
+    ("th", "tha"),
+    ("tk", "tuk"),
+    ("tl", "tgl"),
+    ("tr", "tur"),
+    ("uk", "ukr"),
+    ("ur", "urd"),
+    ("uz", "uzb"),
+    ("vi", "vie"),
+    ("yi", "yid"),
+    ("zh", "cmn"),
+    ("zu", "zul"),
+];
+
+/// The ISO 639-3 code of the language of the text, if it can be detected reliably
+pub fn detect_language(sample: &str) -> Option<String> {
+    let info = whatlang::detect(sample)?;
+    info.is_reliable().then(|| info.lang().code().to_string())
+}
+
+/// Whether a document in the detected language should be searched with `--rga-lang=wanted`.
+///
+/// Documents without a (reliably) detected language are always searched.
+pub fn language_wanted(wanted: &[String], detected: Option<&str>) -> bool {
+    let Some(detected) = detected else {
+        return true;
+    };
+    wanted.is_empty()
+        || wanted.iter().any(|w| {
+            let w = w.to_ascii_lowercase();
+            w == detected
+                || ISO_639_1
+                    .iter()
+                    .any(|(two, three)| *two == w && *three == detected)
+        })
+}
+
+/// The language of the start of the output of a file, without its line prefix (the path of a file within an archive)
+pub fn detect_member_language(sample: &[u8], line_prefix: &str) -> Option<String> {
+    let text = String::from_utf8_lossy(sample);
+    let text: Vec<&str> = text
+        .lines()
+        .map(|line| strip_line_prefix(line, line_prefix))
+        .collect();
+    detect_language(&text.join("\n"))
+}
+
+/// Detects the language from the start of the output of a file and returns empty output if it is not wanted.
+/// The line prefix (the path of a file within an archive) is removed before the detection.
+///
+/// The rest of unwanted output is still read, since archives are read in order.
+pub async fn filter_language(
+    mut inp: ReadBox,
+    wanted: &[String],
+    line_prefix: &str,
+) -> Result<ReadBox> {
+    let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+    (&mut inp)
+        .take(SAMPLE_SIZE as u64)
+        .read_to_end(&mut sample)
+        .await?;
+    if language_wanted(
+        wanted,
+        detect_member_language(&sample, line_prefix).as_deref(),
+    ) {
+        return Ok(Box::pin(Cursor::new(sample).chain(inp)));
+    }
+    tokio::io::copy(&mut inp, &mut tokio::io::sink()).await?;
+    Ok(Box::pin(tokio::io::empty()))
+}
+
+/// Leaves out the lines of the files whose language (recorded in the location map) is not wanted.
+///
+/// The location map can still be filled while reading, a line only has to be recorded before it is read.
+pub fn filter_members(inp: ReadBox, map: Arc<Mutex<LocationMap>>, wanted: Vec<String>) -> ReadBox {
+    let s = stream! {
+        let mut line: u64 = 1;
+        let mut keep = true;
+        let mut line_start = true;
+        for await bytes in ReaderStream::new(inp) {
+            let bytes = match bytes {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    yield Err(e);
+                    continue;
+                }
+            };
+            let mut kept = Vec::with_capacity(bytes.len());
+            for part in bytes.split_inclusive(|b| *b == b'\n') {
+                if line_start {
+                    let map = map.lock().expect("location map poisoned");
+                    let span = map.members.iter().rev().find(|m| m.first_line <= line);
+                    keep = language_wanted(&wanted, span.and_then(|m| m.language.as_deref()));
+                }
+                if keep {
+                    kept.extend_from_slice(part);
+                }
+                line_start = part.ends_with(b"\n");
+                if line_start {
+                    line += 1;
+                }
+            }
+            yield Ok(Bytes::from(kept));
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const GERMAN: &str = "Der schnelle braune Fuchs springt über den faulen Hund. Die Verhandlung wurde auf nächste Woche verschoben, weil der Zeuge nicht erschienen ist.";
+
+    #[tokio::test]
+    async fn filter() -> Result<()> {
+        assert_eq!(detect_language(GERMAN).as_deref(), Some("deu"));
+        let wanted = vec!["en".to_string(), "fr".to_string()];
+        let mut oup = String::new();
+        filter_language(Box::pin(Cursor::new(GERMAN)), &wanted, "")
+            .await?
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "");
+        let wanted = vec!["DE".to_string()];
+        filter_language(Box::pin(Cursor::new(GERMAN)), &wanted, "")
+            .await?
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, GERMAN);
+        // too short to detect
+        assert!(language_wanted(&wanted, detect_language("1234").as_deref()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn without_line_prefix() -> Result<()> {
+        // English file names, the German text is still not wanted
+        let prefix = "the quick brown fox jumps over the lazy dog and the house: ";
+        let text: String = GERMAN
+            .split(". ")
+            .map(|sentence| format!("{prefix}{sentence}\n"))
+            .collect();
+        let mut oup = String::new();
+        filter_language(Box::pin(Cursor::new(text)), &["en".to_string()], prefix)
+            .await?
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "");
+        Ok(())
+    }
+}
@@ -3,14 +3,25 @@
 pub mod adapted_iter;
 pub mod adapters;
 mod caching_writer;
+pub mod checkpoint;
 pub mod config;
+pub mod editor_server;
 pub mod expand;
+pub mod failures;
+pub mod git_history;
+pub mod language;
+pub mod location;
 pub mod matching;
+pub mod ntfs_streams;
+pub mod passwords;
 pub mod preproc;
 pub mod preproc_cache;
 pub mod recurse;
+pub mod report;
+pub mod semantic;
 #[cfg(test)]
 pub mod test_utils;
+pub mod xattrs;
 use anyhow::Context;
 use anyhow::Result;
 use async_stream::stream;
@@ -63,6 +74,28 @@ pub fn print_bytes(bytes: impl Into<f64>) -> String {
     pretty_bytes::converter::convert(bytes.into())
 }
 
+/// Format a unix timestamp as `YYYY-MM-DD HH:MM:SS UTC`
+pub fn print_unix_time(secs: i64) -> String {
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = secs.div_euclid(86400);
+    let time = secs.rem_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
 pub fn to_io_err(e: anyhow::Error) -> std::io::Error {
     std::io::Error::new(std::io::ErrorKind::Other, e)
 }
@@ -0,0 +1,439 @@
+use crate::config::RgaConfig;
+use crate::language::{SAMPLE_SIZE, detect_member_language, language_wanted};
+use crate::passwords::{PasswordState, UsedPassword};
+use crate::preproc::rga_location_map;
+use crate::{
+    adapted_iter::AdaptedFilesIterBox,
+    adapters::{AdaptInfo, ReadBox},
+    to_io_err,
+};
+use anyhow::{Context, Result};
+use async_stream::stream;
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// One file (e.g. a member of an archive) within the adapted output
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MemberSpan {
+    /// first line (1-based) of the adapted output that belongs to this file
+    pub first_line: u64,
+    /// the line prefix that the adapters added to every line of this file
+    pub line_prefix: String,
+    /// the detected language (ISO 639-3) of this file, only detected with `--rga-lang`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// A `== Page N ==` line of the heading page style (see `--rga-page-style`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PageHeading {
+    /// line (1-based) of the adapted output
+    pub line: u64,
+    pub page: u32,
+}
+
+/// Maps lines of the adapted output back to their location in the source document.
+///
+/// Stored in the cache next to the adapted output.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LocationMap {
+    pub members: Vec<MemberSpan>,
+    /// the page headings, since the lines after them have no page prefix
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub page_headings: Vec<PageHeading>,
+    /// which of the passwords decrypted the file or files within it, by their line prefix
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub passwords: BTreeMap<String, UsedPassword>,
+}
+
+/// Location of a line of adapted output within the original document
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SourceLocation {
+    /// path within the archive(s), e.g. `dir/inner.zip: file.pdf`. None for files that are not archives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    /// page number, for adapters that output page prefixes (e.g. poppler)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+    /// line number within the adapted output of the member
+    pub member_line: u64,
+    /// number of bytes at the start of the line that were inserted by rga (file name and page prefixes)
+    pub prefix_len: usize,
+    /// the given column (1-based, as output by `rg --column`) translated to a column in the text without prefixes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<u64>,
+    /// the line of adapted output without prefixes
+    pub text: String,
+}
+
+impl LocationMap {
+    /// Find the source location of the given line (1-based) of adapted output with the content `text`.
+    ///
+    /// If a column (1-based byte offset within the adapted line) is given, it is translated to the column in the text without prefixes.
+    pub fn locate(&self, line: u64, column: Option<u64>, text: &str) -> SourceLocation {
+        let span = self.members.iter().rev().find(|m| m.first_line <= line);
+        let (member, member_line, rest) = match span {
+            Some(span) => (
+                Some(span.line_prefix.trim_end_matches(": ").to_string()).filter(|m| !m.is_empty()),
+                line - span.first_line + 1,
+                strip_line_prefix(text, &span.line_prefix),
+            ),
+            None => (None, line, text),
+        };
+        let first_line = span.map_or(1, |s| s.first_line);
+        let (page, rest) = match parse_page_prefix(rest) {
+            Some((page, rest)) => (Some(page), rest),
+            // the last heading before the line, within the same member
+            None => (
+                self.page_headings
+                    .iter()
+                    .rev()
+                    .find(|h| h.line <= line)
+                    .filter(|h| h.line >= first_line)
+                    .map(|h| h.page),
+                rest,
+            ),
+        };
+        let prefix_len = text.len() - rest.len();
+        SourceLocation {
+            member,
+            page,
+            member_line,
+            prefix_len,
+            column: column.map(|c| c.saturating_sub(prefix_len as u64).max(1)),
+            text: rest.to_string(),
+        }
+    }
+}
+
+impl LocationMap {
+    /// The location map of the output without the files whose language is not wanted (see `language::filter_members`)
+    pub fn filtered(&self, wanted: &[String]) -> LocationMap {
+        if wanted.is_empty() {
+            return self.clone();
+        }
+        let mut filtered = LocationMap {
+            passwords: self.passwords.clone(),
+            ..Default::default()
+        };
+        // the number of lines of the unwanted files before the current one
+        let mut removed = 0;
+        for (i, member) in self.members.iter().enumerate() {
+            let end = self.members.get(i + 1).map_or(u64::MAX, |m| m.first_line);
+            if !language_wanted(wanted, member.language.as_deref()) {
+                removed = removed.saturating_add(end - member.first_line);
+                continue;
+            }
+            filtered.members.push(MemberSpan {
+                first_line: member.first_line - removed,
+                ..member.clone()
+            });
+            filtered.page_headings.extend(
+                self.page_headings
+                    .iter()
+                    .filter(|h| h.line >= member.first_line && h.line < end)
+                    .map(|h| PageHeading {
+                        line: h.line - removed,
+                        page: h.page,
+                    }),
+            );
+        }
+        filtered
+    }
+}
+
+/// Strip the line prefix, also if it includes a line number (see `--rga-member-line-numbers`)
+pub fn strip_line_prefix<'a>(text: &'a str, line_prefix: &str) -> &'a str {
+    if let Some(rest) = text.strip_prefix(line_prefix) {
+        return rest;
+    }
+    let base = line_prefix.strip_suffix(' ').unwrap_or(line_prefix);
+    text.strip_prefix(base)
+        .map(|rest| rest.trim_start_matches(|c: char| c.is_ascii_digit()))
+        .and_then(|rest| rest.strip_prefix(": "))
+        .unwrap_or(text)
+}
+
+/// Parse the "Page N: " prefix added by the postprocpagebreaks adapter, or the "Slide N: " prefix of the pptx adapter.
+/// The page number can be followed by the section from the outline of a PDF: "Page N [3.1 Results]: ".
+fn parse_page_prefix(text: &str) -> Option<(u32, &str)> {
+    let rest = text
+        .strip_prefix("Page ")
+        .or_else(|| text.strip_prefix("Slide "))?;
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    let page = rest[..digits].parse().ok()?;
+    let rest = &rest[digits..];
+    let rest = match rest.strip_prefix(" [") {
+        Some(section) => &section[section.find("]: ")? + 1..],
+        None => rest,
+    };
+    Some((page, rest.strip_prefix(": ")?))
+}
+
+/// Parse a page heading `== Page N ==` or `== Page N [3.1 Results] ==` added with the heading page style
+fn parse_page_heading(text: &str) -> Option<u32> {
+    let label = text.strip_prefix("== ")?.strip_suffix(" ==")?;
+    let rest = label.strip_prefix("Page ")?;
+    let digits = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let page = rest[..digits].parse().ok()?;
+    let rest = &rest[digits..];
+    (rest.is_empty() || rest.starts_with(" [")).then_some(page)
+}
+
+/// How much of the start of each line is kept to find page headings, longer lines are not headings
+const MAX_HEADING_LEN: usize = 1024;
+
+/// Like `concat_read_streams`, but records where each file starts in the output, and the page headings.
+///
+/// `first_line` is the line number of the first line of the output (greater than 1 when resuming from a checkpoint).
+/// With `detect_language`, the language of each file is detected from the start of its output and recorded as well.
+pub fn concat_read_streams_with_locations(
+    input: AdaptedFilesIterBox,
+    map: Arc<Mutex<LocationMap>>,
+    first_line: u64,
+    detect_language: bool,
+) -> ReadBox {
+    let s = stream! {
+        let mut line: u64 = first_line;
+        for await output in input {
+            let mut output = output.map_err(to_io_err)?;
+            let mut language = None;
+            if detect_language {
+                let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+                (&mut output.inp)
+                    .take(SAMPLE_SIZE as u64)
+                    .read_to_end(&mut sample)
+                    .await?;
+                language = detect_member_language(&sample, &output.line_prefix);
+                output.inp = Box::pin(Cursor::new(sample).chain(output.inp));
+            }
+            map.lock().expect("location map poisoned").members.push(MemberSpan {
+                first_line: line,
+                line_prefix: output.line_prefix.clone(),
+                language,
+            });
+            // the start of the current line
+            let mut current = Vec::new();
+            for await bytes in ReaderStream::new(output.inp) {
+                if let Ok(bytes) = &bytes {
+                    for part in bytes.split_inclusive(|b| *b == b'\n') {
+                        let keep = part.len().min(MAX_HEADING_LEN.saturating_sub(current.len()));
+                        current.extend_from_slice(&part[..keep]);
+                        if part.ends_with(b"\n") {
+                            let text = String::from_utf8_lossy(&current);
+                            let text = text.trim_end_matches(['\n', '\r']);
+                            if let Some(page) = parse_page_heading(strip_line_prefix(text, &output.line_prefix)) {
+                                let heading = PageHeading { line, page };
+                                map.lock().expect("location map poisoned").page_headings.push(heading);
+                            }
+                            current.clear();
+                            line += 1;
+                        }
+                    }
+                }
+                yield bytes;
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+/// A match from the JSON output of rg (`rg --json`)
+#[derive(Debug, PartialEq)]
+pub struct RgMatch {
+    pub path: String,
+    /// line number in the adapted output
+    pub line: u64,
+    /// 1-based byte offset of the first submatch
+    pub column: Option<u64>,
+    /// the matched line without the trailing newline
+    pub text: String,
+    /// the text of the first submatch
+    pub submatch: String,
+}
+
+/// Parse a line of `rg --json` output. Returns None for other messages than matches.
+pub fn parse_rg_match(line: &str) -> Result<Option<RgMatch>> {
+    let msg: Value = serde_json::from_str(line).context("invalid json from rg")?;
+    if msg["type"] != "match" {
+        return Ok(None);
+    }
+    let data = &msg["data"];
+    // non-utf8 paths and lines are output as base64 `bytes`, skip them
+    let (Some(path), Some(text), Some(line)) = (
+        data["path"]["text"].as_str(),
+        data["lines"]["text"].as_str(),
+        data["line_number"].as_u64(),
+    ) else {
+        return Ok(None);
+    };
+    Ok(Some(RgMatch {
+        path: path.to_string(),
+        line,
+        column: data["submatches"][0]["start"].as_u64().map(|s| s + 1),
+        text: text.trim_end_matches(['\n', '\r']).to_string(),
+        submatch: data["submatches"][0]["match"]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    }))
+}
+
+/// Finds the source locations of matches output by rg, getting the location map of each file only once.
+pub struct MatchLocator {
+    config: RgaConfig,
+    maps: HashMap<String, LocationMap>,
+}
+
+impl MatchLocator {
+    pub fn new(config: RgaConfig) -> MatchLocator {
+        MatchLocator {
+            config,
+            maps: HashMap::new(),
+        }
+    }
+
+    async fn location_map(&self, path: &Path) -> Result<LocationMap> {
+        let inp = tokio::fs::File::open(path).await?;
+        let ai = AdaptInfo {
+            inp: Box::pin(inp),
+            filepath_hint: path.to_path_buf(),
+            is_real_file: true,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: !self.config.no_prefix_filenames,
+            config: self.config.clone(),
+            passwords: PasswordState::default(),
+        };
+        rga_location_map(ai).await
+    }
+
+    /// Without a location map (e.g. if the cache is disabled), only the page is found.
+    pub async fn locate(&mut self, m: &RgMatch) -> SourceLocation {
+        if !self.maps.contains_key(&m.path) {
+            let map = self
+                .location_map(Path::new(&m.path))
+                .await
+                .unwrap_or_else(|e| {
+                    debug!("no location map for {}: {:?}", m.path, e);
+                    LocationMap::default()
+                });
+            self.maps.insert(m.path.clone(), map);
+        }
+        self.maps[&m.path].locate(m.line, m.column, &m.text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn locate() {
+        let map = LocationMap {
+            members: vec![
+                MemberSpan {
+                    first_line: 1,
+                    line_prefix: "a.txt: ".to_string(),
+                    language: None,
+                },
+                MemberSpan {
+                    first_line: 4,
+                    line_prefix: "dir/b.pdf: ".to_string(),
+                    language: None,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            map.locate(5, Some(25), "dir/b.pdf: Page 2: hello"),
+            SourceLocation {
+                member: Some("dir/b.pdf".to_string()),
+                page: Some(2),
+                member_line: 2,
+                prefix_len: 19,
+                column: Some(6),
+                text: "hello".to_string()
+            }
+        );
+        assert_eq!(
+            map.locate(2, None, "a.txt: foo"),
+            SourceLocation {
+                member: Some("a.txt".to_string()),
+                page: None,
+                member_line: 2,
+                prefix_len: 7,
+                column: None,
+                text: "foo".to_string()
+            }
+        );
+        // with --rga-member-line-numbers
+        assert_eq!(map.locate(3, None, "a.txt:3: foo").text, "foo");
+        assert_eq!(map.locate(6, None, "dir/b.pdf: Slide 7: bar").page, Some(7));
+        let located = map.locate(6, None, "dir/b.pdf: Page 3 [2.1 Setup: Tools]: baz");
+        assert_eq!((located.page, located.text.as_str()), (Some(3), "baz"));
+    }
+
+    #[tokio::test]
+    async fn page_headings() -> Result<()> {
+        let member = |line_prefix: &str, text: &'static str| AdaptInfo {
+            inp: Box::pin(text.as_bytes()),
+            line_prefix: line_prefix.to_string(),
+            ..simple_adapt_info(Path::new("a.zip"), Box::pin(&b""[..])).0
+        };
+        let input: AdaptedFilesIterBox = Box::pin(tokio_stream::iter([
+            Ok(member(
+                "a.pdf: ",
+                "a.pdf: == Page 1 ==\na.pdf: one\na.pdf: == Page 2 [Results] ==\na.pdf: two\n",
+            )),
+            Ok(member("b.txt: ", "b.txt: three\n")),
+        ]));
+        let map = Arc::new(Mutex::new(LocationMap::default()));
+        let mut out = String::new();
+        concat_read_streams_with_locations(input, map.clone(), 1, false)
+            .read_to_string(&mut out)
+            .await?;
+        let map = map.lock().unwrap().clone();
+        assert_eq!(
+            map.page_headings
+                .iter()
+                .map(|h| (h.line, h.page))
+                .collect::<Vec<_>>(),
+            [(1, 1), (3, 2)]
+        );
+        assert_eq!(map.locate(2, None, "a.pdf: one").page, Some(1));
+        assert_eq!(map.locate(4, None, "a.pdf: two").page, Some(2));
+        assert_eq!(map.locate(5, None, "b.txt: three").page, None);
+        Ok(())
+    }
+    #[test]
+    fn rg_json() -> Result<()> {
+        let line = r#"{"type":"match","data":{"path":{"text":"a.zip"},"lines":{"text":"dir/b.pdf: Page 2: hello\n"},"line_number":5,"absolute_offset":0,"submatches":[{"match":{"text":"hello"},"start":19,"end":24}]}}"#;
+        assert_eq!(
+            parse_rg_match(line)?,
+            Some(RgMatch {
+                path: "a.zip".to_string(),
+                line: 5,
+                column: Some(20),
+                text: "dir/b.pdf: Page 2: hello".to_string(),
+                submatch: "hello".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_rg_match(r#"{"type":"begin","data":{"path":{"text":"a.zip"}}}"#)?,
+            None
+        );
+        Ok(())
+    }
+}
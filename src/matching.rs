@@ -4,10 +4,12 @@
 use crate::adapters::*;
 
 use anyhow::*;
+use log::debug;
 
 use regex::{Regex, RegexSet};
 
 use std::iter::Iterator;
+use std::path::{Path, PathBuf};
 
 use std::sync::Arc;
 
@@ -20,8 +22,18 @@ pub enum FastFileMatcher {
      *
      */
     FileExtension(String),
+    /// a file on disk directly within a directory with one of the given names, which has
+    /// siblings with all the other names, e.g. `["cur", "new", "tmp"]` for the messages of a Maildir.
+    /// Only used if no extension or mime type matches. Not part of the pre-glob, so only found with `--rga-accurate`
+    DirectoryShape(Vec<String>),
+    /// a file on disk with the given extension in a directory that also contains a file with the given name,
+    /// e.g. `("log", "CURRENT")` for the write-ahead logs of LevelDB databases.
+    /// Only used if no extension or mime type matches. Not part of the pre-glob, so only found with `--rga-accurate`
+    ExtensionWithSibling(String, String),
+    /// a file on disk whose path matches the glob, e.g. `**/objects/[0-9a-f][0-9a-f]/*` for the loose objects of git repositories.
+    /// Only used if no extension or mime type matches. Not part of the pre-glob, so only found with `--rga-accurate`
+    PathGlob(String),
     // todo: maybe add others, e.g. regex on whole filename or even paths
-    // todo: maybe allow matching a directory (e.g. /var/lib/postgres)
 }
 
 #[derive(Clone, Debug)]
@@ -46,6 +58,8 @@ pub struct FileMeta {
     pub lossy_filename: String,
     // only given when slow matching is enabled
     pub mimetype: Option<&'static str>,
+    // only given for files on disk, needed for directory shape matchers
+    pub path: Option<PathBuf>,
 }
 
 pub fn extension_to_regex(extension: &str) -> Regex {
@@ -53,6 +67,65 @@ pub fn extension_to_regex(extension: &str) -> Regex {
         .expect("we know this regex compiles")
 }
 
+/// Whether the file at `path` is directly in one of the directories `names`, next to all the others
+pub fn matches_directory_shape(names: &[String], path: &Path) -> bool {
+    let Some(dir) = path.parent() else {
+        return false;
+    };
+    let in_shape_dir = dir
+        .file_name()
+        .is_some_and(|d| names.iter().any(|n| d == n.as_str()));
+    let Some(base) = dir.parent() else {
+        return false;
+    };
+    in_shape_dir && names.iter().all(|n| base.join(n).is_dir()) && path.is_file()
+}
+
+/// Whether the file at `path` has the extension and a sibling file with the given name
+pub fn matches_extension_with_sibling(extension: &str, sibling: &str, path: &Path) -> bool {
+    let has_extension = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension));
+    has_extension && path.with_file_name(sibling).is_file() && path.is_file()
+}
+
+/// Whether the path of the file at `path` matches the glob, with `*` not matching `/`
+pub fn matches_path_glob(glob: &str, path: &Path) -> bool {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    glob::Pattern::new(glob).is_ok_and(|p| p.matches_path_with(path, options)) && path.is_file()
+}
+
+/// Whether a matcher that needs to look at the file system matches the file at `path`
+fn matches_path(matcher: &FastFileMatcher, path: &Path) -> bool {
+    match matcher {
+        FastFileMatcher::DirectoryShape(names) => matches_directory_shape(names, path),
+        FastFileMatcher::ExtensionWithSibling(extension, sibling) => {
+            matches_extension_with_sibling(extension, sibling, path)
+        }
+        FastFileMatcher::PathGlob(glob) => matches_path_glob(glob, path),
+        FastFileMatcher::FileExtension(_) => false,
+    }
+}
+
+/// Adapters that are disabled by default and read the same files as an adapter that is enabled by default,
+/// instead of it if they are enabled (e.g. `ocr` recognizes the text of the images `exif` reads the metadata of).
+static KNOWN_OVERLAPS: &[(&str, &str)] = &[("ocr", "exif"), ("whisper", "ffmpeg")];
+
+/// Whether all adapters matching a file are known to overlap, so the first of them is chosen without a warning
+pub fn is_known_overlap(names: &[&str]) -> bool {
+    names.iter().enumerate().all(|(i, a)| {
+        names[i + 1..].iter().all(|b| {
+            a == b
+                || KNOWN_OVERLAPS
+                    .iter()
+                    .any(|pair| *pair == (*a, *b) || *pair == (*b, *a))
+        })
+    })
+}
+
 #[allow(clippy::type_complexity)]
 pub fn adapter_matcher(
     adapters: &[Arc<dyn FileAdapter>],
@@ -62,6 +135,7 @@ pub fn adapter_matcher(
     let adapter_names: Vec<String> = adapters.iter().map(|e| e.metadata().name.clone()).collect();
     let mut fname_regexes = vec![];
     let mut mime_regexes = vec![];
+    let mut path_matchers = vec![];
     for adapter in adapters.iter() {
         let metadata = adapter.metadata();
         use FileMatcher::*;
@@ -75,6 +149,11 @@ pub fn adapter_matcher(
                     adapter.clone(),
                     Fast(FastFileMatcher::FileExtension(re.clone())),
                 )),
+                Fast(
+                    m @ (FastFileMatcher::DirectoryShape(_)
+                    | FastFileMatcher::ExtensionWithSibling(_, _)
+                    | FastFileMatcher::PathGlob(_)),
+                ) => path_matchers.push((adapter.clone(), m.clone())),
             };
         }
     }
@@ -111,18 +190,27 @@ pub fn adapter_matcher(
                     .position(|r| r == &e.0.metadata().name)
                     .expect("impossib7")
             });
+            let names: Vec<&str> = v.iter().map(|m| m.0.metadata().name.as_str()).collect();
+            if is_known_overlap(&names) {
+                debug!("{}: chose {} of {:?}", meta.lossy_filename, names[0], names);
+                return Some(v[0].clone());
+            }
             eprintln!(
                 "Warning: found multiple adapters for {}:",
                 meta.lossy_filename
             );
-            for mmatch in v.iter() {
-                eprintln!(" - {}", mmatch.0.metadata().name);
+            for name in names {
+                eprintln!(" - {name}");
             }
             return Some(v[0].clone());
         }
         if mime_matches.is_empty() {
             if fname_matches.is_empty() {
-                None
+                let path = meta.path.as_deref()?;
+                path_matchers
+                    .iter()
+                    .find(|(_, matcher)| matches_path(matcher, path))
+                    .map(|(adapter, matcher)| (adapter.clone(), FileMatcher::Fast(matcher.clone())))
             } else {
                 let (_, adapter, matcher) = &fname_regexes[fname_matches[0]];
                 Some((adapter.clone(), matcher.clone()))
@@ -0,0 +1,78 @@
+//! NTFS alternate data streams, see `--rga-ntfs-streams`.
+
+use anyhow::Result;
+use std::path::Path;
+
+/// Names of the alternate data streams of a file (e.g. `Zone.Identifier`).
+///
+/// Always empty on other platforms than Windows and on file systems without streams.
+#[cfg(windows)]
+pub fn alternate_streams(path: &Path) -> Result<Vec<String>> {
+    use anyhow::Context;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Foundation::{
+        ERROR_HANDLE_EOF, ERROR_INVALID_PARAMETER, INVALID_HANDLE_VALUE,
+    };
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+    let data_ptr = &mut data as *mut WIN32_FIND_STREAM_DATA as *mut _;
+    let handle = unsafe { FindFirstStreamW(wide.as_ptr(), FindStreamInfoStandard, data_ptr, 0) };
+    if handle == INVALID_HANDLE_VALUE {
+        let err = std::io::Error::last_os_error();
+        // ERROR_HANDLE_EOF: no streams, ERROR_INVALID_PARAMETER: file system without streams (e.g. FAT)
+        return match err.raw_os_error() {
+            Some(c) if c == ERROR_HANDLE_EOF as i32 || c == ERROR_INVALID_PARAMETER as i32 => {
+                Ok(Vec::new())
+            }
+            _ => Err(err).with_context(|| format!("listing streams of {}", path.display())),
+        };
+    }
+    let mut streams = Vec::new();
+    loop {
+        let name = &data.cStreamName;
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        if let Some(name) = parse_stream_name(&String::from_utf16_lossy(&name[..len])) {
+            streams.push(name);
+        }
+        if unsafe { FindNextStreamW(handle, data_ptr) } == 0 {
+            break;
+        }
+    }
+    unsafe { FindClose(handle) };
+    Ok(streams)
+}
+
+#[cfg(not(windows))]
+pub fn alternate_streams(_path: &Path) -> Result<Vec<String>> {
+    Ok(Vec::new())
+}
+
+/// `:name:$DATA` -> `name`. None for the main stream (`::$DATA`) and other types than data streams
+#[cfg_attr(not(windows), allow(dead_code))]
+fn parse_stream_name(raw: &str) -> Option<String> {
+    let name = raw.strip_prefix(':')?.strip_suffix(":$DATA")?;
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_names() {
+        assert_eq!(
+            parse_stream_name(":Zone.Identifier:$DATA").as_deref(),
+            Some("Zone.Identifier")
+        );
+        assert_eq!(parse_stream_name("::$DATA"), None);
+    }
+}
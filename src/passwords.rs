@@ -0,0 +1,278 @@
+//! Passwords for encrypted documents, see `--rga-password-file`, `--rga-pdf-password` and `--rga-archive-password`.
+//!
+//! Used by the PDF (poppler), zip and 7z adapters and custom adapters with `password_args`. Encrypted Office documents are not supported.
+
+use crate::config::RgaConfig;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    /// the output of password commands, which are only run once
+    static ref COMMAND_PASSWORDS: Mutex<HashMap<Vec<String>, Vec<String>>> = Mutex::new(HashMap::new());
+}
+
+/// The passwords entered and the ones that worked while adapting one file, including the files within it.
+///
+/// Passed to the adapters in `AdaptInfo` and to the files within the file, each file that is searched starts with
+/// `PasswordState::default()`, so the in-process modes (like the editor server) don't carry them over to unrelated files.
+#[derive(Debug, Default, Clone)]
+pub struct PasswordState(Arc<Mutex<PasswordStateInner>>);
+
+#[derive(Debug, Default)]
+struct PasswordStateInner {
+    /// which passwords worked, by path within the file
+    used: BTreeMap<String, UsedPassword>,
+    /// the passwords entered on the terminal (`--rga-archive-password-prompt`)
+    prompted: Vec<String>,
+}
+
+impl PasswordState {
+    /// Record which password decrypted the file at `path` (the line prefix of the file within an archive).
+    pub fn record_used(&self, path: &str, password: UsedPassword) {
+        let mut state = self.0.lock().expect("poisoned");
+        state.used.insert(path.to_string(), password);
+    }
+
+    /// The passwords that worked since the last call, stored in the cache with the location map.
+    pub fn take_used(&self) -> BTreeMap<String, UsedPassword> {
+        std::mem::take(&mut self.0.lock().expect("poisoned").used)
+    }
+
+    /// Remember a password entered on the terminal, to try it for the other archives of the file.
+    pub fn add_prompted(&self, password: String) {
+        self.0.lock().expect("poisoned").prompted.push(password);
+    }
+}
+
+/// Which password decrypted a file. Stored in the cache instead of the password itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UsedPassword {
+    /// the password at this position (1-based) of the candidates, in the order they are tried
+    /// (e.g. `--rga-pdf-password`, then `pdf.password_command` and the lines of `--rga-password-file`)
+    Candidate(usize),
+    /// a password entered on the terminal (`--rga-archive-password-prompt`)
+    Prompt,
+}
+
+/// The password candidates from `--rga-password-file` (one per line, in the order to try them).
+pub fn password_candidates(config: &RgaConfig) -> Result<Vec<String>> {
+    let Some(path) = &config.password_file else {
+        return Ok(Vec::new());
+    };
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("reading password file {path}"))?;
+    Ok(parse_password_list(&content))
+}
+
+/// The password candidates for PDFs: `--rga-pdf-password`, the output of `pdf.password_command`
+/// (e.g. a keyring lookup) and then the password file.
+pub fn pdf_password_candidates(config: &RgaConfig) -> Result<Vec<String>> {
+    let mut passwords: Vec<String> = config.pdf.password.iter().cloned().collect();
+    if let Some(command) = &config.pdf.password_command {
+        passwords.extend(command_passwords(command)?);
+    }
+    passwords.extend(password_candidates(config)?);
+    Ok(passwords)
+}
+
+/// The password candidates for archives: `--rga-archive-password`, the password file and the passwords
+/// entered for other archives of the same file.
+pub fn archive_password_candidates(
+    config: &RgaConfig,
+    state: &PasswordState,
+) -> Result<Vec<String>> {
+    let mut passwords = config.archive_passwords.clone();
+    passwords.extend(password_candidates(config)?);
+    let state = state.0.lock().expect("poisoned");
+    passwords.extend(state.prompted.iter().cloned());
+    Ok(passwords)
+}
+
+/// Ask for a password on the terminal, without echoing it. Returns None if there is no terminal or nothing was entered.
+///
+/// rg runs several preprocessors at once, so the prompts are serialized with a lock file.
+pub fn prompt_password(what: &str) -> Result<Option<String>> {
+    use std::io::{BufRead, Write};
+    let Ok(tty) = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+    else {
+        return Ok(None);
+    };
+    let _lock = PromptLock::acquire()?;
+    let mut out = &tty;
+    write!(out, "rga: password for {what}: ")?;
+    out.flush()?;
+    let stty = |arg: &str| {
+        std::process::Command::new("stty")
+            .arg(arg)
+            .stdin(tty.try_clone()?)
+            .status()
+    };
+    let echo_off = stty("-echo").is_ok_and(|s| s.success());
+    let mut line = String::new();
+    let read = std::io::BufReader::new(&tty).read_line(&mut line);
+    if echo_off {
+        stty("echo").ok();
+        writeln!(out)?;
+    }
+    read.context("reading password from terminal")?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    if password.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(password))
+}
+
+/// A lock file in the temporary directory, removed when dropped
+struct PromptLock(std::path::PathBuf);
+
+impl PromptLock {
+    fn acquire() -> Result<PromptLock> {
+        let path = std::env::temp_dir().join("rga-password-prompt.lock");
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(PromptLock(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    // left behind by a preprocessor that was killed while asking
+                    let stale = std::fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .is_ok_and(|t| t.elapsed().unwrap_or_default().as_secs() > 600);
+                    if stale {
+                        std::fs::remove_file(&path).ok();
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                Err(e) => return Err(e).with_context(|| format!("creating {}", path.display())),
+            }
+        }
+    }
+}
+
+impl Drop for PromptLock {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+/// Every line of the output of the command is a password candidate
+fn command_passwords(command: &[String]) -> Result<Vec<String>> {
+    let mut cache = COMMAND_PASSWORDS.lock().expect("poisoned");
+    if let Some(passwords) = cache.get(command) {
+        return Ok(passwords.clone());
+    }
+    let (binary, args) = command
+        .split_first()
+        .context("pdf.password_command is empty")?;
+    let output = std::process::Command::new(binary)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .with_context(|| format!("running password command {binary}"))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "password command {binary} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let passwords = parse_password_list(&String::from_utf8_lossy(&output.stdout));
+    cache.insert(command.to_vec(), passwords.clone());
+    Ok(passwords)
+}
+
+fn parse_password_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn password_list() {
+        // windows line endings, spaces are part of the password
+        assert_eq!(
+            parse_password_list("hunter2\r\n\r\n correct horse\n"),
+            vec!["hunter2", " correct horse"]
+        );
+    }
+
+    #[test]
+    fn archive_passwords() -> Result<()> {
+        let mut password_file = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut password_file, b"from-file\n")?;
+        let config = RgaConfig {
+            archive_passwords: vec!["first".to_string(), "second".to_string()],
+            password_file: Some(password_file.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            archive_password_candidates(&config, &PasswordState::default())?,
+            vec!["first", "second", "from-file"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn state_per_file() {
+        let config = RgaConfig::default();
+        let state = PasswordState::default();
+        state.add_prompted("entered".to_string());
+        state.record_used("a.zip", UsedPassword::Prompt);
+        let other_file = PasswordState::default();
+        assert_eq!(
+            archive_password_candidates(&config, &other_file).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(other_file.take_used().is_empty());
+        // the files within the file share its state
+        assert_eq!(
+            archive_password_candidates(&config, &state.clone()).unwrap(),
+            vec!["entered"]
+        );
+        assert_eq!(state.take_used().len(), 1);
+    }
+
+    #[test]
+    fn used_password_not_stored() -> Result<()> {
+        let mut map = crate::location::LocationMap::default();
+        map.passwords
+            .insert("doc.pdf".to_string(), UsedPassword::Candidate(3));
+        map.passwords
+            .insert("a.zip".to_string(), UsedPassword::Prompt);
+        assert_eq!(
+            serde_json::to_string(&map)?,
+            r#"{"members":[],"passwords":{"a.zip":"prompt","doc.pdf":{"candidate":3}}}"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pdf_passwords() -> Result<()> {
+        let mut config = RgaConfig::default();
+        config.pdf.password = Some("from-flag".to_string());
+        config.pdf.password_command = Some(vec!["echo".to_string(), "from-keyring".to_string()]);
+        assert_eq!(
+            pdf_password_candidates(&config)?,
+            vec!["from-flag", "from-keyring"]
+        );
+        config.pdf.password_command = Some(vec!["false".to_string()]);
+        assert!(pdf_password_candidates(&config).is_err());
+        Ok(())
+    }
+}
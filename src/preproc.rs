@@ -1,10 +1,15 @@
-use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapted_iter::{AdaptedFilesIterBox, SORT_BUFFER_LIMIT, sorted_by_path};
 use crate::adapters::*;
 use crate::caching_writer::async_read_and_write_to_cache;
+use crate::checkpoint::{CheckpointWriter, Progress};
 use crate::config::RgaConfig;
+use crate::failures::{collecting_failures, record_adapter_error, record_stream_errors};
+use crate::language::{filter_language, filter_members};
+use crate::location::{LocationMap, SourceLocation, concat_read_streams_with_locations};
 use crate::matching::*;
+use crate::passwords::PasswordState;
 use crate::preproc_cache::CacheKey;
-use crate::recurse::concat_read_streams;
+use crate::recurse::{ExpansionLimits, concat_read_streams, truncate_output};
 use crate::{
     preproc_cache::{PreprocCache, open_cache_db},
     print_bytes,
@@ -17,9 +22,10 @@ use log::*;
 use postproc::PostprocPrefix;
 use std::future::Future;
 use std::io::Cursor;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use tokio::io::AsyncBufReadExt;
 use tokio::io::BufReader;
 use tokio::io::{AsyncBufRead, AsyncReadExt};
@@ -32,7 +38,11 @@ async fn choose_adapter(
     archive_recursion_depth: i32,
     inp: &mut (impl AsyncBufRead + Unpin),
 ) -> Result<Option<(Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters)>> {
-    let active_adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
+    let active_adapters = get_adapters_filtered(
+        config.custom_adapters.clone(),
+        &config.adapter_aliases,
+        &config.adapters,
+    )?;
     let adapters = adapter_matcher(&active_adapters, config.accurate)?;
     let filename = filepath_hint
         .file_name()
@@ -41,8 +51,8 @@ async fn choose_adapter(
 
     let mimetype = if config.accurate {
         let buf = inp.fill_buf().await?; // fill but do not consume!
-        if buf.starts_with(b"From \x0d") || buf.starts_with(b"From -") {
-            Some("application/mbox")
+        if let Some(mimetype) = magic_mimetype(buf) {
+            Some(mimetype)
         } else {
             let mimetype = tree_magic::from_u8(buf);
             debug!("mimetype: {:?}", mimetype);
@@ -54,10 +64,48 @@ async fn choose_adapter(
     let adapter = adapters(FileMeta {
         mimetype,
         lossy_filename: filename.to_string_lossy().to_string(),
+        // files within archives are not on disk
+        path: (archive_recursion_depth == 0).then(|| filepath_hint.to_path_buf()),
     });
     Ok(adapter.map(|e| (e.0, e.1, active_adapters)))
 }
 
+/// The mime type of formats that tree_magic doesn't detect, by their first bytes
+fn magic_mimetype(buf: &[u8]) -> Option<&'static str> {
+    if buf.starts_with(b"From \x0d") || buf.starts_with(b"From -") {
+        Some("application/mbox")
+    } else if buf.starts_with(&lucene::CODEC_MAGIC.to_be_bytes()) {
+        Some(lucene::MIMETYPE)
+    } else if buf.starts_with(minidump::SIGNATURE) {
+        Some(minidump::MIMETYPE)
+    } else {
+        None
+    }
+}
+
+/// Whether any active adapter matches the file name, without looking at the content.
+///
+/// Used by rga-preproc to quickly pass through other files when it is called by a stock rg
+/// without a (matching) `--pre-glob`.
+pub fn matches_any_adapter(config: &RgaConfig, path: &Path) -> Result<bool> {
+    let active_adapters = get_adapters_filtered(
+        config.custom_adapters.clone(),
+        &config.adapter_aliases,
+        &config.adapters,
+    )?;
+    let adapters = adapter_matcher(&active_adapters, false)?;
+    Ok(adapters(FileMeta {
+        mimetype: None,
+        path: Some(path.to_path_buf()),
+        lossy_filename: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    })
+    .is_some())
+}
+
 enum Ret {
     Recurse(AdaptInfo, Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters),
     Passthrough(AdaptInfo),
@@ -78,26 +126,23 @@ async fn buf_choose_adapter(ai: AdaptInfo) -> Result<Ret> {
     let (a, b, c) = match adapter {
         Some(x) => x,
         None => {
-            // allow passthrough if the file is in an archive or accurate matching is enabled
-            // otherwise it should have been filtered out by rg pre-glob since rg can handle those better than us
-            let allow_cat = !ai.is_real_file || ai.config.accurate;
-            if allow_cat {
-                if ai.postprocess {
-                    (
-                        Arc::new(PostprocPrefix {}) as Arc<dyn FileAdapter>,
-                        FileMatcher::Fast(FastFileMatcher::FileExtension("default".to_string())),
-                        Vec::new(),
-                    )
-                } else {
-                    return Ok(Ret::Passthrough(ai));
-                }
+            if ai.is_real_file && !ai.config.preprocesses_unmatched_files() {
+                // the pre-glob also selects files that the adapters matching by path don't accept
+                // (e.g. `*.log` files without the `CURRENT` file of LevelDB), rg searches them as they are
+                debug!(
+                    "no adapter matches {}, passing through",
+                    ai.filepath_hint.display()
+                );
+                return Ok(Ret::Passthrough(ai));
+            }
+            if ai.postprocess {
+                (
+                    Arc::new(PostprocPrefix {}) as Arc<dyn FileAdapter>,
+                    FileMatcher::Fast(FastFileMatcher::FileExtension("default".to_string())),
+                    Vec::new(),
+                )
             } else {
-                return Err(format_err!(
-                    "No adapter found for file {:?}, passthrough disabled.",
-                    ai.filepath_hint
-                        .file_name()
-                        .ok_or_else(|| format_err!("Empty filename"))?
-                ));
+                return Ok(Ret::Passthrough(ai));
             }
         }
     };
@@ -109,6 +154,8 @@ async fn buf_choose_adapter(ai: AdaptInfo) -> Result<Ret> {
  *
  * If a cache is passed, read/write to it.
  *
+ * If failures are being collected (see `failures::RGA_FAILURE_LOG`), errors of the chosen adapter
+ * are recorded and the file is treated as empty / truncated instead of returning an error.
  */
 pub async fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
     debug!("path (hint) to preprocess: {:?}", ai.filepath_hint);
@@ -122,17 +169,79 @@ pub async fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
         }
     };
     let path_hint_copy = ai.filepath_hint.clone();
-    adapt_caching(ai, adapter, detection_reason, active_adapters)
+    let adapter_name = adapter.metadata().name.clone();
+    let res = adapt_caching(ai, adapter, detection_reason, active_adapters)
         .await
-        .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()))
+        .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()));
+    if !collecting_failures() {
+        return res;
+    }
+    Ok(match res {
+        Ok(inp) => record_stream_errors(adapter_name, path_hint_copy, inp),
+        Err(e) => record_adapter_error(&adapter_name, &path_hint_copy, e),
+    })
+}
+
+/**
+ * Preprocess an NTFS alternate data stream of a file (see `--rga-ntfs-streams`).
+ *
+ * The stream is adapted like a file named `file.docx:stream` within an archive, so its output is not cached.
+ */
+pub async fn rga_preproc_ntfs_stream(
+    config: RgaConfig,
+    path: &Path,
+    stream: &str,
+) -> Result<ReadBox> {
+    let mut stream_path = path.as_os_str().to_owned();
+    stream_path.push(":");
+    stream_path.push(stream);
+    let stream_path = PathBuf::from(stream_path);
+    let inp = tokio::fs::File::open(&stream_path)
+        .await
+        .with_context(|| format!("opening stream {}", stream_path.display()))?;
+    let name = stream_path
+        .file_name()
+        .ok_or_else(|| format_err!("Empty filename"))?;
+    let line_prefix = format!("{}: ", name.to_string_lossy());
+    rga_preproc_member(config, stream_path, line_prefix, Box::pin(inp)).await
+}
+
+/**
+ * Preprocess a file that is not on disk, like a file within an archive, with the given line prefix.
+ *
+ * Its output is not cached.
+ */
+pub async fn rga_preproc_member(
+    config: RgaConfig,
+    filepath_hint: PathBuf,
+    line_prefix: String,
+    inp: ReadBox,
+) -> Result<ReadBox> {
+    let ai = AdaptInfo {
+        inp,
+        line_prefix,
+        filepath_hint,
+        is_real_file: false,
+        archive_recursion_depth: 0,
+        postprocess: !config.no_prefix_filenames,
+        config,
+        passwords: PasswordState::default(),
+    };
+    Ok(match buf_choose_adapter(ai).await? {
+        Ret::Recurse(ai, adapter, detection_reason, _) => {
+            concat_read_streams(loop_adapt(adapter.as_ref(), detection_reason, ai).await?)
+        }
+        Ret::Passthrough(ai) => ai.inp,
+    })
 }
 
 async fn adapt_caching(
-    ai: AdaptInfo,
+    mut ai: AdaptInfo,
     adapter: Arc<dyn FileAdapter>,
     detection_reason: FileMatcher,
     active_adapters: ActiveAdapters,
 ) -> Result<ReadBox> {
+    let password_state = ai.passwords.clone();
     let meta = adapter.metadata();
     debug!(
         "Chose adapter '{}' because of matcher {:?}",
@@ -145,9 +254,13 @@ async fn adapt_caching(
     );
     let cache_compression_level = ai.config.cache.compression_level;
     let cache_max_blob_len = ai.config.cache.max_blob_len;
+    let max_output_size = ai.config.max_output_size_for(&meta.name);
+    let sort = ai.config.sort;
+    let lang = ai.config.lang.clone();
 
+    let cache_path = ai.config.cache.path.0.clone();
     let cache = if ai.is_real_file && !ai.config.cache.disabled {
-        Some(open_cache_db(Path::new(&ai.config.cache.path.0)).await?)
+        Some(open_cache_db(Path::new(&cache_path)).await?)
     } else {
         None
     };
@@ -155,6 +268,7 @@ async fn adapt_caching(
     let mut cache = cache.context("No cache?")?;
     let cache_key = CacheKey::new(
         ai.postprocess,
+        &ai.config,
         &ai.filepath_hint,
         adapter.as_ref(),
         &active_adapters,
@@ -162,11 +276,84 @@ async fn adapt_caching(
     // let dbg_ctx = format!("adapter {}", &adapter.metadata().name);
     let cached = cache.get(&cache_key).await.context("cache.get")?;
     match cached {
-        Some(cached) => Ok(Box::pin(ZstdDecoder::new(Cursor::new(cached)))),
+        Some(cached) => {
+            let inp: ReadBox = Box::pin(ZstdDecoder::new(Cursor::new(cached)));
+            if lang.is_empty() {
+                return Ok(inp);
+            }
+            // the languages of the files were recorded when the output was cached
+            let location_map = cache
+                .get_location_map(&cache_key)
+                .await?
+                .unwrap_or_default();
+            Ok(filter_members(
+                inp,
+                Arc::new(Mutex::new(location_map)),
+                lang,
+            ))
+        }
         None => {
             debug!("cache MISS, running adapter with caching...");
-            let inp = loop_adapt(adapter.as_ref(), detection_reason, ai).await?;
-            let inp = concat_read_streams(inp);
+            // with sorting, all entries are read before the first output, so checkpoints can't be used
+            let checkpoint = if sort {
+                None
+            } else {
+                cache.get_checkpoint(&cache_key).await?
+            };
+            let (resumed, location_map, progress) = match checkpoint {
+                Some(checkpoint) => {
+                    debug!(
+                        "resuming from checkpoint after {} entries",
+                        checkpoint.completed_entries
+                    );
+                    let mut resumed = Vec::new();
+                    ZstdDecoder::new(Cursor::new(checkpoint.output_zstd))
+                        .read_to_end(&mut resumed)
+                        .await?;
+                    let progress = Progress {
+                        skip: checkpoint.completed_entries,
+                        ..Default::default()
+                    };
+                    (resumed, checkpoint.location_map, progress)
+                }
+                None => (Vec::new(), LocationMap::default(), Progress::default()),
+            };
+            let progress = Arc::new(progress);
+            // the whole output is cached with the language of each file, and filtered below
+            ai.config.lang = Vec::new();
+            let inp =
+                loop_adapt_resumable(adapter.as_ref(), detection_reason, ai, progress.clone())
+                    .await?;
+            let inp = if sort {
+                sorted_by_path(inp, SORT_BUFFER_LIMIT)
+            } else {
+                inp
+            };
+            let location_map = Arc::new(Mutex::new(location_map));
+            let first_line = 1 + resumed.iter().filter(|b| **b == b'\n').count() as u64;
+            let inp = concat_read_streams_with_locations(
+                inp,
+                location_map.clone(),
+                first_line,
+                !lang.is_empty(),
+            );
+            let inp = if sort {
+                inp
+            } else {
+                CheckpointWriter {
+                    cache_path: PathBuf::from(&cache_path),
+                    key: cache_key.clone(),
+                    compression_level: cache_compression_level.0,
+                    max_blob_len: cache_max_blob_len.0,
+                }
+                .tap(inp, resumed.clone(), progress, location_map.clone())
+            };
+            let inp: ReadBox = Box::pin(Cursor::new(resumed).chain(inp));
+            let inp = match max_output_size {
+                Some(max_len) => truncate_output(inp, max_len),
+                None => inp,
+            };
+            let filter_map = location_map.clone();
             let inp = async_read_and_write_to_cache(
                 inp,
                 cache_max_blob_len.0,
@@ -184,14 +371,110 @@ async fn adapt_caching(
                                 .await
                                 .context("writing to cache")?
                         }
+                        let mut location_map = location_map.lock().expect("poisoned").clone();
+                        location_map.passwords = password_state.take_used();
+                        cache
+                            .set_location_map(&cache_key, &location_map)
+                            .await
+                            .context("writing location map to cache")?;
+                        cache
+                            .delete_checkpoint(&cache_key)
+                            .await
+                            .context("deleting checkpoint")?;
                         Ok(())
                     })
                 }),
             )?;
+            if lang.is_empty() {
+                return Ok(Box::pin(inp));
+            }
+            Ok(filter_members(Box::pin(inp), filter_map, lang))
+        }
+    }
+}
+
+/**
+ * Find the location in the original document of a line (1-based) of the adapted output of a file,
+ * e.g. the file within an archive and the page number.
+ * If a column is given (as output by `rg --column`), it is translated to the column without the prefixes rga added.
+ *
+ * Uses the location map stored in the cache, adapting the file first if necessary.
+ */
+pub async fn rga_locate(ai: AdaptInfo, line: u64, column: Option<u64>) -> Result<SourceLocation> {
+    let (location_map, text) = adapt_with_location_map(ai, Some(line)).await?;
+    let text = text.context("line not read")?;
+    Ok(location_map.locate(line, column, &text))
+}
+
+/**
+ * Get the location map of the adapted output of a file (see `rga_locate`).
+ */
+pub async fn rga_location_map(ai: AdaptInfo) -> Result<LocationMap> {
+    Ok(adapt_with_location_map(ai, None).await?.0)
+}
+
+/// Adapts the file (using the cache) and returns the location map and the given line of the adapted output
+async fn adapt_with_location_map(
+    ai: AdaptInfo,
+    line: Option<u64>,
+) -> Result<(LocationMap, Option<String>)> {
+    let (ai, adapter, detection_reason, active_adapters) = match buf_choose_adapter(ai).await? {
+        Ret::Recurse(ai, a, b, c) => (ai, a, b, c),
+        Ret::Passthrough(ai) => {
+            return Ok((LocationMap::default(), read_line_opt(ai.inp, line).await?));
+        }
+    };
+    let cache_path = ai.config.cache.path.0.clone();
+    let lang = ai.config.lang.clone();
+    let cache_key = CacheKey::new(
+        ai.postprocess,
+        &ai.config,
+        &ai.filepath_hint,
+        adapter.as_ref(),
+        &active_adapters,
+    )?;
+    let oup = adapt_caching(ai, adapter, detection_reason, active_adapters).await?;
+    // read everything so the location map is written to the cache
+    let text = read_line_opt(oup, line).await?;
+    let cache = open_cache_db(Path::new(&cache_path)).await?;
+    let location_map = cache
+        .get_location_map(&cache_key)
+        .await?
+        .context("no location map in cache")?
+        // the line numbers of the output without the files in other languages
+        .filtered(&lang);
+    Ok((location_map, text))
+}
+
+/// Reads the whole input and returns the given line, if any
+async fn read_line_opt(inp: ReadBox, line: Option<u64>) -> Result<Option<String>> {
+    match line {
+        Some(line) => Ok(Some(read_line(inp, line).await?)),
+        None => read_discard(inp).await.map(|_| None),
+    }
+}
 
-            Ok(Box::pin(inp))
+/// Reads the whole input and returns the given line (1-based) without the trailing newline
+async fn read_line(inp: ReadBox, line: u64) -> Result<String> {
+    let mut inp = BufReader::new(inp);
+    let mut found = None;
+    let mut buf = Vec::new();
+    let mut current = 0;
+    loop {
+        buf.clear();
+        if inp.read_until(b'\n', &mut buf).await? == 0 {
+            break;
+        }
+        current += 1;
+        if current == line {
+            found = Some(
+                String::from_utf8_lossy(&buf)
+                    .trim_end_matches('\n')
+                    .to_string(),
+            );
         }
     }
+    found.with_context(|| format!("output only has {current} lines"))
 }
 
 async fn read_discard(mut x: ReadBox) -> Result<()> {
@@ -210,14 +493,54 @@ pub fn loop_adapt(
     detection_reason: FileMatcher,
     ai: AdaptInfo,
 ) -> Pin<Box<dyn Future<Output = anyhow::Result<AdaptedFilesIterBox>> + Send + '_>> {
-    Box::pin(async move { loop_adapt_inner(adapter, detection_reason, ai).await })
+    Box::pin(async move { loop_adapt_inner(adapter, detection_reason, ai, None, None).await })
 }
-pub async fn loop_adapt_inner(
+/// `loop_adapt` for the files within another, sharing its limits
+fn loop_adapt_nested(
+    adapter: &dyn FileAdapter,
+    detection_reason: FileMatcher,
+    ai: AdaptInfo,
+    limits: Arc<ExpansionLimits>,
+) -> Pin<Box<dyn Future<Output = anyhow::Result<AdaptedFilesIterBox>> + Send + '_>> {
+    Box::pin(
+        async move { loop_adapt_inner(adapter, detection_reason, ai, None, Some(limits)).await },
+    )
+}
+/// Like `loop_adapt`, but skips the first `progress.skip` entries of the file and counts the completed entries (see `checkpoint`)
+pub async fn loop_adapt_resumable(
     adapter: &dyn FileAdapter,
     detection_reason: FileMatcher,
     ai: AdaptInfo,
+    progress: Arc<Progress>,
 ) -> anyhow::Result<AdaptedFilesIterBox> {
+    loop_adapt_inner(adapter, detection_reason, ai, Some(progress), None).await
+}
+pub async fn loop_adapt_inner(
+    adapter: &dyn FileAdapter,
+    detection_reason: FileMatcher,
+    mut ai: AdaptInfo,
+    progress: Option<Arc<Progress>>,
+    limits: Option<Arc<ExpansionLimits>>,
+) -> anyhow::Result<AdaptedFilesIterBox> {
+    // shared with the nested archives
+    let limits = match limits {
+        Some(limits) => limits,
+        None => {
+            let limits = Arc::new(ExpansionLimits::new(&ai.config));
+            let size = match ai.is_real_file {
+                true => tokio::fs::metadata(&ai.filepath_hint).await.ok(),
+                false => None,
+            };
+            match size {
+                Some(meta) => limits.set_input_size(meta.len()),
+                None => ai.inp = limits.count_input(ai.inp),
+            }
+            limits
+        }
+    };
     let fph = ai.filepath_hint.clone();
+    let line_prefix = ai.line_prefix.clone();
+    let recurses = adapter.metadata().recurses;
     let inp = adapter.adapt(ai, &detection_reason).await;
     let inp = if adapter.metadata().name == "postprocprefix" {
         // don't add confusing error context
@@ -232,14 +555,36 @@ pub async fn loop_adapt_inner(
         })?
     };
     let s = stream! {
+        let mut entries: u64 = 0;
         for await file in inp {
             trace!("next file");
-            match buf_choose_adapter(file?).await? {
+            if limits.exceeded() {
+                // stop reading, the rest of the archive might be a bomb
+                break;
+            }
+            let mut file = match file {
+                // caused by the cut off data
+                Err(_) if limits.exceeded() => break,
+                file => file?,
+            };
+            if let Some(progress) = &progress {
+                // the output of the previous entries was read completely before the next entry is requested
+                progress.completed.store(entries, Ordering::SeqCst);
+                entries += 1;
+                if entries <= progress.skip {
+                    read_discard(file.inp).await?;
+                    continue;
+                }
+            }
+            if recurses {
+                file.inp = limits.limit(file.inp);
+            }
+            match buf_choose_adapter(file).await? {
                 Ret::Recurse(ai, adapter, detection_reason, _active_adapters) => {
                     if ai.archive_recursion_depth >= ai.config.max_archive_recursion.0 {
                         // some adapters (esp. zip) assume that the entry is read fully and might hang otherwise
                         read_discard(ai.inp).await?;
-                        let s = format!("{}[rga: max archive recursion reached ({})]\n", ai.line_prefix, ai.archive_recursion_depth).into_bytes();
+                        let s = format!("{}[rga: skipped, exceeds limits (archive recursion depth {})]\n", ai.line_prefix, ai.archive_recursion_depth).into_bytes();
                         yield Ok(AdaptInfo {
                             inp: Box::pin(Cursor::new(s)),
                             ..ai
@@ -255,18 +600,200 @@ pub async fn loop_adapt_inner(
                         ai.filepath_hint.to_string_lossy(),
                         &adapter.metadata().name
                     );
-                    for await ifile in loop_adapt(adapter.as_ref(), detection_reason, ai).await? {
-                        yield ifile;
+                    // `max_output_size` is applied to the whole output of the searched file in `adapt_caching`
+                    let max_len = ai.config.adapter_max_output_size.get(&adapter.metadata().name).map(|s| s.0);
+                    let inner = loop_adapt_nested(adapter.as_ref(), detection_reason, ai, limits.clone()).await;
+                    let inner = match inner {
+                        Err(_) if limits.exceeded() => break,
+                        inner => inner?,
+                    };
+                    let mut cut_off = false;
+                    for await ifile in inner {
+                        match ifile {
+                            Err(_) if limits.exceeded() => {
+                                cut_off = true;
+                                break;
+                            }
+                            Ok(mut ifile) => {
+                                if let Some(max_len) = max_len {
+                                    ifile.inp = truncate_output(ifile.inp, max_len);
+                                }
+                                yield Ok(ifile);
+                            }
+                            Err(e) => yield Err(e),
+                        }
+                    }
+                    if cut_off {
+                        break;
                     }
                 }
-                Ret::Passthrough(ai) => {
+                Ret::Passthrough(mut ai) => {
                     debug!("no adapter for {}, ending recursion", ai.filepath_hint.to_string_lossy());
+                    ai.inp = limits.ignore_errors_when_exceeded(ai.inp);
+                    if !ai.config.lang.is_empty() {
+                        // the language of each file within an archive, not of the whole archive
+                        ai.inp = filter_language(ai.inp, &ai.config.lang, &ai.line_prefix).await?;
+                    }
                     yield Ok(ai);
                 }
             }
             trace!("done with files");
         }
+        if let Some(marker) = limits.take_marker(&line_prefix) {
+            yield Ok(AdaptInfo {
+                filepath_hint: fph,
+                is_real_file: false,
+                archive_recursion_depth: 0,
+                inp: Box::pin(Cursor::new(marker.into_bytes())),
+                line_prefix,
+                postprocess: false,
+                config: RgaConfig::default(),
+                passwords: PasswordState::default(),
+            });
+        }
+        if let Some(progress) = &progress {
+            progress.completed.store(entries, Ordering::SeqCst);
+        }
         trace!("stream ended");
     };
     Ok(Box::pin(s))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn stock_rg_quick_exit() -> Result<()> {
+        let config = RgaConfig::default();
+        assert!(matches_any_adapter(
+            &config,
+            &test_data_dir().join("short.pdf")
+        )?);
+        assert!(matches_any_adapter(&config, Path::new("archive.ZIP"))?);
+        // rg searches these files as is, without waiting for the output of rga-preproc
+        assert!(!matches_any_adapter(&config, Path::new("notes.txt"))?);
+        assert!(!matches_any_adapter(&config, Path::new("Makefile"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn magic_mimetypes() {
+        assert_eq!(magic_mimetype(b"From -\n"), Some("application/mbox"));
+        assert_eq!(
+            magic_mimetype(&[0x3f, 0xd7, 0x6c, 0x17, 5]),
+            Some(lucene::MIMETYPE)
+        );
+        assert_eq!(magic_mimetype(b"MDMP\x93\xa7"), Some(minidump::MIMETYPE));
+        assert_eq!(magic_mimetype(b"hello"), None);
+    }
+
+    #[tokio::test]
+    async fn pre_glob_without_adapter() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        // selected by the pre-glob (`*.log`, `**/tmp/*`, `*.mdb`), but not LevelDB logs, Maildir messages or LMDB data files
+        for name in ["foo.log", "tmp/notes.txt", "data.mdb"] {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, "hello world\n")?;
+            let (mut a, _) = simple_fs_adapt_info(&path).await?;
+            a.line_prefix = String::new();
+            a.config.cache.disabled = true;
+            let mut oup = Vec::new();
+            rga_preproc(a).await?.read_to_end(&mut oup).await?;
+            assert_eq!(String::from_utf8(oup)?, "hello world\n", "{name}");
+        }
+        Ok(())
+    }
+
+    const GERMAN: &str = "Der schnelle braune Fuchs springt über den faulen Hund. Die Verhandlung wurde auf nächste Woche verschoben, weil der Zeuge nicht erschienen ist.\n";
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog. The hearing was postponed until next week because the witness did not appear.\n";
+
+    /// a zip with a German and an English text file
+    async fn texts_zip() -> Result<Vec<u8>> {
+        use async_zip::{Compression, ZipEntryBuilder, write::ZipFileWriter};
+        let mut cursor = Cursor::new(Vec::new());
+        let mut archive = ZipFileWriter::new(&mut cursor);
+        for (name, content) in [("de.txt", GERMAN), ("en.txt", ENGLISH)] {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Stored);
+            archive
+                .write_entry_whole(options, content.as_bytes())
+                .await?;
+        }
+        archive.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    #[tokio::test]
+    async fn language_per_member() -> Result<()> {
+        let (mut a, d) = simple_adapt_info(
+            Path::new("texts.zip"),
+            Box::pin(Cursor::new(texts_zip().await?)),
+        );
+        a.config.lang = vec!["en".to_string()];
+        let res = loop_adapt(&zip::ZipAdapter::new(), d, a).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            format!("PREFIX:en.txt: {ENGLISH}")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn language_on_cache_hit() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("texts.zip");
+        std::fs::write(&path, texts_zip().await?)?;
+        let adapt = |lang: &str| {
+            let (path, cache_path) = (path.clone(), dir.path().join("cache"));
+            let lang = vec![lang.to_string()];
+            async move {
+                let (mut a, _) = simple_fs_adapt_info(&path).await?;
+                a.line_prefix = String::new();
+                a.config.cache.path.0 = cache_path.to_string_lossy().into_owned();
+                a.config.lang = lang;
+                Ok::<_, anyhow::Error>(a)
+            }
+        };
+        // the first run caches the output of both files, the others filter the cached output
+        for (lang, expected) in [("en", ENGLISH), ("en", ENGLISH), ("de", GERMAN)] {
+            let mut oup = Vec::new();
+            rga_preproc(adapt(lang).await?)
+                .await?
+                .read_to_end(&mut oup)
+                .await?;
+            let name = format!("{lang}.txt: ");
+            assert_eq!(String::from_utf8(oup)?, format!("{name}{expected}"));
+            let location = rga_locate(adapt(lang).await?, 1, None).await?;
+            assert_eq!(location.member.as_deref(), Some(&name[..6]));
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn adapter_max_output_size_per_member() -> Result<()> {
+        use async_zip::{Compression, ZipEntryBuilder, write::ZipFileWriter};
+        let mut cursor = Cursor::new(Vec::new());
+        let mut archive = ZipFileWriter::new(&mut cursor);
+        let options = ZipEntryBuilder::new("data.json".to_string(), Compression::Stored);
+        archive
+            .write_entry_whole(options, br#"{"a":1,"b":2}"#)
+            .await?;
+        archive.close().await?;
+        let (mut a, d) = simple_adapt_info(
+            Path::new("data.zip"),
+            Box::pin(Cursor::new(cursor.into_inner())),
+        );
+        a.config.adapters = vec!["+gron".into()];
+        a.config
+            .adapter_max_output_size
+            .insert("gron".to_string(), crate::config::ByteSize(40));
+        let res = loop_adapt(&zip::ZipAdapter::new(), d, a).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:data.json: json.a = 1\n[rga: output truncated at 40 B]\n"
+        );
+        Ok(())
+    }
+}
@@ -1,12 +1,21 @@
-use crate::{adapters::FileAdapter, preproc::ActiveAdapters};
+use crate::{
+    adapters::FileAdapter,
+    checkpoint::Checkpoint,
+    config::{PdfConfig, RgaConfig},
+    location::LocationMap,
+    preproc::ActiveAdapters,
+};
 use anyhow::{Context, Result};
 use log::warn;
 use path_clean::PathClean;
 use rusqlite::{OptionalExtension, named_params};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
 use std::{path::Path, time::UNIX_EPOCH};
 use tokio_rusqlite::Connection;
 
-static SCHEMA_VERSION: i32 = 3;
+static SCHEMA_VERSION: i32 = 6;
 #[derive(Clone)]
 pub struct CacheKey {
     config_hash: String,
@@ -16,16 +25,118 @@ pub struct CacheKey {
     file_path: String,
     file_mtime_unix_ms: i64,
 }
+/// 64 bit FNV-1a. Used instead of the std hasher since it needs to be stable across rust versions.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The random salt of the password digests in the cache at `cache_dir`, created on first use
+fn password_salt(cache_dir: &Path) -> Result<(u64, u64)> {
+    let path = cache_dir.join("password-salt");
+    let random = || RandomState::new().build_hasher().finish();
+    let salt = format!("{:016x}{:016x}", random(), random());
+    std::fs::create_dir_all(cache_dir)?;
+    let salt = match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+    {
+        Ok(mut file) => {
+            file.write_all(salt.as_bytes())?;
+            salt
+        }
+        // created by a concurrent rga-preproc
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => std::fs::read_to_string(&path)?,
+        Err(e) => return Err(e).with_context(|| format!("creating {}", path.display())),
+    };
+    let parse = |s: Option<&str>| s.and_then(|s| u64::from_str_radix(s, 16).ok());
+    parse(salt.get(..16))
+        .zip(parse(salt.get(16..32)))
+        .with_context(|| format!("invalid password salt in {}", path.display()))
+}
+
+/// Salted digest of the passwords (`--rga-archive-password` and `--rga-pdf-password`),
+/// since the cache key is stored on disk
+fn passwords_digest(config: &RgaConfig) -> Result<Option<String>> {
+    let passwords: Vec<&str> = config
+        .archive_passwords
+        .iter()
+        .map(String::as_str)
+        .chain(config.pdf.password.as_deref())
+        .collect();
+    if passwords.is_empty() {
+        return Ok(None);
+    }
+    let (k0, k1) = password_salt(Path::new(&config.cache.path.0))?;
+    // SipHash-2-4, unlike the std hasher its output does not change between rust versions
+    #[allow(deprecated)]
+    let mut hasher = std::hash::SipHasher::new_with_keys(k0, k1);
+    hasher.write_usize(config.archive_passwords.len());
+    for password in passwords {
+        hasher.write(password.as_bytes());
+        hasher.write_u8(0);
+    }
+    Ok(Some(format!("{:016x}", hasher.finish())))
+}
+
+/// Hash of the config options that change the output of adapters
+fn config_hash(postprocess: bool, config: &RgaConfig) -> Result<String> {
+    let base = if postprocess { "a41e2e9" } else { "f1502a3" };
+    let pdf = PdfConfig {
+        password: None,
+        ..config.pdf.clone()
+    };
+    let output_options = serde_json::to_string(&serde_json::json!({
+        "member_line_numbers": config.member_line_numbers,
+        "fallback_encodings": config.fallback_encodings,
+        "page_style": config.page_style,
+        "binary": config.binary,
+        "binary_adapter": config.binary_adapter,
+        "strings_min_length": config.strings_min_length,
+        "max_rows": config.max_rows,
+        "parquet_columns": config.parquet_columns,
+        "max_output_size": config.max_output_size,
+        "max_decompressed_size": config.max_decompressed_size,
+        "max_expansion_ratio": config.max_expansion_ratio,
+        "adapter_max_output_size": config.adapter_max_output_size,
+        "sort": config.sort,
+        // the language of each file is only detected with --rga-lang, the output is filtered after the cache
+        "detect_language": !config.lang.is_empty(),
+        "password_file": config.password_file,
+        "passwords": passwords_digest(config)?,
+        "tar_metadata": config.tar_metadata,
+        "sqlite_text_blobs": config.sqlite_text_blobs,
+        "dicom_redact": config.dicom_redact,
+        "ocr": config.ocr,
+        "whisper": config.whisper,
+        "pdf": pdf,
+        "protobuf": config.protobuf,
+        // which adapter handles a file, and how custom adapters convert it
+        "adapter_aliases": config.adapter_aliases,
+        "custom_adapters": config.custom_adapters,
+    }))?;
+    Ok(format!("{base}-{:016x}", fnv1a(output_options.as_bytes())))
+}
+
 impl CacheKey {
     pub fn new(
         postprocess: bool,
+        config: &RgaConfig,
         filepath_hint: &Path,
         adapter: &dyn FileAdapter,
         active_adapters: &ActiveAdapters,
     ) -> Result<CacheKey> {
         let meta = std::fs::metadata(filepath_hint)
             .with_context(|| format!("reading metadata for {}", filepath_hint.to_string_lossy()))?;
-        let modified = meta.modified().expect("weird OS that can't into mtime");
+        let mut modified = meta.modified().expect("weird OS that can't into mtime");
+        // sqlite databases in WAL mode are changed without touching the database file
+        let mut wal = filepath_hint.as_os_str().to_owned();
+        wal.push("-wal");
+        if let Ok(wal_modified) = std::fs::metadata(&wal).and_then(|m| m.modified()) {
+            modified = modified.max(wal_modified);
+        }
         let file_mtime_unix_ms = modified.duration_since(UNIX_EPOCH)?.as_millis() as i64;
         let active_adapters = if adapter.metadata().recurses {
             serde_json::to_string(
@@ -38,11 +149,7 @@ impl CacheKey {
             "null".to_string()
         };
         Ok(CacheKey {
-            config_hash: if postprocess {
-                "a41e2e9".to_string()
-            } else {
-                "f1502a3".to_string()
-            }, // todo: when we add more config options that affect caching, create a struct and actually hash it
+            config_hash: config_hash(postprocess, config)?,
             adapter: adapter.metadata().name.clone(),
             adapter_version: adapter.metadata().version,
             file_path: filepath_hint.clean().to_string_lossy().to_string(),
@@ -56,6 +163,11 @@ impl CacheKey {
 pub trait PreprocCache {
     async fn get(&self, key: &CacheKey) -> Result<Option<Vec<u8>>>;
     async fn set(&mut self, key: &CacheKey, value: Vec<u8>) -> Result<()>;
+    async fn get_location_map(&self, key: &CacheKey) -> Result<Option<LocationMap>>;
+    async fn set_location_map(&mut self, key: &CacheKey, map: &LocationMap) -> Result<()>;
+    async fn get_checkpoint(&self, key: &CacheKey) -> Result<Option<Checkpoint>>;
+    async fn set_checkpoint(&mut self, key: &CacheKey, checkpoint: &Checkpoint) -> Result<()>;
+    async fn delete_checkpoint(&mut self, key: &CacheKey) -> Result<()>;
 }
 
 async fn connect_pragmas(db: &Connection) -> Result<()> {
@@ -84,6 +196,34 @@ async fn connect_pragmas(db: &Connection) -> Result<()> {
         )?;
 
         db.execute("create unique index if not exists preproc_cache_idx on preproc_cache (config_hash, adapter, adapter_version, file_path, active_adapters)", [])?;
+        // stored separately from preproc_cache since it is also written if the output is too large to cache
+        db.execute("
+            create table if not exists preproc_location_map (
+                config_hash text not null,
+                adapter text not null,
+                adapter_version integer not null,
+                active_adapters text not null,
+                file_path text not null,
+                file_mtime_unix_ms integer not null,
+                location_map_json text not null
+            ) strict", []
+        )?;
+        db.execute("create unique index if not exists preproc_location_map_idx on preproc_location_map (config_hash, adapter, adapter_version, file_path, active_adapters)", [])?;
+        // progress of interrupted adaptations of large files, see checkpoint.rs
+        db.execute("
+            create table if not exists preproc_checkpoint (
+                config_hash text not null,
+                adapter text not null,
+                adapter_version integer not null,
+                active_adapters text not null,
+                file_path text not null,
+                file_mtime_unix_ms integer not null,
+                completed_entries integer not null,
+                location_map_json text not null,
+                output_zstd blob not null
+            ) strict", []
+        )?;
+        db.execute("create unique index if not exists preproc_checkpoint_idx on preproc_checkpoint (config_hash, adapter, adapter_version, file_path, active_adapters)", [])?;
 
         Ok(())
     })
@@ -110,6 +250,8 @@ impl SqliteCache {
             if schema_version != SCHEMA_VERSION {
                 warn!("Cache schema version mismatch, clearing cache");
                 db.execute("drop table if exists preproc_cache", [])?;
+                db.execute("drop table if exists preproc_location_map", [])?;
+                db.execute("drop table if exists preproc_checkpoint", [])?;
                 db.pragma_update(None, "user_version", format!("{SCHEMA_VERSION}"))?;
             }
             Ok(())
@@ -186,7 +328,169 @@ impl PreprocCache for SqliteCache {
             })
             .await?)
     }
+
+    async fn get_location_map(&self, key: &CacheKey) -> Result<Option<LocationMap>> {
+        let key = (*key).clone();
+        let json = self
+            .db
+            .call(move |db| {
+                Ok(db
+                    .query_row(
+                        "select location_map_json from preproc_location_map where
+                            adapter = :adapter
+                        and config_hash = :config_hash
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path
+                        and file_mtime_unix_ms = :file_mtime_unix_ms
+                ",
+                        named_params! {
+                            ":config_hash": &key.config_hash,
+                            ":adapter": &key.adapter,
+                            ":adapter_version": &key.adapter_version,
+                            ":active_adapters": &key.active_adapters,
+                            ":file_path": &key.file_path,
+                            ":file_mtime_unix_ms": &key.file_mtime_unix_ms
+                        },
+                        |r| r.get::<_, String>(0),
+                    )
+                    .optional()?)
+            })
+            .await
+            .context("reading location map from cache")?;
+        json.map(|json| serde_json::from_str(&json).context("parsing location map"))
+            .transpose()
+    }
+
+    async fn set_location_map(&mut self, key: &CacheKey, map: &LocationMap) -> Result<()> {
+        let key = (*key).clone();
+        let json = serde_json::to_string(map)?;
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "insert into preproc_location_map (config_hash, adapter, adapter_version, active_adapters, file_path, file_mtime_unix_ms, location_map_json) values
+                        (:config_hash, :adapter, :adapter_version, :active_adapters, :file_path, :file_mtime_unix_ms, :location_map_json)
+                    on conflict (config_hash, adapter, adapter_version, active_adapters, file_path) do update set
+                        file_mtime_unix_ms = :file_mtime_unix_ms,
+                        location_map_json = :location_map_json",
+                    named_params! {
+                        ":config_hash": &key.config_hash,
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path,
+                        ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
+                        ":location_map_json": json
+                    })?;
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn get_checkpoint(&self, key: &CacheKey) -> Result<Option<Checkpoint>> {
+        let key = (*key).clone();
+        let row = self
+            .db
+            .call(move |db| {
+                Ok(db
+                    .query_row(
+                        "select completed_entries, location_map_json, output_zstd from preproc_checkpoint where
+                            adapter = :adapter
+                        and config_hash = :config_hash
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path
+                        and file_mtime_unix_ms = :file_mtime_unix_ms
+                ",
+                        named_params! {
+                            ":config_hash": &key.config_hash,
+                            ":adapter": &key.adapter,
+                            ":adapter_version": &key.adapter_version,
+                            ":active_adapters": &key.active_adapters,
+                            ":file_path": &key.file_path,
+                            ":file_mtime_unix_ms": &key.file_mtime_unix_ms
+                        },
+                        |r| {
+                            Ok((
+                                r.get::<_, i64>(0)?,
+                                r.get::<_, String>(1)?,
+                                r.get::<_, Vec<u8>>(2)?,
+                            ))
+                        },
+                    )
+                    .optional()?)
+            })
+            .await
+            .context("reading checkpoint from cache")?;
+        row.map(|(completed_entries, json, output_zstd)| {
+            Ok(Checkpoint {
+                completed_entries: completed_entries as u64,
+                location_map: serde_json::from_str(&json).context("parsing location map")?,
+                output_zstd,
+            })
+        })
+        .transpose()
+    }
+
+    async fn set_checkpoint(&mut self, key: &CacheKey, checkpoint: &Checkpoint) -> Result<()> {
+        let key = (*key).clone();
+        let json = serde_json::to_string(&checkpoint.location_map)?;
+        let completed_entries = checkpoint.completed_entries as i64;
+        let output_zstd = checkpoint.output_zstd.clone();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "insert into preproc_checkpoint (config_hash, adapter, adapter_version, active_adapters, file_path, file_mtime_unix_ms, completed_entries, location_map_json, output_zstd) values
+                        (:config_hash, :adapter, :adapter_version, :active_adapters, :file_path, :file_mtime_unix_ms, :completed_entries, :location_map_json, :output_zstd)
+                    on conflict (config_hash, adapter, adapter_version, active_adapters, file_path) do update set
+                        file_mtime_unix_ms = :file_mtime_unix_ms,
+                        completed_entries = :completed_entries,
+                        location_map_json = :location_map_json,
+                        output_zstd = :output_zstd",
+                    named_params! {
+                        ":config_hash": &key.config_hash,
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path,
+                        ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
+                        ":completed_entries": completed_entries,
+                        ":location_map_json": json,
+                        ":output_zstd": output_zstd
+                    })?;
+                Ok(())
+            })
+            .await?)
+    }
+
+    async fn delete_checkpoint(&mut self, key: &CacheKey) -> Result<()> {
+        let key = (*key).clone();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "delete from preproc_checkpoint where
+                            adapter = :adapter
+                        and config_hash = :config_hash
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path",
+                    named_params! {
+                        ":config_hash": &key.config_hash,
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path
+                    },
+                )?;
+                Ok(())
+            })
+            .await?)
+    }
 }
+
 /// opens a default cache
 pub async fn open_cache_db(path: &Path) -> Result<impl PreprocCache + use<>> {
     std::fs::create_dir_all(path)?;
@@ -205,4 +509,28 @@ mod test {
         // db.set();
         Ok(())
     }
+
+    #[test]
+    fn passwords_not_in_key() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut config = RgaConfig::default();
+        config.cache.path.0 = dir.path().to_string_lossy().into_owned();
+        assert_eq!(passwords_digest(&config)?, None);
+        config.archive_passwords = vec!["secret".to_string()];
+        let key = config_hash(true, &config)?;
+        assert_eq!(key, config_hash(true, &config)?);
+        config.archive_passwords = vec!["other".to_string()];
+        assert_ne!(key, config_hash(true, &config)?);
+
+        let salt = std::fs::read_to_string(dir.path().join("password-salt"))?;
+        config.archive_passwords = vec!["secret".to_string()];
+        let other_dir = tempfile::tempdir()?;
+        config.cache.path.0 = other_dir.path().to_string_lossy().into_owned();
+        assert_ne!(key, config_hash(true, &config)?);
+        assert_ne!(
+            salt,
+            std::fs::read_to_string(other_dir.path().join("password-salt"))?
+        );
+        Ok(())
+    }
 }
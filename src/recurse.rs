@@ -1,7 +1,11 @@
 use tokio_util::io::{ReaderStream, StreamReader};
 
-use crate::{adapted_iter::AdaptedFilesIterBox, adapters::*, to_io_err};
+use crate::config::RgaConfig;
+use crate::{adapted_iter::AdaptedFilesIterBox, adapters::*, print_bytes, to_io_err};
 use async_stream::stream;
+use bytes::{Bytes, BytesMut};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub fn concat_read_streams(input: AdaptedFilesIterBox) -> ReadBox {
     let s = stream! {
@@ -15,3 +19,247 @@ pub fn concat_read_streams(input: AdaptedFilesIterBox) -> ReadBox {
     };
     Box::pin(StreamReader::new(s))
 }
+
+/// Passes through at most `max_len` bytes of the input.
+/// If the input is longer, it is cut off after the last complete line and a line `[rga: output truncated at N MB]` is appended.
+/// Only if the first line is already longer, it is cut off within the line (at a character boundary).
+pub fn truncate_output(inp: ReadBox, max_len: usize) -> ReadBox {
+    let s = stream! {
+        let mut remaining = max_len;
+        // the bytes after the last newline, held back until the line is complete
+        let mut pending = BytesMut::new();
+        let mut first_line = true;
+        for await bytes in ReaderStream::new(inp) {
+            let bytes = bytes?;
+            if bytes.len() > remaining {
+                pending.extend_from_slice(&bytes[..remaining]);
+                let marker = format!("[rga: output truncated at {}]\n", print_bytes(max_len as f64));
+                let marker = match pending.iter().rposition(|b| *b == b'\n') {
+                    Some(i) => {
+                        yield Ok(pending.split_to(i + 1).freeze());
+                        marker
+                    }
+                    None if first_line => {
+                        let next = bytes.get(remaining).copied();
+                        // not before a UTF-8 continuation byte
+                        let end = (0..=pending.len())
+                            .rev()
+                            .find(|i| pending.get(*i).copied().or(next).is_none_or(|b| b & 0xc0 != 0x80))
+                            .unwrap_or(0);
+                        yield Ok(pending.split_to(end).freeze());
+                        format!("\n{marker}")
+                    }
+                    None => marker,
+                };
+                yield Ok(Bytes::from(marker));
+                pending.clear();
+                break;
+            }
+            remaining -= bytes.len();
+            match bytes.iter().rposition(|b| *b == b'\n') {
+                Some(i) => {
+                    first_line = false;
+                    pending.extend_from_slice(&bytes[..=i]);
+                    yield Ok(pending.split().freeze());
+                    pending.extend_from_slice(&bytes[i + 1..]);
+                }
+                None => pending.extend_from_slice(&bytes),
+            }
+        }
+        if !pending.is_empty() {
+            yield std::io::Result::Ok(pending.freeze());
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+/// The expansion ratio is only checked from this extracted size on, so small files that compress well are not skipped
+const MIN_SIZE_FOR_RATIO: u64 = 16 * 1024 * 1024;
+
+/// Guards against zip bombs: counts the bytes extracted from one file by archive and decompression adapters,
+/// including nested archives (`--rga-max-decompressed-size` and `--rga-max-expansion-ratio`).
+pub struct ExpansionLimits {
+    max_size: Option<u64>,
+    max_ratio: u64,
+    input_size: AtomicU64,
+    extracted: AtomicU64,
+    /// why the extraction was stopped
+    exceeded: Mutex<Option<String>>,
+    marker_shown: AtomicBool,
+}
+
+impl ExpansionLimits {
+    pub fn new(config: &RgaConfig) -> ExpansionLimits {
+        ExpansionLimits {
+            max_size: config.max_decompressed_size.map(|s| s.0 as u64),
+            max_ratio: config.max_expansion_ratio.0,
+            input_size: AtomicU64::new(0),
+            extracted: AtomicU64::new(0),
+            exceeded: Mutex::new(None),
+            marker_shown: AtomicBool::new(false),
+        }
+    }
+
+    /// Set the size of a file on disk
+    pub fn set_input_size(&self, size: u64) {
+        self.input_size.store(size, Ordering::SeqCst);
+    }
+
+    /// Count the bytes read from an input that is not on disk, whose size is not known in advance
+    pub fn count_input(self: &Arc<Self>, inp: ReadBox) -> ReadBox {
+        let limits = self.clone();
+        let s = stream! {
+            for await bytes in ReaderStream::new(inp) {
+                let bytes = bytes?;
+                limits.input_size.fetch_add(bytes.len() as u64, Ordering::SeqCst);
+                yield std::io::Result::Ok(bytes);
+            }
+        };
+        Box::pin(StreamReader::new(s))
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.lock().expect("poisoned").is_some()
+    }
+
+    /// Count extracted bytes, returns false if that exceeds a limit
+    fn add_extracted(&self, len: u64) -> bool {
+        let extracted = self.extracted.fetch_add(len, Ordering::SeqCst) + len;
+        let input_size = self.input_size.load(Ordering::SeqCst).max(1);
+        let reason = match self.max_size {
+            Some(max) if extracted > max => {
+                format!("extracted more than {}", print_bytes(max as f64))
+            }
+            _ if self.max_ratio > 0
+                && extracted > MIN_SIZE_FOR_RATIO
+                && extracted / input_size > self.max_ratio =>
+            {
+                format!(
+                    "extracted {} from {}, more than {} times the size",
+                    print_bytes(extracted as f64),
+                    print_bytes(input_size as f64),
+                    self.max_ratio
+                )
+            }
+            _ => return !self.exceeded(),
+        };
+        self.exceeded
+            .lock()
+            .expect("poisoned")
+            .get_or_insert(reason);
+        false
+    }
+
+    /// Pass through the data extracted by an adapter, ending it early once a limit is exceeded
+    pub fn limit(self: &Arc<Self>, inp: ReadBox) -> ReadBox {
+        let limits = self.clone();
+        let s = stream! {
+            for await bytes in ReaderStream::new(inp) {
+                let bytes = bytes?;
+                if !limits.add_extracted(bytes.len() as u64) {
+                    break;
+                }
+                yield std::io::Result::Ok(bytes);
+            }
+        };
+        Box::pin(StreamReader::new(s))
+    }
+
+    /// Once a limit is exceeded, adapters can fail on the data that was cut off. Their read errors end the output instead.
+    pub fn ignore_errors_when_exceeded(self: &Arc<Self>, inp: ReadBox) -> ReadBox {
+        let limits = self.clone();
+        let s = stream! {
+            for await bytes in ReaderStream::new(inp) {
+                match bytes {
+                    Err(_) if limits.exceeded() => break,
+                    bytes => yield bytes,
+                }
+            }
+        };
+        Box::pin(StreamReader::new(s))
+    }
+
+    /// The line `[rga: skipped, exceeds limits (...)]`, for the first caller after a limit was exceeded
+    pub fn take_marker(&self, line_prefix: &str) -> Option<String> {
+        let reason = self.exceeded.lock().expect("poisoned").clone()?;
+        if self.marker_shown.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        Some(format!(
+            "{line_prefix}[rga: skipped, exceeds limits ({reason})]\n"
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use tokio::io::AsyncReadExt;
+    use tokio_test::io::Builder;
+
+    #[tokio::test]
+    async fn truncate() -> anyhow::Result<()> {
+        let mut oup = String::new();
+        let mock = Builder::new().read(b"hello\nwor").read(b"ld\n").build();
+        truncate_output(Box::pin(mock), 12)
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "hello\nworld\n");
+
+        let mut oup = String::new();
+        let mock = Builder::new().read(b"hello\nwor").read(b"ld\n").build();
+        truncate_output(Box::pin(mock), 10)
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "hello\n[rga: output truncated at 10 B]\n");
+
+        // a single long line is cut between characters
+        let mut oup = String::new();
+        let mock = Builder::new().read("grüße".as_bytes()).build();
+        truncate_output(Box::pin(mock), 3)
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "gr\n[rga: output truncated at 3 B]\n");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expansion_limits() -> anyhow::Result<()> {
+        let config = RgaConfig {
+            max_decompressed_size: Some(crate::config::ByteSize(12)),
+            ..Default::default()
+        };
+        let limits = Arc::new(ExpansionLimits::new(&config));
+        let mut oup = String::new();
+        let mock = Builder::new().read(b"hello\n").read(b"world\n").build();
+        limits
+            .limit(Box::pin(mock))
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "hello\nworld\n");
+        assert_eq!(limits.take_marker("a.zip: "), None);
+
+        let mut oup = String::new();
+        let mock = Builder::new().read(b"more\n").build();
+        limits
+            .limit(Box::pin(mock))
+            .read_to_string(&mut oup)
+            .await?;
+        assert_eq!(oup, "");
+        assert_eq!(
+            limits.take_marker("a.zip: ").as_deref(),
+            Some("a.zip: [rga: skipped, exceeds limits (extracted more than 12 B)]\n")
+        );
+        // only shown once
+        assert_eq!(limits.take_marker("a.zip: "), None);
+
+        // 1 KB expanding to 32 MB
+        let limits = Arc::new(ExpansionLimits::new(&RgaConfig::default()));
+        limits.set_input_size(1000);
+        assert!(limits.add_extracted(MIN_SIZE_FOR_RATIO));
+        assert!(!limits.add_extracted(MIN_SIZE_FOR_RATIO));
+        assert!(limits.exceeded());
+        Ok(())
+    }
+}
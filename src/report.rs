@@ -0,0 +1,127 @@
+//! Match reports as CSV / TSV, see `--rga-output`.
+
+use crate::config::{OutputFormat, RgaConfig};
+use crate::location::{MatchLocator, parse_rg_match};
+use anyhow::{Context, Result};
+use std::borrow::Cow;
+use std::io::Write;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+
+const HEADER: [&str; 6] = ["path", "member", "page", "line", "text", "match"];
+
+fn csv_field(s: &str) -> Cow<'_, str> {
+    // spreadsheet applications would run cells starting with these as formulas
+    let s = if s.starts_with(['=', '+', '-', '@']) {
+        Cow::Owned(format!("'{s}"))
+    } else {
+        Cow::Borrowed(s)
+    };
+    if s.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        s
+    }
+}
+
+fn tsv_field(s: &str) -> Cow<'_, str> {
+    if s.contains(['\t', '\n', '\r']) {
+        Cow::Owned(s.replace(['\t', '\n', '\r'], " "))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// Write one row of the report
+pub fn write_row(format: OutputFormat, out: &mut impl Write, fields: &[&str]) -> Result<()> {
+    let row = match format {
+        OutputFormat::Csv => fields
+            .iter()
+            .map(|f| csv_field(f))
+            .collect::<Vec<_>>()
+            .join(","),
+        OutputFormat::Tsv => fields
+            .iter()
+            .map(|f| tsv_field(f))
+            .collect::<Vec<_>>()
+            .join("\t"),
+    };
+    let line_end = match format {
+        OutputFormat::Csv => "\r\n",
+        OutputFormat::Tsv => "\n",
+    };
+    write!(out, "{row}{line_end}")?;
+    Ok(())
+}
+
+pub fn write_header(format: OutputFormat, out: &mut impl Write) -> Result<()> {
+    write_row(format, out, &HEADER)
+}
+
+/// Run rg (which must output `--json`) and write one row per match to stdout.
+///
+/// Returns the exit code of rg.
+pub async fn write_report(
+    config: &RgaConfig,
+    format: OutputFormat,
+    mut cmd: Command,
+) -> Result<i32> {
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("could not spawn rg")?;
+    let stdout = child.stdout.take().context("no stdout")?;
+    let mut lines = BufReader::new(stdout).lines();
+    let mut locator = MatchLocator::new(config.clone());
+    let mut out = std::io::stdout();
+    while let Some(line) = lines.next_line().await? {
+        let Some(m) = parse_rg_match(&line)? else {
+            continue;
+        };
+        let location = locator.locate(&m).await;
+        let page = location.page.map(|p| p.to_string()).unwrap_or_default();
+        write_row(
+            format,
+            &mut out,
+            &[
+                &m.path,
+                location.member.as_deref().unwrap_or(""),
+                &page,
+                &location.member_line.to_string(),
+                &location.text,
+                &m.submatch,
+            ],
+        )?;
+    }
+    out.flush()?;
+    Ok(child.wait().await?.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn quoting() -> Result<()> {
+        let fields = ["a.zip", "dir/b, c.pdf", "2", "1", "say \"hi\"\tthere", "hi"];
+        let mut csv = Vec::new();
+        write_row(OutputFormat::Csv, &mut csv, &fields)?;
+        assert_eq!(
+            String::from_utf8(csv)?,
+            "a.zip,\"dir/b, c.pdf\",2,1,\"say \"\"hi\"\"\tthere\",hi\r\n"
+        );
+        let mut tsv = Vec::new();
+        write_row(OutputFormat::Tsv, &mut tsv, &fields)?;
+        assert_eq!(
+            String::from_utf8(tsv)?,
+            "a.zip\tdir/b, c.pdf\t2\t1\tsay \"hi\" there\thi\n"
+        );
+
+        let mut csv = Vec::new();
+        write_row(OutputFormat::Csv, &mut csv, &["=1+1", "-x, y", "a=b"])?;
+        assert_eq!(String::from_utf8(csv)?, "'=1+1,\"'-x, y\",a=b\r\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+//! Search by meaning with text embeddings, see `--rga-semantic`.
+//!
+//! The adapted output of every file is split into chunks (at most `semantic.chunk_lines` lines, ending at page breaks and archive members),
+//! which are embedded with `semantic.embed_command`. The embeddings are stored in `semantic.sqlite3` in the cache directory,
+//! keyed by the embedding command, file path and modification time.
+
+use crate::adapters::{AdaptInfo, custom::map_exe_error};
+use crate::config::RgaConfig;
+use crate::location::LocationMap;
+use crate::passwords::PasswordState;
+use crate::preproc::{rga_location_map, rga_preproc};
+use anyhow::{Context, Result};
+use log::*;
+use rusqlite::named_params;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rusqlite::Connection;
+
+const DEFAULT_RESULTS: usize = 10;
+const DEFAULT_CHUNK_LINES: usize = 10;
+/// number of chunks passed to one run of the embedding command
+const EMBED_BATCH_SIZE: usize = 256;
+
+/// A chunk of the adapted output of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// first line (1-based) of the chunk in the adapted output
+    pub line: u64,
+    /// path within the archive(s), see `SourceLocation`
+    pub member: Option<String>,
+    pub page: Option<u32>,
+    /// the lines of the chunk without the prefixes added by rga
+    pub text: String,
+}
+
+#[derive(Debug)]
+pub struct SemanticHit {
+    pub path: PathBuf,
+    pub chunk: Chunk,
+    /// cosine similarity to the query
+    pub score: f32,
+}
+
+/// Split adapted output into chunks of at most `max_lines` lines that don't span members or pages.
+pub fn split_chunks(map: &LocationMap, text: &str, max_lines: usize) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut lines_in_chunk = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i as u64 + 1;
+        let location = map.locate(line_number, None, line);
+        let continues = chunks.last().is_some_and(|c| {
+            lines_in_chunk < max_lines && c.member == location.member && c.page == location.page
+        });
+        if continues {
+            let chunk = chunks.last_mut().expect("checked above");
+            chunk.text.push('\n');
+            chunk.text.push_str(&location.text);
+            lines_in_chunk += 1;
+        } else {
+            chunks.push(Chunk {
+                line: line_number,
+                member: location.member,
+                page: location.page,
+                text: location.text,
+            });
+            lines_in_chunk = 1;
+        }
+    }
+    chunks.retain(|c| !c.text.trim().is_empty());
+    chunks
+}
+
+/// Run the embedding command on the given texts
+async fn embed(command: &[String], texts: &[String]) -> Result<Vec<Vec<f32>>> {
+    let (binary, args) = command
+        .split_first()
+        .context("semantic.embed_command must not be empty")?;
+    let mut child = tokio::process::Command::new(binary)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            map_exe_error(
+                e,
+                binary,
+                "Please check semantic.embed_command in the config file.",
+            )
+        })?;
+    let mut input = Vec::new();
+    for text in texts {
+        serde_json::to_writer(&mut input, text)?;
+        input.push(b'\n');
+    }
+    let mut stdin = child.stdin.take().context("no stdin")?;
+    // written concurrently so the command can't block on a full stdout pipe
+    let writer = tokio::spawn(async move { stdin.write_all(&input).await });
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .context("no stdout")?
+        .read_to_string(&mut output)
+        .await?;
+    let status = child.wait().await?;
+    if !status.success() {
+        anyhow::bail!("{binary} failed with {status}");
+    }
+    writer.await??;
+    let embeddings = output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str::<Vec<f32>>(l).context("invalid embedding"))
+        .collect::<Result<Vec<_>>>()?;
+    if embeddings.len() != texts.len() {
+        anyhow::bail!(
+            "{binary} returned {} embeddings for {} texts",
+            embeddings.len(),
+            texts.len()
+        );
+    }
+    Ok(embeddings)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let n = norm(a) * norm(b);
+    if n == 0.0 { 0.0 } else { dot / n }
+}
+
+fn embedding_to_blob(e: &[f32]) -> Vec<u8> {
+    e.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+fn embedding_from_blob(b: &[u8]) -> Vec<f32> {
+    b.chunks_exact(4)
+        .map(|f| f32::from_le_bytes([f[0], f[1], f[2], f[3]]))
+        .collect()
+}
+
+/// The stored embeddings of the chunks of files
+struct SemanticIndex {
+    db: Connection,
+    /// the embedding command, embeddings of different models can't be compared
+    model: String,
+}
+
+impl SemanticIndex {
+    async fn open(cache_path: &Path, model: String) -> Result<SemanticIndex> {
+        std::fs::create_dir_all(cache_path)?;
+        let db = Connection::open(cache_path.join("semantic.sqlite3")).await?;
+        db.call(|db| {
+            db.pragma_update(None, "journal_mode", "wal")?;
+            db.pragma_update(None, "synchronous", "off")?;
+            db.execute(
+                "
+                create table if not exists semantic_chunk (
+                    model text not null,
+                    file_path text not null,
+                    file_mtime_unix_ms integer not null,
+                    line integer not null,
+                    member text,
+                    page integer,
+                    text text not null,
+                    embedding blob not null -- little endian f32
+                ) strict",
+                [],
+            )?;
+            db.execute(
+                "create index if not exists semantic_chunk_idx on semantic_chunk (model, file_path)",
+                [],
+            )?;
+            Ok(())
+        })
+        .await
+        .context("opening semantic index")?;
+        Ok(SemanticIndex { db, model })
+    }
+
+    /// The chunks of the file if it was indexed at the given modification time
+    async fn get(&self, path: &str, mtime: i64) -> Result<Option<Vec<(Chunk, Vec<f32>)>>> {
+        let (model, path) = (self.model.clone(), path.to_string());
+        let rows = self
+            .db
+            .call(move |db| {
+                let mut stmt = db.prepare(
+                    "select file_mtime_unix_ms, line, member, page, text, embedding from semantic_chunk
+                        where model = :model and file_path = :file_path order by line",
+                )?;
+                let rows = stmt
+                    .query_map(
+                        named_params! {":model": model, ":file_path": path},
+                        |r| {
+                            Ok((
+                                r.get::<_, i64>(0)?,
+                                Chunk {
+                                    line: r.get(1)?,
+                                    member: r.get(2)?,
+                                    page: r.get(3)?,
+                                    text: r.get(4)?,
+                                },
+                                embedding_from_blob(&r.get::<_, Vec<u8>>(5)?),
+                            ))
+                        },
+                    )?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                Ok(rows)
+            })
+            .await?;
+        if rows.is_empty() || rows.iter().any(|(m, _, _)| *m != mtime) {
+            return Ok(None);
+        }
+        Ok(Some(
+            rows.into_iter()
+                // files without text are stored as a single empty chunk
+                .filter(|(_, c, _)| !c.text.is_empty())
+                .map(|(_, c, e)| (c, e))
+                .collect(),
+        ))
+    }
+
+    async fn set(&self, path: &str, mtime: i64, chunks: Vec<(Chunk, Vec<f32>)>) -> Result<()> {
+        let (model, path) = (self.model.clone(), path.to_string());
+        self.db
+            .call(move |db| {
+                let tx = db.transaction()?;
+                tx.execute(
+                    "delete from semantic_chunk where model = :model and file_path = :file_path",
+                    named_params! {":model": model, ":file_path": path},
+                )?;
+                let empty = (
+                    Chunk {
+                        line: 0,
+                        member: None,
+                        page: None,
+                        text: String::new(),
+                    },
+                    Vec::new(),
+                );
+                let rows = if chunks.is_empty() { vec![empty] } else { chunks };
+                for (chunk, embedding) in rows {
+                    tx.execute(
+                        "insert into semantic_chunk (model, file_path, file_mtime_unix_ms, line, member, page, text, embedding)
+                            values (:model, :file_path, :mtime, :line, :member, :page, :text, :embedding)",
+                        named_params! {
+                            ":model": model,
+                            ":file_path": path,
+                            ":mtime": mtime,
+                            ":line": chunk.line,
+                            ":member": chunk.member,
+                            ":page": chunk.page,
+                            ":text": chunk.text,
+                            ":embedding": embedding_to_blob(&embedding),
+                        },
+                    )?;
+                }
+                tx.commit()?;
+                Ok(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+/// Adapt the file (using the preprocessing cache) and split the output into chunks
+async fn file_chunks(config: &RgaConfig, path: &Path, max_lines: usize) -> Result<Vec<Chunk>> {
+    let adapt_info = |inp: tokio::fs::File| AdaptInfo {
+        inp: Box::pin(inp),
+        filepath_hint: path.to_path_buf(),
+        is_real_file: true,
+        line_prefix: "".to_string(),
+        archive_recursion_depth: 0,
+        postprocess: !config.no_prefix_filenames,
+        config: config.clone(),
+        passwords: PasswordState::default(),
+    };
+    let map = rga_location_map(adapt_info(tokio::fs::File::open(path).await?)).await?;
+    let mut text = Vec::new();
+    rga_preproc(adapt_info(tokio::fs::File::open(path).await?))
+        .await?
+        .read_to_end(&mut text)
+        .await?;
+    Ok(split_chunks(
+        &map,
+        &String::from_utf8_lossy(&text),
+        max_lines,
+    ))
+}
+
+/// Find the chunks of the given files that are most similar to the query.
+pub async fn semantic_search(
+    config: &RgaConfig,
+    query: &str,
+    files: &[PathBuf],
+) -> Result<Vec<SemanticHit>> {
+    let command = config.semantic.embed_command.clone().context(
+        "--rga-semantic requires an embedding command, set semantic.embed_command in the config file",
+    )?;
+    if config.cache.disabled {
+        anyhow::bail!("--rga-semantic stores the embeddings in the cache, it can't be disabled");
+    }
+    let max_lines = config
+        .semantic
+        .chunk_lines
+        .unwrap_or(DEFAULT_CHUNK_LINES)
+        .max(1);
+    let index = SemanticIndex::open(
+        Path::new(&config.cache.path.0),
+        serde_json::to_string(&command)?,
+    )
+    .await?;
+    let query_embedding = embed(&command, &[query.to_string()])
+        .await?
+        .pop()
+        .context("no embedding for the query")?;
+
+    let mut hits = Vec::new();
+    for path in files {
+        let key = path.to_string_lossy().to_string();
+        let mtime = std::fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_millis() as i64;
+        let chunks = match index.get(&key, mtime).await? {
+            Some(chunks) => chunks,
+            None => {
+                let chunks = match file_chunks(config, path, max_lines).await {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        warn!("skipping {}: {:#}", path.display(), e);
+                        continue;
+                    }
+                };
+                debug!("embedding {} chunks of {}", chunks.len(), path.display());
+                let mut embedded = Vec::with_capacity(chunks.len());
+                for batch in chunks.chunks(EMBED_BATCH_SIZE) {
+                    let texts: Vec<String> = batch.iter().map(|c| c.text.clone()).collect();
+                    let embeddings = embed(&command, &texts).await?;
+                    embedded.extend(batch.iter().cloned().zip(embeddings));
+                }
+                index.set(&key, mtime, embedded.clone()).await?;
+                embedded
+            }
+        };
+        hits.extend(chunks.into_iter().map(|(chunk, embedding)| SemanticHit {
+            path: path.clone(),
+            score: cosine_similarity(&query_embedding, &embedding),
+            chunk,
+        }));
+    }
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(config.semantic.results.unwrap_or(DEFAULT_RESULTS));
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::location::MemberSpan;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn chunks() {
+        let map = LocationMap {
+            members: vec![
+                MemberSpan {
+                    first_line: 1,
+                    line_prefix: "a.pdf: ".to_string(),
+                    language: None,
+                },
+                MemberSpan {
+                    first_line: 4,
+                    line_prefix: "b.txt: ".to_string(),
+                    language: None,
+                },
+            ],
+            ..Default::default()
+        };
+        let text = "a.pdf: Page 1: one\na.pdf: Page 1: two\na.pdf: Page 2: three\nb.txt: four\nb.txt: five\nb.txt: six\n";
+        let chunks = split_chunks(&map, text, 2);
+        let summary: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.line, c.member.as_deref(), c.page, c.text.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, Some("a.pdf"), Some(1), "one\ntwo"),
+                (3, Some("a.pdf"), Some(2), "three"),
+                (4, Some("b.txt"), None, "four\nfive"),
+                (6, Some("b.txt"), None, "six"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn embed_command() -> Result<()> {
+        // the length of the text as the only dimension
+        let command: Vec<String> = ["sh", "-c", "while read -r l; do echo \"[${#l}]\"; done"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let embeddings = embed(&command, &["a".to_string(), "abc".to_string()]).await?;
+        assert_eq!(embeddings, vec![vec![3.0], vec![5.0]]);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]), 1.0);
+        Ok(())
+    }
+}
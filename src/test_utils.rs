@@ -1,15 +1,19 @@
 use crate::{
     adapted_iter::AdaptedFilesIterBox,
     adapters::{
-        AdaptInfo, ReadBox,
+        AdaptInfo, FileAdapter, ReadBox, ar,
+        cfb::{self, DIR_ENTRY_SIZE, HEADER_SIZE, NO_STREAM, ROOT, STORAGE, STREAM},
         custom::{BUILTIN_SPAWNING_ADAPTERS, CustomSpawningFileAdapter},
+        pre_glob,
     },
     config::RgaConfig,
-    matching::{FastFileMatcher, FileMatcher},
+    matching::{FastFileMatcher, FileMatcher, FileMeta, adapter_matcher},
+    passwords::PasswordState,
     recurse::concat_read_streams,
 };
 use anyhow::Result;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::{fs::File, io::AsyncReadExt};
 
 pub use pretty_assertions::{assert_eq, assert_ne};
@@ -43,6 +47,7 @@ pub fn simple_adapt_info_full(
             inp,
             line_prefix: "PREFIX:".to_string(),
             config: RgaConfig::default(),
+            passwords: PasswordState::default(),
             postprocess: true,
         },
         FastFileMatcher::FileExtension(
@@ -56,6 +61,39 @@ pub fn simple_adapt_info_full(
     )
 }
 
+/// Whether rg would call rga-preproc for the file with the given `--pre-glob` (`*` or `*.{ext,...}`)
+pub fn pre_glob_selects(pre_glob: &str, path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match pre_glob
+        .strip_prefix("*.{")
+        .and_then(|g| g.strip_suffix('}'))
+    {
+        Some(extensions) => extensions
+            .split(',')
+            .any(|ext| !ext.is_empty() && name.ends_with(&format!(".{ext}"))),
+        None => pre_glob == "*",
+    }
+}
+
+/// Whether rg would call rga-preproc for the file on disk and the adapter would be chosen for it, with or without `--rga-accurate`
+pub fn found_by(adapter: Arc<dyn FileAdapter>, path: &Path, accurate: bool) -> Result<bool> {
+    let adapters = [adapter];
+    if !pre_glob_selects(&pre_glob(&adapters, accurate), path) {
+        return Ok(false);
+    }
+    let matcher = adapter_matcher(&adapters, accurate)?;
+    Ok(matcher(FileMeta {
+        lossy_filename: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        mimetype: accurate.then_some("application/octet-stream"),
+        path: Some(path.to_path_buf()),
+    })
+    .is_some())
+}
+
 pub async fn adapted_to_vec(adapted: AdaptedFilesIterBox) -> Result<Vec<u8>> {
     let mut res = concat_read_streams(adapted);
 
@@ -73,6 +111,213 @@ pub fn poppler_adapter() -> CustomSpawningFileAdapter {
     adapter.to_adapter()
 }
 
+/// Create a GNU ar archive
+pub fn create_ar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut ar = ar::MAGIC.to_vec();
+    let mut long_names = Vec::new();
+    let mut members = Vec::new();
+    for (name, data) in files {
+        let name = if *name == "/" {
+            name.to_string()
+        } else if name.len() > 15 {
+            let offset = long_names.len();
+            long_names.extend_from_slice(format!("{name}/\n").as_bytes());
+            format!("/{offset}")
+        } else {
+            format!("{name}/")
+        };
+        members.push((name, data.to_vec()));
+    }
+    if !long_names.is_empty() {
+        members.insert(0, ("//".to_string(), long_names));
+    }
+    for (name, data) in members {
+        ar.extend_from_slice(
+            format!(
+                "{name:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                0,
+                0,
+                0,
+                644,
+                data.len()
+            )
+            .as_bytes(),
+        );
+        ar.extend_from_slice(&data);
+        if data.len() % 2 == 1 {
+            ar.push(b'\n');
+        }
+    }
+    ar
+}
+
+const SECTOR: usize = 512;
+const MINI_SECTOR: usize = 64;
+const END_OF_CHAIN: u32 = 0xffff_fffe;
+const FAT_SECT: u32 = 0xffff_fffd;
+
+fn cfb_entry(name: &str, kind: u8, child: u32, right: u32, start: u32, size: u64) -> Vec<u8> {
+    let mut e = vec![0u8; DIR_ENTRY_SIZE];
+    let units: Vec<u8> = name.encode_utf16().flat_map(|u| u.to_le_bytes()).collect();
+    e[..units.len()].copy_from_slice(&units);
+    e[64..66].copy_from_slice(&(units.len() as u16 + 2).to_le_bytes());
+    e[66] = kind;
+    e[67] = 1;
+    e[68..72].copy_from_slice(&NO_STREAM.to_le_bytes());
+    e[72..76].copy_from_slice(&right.to_le_bytes());
+    e[76..80].copy_from_slice(&child.to_le_bytes());
+    e[116..120].copy_from_slice(&start.to_le_bytes());
+    e[120..128].copy_from_slice(&size.to_le_bytes());
+    e
+}
+
+/// Append data as a chain of sectors, returns the first sector
+fn cfb_append(sectors: &mut Vec<u8>, fat: &mut Vec<u32>, data: &[u8]) -> u32 {
+    let start = fat.len() as u32;
+    let count = data.len().div_ceil(SECTOR).max(1);
+    for i in 0..count {
+        fat.push(if i + 1 == count {
+            END_OF_CHAIN
+        } else {
+            start + i as u32 + 1
+        });
+    }
+    sectors.extend_from_slice(data);
+    sectors.resize(fat.len() * SECTOR, 0);
+    start
+}
+
+/// Create a compound file (version 3) with the given streams, storages are created from the `/` separated paths.
+///
+/// Streams smaller than 4096 bytes are stored in the mini stream.
+pub fn create_cfb(streams: &[(&str, &[u8])]) -> Vec<u8> {
+    // (name, is storage, data, children)
+    let mut nodes: Vec<(String, bool, Vec<u8>, Vec<usize>)> =
+        vec![("Root Entry".to_string(), true, Vec::new(), Vec::new())];
+    for (path, data) in streams {
+        let mut parent = 0;
+        let components: Vec<&str> = path.split('/').collect();
+        for (i, name) in components.iter().enumerate() {
+            let is_storage = i + 1 < components.len();
+            let existing = nodes[parent]
+                .3
+                .iter()
+                .copied()
+                .find(|c| nodes[*c].0 == *name);
+            parent = match existing {
+                Some(c) => c,
+                None => {
+                    let data = if is_storage {
+                        Vec::new()
+                    } else {
+                        data.to_vec()
+                    };
+                    nodes.push((name.to_string(), is_storage, data, Vec::new()));
+                    let id = nodes.len() - 1;
+                    nodes[parent].3.push(id);
+                    id
+                }
+            };
+        }
+    }
+    // data of the regular sectors, FAT and mini FAT
+    let mut sectors: Vec<u8> = Vec::new();
+    let mut fat: Vec<u32> = Vec::new();
+    let mut mini_stream: Vec<u8> = Vec::new();
+    let mut mini_fat: Vec<u32> = Vec::new();
+    let mut starts = vec![END_OF_CHAIN; nodes.len()];
+    for (id, (_, is_storage, data, _)) in nodes.iter().enumerate() {
+        if *is_storage || data.is_empty() {
+            continue;
+        }
+        if data.len() < 4096 {
+            let start = mini_fat.len() as u32;
+            let count = data.len().div_ceil(MINI_SECTOR);
+            for i in 0..count {
+                mini_fat.push(if i + 1 == count {
+                    END_OF_CHAIN
+                } else {
+                    start + i as u32 + 1
+                });
+            }
+            mini_stream.extend_from_slice(data);
+            mini_stream.resize(mini_fat.len() * MINI_SECTOR, 0);
+            starts[id] = start;
+        } else {
+            starts[id] = cfb_append(&mut sectors, &mut fat, data);
+        }
+    }
+    let mini_stream_start = cfb_append(&mut sectors, &mut fat, &mini_stream);
+    let mini_fat_bytes: Vec<u8> = mini_fat.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mini_fat_start = cfb_append(&mut sectors, &mut fat, &mini_fat_bytes);
+    let mut dir = Vec::new();
+    for (id, (name, is_storage, data, children)) in nodes.iter().enumerate() {
+        let child = children.first().map(|c| *c as u32).unwrap_or(NO_STREAM);
+        // siblings are linked as a list of right siblings
+        let right = nodes
+            .iter()
+            .find_map(|n| {
+                let pos = n.3.iter().position(|c| *c == id)?;
+                Some(n.3.get(pos + 1).map(|c| *c as u32).unwrap_or(NO_STREAM))
+            })
+            .unwrap_or(NO_STREAM);
+        dir.extend(if id == 0 {
+            cfb_entry(
+                name,
+                ROOT,
+                child,
+                NO_STREAM,
+                mini_stream_start,
+                mini_stream.len() as u64,
+            )
+        } else if *is_storage {
+            cfb_entry(name, STORAGE, child, right, 0, 0)
+        } else {
+            cfb_entry(
+                name,
+                STREAM,
+                NO_STREAM,
+                right,
+                starts[id],
+                data.len() as u64,
+            )
+        });
+    }
+    let dir_start = cfb_append(&mut sectors, &mut fat, &dir);
+    // the FAT describes its own sectors too
+    let fat_start = fat.len() as u32;
+    let num_fat_sectors = fat.len().div_ceil(SECTOR / 4 - 1);
+    fat.extend(std::iter::repeat_n(FAT_SECT, num_fat_sectors));
+    let mut fat_bytes: Vec<u8> = fat.iter().flat_map(|s| s.to_le_bytes()).collect();
+    fat_bytes.resize(num_fat_sectors * SECTOR, 0xff);
+    sectors.extend(fat_bytes);
+
+    let mut header = vec![0u8; HEADER_SIZE];
+    header[..8].copy_from_slice(cfb::MAGIC);
+    header[0x18..0x1a].copy_from_slice(&0x3eu16.to_le_bytes());
+    header[0x1a..0x1c].copy_from_slice(&3u16.to_le_bytes());
+    header[0x1c..0x1e].copy_from_slice(&0xfffeu16.to_le_bytes());
+    header[0x1e..0x20].copy_from_slice(&9u16.to_le_bytes());
+    header[0x20..0x22].copy_from_slice(&6u16.to_le_bytes());
+    header[0x2c..0x30].copy_from_slice(&(num_fat_sectors as u32).to_le_bytes());
+    header[0x30..0x34].copy_from_slice(&dir_start.to_le_bytes());
+    header[0x38..0x3c].copy_from_slice(&4096u32.to_le_bytes());
+    header[0x3c..0x40].copy_from_slice(&mini_fat_start.to_le_bytes());
+    header[0x40..0x44]
+        .copy_from_slice(&(mini_fat_bytes.len().div_ceil(SECTOR) as u32).to_le_bytes());
+    header[0x44..0x48].copy_from_slice(&END_OF_CHAIN.to_le_bytes());
+    for i in 0..109 {
+        let s = if i < num_fat_sectors {
+            fat_start + i as u32
+        } else {
+            NO_STREAM
+        };
+        header[0x4c + i * 4..0x50 + i * 4].copy_from_slice(&s.to_le_bytes());
+    }
+    header.extend(sectors);
+    header
+}
+
 #[cfg(test)]
 pub fn init_logging() {
     let _ = env_logger::builder().is_test(true).try_init();
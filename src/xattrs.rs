@@ -0,0 +1,94 @@
+//! Extended attributes of files, see `--rga-xattrs`.
+
+use anyhow::Result;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Namespaces of Linux attributes that are not user metadata (and mostly binary)
+#[cfg_attr(not(unix), allow(dead_code))]
+const SKIPPED_NAMESPACES: &[&str] = &["security.", "system.", "trusted."];
+
+/// The extended attributes of a file as lines `xattr:NAME: VALUE`, one line per line of the value.
+///
+/// Attributes with binary values are skipped, except for binary plists (used by macOS e.g. for Finder comments and tags),
+/// of which the strings are output.
+/// Always empty on platforms without extended attributes.
+#[cfg(unix)]
+pub fn xattr_lines(path: &Path) -> Result<String> {
+    use anyhow::Context;
+    let mut out = String::new();
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(out);
+    }
+    let names =
+        xattr::list(path).with_context(|| format!("listing xattrs of {}", path.display()))?;
+    for name in names {
+        let name = name.to_string_lossy();
+        if SKIPPED_NAMESPACES.iter().any(|ns| name.starts_with(ns)) {
+            continue;
+        }
+        let Some(value) = xattr::get(path, name.as_ref())? else {
+            continue;
+        };
+        if let Some(text) = value_to_text(&value) {
+            for line in text.lines() {
+                out.push_str(&format!("xattr:{name}: {line}\n"));
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(not(unix))]
+pub fn xattr_lines(_path: &Path) -> Result<String> {
+    Ok(String::new())
+}
+
+/// The text of an attribute value, None if it is binary
+#[cfg_attr(not(unix), allow(dead_code))]
+fn value_to_text(value: &[u8]) -> Option<String> {
+    if value.starts_with(b"bplist") {
+        let mut strings = Vec::new();
+        plist_strings(
+            &plist::Value::from_reader(Cursor::new(value)).ok()?,
+            &mut strings,
+        );
+        return Some(strings.join(", ")).filter(|s| !s.is_empty());
+    }
+    let text = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+    if text.contains('\0') {
+        return None;
+    }
+    Some(text.to_string())
+}
+
+fn plist_strings(value: &plist::Value, out: &mut Vec<String>) {
+    match value {
+        plist::Value::String(s) => out.push(s.clone()),
+        plist::Value::Array(a) => a.iter().for_each(|v| plist_strings(v, out)),
+        plist::Value::Dictionary(d) => d.values().for_each(|v| plist_strings(v, out)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() -> Result<()> {
+        assert_eq!(
+            value_to_text(b"0083;5f1d7a2b;Safari;\0").as_deref(),
+            Some("0083;5f1d7a2b;Safari;")
+        );
+        assert_eq!(value_to_text(b"\x00\x01\x02"), None);
+        let tags = plist::Value::Array(vec![
+            plist::Value::String("invoice".to_string()),
+            plist::Value::String("Red\n6".to_string()),
+        ]);
+        let mut bplist = Vec::new();
+        tags.to_writer_binary(&mut bplist)?;
+        assert_eq!(value_to_text(&bplist).as_deref(), Some("invoice, Red\n6"));
+        Ok(())
+    }
+}